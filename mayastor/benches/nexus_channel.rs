@@ -0,0 +1,86 @@
+//! Cost of selecting a child on a nexus channel while another core completes
+//! IO of the channel, with the number of IOs in flight next to the fields
+//! read on every IO or on a cache line of its own, as in `NexusChannelInner`.
+
+use std::{
+    hint::black_box,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use mayastor::core::Padded;
+
+/// the fields of a channel read on every IO, with the counter next to them
+#[derive(Default)]
+#[repr(C)]
+struct Adjacent {
+    previous: AtomicU64,
+    readers: u64,
+    in_flight: AtomicU64,
+}
+
+/// the same fields with the counter on a cache line of its own
+#[derive(Default)]
+#[repr(C)]
+struct Separated {
+    previous: AtomicU64,
+    readers: u64,
+    in_flight: Padded<AtomicU64>,
+}
+
+trait Channel: Default + Send + Sync + 'static {
+    fn select(&self) -> u64;
+    fn in_flight(&self) -> &AtomicU64;
+}
+
+macro_rules! channel {
+    ($t:ty) => {
+        impl Channel for $t {
+            fn select(&self) -> u64 {
+                let next = (self.previous.load(Ordering::Relaxed) + 1)
+                    % self.readers.max(3);
+                self.previous.store(next, Ordering::Relaxed);
+                next
+            }
+
+            fn in_flight(&self) -> &AtomicU64 {
+                &self.in_flight
+            }
+        }
+    };
+}
+
+channel!(Adjacent);
+channel!(Separated);
+
+/// select children while another thread updates the number of IOs in flight
+fn select<C: Channel>(c: &mut Criterion, name: &str) {
+    let channel = Arc::new(C::default());
+    let stop = Arc::new(AtomicBool::new(false));
+    let completer = {
+        let (channel, stop) = (channel.clone(), stop.clone());
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                channel.in_flight().fetch_add(1, Ordering::Relaxed);
+                channel.in_flight().fetch_sub(1, Ordering::Relaxed);
+            }
+        })
+    };
+
+    c.bench_function(name, |b| b.iter(|| black_box(channel.select())));
+
+    stop.store(true, Ordering::Relaxed);
+    completer.join().unwrap();
+}
+
+fn bench(c: &mut Criterion) {
+    select::<Adjacent>(c, "child_select/in_flight_adjacent");
+    select::<Separated>(c, "child_select/in_flight_padded");
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,97 @@
+//! Definition of a trait for the object stores backups are kept in together
+//! with the errors of backups and restores.
+
+use async_trait::async_trait;
+use snafu::Snafu;
+
+use crate::{
+    core::CoreError,
+    jsonrpc::{Code, RpcErrorCode},
+    lvs,
+};
+
+/// Definition of errors that can be returned by backups and restores.
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum BackupError {
+    #[snafu(display("Replica {} not found", name))]
+    ReplicaNotFound { name: String },
+    #[snafu(display("Pool {} not found", name))]
+    PoolNotFound { name: String },
+    #[snafu(display("Backup job {} is already running", id))]
+    JobRunning { id: String },
+    #[snafu(display("Invalid object store configuration: {}", reason))]
+    InvalidConfig { reason: String },
+    #[snafu(display(
+        "Failed to build object store request for {}. Error {}",
+        key,
+        source
+    ))]
+    BuildRequest { key: String, source: http::Error },
+    #[snafu(display(
+        "Failed to reach object store for {}. Error {}",
+        key,
+        source
+    ))]
+    Request { key: String, source: hyper::Error },
+    #[snafu(display(
+        "Object store refused request for {} with status {}",
+        key,
+        status
+    ))]
+    Refused { key: String, status: u16 },
+    #[snafu(display("Object store operation on {} timed out", key))]
+    OpTimeout { key: String },
+    #[snafu(display("Failed to wait for object store operation on {}", key))]
+    OpWait {
+        key: String,
+        source: futures::channel::oneshot::Canceled,
+    },
+    #[snafu(display(
+        "Invalid manifest of backup {}. Error {}",
+        backup,
+        source
+    ))]
+    InvalidManifest {
+        backup: String,
+        source: serde_json::Error,
+    },
+    #[snafu(display("Chunk {} of backup {} is corrupted", index, backup))]
+    CorruptChunk { backup: String, index: u64 },
+    #[snafu(display("IO on replica {} failed. Error {}", name, source))]
+    ReplicaIo { name: String, source: CoreError },
+    #[snafu(display("Failed to create replica {}. Error {}", name, source))]
+    CreateReplica { name: String, source: lvs::Error },
+}
+
+impl RpcErrorCode for BackupError {
+    fn rpc_error_code(&self) -> Code {
+        match self {
+            Self::ReplicaNotFound {
+                ..
+            }
+            | Self::PoolNotFound {
+                ..
+            } => Code::NotFound,
+            Self::JobRunning {
+                ..
+            } => Code::AlreadyExists,
+            Self::InvalidConfig {
+                ..
+            } => Code::InvalidParams,
+            _ => Code::InternalError,
+        }
+    }
+}
+
+/// Trait defining the operations on an object store needed by backups.
+/// Keys are relative to the location of the backups in the store.
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    /// Store an object under the given key, replacing any object stored
+    /// under it.
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), BackupError>;
+
+    /// Returns the object stored under the given key.
+    async fn get(&self, key: &str) -> Result<Vec<u8>, BackupError>;
+}
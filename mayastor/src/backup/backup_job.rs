@@ -0,0 +1,460 @@
+//! Jobs backing up replicas to an object store and restoring them.
+//!
+//! The objects of a backup are stored under the name of the backup: its
+//! chunks as `<backup>/<index>`, with an index of 8 decimal digits, and the
+//! manifest as `<backup>/manifest.json`.
+
+use std::{cmp::min, collections::HashMap, convert::TryFrom, sync::Arc};
+
+use crc::crc32::checksum_castagnoli;
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use spdk_rs::DmaBuf;
+
+use crate::{
+    backup::{
+        backup_defs::{CreateReplica, InvalidManifest, ReplicaIo},
+        BackupError,
+        ObjectStore,
+        S3Config,
+        S3Store,
+    },
+    core::{Bdev, BdevHandle, CoreError, Reactors, UntypedBdev},
+    jsonrpc::jsonrpc_register,
+    lvs::{Lvol, Lvs},
+};
+
+/// number of bytes of a replica stored in a chunk
+const BACKUP_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Compression of the chunks of a backup.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Lz4,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+/// A chunk of a backup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    /// crc32c of the data of the chunk, before compression
+    pub crc: u32,
+    /// the chunk only holds zeroes and is not stored
+    #[serde(default)]
+    pub zero: bool,
+}
+
+/// The manifest of a backup, describing its chunks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// name of the replica that was backed up
+    pub replica: String,
+    /// size of the replica in bytes
+    pub size: u64,
+    /// number of bytes of the replica in a chunk, the last one may be
+    /// shorter
+    pub chunk_size: u64,
+    pub compression: Compression,
+    pub chunks: Vec<ChunkInfo>,
+}
+
+/// Kind of a backup job.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    Backup,
+    Restore,
+}
+
+/// State of a backup job.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// A backup or restore, running or finished.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupJob {
+    pub kind: JobKind,
+    /// name of the backup
+    pub backup: String,
+    /// name of the replica backed up or restored to
+    pub replica: String,
+    pub state: JobState,
+    /// number of bytes to transfer, 0 until known
+    pub size: u64,
+    /// number of bytes transferred so far
+    pub done: u64,
+    /// reason the job failed
+    pub error: Option<String>,
+}
+
+/// Backup jobs by the name of the backup, or of the replica for restores.
+static JOBS: Lazy<Mutex<HashMap<String, BackupJob>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// returns the backups and restores, running or finished
+pub fn backup_jobs() -> Vec<BackupJob> {
+    JOBS.lock().values().cloned().collect()
+}
+
+fn manifest_key(backup: &str) -> String {
+    format!("{}/manifest.json", backup)
+}
+
+fn chunk_key(backup: &str, index: usize) -> String {
+    format!("{}/{:08}", backup, index)
+}
+
+/// Register a new job under the id, replacing a finished one.
+fn start_job(id: &str, job: BackupJob) -> Result<(), BackupError> {
+    let mut jobs = JOBS.lock();
+    if jobs.get(id).map_or(false, |j| j.state == JobState::Running) {
+        return Err(BackupError::JobRunning {
+            id: id.to_string(),
+        });
+    }
+    jobs.insert(id.to_string(), job);
+    Ok(())
+}
+
+/// record the progress of a job
+fn update_job(id: &str, f: impl FnOnce(&mut BackupJob)) {
+    if let Some(job) = JOBS.lock().get_mut(id) {
+        f(job);
+    }
+}
+
+/// record the outcome of a job
+fn finish_job(id: &str, result: Result<(), BackupError>) {
+    update_job(id, |job| match result {
+        Ok(_) => job.state = JobState::Completed,
+        Err(e) => {
+            error!("backup job {} failed: {}", id, e);
+            job.state = JobState::Failed;
+            job.error = Some(e.to_string());
+        }
+    });
+}
+
+fn dma_malloc(hdl: &BdevHandle, size: u64) -> Result<DmaBuf, CoreError> {
+    hdl.dma_malloc(size)
+        .map_err(|_| CoreError::DmaAllocationError {
+            size,
+        })
+}
+
+/// Back up the replica to the store under the name of the backup, in the
+/// background. The replica should be a snapshot, the backup of a replica
+/// written to meanwhile is not a consistent image of it.
+pub async fn backup_replica(
+    store: Arc<dyn ObjectStore>,
+    replica: &str,
+    backup: &str,
+    compression: Compression,
+) -> Result<(), BackupError> {
+    let lvol = UntypedBdev::lookup_by_name(replica)
+        .and_then(|bdev| Lvol::try_from(bdev).ok())
+        .ok_or_else(|| BackupError::ReplicaNotFound {
+            name: replica.to_string(),
+        })?;
+    if !lvol.is_snapshot() {
+        warn!("backing up replica {} which is not a snapshot", replica);
+    }
+
+    start_job(
+        backup,
+        BackupJob {
+            kind: JobKind::Backup,
+            backup: backup.to_string(),
+            replica: replica.to_string(),
+            state: JobState::Running,
+            size: lvol.size(),
+            done: 0,
+            error: None,
+        },
+    )?;
+
+    info!("backing up replica {} as {}", replica, backup);
+    let backup = backup.to_string();
+    Reactors::current()
+        .spawn_local(async move {
+            let result = run_backup(store, &lvol, &backup, compression).await;
+            finish_job(&backup, result);
+        })
+        .detach();
+    Ok(())
+}
+
+/// store the chunks of the replica followed by the manifest
+async fn run_backup(
+    store: Arc<dyn ObjectStore>,
+    lvol: &Lvol,
+    backup: &str,
+    compression: Compression,
+) -> Result<(), BackupError> {
+    let name = lvol.name();
+    let size = lvol.size();
+    let hdl = Bdev::open(&lvol.as_bdev(), false)
+        .and_then(|desc| desc.into_handle())
+        .context(ReplicaIo {
+            name: name.clone(),
+        })?;
+    let mut buf =
+        dma_malloc(&hdl, min(BACKUP_CHUNK_SIZE, size)).context(ReplicaIo {
+            name: name.clone(),
+        })?;
+
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+    while offset < size {
+        let len = min(BACKUP_CHUNK_SIZE, size - offset);
+        if len != buf.len() as u64 {
+            buf = dma_malloc(&hdl, len).context(ReplicaIo {
+                name: name.clone(),
+            })?;
+        }
+        hdl.read_at(offset, &mut buf).await.context(ReplicaIo {
+            name: name.clone(),
+        })?;
+
+        let data = buf.as_slice();
+        let chunk = ChunkInfo {
+            crc: checksum_castagnoli(data),
+            zero: data.iter().all(|b| *b == 0),
+        };
+        if !chunk.zero {
+            let object = match compression {
+                Compression::None => data.to_vec(),
+                Compression::Lz4 => lz4_flex::compress_prepend_size(data),
+            };
+            store.put(&chunk_key(backup, chunks.len()), object).await?;
+        }
+        chunks.push(chunk);
+
+        offset += len;
+        update_job(backup, |job| job.done = offset);
+    }
+
+    let manifest = Manifest {
+        replica: name,
+        size,
+        chunk_size: BACKUP_CHUNK_SIZE,
+        compression,
+        chunks,
+    };
+    let manifest = serde_json::to_vec(&manifest)
+        .expect("Failed to serialise backup manifest");
+    store.put(&manifest_key(backup), manifest).await
+}
+
+/// Restore the backup from the store into a new replica on the pool, in the
+/// background. The replica is destroyed again if the restore fails.
+pub async fn restore_backup(
+    store: Arc<dyn ObjectStore>,
+    backup: &str,
+    pool: &str,
+    replica: &str,
+    uuid: Option<String>,
+    thin: bool,
+) -> Result<(), BackupError> {
+    let pool = Lvs::lookup(pool).ok_or_else(|| BackupError::PoolNotFound {
+        name: pool.to_string(),
+    })?;
+
+    start_job(
+        replica,
+        BackupJob {
+            kind: JobKind::Restore,
+            backup: backup.to_string(),
+            replica: replica.to_string(),
+            state: JobState::Running,
+            size: 0,
+            done: 0,
+            error: None,
+        },
+    )?;
+
+    info!("restoring backup {} into replica {}", backup, replica);
+    let backup = backup.to_string();
+    let replica = replica.to_string();
+    Reactors::current()
+        .spawn_local(async move {
+            let result =
+                run_restore(store, &backup, pool, &replica, uuid, thin).await;
+            finish_job(&replica, result);
+        })
+        .detach();
+    Ok(())
+}
+
+/// create the replica and write the chunks of the backup to it
+async fn run_restore(
+    store: Arc<dyn ObjectStore>,
+    backup: &str,
+    pool: Lvs,
+    replica: &str,
+    uuid: Option<String>,
+    thin: bool,
+) -> Result<(), BackupError> {
+    let manifest = store.get(&manifest_key(backup)).await?;
+    let manifest: Manifest =
+        serde_json::from_slice(&manifest).context(InvalidManifest {
+            backup,
+        })?;
+    update_job(replica, |job| job.size = manifest.size);
+
+    let lvol = pool
+        .create_lvol(replica, manifest.size, uuid.as_deref(), thin)
+        .await
+        .context(CreateReplica {
+            name: replica,
+        })?;
+
+    let result = write_chunks(&store, backup, &manifest, &lvol, thin).await;
+    if result.is_err() {
+        if let Err(e) = lvol.destroy().await {
+            error!("failed to destroy replica {}: {}", replica, e);
+        }
+    }
+    result
+}
+
+/// write the chunks of the backup to the replica, verifying them
+async fn write_chunks(
+    store: &Arc<dyn ObjectStore>,
+    backup: &str,
+    manifest: &Manifest,
+    lvol: &Lvol,
+    thin: bool,
+) -> Result<(), BackupError> {
+    let name = lvol.name();
+    let hdl = Bdev::open(&lvol.as_bdev(), true)
+        .and_then(|desc| desc.into_handle())
+        .context(ReplicaIo {
+            name: name.clone(),
+        })?;
+
+    for (index, chunk) in manifest.chunks.iter().enumerate() {
+        let offset = index as u64 * manifest.chunk_size;
+        let len = min(manifest.chunk_size, manifest.size - offset);
+        let corrupt = || BackupError::CorruptChunk {
+            backup: backup.to_string(),
+            index: index as u64,
+        };
+
+        if chunk.zero {
+            // the blocks of a new thin replica read as zeroes already
+            if !thin {
+                hdl.write_zeroes_at(offset, len).await.context(ReplicaIo {
+                    name: name.clone(),
+                })?;
+            }
+        } else {
+            let object = store.get(&chunk_key(backup, index)).await?;
+            let data = match manifest.compression {
+                Compression::None => object,
+                Compression::Lz4 => {
+                    lz4_flex::decompress_size_prepended(&object)
+                        .map_err(|_| corrupt())?
+                }
+            };
+            if data.len() as u64 != len
+                || checksum_castagnoli(&data) != chunk.crc
+            {
+                return Err(corrupt());
+            }
+
+            let mut buf = dma_malloc(&hdl, len).context(ReplicaIo {
+                name: name.clone(),
+            })?;
+            buf.as_mut_slice().copy_from_slice(&data);
+            hdl.write_at(offset, &buf).await.context(ReplicaIo {
+                name: name.clone(),
+            })?;
+        }
+
+        update_job(&name, |job| job.done = offset + len);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct BackupArgs {
+    /// name of the replica, normally a snapshot
+    replica: String,
+    /// name of the backup, the name of the replica when not given
+    #[serde(default)]
+    backup: Option<String>,
+    store: S3Config,
+    #[serde(default)]
+    compression: Compression,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestoreArgs {
+    /// name of the backup
+    backup: String,
+    store: S3Config,
+    /// pool to create the replica on
+    pool: String,
+    /// name of the replica to create
+    replica: String,
+    #[serde(default)]
+    uuid: Option<String>,
+    #[serde(default)]
+    thin: bool,
+}
+
+async fn backup(args: BackupArgs) -> Result<(), BackupError> {
+    let store = Arc::new(S3Store::new(args.store)?);
+    let backup = args.backup.unwrap_or_else(|| args.replica.clone());
+    backup_replica(store, &args.replica, &backup, args.compression).await
+}
+
+async fn restore(args: RestoreArgs) -> Result<(), BackupError> {
+    let store = Arc::new(S3Store::new(args.store)?);
+    restore_backup(
+        store,
+        &args.backup,
+        &args.pool,
+        &args.replica,
+        args.uuid,
+        args.thin,
+    )
+    .await
+}
+
+async fn list(_: ()) -> Result<Vec<BackupJob>, BackupError> {
+    Ok(backup_jobs())
+}
+
+/// Register the json-rpc methods to back up replicas, restore them and
+/// follow the progress of the jobs.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, BackupError>(
+        "backup_create",
+        |args: BackupArgs| backup(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, BackupError>(
+        "backup_restore",
+        |args: RestoreArgs| restore(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, BackupError>("backup_jobs", |args: ()| {
+        list(args).boxed_local()
+    });
+}
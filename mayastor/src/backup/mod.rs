@@ -0,0 +1,35 @@
+//!
+//! Backup of replicas to an object store and their restore.
+//!
+//! A backup streams the data of a replica, normally a snapshot, to an object
+//! store in chunks. Each chunk is stored as an object of its own, optionally
+//! compressed, and its checksum is kept in the manifest of the backup, which
+//! is written last so that a backup without a manifest is known to be
+//! incomplete. Chunks holding only zeroes are not stored.
+//!
+//! A backup is restored into a new replica on a local pool, verifying the
+//! checksum of every chunk. Backups and restores run in the background, their
+//! progress is reported by the `backup_jobs` json-rpc method.
+
+pub use backup_defs::{BackupError, ObjectStore};
+pub use backup_job::{
+    backup_jobs,
+    backup_replica,
+    restore_backup,
+    BackupJob,
+    ChunkInfo,
+    Compression,
+    JobKind,
+    JobState,
+    Manifest,
+};
+pub use s3::{S3Config, S3Store};
+
+mod backup_defs;
+mod backup_job;
+mod s3;
+
+/// Register the json-rpc methods to back up replicas and restore them.
+pub(crate) fn register_jsonrpc_methods() {
+    backup_job::register_jsonrpc_methods();
+}
@@ -0,0 +1,437 @@
+//! Object store speaking the S3 protocol.
+//!
+//! Objects are addressed path style, as `<endpoint>/<bucket>/<prefix><key>`,
+//! which all S3 compatible stores support, and requests are signed with
+//! signature version 4. The credentials are taken from the configuration or
+//! else from the `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` environment
+//! variables. The endpoint is an `https://` one, whose certificate is checked
+//! against the root certificates of the host, or an `http://` one, which
+//! leaves the backups readable on the network unless the nexus encrypts its
+//! data.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::Future;
+use hmac::{Hmac, Mac, NewMac};
+use hyper::{client::HttpConnector, Body, Client, Method, Request, Uri};
+use hyper_rustls::HttpsConnector;
+use sha2::{Digest, Sha256};
+use snafu::ResultExt;
+
+use crate::{
+    backup::backup_defs::{
+        BackupError,
+        BuildRequest,
+        ObjectStore,
+        OpWait,
+        Request as RequestError,
+    },
+    core::{self, runtime::https_connector},
+};
+
+/// Time an object store request may take before it is failed.
+const S3_OP_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Location of backups in an S3 compatible object store.
+#[derive(Clone, Deserialize)]
+pub struct S3Config {
+    /// endpoint of the store, e.g. `http://minio:9000`
+    pub endpoint: String,
+    /// region the bucket is in
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub bucket: String,
+    /// prefix of the keys of the backups
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub access_key: Option<String>,
+    #[serde(default)]
+    pub secret_key: Option<String>,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+impl std::fmt::Debug for S3Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Config")
+            .field("endpoint", &self.endpoint)
+            .field("region", &self.region)
+            .field("bucket", &self.bucket)
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+/// client of the object store, speaking https or http
+type S3Client = Client<HttpsConnector<HttpConnector>>;
+
+/// S3 object store client
+pub struct S3Store {
+    client: S3Client,
+    config: S3Config,
+    /// host and port of the endpoint
+    host: String,
+    access_key: String,
+    secret_key: String,
+}
+
+impl std::fmt::Debug for S3Store {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Store")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// lower case hex encoding of the bytes
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+        .expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Derive the key requests of the day are signed with.
+fn signing_key(
+    secret: &str,
+    date: &str,
+    region: &str,
+    service: &str,
+) -> Vec<u8> {
+    let key =
+        hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+/// Sign a request without query with signature version 4, returning the
+/// signature. The headers are those that are signed, with their names in
+/// lower case and sorted.
+#[allow(clippy::too_many_arguments)]
+fn signature(
+    secret: &str,
+    region: &str,
+    service: &str,
+    amz_date: &str,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    payload_hash: &str,
+) -> String {
+    let canonical_headers = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect::<String>();
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        method,
+        path,
+        canonical_headers,
+        signed_headers(headers),
+        payload_hash
+    );
+    let date = &amz_date[.. 8];
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope(date, region, service),
+        to_hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    to_hex(&hmac_sha256(
+        &signing_key(secret, date, region, service),
+        string_to_sign.as_bytes(),
+    ))
+}
+
+/// the names of the signed headers
+fn signed_headers(headers: &[(&str, &str)]) -> String {
+    headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// the scope of the credentials a request is signed with
+fn scope(date: &str, region: &str, service: &str) -> String {
+    format!("{}/{}/{}/aws4_request", date, region, service)
+}
+
+/// percent encode a path, leaving the slashes in place
+fn encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A' ..= b'Z' | b'a' ..= b'z' | b'0' ..= b'9' => {
+                (b as char).to_string()
+            }
+            b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl S3Store {
+    /// Create a client of the store of the configuration.
+    pub fn new(config: S3Config) -> Result<Self, BackupError> {
+        let invalid = |reason: &str| BackupError::InvalidConfig {
+            reason: reason.to_string(),
+        };
+        let uri = config
+            .endpoint
+            .parse::<Uri>()
+            .map_err(|_| invalid("endpoint is not a valid uri"))?;
+        match uri.scheme_str() {
+            Some("https") => {}
+            Some("http") => warn!(
+                "backups are sent to {} in clear, use an https endpoint",
+                config.endpoint
+            ),
+            _ => return Err(invalid("the endpoint must be https or http")),
+        }
+        let host = uri
+            .authority()
+            .ok_or_else(|| invalid("endpoint has no host"))?
+            .to_string();
+        if config.bucket.is_empty() {
+            return Err(invalid("no bucket given"));
+        }
+
+        let access_key = config
+            .access_key
+            .clone()
+            .or_else(|| std::env::var("AWS_ACCESS_KEY_ID").ok())
+            .ok_or_else(|| invalid("no access key given"))?;
+        let secret_key = config
+            .secret_key
+            .clone()
+            .or_else(|| std::env::var("AWS_SECRET_ACCESS_KEY").ok())
+            .ok_or_else(|| invalid("no secret key given"))?;
+
+        let connector = https_connector().map_err(|e| {
+            invalid(&format!("failed to load root certificates: {}", e))
+        })?;
+
+        Ok(Self {
+            client: Client::builder().build(connector),
+            config,
+            host,
+            access_key,
+            secret_key,
+        })
+    }
+
+    /// Build a request on the object under the key, signed with signature
+    /// version 4.
+    fn request(
+        &self,
+        method: Method,
+        key: &str,
+        body: Vec<u8>,
+    ) -> Result<Request<Body>, BackupError> {
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let path = encode_path(&format!(
+            "/{}/{}{}",
+            self.config.bucket, self.config.prefix, key
+        ));
+        let payload_hash = to_hex(&Sha256::digest(&body));
+
+        let headers = [
+            ("host", self.host.as_str()),
+            ("x-amz-content-sha256", payload_hash.as_str()),
+            ("x-amz-date", amz_date.as_str()),
+        ];
+        let signature = signature(
+            &self.secret_key,
+            &self.config.region,
+            "s3",
+            &amz_date,
+            method.as_str(),
+            &path,
+            &headers,
+            &payload_hash,
+        );
+        let signed_headers = signed_headers(&headers);
+        let scope = scope(&date, &self.config.region, "s3");
+
+        Request::builder()
+            .method(method)
+            .uri(format!(
+                "{}{}",
+                self.config.endpoint.trim_end_matches('/'),
+                path
+            ))
+            .header("host", &self.host)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header(
+                "authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, \
+                    Signature={}",
+                    self.access_key, scope, signed_headers, signature
+                ),
+            )
+            .body(Body::from(body))
+            .context(BuildRequest {
+                key,
+            })
+    }
+
+    /// Send a request and return the body of the reply.
+    async fn send(
+        client: S3Client,
+        key: String,
+        request: Request<Body>,
+    ) -> Result<Vec<u8>, BackupError> {
+        let reply = client.request(request).await.context(RequestError {
+            key: key.clone(),
+        })?;
+        if !reply.status().is_success() {
+            return Err(BackupError::Refused {
+                key,
+                status: reply.status().as_u16(),
+            });
+        }
+        let bytes = hyper::body::to_bytes(reply.into_body()).await.context(
+            RequestError {
+                key,
+            },
+        )?;
+        Ok(bytes.to_vec())
+    }
+
+    /// Executes a future representing a request on the tokio runtime and
+    /// waits for its result.
+    async fn execute_op<T: 'static + Send>(
+        key: &str,
+        f: impl Future<Output = Result<T, BackupError>> + Send + 'static,
+    ) -> Result<T, BackupError> {
+        let op_key = key.to_string();
+        let rx = core::runtime::spawn_await(async move {
+            match tokio::time::timeout(S3_OP_TIMEOUT, f).await {
+                Ok(result) => result,
+                Err(_) => Err(BackupError::OpTimeout {
+                    key: op_key,
+                }),
+            }
+        });
+
+        rx.await.context(OpWait {
+            key,
+        })?
+    }
+}
+
+#[async_trait]
+impl ObjectStore for S3Store {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), BackupError> {
+        let request = self.request(Method::PUT, key, data)?;
+        let client = self.client.clone();
+        let op_key = key.to_string();
+        Self::execute_op(key, async move {
+            Self::send(client, op_key, request).await.map(|_| ())
+        })
+        .await
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, BackupError> {
+        let request = self.request(Method::GET, key, Vec::new())?;
+        let client = self.client.clone();
+        let op_key = key.to_string();
+        Self::execute_op(key, async move {
+            Self::send(client, op_key, request).await
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derive_signing_key() {
+        // example of the signature version 4 documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            to_hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    /// the get-vanilla case of the signature version 4 test suite
+    #[test]
+    fn sign_get_vanilla() {
+        let empty = to_hex(&Sha256::digest(b""));
+        assert_eq!(
+            signature(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "us-east-1",
+                "service",
+                "20150830T123600Z",
+                "GET",
+                "/",
+                &[
+                    ("host", "example.amazonaws.com"),
+                    ("x-amz-date", "20150830T123600Z")
+                ],
+                &empty,
+            ),
+            "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    /// the GET object example of the S3 signature version 4 documentation
+    #[test]
+    fn sign_s3_get_object() {
+        let empty = to_hex(&Sha256::digest(b""));
+        let headers = [
+            ("host", "examplebucket.s3.amazonaws.com"),
+            ("range", "bytes=0-9"),
+            ("x-amz-content-sha256", empty.as_str()),
+            ("x-amz-date", "20130524T000000Z"),
+        ];
+        assert_eq!(
+            signed_headers(&headers),
+            "host;range;x-amz-content-sha256;x-amz-date"
+        );
+        assert_eq!(
+            signature(
+                "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY",
+                "us-east-1",
+                "s3",
+                "20130524T000000Z",
+                "GET",
+                "/test.txt",
+                &headers,
+                &empty,
+            ),
+            "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"
+        );
+    }
+
+    #[test]
+    fn path_encoding() {
+        assert_eq!(
+            encode_path("/b/backups/v 1/00000001"),
+            "/b/backups/v%201/00000001"
+        );
+    }
+}
@@ -39,11 +39,13 @@ pub(crate) mod uri {
     use crate::{
         bdev::{
             aio,
+            iscsi,
             loopback,
             malloc,
             null,
             nvme,
             nvmx,
+            pmem,
             uring,
             BdevCreateDestroy,
         },
@@ -63,11 +65,13 @@ pub(crate) mod uri {
         match url.scheme() {
             "aio" => Ok(Box::new(aio::Aio::try_from(&url)?)),
             "bdev" => Ok(Box::new(loopback::Loopback::try_from(&url)?)),
+            "iscsi" => Ok(Box::new(iscsi::Iscsi::try_from(&url)?)),
             "loopback" => Ok(Box::new(loopback::Loopback::try_from(&url)?)),
             "malloc" => Ok(Box::new(malloc::Malloc::try_from(&url)?)),
             "null" => Ok(Box::new(null::Null::try_from(&url)?)),
             "nvmf" => Ok(Box::new(nvmx::NvmfDeviceTemplate::try_from(&url)?)),
             "pcie" => Ok(Box::new(nvme::NVMe::try_from(&url)?)),
+            "pmem" => Ok(Box::new(pmem::Pmem::try_from(&url)?)),
             "uring" => Ok(Box::new(uring::Uring::try_from(&url)?)),
 
             scheme => Err(NexusBdevError::UriSchemeUnsupported {
@@ -15,6 +15,7 @@ use once_cell::sync::{Lazy, OnceCell};
 use spdk_rs::{
     libspdk::{
         iovec,
+        spdk_bdev_flush_blocks,
         spdk_bdev_free_io,
         spdk_bdev_io,
         spdk_bdev_readv_blocks,
@@ -30,7 +31,7 @@ use spdk_rs::{
 };
 
 use crate::core::{
-    mempool::MemoryPool,
+    mempool::{MemoryPool, MemoryPoolStats},
     Bdev,
     BdevHandle,
     BlockDevice,
@@ -48,6 +49,7 @@ use crate::core::{
     IoCompletionStatus,
     NvmeCommandStatus,
     NvmeStatus,
+    SglCaps,
     UntypedBdev,
 };
 
@@ -214,6 +216,14 @@ impl BlockDevice for SpdkBlockDevice {
     fn alignment(&self) -> u64 {
         self.bdev.alignment()
     }
+    /// returns how the device takes scatter gather lists, the bdev layer
+    /// bounces the buffers that are not aligned
+    fn sgl_caps(&self) -> SglCaps {
+        SglCaps {
+            sgl: true,
+            buf_align: self.bdev.alignment(),
+        }
+    }
     /// returns true if the IO type is supported
     fn io_type_supported(&self, io_type: IoType) -> bool {
         self.bdev.io_type_supported(io_type)
@@ -295,6 +305,9 @@ fn io_type_to_err(
         IoType::Reset => CoreError::ResetDispatch {
             source,
         },
+        IoType::Flush => CoreError::FlushDispatch {
+            source,
+        },
         _ => {
             warn!("Unsupported I/O operation: {:?}", op);
             CoreError::NotSupported {
@@ -314,6 +327,11 @@ pub fn bdev_io_ctx_pool_init(size: u64) {
     });
 }
 
+/// Returns the usage of the memory pool of bdev I/O contexts.
+pub(crate) fn bdev_io_ctx_pool_stats() -> Option<MemoryPoolStats> {
+    BDEV_IOCTX_POOL.get().map(|p| p.stats())
+}
+
 /// Allocate a bdev I/O context from the pool.
 fn alloc_bdev_io_ctx(
     op: IoType,
@@ -514,6 +532,44 @@ impl BlockDeviceHandle for SpdkBlockDeviceHandle {
         }
     }
 
+    fn flush_io(
+        &self,
+        cb: IoCompletionCallback,
+        cb_arg: IoCompletionCallbackArg,
+    ) -> Result<(), CoreError> {
+        let num_blocks = self.device.num_blocks();
+        let ctx = alloc_bdev_io_ctx(
+            IoType::Flush,
+            IoCtx {
+                handle: self,
+                cb,
+                cb_arg,
+            },
+            0,
+            num_blocks,
+        )?;
+
+        let (desc, chan) = self.handle.io_tuple();
+        let rc = unsafe {
+            spdk_bdev_flush_blocks(
+                desc,
+                chan,
+                0,
+                num_blocks,
+                Some(bdev_io_completion),
+                ctx as *mut c_void,
+            )
+        };
+
+        if rc < 0 {
+            Err(CoreError::FlushDispatch {
+                source: Errno::ENOMEM,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
     fn unmap_blocks(
         &self,
         offset_blocks: u64,
@@ -0,0 +1,276 @@
+//! The iSCSI initiator bdev, connecting to a LUN of an existing iSCSI target.
+//! It lets the LUNs of legacy arrays be children of a nexus, to mirror their
+//! data into replicas while migrating off them:
+//! ```ignore
+//!     iscsi://192.168.1.10:3260/iqn.2001-04.com.example:disk1/0
+//! ```
+//! The path is the IQN of the target followed by the LUN, 0 if omitted.
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    os::raw::{c_int, c_void},
+};
+
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use snafu::ResultExt;
+use url::Url;
+
+use spdk_rs::libspdk::{create_iscsi_disk, delete_iscsi_disk, spdk_bdev};
+
+use crate::{
+    bdev::{dev::reject_unknown_parameters, util::uri, CreateDestroy, GetName},
+    core::UntypedBdev,
+    ffihelper::{
+        cb_arg,
+        done_errno_cb,
+        errno_result_from_i32,
+        ErrnoResult,
+        IntoCString,
+    },
+    nexus_uri::{self, NexusBdevError},
+};
+
+/// port of an iSCSI target when the URI has none
+const DEFAULT_ISCSI_PORT: u16 = 3260;
+
+/// name the initiator logs in with when the URI does not give one
+const DEFAULT_INITIATOR_IQN: &str = "iqn.2019-05.io.openebs:mayastor";
+
+#[derive(Debug)]
+pub(super) struct Iscsi {
+    /// name of the bdev, the URI without its scheme and parameters
+    name: String,
+    /// alias which can be used to open the bdev
+    alias: String,
+    /// the URL of the LUN, as given to the initiator
+    iscsi_url: String,
+    /// name of the initiator
+    initiator_iqn: String,
+    /// uuid of the spdk bdev
+    uuid: Option<uuid::Uuid>,
+}
+
+/// Convert a URI to an Iscsi "object"
+impl TryFrom<&Url> for Iscsi {
+    type Error = NexusBdevError;
+
+    fn try_from(url: &Url) -> Result<Self, Self::Error> {
+        let host =
+            url.host_str().ok_or_else(|| NexusBdevError::UriInvalid {
+                uri: url.to_string(),
+                message: String::from("missing host"),
+            })?;
+
+        let segments = uri::segments(url);
+
+        if segments.is_empty() {
+            return Err(NexusBdevError::UriInvalid {
+                uri: url.to_string(),
+                message: String::from("no target IQN"),
+            });
+        }
+
+        if segments.len() > 2 {
+            return Err(NexusBdevError::UriInvalid {
+                uri: url.to_string(),
+                message: String::from("too many path segments"),
+            });
+        }
+
+        let lun: u32 = match segments.get(1) {
+            Some(value) => {
+                value.parse().context(nexus_uri::IntParamParseError {
+                    uri: url.to_string(),
+                    parameter: String::from("lun"),
+                    value: value.to_string(),
+                })?
+            }
+            None => 0,
+        };
+
+        let mut parameters: HashMap<String, String> =
+            url.query_pairs().into_owned().collect();
+
+        let initiator_iqn = parameters
+            .remove("initiator_iqn")
+            .unwrap_or_else(|| DEFAULT_INITIATOR_IQN.to_string());
+
+        let uuid = uri::uuid(parameters.remove("uuid")).context(
+            nexus_uri::UuidParamParseError {
+                uri: url.to_string(),
+            },
+        )?;
+
+        reject_unknown_parameters(url, parameters)?;
+
+        let port = url.port().unwrap_or(DEFAULT_ISCSI_PORT);
+
+        Ok(Iscsi {
+            name: format!("{}:{}/{}/{}", host, port, segments[0], lun),
+            alias: url.to_string(),
+            iscsi_url: format!(
+                "iscsi://{}:{}/{}/{}",
+                host, port, segments[0], lun
+            ),
+            initiator_iqn,
+            uuid,
+        })
+    }
+}
+
+impl GetName for Iscsi {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl CreateDestroy for Iscsi {
+    type Error = NexusBdevError;
+
+    /// Log in to the target and create a bdev of the LUN
+    async fn create(&self) -> Result<String, Self::Error> {
+        extern "C" fn iscsi_create_cb(
+            arg: *mut c_void,
+            _bdev: *mut spdk_bdev,
+            errno: c_int,
+        ) {
+            let sender = unsafe {
+                Box::from_raw(arg as *mut oneshot::Sender<ErrnoResult<()>>)
+            };
+
+            sender
+                .send(errno_result_from_i32((), errno))
+                .expect("done callback receiver side disappeared");
+        }
+
+        if UntypedBdev::lookup_by_name(&self.name).is_some() {
+            return Err(NexusBdevError::BdevExists {
+                name: self.get_name(),
+            });
+        }
+
+        let cname = self.name.clone().into_cstring();
+        let curl = self.iscsi_url.clone().into_cstring();
+        let ciqn = self.initiator_iqn.clone().into_cstring();
+        let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
+
+        let errno = unsafe {
+            create_iscsi_disk(
+                cname.as_ptr(),
+                curl.as_ptr(),
+                ciqn.as_ptr(),
+                Some(iscsi_create_cb),
+                cb_arg(sender),
+            )
+        };
+
+        errno_result_from_i32((), errno).context(
+            nexus_uri::CreateBdevInvalidParams {
+                name: self.get_name(),
+            },
+        )?;
+
+        receiver
+            .await
+            .context(nexus_uri::CancelBdev {
+                name: self.get_name(),
+            })?
+            .context(nexus_uri::CreateBdev {
+                name: self.get_name(),
+            })?;
+
+        if let Some(mut bdev) = UntypedBdev::lookup_by_name(&self.name) {
+            if let Some(uuid) = self.uuid {
+                unsafe { bdev.set_raw_uuid(uuid.into()) };
+            }
+
+            if !bdev.add_alias(&self.alias) {
+                error!(
+                    "failed to add alias {} to device {}",
+                    self.alias,
+                    self.get_name()
+                );
+            }
+
+            return Ok(self.get_name());
+        }
+
+        Err(NexusBdevError::BdevNotFound {
+            name: self.get_name(),
+        })
+    }
+
+    /// Log out of the target and destroy the bdev of the LUN
+    async fn destroy(self: Box<Self>) -> Result<(), Self::Error> {
+        match UntypedBdev::lookup_by_name(&self.name) {
+            Some(mut bdev) => {
+                bdev.remove_alias(&self.alias);
+                let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
+                unsafe {
+                    delete_iscsi_disk(
+                        bdev.unsafe_inner_mut_ptr(),
+                        Some(done_errno_cb),
+                        cb_arg(sender),
+                    );
+                }
+                receiver
+                    .await
+                    .context(nexus_uri::CancelBdev {
+                        name: self.get_name(),
+                    })?
+                    .context(nexus_uri::DestroyBdev {
+                        name: self.get_name(),
+                    })
+            }
+            None => Err(NexusBdevError::BdevNotFound {
+                name: self.get_name(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use url::Url;
+
+    use super::Iscsi;
+
+    #[test]
+    fn iscsi_uri() {
+        let url =
+            Url::parse("iscsi://10.0.0.1/iqn.2001-04.com.example:disk1/3")
+                .unwrap();
+        let iscsi = Iscsi::try_from(&url).unwrap();
+        assert_eq!(iscsi.name, "10.0.0.1:3260/iqn.2001-04.com.example:disk1/3");
+        assert_eq!(
+            iscsi.iscsi_url,
+            "iscsi://10.0.0.1:3260/iqn.2001-04.com.example:disk1/3"
+        );
+        assert_eq!(iscsi.initiator_iqn, "iqn.2019-05.io.openebs:mayastor");
+
+        let url = Url::parse(
+            "iscsi://[fd00::1]:3261/iqn.2001-04.com.example:disk1\
+             ?initiator_iqn=iqn.2019-05.io.openebs:node1",
+        )
+        .unwrap();
+        let iscsi = Iscsi::try_from(&url).unwrap();
+        assert_eq!(
+            iscsi.iscsi_url,
+            "iscsi://[fd00::1]:3261/iqn.2001-04.com.example:disk1/0"
+        );
+        assert_eq!(iscsi.initiator_iqn, "iqn.2019-05.io.openebs:node1");
+
+        for uri in &[
+            "iscsi://10.0.0.1/",
+            "iscsi://10.0.0.1/iqn.2001-04.com.example:disk1/lun",
+            "iscsi://10.0.0.1/iqn.2001-04.com.example:disk1/0/1",
+            "iscsi://10.0.0.1/iqn.2001-04.com.example:disk1?chap=1",
+        ] {
+            assert!(Iscsi::try_from(&Url::parse(uri).unwrap()).is_err());
+        }
+    }
+}
@@ -14,6 +14,7 @@ mod aio;
 pub(crate) mod dev;
 pub(crate) use dev::uri;
 pub(crate) mod device;
+mod iscsi;
 mod loopback;
 mod malloc;
 pub mod nexus;
@@ -22,6 +23,7 @@ pub mod null_ng;
 mod nvme;
 mod nvmf;
 pub(crate) mod nvmx;
+mod pmem;
 mod uring;
 pub mod util;
 
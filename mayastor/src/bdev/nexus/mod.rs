@@ -3,19 +3,77 @@
 use futures::{future::Future, FutureExt};
 use std::pin::Pin;
 
+use crate::subsys::ShareNaming;
+
+mod nexus_access;
+mod nexus_backpressure;
 mod nexus_bdev;
 mod nexus_bdev_children;
 mod nexus_bdev_rebuild;
 mod nexus_bdev_snapshot;
+mod nexus_block_emu;
+mod nexus_cache;
 mod nexus_channel;
+mod nexus_checksum;
 mod nexus_child;
+mod nexus_child_snapshot;
+mod nexus_child_state;
+mod nexus_compare;
+mod nexus_crypto;
+mod nexus_direct;
+mod nexus_fence;
 mod nexus_io;
+mod nexus_io_debug;
+mod nexus_io_limit;
 mod nexus_iter;
+mod nexus_journal;
+mod nexus_latency;
+mod nexus_migrate;
 mod nexus_module;
 mod nexus_nbd;
+mod nexus_options;
 mod nexus_persistence;
+mod nexus_protect;
+mod nexus_qos;
+mod nexus_read_ahead;
+mod nexus_read_cache;
+mod nexus_read_latency;
+mod nexus_rebuild_window;
+mod nexus_replication;
+mod nexus_retry;
+mod nexus_sgl;
 mod nexus_share;
+#[cfg(test)]
+mod nexus_sim;
+mod nexus_size_policy;
+mod nexus_snapshot_schedule;
+mod nexus_standby;
+mod nexus_tier;
+mod nexus_topology;
+mod nexus_trace;
+mod nexus_validate;
+mod nexus_write_lock;
 
+pub use nexus_access::{AccessMode, NexusHosts};
+pub(crate) use nexus_bdev::{
+    max_children,
+    nexus_create_internal,
+    CreateChecksums,
+    CreateChild,
+    CreateReadCache,
+    CreateRebuild,
+    CreateReplication,
+    OpenChild,
+    RebuildJobNotFound,
+    RebuildOperation,
+    RemoveRebuildJob,
+    ResolveKey,
+    ShareNbdNexus,
+    ShareNvmfNexus,
+    TraceFile,
+    UnshareNexus,
+    NEXUS_PRODUCT_ID,
+};
 pub use nexus_bdev::{
     nexus_create,
     nexus_create_v2,
@@ -28,24 +86,21 @@ pub use nexus_bdev::{
     NvmeAnaState,
     VerboseError,
 };
-pub(crate) use nexus_bdev::{
-    CreateChild,
-    CreateRebuild,
-    OpenChild,
-    RebuildJobNotFound,
-    RebuildOperation,
-    RemoveRebuildJob,
-    ShareNbdNexus,
-    ShareNvmfNexus,
-    UnshareNexus,
-    NEXUS_PRODUCT_ID,
+pub(crate) use nexus_block_emu::{
+    block_len_compatible,
+    BlockEmulation,
+    EmulatedHandle,
 };
+pub use nexus_cache::NexusCacheOpts;
+pub(crate) use nexus_cache::{CacheChannel, CacheRead, CacheWrite, WriteCache};
 pub(crate) use nexus_channel::{
     fault_nexus_child,
     DrEvent,
     NexusChannel,
     NexusChannelInner,
 };
+pub use nexus_checksum::ChecksumStats;
+pub(crate) use nexus_checksum::{checksum_repair, ChecksumStore};
 pub use nexus_child::{
     lookup_nexus_child,
     ChildError,
@@ -53,19 +108,102 @@ pub use nexus_child::{
     NexusChild,
     Reason,
 };
-pub(crate) use nexus_io::{nexus_submit_request, NioCtx};
+pub(crate) use nexus_child_snapshot::{ChildRole, ChildSnapshot};
+pub(crate) use nexus_child_state::{ChildStates, RebuildOutcome};
+pub(crate) use nexus_compare::compare_and_write;
+pub use nexus_crypto::nexus_create_encrypted;
+pub(crate) use nexus_crypto::{CryptBuf, CryptoKey, NexusCrypto};
+pub(crate) use nexus_direct::is_direct;
+pub(crate) use nexus_fence::{
+    defer_epoch_claim,
+    take_deferred_claim,
+    EpochLease,
+    EpochQueue,
+};
+pub(crate) use nexus_io::{
+    nexus_admit_checked,
+    nexus_admit_request,
+    nexus_child_retire,
+    nexus_complete_miscompare,
+    nexus_complete_read,
+    nexus_complete_request,
+    nexus_flush_request,
+    nexus_resubmit_request,
+    nexus_submit_request,
+    NioCtx,
+};
+pub(crate) use nexus_io_debug::IoDebugLog;
+pub use nexus_io_debug::{IoDebugEvent, IoDebugKind, IoDebugStats};
+pub use nexus_io_limit::IoLimitStats;
+pub(crate) use nexus_io_limit::{IoLimit, LimitQueue};
+pub(crate) use nexus_iter::{nexus_index_uuid, nexus_unindex_uuid};
 pub use nexus_iter::{
     nexus_iter,
     nexus_iter_mut,
     nexus_lookup,
+    nexus_lookup_any_mut,
     nexus_lookup_mut,
     nexus_lookup_name_uuid,
     nexus_lookup_uuid_mut,
 };
+pub(crate) use nexus_journal::{journal_writeback, WriteJournal};
+pub(crate) use nexus_latency::ChildLatencies;
+pub use nexus_latency::{
+    ChildLatency,
+    SlowChildEvent,
+    SlowChildOpts,
+    SlowChildStatus,
+};
+pub use nexus_migrate::{nexus_migrate_prepare, MigrationRole};
 pub(crate) use nexus_module::{NexusModule, NEXUS_MODULE_NAME};
 pub(crate) use nexus_nbd::{NbdDisk, NbdError};
+pub use nexus_options::{
+    NexusIoOpts,
+    NexusOptions,
+    NexusOptionsUpdate,
+    ReadPolicy,
+};
 pub(crate) use nexus_persistence::PersistOp;
 pub use nexus_persistence::{ChildInfo, NexusInfo};
+pub(crate) use nexus_qos::{qos_group_refresh, QosChannel, QosLimiter};
+pub use nexus_qos::{qos_group_set, qos_groups, NexusQos, QosGroup};
+pub use nexus_read_ahead::ReadAheadStats;
+pub(crate) use nexus_read_ahead::{ReadAhead, ReadAheadCounters};
+pub(crate) use nexus_read_cache::{ReadCache, ReadCacheChannel};
+pub(crate) use nexus_read_latency::ReadLatencies;
+pub(crate) use nexus_rebuild_window::rebuild_admit;
+pub use nexus_rebuild_window::{
+    rebuild_windows,
+    set_rebuild_windows,
+    RebuildWindow,
+    RebuildWindowStatus,
+};
+pub(crate) use nexus_replication::{ChangeTracker, Replication};
+pub use nexus_replication::{
+    ReplicationOpts,
+    ReplicationState,
+    ReplicationStatus,
+};
+pub use nexus_retry::ChannelMemoryPressure;
+pub(crate) use nexus_retry::RetryQueue;
+pub(crate) use nexus_sgl::{Bounce, BounceBuf, SglCounters};
+pub use nexus_sgl::{ChildSglCaps, SglStats};
+pub use nexus_share::DrainOpts;
+pub(crate) use nexus_size_policy::default_size_policy;
+pub use nexus_size_policy::{nexus_create_with_size_policy, ChildSizePolicy};
+pub use nexus_snapshot_schedule::SnapshotSchedule;
+pub(crate) use nexus_snapshot_schedule::SnapshotScheduler;
+pub(crate) use nexus_tier::NexusTier;
+pub use nexus_tier::{TieringOpts, TieringStatus};
+pub use nexus_topology::{Layer, LayerKind};
+pub(crate) use nexus_trace::NexusTrace;
+pub use nexus_trace::{TraceOp, TraceOpts, TraceRecord, TraceStats};
+pub use nexus_validate::{
+    nexus_validate_create,
+    ChildValidation,
+    NexusValidation,
+};
+pub(crate) use nexus_write_lock::WriteLocks;
 
 /// TODO
 #[derive(Deserialize)]
@@ -78,6 +216,12 @@ struct NexusShareArgs {
     cntlid_min: u16,
     /// TODO
     cntlid_max: u16,
+    /// logical block size to expose, 512 or 4096, if the bdev is a nexus
+    #[serde(default)]
+    block_size: Option<u32>,
+    /// names the share exposes instead of those of the target configuration
+    #[serde(default, flatten)]
+    naming: ShareNaming,
 }
 
 /// TODO
@@ -87,13 +231,57 @@ struct NexusShareReply {
     uri: String,
 }
 
+/// Arguments of the nexus_rename json-rpc method.
+#[derive(Deserialize)]
+struct NexusRenameArgs {
+    /// name or uuid of the nexus
+    name: String,
+    /// name the nexus is to be known by
+    new_name: String,
+}
+
+/// Arguments of the nexus_unshare json-rpc method.
+#[derive(Deserialize)]
+struct NexusUnshareArgs {
+    /// name or uuid of the nexus
+    name: String,
+    #[serde(flatten)]
+    drain: DrainOpts,
+}
+
 /// public function which simply calls register module
 pub fn register_module() {
     nexus_module::register_module();
+    nexus_migrate::register_jsonrpc_methods();
+    nexus_qos::register_jsonrpc_methods();
+    nexus_cache::register_jsonrpc_methods();
+    nexus_read_cache::register_jsonrpc_methods();
+    nexus_checksum::register_jsonrpc_methods();
+    nexus_trace::register_jsonrpc_methods();
+    nexus_protect::register_jsonrpc_methods();
+    nexus_write_lock::register_jsonrpc_methods();
+    nexus_snapshot_schedule::register_jsonrpc_methods();
+    nexus_replication::register_jsonrpc_methods();
+    nexus_tier::register_jsonrpc_methods();
+    nexus_latency::register_jsonrpc_methods();
+    nexus_options::register_jsonrpc_methods();
+    nexus_topology::register_jsonrpc_methods();
+    nexus_io_debug::register_jsonrpc_methods();
+    nexus_access::register_jsonrpc_methods();
+    nexus_sgl::register_jsonrpc_methods();
+    nexus_retry::register_jsonrpc_methods();
+    nexus_io_limit::register_jsonrpc_methods();
+    nexus_validate::register_jsonrpc_methods();
+    nexus_size_policy::register_jsonrpc_methods();
+    nexus_crypto::register_jsonrpc_methods();
+    nexus_standby::register_jsonrpc_methods();
+    nexus_rebuild_window::register_jsonrpc_methods();
+    nexus_read_ahead::register_jsonrpc_methods();
 
     use crate::{
         core::{Share, UntypedBdev},
-        jsonrpc::{jsonrpc_register, Code, JsonRpcError, Result},
+        jsonrpc::{jsonrpc_register, Code, JsonRpcError, Result, RpcErrorCode},
+        subsys::set_share_naming,
     };
 
     jsonrpc_register(
@@ -108,6 +296,31 @@ pub fn register_module() {
                         message: "invalid protocol".to_string(),
                     });
                 }
+                if let Some(blk_size) = args.block_size {
+                    match nexus_lookup_any_mut(&args.name) {
+                        Some(nexus) => nexus
+                            .set_logical_block_size(blk_size)
+                            .await
+                            .map_err(|e| JsonRpcError {
+                                code: e.rpc_error_code(),
+                                message: e.to_string(),
+                            })?,
+                        None => {
+                            return Err(JsonRpcError {
+                                code: Code::InvalidParams,
+                                message: "block size can only be set for a nexus".to_string(),
+                            })
+                        }
+                    }
+                }
+                if args.naming != ShareNaming::default() {
+                    set_share_naming(&args.name, args.naming).map_err(|e| {
+                        JsonRpcError {
+                            code: e.rpc_error_code(),
+                            message: e.to_string(),
+                        }
+                    })?;
+                }
                 if let Some(mut bdev) = UntypedBdev::lookup_by_name(&args.name) {
                     let mut bdev = Pin::new(&mut bdev);
                     match proto.as_str() {
@@ -138,6 +351,38 @@ pub fn register_module() {
             Box::pin(f.boxed_local())
         },
     );
+
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_unshare",
+        |args: NexusUnshareArgs| {
+            async move {
+                match nexus_lookup_any_mut(&args.name) {
+                    Some(nexus) => {
+                        nexus.unshare_nexus_drained(args.drain).await
+                    }
+                    None => Err(Error::NexusNotFound {
+                        name: args.name,
+                    }),
+                }
+            }
+            .boxed_local()
+        },
+    );
+
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_rename",
+        |args: NexusRenameArgs| {
+            async move {
+                match nexus_lookup_any_mut(&args.name) {
+                    Some(nexus) => nexus.rename(&args.new_name).await,
+                    None => Err(Error::NexusNotFound {
+                        name: args.name,
+                    }),
+                }
+            }
+            .boxed_local()
+        },
+    );
 }
 
 /// called during shutdown so that all nexus children are in Destroying state
@@ -0,0 +1,150 @@
+//!
+//! Access mode of a nexus shared with several initiators.
+//!
+//! Any initiator may connect to a shared nexus, but by default the nexus
+//! assumes that a single host writes to it, which is the case of a volume
+//! mounted by one node. Clustered filesystems such as OCFS2 and GFS2 mount
+//! the volume on several nodes at once, all of them writing to it, and when
+//! a node fails the others recover it from the journal it wrote to the
+//! volume, relying on every write acknowledged to it being on the volume.
+//! The `multi_writer` access mode supports them explicitly:
+//!
+//! - the volatile write cache, which acknowledges writes before they are on the
+//!   children, can not be enabled on the nexus, and a nexus with a write cache
+//!   can not be switched to the mode
+//! - the read cache stays, as all hosts write through the nexus, which
+//!   invalidates what they write whichever channel it comes from
+//! - IO resubmitted after a child failed or while the channels are reconfigured
+//!   has not completed yet, so no write of another host can depend on it, and
+//!   it is resubmitted in both modes
+//!
+//! The mode is set with the `nexus_set_access_mode` json-rpc method, and
+//! `nexus_hosts` reports the mode along with the hosts connected to the
+//! nexus, one entry per controller. A nexus in `single_writer` mode with
+//! controllers of several hosts is reported as such.
+
+use std::collections::BTreeSet;
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use super::{nexus_lookup_any_mut, Error, Nexus};
+use crate::{
+    jsonrpc::jsonrpc_register,
+    subsys::{ConnectedHost, NvmfSubsystem},
+};
+
+/// How the hosts a nexus is shared with access it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessMode {
+    /// a single host writes to the nexus
+    SingleWriter,
+    /// several hosts read and write the nexus at once
+    MultiWriter,
+}
+
+impl Default for AccessMode {
+    fn default() -> Self {
+        Self::SingleWriter
+    }
+}
+
+/// The hosts connected to a nexus.
+#[derive(Debug, Clone, Serialize)]
+pub struct NexusHosts {
+    pub name: String,
+    pub mode: AccessMode,
+    /// one entry per controller, a host with several paths has several
+    pub hosts: Vec<ConnectedHost>,
+    /// set when several hosts are connected to a `single_writer` nexus
+    pub multiple_hosts: bool,
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the access mode of this nexus
+    pub fn access_mode(&self) -> AccessMode {
+        self.access_mode.load()
+    }
+
+    /// returns true if several hosts may write to this nexus at once
+    pub fn is_multi_writer(&self) -> bool {
+        self.access_mode() == AccessMode::MultiWriter
+    }
+
+    /// Set the access mode of this nexus.
+    pub fn set_access_mode(&self, mode: AccessMode) -> Result<(), Error> {
+        if mode == AccessMode::MultiWriter && self.write_cache().is_some() {
+            return Err(Error::InvalidArguments {
+                name: self.name.clone(),
+                args: "a nexus with a write cache can not have several \
+                    writers"
+                    .to_string(),
+            });
+        }
+        if self.access_mode.swap(mode) != mode {
+            info!("{}: access mode set to {:?}", self.name, mode);
+        }
+        Ok(())
+    }
+
+    /// returns the hosts connected to this nexus
+    pub fn hosts(&self) -> NexusHosts {
+        let hosts = NvmfSubsystem::nqn_lookup(&self.name)
+            .map(|s| s.connected_hosts())
+            .unwrap_or_default();
+        let distinct =
+            hosts.iter().map(|h| &h.host_nqn).collect::<BTreeSet<_>>();
+        NexusHosts {
+            name: self.name.clone(),
+            mode: self.access_mode(),
+            multiple_hosts: !self.is_multi_writer() && distinct.len() > 1,
+            hosts,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetModeArgs {
+    /// name or uuid of the nexus
+    name: String,
+    mode: AccessMode,
+}
+
+#[derive(Debug, Deserialize)]
+struct HostsArgs {
+    /// name or uuid of the nexus
+    name: String,
+}
+
+async fn set_mode(args: SetModeArgs) -> Result<NexusHosts, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.set_access_mode(args.mode)?;
+    Ok(nexus.hosts())
+}
+
+async fn hosts(args: HostsArgs) -> Result<NexusHosts, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    Ok(nexus.hosts())
+}
+
+/// Register the json-rpc methods to set the access mode of nexuses and to
+/// list their hosts.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_set_access_mode",
+        |args: SetModeArgs| set_mode(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>("nexus_hosts", |args: HostsArgs| {
+        hosts(args).boxed_local()
+    });
+}
@@ -0,0 +1,41 @@
+//!
+//! Back-pressure on the initiators of a nexus.
+//!
+//! IO a nexus can not submit to its children right away is held back on the
+//! channel it was submitted on: by the QoS limits, while the channel is
+//! reconfigured, while a child has no memory for it, or while the nexus is
+//! at its limit of outstanding IOs. Under overload those
+//! queues grow as long as the initiators keep submitting, and with them the
+//! memory of the nexus. Once the IOs held back on a channel reach the
+//! back-pressure depth of the nexus, new IO is completed with NOMEM instead.
+//! The bdev layer keeps such IO and submits it again only as IO of the nexus
+//! completes, so the NVMf target runs out of requests for its queue pairs and
+//! stops taking commands from the hosts: what is outstanding is bounded by
+//! the queue depth the target grants rather than by the memory of the nexus.
+//! IO that is held back already is not pushed back, which keeps its order.
+//!
+//! The number of IOs held back and pushed back per channel is reported with
+//! the memory pressure by `nexus_memory_pressure`.
+
+use super::NexusChannelInner;
+
+impl NexusChannelInner {
+    /// returns the number of IOs held back on the channel
+    pub(crate) fn held(&self) -> usize {
+        self.retry_held()
+            + self.limit_held()
+            + self.epoch_held()
+            + self.qos.as_ref().map_or(0, |q| q.held())
+    }
+
+    /// Returns true if new IO must be pushed back to the initiator for too
+    /// many IOs being held back on the channel, counting it.
+    pub(crate) fn push_back(&mut self) -> bool {
+        let depth = self.opts.io_backpressure_depth as usize;
+        if depth == 0 || self.held() < depth {
+            return false;
+        }
+        self.retry.pushed_back += 1;
+        true
+    }
+}
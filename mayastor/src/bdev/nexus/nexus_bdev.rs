@@ -10,6 +10,7 @@ use std::{
     mem::MaybeUninit,
     os::raw::c_void,
     pin::Pin,
+    sync::Arc,
 };
 
 use crossbeam::atomic::AtomicCell;
@@ -19,24 +20,57 @@ use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 use tonic::{Code, Status};
 use uuid::Uuid;
+use zeroize::Zeroizing;
 
 use super::{
+    default_size_policy,
+    journal_writeback,
+    nexus_index_uuid,
     nexus_lookup_name_uuid,
     nexus_submit_request,
+    nexus_unindex_uuid,
+    qos_group_refresh,
+    AccessMode,
+    ChangeTracker,
+    ChecksumStore,
     ChildError,
+    ChildLatencies,
+    ChildSizePolicy,
+    ChildSnapshot,
     ChildState,
+    CryptoKey,
     DrEvent,
+    EpochLease,
+    IoDebugLog,
+    IoLimit,
     NbdDisk,
     NbdError,
+    NexusCacheOpts,
     NexusChannel,
     NexusChild,
+    NexusCrypto,
+    NexusIoOpts,
     NexusModule,
+    NexusQos,
+    NexusTier,
+    NexusTrace,
     PersistOp,
+    QosLimiter,
+    ReadAheadCounters,
+    ReadCache,
+    Reason,
+    Replication,
+    SglCounters,
+    SnapshotScheduler,
+    WriteCache,
+    WriteJournal,
+    WriteLocks,
 };
 
 use crate::{
     bdev::{device_destroy, nexus::nexus_persistence::PersistentNexusInfo},
     core::{
+        for_each_channel,
         Bdev,
         BdevHandle,
         Command,
@@ -44,14 +78,20 @@ use crate::{
         Cores,
         DeviceEventSink,
         IoType,
+        ProtectionInfo,
         Protocol,
-        Reactor,
+        Reactors,
         Share,
+        Snapshot,
+        UntypedBdev,
         MWQ,
     },
+    grpc::error_detail::ErrorDetail,
+    jsonrpc::{Code as JsonRpcCode, RpcErrorCode},
+    kms::kms_defs::KmsError,
     nexus_uri::NexusBdevError,
-    rebuild::RebuildError,
-    subsys::{NvmfError, NvmfSubsystem},
+    rebuild::{RebuildError, RebuildJob},
+    subsys::{Config, NvmfError, NvmfSubsystem},
 };
 
 use spdk_rs::{
@@ -108,12 +148,22 @@ pub enum Error {
     UuidExists { uuid: String, nexus: String },
     #[snafu(display("Nexus with name \"{}\" already exists", name))]
     NameExists { name: String },
+    #[snafu(display("Nexus {} is protected against deletion", name))]
+    Protected { name: String },
     #[snafu(display("Invalid encryption key"))]
     InvalidKey {},
     #[snafu(display("Failed to create crypto bdev for nexus {}", name))]
     CreateCryptoBdev { source: Errno, name: String },
     #[snafu(display("Failed to destroy crypto bdev for nexus {}", name))]
     DestroyCryptoBdev { source: Errno, name: String },
+    #[snafu(display(
+        "Encryption of nexus {} can not be combined with its {}",
+        name,
+        feature
+    ))]
+    CryptoConflict { name: String, feature: String },
+    #[snafu(display("Failed to get the encryption key of nexus {}", name))]
+    ResolveKey { source: KmsError, name: String },
     #[snafu(display(
         "The nexus {} has been already shared with a different protocol",
         name
@@ -155,16 +205,34 @@ pub enum Error {
         num_blocks: u64,
         block_size: u64,
     },
-    #[snafu(display("Children of nexus {} have mixed block sizes", name))]
+    #[snafu(display(
+        "Children of nexus {} have block sizes that are not multiples of \
+        each other",
+        name
+    ))]
     MixedBlockSizes { name: String },
+    #[snafu(display(
+        "Children of nexus {} interleave protection information with their \
+        data differently",
+        name
+    ))]
+    MixedProtectionInfo { name: String },
     #[snafu(display(
         "Child {} of nexus {} has incompatible size or block size",
         child,
         name
     ))]
     ChildGeometry { child: String, name: String },
+    #[snafu(display("Failed to resize child {} of nexus {}", child, name))]
+    ChildResize {
+        source: crate::lvs::Error,
+        child: String,
+        name: String,
+    },
     #[snafu(display("Child {} of nexus {} cannot be found", child, name))]
     ChildMissing { child: String, name: String },
+    #[snafu(display("Child {} of nexus {} is not on standby", child, name))]
+    ChildNotStandby { child: String, name: String },
     #[snafu(display("Child {} of nexus {} has no error store", child, name))]
     ChildMissingErrStore { child: String, name: String },
     #[snafu(display(
@@ -223,6 +291,18 @@ pub enum Error {
     ChildNotFound { child: String, name: String },
     #[snafu(display("Child {} of nexus {} already exists", child, name))]
     ChildAlreadyExists { child: String, name: String },
+    #[snafu(display(
+        "Nexus {} can not have more than {} children",
+        name,
+        max
+    ))]
+    TooManyChildren { name: String, max: usize },
+    #[snafu(display(
+        "Nexus {} can not expose a block size of {}, it must be 512 or 4096",
+        name,
+        blk_size
+    ))]
+    InvalidBlockSize { name: String, blk_size: u32 },
     #[snafu(display("Failed to pause child {} of nexus {}", child, name))]
     PauseChild { child: String, name: String },
     #[snafu(display("Suitable rebuild source for nexus {} not found", name))]
@@ -273,8 +353,41 @@ pub enum Error {
     InvalidNvmeAnaState { ana_value: i32 },
     #[snafu(display("Invalid arguments for nexus {}: {}", name, args))]
     InvalidArguments { name: String, args: String },
+    #[snafu(display(
+        "Nexus {} still has {} IOs in flight after draining",
+        name,
+        in_flight
+    ))]
+    DrainTimeout { name: String, in_flight: u64 },
     #[snafu(display("Failed to create nexus {}", name))]
     NexusCreate { name: String },
+    #[snafu(display("Failed to create read cache of nexus {}", name))]
+    CreateReadCache {
+        source: NexusBdevError,
+        name: String,
+    },
+    #[snafu(display("Failed to open read cache of nexus {}", name))]
+    OpenReadCache { source: CoreError, name: String },
+    #[snafu(display("Failed to create checksum sidecar of nexus {}", name))]
+    CreateChecksums {
+        source: NexusBdevError,
+        name: String,
+    },
+    #[snafu(display("Failed to open checksum sidecar of nexus {}", name))]
+    OpenChecksums { source: CoreError, name: String },
+    #[snafu(display("Failed to create replication target of nexus {}", name))]
+    CreateReplication {
+        source: NexusBdevError,
+        name: String,
+    },
+    #[snafu(display("Failed to open replication target of nexus {}", name))]
+    OpenReplication { source: CoreError, name: String },
+    #[snafu(display("Failed to open trace file {} of nexus {}", path, name))]
+    TraceFile {
+        source: std::io::Error,
+        name: String,
+        path: String,
+    },
     #[snafu(display("Failed to destroy nexus {}", name))]
     NexusDestroy { name: String },
     #[snafu(display(
@@ -311,7 +424,8 @@ impl From<NvmfError> for Error {
 
 impl From<Error> for tonic::Status {
     fn from(e: Error) -> Self {
-        match e {
+        let detail = ErrorDetail::from(&e);
+        let status = match e {
             Error::NexusNotFound {
                 ..
             } => Status::not_found(e.to_string()),
@@ -321,6 +435,22 @@ impl From<Error> for tonic::Status {
             Error::InvalidKey {
                 ..
             } => Status::invalid_argument(e.to_string()),
+            Error::CryptoConflict {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            Error::Protected {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            Error::ResolveKey {
+                source:
+                    KmsError::InvalidKeyRef {
+                        ..
+                    },
+                ..
+            } => Status::invalid_argument(e.to_string()),
+            Error::ResolveKey {
+                ..
+            } => Status::unavailable(e.to_string()),
             Error::AlreadyShared {
                 ..
             } => Status::invalid_argument(e.to_string()),
@@ -330,12 +460,18 @@ impl From<Error> for tonic::Status {
             Error::NotSharedNvmf {
                 ..
             } => Status::invalid_argument(e.to_string()),
+            Error::DrainTimeout {
+                ..
+            } => Status::deadline_exceeded(e.to_string()),
             Error::CreateChild {
                 ..
             } => Status::invalid_argument(e.to_string()),
             Error::MixedBlockSizes {
                 ..
             } => Status::invalid_argument(e.to_string()),
+            Error::MixedProtectionInfo {
+                ..
+            } => Status::invalid_argument(e.to_string()),
             Error::ChildGeometry {
                 ..
             } => Status::invalid_argument(e.to_string()),
@@ -345,10 +481,61 @@ impl From<Error> for tonic::Status {
             Error::DestroyLastChild {
                 ..
             } => Status::invalid_argument(e.to_string()),
+            Error::TooManyChildren {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            Error::InvalidBlockSize {
+                ..
+            } => Status::invalid_argument(e.to_string()),
             Error::ChildNotFound {
                 ..
             } => Status::not_found(e.to_string()),
             e => Status::new(Code::Internal, e.to_string()),
+        };
+        detail.attach(status)
+    }
+}
+
+impl RpcErrorCode for Error {
+    fn rpc_error_code(&self) -> JsonRpcCode {
+        match self {
+            Error::NexusNotFound {
+                ..
+            }
+            | Error::ChildNotFound {
+                ..
+            } => JsonRpcCode::NotFound,
+            Error::NameExists {
+                ..
+            }
+            | Error::UuidExists {
+                ..
+            } => JsonRpcCode::AlreadyExists,
+            Error::InvalidUuid {
+                ..
+            }
+            | Error::InvalidKey {
+                ..
+            }
+            | Error::InvalidArguments {
+                ..
+            }
+            | Error::NotSharedNvmf {
+                ..
+            }
+            | Error::TooManyChildren {
+                ..
+            }
+            | Error::InvalidBlockSize {
+                ..
+            }
+            | Error::Protected {
+                ..
+            }
+            | Error::ChildNotStandby {
+                ..
+            } => JsonRpcCode::InvalidParams,
+            _ => JsonRpcCode::InternalError,
         }
     }
 }
@@ -445,6 +632,8 @@ pub struct Nexus<'n> {
     /// be larger. The actual Nexus size will be calculated based on the
     /// capabilities of the underlying child devices.
     pub(crate) req_size: u64,
+    /// what to do with children smaller than the requested size
+    pub(crate) size_policy: ChildSizePolicy,
     /// number of children part of this nexus
     pub(crate) child_count: u32,
     /// vector of children
@@ -465,12 +654,63 @@ pub struct Nexus<'n> {
     /// enum containing the protocol-specific target used to publish the nexus
     pub nexus_target: Option<NexusTarget>,
     /// Indicates if the Nexus has an I/O device.
-    has_io_device: bool,
+    pub(crate) has_io_device: bool,
     /// Nexus pause counter to allow concurrent pause/resume.
     pause_state: AtomicCell<NexusPauseState>,
     pause_waiters: Vec<oneshot::Sender<i32>>,
     /// Information associated with the persisted NexusInfo structure.
     pub nexus_info: futures::lock::Mutex<PersistentNexusInfo>,
+    /// Set when a nexus with a newer epoch took over the replicas.
+    pub(crate) fenced: AtomicCell<bool>,
+    /// Ownership of the persistent entry, checked before writes.
+    pub(crate) epoch_lease: EpochLease,
+    /// Set when the nexus must not be destroyed without force.
+    pub(crate) protected: AtomicCell<bool>,
+    /// Whether one or several hosts write to the nexus.
+    pub(crate) access_mode: AtomicCell<AccessMode>,
+    /// Number of reads and writes by how their data went to the children.
+    pub(crate) sgl_counters: SglCounters,
+    /// IO outstanding on the nexus under its limit.
+    pub(crate) io_limit: IoLimit,
+    /// QoS limits of the nexus.
+    pub(crate) qos: parking_lot::Mutex<NexusQos>,
+    /// Limiter enforcing the QoS limits, shared by all channels.
+    pub(crate) qos_limiter: parking_lot::Mutex<Option<Arc<QosLimiter>>>,
+    /// Volatile write cache, shared by all channels.
+    pub(crate) write_cache: parking_lot::Mutex<Option<Arc<WriteCache>>>,
+    /// Read cache on a local device, shared by all channels.
+    pub(crate) read_cache: parking_lot::Mutex<Option<Arc<ReadCache>>>,
+    /// Data generation and extents read ahead, over all channels.
+    pub(crate) read_ahead: ReadAheadCounters,
+    /// Checksums of the blocks of the nexus, shared by all channels.
+    pub(crate) checksums: parking_lot::Mutex<Option<Arc<ChecksumStore>>>,
+    /// Encryption of the data of the nexus, shared by all channels.
+    pub(crate) crypto: parking_lot::Mutex<Option<Arc<NexusCrypto>>>,
+    /// key the nexus was created with, to encrypt its data once it is open
+    pub(crate) crypto_key: Option<CryptoKey>,
+    /// Trace of the IOs of the nexus, shared by all channels.
+    pub(crate) trace: parking_lot::Mutex<Option<Arc<NexusTrace>>>,
+    /// Debug log of the routing of the IOs, shared by all channels.
+    pub(crate) io_debug: parking_lot::Mutex<Option<Arc<IoDebugLog>>>,
+    /// Range locks serializing overlapping writes, shared by all channels.
+    pub(crate) write_locks: WriteLocks,
+    /// Journal of the recent writes on the children, shared by all channels.
+    pub(crate) journal: Option<Arc<WriteJournal>>,
+    /// Schedule of the snapshots of the nexus.
+    pub(crate) snapshot_schedule:
+        parking_lot::Mutex<Option<Arc<SnapshotScheduler>>>,
+    /// Segments written to since they were last replicated.
+    pub(crate) changes: ChangeTracker,
+    /// Replication of the nexus to a remote replica.
+    pub(crate) replication: parking_lot::Mutex<Option<Arc<Replication>>>,
+    /// Tiering of the data between a fast child and the others.
+    pub(crate) tier: parking_lot::Mutex<Option<Arc<NexusTier>>>,
+    /// latencies of the children, when slow children are detected
+    pub(crate) child_latencies: parking_lot::Mutex<Option<Arc<ChildLatencies>>>,
+    /// options of the IO path, copied to every channel
+    pub(crate) io_opts: parking_lot::Mutex<NexusIoOpts>,
+    /// children the channels submit IO to, as of the last reconfiguration
+    pub(crate) child_snapshot: Snapshot<ChildSnapshot>,
     /// TODO
     event_sink: Option<DeviceEventSink>,
     /// Prevent auto-Unpin.
@@ -526,7 +766,6 @@ impl ToString for NexusStatus {
 
 /// TODO
 struct UpdateFailFastCtx {
-    sender: oneshot::Sender<bool>,
     nexus: String,
     child: Option<String>,
 }
@@ -542,14 +781,6 @@ fn update_failfast_cb(
     ChannelTraverseStatus::Ok
 }
 
-/// TODO
-fn update_failfast_done(
-    _status: ChannelTraverseStatus,
-    ctx: UpdateFailFastCtx,
-) {
-    ctx.sender.send(true).expect("Receiver disappeared");
-}
-
 impl<'n> Nexus<'n> {
     /// create a new nexus instance with optionally directly attaching
     /// children to it.
@@ -571,6 +802,7 @@ impl<'n> Nexus<'n> {
             data_ent_offset: 0,
             share_handle: None,
             req_size: size,
+            size_policy: ChildSizePolicy::default(),
             nexus_target: None,
             nvme_params,
             has_io_device: false,
@@ -580,6 +812,35 @@ impl<'n> Nexus<'n> {
                 nexus_info_key,
             )),
             nexus_uuid: Default::default(),
+            fenced: AtomicCell::new(false),
+            epoch_lease: EpochLease::default(),
+            protected: AtomicCell::new(false),
+            access_mode: AtomicCell::new(AccessMode::default()),
+            sgl_counters: SglCounters::default(),
+            io_limit: IoLimit::default(),
+            qos: parking_lot::Mutex::new(NexusQos::default()),
+            qos_limiter: parking_lot::Mutex::new(None),
+            write_cache: parking_lot::Mutex::new(None),
+            read_cache: parking_lot::Mutex::new(None),
+            read_ahead: ReadAheadCounters::default(),
+            checksums: parking_lot::Mutex::new(None),
+            crypto: parking_lot::Mutex::new(None),
+            crypto_key: None,
+            trace: parking_lot::Mutex::new(None),
+            io_debug: parking_lot::Mutex::new(None),
+            write_locks: WriteLocks::default(),
+            journal: if Config::get().nexus_opts.write_journal {
+                Some(Arc::new(WriteJournal::new()))
+            } else {
+                None
+            },
+            snapshot_schedule: parking_lot::Mutex::new(None),
+            changes: ChangeTracker::default(),
+            replication: parking_lot::Mutex::new(None),
+            tier: parking_lot::Mutex::new(None),
+            child_latencies: parking_lot::Mutex::new(None),
+            io_opts: parking_lot::Mutex::new(NexusIoOpts::default()),
+            child_snapshot: Snapshot::default(),
             event_sink: None,
             _pin: Default::default(),
         };
@@ -605,6 +866,11 @@ impl<'n> Nexus<'n> {
             // Set the nexus UUID to be the specified nexus UUID, otherwise
             // inherit the bdev UUID.
             n.nexus_uuid = nexus_uuid.unwrap_or_else(|| n.bdev().uuid());
+
+            if let Some(journal) = n.journal.clone() {
+                Reactors::master()
+                    .send_future(journal_writeback(name.to_string(), journal));
+            }
         }
 
         // register children
@@ -700,21 +966,13 @@ impl<'n> Nexus<'n> {
             self.name, event
         );
 
-        let (sender, recv) = oneshot::channel::<ChannelTraverseStatus>();
+        self.publish_children();
 
-        self.traverse_io_channels(
-            |chan, _sender| -> ChannelTraverseStatus {
-                chan.inner_mut().refresh();
-                ChannelTraverseStatus::Ok
-            },
-            |status, sender| {
-                info!("{}: Reconfigure completed", self.name);
-                sender.send(status).expect("reconfigure channel gone");
-            },
-            sender,
-        );
-
-        let result = recv.await.expect("reconfigure sender already dropped");
+        let result = for_each_channel(self, (), |chan, _| {
+            chan.inner_mut().refresh();
+            ChannelTraverseStatus::Ok
+        })
+        .await;
 
         info!(
             "{}: Dynamic reconfiguration event: {:?} completed {:?}",
@@ -734,6 +992,8 @@ impl<'n> Nexus<'n> {
         debug!("Opening nexus {}", nex.name);
 
         nex.as_mut().try_open_children().await?;
+        nex.publish_children();
+        nex.as_mut().open_crypto()?;
 
         // Register the bdev with SPDK and set the callbacks for io channel
         // creation.
@@ -783,6 +1043,23 @@ impl<'n> Nexus<'n> {
 
         self.as_mut().destroy_shares().await;
 
+        // write back whatever is left in the write cache
+        if self.write_cache().is_some() {
+            let _ = self.set_write_cache(NexusCacheOpts::default()).await;
+        }
+        if self.read_cache().is_some() {
+            let _ = self.set_read_cache(None).await;
+        }
+        if self.checksum_store().is_some() {
+            let _ = self.set_checksums(None).await;
+        }
+        // destage whatever is only on the fast child
+        if self.tier().is_some() {
+            let _ = self.as_mut().set_tiering(None).await;
+        }
+        self.stop_trace().await;
+        self.disable_io_debug().await;
+
         // wait for all rebuild jobs to be cancelled before proceeding with the
         // destruction of the nexus
         for child in self.children.iter() {
@@ -806,10 +1083,20 @@ impl<'n> Nexus<'n> {
         // Persist the fact that the nexus destruction has completed.
         self.persist(PersistOp::Shutdown).await;
 
+        let qos_group = self.qos().group;
+        let uuid = self.uuid();
+
         unsafe {
             let name = self.name.clone();
             match self.bdev_mut().unregister_bdev_async().await {
-                Ok(_) => Ok(()),
+                Ok(_) => {
+                    nexus_unindex_uuid(uuid);
+                    // the remaining members get the share of this nexus
+                    if let Some(group) = qos_group {
+                        qos_group_refresh(&group).await;
+                    }
+                    Ok(())
+                }
                 Err(_) => Err(Error::NexusDestroy {
                     name,
                 }),
@@ -817,6 +1104,78 @@ impl<'n> Nexus<'n> {
         }
     }
 
+    /// Rename the nexus. The new name is added as an alias of the nexus bdev,
+    /// through which the nexus can be opened and looked up, as SPDK can not
+    /// rename a bdev. The bdev name, and with it the NQN the nexus is shared
+    /// under, stays the one the nexus was created with, which is why a shared
+    /// nexus can not be renamed. The persisted nexus info is keyed by uuid
+    /// and is not affected.
+    pub async fn rename(
+        mut self: Pin<&mut Self>,
+        new_name: &str,
+    ) -> Result<(), Error> {
+        let name = self.name.clone();
+        if name == new_name {
+            return Ok(());
+        }
+
+        if nexus_lookup_name_uuid(new_name, None).is_some()
+            || UntypedBdev::lookup_by_name(new_name).is_some()
+        {
+            return Err(Error::NameExists {
+                name: new_name.to_string(),
+            });
+        }
+
+        if self.shared().is_some() || self.nexus_target.is_some() {
+            return Err(Error::AlreadyShared {
+                name,
+            });
+        }
+
+        // the caches, the checksum sidecar and the rebuild jobs look the
+        // nexus up by the name it had when they started
+        if self.write_cache.lock().is_some()
+            || self.read_cache.lock().is_some()
+            || self.checksums.lock().is_some()
+            || self
+                .children
+                .iter()
+                .any(|c| RebuildJob::lookup(&c.name).is_ok())
+        {
+            return Err(Error::InvalidArguments {
+                name,
+                args: "a nexus can not be renamed while it is cached, \
+                    checksummed or rebuilding"
+                    .to_string(),
+            });
+        }
+
+        let bdev_name = self.bdev_name();
+        if let Some(mut bdev) = UntypedBdev::lookup_by_name(&bdev_name) {
+            if !bdev.add_alias(new_name) {
+                return Err(Error::NameExists {
+                    name: new_name.to_string(),
+                });
+            }
+            if name != bdev_name {
+                bdev.remove_alias(&name);
+            }
+        }
+
+        info!("renaming nexus {} to {}", name, new_name);
+
+        unsafe {
+            let nexus = self.as_mut().get_unchecked_mut();
+            nexus.name = new_name.to_string();
+            for child in nexus.children.iter_mut() {
+                child.set_parent(new_name);
+            }
+        }
+        nexus_index_uuid(self.uuid(), new_name);
+        Ok(())
+    }
+
     /// Resume IO to the bdev.
     /// Note: in order to handle concurrent resumes properly, this function must
     /// be called only from the master core.
@@ -944,10 +1303,7 @@ impl<'n> Nexus<'n> {
         increment: bool,
         child: Option<String>,
     ) -> Result<(), Error> {
-        let (sender, r) = oneshot::channel::<bool>();
-
         let ctx = UpdateFailFastCtx {
-            sender,
             nexus: self.name.clone(),
             child,
         };
@@ -955,11 +1311,7 @@ impl<'n> Nexus<'n> {
         // let io_device = self.io_device.as_ref().expect("Nexus not opened");
         assert!(self.has_io_device);
 
-        self.traverse_io_channels(
-            update_failfast_cb,
-            update_failfast_done,
-            ctx,
-        );
+        let r = for_each_channel(self, ctx, update_failfast_cb);
 
         info!("{}: Updating fail-fast, increment={}", self.name, increment);
         r.await.expect("update failfast sender already dropped");
@@ -971,21 +1323,14 @@ impl<'n> Nexus<'n> {
         &self,
         child: Option<String>,
     ) -> Result<(), Error> {
-        let (sender, r) = oneshot::channel::<bool>();
-
         let ctx = UpdateFailFastCtx {
-            sender,
             nexus: self.name.clone(),
             child,
         };
 
         // if let Some(io_device) = self.io_device.as_ref() {
         if self.has_io_device {
-            self.traverse_io_channels(
-                update_failfast_cb,
-                update_failfast_done,
-                ctx,
-            );
+            let r = for_each_channel(self, ctx, update_failfast_cb);
 
             debug!(?self, "all channels retired");
             r.await.expect("update failfast sender already dropped");
@@ -1109,6 +1454,18 @@ impl<'n> Nexus<'n> {
         unimplemented!();
     }
 
+    /// Returns the protection information format shared by all children,
+    /// None if any child has none or they differ. The nexus does not pass
+    /// protection information through to its children yet, so this only
+    /// tells whether it could.
+    pub fn protection_info(&self) -> Option<ProtectionInfo> {
+        ProtectionInfo::common(
+            self.children
+                .iter()
+                .map(|c| c.get_device().ok().and_then(|d| d.protection_info())),
+        )
+    }
+
     /// Status of the nexus
     /// Online
     /// All children must also be online
@@ -1119,7 +1476,13 @@ impl<'n> Nexus<'n> {
     /// Faulted
     /// No child is online so the nexus is faulted
     /// This may be made more configurable in the future
+    ///
+    /// A fenced nexus is always faulted.
     pub fn status(&self) -> NexusStatus {
+        if self.is_fenced() {
+            return NexusStatus::Faulted;
+        }
+
         match *self.state.lock() {
             NexusState::Init => NexusStatus::Degraded,
             NexusState::Closed => NexusStatus::Faulted,
@@ -1127,8 +1490,15 @@ impl<'n> Nexus<'n> {
                 if self
                     .children
                     .iter()
-                    // All children are online, so the Nexus is also online
-                    .all(|c| c.state() == ChildState::Open)
+                    // All children are online, so the Nexus is also online,
+                    // children on standby are not counted
+                    .all(|c| {
+                        matches!(
+                            c.state(),
+                            ChildState::Open
+                                | ChildState::Faulted(Reason::Standby)
+                        )
+                    })
                 {
                     NexusStatus::Online
                 } else if self
@@ -1169,6 +1539,22 @@ impl<'n> Nexus<'n> {
         (*self.bdev_mut().unsafe_inner_mut_ptr()).required_alignment = new_val;
     }
 
+    /// Advertise a volatile write cache to the frontends when the nexus has
+    /// a write cache or any child can flush one, so that initiators send
+    /// flushes for fsync.
+    pub(crate) fn update_write_cache_flag(self: Pin<&mut Self>) {
+        let volatile = self.write_cache().is_some()
+            || self
+                .children
+                .iter()
+                .filter_map(|c| c.get_device().ok())
+                .any(|d| d.io_type_supported(IoType::Flush));
+        unsafe {
+            (*self.bdev_mut().unsafe_inner_mut_ptr()).write_cache =
+                volatile as i32;
+        }
+    }
+
     /// Sets the block size of the underlying device.
     pub(crate) unsafe fn set_block_len(self: Pin<&mut Self>, blk_size: u32) {
         self.bdev_mut().set_block_len(blk_size)
@@ -1234,17 +1620,17 @@ impl IoDevice for Nexus<'_> {
 
 impl IoDeviceChannelTraverse for Nexus<'_> {}
 
-unsafe fn unsafe_static_ptr(nexus: &Nexus) -> *mut Nexus<'static> {
-    let r = ::std::mem::transmute::<_, &'static Nexus>(nexus);
-    r as *const Nexus as *mut Nexus
-}
-
 impl<'n> BdevOps for Nexus<'n> {
     type ChannelData = NexusChannel;
     type BdevData = Nexus<'n>;
     type IoDev = Nexus<'n>;
 
-    /// TODO
+    /// Destruct the nexus once its bdev is unregistered. The children are
+    /// normally closed by then, as Nexus::destroy closes them first, but
+    /// not when the bdev is unregistered by SPDK, as on shutdown. This
+    /// callback can not wait on the removal of their devices, so the
+    /// children still open are detached from their devices, and the
+    /// devices are destroyed on the master reactor after it returned.
     fn destruct(mut self: Pin<&mut Self>) {
         // A closed operation might already be in progress calling unregister
         // will trip an assertion within the external libraries
@@ -1255,27 +1641,34 @@ impl<'n> BdevOps for Nexus<'n> {
 
         trace!("{}: closing, from state: {:?} ", self.name, self.state);
 
-        let self_ptr = unsafe { unsafe_static_ptr(&*self) };
-
-        Reactor::block_on(async move {
-            let self_ref = unsafe { &mut *self_ptr };
+        let name = self.name.clone();
+        let devices = unsafe {
+            let n = self.as_mut().get_unchecked_mut();
+            let devices = n
+                .children
+                .iter_mut()
+                .filter(|c| c.state() == ChildState::Open)
+                .filter_map(|c| c.detach())
+                .collect::<Vec<_>>();
+            n.children.clear();
+            n.child_count = 0;
+            devices
+        };
 
-            for child in self_ref.children.iter_mut() {
-                if child.state() == ChildState::Open {
-                    if let Err(e) = child.close().await {
+        if !devices.is_empty() {
+            Reactors::master().send_future(async move {
+                for device in devices {
+                    if let Err(e) = device_destroy(&device).await {
                         error!(
                             "{}: child {} failed to close with error {}",
-                            self_ref.name,
-                            child.get_name(),
+                            name,
+                            device,
                             e.verbose()
                         );
                     }
                 }
-            }
-
-            self_ref.children.clear();
-            self_ref.child_count = 0;
-        });
+            });
+        }
 
         self.as_mut().unregister_io_device();
 
@@ -1303,10 +1696,19 @@ impl<'n> BdevOps for Nexus<'n> {
             // we always assume the device supports read/write commands
             // allow NVMe Admin as it is needed for local replicas
             IoType::Read | IoType::Write | IoType::NvmeAdmin => true,
-            IoType::Flush
-            | IoType::Reset
-            | IoType::Unmap
-            | IoType::WriteZeros => {
+            // flush is forwarded to the children that can flush and
+            // completes right away if none can
+            IoType::Flush => true,
+            // zeroes on the children would read back as garbage, let the
+            // bdev layer write encrypted zeroes instead
+            IoType::WriteZeros if self.is_encrypted() => false,
+            // compared against the children with the range locked, the bdev
+            // layer emulates it when the children hold ciphertext or the
+            // write cache may hold newer data
+            IoType::Compare | IoType::CompareAndWrite => {
+                !self.is_encrypted() && self.write_cache().is_none()
+            }
+            IoType::Reset | IoType::Unmap | IoType::WriteZeros => {
                 let supported = self.io_is_supported(io_type);
                 if !supported {
                     trace!(
@@ -1351,6 +1753,11 @@ impl<'n> BdevOps for Nexus<'n> {
 /// be a configuration mismatch that would prevent us from going online.
 /// Currently, we can only determine this once we are already online,
 /// and so we check the errors twice for now.
+/// Maximum number of children of a nexus, as configured.
+pub(crate) fn max_children() -> usize {
+    Config::get().nexus_opts.max_children as usize
+}
+
 pub async fn nexus_create(
     name: &str,
     size: u64,
@@ -1365,6 +1772,8 @@ pub async fn nexus_create(
         NexusNvmeParams::default(),
         children,
         None,
+        default_size_policy(),
+        None,
     )
     .await
 }
@@ -1419,6 +1828,8 @@ pub async fn nexus_create_v2(
                 nvme_params,
                 children,
                 nexus_info_key,
+                default_size_policy(),
+                None,
             )
             .await
         }
@@ -1431,13 +1842,16 @@ pub async fn nexus_create_v2(
                 nvme_params,
                 children,
                 nexus_info_key,
+                default_size_policy(),
+                None,
             )
             .await
         }
     }
 }
 
-async fn nexus_create_internal(
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn nexus_create_internal(
     name: &str,
     size: u64,
     bdev_uuid: Option<&str>,
@@ -1445,6 +1859,8 @@ async fn nexus_create_internal(
     nvme_params: NexusNvmeParams,
     children: &[String],
     nexus_info_key: Option<String>,
+    size_policy: ChildSizePolicy,
+    crypto_key: Option<Zeroizing<String>>,
 ) -> Result<(), Error> {
     if let Some(nexus) = nexus_lookup_name_uuid(name, nexus_uuid) {
         // FIXME: Instead of error, we return Ok without checking
@@ -1465,6 +1881,13 @@ async fn nexus_create_internal(
         return Ok(());
     }
 
+    if children.len() > max_children() {
+        return Err(Error::TooManyChildren {
+            name: name.to_owned(),
+            max: max_children(),
+        });
+    }
+
     // Create a new Nexus object, and immediately add it to the global list.
     // This is necessary to ensure proper cleanup, as the code responsible for
     // closing a child assumes that the nexus to which it belongs will appear
@@ -1479,6 +1902,8 @@ async fn nexus_create_internal(
         None,
         nexus_info_key,
     );
+    nexus_bdev.data_mut().set_size_policy(size_policy);
+    nexus_bdev.data_mut().set_crypto_key(crypto_key);
 
     for child in children {
         if let Err(error) =
@@ -1527,6 +1952,9 @@ async fn nexus_create_internal(
             Err(error)
         }
 
-        Ok(_) => Ok(()),
+        Ok(_) => {
+            nexus_index_uuid(nexus_bdev.data().uuid(), name);
+            Ok(())
+        }
     }
 }
@@ -23,13 +23,18 @@
 //! When reconfiguring the nexus, we traverse all our children, create new IO
 //! channels for all children that are in the open state.
 
-use std::{cmp::min, pin::Pin};
+use std::{
+    cmp::{max, min},
+    pin::Pin,
+};
 
 use futures::future::join_all;
 use snafu::ResultExt;
 
 use super::{
+    block_len_compatible,
     fault_nexus_child,
+    max_children,
     nexus_iter_mut,
     ChildState,
     CreateChild,
@@ -51,7 +56,14 @@ use crate::{
         device_lookup,
         nexus::nexus_persistence::PersistOp,
     },
-    core::{partition, DeviceEventListener, DeviceEventType, Reactors},
+    core::{
+        partition,
+        DeviceEventListener,
+        DeviceEventType,
+        ProtectionInfo,
+        Reactors,
+    },
+    grpc::validate,
     nexus_uri::NexusBdevError,
 };
 
@@ -124,7 +136,8 @@ impl<'n> Nexus<'n> {
         uri: &str,
         norebuild: bool,
     ) -> Result<NexusStatus, Error> {
-        let status = self.as_mut().add_child_only(uri).await?;
+        let status =
+            self.as_mut().add_child_only(uri, Reason::OutOfSync).await?;
 
         if !norebuild {
             if let Err(e) = self.as_mut().start_rebuild(uri).await {
@@ -148,10 +161,20 @@ impl<'n> Nexus<'n> {
 
     /// The child may require a rebuild first, so the nexus will
     /// transition to degraded mode when the addition has been successful.
-    async fn add_child_only(
+    /// The child is added faulted with the reason given, out of sync or on
+    /// standby.
+    pub(crate) async fn add_child_only(
         mut self: Pin<&mut Self>,
         uri: &str,
+        reason: Reason,
     ) -> Result<NexusStatus, Error> {
+        if self.children.len() >= max_children() {
+            return Err(Error::TooManyChildren {
+                name: self.name.clone(),
+                max: max_children(),
+            });
+        }
+
         let name = device_create(uri).await.context(CreateChild {
             name: self.name.clone(),
         })?;
@@ -161,10 +184,10 @@ impl<'n> Nexus<'n> {
 
         let child_bdev = match device_lookup(&name) {
             Some(child) => {
-                if child.block_len() != self.block_len()
+                if !block_len_compatible(child.block_len(), self.block_len())
                     || self
-                        .min_num_blocks()
-                        .map_or(true, |n| n > child.num_blocks())
+                        .min_child_size()
+                        .map_or(true, |n| n > child.size_in_bytes())
                 {
                     if let Err(err) = device_destroy(uri).await {
                         error!(
@@ -202,6 +225,7 @@ impl<'n> Nexus<'n> {
             Some(child_bdev),
         );
 
+        child.set_block_emulation(self.block_len());
         let mut child_name = child.open(self.req_size);
 
         if let Ok(ref name) = child_name {
@@ -227,7 +251,7 @@ impl<'n> Nexus<'n> {
             Ok(cn) => {
                 // it can never take part in the IO path
                 // of the nexus until it's rebuilt from a healthy child.
-                child.fault(Reason::OutOfSync).await;
+                child.fault(reason).await;
                 let child_state = child.state();
 
                 // Register event listener for newly added child.
@@ -237,6 +261,7 @@ impl<'n> Nexus<'n> {
                     self.as_mut().get_unchecked_mut().children.push(child);
                     self.as_mut().get_unchecked_mut().child_count += 1;
                 }
+                self.as_mut().update_write_cache_flag();
 
                 self.persist(PersistOp::AddChild((cn, child_state))).await;
 
@@ -263,6 +288,7 @@ impl<'n> Nexus<'n> {
         mut self: Pin<&mut Self>,
         uri: &str,
     ) -> Result<(), Error> {
+        let uri: &str = &self.child_uri(uri);
         if self.child_count == 1 {
             return Err(Error::DestroyLastChild {
                 name: self.name.clone(),
@@ -330,6 +356,7 @@ impl<'n> Nexus<'n> {
         mut self: Pin<&mut Self>,
         name: &str,
     ) -> Result<NexusStatus, Error> {
+        let name: &str = &self.child_uri(name);
         trace!("{}: Offline child request for {}", self.name, name);
 
         let cancelled_rebuilding_children =
@@ -366,6 +393,7 @@ impl<'n> Nexus<'n> {
         name: &str,
         reason: Reason,
     ) -> Result<(), Error> {
+        let name: &str = &self.child_uri(name);
         trace!("{}: fault child request for {}", self.name, name);
 
         if self.child_count < 2 {
@@ -431,6 +459,7 @@ impl<'n> Nexus<'n> {
         mut self: Pin<&mut Self>,
         name: &str,
     ) -> Result<NexusStatus, Error> {
+        let name: &str = &self.child_uri(name);
         let nexus_name = self.name.clone();
         let nexus_size = self.req_size;
 
@@ -487,11 +516,16 @@ impl<'n> Nexus<'n> {
                 name,
             });
         }
+        self.as_mut().adopt_child_sizes().await?;
 
-        // Determine Nexus block size and data start and end offsets.
-        let mut start_blk = 0;
-        let mut end_blk = 0;
+        // Determine Nexus block size and data start and end offsets. The
+        // children may have different block sizes, in which case the nexus
+        // uses the smallest one and the offsets are worked out in bytes.
+        let mut start_byte = 0;
+        let mut end_byte = 0;
         let mut blk_size = 0;
+        let mut max_blk_size = 0;
+        let mut pi = Vec::new();
 
         for child in self.children.iter() {
             let dev = match child.get_device() {
@@ -506,23 +540,27 @@ impl<'n> Nexus<'n> {
             let nb = dev.num_blocks();
             let bs = dev.block_len();
 
+            pi.push(dev.protection_info());
+
             if blk_size == 0 {
                 blk_size = bs;
-            } else if bs != blk_size {
+            } else if !block_len_compatible(bs, blk_size) {
                 return Err(Error::MixedBlockSizes {
                     name: self.name.clone(),
                 });
             }
+            blk_size = min(blk_size, bs);
+            max_blk_size = max(max_blk_size, bs);
 
             match partition::calc_data_partition(self.req_size, nb, bs) {
                 Some((start, end)) => {
-                    if start_blk == 0 {
-                        start_blk = start;
-                        end_blk = end;
+                    if start_byte == 0 {
+                        start_byte = start * bs;
+                        end_byte = end * bs;
                     } else {
-                        end_blk = min(end_blk, end);
+                        end_byte = min(end_byte, end * bs);
 
-                        if start_blk != start {
+                        if start_byte != start * bs {
                             return Err(Error::ChildGeometry {
                                 child: child.name.clone(),
                                 name,
@@ -541,11 +579,37 @@ impl<'n> Nexus<'n> {
             }
         }
 
+        // the nexus reads and writes the data of the children only, so
+        // children with and without separate protection information go
+        // together, but not those that interleave it with their data
+        if ProtectionInfo::mixed_interleave(&pi) {
+            return Err(Error::MixedProtectionInfo {
+                name,
+            });
+        }
+        if pi.iter().any(Option::is_some) {
+            warn!(
+                "{}: children have protection information {:?}, which the \
+                nexus does not pass through",
+                name, pi
+            );
+        }
+
+        // the nexus must end on a whole block of every child
+        end_byte -= end_byte % max_blk_size;
+        if start_byte % max_blk_size != 0 || end_byte <= start_byte {
+            return Err(Error::MixedBlockSizes {
+                name,
+            });
+        }
+
         unsafe {
-            self.as_mut().set_data_ent_offset(start_blk);
+            self.as_mut().set_data_ent_offset(start_byte / blk_size);
             self.as_mut().set_block_len(blk_size as u32);
-            self.as_mut().set_num_blocks(end_blk - start_blk);
+            self.as_mut()
+                .set_num_blocks((end_byte - start_byte) / blk_size);
         }
+        self.as_mut().update_write_cache_flag();
 
         let size = self.req_size;
 
@@ -557,6 +621,7 @@ impl<'n> Nexus<'n> {
 
         unsafe {
             for child in self.as_mut().get_unchecked_mut().children.iter_mut() {
+                child.set_block_emulation(blk_size);
                 match child.open(size) {
                     Ok(child_name) => {
                         info!(
@@ -684,16 +749,32 @@ impl<'n> Nexus<'n> {
     }
 
     /// The nexus is allowed to be smaller then the underlying child devices
-    /// this function returns the smallest blkcnt of all online children as
-    /// they MAY vary in size.
-    pub(crate) fn min_num_blocks(&self) -> Option<u64> {
+    /// this function returns the smallest size in bytes of all online
+    /// children as they MAY vary in size and block size.
+    pub(crate) fn min_child_size(&self) -> Option<u64> {
         self.children
             .iter()
             .filter(|c| c.state() == ChildState::Open)
-            .map(|c| c.get_device().unwrap().num_blocks())
+            .map(|c| c.get_device().unwrap().size_in_bytes())
             .reduce(min)
     }
 
+    /// Child RPCs address a child by the URI it was added with, written the
+    /// same or in the normalized form the gRPC API adds children with, or by
+    /// its uuid. Returns the URI of the child in either case, or the given id
+    /// as it is if no child matches.
+    pub fn child_uri(&self, id: &str) -> String {
+        self.children
+            .iter()
+            .find(|c| c.get_name() == id)
+            .or_else(|| {
+                let uri = validate::child_uri(id).ok()?;
+                self.children.iter().find(|c| c.get_name() == uri)
+            })
+            .or_else(|| self.children.iter().find(|c| c.match_uuid(id)))
+            .map_or_else(|| id.to_string(), |c| c.get_name().to_string())
+    }
+
     /// Looks up a child based on the underlying block device name.
     pub fn lookup_child(&self, device_name: &str) -> Option<&NexusChild> {
         self.children
@@ -713,11 +794,12 @@ impl<'n> Nexus<'n> {
             .find(|c| c.match_device_name(device_name))
     }
 
-    /// Looks up a child by its URL.
+    /// Looks up a child by its URL or uuid.
     pub fn get_child_by_name(
         self: Pin<&mut Self>,
         name: &str,
     ) -> Result<&mut NexusChild<'n>, Error> {
+        let name: &str = &self.child_uri(name);
         let nexus_name = self.name.clone();
         let n = unsafe { Pin::get_unchecked_mut(self) };
         match n.children.iter_mut().find(|c| c.get_name() == name) {
@@ -728,6 +810,12 @@ impl<'n> Nexus<'n> {
             }),
         }
     }
+
+    /// Returns the number of IO faults of all children, summed over the
+    /// cores that saw them.
+    pub fn io_faults(&self) -> u64 {
+        self.children.iter().map(|c| c.io_faults()).sum()
+    }
 }
 
 impl<'n> DeviceEventListener for Nexus<'n> {
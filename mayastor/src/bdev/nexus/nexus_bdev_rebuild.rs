@@ -4,7 +4,9 @@ use std::pin::Pin;
 
 use super::{
     nexus_lookup_mut,
+    rebuild_admit,
     ChildState,
+    ChildStates,
     CreateRebuild,
     DrEvent,
     Error,
@@ -12,6 +14,7 @@ use super::{
     Reason,
     RebuildJobNotFound,
     RebuildOperation,
+    RebuildOutcome,
     RemoveRebuildJob,
     VerboseError,
 };
@@ -35,6 +38,7 @@ impl<'n> Nexus<'n> {
         self: Pin<&mut Self>,
         name: &str,
     ) -> Result<Receiver<RebuildState>, Error> {
+        let name: &str = &self.child_uri(name);
         trace!("{}: start rebuild request for {}", self.name, name);
 
         let src_child_name = match self
@@ -48,12 +52,15 @@ impl<'n> Nexus<'n> {
             }),
         }?;
 
-        let dst_child_name =
+        let (dst_child_name, dst_device) =
             match self.children.iter().find(|c| c.get_name() == name) {
                 Some(c)
                     if c.state() == ChildState::Faulted(Reason::OutOfSync) =>
                 {
-                    Ok(c.name.clone())
+                    Ok((
+                        c.name.clone(),
+                        c.get_device().ok().map(|d| d.device_name()),
+                    ))
                 }
                 Some(c) => Err(Error::ChildNotDegraded {
                     child: name.to_owned(),
@@ -85,6 +92,13 @@ impl<'n> Nexus<'n> {
             name: self.name.clone(),
         })?;
 
+        // the header of the journal of the child is updated again once it
+        // has been rebuilt
+        let journal = self.journal.clone();
+        if let (Some(journal), Some(device)) = (&journal, &dst_device) {
+            journal.thaw(device);
+        }
+
         // We're now rebuilding the `dst_child` which means it HAS to become an
         // active participant in the frontend nexus bdev for Writes.
         // This is because the rebuild job copies from src to target child
@@ -95,6 +109,29 @@ impl<'n> Nexus<'n> {
         // rebuilt ranges in sync with the other children.
         self.reconfigure(DrEvent::ChildRebuild).await;
 
+        // With a write journal only the blocks written since the child last
+        // applied all writes are copied. The journal is looked at once the
+        // child receives the writes, so that none falls in between.
+        if let Some(journal) = journal {
+            if let Some(ranges) =
+                journal.resync_ranges(&self.name, &dst_child_name).await
+            {
+                let ranges = ranges
+                    .into_iter()
+                    .map(|r| {
+                        r.start + self.data_ent_offset
+                            .. r.end + self.data_ent_offset
+                    })
+                    .collect::<Vec<_>>();
+                job.restrict(&ranges);
+            }
+        }
+
+        // outside of the rebuild windows the job waits for one to open
+        if !rebuild_admit(&self.name, &dst_child_name) {
+            return Ok(job.completion());
+        }
+
         job.as_client().start().context(RebuildOperation {
             job: name.to_owned(),
             name: self.name.clone(),
@@ -121,6 +158,7 @@ impl<'n> Nexus<'n> {
 
     /// Stop a rebuild job in the background
     pub async fn stop_rebuild(&self, name: &str) -> Result<(), Error> {
+        let name: &str = &self.child_uri(name);
         match self.get_rebuild_job(name) {
             Ok(rj) => rj.as_client().stop().context(RebuildOperation {
                 job: name.to_owned(),
@@ -137,6 +175,7 @@ impl<'n> Nexus<'n> {
         self: Pin<&mut Self>,
         name: &str,
     ) -> Result<(), Error> {
+        let name: &str = &self.child_uri(name);
         let rj = self.get_rebuild_job(name)?.as_client();
         rj.pause().context(RebuildOperation {
             job: name.to_owned(),
@@ -149,6 +188,7 @@ impl<'n> Nexus<'n> {
         self: Pin<&mut Self>,
         name: &str,
     ) -> Result<(), Error> {
+        let name: &str = &self.child_uri(name);
         let rj = self.get_rebuild_job(name)?.as_client();
         rj.resume().context(RebuildOperation {
             job: name.to_owned(),
@@ -161,6 +201,7 @@ impl<'n> Nexus<'n> {
         self: Pin<&mut Self>,
         name: &str,
     ) -> Result<RebuildState, Error> {
+        let name: &str = &self.child_uri(name);
         let rj = self.get_rebuild_job(name)?;
         Ok(rj.state())
     }
@@ -170,12 +211,14 @@ impl<'n> Nexus<'n> {
         self: Pin<&mut Self>,
         name: &str,
     ) -> Result<RebuildStats, Error> {
+        let name: &str = &self.child_uri(name);
         let rj = self.get_rebuild_job(name)?;
         Ok(rj.stats())
     }
 
     /// Returns the rebuild progress of child target `name`
     pub fn get_rebuild_progress(&self, name: &str) -> Result<u32, Error> {
+        let name: &str = &self.child_uri(name);
         let rj = self.get_rebuild_job(name)?;
 
         Ok(rj.as_client().stats().progress as u32)
@@ -255,9 +298,8 @@ impl<'n> Nexus<'n> {
         let recovering_child =
             self.as_mut().get_child_by_name(&job.destination)?;
 
-        match job.state() {
-            RebuildState::Completed => {
-                recovering_child.set_state(ChildState::Open);
+        match recovering_child.on_rebuild_done(job.state()) {
+            RebuildOutcome::Rebuilt => {
                 info!(
                     "Child {} has been rebuilt successfully",
                     recovering_child.get_name()
@@ -267,13 +309,21 @@ impl<'n> Nexus<'n> {
                 self.persist(PersistOp::Update((child_name, child_state)))
                     .await;
             }
-            RebuildState::Stopped => {
+            RebuildOutcome::Stopped => {
                 info!(
                     "Rebuild job for child {} of nexus {} stopped",
                     &job.destination, &self.name,
                 );
             }
-            RebuildState::Failed => {
+            RebuildOutcome::Stale => {
+                warn!(
+                    "Rebuild job for child {} of nexus {} completed but the child is now {}, not opening it",
+                    &job.destination,
+                    &self.name,
+                    recovering_child.state(),
+                );
+            }
+            RebuildOutcome::Failed => {
                 // rebuild has failed so we need to set the child as faulted
                 // allowing the control plane to replace it with another
                 if let Some(RebuildError::ReadIoError {
@@ -284,19 +334,11 @@ impl<'n> Nexus<'n> {
                 }
                 recovering_child.fault(Reason::RebuildFailed).await;
                 error!(
-                    "Rebuild job for child {} of nexus {} failed, error: {}",
-                    &job.destination,
-                    &self.name,
-                    job.error_desc(),
-                );
-            }
-            _ => {
-                recovering_child.fault(Reason::RebuildFailed).await;
-                error!(
-                    "Rebuild job for child {} of nexus {} failed with state {:?}",
+                    "Rebuild job for child {} of nexus {} failed with state {:?}, error: {}",
                     &job.destination,
                     &self.name,
                     job.state(),
+                    job.error_desc(),
                 );
             }
         }
@@ -0,0 +1,666 @@
+//!
+//! Block size emulation of nexus children.
+//!
+//! A nexus may be assembled from children of different logical block sizes,
+//! eg 512 byte and 4KiB devices while a volume is migrated from one device
+//! generation to the next. The nexus presents the smallest block size of its
+//! children, and the IO handles of the children with a larger block size are
+//! wrapped into an `EmulatedHandle`, which turns the IO of the nexus into IO
+//! of whole native blocks of the child:
+//!
+//! - IO that is aligned to the native blocks is passed through;
+//! - unaligned reads go through a bounce buffer of whole native blocks;
+//! - unaligned writes, write zeroes and unmaps read the native blocks they
+//!   touch, patch them and write them back.
+//!
+//! Two read-modify-writes touching the same native block would lose one of
+//! the writes, so all writes to a child, from any core, lock the native
+//! blocks they cover and queue behind overlapping writes in flight.
+
+use std::{collections::VecDeque, ops::Range, rc::Rc};
+
+use parking_lot::Mutex;
+use spdk_rs::{DmaBuf, DmaError, IoVec};
+
+use crate::core::{
+    BlockDevice,
+    BlockDeviceHandle,
+    CoreError,
+    GenericStatusCode,
+    IoCompletionCallback,
+    IoCompletionCallbackArg,
+    IoCompletionStatus,
+    Mthread,
+    NvmeCommandStatus,
+};
+
+/// Whether a child of the given block size can take part in a nexus of the
+/// given block size, ie one of them is a multiple of the other.
+pub(crate) fn block_len_compatible(child: u64, nexus: u64) -> bool {
+    child > 0 && nexus > 0 && (child.max(nexus) % child.min(nexus) == 0)
+}
+
+/// Range of native blocks covered by an IO of the nexus, with the offset of
+/// the IO within the first native block in bytes.
+fn native_range(
+    block_len: u64,
+    native_len: u64,
+    offset_blocks: u64,
+    num_blocks: u64,
+) -> (Range<u64>, u64) {
+    let start = offset_blocks * block_len;
+    let end = start + num_blocks * block_len;
+    let first = start / native_len;
+    let last = (end + native_len - 1) / native_len;
+    (first .. last, start - first * native_len)
+}
+
+fn overlaps(a: &Range<u64>, b: &Range<u64>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// A write waiting for the native blocks it covers.
+struct Waiter {
+    range: Range<u64>,
+    thread: Mthread,
+    io: *mut EmuIo,
+}
+
+// the IO is only ever touched on the thread it was submitted on
+unsafe impl Send for Waiter {}
+
+#[derive(Default)]
+struct RangeLocks {
+    locked: Vec<Range<u64>>,
+    waiting: VecDeque<Waiter>,
+}
+
+/// Emulation state of a child, shared by its IO handles on all cores.
+pub(crate) struct BlockEmulation {
+    /// block size presented to the nexus
+    block_len: u64,
+    /// native blocks being written
+    locks: Mutex<RangeLocks>,
+}
+
+impl std::fmt::Debug for BlockEmulation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BlockEmulation {{ block_len: {} }}", self.block_len)
+    }
+}
+
+impl BlockEmulation {
+    pub(crate) fn new(block_len: u64) -> Self {
+        Self {
+            block_len,
+            locks: Mutex::new(RangeLocks::default()),
+        }
+    }
+
+    /// Lock the range for the IO, returning false if the IO has been queued
+    /// behind an overlapping one. Queued IOs never overlap a later one, so
+    /// that writes are started in the order they were submitted in.
+    fn lock(&self, range: Range<u64>, io: *mut EmuIo) -> bool {
+        let mut locks = self.locks.lock();
+        if locks.locked.iter().any(|r| overlaps(r, &range))
+            || locks.waiting.iter().any(|w| overlaps(&w.range, &range))
+        {
+            locks.waiting.push_back(Waiter {
+                range,
+                thread: Mthread::current().unwrap(),
+                io,
+            });
+            false
+        } else {
+            locks.locked.push(range);
+            true
+        }
+    }
+
+    /// Unlock the range and start the queued IOs that may go now, each on
+    /// the thread it was submitted on.
+    fn unlock(&self, range: &Range<u64>) {
+        let granted = {
+            let mut locks = self.locks.lock();
+            if let Some(i) = locks.locked.iter().position(|r| r == range) {
+                locks.locked.swap_remove(i);
+            }
+
+            let mut granted = Vec::new();
+            let mut waiting = VecDeque::new();
+            while let Some(w) = locks.waiting.pop_front() {
+                if locks.locked.iter().any(|r| overlaps(r, &w.range))
+                    || waiting
+                        .iter()
+                        .any(|o: &Waiter| overlaps(&o.range, &w.range))
+                {
+                    waiting.push_back(w);
+                } else {
+                    locks.locked.push(w.range.clone());
+                    granted.push(w);
+                }
+            }
+            locks.waiting = waiting;
+            granted
+        };
+
+        for w in granted {
+            w.thread.msg(EmuIoPtr(w.io), |p| EmuIo::start_queued(p.0));
+        }
+    }
+}
+
+#[derive(Debug)]
+struct EmuIoPtr(*mut EmuIo);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EmuOp {
+    Read,
+    Write,
+    WriteZeroes,
+    Unmap,
+}
+
+/// An IO of the nexus in flight on an emulated child.
+struct EmuIo {
+    inner: Rc<dyn BlockDeviceHandle>,
+    emu: std::sync::Arc<BlockEmulation>,
+    op: EmuOp,
+    iov: *mut IoVec,
+    iovcnt: i32,
+    /// native blocks covered by the IO
+    range: Range<u64>,
+    /// offset of the IO within the first native block in bytes
+    skip: usize,
+    /// length of the IO in bytes
+    len: usize,
+    aligned: bool,
+    buf: Option<DmaBuf>,
+    buf_iov: IoVec,
+    cb: IoCompletionCallback,
+    cb_arg: IoCompletionCallbackArg,
+}
+
+fn failed() -> IoCompletionStatus {
+    IoCompletionStatus::NvmeError(NvmeCommandStatus::GenericCommandStatus(
+        GenericStatusCode::InternalDeviceError,
+    ))
+}
+
+/// copy the data of the iovs into the slice
+fn gather(iov: *mut IoVec, iovcnt: i32, dst: &mut [u8]) {
+    let iovs = unsafe { std::slice::from_raw_parts(iov, iovcnt as usize) };
+    let mut off = 0;
+    for v in iovs {
+        if off == dst.len() {
+            break;
+        }
+        let n = (v.iov_len as usize).min(dst.len() - off);
+        let src =
+            unsafe { std::slice::from_raw_parts(v.iov_base as *const u8, n) };
+        dst[off .. off + n].copy_from_slice(src);
+        off += n;
+    }
+}
+
+/// copy the slice into the iovs
+fn scatter(iov: *mut IoVec, iovcnt: i32, src: &[u8]) {
+    let iovs = unsafe { std::slice::from_raw_parts(iov, iovcnt as usize) };
+    let mut off = 0;
+    for v in iovs {
+        if off == src.len() {
+            break;
+        }
+        let n = (v.iov_len as usize).min(src.len() - off);
+        let dst =
+            unsafe { std::slice::from_raw_parts_mut(v.iov_base as *mut u8, n) };
+        dst.copy_from_slice(&src[off .. off + n]);
+        off += n;
+    }
+}
+
+impl EmuIo {
+    /// allocate the bounce buffer covering the native blocks of the IO
+    fn alloc_buf(&mut self) -> Result<(), CoreError> {
+        let size = (self.range.end - self.range.start)
+            * self.inner.get_device().block_len();
+        let mut buf = self.inner.dma_malloc(size).map_err(|_| {
+            CoreError::DmaAllocationError {
+                size,
+            }
+        })?;
+        self.buf_iov = IoVec {
+            iov_base: buf.as_mut_slice().as_mut_ptr().cast(),
+            iov_len: size as _,
+        };
+        self.buf = Some(buf);
+        Ok(())
+    }
+
+    fn num_native(&self) -> u64 {
+        self.range.end - self.range.start
+    }
+
+    /// start a write that holds its range lock
+    fn start(ptr: *mut EmuIo) -> Result<(), CoreError> {
+        let io = unsafe { &mut *ptr };
+        let (start, num) = (io.range.start, io.num_native());
+
+        if io.aligned {
+            return match io.op {
+                EmuOp::Write => io.inner.writev_blocks(
+                    io.iov,
+                    io.iovcnt,
+                    start,
+                    num,
+                    Self::write_done,
+                    ptr.cast(),
+                ),
+                EmuOp::WriteZeroes => io.inner.write_zeroes(
+                    start,
+                    num,
+                    Self::write_done,
+                    ptr.cast(),
+                ),
+                EmuOp::Unmap => io.inner.unmap_blocks(
+                    start,
+                    num,
+                    Self::write_done,
+                    ptr.cast(),
+                ),
+                EmuOp::Read => unreachable!(),
+            };
+        }
+
+        io.alloc_buf()?;
+        io.inner.readv_blocks(
+            &mut io.buf_iov,
+            1,
+            start,
+            num,
+            Self::rmw_read_done,
+            ptr.cast(),
+        )
+    }
+
+    /// start a write that was queued behind an overlapping one
+    fn start_queued(ptr: *mut EmuIo) {
+        if let Err(e) = Self::start(ptr) {
+            let io = unsafe { &*ptr };
+            error!("failed to submit emulated write: {}", e);
+            let inner = Rc::clone(&io.inner);
+            Self::complete(ptr, inner.get_device(), failed());
+        }
+    }
+
+    /// release the range lock and complete the write
+    fn complete(
+        ptr: *mut EmuIo,
+        device: &dyn BlockDevice,
+        status: IoCompletionStatus,
+    ) {
+        let io = unsafe { Box::from_raw(ptr) };
+        io.emu.unlock(&io.range);
+        (io.cb)(device, status, io.cb_arg);
+    }
+
+    fn write_done(
+        device: &dyn BlockDevice,
+        status: IoCompletionStatus,
+        ctx: IoCompletionCallbackArg,
+    ) {
+        Self::complete(ctx.cast(), device, status);
+    }
+
+    fn rmw_read_done(
+        device: &dyn BlockDevice,
+        status: IoCompletionStatus,
+        ctx: IoCompletionCallbackArg,
+    ) {
+        let ptr: *mut EmuIo = ctx.cast();
+        if status != IoCompletionStatus::Success {
+            Self::complete(ptr, device, status);
+            return;
+        }
+
+        let io = unsafe { &mut *ptr };
+        let (skip, len) = (io.skip, io.len);
+        let data =
+            &mut io.buf.as_mut().unwrap().as_mut_slice()[skip .. skip + len];
+        match io.op {
+            EmuOp::Write => gather(io.iov, io.iovcnt, data),
+            // unmapped blocks are not guaranteed to read back as anything, so
+            // zeroing the partial blocks is as good as unmapping them
+            _ => data.iter_mut().for_each(|b| *b = 0),
+        }
+
+        let (start, num) = (io.range.start, io.num_native());
+        if let Err(e) = io.inner.writev_blocks(
+            &mut io.buf_iov,
+            1,
+            start,
+            num,
+            Self::write_done,
+            ctx,
+        ) {
+            error!("failed to submit emulated write: {}", e);
+            Self::complete(ptr, device, failed());
+        }
+    }
+
+    fn read_done(
+        device: &dyn BlockDevice,
+        status: IoCompletionStatus,
+        ctx: IoCompletionCallbackArg,
+    ) {
+        let mut io = unsafe { Box::from_raw(ctx as *mut EmuIo) };
+        if status == IoCompletionStatus::Success {
+            let (skip, len) = (io.skip, io.len);
+            let data =
+                &io.buf.as_mut().unwrap().as_mut_slice()[skip .. skip + len];
+            scatter(io.iov, io.iovcnt, data);
+        }
+        (io.cb)(device, status, io.cb_arg);
+    }
+}
+
+/// IO handle of a child presenting the block size of the nexus on top of the
+/// native block size of the child.
+pub(crate) struct EmulatedHandle {
+    inner: Rc<dyn BlockDeviceHandle>,
+    emu: std::sync::Arc<BlockEmulation>,
+}
+
+impl EmulatedHandle {
+    pub(crate) fn new(
+        inner: Box<dyn BlockDeviceHandle>,
+        emu: std::sync::Arc<BlockEmulation>,
+    ) -> Self {
+        Self {
+            inner: Rc::from(inner),
+            emu,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn io(
+        &self,
+        op: EmuOp,
+        iov: *mut IoVec,
+        iovcnt: i32,
+        offset_blocks: u64,
+        num_blocks: u64,
+        cb: IoCompletionCallback,
+        cb_arg: IoCompletionCallbackArg,
+    ) -> Box<EmuIo> {
+        let block_len = self.emu.block_len;
+        let native_len = self.inner.get_device().block_len();
+        let (range, skip) =
+            native_range(block_len, native_len, offset_blocks, num_blocks);
+        let len = num_blocks * block_len;
+        Box::new(EmuIo {
+            inner: Rc::clone(&self.inner),
+            emu: self.emu.clone(),
+            op,
+            iov,
+            iovcnt,
+            aligned: skip == 0 && len % native_len == 0,
+            range,
+            skip: skip as usize,
+            len: len as usize,
+            buf: None,
+            buf_iov: IoVec {
+                iov_base: std::ptr::null_mut(),
+                iov_len: 0,
+            },
+            cb,
+            cb_arg,
+        })
+    }
+
+    /// submit a write, which has to wait for overlapping writes first
+    fn submit_write(&self, io: Box<EmuIo>) -> Result<(), CoreError> {
+        let range = io.range.clone();
+        let ptr = Box::into_raw(io);
+        if !self.emu.lock(range.clone(), ptr) {
+            return Ok(());
+        }
+        EmuIo::start(ptr).map_err(|e| {
+            let _ = unsafe { Box::from_raw(ptr) };
+            self.emu.unlock(&range);
+            e
+        })
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl BlockDeviceHandle for EmulatedHandle {
+    fn get_device(&self) -> &dyn BlockDevice {
+        self.inner.get_device()
+    }
+
+    fn dma_malloc(&self, size: u64) -> Result<DmaBuf, DmaError> {
+        self.inner.dma_malloc(size)
+    }
+
+    async fn read_at(
+        &self,
+        offset: u64,
+        buffer: &mut DmaBuf,
+    ) -> Result<u64, CoreError> {
+        self.inner.read_at(offset, buffer).await
+    }
+
+    async fn write_at(
+        &self,
+        offset: u64,
+        buffer: &DmaBuf,
+    ) -> Result<u64, CoreError> {
+        self.inner.write_at(offset, buffer).await
+    }
+
+    fn readv_blocks(
+        &self,
+        iov: *mut IoVec,
+        iovcnt: i32,
+        offset_blocks: u64,
+        num_blocks: u64,
+        cb: IoCompletionCallback,
+        cb_arg: IoCompletionCallbackArg,
+    ) -> Result<(), CoreError> {
+        let mut io = self.io(
+            EmuOp::Read,
+            iov,
+            iovcnt,
+            offset_blocks,
+            num_blocks,
+            cb,
+            cb_arg,
+        );
+        let (start, num) = (io.range.start, io.num_native());
+        if io.aligned {
+            return self
+                .inner
+                .readv_blocks(iov, iovcnt, start, num, cb, cb_arg);
+        }
+
+        io.alloc_buf()?;
+        let buf_iov: *mut IoVec = &mut io.buf_iov;
+        let ptr = Box::into_raw(io);
+        self.inner
+            .readv_blocks(buf_iov, 1, start, num, EmuIo::read_done, ptr.cast())
+            .map_err(|e| {
+                let _ = unsafe { Box::from_raw(ptr) };
+                e
+            })
+    }
+
+    fn writev_blocks(
+        &self,
+        iov: *mut IoVec,
+        iovcnt: i32,
+        offset_blocks: u64,
+        num_blocks: u64,
+        cb: IoCompletionCallback,
+        cb_arg: IoCompletionCallbackArg,
+    ) -> Result<(), CoreError> {
+        self.submit_write(self.io(
+            EmuOp::Write,
+            iov,
+            iovcnt,
+            offset_blocks,
+            num_blocks,
+            cb,
+            cb_arg,
+        ))
+    }
+
+    fn reset(
+        &self,
+        cb: IoCompletionCallback,
+        cb_arg: IoCompletionCallbackArg,
+    ) -> Result<(), CoreError> {
+        self.inner.reset(cb, cb_arg)
+    }
+
+    fn flush_io(
+        &self,
+        cb: IoCompletionCallback,
+        cb_arg: IoCompletionCallbackArg,
+    ) -> Result<(), CoreError> {
+        // a flush only covers writes that have completed, which includes
+        // the writes of completed read-modify-writes
+        self.inner.flush_io(cb, cb_arg)
+    }
+
+    fn unmap_blocks(
+        &self,
+        offset_blocks: u64,
+        num_blocks: u64,
+        cb: IoCompletionCallback,
+        cb_arg: IoCompletionCallbackArg,
+    ) -> Result<(), CoreError> {
+        self.submit_write(self.io(
+            EmuOp::Unmap,
+            std::ptr::null_mut(),
+            0,
+            offset_blocks,
+            num_blocks,
+            cb,
+            cb_arg,
+        ))
+    }
+
+    fn write_zeroes(
+        &self,
+        offset_blocks: u64,
+        num_blocks: u64,
+        cb: IoCompletionCallback,
+        cb_arg: IoCompletionCallbackArg,
+    ) -> Result<(), CoreError> {
+        self.submit_write(self.io(
+            EmuOp::WriteZeroes,
+            std::ptr::null_mut(),
+            0,
+            offset_blocks,
+            num_blocks,
+            cb,
+            cb_arg,
+        ))
+    }
+
+    async fn nvme_admin_custom(&self, opcode: u8) -> Result<(), CoreError> {
+        self.inner.nvme_admin_custom(opcode).await
+    }
+
+    async fn nvme_admin(
+        &self,
+        nvme_cmd: &spdk_rs::libspdk::spdk_nvme_cmd,
+        buffer: Option<&mut DmaBuf>,
+    ) -> Result<(), CoreError> {
+        self.inner.nvme_admin(nvme_cmd, buffer).await
+    }
+
+    async fn nvme_identify_ctrlr(&self) -> Result<DmaBuf, CoreError> {
+        self.inner.nvme_identify_ctrlr().await
+    }
+
+    async fn create_snapshot(&self) -> Result<u64, CoreError> {
+        self.inner.create_snapshot().await
+    }
+
+    async fn nvme_resv_register(
+        &self,
+        current_key: u64,
+        new_key: u64,
+        register_action: u8,
+        cptpl: u8,
+    ) -> Result<(), CoreError> {
+        self.inner
+            .nvme_resv_register(current_key, new_key, register_action, cptpl)
+            .await
+    }
+
+    async fn nvme_resv_acquire(
+        &self,
+        current_key: u64,
+        preempt_key: u64,
+        acquire_action: u8,
+        resv_type: u8,
+    ) -> Result<(), CoreError> {
+        self.inner
+            .nvme_resv_acquire(
+                current_key,
+                preempt_key,
+                acquire_action,
+                resv_type,
+            )
+            .await
+    }
+
+    async fn nvme_resv_report(
+        &self,
+        cdw11: u32,
+        buffer: &mut DmaBuf,
+    ) -> Result<(), CoreError> {
+        self.inner.nvme_resv_report(cdw11, buffer).await
+    }
+
+    async fn io_passthru(
+        &self,
+        nvme_cmd: &spdk_rs::libspdk::spdk_nvme_cmd,
+        buffer: Option<&mut DmaBuf>,
+    ) -> Result<(), CoreError> {
+        self.inner.io_passthru(nvme_cmd, buffer).await
+    }
+
+    async fn host_id(&self) -> Result<[u8; 16], CoreError> {
+        self.inner.host_id().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compatible() {
+        assert!(block_len_compatible(512, 512));
+        assert!(block_len_compatible(4096, 512));
+        assert!(block_len_compatible(512, 4096));
+        assert!(!block_len_compatible(520, 4096));
+        assert!(!block_len_compatible(0, 512));
+    }
+
+    #[test]
+    fn ranges() {
+        // aligned to the native blocks
+        assert_eq!(native_range(512, 4096, 8, 16), (1 .. 3, 0));
+        // within a single native block
+        assert_eq!(native_range(512, 4096, 9, 1), (1 .. 2, 512));
+        // straddling two native blocks
+        assert_eq!(native_range(512, 4096, 7, 2), (0 .. 2, 3584));
+        // nexus blocks larger than the native ones are always aligned
+        assert_eq!(native_range(4096, 512, 3, 2), (24 .. 40, 0));
+    }
+}
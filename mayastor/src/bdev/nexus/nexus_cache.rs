@@ -0,0 +1,820 @@
+//!
+//! Optional write-back cache of a nexus, held in DRAM.
+//!
+//! THE CACHE IS VOLATILE: writes are acknowledged as soon as they are in the
+//! cache, so data that has not been written back to the children yet is lost
+//! when the process goes away uncleanly. It is meant for volumes that can
+//! tolerate that in exchange for lower write latency, and is off by default.
+//!
+//! The cache is shared by all channels of the nexus and bounded in size. Each
+//! channel runs a poller which writes dirty data back to all children
+//! periodically, or right away when IO is waiting for it or the amount of
+//! dirty data grows too large. The bdev layer does not pass FUA down to us,
+//! so flush commands are the durability barrier: a flush completes once all
+//! data written before it has been written back, and flushed on the
+//! children.
+//!
+//! Reads are served from the cache when all their blocks are cached. Reads
+//! and discards that overlap dirty data wait until it has been written back
+//! and then go to the children, discarded blocks are dropped from the cache.
+//! Clean blocks are evicted in the order they were written back.
+
+use std::{
+    cell::Cell,
+    cmp::{max, min},
+    collections::{BTreeMap, VecDeque},
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crossbeam::atomic::AtomicCell;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use spdk_rs::{libspdk::spdk_bdev_io, ChannelTraverseStatus};
+
+use super::{
+    fault_nexus_child,
+    nexus_child_retire,
+    nexus_flush_request,
+    nexus_lookup_any_mut,
+    nexus_lookup_mut,
+    nexus_resubmit_request,
+    ChildState,
+    Error,
+    Nexus,
+    NexusChannel,
+};
+
+use crate::{
+    core::{for_each_channel, poller, Reactors},
+    jsonrpc::jsonrpc_register,
+    sleep::mayastor_sleep,
+};
+
+/// Interval in usec at which the channels check on waiting IO and dirty data.
+const CACHE_POLL_INTERVAL_US: u64 = 1000;
+
+/// Share of the cache that may be dirty when none is configured.
+const CACHE_DEFAULT_DIRTY_PCT: u8 = 50;
+
+/// Writeback interval when none is configured.
+const CACHE_DEFAULT_WRITEBACK_MS: u64 = 100;
+
+/// Maximum amount of data a channel writes back in one go.
+const CACHE_WRITEBACK_BYTES: u64 = 1024 * 1024;
+
+/// Write cache settings of a nexus.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NexusCacheOpts {
+    /// size of the cache in bytes, 0 disables the cache
+    pub size: u64,
+    /// percentage of the cache that may hold dirty data
+    pub dirty_pct: u8,
+    /// interval in ms at which dirty data is written back
+    pub writeback_ms: u64,
+}
+
+impl NexusCacheOpts {
+    fn dirty_pct(&self) -> usize {
+        match self.dirty_pct {
+            0 => CACHE_DEFAULT_DIRTY_PCT as usize,
+            pct => min(pct, 100) as usize,
+        }
+    }
+
+    fn writeback_interval(&self) -> Duration {
+        Duration::from_millis(match self.writeback_ms {
+            0 => CACHE_DEFAULT_WRITEBACK_MS,
+            ms => ms,
+        })
+    }
+}
+
+/// A cached block.
+struct CachedBlock {
+    data: Box<[u8]>,
+    /// generation of the last write to the block
+    gen: u64,
+    dirty: bool,
+    /// set while the block is being written back
+    flushing: bool,
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// cached blocks by lba
+    blocks: BTreeMap<u64, CachedBlock>,
+    /// lba of the dirty blocks by the generation of their last write
+    dirty: BTreeMap<u64, u64>,
+    /// clean blocks in the order they became clean, may contain blocks that
+    /// have been evicted or written to since
+    clean: VecDeque<u64>,
+    /// generation of the last write
+    gen: u64,
+}
+
+impl CacheState {
+    /// evict clean blocks until at most max blocks are cached
+    fn evict(&mut self, max: usize) {
+        while self.blocks.len() > max {
+            match self.clean.pop_front() {
+                Some(lba) => {
+                    if matches!(self.blocks.get(&lba), Some(b) if !b.dirty) {
+                        self.blocks.remove(&lba);
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// A run of dirty blocks taken from the cache to be written back.
+struct WritebackRun {
+    lba: u64,
+    data: Vec<u8>,
+    /// generation of each block at the time it was taken
+    gens: Vec<u64>,
+}
+
+/// Result of looking up a read in the cache.
+enum Lookup {
+    /// all blocks are cached
+    Hit,
+    /// some blocks are missing and none is dirty
+    Miss,
+    /// some blocks are missing and some are dirty
+    Dirty,
+}
+
+/// The write cache of a nexus, shared by all its channels.
+pub(crate) struct WriteCache {
+    opts: NexusCacheOpts,
+    block_len: u64,
+    max_blocks: usize,
+    max_dirty: usize,
+    /// set while the cache is being drained, no new data is cached then
+    draining: AtomicCell<bool>,
+    state: parking_lot::Mutex<CacheState>,
+}
+
+impl std::fmt::Debug for WriteCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WriteCache")
+            .field("opts", &self.opts)
+            .field("draining", &self.draining.load())
+            .finish()
+    }
+}
+
+impl WriteCache {
+    fn new(opts: NexusCacheOpts, block_len: u64) -> Self {
+        let max_blocks = max((opts.size / block_len) as usize, 1);
+        let max_dirty = max(max_blocks * opts.dirty_pct() / 100, 1);
+        Self {
+            opts,
+            block_len,
+            max_blocks,
+            max_dirty,
+            draining: AtomicCell::new(false),
+            state: parking_lot::Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Cache the data written at the given lba, returns false if the dirty
+    /// data would exceed the limit.
+    fn write(&self, lba: u64, data: &[u8]) -> bool {
+        let bl = self.block_len as usize;
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+
+        let new_dirty = (0 .. (data.len() / bl) as u64)
+            .filter(
+                |i| !matches!(state.blocks.get(&(lba + i)), Some(b) if b.dirty),
+            )
+            .count();
+        if state.dirty.len() + new_dirty > self.max_dirty {
+            return false;
+        }
+
+        for (i, chunk) in data.chunks_exact(bl).enumerate() {
+            let lba = lba + i as u64;
+            state.gen += 1;
+            let gen = state.gen;
+
+            match state.blocks.get_mut(&lba) {
+                Some(b) => {
+                    if b.dirty {
+                        state.dirty.remove(&b.gen);
+                    }
+                    b.data.copy_from_slice(chunk);
+                    b.gen = gen;
+                    b.dirty = true;
+                }
+                None => {
+                    state.blocks.insert(
+                        lba,
+                        CachedBlock {
+                            data: chunk.into(),
+                            gen,
+                            dirty: true,
+                            flushing: false,
+                        },
+                    );
+                }
+            }
+            state.dirty.insert(gen, lba);
+        }
+
+        state.evict(self.max_blocks);
+        true
+    }
+
+    /// copy the given blocks into out if they are all cached
+    fn read(&self, lba: u64, num_blocks: u64, out: &mut [u8]) -> Lookup {
+        let state = self.state.lock();
+        let mut dirty = false;
+        let mut all = true;
+
+        for b in (lba .. lba + num_blocks).map(|lba| state.blocks.get(&lba)) {
+            match b {
+                Some(b) => dirty |= b.dirty,
+                None => all = false,
+            }
+        }
+
+        if !all {
+            return if dirty { Lookup::Dirty } else { Lookup::Miss };
+        }
+
+        for (b, chunk) in state
+            .blocks
+            .range(lba .. lba + num_blocks)
+            .map(|(_, b)| b)
+            .zip(out.chunks_exact_mut(self.block_len as usize))
+        {
+            chunk.copy_from_slice(&b.data);
+        }
+        Lookup::Hit
+    }
+
+    /// returns true if any of the given blocks is dirty
+    fn overlaps_dirty(&self, lba: u64, num_blocks: u64) -> bool {
+        self.state
+            .lock()
+            .blocks
+            .range(lba .. lba + num_blocks)
+            .any(|(_, b)| b.dirty)
+    }
+
+    /// drop the given clean blocks from the cache
+    fn invalidate(&self, lba: u64, num_blocks: u64) {
+        let mut state = self.state.lock();
+        let clean = state
+            .blocks
+            .range(lba .. lba + num_blocks)
+            .filter(|(_, b)| !b.dirty)
+            .map(|(lba, _)| *lba)
+            .collect::<Vec<_>>();
+        clean.iter().for_each(|lba| {
+            state.blocks.remove(lba);
+        });
+    }
+
+    /// generation of the last write
+    fn gen(&self) -> u64 {
+        self.state.lock().gen
+    }
+
+    /// returns true if all writes up to the given generation are written back
+    fn written_back_to(&self, gen: u64) -> bool {
+        self.state
+            .lock()
+            .dirty
+            .keys()
+            .next()
+            .map_or(true, |g| *g > gen)
+    }
+
+    /// returns true if the cache holds no dirty data
+    fn is_clean(&self) -> bool {
+        self.state.lock().dirty.is_empty()
+    }
+
+    /// returns true if more than half the dirty limit is used
+    fn is_filling_up(&self) -> bool {
+        self.state.lock().dirty.len() * 2 > self.max_dirty
+    }
+
+    /// Take up to max dirty blocks that are not being written back yet, in
+    /// runs of consecutive blocks.
+    fn take_dirty(&self, max: usize) -> Vec<WritebackRun> {
+        let mut state = self.state.lock();
+        let mut runs: Vec<WritebackRun> = Vec::new();
+        let mut taken = 0;
+
+        for (lba, b) in state.blocks.iter_mut() {
+            if taken == max {
+                break;
+            }
+            if !b.dirty || b.flushing {
+                continue;
+            }
+
+            b.flushing = true;
+            taken += 1;
+            match runs.last_mut() {
+                Some(run) if run.lba + run.gens.len() as u64 == *lba => {
+                    run.data.extend_from_slice(&b.data);
+                    run.gens.push(b.gen);
+                }
+                _ => runs.push(WritebackRun {
+                    lba: *lba,
+                    data: b.data.to_vec(),
+                    gens: vec![b.gen],
+                }),
+            }
+        }
+        runs
+    }
+
+    /// Mark a run as written back. Blocks that have been written to in the
+    /// meantime stay dirty, as do all blocks of a run that failed.
+    fn written_back(&self, run: &WritebackRun, ok: bool) {
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+
+        for (i, gen) in run.gens.iter().enumerate() {
+            let lba = run.lba + i as u64;
+            if let Some(b) = state.blocks.get_mut(&lba) {
+                b.flushing = false;
+                if ok && b.gen == *gen {
+                    b.dirty = false;
+                    state.dirty.remove(gen);
+                    state.clean.push_back(lba);
+                }
+            }
+        }
+
+        state.evict(self.max_blocks);
+    }
+}
+
+/// Write a run of blocks to all children that take writes, returns true if
+/// it reached at least one of them. Children that fail the write are faulted
+/// and retired.
+async fn write_run(
+    nexus_name: &str,
+    block_len: u64,
+    run: &WritebackRun,
+) -> bool {
+    let (offset, handles) = match nexus_lookup_mut(nexus_name) {
+        Some(nexus) => (
+            (run.lba + nexus.data_ent_offset) * block_len,
+            nexus
+                .children
+                .iter()
+                .filter(|c| c.state() == ChildState::Open || c.rebuilding())
+                .filter_map(|c| c.get_io_handle().ok())
+                .collect::<Vec<_>>(),
+        ),
+        None => return false,
+    };
+
+    let mut written = 0;
+    for hdl in handles {
+        let mut buf = match hdl.dma_malloc(run.data.len() as u64) {
+            Ok(buf) => buf,
+            Err(e) => {
+                error!(
+                    "{}: writeback buffer allocation failed: {}",
+                    nexus_name, e
+                );
+                continue;
+            }
+        };
+        buf.as_mut_slice().copy_from_slice(&run.data);
+
+        match hdl.write_at(offset, &buf).await {
+            Ok(_) => written += 1,
+            Err(e) => {
+                let device = hdl.get_device().device_name();
+                error!("{}: writeback to {} failed: {}", nexus_name, device, e);
+                if let Some(nexus) = nexus_lookup_mut(nexus_name) {
+                    if fault_nexus_child(nexus, &device) {
+                        Reactors::master().send_future(nexus_child_retire(
+                            nexus_name.to_string(),
+                            device,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    written > 0
+}
+
+/// write the runs back and mark them written back
+async fn writeback(
+    nexus_name: &str,
+    cache: &WriteCache,
+    runs: Vec<WritebackRun>,
+) {
+    for run in runs {
+        let ok = write_run(nexus_name, cache.block_len, &run).await;
+        cache.written_back(&run, ok);
+    }
+}
+
+/// Condition IO on a channel is waiting for.
+#[derive(Debug, Clone, Copy)]
+enum CacheWait {
+    /// blocks in the range to be written back
+    Range(u64, u64),
+    /// room in the cache for more dirty data
+    Room,
+    /// all writes up to the generation to be written back
+    Flush(u64),
+}
+
+/// How a write was handled by the cache.
+pub(crate) enum CacheWrite {
+    /// the data is in the cache and the IO can be completed
+    Cached,
+    /// the IO waits and is resubmitted later
+    Waiting,
+    /// the IO must be submitted to the children
+    PassThrough,
+}
+
+/// How a read was handled by the cache.
+pub(crate) enum CacheRead {
+    /// the data has been copied from the cache
+    Hit,
+    /// the IO waits and is resubmitted later
+    Waiting,
+    /// the IO must be submitted to the children
+    Miss,
+}
+
+/// Per channel cache state: the cache of the nexus, the IO waiting for it on
+/// this channel and the writeback in progress.
+pub(crate) struct CacheChannel {
+    cache: Arc<WriteCache>,
+    nexus_name: String,
+    waiting: VecDeque<(CacheWait, *mut spdk_bdev_io)>,
+    /// set while a writeback started by this channel is in progress
+    busy: Rc<Cell<bool>>,
+    last_writeback: Instant,
+    poller: Option<poller::Poller<'static>>,
+}
+
+impl CacheChannel {
+    /// Create the cache state of a channel. Must be called on the thread of
+    /// the channel, where the poller runs.
+    pub(crate) fn new(cache: Arc<WriteCache>, nexus_name: &str) -> Box<Self> {
+        let mut chan = Box::new(Self {
+            cache,
+            nexus_name: nexus_name.to_string(),
+            waiting: VecDeque::new(),
+            busy: Rc::new(Cell::new(false)),
+            last_writeback: Instant::now(),
+            poller: None,
+        });
+
+        let chan_ptr = &mut *chan as *mut CacheChannel;
+        chan.poller = Some(
+            poller::Builder::new()
+                .with_name("nexus_cache_poller")
+                .with_interval(CACHE_POLL_INTERVAL_US)
+                .with_poll_fn(move || {
+                    // resubmitting IO re-enters the channel, so the ready IO
+                    // is collected first
+                    let ready = unsafe { (*chan_ptr).take_ready() };
+                    let count = ready.len() as i32;
+                    ready.into_iter().for_each(|(wait, io)| match wait {
+                        CacheWait::Flush(_) => nexus_flush_request(io),
+                        _ => nexus_resubmit_request(io),
+                    });
+                    unsafe { (*chan_ptr).start_writeback() };
+                    count
+                })
+                .build(),
+        );
+
+        chan
+    }
+
+    fn wait(&mut self, wait: CacheWait, io: *mut spdk_bdev_io) {
+        self.waiting.push_back((wait, io));
+    }
+
+    /// Cache the data of a write. Writes wait behind writes already waiting
+    /// for room to keep their order.
+    pub(crate) fn write(
+        &mut self,
+        io: *mut spdk_bdev_io,
+        lba: u64,
+        data: &[u8],
+    ) -> CacheWrite {
+        // writes larger than the dirty limit never fit and bypass the cache
+        let num_blocks = data.len() as u64 / self.cache.block_len;
+        if self.cache.draining.load()
+            || num_blocks as usize > self.cache.max_dirty
+        {
+            return if self.cache.overlaps_dirty(lba, num_blocks) {
+                self.wait(CacheWait::Range(lba, num_blocks), io);
+                CacheWrite::Waiting
+            } else {
+                self.cache.invalidate(lba, num_blocks);
+                CacheWrite::PassThrough
+            };
+        }
+
+        if !self
+            .waiting
+            .iter()
+            .any(|(w, _)| matches!(w, CacheWait::Room))
+            && self.cache.write(lba, data)
+        {
+            CacheWrite::Cached
+        } else {
+            self.wait(CacheWait::Room, io);
+            CacheWrite::Waiting
+        }
+    }
+
+    /// Serve a read from the cache.
+    pub(crate) fn read(
+        &mut self,
+        io: *mut spdk_bdev_io,
+        lba: u64,
+        num_blocks: u64,
+        out: &mut [u8],
+    ) -> CacheRead {
+        match self.cache.read(lba, num_blocks, out) {
+            Lookup::Hit => CacheRead::Hit,
+            Lookup::Miss => CacheRead::Miss,
+            Lookup::Dirty => {
+                self.wait(CacheWait::Range(lba, num_blocks), io);
+                CacheRead::Waiting
+            }
+        }
+    }
+
+    /// Returns true if an unmap or write zeroes IO waits for dirty blocks in
+    /// its range, otherwise the range is dropped from the cache and the IO
+    /// must be submitted to the children.
+    pub(crate) fn discard(
+        &mut self,
+        io: *mut spdk_bdev_io,
+        lba: u64,
+        num_blocks: u64,
+    ) -> bool {
+        if self.cache.overlaps_dirty(lba, num_blocks) {
+            self.wait(CacheWait::Range(lba, num_blocks), io);
+            true
+        } else {
+            self.cache.invalidate(lba, num_blocks);
+            false
+        }
+    }
+
+    /// Hold a flush until all data written so far has been written back.
+    pub(crate) fn flush(&mut self, io: *mut spdk_bdev_io) {
+        let gen = self.cache.gen();
+        self.wait(CacheWait::Flush(gen), io);
+    }
+
+    /// take the waiting IO whose condition is met, in order
+    fn take_ready(&mut self) -> Vec<(CacheWait, *mut spdk_bdev_io)> {
+        let cache = &self.cache;
+        let (ready, waiting) =
+            self.waiting.drain(..).partition::<Vec<_>, _>(|(wait, _)| {
+                match *wait {
+                    CacheWait::Range(lba, n) => !cache.overlaps_dirty(lba, n),
+                    CacheWait::Room => !cache.is_filling_up(),
+                    CacheWait::Flush(gen) => cache.written_back_to(gen),
+                }
+            });
+        self.waiting = waiting.into();
+        ready
+    }
+
+    /// Start writing dirty data back when it is due, there is IO waiting for
+    /// it or the cache fills up.
+    fn start_writeback(&mut self) {
+        if self.busy.get() || self.cache.is_clean() {
+            return;
+        }
+
+        let urgent = !self.waiting.is_empty()
+            || self.cache.draining.load()
+            || self.cache.is_filling_up();
+        if !urgent
+            && self.last_writeback.elapsed()
+                < self.cache.opts.writeback_interval()
+        {
+            return;
+        }
+
+        let runs = self
+            .cache
+            .take_dirty(
+                max(CACHE_WRITEBACK_BYTES / self.cache.block_len, 1) as usize
+            );
+        if runs.is_empty() {
+            return;
+        }
+
+        self.last_writeback = Instant::now();
+        self.busy.set(true);
+        let busy = self.busy.clone();
+        let cache = self.cache.clone();
+        let nexus_name = self.nexus_name.clone();
+        Reactors::current()
+            .spawn_local(async move {
+                writeback(&nexus_name, &cache, runs).await;
+                busy.set(false);
+            })
+            .detach();
+    }
+
+    /// Stop caching on the channel, returning the waiting IO which must be
+    /// resubmitted by the caller.
+    pub(crate) fn stop(mut self: Box<Self>) -> Vec<*mut spdk_bdev_io> {
+        if let Some(p) = self.poller.take() {
+            p.stop();
+        }
+        self.waiting.drain(..).map(|(_, io)| io).collect()
+    }
+}
+
+/// Context to install a new cache on all channels of a nexus.
+struct SetCacheCtx {
+    cache: Option<Arc<WriteCache>>,
+    nexus_name: String,
+}
+
+fn set_cache_cb(
+    channel: &mut NexusChannel,
+    ctx: &mut SetCacheCtx,
+) -> ChannelTraverseStatus {
+    channel
+        .inner_mut()
+        .set_cache(ctx.cache.clone(), &ctx.nexus_name);
+    ChannelTraverseStatus::Ok
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the write cache settings of this nexus
+    pub fn write_cache_opts(&self) -> NexusCacheOpts {
+        self.write_cache
+            .lock()
+            .as_ref()
+            .map(|c| c.opts.clone())
+            .unwrap_or_default()
+    }
+
+    /// returns the write cache of this nexus, for newly created channels
+    pub(crate) fn write_cache(&self) -> Option<Arc<WriteCache>> {
+        self.write_cache.lock().clone()
+    }
+
+    /// Change the write cache of this nexus. The current cache is drained
+    /// first, so all data written before is on the children when the new
+    /// settings take effect. A size of 0 disables the cache.
+    pub async fn set_write_cache(
+        &self,
+        opts: NexusCacheOpts,
+    ) -> Result<(), Error> {
+        if opts.size > 0 && opts.size < self.block_len() {
+            return Err(Error::InvalidArguments {
+                name: self.name.clone(),
+                args: format!(
+                    "cache size {} is smaller than the block size",
+                    opts.size
+                ),
+            });
+        }
+        if opts.size > 0 {
+            self.check_plaintext("write cache")?;
+            if self.journal.is_some() {
+                return Err(Error::InvalidArguments {
+                    name: self.name.clone(),
+                    args: "a write cache can not be used with the write \
+                        journal"
+                        .to_string(),
+                });
+            }
+            if self.is_multi_writer() {
+                return Err(Error::InvalidArguments {
+                    name: self.name.clone(),
+                    args: "a write cache can not be used with several \
+                        writers"
+                        .to_string(),
+                });
+            }
+        }
+
+        if let Some(cache) = self.write_cache() {
+            info!("{}: draining write cache", self.name);
+            self.drain_write_cache(&cache).await;
+            *self.write_cache.lock() = None;
+            self.install_write_cache(None).await;
+        }
+
+        if opts.size > 0 {
+            warn!(
+                "{}: enabling VOLATILE write cache {:?}, acknowledged writes \
+                are lost if the process exits before they are written back",
+                self.name, opts
+            );
+            let cache = Arc::new(WriteCache::new(opts, self.block_len()));
+            *self.write_cache.lock() = Some(cache.clone());
+            self.install_write_cache(Some(cache)).await;
+        }
+
+        if let Some(nexus) = nexus_lookup_mut(&self.name) {
+            nexus.update_write_cache_flag();
+        }
+        Ok(())
+    }
+
+    /// Write back all dirty data of the cache. New writes bypass the cache
+    /// from here on. The channels write back as well, but the nexus may not
+    /// have any, so dirty data is written back from here too.
+    async fn drain_write_cache(&self, cache: &WriteCache) {
+        cache.draining.store(true);
+        while !cache.is_clean() {
+            let runs = cache
+                .take_dirty(
+                    max(CACHE_WRITEBACK_BYTES / cache.block_len, 1) as usize
+                );
+            if runs.is_empty() {
+                let _ = mayastor_sleep(Duration::from_millis(1)).await;
+            } else {
+                writeback(&self.name, cache, runs).await;
+            }
+        }
+    }
+
+    /// install the cache on all channels
+    async fn install_write_cache(&self, cache: Option<Arc<WriteCache>>) {
+        if self.has_io_device {
+            let r = for_each_channel(
+                self,
+                SetCacheCtx {
+                    cache,
+                    nexus_name: self.name.clone(),
+                },
+                set_cache_cb,
+            );
+            r.await.expect("set cache sender already dropped");
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetCacheArgs {
+    /// name of the nexus
+    name: String,
+    /// new cache settings, a size of 0 disables the cache
+    #[serde(flatten)]
+    opts: NexusCacheOpts,
+}
+
+#[derive(Debug, Serialize)]
+struct SetCacheReply {
+    #[serde(flatten)]
+    opts: NexusCacheOpts,
+    /// always true when the cache is enabled: acknowledged writes are lost
+    /// if the process exits before they are written back
+    volatile: bool,
+}
+
+async fn set_cache(args: SetCacheArgs) -> Result<SetCacheReply, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.set_write_cache(args.opts).await?;
+    let opts = nexus.write_cache_opts();
+    Ok(SetCacheReply {
+        volatile: opts.size > 0,
+        opts,
+    })
+}
+
+/// Register the json-rpc method to configure the write cache of a nexus.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_set_write_cache",
+        |args: SetCacheArgs| set_cache(args).boxed_local(),
+    );
+}
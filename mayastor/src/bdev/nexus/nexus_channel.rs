@@ -1,16 +1,56 @@
 //!
 //! IO is driven by means of so called channels.
-use std::{ffi::c_void, fmt::Debug, pin::Pin};
-
-use super::{ChildState, Nexus, Reason};
-
-use crate::core::{BlockDeviceHandle, Cores, Mthread};
+//!
+//! The channel data lives in the buffer SPDK allocates with the channel,
+//! rather than behind a pointer of its own, so that the submission path
+//! reaches it without a second indirection. The fields the submission path
+//! reads on every IO come first, the handles of the children being kept in
+//! one array per kind of IO so that selecting a reader only walks the
+//! readers, followed by the state of the features, which are None unless
+//! enabled. The number of IOs in flight is updated by the cores IO
+//! completes on, and is kept on a cache line of its own so that these
+//! updates do not evict the fields read on every IO from the cache of the
+//! core of the channel.
+use std::{
+    ffi::c_void,
+    fmt::Debug,
+    pin::Pin,
+    sync::{atomic::AtomicU64, Arc},
+};
+
+use super::{
+    is_direct,
+    nexus_resubmit_request,
+    CacheChannel,
+    ChecksumStore,
+    ChildLatencies,
+    ChildState,
+    EpochQueue,
+    IoDebugLog,
+    LimitQueue,
+    Nexus,
+    NexusCrypto,
+    NexusIoOpts,
+    NexusTier,
+    NexusTrace,
+    QosChannel,
+    QosLimiter,
+    ReadAhead,
+    ReadCacheChannel,
+    ReadLatencies,
+    ReadPolicy,
+    Reason,
+    RetryQueue,
+    WriteCache,
+};
+
+use crate::core::{BlockDeviceHandle, Cores, Mthread, Padded};
 
 /// io channel, per core
 #[repr(C)]
 #[derive(Debug)]
 pub struct NexusChannel {
-    inner: *mut NexusChannelInner,
+    inner: NexusChannelInner,
 }
 
 #[repr(C)]
@@ -19,7 +59,42 @@ pub(crate) struct NexusChannelInner {
     pub(crate) readers: Vec<Box<dyn BlockDeviceHandle>>,
     pub(crate) previous: usize,
     pub(crate) fail_fast: u32,
+    /// IO is submitted straight to the only child of the channel
+    pub(crate) direct: bool,
+    /// options of the IO path of the nexus
+    pub(crate) opts: NexusIoOpts,
     nexus_ref: *mut c_void,
+    /// QoS state, None if the nexus has no limits
+    pub(crate) qos: Option<Box<QosChannel>>,
+    /// write cache state, None if the nexus has no write cache
+    pub(crate) cache: Option<Box<CacheChannel>>,
+    /// read cache state, None if the nexus has no read cache
+    pub(crate) read_cache: Option<ReadCacheChannel>,
+    /// stream of sequential reads and the extents read ahead of it
+    pub(crate) read_ahead: ReadAhead,
+    /// latencies of the reads of the children, to read from the fastest
+    pub(crate) read_latencies: ReadLatencies,
+    /// checksums of the blocks of the nexus, None if it keeps none
+    pub(crate) checksums: Option<Arc<ChecksumStore>>,
+    /// encryption of the data of the nexus, None if it is not encrypted
+    pub(crate) crypto: Option<Arc<NexusCrypto>>,
+    /// trace of the IOs of the nexus, None if it is not traced
+    pub(crate) trace: Option<Arc<NexusTrace>>,
+    /// debug log of the routing of the IOs, None if it is off
+    pub(crate) io_debug: Option<Arc<IoDebugLog>>,
+    /// location of the data of a tiered nexus, None if it is not tiered
+    pub(crate) tier: Option<Arc<NexusTier>>,
+    /// latencies of the children, None if slow children are not detected
+    pub(crate) latencies: Option<Arc<ChildLatencies>>,
+    /// IO waiting for the channel to have children again
+    pub(crate) retry: RetryQueue,
+    /// IO waiting for the nexus to be under its limit of outstanding IOs
+    pub(crate) limit: LimitQueue,
+    /// writes waiting for the epoch of the nexus to be checked
+    pub(crate) epoch: EpochQueue,
+    /// number of IOs submitted to the nexus on this channel and not yet
+    /// completed, held IOs may be completed on other cores
+    pub(crate) io_in_flight: Padded<AtomicU64>,
 }
 
 impl Debug for NexusChannelInner {
@@ -45,6 +120,12 @@ pub enum DrEvent {
     ChildRemove,
     /// Child rebuild event
     ChildRebuild,
+    /// the block size exposed by the nexus changed
+    BlockSize,
+    /// a child is no longer read from for being slow, or is read from again
+    ChildSlow,
+    /// the options of the IO path of the nexus changed
+    Options,
 }
 
 /// Mark nexus child as faulted based on its device name
@@ -52,7 +133,6 @@ pub(crate) fn fault_nexus_child(nexus: Pin<&mut Nexus>, name: &str) -> bool {
     nexus
         .children
         .iter()
-        .filter(|c| c.state() == ChildState::Open)
         .filter(|c| {
             // If there were previous retires, we do not have a reference
             // to a BlockDevice. We do however, know it can't be the device
@@ -64,18 +144,14 @@ pub(crate) fn fault_nexus_child(nexus: Pin<&mut Nexus>, name: &str) -> bool {
                 false
             }
         })
-        .any(|c| {
-            Ok(ChildState::Open)
-                == c.state.compare_exchange(
-                    ChildState::Open,
-                    ChildState::Faulted(Reason::IoError),
-                )
-        })
+        .inspect(|c| c.record_io_fault())
+        .filter(|c| c.state() == ChildState::Open)
+        .any(|c| c.merge_fault(Reason::IoError))
 }
 
 impl NexusChannelInner {
     /// Returns reference to channel's Nexus.
-    fn get_nexus(&self) -> &Nexus {
+    pub(super) fn get_nexus(&self) -> &Nexus {
         unsafe {
             let n = self.nexus_ref as *const Nexus;
             &*n
@@ -95,9 +171,13 @@ impl NexusChannelInner {
     /// not the case but a side effect of using the async. As we poll
     /// threads more often depending on what core we are on etc, we might be
     /// "awaiting' while the thread is already trying to submit IO.
-    pub(crate) fn child_select(&mut self) -> Option<usize> {
+    pub(crate) fn child_select(&mut self, bytes: u64) -> Option<usize> {
         if self.readers.is_empty() {
             None
+        } else if self.opts.read_policy == ReadPolicy::Preferred {
+            Some(0)
+        } else if self.opts.read_policy == ReadPolicy::LowestLatency {
+            self.latency_select(bytes, None)
         } else {
             if self.previous < self.readers.len() - 1 {
                 self.previous += 1;
@@ -108,6 +188,39 @@ impl NexusChannelInner {
         }
     }
 
+    /// Select the child to read from in a tiered nexus: the fast child when
+    /// the latest data is only on it, one of the capacity children
+    /// otherwise.
+    pub(crate) fn tier_select(
+        &mut self,
+        tier: &NexusTier,
+        on_fast: bool,
+        bytes: u64,
+    ) -> Option<usize> {
+        let fast = self
+            .readers
+            .iter()
+            .position(|r| tier.is_fast(&r.get_device().device_name()));
+        if on_fast {
+            return fast;
+        }
+        if self.opts.read_policy == ReadPolicy::Preferred {
+            return (0 .. self.readers.len())
+                .find(|i| Some(*i) != fast)
+                .or(fast);
+        }
+        if self.opts.read_policy == ReadPolicy::LowestLatency {
+            return self.latency_select(bytes, fast);
+        }
+        for _ in 0 .. self.readers.len() {
+            let i = self.child_select(bytes)?;
+            if Some(i) != fast {
+                return Some(i);
+            }
+        }
+        fast
+    }
+
     /// Remove a child from the readers and/or writers
     pub fn remove_child(&mut self, name: &str) -> bool {
         self.previous = 0;
@@ -127,6 +240,7 @@ impl NexusChannelInner {
             .retain(|c| c.get_device().device_name() != name);
         self.writers
             .retain(|c| c.get_device().device_name() != name);
+        self.read_latencies.forget_readers();
 
         trace!(?name,
             "core: {} thread: {}: New number of IO channels write:{} read:{} out of {} children",
@@ -139,6 +253,36 @@ impl NexusChannelInner {
         self.fault_child(name)
     }
 
+    /// Replace the QoS limiter of this channel. IO held back under the old
+    /// limits is held back under the new ones, or submitted right away when
+    /// the nexus no longer has limits.
+    pub(crate) fn set_qos(&mut self, limiter: Option<Arc<QosLimiter>>) {
+        let held = self.qos.take().map(|q| q.stop()).unwrap_or_default();
+
+        match limiter {
+            Some(limiter) => {
+                let mut qos = QosChannel::new(limiter);
+                held.into_iter().for_each(|io| qos.hold_back(io));
+                self.qos = Some(qos);
+            }
+            None => held
+                .into_iter()
+                .for_each(|(io, _)| nexus_resubmit_request(io)),
+        }
+    }
+
+    /// Replace the write cache of this channel. IO waiting for the old cache
+    /// is resubmitted.
+    pub(crate) fn set_cache(
+        &mut self,
+        cache: Option<Arc<WriteCache>>,
+        nexus_name: &str,
+    ) {
+        let waiting = self.cache.take().map(|c| c.stop()).unwrap_or_default();
+        self.cache = cache.map(|c| CacheChannel::new(c, nexus_name));
+        waiting.into_iter().for_each(nexus_resubmit_request);
+    }
+
     /// Fault the child by marking its status.
     pub fn fault_child(&mut self, name: &str) -> bool {
         fault_nexus_child(self.get_nexus_mut(), name)
@@ -166,61 +310,32 @@ impl NexusChannelInner {
         // clearing the values will drop any existing handles in the
         // channel
         self.previous = 0;
+        self.opts = self.get_nexus().io_opts();
+        self.read_ahead.clear();
+        self.read_latencies.forget_readers();
 
         // nvmx will drop the IO qpairs which is different from all other
         // bdevs we might be dealing with. So instead of clearing and refreshing
         // which had no side effects before, we create a new vector and
         // swap them out later
 
-        let mut writers = Vec::new();
-        let mut readers = Vec::new();
-
-        // iterate over all our children which are in the open state
-        unsafe {
-            self.get_nexus_mut()
-                .get_unchecked_mut()
-                .children
-                .iter_mut()
-                .filter(|c| c.state() == ChildState::Open)
-                .for_each(|c| match (c.get_io_handle(), c.get_io_handle()) {
-                    (Ok(w), Ok(r)) => {
-                        writers.push(w);
-                        readers.push(r);
-                    }
-                    _ => {
-                        c.set_state(ChildState::Faulted(Reason::CantOpen));
-                        error!("failed to get I/O handle for {}", c.get_name());
-                    }
-                });
-        }
-
-        // then add write-only children
-        if !self.readers.is_empty() {
-            unsafe {
-                self.get_nexus_mut()
-                    .get_unchecked_mut()
-                    .children
-                    .iter_mut()
-                    .filter(|c| c.rebuilding())
-                    .for_each(|c| {
-                        if let Ok(hdl) = c.get_io_handle() {
-                            writers.push(hdl);
-                        } else {
-                            c.set_state(ChildState::Faulted(Reason::CantOpen));
-                            error!(
-                                "failed to get I/O handle for {}",
-                                c.get_name()
-                            );
-                        }
-                    });
-            }
-        }
+        let snapshot = self.get_nexus().child_snapshot();
+        let (writers, readers) = snapshot.handles(self.get_nexus_mut());
 
         self.writers.clear();
         self.readers.clear();
 
         self.writers = writers;
         self.readers = readers;
+        self.direct = is_direct(
+            self.opts.direct,
+            &snapshot,
+            &self.writers,
+            &self.readers,
+        );
+
+        // the IO deferred while the channel had no children can go now
+        self.resubmit_deferred();
 
         trace!(
             "{}: New number of IO channels write:{} read:{} out of {} children",
@@ -235,47 +350,83 @@ impl NexusChannelInner {
 }
 
 impl NexusChannel {
-    /// TODO
+    /// Create the channel data of a nexus, from the SPDK callback creating
+    /// the channel. The handles are got from the children in the latest
+    /// snapshot the nexus published, and the features from the nexus, none
+    /// of which waits on the control plane, so nothing blocks here.
     pub(crate) fn new(mut nexus: Pin<&mut Nexus>) -> Self {
-        let mut writers = Vec::new();
-        let mut readers = Vec::new();
-
-        unsafe {
-            nexus.as_mut().get_unchecked_mut()
-                .children
-                .iter_mut()
-                .filter(|c| c.state() == ChildState::Open)
-                .for_each(|c| match (c.get_io_handle(), c.get_io_handle()) {
-                    (Ok(w), Ok(r)) => {
-                        writers.push(w);
-                        readers.push(r);
-                    }
-                    _ => {
-                        c.set_state(ChildState::Faulted(Reason::CantOpen));
-                        error!("Failed to get I/O handle for {}, skipping block device", c.get_name())
-                    }
-                });
-        }
-
-        let channels = Box::new(NexusChannelInner {
+        let snapshot = nexus.child_snapshot();
+        let (writers, readers) = snapshot.handles(nexus.as_mut());
+
+        let qos = nexus.qos_limiter().map(QosChannel::new);
+        let cache = nexus
+            .write_cache()
+            .map(|c| CacheChannel::new(c, &nexus.name));
+        let read_cache = nexus.read_cache().and_then(ReadCacheChannel::new);
+        let checksums = nexus.checksum_store();
+        let crypto = nexus.crypto();
+        let trace = nexus.trace();
+        let io_debug = nexus.io_debug();
+        let tier = nexus.tier();
+        let latencies = nexus.child_latencies();
+        let opts = nexus.io_opts();
+        let direct = is_direct(opts.direct, &snapshot, &writers, &readers);
+
+        let inner = NexusChannelInner {
             writers,
             readers,
             previous: 0,
+            qos,
+            cache,
+            read_cache,
+            read_ahead: ReadAhead::default(),
+            read_latencies: ReadLatencies::default(),
+            checksums,
+            crypto,
+            trace,
+            io_debug,
+            tier,
+            latencies,
+            retry: RetryQueue::default(),
+            limit: LimitQueue::default(),
+            epoch: EpochQueue::default(),
+            io_in_flight: Padded::new(AtomicU64::new(0)),
             nexus_ref: unsafe { &mut *Pin::get_unchecked_mut(nexus) }
                 as *mut Nexus as *mut c_void,
             fail_fast: 0,
-        });
+            opts,
+            direct,
+        };
 
         Self {
-            inner: Box::into_raw(channels),
+            inner,
         }
     }
 
-    /// TODO
-    pub(crate) fn clear(self) {
-        let inner = unsafe { &mut *self.inner };
+    /// Stop the channel, resubmitting the IO it holds, before it is dropped.
+    pub(crate) fn clear(mut self) {
+        let inner = &mut self.inner;
         inner.writers.clear();
         inner.readers.clear();
+        if let Some(qos) = inner.qos.take() {
+            qos.stop()
+                .into_iter()
+                .for_each(|(io, _)| nexus_resubmit_request(io));
+        }
+        inner.stop_epoch_hold();
+        inner.stop_limiting();
+        if let Some(cache) = inner.cache.take() {
+            cache.stop().into_iter().for_each(nexus_resubmit_request);
+        }
+        inner.read_cache.take();
+        inner.read_ahead.clear();
+        inner.checksums.take();
+        inner.crypto.take();
+        inner.trace.take();
+        inner.io_debug.take();
+        inner.tier.take();
+        inner.latencies.take();
+        inner.stop_deferring();
     }
 
     /*
@@ -306,7 +457,7 @@ impl NexusChannel {
                     channels.readers.push(r);
                 }
                 _ => {
-                    c.set_state(ChildState::Faulted(Reason::CantOpen));
+                    c.merge_fault(Reason::CantOpen);
                     error!("Failed to get I/O handle for {}, skipping block device", c.get_name())
                 }
             });
@@ -326,13 +477,15 @@ impl NexusChannel {
     }
     */
 
-    /// TODO
+    /// returns the channel data
+    #[inline]
     pub(crate) fn inner(&self) -> &NexusChannelInner {
-        unsafe { &*self.inner }
+        &self.inner
     }
 
-    /// TODO
+    /// returns the channel data
+    #[inline]
     pub(crate) fn inner_mut(&mut self) -> &mut NexusChannelInner {
-        unsafe { &mut *self.inner }
+        &mut self.inner
     }
 }
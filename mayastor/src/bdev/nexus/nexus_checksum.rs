@@ -0,0 +1,567 @@
+//!
+//! Optional software checksums of the blocks of a nexus.
+//!
+//! Replicas on devices without protection information hardware have no way
+//! to tell that a block came back different from how it was written. In
+//! checksum mode the nexus keeps a CRC32C of every block it writes in a
+//! sidecar device, typically an lvol on a local pool, and verifies every
+//! block it reads from a child against it.
+//!
+//! When a read does not match, the nexus locks the range, reads it from all
+//! healthy children and completes the read with the first copy that matches.
+//! Children that returned a bad copy are repaired by writing the good copy
+//! to them. The read fails when no child has a good copy.
+//!
+//! The checksum of a block is only known once a write of it through this
+//! nexus has completed; blocks written before the sidecar was attached or
+//! unmapped since are not verified. The sidecar holds one little endian
+//! CRC32C per block, 0 meaning unknown, and must move with the volume: a
+//! sidecar that missed writes to the volume reports them as corruption.
+
+use std::{
+    cmp::{max, min},
+    collections::BTreeSet,
+    convert::TryInto,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crc::crc32::checksum_castagnoli;
+use crossbeam::atomic::AtomicCell;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use spdk_rs::{libspdk::spdk_bdev_io, ChannelTraverseStatus};
+
+use super::{
+    nexus_complete_read,
+    nexus_complete_request,
+    nexus_lookup_any_mut,
+    nexus_lookup_mut,
+    ChildState,
+    CreateChecksums,
+    Error,
+    Nexus,
+    NexusChannel,
+};
+
+use crate::{
+    bdev::{device_create, device_destroy, device_open},
+    core::{
+        for_each_channel,
+        BlockDeviceDescriptor,
+        BlockDeviceHandle,
+        CoreError,
+        RangeContext,
+        Reactors,
+        UntypedBdev,
+    },
+    jsonrpc::jsonrpc_register,
+    sleep::mayastor_sleep,
+};
+
+/// Interval at which updated checksums are written to the sidecar.
+const CHECKSUM_WRITEBACK_MS: u64 = 100;
+
+/// Amount of the sidecar read in one go when loading it.
+const CHECKSUM_LOAD_BYTES: u64 = 1024 * 1024;
+
+/// checksum of a block, never 0 which stands for unknown
+fn block_checksum(data: &[u8]) -> u32 {
+    max(checksum_castagnoli(data), 1)
+}
+
+/// Checksum statistics of a nexus.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ChecksumStats {
+    /// reads that did not match their checksums
+    pub mismatches: u64,
+    /// children repaired with a good copy from another child
+    pub repaired: u64,
+    /// reads failed because no child had a good copy
+    pub unrecoverable: u64,
+}
+
+/// The checksums of the blocks of a nexus, shared by all its channels.
+pub(crate) struct ChecksumStore {
+    uri: String,
+    desc: Box<dyn BlockDeviceDescriptor>,
+    block_len: u64,
+    /// checksum of each block of the nexus, 0 when not known
+    table: parking_lot::Mutex<Vec<u32>>,
+    /// blocks of the sidecar with checksums that have not been written yet
+    dirty: parking_lot::Mutex<BTreeSet<u64>>,
+    /// set when the sidecar is being detached
+    disabled: AtomicCell<bool>,
+    mismatches: AtomicU64,
+    repaired: AtomicU64,
+    unrecoverable: AtomicU64,
+}
+
+impl std::fmt::Debug for ChecksumStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChecksumStore")
+            .field("uri", &self.uri)
+            .field("disabled", &self.disabled.load())
+            .field("mismatches", &self.mismatches)
+            .field("repaired", &self.repaired)
+            .field("unrecoverable", &self.unrecoverable)
+            .finish()
+    }
+}
+
+impl ChecksumStore {
+    /// Load the checksums of a nexus of the given size from the sidecar.
+    async fn load(
+        uri: &str,
+        desc: Box<dyn BlockDeviceDescriptor>,
+        block_len: u64,
+        num_blocks: u64,
+    ) -> Result<Self, CoreError> {
+        let hdl = desc.get_io_handle()?;
+        let sidecar_block_len = hdl.get_device().block_len();
+        let total = num_blocks * 4;
+        let chunk = max(
+            CHECKSUM_LOAD_BYTES / sidecar_block_len * sidecar_block_len,
+            sidecar_block_len,
+        );
+
+        let mut table = Vec::with_capacity(num_blocks as usize);
+        let mut offset = 0;
+        while offset < total {
+            let len = min(
+                chunk,
+                (total - offset + sidecar_block_len - 1) / sidecar_block_len
+                    * sidecar_block_len,
+            );
+            let mut buf = hdl.dma_malloc(len).map_err(|_| {
+                CoreError::DmaAllocationError {
+                    size: len,
+                }
+            })?;
+            hdl.read_at(offset, &mut buf).await?;
+            table.extend(
+                buf.as_slice()
+                    .chunks_exact(4)
+                    .map(|c| u32::from_le_bytes(c.try_into().unwrap())),
+            );
+            offset += len;
+        }
+        table.truncate(num_blocks as usize);
+
+        Ok(Self {
+            uri: uri.to_string(),
+            desc,
+            block_len,
+            table: parking_lot::Mutex::new(table),
+            dirty: parking_lot::Mutex::new(BTreeSet::new()),
+            disabled: AtomicCell::new(false),
+            mismatches: AtomicU64::new(0),
+            repaired: AtomicU64::new(0),
+            unrecoverable: AtomicU64::new(0),
+        })
+    }
+
+    fn stats(&self) -> ChecksumStats {
+        ChecksumStats {
+            mismatches: self.mismatches.load(Ordering::Relaxed),
+            repaired: self.repaired.load(Ordering::Relaxed),
+            unrecoverable: self.unrecoverable.load(Ordering::Relaxed),
+        }
+    }
+
+    /// mark the sidecar blocks holding the given checksums dirty
+    fn mark_dirty(&self, lba: u64, num_blocks: u64) {
+        let per_block = self.desc.get_device().block_len() / 4;
+        let mut dirty = self.dirty.lock();
+        (lba / per_block ..= (lba + num_blocks - 1) / per_block).for_each(
+            |b| {
+                dirty.insert(b);
+            },
+        );
+    }
+
+    /// record the checksums of data written at the given block
+    pub(crate) fn record(&self, lba: u64, data: &[u8]) {
+        let num_blocks = data.len() as u64 / self.block_len;
+        {
+            let mut table = self.table.lock();
+            for (i, block) in
+                data.chunks_exact(self.block_len as usize).enumerate()
+            {
+                if let Some(c) = table.get_mut(lba as usize + i) {
+                    *c = block_checksum(block);
+                }
+            }
+        }
+        self.mark_dirty(lba, num_blocks);
+    }
+
+    /// forget the checksums of blocks whose content is no longer known
+    pub(crate) fn forget(&self, lba: u64, num_blocks: u64) {
+        {
+            let mut table = self.table.lock();
+            let end = min((lba + num_blocks) as usize, table.len());
+            table[min(lba as usize, end) .. end]
+                .iter_mut()
+                .for_each(|c| *c = 0);
+        }
+        self.mark_dirty(lba, num_blocks);
+    }
+
+    /// returns true if the data read at the given block matches the known
+    /// checksums
+    pub(crate) fn verify(&self, lba: u64, data: &[u8]) -> bool {
+        let table = self.table.lock();
+        data.chunks_exact(self.block_len as usize).enumerate().all(
+            |(i, block)| match table.get(lba as usize + i) {
+                Some(0) | None => true,
+                Some(c) => *c == block_checksum(block),
+            },
+        )
+    }
+
+    /// write the dirty blocks of the sidecar
+    async fn write_dirty(&self) {
+        let blocks = std::mem::take(&mut *self.dirty.lock());
+        if blocks.is_empty() {
+            return;
+        }
+
+        let hdl = match self.desc.get_io_handle() {
+            Ok(hdl) => hdl,
+            Err(e) => {
+                error!("failed to get handle of sidecar {}: {}", self.uri, e);
+                self.dirty.lock().extend(blocks);
+                return;
+            }
+        };
+
+        let sidecar_block_len = hdl.get_device().block_len();
+        let per_block = (sidecar_block_len / 4) as usize;
+        for b in blocks {
+            let mut buf = match hdl.dma_malloc(sidecar_block_len) {
+                Ok(buf) => buf,
+                Err(_) => {
+                    self.dirty.lock().insert(b);
+                    continue;
+                }
+            };
+
+            {
+                let table = self.table.lock();
+                let start = b as usize * per_block;
+                for (i, c) in buf.as_mut_slice().chunks_exact_mut(4).enumerate()
+                {
+                    let v = table.get(start + i).copied().unwrap_or(0);
+                    c.copy_from_slice(&v.to_le_bytes());
+                }
+            }
+
+            if let Err(e) = hdl.write_at(b * sidecar_block_len, &buf).await {
+                error!("failed to write sidecar {}: {}", self.uri, e);
+                self.dirty.lock().insert(b);
+            }
+        }
+    }
+}
+
+/// write updated checksums to the sidecar until it is detached
+async fn checksum_writeback(store: Arc<ChecksumStore>) {
+    while !store.disabled.load() {
+        let _ =
+            mayastor_sleep(Duration::from_millis(CHECKSUM_WRITEBACK_MS)).await;
+        store.write_dirty().await;
+    }
+}
+
+/// Read the given blocks from the children with the range locked, returning
+/// the first copy that matches the checksums. Children that returned another
+/// copy are repaired with it.
+async fn repair_locked(
+    nexus_name: &str,
+    store: &ChecksumStore,
+    lba: u64,
+    num_blocks: u64,
+) -> Option<Vec<u8>> {
+    let (offset, len, children) = {
+        let nexus = nexus_lookup_mut(nexus_name)?;
+        let block_len = nexus.block_len();
+        (
+            (lba + nexus.data_ent_offset) * block_len,
+            num_blocks * block_len,
+            nexus
+                .children
+                .iter()
+                .filter(|c| c.state() == ChildState::Open)
+                .filter_map(|c| {
+                    c.get_io_handle()
+                        .ok()
+                        .map(|h| (c.get_name().to_string(), h))
+                })
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    let mut good: Option<Vec<u8>> = None;
+    let mut bad = Vec::new();
+    for (name, hdl) in children {
+        let mut buf = hdl.dma_malloc(len).ok()?;
+        match hdl.read_at(offset, &mut buf).await {
+            Ok(_) if store.verify(lba, buf.as_slice()) => {
+                if good.is_none() {
+                    good = Some(buf.as_slice().to_vec());
+                }
+            }
+            _ => bad.push((name, hdl)),
+        }
+    }
+
+    let good = good?;
+    for (name, hdl) in bad {
+        let mut buf = match hdl.dma_malloc(len) {
+            Ok(buf) => buf,
+            Err(_) => continue,
+        };
+        buf.as_mut_slice().copy_from_slice(&good);
+        match hdl.write_at(offset, &buf).await {
+            Ok(_) => {
+                warn!(
+                    "{}: repaired blocks {}..{} of child {}",
+                    nexus_name,
+                    lba,
+                    lba + num_blocks,
+                    name
+                );
+                store.repaired.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => error!(
+                "{}: failed to repair blocks {}..{} of child {}: {}",
+                nexus_name,
+                lba,
+                lba + num_blocks,
+                name,
+                e
+            ),
+        }
+    }
+
+    Some(good)
+}
+
+/// Complete a read that did not match its checksums with a good copy from
+/// any child. The range is locked so that no write to it is in flight while
+/// children are repaired.
+pub(crate) async fn checksum_repair(
+    nexus_name: String,
+    store: Arc<ChecksumStore>,
+    io: *mut spdk_bdev_io,
+    lba: u64,
+    num_blocks: u64,
+) {
+    store.mismatches.fetch_add(1, Ordering::Relaxed);
+    warn!(
+        "{}: blocks {}..{} do not match their checksums",
+        nexus_name,
+        lba,
+        lba + num_blocks
+    );
+
+    let data = async {
+        let desc = UntypedBdev::open_by_name(&nexus_name, false).ok()?;
+        let ch = desc.get_channel()?;
+        let mut ctx = RangeContext::new(lba, num_blocks);
+        desc.lock_lba_range(&mut ctx, &ch).await.ok()?;
+        let data = repair_locked(&nexus_name, &store, lba, num_blocks).await;
+        let _ = desc.unlock_lba_range(&mut ctx, &ch).await;
+        data
+    }
+    .await;
+
+    match data {
+        Some(data) => nexus_complete_read(io, &data),
+        None => {
+            error!(
+                "{}: no child has a good copy of blocks {}..{}",
+                nexus_name,
+                lba,
+                lba + num_blocks
+            );
+            store.unrecoverable.fetch_add(1, Ordering::Relaxed);
+            nexus_complete_request(io, false);
+        }
+    }
+}
+
+/// Context to install a checksum store on all channels of a nexus.
+struct SetChecksumsCtx {
+    store: Option<Arc<ChecksumStore>>,
+}
+
+fn set_checksums_cb(
+    channel: &mut NexusChannel,
+    ctx: &mut SetChecksumsCtx,
+) -> ChannelTraverseStatus {
+    channel.inner_mut().checksums = ctx.store.clone();
+    ChannelTraverseStatus::Ok
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the uri of the checksum sidecar of this nexus
+    pub fn checksums_uri(&self) -> Option<String> {
+        self.checksums.lock().as_ref().map(|c| c.uri.clone())
+    }
+
+    /// returns the checksum statistics of this nexus
+    pub fn checksum_stats(&self) -> Option<ChecksumStats> {
+        self.checksums.lock().as_ref().map(|c| c.stats())
+    }
+
+    /// returns the checksum store of this nexus, for newly created channels
+    pub(crate) fn checksum_store(&self) -> Option<Arc<ChecksumStore>> {
+        self.checksums.lock().clone()
+    }
+
+    /// Keep checksums of the blocks of this nexus in the sidecar device at
+    /// the given uri, replacing the current one. None turns checksums off.
+    /// The sidecar needs 4 bytes per block of the nexus.
+    pub async fn set_checksums(&self, uri: Option<&str>) -> Result<(), Error> {
+        if uri.is_some() {
+            self.check_plaintext("checksums")?;
+        }
+
+        if let Some(store) = self.checksums.lock().take() {
+            self.remove_checksums(store).await;
+        }
+
+        let uri = match uri {
+            Some(uri) => uri,
+            None => return Ok(()),
+        };
+
+        let device = device_create(uri).await.context(CreateChecksums {
+            name: self.name.clone(),
+        })?;
+        let store = match device_open(&device, true) {
+            Ok(desc) => {
+                let dev = desc.get_device();
+                if dev.size_in_bytes() < self.num_blocks() * 4 {
+                    Err(Error::InvalidArguments {
+                        name: self.name.clone(),
+                        args: format!(
+                            "checksum sidecar {} must hold at least {} bytes",
+                            uri,
+                            self.num_blocks() * 4
+                        ),
+                    })
+                } else {
+                    ChecksumStore::load(
+                        uri,
+                        desc,
+                        self.block_len(),
+                        self.num_blocks(),
+                    )
+                    .await
+                    .map_err(|source| {
+                        Error::OpenChecksums {
+                            source,
+                            name: self.name.clone(),
+                        }
+                    })
+                }
+            }
+            Err(source) => Err(Error::OpenChecksums {
+                source,
+                name: self.name.clone(),
+            }),
+        };
+
+        let store = match store {
+            Ok(store) => Arc::new(store),
+            Err(e) => {
+                let _ = device_destroy(uri).await;
+                return Err(e);
+            }
+        };
+
+        info!("{}: keeping checksums in {}", self.name, uri);
+        *self.checksums.lock() = Some(store.clone());
+        self.install_checksums(Some(store.clone())).await;
+        Reactors::master().send_future(checksum_writeback(store));
+        Ok(())
+    }
+
+    /// Stop keeping checksums, write the last updates to the sidecar and
+    /// release it.
+    async fn remove_checksums(&self, store: Arc<ChecksumStore>) {
+        info!("{}: detaching checksum sidecar {}", self.name, store.uri);
+        self.install_checksums(None).await;
+        store.disabled.store(true);
+        store.write_dirty().await;
+
+        let uri = store.uri.clone();
+        drop(store);
+        if let Err(e) = device_destroy(&uri).await {
+            error!(
+                "{}: failed to release checksum sidecar {}: {}",
+                self.name, uri, e
+            );
+        }
+    }
+
+    /// install the checksum store on all channels
+    async fn install_checksums(&self, store: Option<Arc<ChecksumStore>>) {
+        if self.has_io_device {
+            let r = for_each_channel(
+                self,
+                SetChecksumsCtx {
+                    store,
+                },
+                set_checksums_cb,
+            );
+            r.await.expect("set checksums sender already dropped");
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetChecksumsArgs {
+    /// name of the nexus
+    name: String,
+    /// uri of the checksum sidecar, omit to turn checksums off
+    #[serde(default)]
+    uri: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChecksumsReply {
+    uri: Option<String>,
+    stats: Option<ChecksumStats>,
+}
+
+async fn set_checksums(
+    args: SetChecksumsArgs,
+) -> Result<ChecksumsReply, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.set_checksums(args.uri.as_deref()).await?;
+    Ok(ChecksumsReply {
+        uri: nexus.checksums_uri(),
+        stats: nexus.checksum_stats(),
+    })
+}
+
+/// Register the json-rpc method to turn checksums of a nexus on or off.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_set_checksums",
+        |args: SetChecksumsArgs| set_checksums(args).boxed_local(),
+    );
+}
@@ -1,6 +1,8 @@
 use std::{
+    collections::HashMap,
     fmt::{Debug, Display, Formatter},
     marker::PhantomData,
+    sync::Arc,
 };
 
 use crossbeam::atomic::AtomicCell;
@@ -10,7 +12,15 @@ use serde::Serialize;
 use snafu::{ResultExt, Snafu};
 use url::Url;
 
-use super::{nexus_iter_mut, nexus_lookup_mut, DrEvent, VerboseError};
+use super::{
+    nexus_iter_mut,
+    nexus_lookup_mut,
+    BlockEmulation,
+    ChildStates,
+    DrEvent,
+    EmulatedHandle,
+    VerboseError,
+};
 
 use crate::{
     bdev::{device_create, device_destroy, device_lookup},
@@ -19,8 +29,8 @@ use crate::{
         BlockDeviceDescriptor,
         BlockDeviceHandle,
         CoreError,
+        Cores,
         DeviceEventSink,
-        Reactor,
         Reactors,
     },
     nexus_uri::NexusBdevError,
@@ -106,6 +116,9 @@ pub enum Reason {
     IoError,
     /// the child has been explicitly faulted due to a rpc call
     Rpc,
+    /// the child is open but kept out of the IO path and not rebuilt until
+    /// it is activated
+    Standby,
 }
 
 impl Display for Reason {
@@ -123,6 +136,9 @@ impl Display for Reason {
             }
             Self::IoError => write!(f, "The child had too many I/O errors"),
             Self::Rpc => write!(f, "The child is faulted due to a rpc call"),
+            Self::Standby => {
+                write!(f, "The child is on standby until it is activated")
+            }
         }
     }
 }
@@ -144,6 +160,23 @@ pub enum ChildState {
     Faulted(Reason),
 }
 
+impl ChildState {
+    /// Returns how severe the state is, a state can only be replaced by a
+    /// fault of the same or a higher severity. An out of sync or standby
+    /// child only degrades the nexus, any other fault takes the child out of
+    /// it and a fault requested over rpc is never overridden by the IO path.
+    pub fn severity(&self) -> u8 {
+        match self {
+            Self::Faulted(Reason::Rpc) => 3,
+            Self::Faulted(Reason::OutOfSync)
+            | Self::Faulted(Reason::Standby)
+            | Self::Faulted(Reason::Unknown) => 1,
+            Self::Faulted(_) => 2,
+            _ => 0,
+        }
+    }
+}
+
 impl Display for ChildState {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -179,6 +212,13 @@ pub struct NexusChild<'c> {
     /// TODO
     #[serde(skip_serializing)]
     device_descriptor: Option<Box<dyn BlockDeviceDescriptor>>,
+    /// emulation of the block size of the nexus, if it differs from the one
+    /// of the device
+    #[serde(skip_serializing)]
+    emulation: Option<Arc<BlockEmulation>>,
+    /// number of IO faults seen by each core
+    #[serde(skip_serializing)]
+    io_faults: parking_lot::Mutex<HashMap<u32, u64>>,
     /// TODO
     _c: PhantomData<&'c ()>,
 }
@@ -198,6 +238,20 @@ impl Display for NexusChild<'_> {
     }
 }
 
+impl ChildStates for NexusChild<'_> {
+    fn state(&self) -> ChildState {
+        self.state.load()
+    }
+
+    fn prev_state(&self) -> ChildState {
+        self.prev_state.load()
+    }
+
+    fn set_state(&self, state: ChildState) {
+        NexusChild::set_state(self, state)
+    }
+}
+
 impl<'c> NexusChild<'c> {
     pub(crate) fn set_state(&self, state: ChildState) {
         let prev_state = self.state.swap(state);
@@ -211,6 +265,55 @@ impl<'c> NexusChild<'c> {
         );
     }
 
+    /// Fault the child unless it is in a state of a higher severity already,
+    /// or is being closed or destroyed. Faults are raised by the channels of
+    /// all cores at the same time, merging them makes sure the state of the
+    /// child never goes back to a less severe fault because a core was late.
+    /// Returns true if the state has changed.
+    pub(crate) fn merge_fault(&self, reason: Reason) -> bool {
+        let state = ChildState::Faulted(reason);
+        let mut current = self.state.load();
+        loop {
+            if !matches!(
+                current,
+                ChildState::Init | ChildState::Open | ChildState::Faulted(_)
+            ) || current.severity() > state.severity()
+                || current == state
+            {
+                return false;
+            }
+            match self.state.compare_exchange(current, state) {
+                Ok(prev) => {
+                    self.prev_state.store(prev);
+                    trace!(
+                        "{}: child {}: state merged from {} to {}",
+                        self.parent,
+                        self.name,
+                        prev,
+                        state,
+                    );
+                    return true;
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Count an IO fault of the child on the current core.
+    pub(crate) fn record_io_fault(&self) {
+        *self.io_faults.lock().entry(Cores::current()).or_insert(0) += 1;
+    }
+
+    /// Returns the number of IO faults of the child seen by each core.
+    pub fn io_faults_per_core(&self) -> HashMap<u32, u64> {
+        self.io_faults.lock().clone()
+    }
+
+    /// Returns the number of IO faults of the child over all cores.
+    pub fn io_faults(&self) -> u64 {
+        self.io_faults.lock().values().sum()
+    }
+
     /// Open the child in RW mode and claim the device to be ours. If the child
     /// is already opened by someone else (i.e one of the targets) it will
     /// error out.
@@ -228,29 +331,25 @@ impl<'c> NexusChild<'c> {
         trace!("{}: Opening child device {}", self.parent, self.name);
 
         // verify the state of the child before we open it
-        match self.state() {
-            ChildState::Faulted(reason) => {
+        match self.check_open() {
+            Err(e) => {
                 error!(
-                    "{}: can not open child {} reason {}",
-                    self.parent, self.name, reason
+                    "{}: can not open child {} in state {}: {}",
+                    self.parent,
+                    self.name,
+                    self.state(),
+                    e
                 );
-                return Err(ChildError::ChildFaulted {});
+                return Err(e);
             }
-            ChildState::Open => {
+            Ok(true) => {
                 // the child (should) already be open
                 assert!(self.device.is_some());
                 assert!(self.device_descriptor.is_some());
                 info!("called open on an already opened child");
                 return Ok(self.name.clone());
             }
-            ChildState::Destroying => {
-                error!(
-                    "{}: cannot open child {} being destroyed",
-                    self.parent, self.name
-                );
-                return Err(ChildError::ChildBeingDestroyed {});
-            }
-            _ => {}
+            Ok(false) => {}
         }
 
         let dev = self.device.as_ref().unwrap();
@@ -481,7 +580,7 @@ impl<'c> NexusChild<'c> {
     /// subsequently be rebuilt.
     pub(crate) async fn fault(&mut self, reason: Reason) {
         match reason {
-            Reason::OutOfSync => {
+            Reason::OutOfSync | Reason::Standby => {
                 self.set_state(ChildState::Faulted(reason));
             }
             _ => {
@@ -529,25 +628,21 @@ impl<'c> NexusChild<'c> {
     ) -> Result<String, ChildError> {
         // Only online a child if it was previously set offline. Check for a
         // "Closed" state as that is what offlining a child will set it to.
-        match self.state.load() {
-            ChildState::Closed => {
-                // Re-create the block device as it will have been previously
-                // destroyed.
-                let name = device_create(&self.name).await.context(
-                    ChildBdevCreate {
-                        child: self.name.clone(),
-                    },
-                )?;
-
-                self.device = device_lookup(&name);
-                if self.device.is_none() {
-                    warn!(
-                        "{}: failed to lookup device after successful creation",
-                        self.name,
-                    );
-                }
-            }
-            _ => return Err(ChildError::ChildNotClosed {}),
+        self.check_online()?;
+
+        // Re-create the block device as it will have been previously
+        // destroyed.
+        let name =
+            device_create(&self.name).await.context(ChildBdevCreate {
+                child: self.name.clone(),
+            })?;
+
+        self.device = device_lookup(&name);
+        if self.device.is_none() {
+            warn!(
+                "{}: failed to lookup device after successful creation",
+                self.name,
+            );
         }
 
         let result = self.open(parent_size);
@@ -615,35 +710,13 @@ impl<'c> NexusChild<'c> {
     pub(crate) fn remove(&mut self) {
         info!("{}: removing child", self.name);
 
-        let mut state = self.state();
+        let (state, destroying) = self.on_remove();
 
-        let mut destroying = false;
         // Only remove the device if the child is being destroyed instead of
         // a hot remove event.
-        if state == ChildState::Destroying {
+        if destroying {
             // Block device is being removed, so ensure we don't use it again.
             self.device = None;
-            destroying = true;
-
-            state = self.prev_state.load();
-        }
-        match state {
-            ChildState::Open | ChildState::Faulted(Reason::OutOfSync) => {
-                // Change the state of the child to ensure it is taken out of
-                // the I/O path when the nexus is reconfigured.
-                self.set_state(ChildState::Closed)
-            }
-            // leave the state into whatever we found it as
-            _ => {
-                if destroying {
-                    // Restore the previous state
-                    info!(
-                        "Restoring previous child state {}",
-                        state.to_string()
-                    );
-                    self.set_state(state);
-                }
-            }
         }
 
         // Remove the child from the I/O path. If we had an IO error the block
@@ -652,37 +725,45 @@ impl<'c> NexusChild<'c> {
         // TODO: Revisit nexus reconfiguration once Nexus has switched to
         // BlockDevice-based children and is able to listen to
         // device-related events directly.
-        if state != ChildState::Faulted(Reason::IoError) {
-            let nexus_name = self.parent.clone();
-            Reactor::block_on(async move {
-                match nexus_lookup_mut(&nexus_name) {
-                    Some(n) => n.reconfigure(DrEvent::ChildRemove).await,
-                    None => error!("Nexus {} not found", nexus_name),
-                }
-            });
-        }
-
-        if destroying {
-            // Dropping the last descriptor results in the device being removed.
-            // This must be performed in this function.
-            self.device_descriptor.take();
+        let nexus_name = self.parent.clone();
+        let reconfigure = state != ChildState::Faulted(Reason::IoError);
+        if reconfigure {
+            // channels created from now on no longer get a handle to the child
+            match nexus_lookup_mut(&nexus_name) {
+                Some(n) => n.publish_children(),
+                None => error!("Nexus {} not found", nexus_name),
+            }
         }
 
-        self.remove_complete();
-        info!("Child {} removed", self.name);
-    }
+        // Dropping the last descriptor results in the device being removed,
+        // which must only happen once the channels have dropped their
+        // handles to it. Rather than blocking this callback on the
+        // reconfiguration of the channels, the descriptor is dropped once it
+        // completed.
+        let descriptor = if destroying {
+            self.device_descriptor.take()
+        } else {
+            None
+        };
 
-    /// Signal that the child removal is complete.
-    fn remove_complete(&self) {
         let mut sender = self.remove_channel.0.clone();
         let name = self.name.clone();
-        Reactors::current().send_future(async move {
+        Reactors::master().send_future(async move {
+            if reconfigure {
+                if let Some(n) = nexus_lookup_mut(&nexus_name) {
+                    n.reconfigure(DrEvent::ChildRemove).await;
+                }
+            }
+            drop(descriptor);
+
+            // signal that the child removal is complete
             if let Err(e) = sender.send(()).await {
                 error!(
                     "Failed to send remove complete for child {}, error {}",
                     name, e
                 );
             }
+            info!("Child {} removed", name);
         });
     }
 
@@ -702,6 +783,8 @@ impl<'c> NexusChild<'c> {
             device,
             parent,
             device_descriptor: None,
+            emulation: None,
+            io_faults: parking_lot::Mutex::new(HashMap::new()),
             state: AtomicCell::new(ChildState::Init),
             prev_state: AtomicCell::new(ChildState::Init),
             remove_channel: mpsc::channel(0),
@@ -723,6 +806,21 @@ impl<'c> NexusChild<'c> {
         Ok(())
     }
 
+    /// Detach the child from its device when its nexus is destructed, which
+    /// can not wait for the device to be removed. No removal event reaches
+    /// the child any more, so its descriptor is dropped here rather than on
+    /// removal, and the URI of the device is returned for the device to be
+    /// destroyed without the child.
+    pub(crate) fn detach(&mut self) -> Option<String> {
+        self.device.as_ref()?;
+        self.set_state(ChildState::Destroying);
+        if let Some(desc) = self.device_descriptor.take() {
+            desc.unclaim();
+        }
+        self.device = None;
+        Some(self.name.clone())
+    }
+
     /// Return reference to child's block device.
     pub fn get_device(&self) -> Result<&dyn BlockDevice, ChildError> {
         if let Some(ref device) = self.device {
@@ -763,7 +861,11 @@ impl<'c> NexusChild<'c> {
         &self,
     ) -> Result<Box<dyn BlockDeviceHandle>, CoreError> {
         if let Some(desc) = self.device_descriptor.as_ref() {
-            desc.get_io_handle()
+            let handle = desc.get_io_handle()?;
+            Ok(match &self.emulation {
+                Some(emu) => Box::new(EmulatedHandle::new(handle, emu.clone())),
+                None => handle,
+            })
         } else {
             error!("{}: nexus child does not have valid descriptor", self.name);
             Err(CoreError::InvalidDescriptor {
@@ -772,6 +874,44 @@ impl<'c> NexusChild<'c> {
         }
     }
 
+    /// Set the name of the nexus this child belongs to, after it has been
+    /// renamed.
+    pub(crate) fn set_parent(&mut self, parent: &str) {
+        self.parent = parent.to_string();
+    }
+
+    /// Present the given block size of the nexus to the IO of the nexus,
+    /// emulating it on top of the block size of the device if they differ.
+    pub(crate) fn set_block_emulation(&mut self, block_len: u64) {
+        self.emulation = match &self.device {
+            Some(dev) if dev.block_len() != block_len => {
+                info!(
+                    "{}: emulating block size {} on top of {}",
+                    self.name,
+                    block_len,
+                    dev.block_len()
+                );
+                Some(Arc::new(BlockEmulation::new(block_len)))
+            }
+            _ => None,
+        };
+    }
+
+    /// Whether the child has the given uuid, either as the uuid of its URI or
+    /// as the uuid of its device.
+    pub fn match_uuid(&self, uuid: &str) -> bool {
+        let uri_uuid = Url::parse(&self.name).ok().and_then(|url| {
+            url.query_pairs()
+                .find(|(k, _)| k == "uuid")
+                .map(|(_, v)| v.to_string())
+        });
+        uri_uuid.as_deref() == Some(uuid)
+            || self
+                .device
+                .as_ref()
+                .map_or(false, |d| d.uuid().to_string() == uuid)
+    }
+
     /// TODO
     pub fn match_device_name(&self, bdev_name: &str) -> bool {
         match &self.device {
@@ -816,3 +956,40 @@ pub fn lookup_nexus_child(bdev_name: &str) -> Option<&mut NexusChild> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_fault() {
+        let child = NexusChild::new("malloc:///c0".into(), "n0".into(), None);
+        child.set_state(ChildState::Open);
+
+        // a late core can not turn a fault back into a less severe one
+        assert!(child.merge_fault(Reason::IoError));
+        assert!(!child.merge_fault(Reason::OutOfSync));
+        assert_eq!(child.state(), ChildState::Faulted(Reason::IoError));
+        assert_eq!(child.prev_state.load(), ChildState::Open);
+
+        assert!(child.merge_fault(Reason::Rpc));
+        assert!(!child.merge_fault(Reason::CantOpen));
+        assert_eq!(child.state(), ChildState::Faulted(Reason::Rpc));
+
+        // a child that is going away is left alone
+        child.set_state(ChildState::Destroying);
+        assert!(!child.merge_fault(Reason::IoError));
+        assert_eq!(child.state(), ChildState::Destroying);
+    }
+
+    #[test]
+    fn standby_fault() {
+        let child = NexusChild::new("malloc:///c0".into(), "n0".into(), None);
+        child.set_state(ChildState::Faulted(Reason::Standby));
+
+        // a standby child that fails is faulted for good
+        assert!(!child.merge_fault(Reason::OutOfSync));
+        assert!(child.merge_fault(Reason::IoError));
+        assert_eq!(child.state(), ChildState::Faulted(Reason::IoError));
+    }
+}
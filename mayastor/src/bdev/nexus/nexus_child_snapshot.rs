@@ -0,0 +1,155 @@
+//!
+//! Snapshot of the children a nexus submits IO to.
+//!
+//! The channels of a nexus are created and refreshed on the cores they
+//! belong to, from SPDK callbacks that must not block, while the control
+//! plane changes the children on the master reactor. Rather than having
+//! every channel work out which children to submit IO to from the state of
+//! the children, their rebuild jobs and the detection of slow children, all
+//! of which the control plane changes as it goes, the nexus publishes a
+//! snapshot of that decision before it refreshes its channels, and the
+//! channels only load the latest snapshot, without taking any lock. A
+//! channel gets its handles from the children in the snapshot, skipping
+//! those that were removed from the nexus since it was published.
+
+use std::{collections::HashSet, pin::Pin, sync::Arc};
+
+use super::{ChildState, Nexus, Reason};
+use crate::core::BlockDeviceHandle;
+
+/// How the channels submit IO to a child.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChildRole {
+    /// the child is read from and written to
+    ReadWrite,
+    /// the child is being rebuilt and only written to
+    WriteOnly,
+}
+
+/// A child the channels submit IO to.
+#[derive(Debug, Clone)]
+pub(crate) struct ChildEntry {
+    /// index of the child in the children of the nexus
+    pub(crate) index: usize,
+    pub(crate) name: String,
+    pub(crate) role: ChildRole,
+}
+
+/// The children the channels of a nexus submit IO to.
+#[derive(Debug, Default)]
+pub(crate) struct ChildSnapshot {
+    /// the children read from and written to first, then those only written
+    pub(crate) children: Vec<ChildEntry>,
+    /// device names of the slow children that are not read from
+    pub(crate) ejected: HashSet<String>,
+}
+
+impl ChildSnapshot {
+    /// Get the handles of a channel to the children in the snapshot, as its
+    /// writers and readers, faulting the children that fail to give one.
+    pub(crate) fn handles(
+        &self,
+        nexus: Pin<&mut Nexus>,
+    ) -> (
+        Vec<Box<dyn BlockDeviceHandle>>,
+        Vec<Box<dyn BlockDeviceHandle>>,
+    ) {
+        let mut writers = Vec::with_capacity(self.children.len());
+        let mut readers = Vec::with_capacity(self.children.len());
+        let children = unsafe { &mut nexus.get_unchecked_mut().children };
+
+        for entry in &self.children {
+            // the child was removed since the snapshot was published
+            let child = match children.get_mut(entry.index) {
+                Some(child) if child.name == entry.name => child,
+                _ => continue,
+            };
+            let handles = match entry.role {
+                ChildRole::ReadWrite => child
+                    .get_io_handle()
+                    .and_then(|w| child.get_io_handle().map(|r| (w, Some(r)))),
+                ChildRole::WriteOnly => {
+                    child.get_io_handle().map(|w| (w, None))
+                }
+            };
+            match handles {
+                Ok((w, r)) => {
+                    writers.push(w);
+                    readers.extend(r);
+                }
+                Err(_) => {
+                    child.merge_fault(Reason::CantOpen);
+                    error!("failed to get I/O handle for {}", child.get_name());
+                }
+            }
+        }
+
+        self.retain_readers(&mut readers);
+        (writers, readers)
+    }
+
+    /// Take the readers of the slow children out of the readers of a
+    /// channel, unless none would be left.
+    pub(crate) fn retain_readers(
+        &self,
+        readers: &mut Vec<Box<dyn BlockDeviceHandle>>,
+    ) {
+        let ejected = &self.ejected;
+        if ejected.is_empty()
+            || readers
+                .iter()
+                .all(|r| ejected.contains(&r.get_device().device_name()))
+        {
+            return;
+        }
+        readers.retain(|r| !ejected.contains(&r.get_device().device_name()));
+    }
+}
+
+impl<'n> Nexus<'n> {
+    /// Publish the children the channels of this nexus submit IO to, as they
+    /// are now, for the channels created or refreshed from now on. Called on
+    /// the master reactor.
+    pub(crate) fn publish_children(&self) {
+        let open = self
+            .children
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.state() == ChildState::Open)
+            .map(|(index, c)| ChildEntry {
+                index,
+                name: c.name.clone(),
+                role: ChildRole::ReadWrite,
+            })
+            .collect::<Vec<_>>();
+
+        // a child is only rebuilt from another one
+        let rebuilding = self
+            .children
+            .iter()
+            .enumerate()
+            .filter(|_| !open.is_empty())
+            .filter(|(_, c)| c.rebuilding())
+            .map(|(index, c)| ChildEntry {
+                index,
+                name: c.name.clone(),
+                role: ChildRole::WriteOnly,
+            })
+            .collect::<Vec<_>>();
+
+        let ejected = self
+            .child_latencies()
+            .map(|l| l.ejected())
+            .unwrap_or_default();
+
+        self.child_snapshot.publish(ChildSnapshot {
+            children: open.into_iter().chain(rebuilding).collect(),
+            ejected,
+        });
+    }
+
+    /// returns the latest snapshot of the children the channels submit IO to
+    pub(crate) fn child_snapshot(&self) -> Arc<ChildSnapshot> {
+        self.child_snapshot.load()
+    }
+}
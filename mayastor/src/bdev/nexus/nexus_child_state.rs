@@ -0,0 +1,105 @@
+//!
+//! State transitions of a nexus child.
+//!
+//! The transitions are kept apart from the IO the child does so that the
+//! simulation tests can drive them on fake children, in any order of events
+//! the reactors could deliver them in.
+
+use super::{ChildError, ChildState, Reason};
+use crate::rebuild::RebuildState;
+
+/// What has to be done with a child once its rebuild job is done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RebuildOutcome {
+    /// the child was rebuilt and is now open
+    Rebuilt,
+    /// the job was stopped, the child is left as it is
+    Stopped,
+    /// the job completed but the child changed state in the meantime, eg it
+    /// was faulted or removed, so it must not be opened
+    Stale,
+    /// the job failed, the child must be faulted
+    Failed,
+}
+
+/// State transitions of a child, built on top of getting and setting its
+/// current and previous state.
+pub(crate) trait ChildStates {
+    /// current state of the child
+    fn state(&self) -> ChildState;
+    /// state of the child before the current one
+    fn prev_state(&self) -> ChildState;
+    /// change the state of the child, keeping the current one as previous
+    fn set_state(&self, state: ChildState);
+
+    /// Check whether the child may be opened, returning true if it is
+    /// already open.
+    fn check_open(&self) -> Result<bool, ChildError> {
+        match self.state() {
+            ChildState::Faulted(_) => Err(ChildError::ChildFaulted {}),
+            ChildState::Destroying => Err(ChildError::ChildBeingDestroyed {}),
+            ChildState::Open => Ok(true),
+            _ => Ok(false),
+        }
+    }
+
+    /// Check whether the child may be onlined, which is only the case if it
+    /// was offlined before.
+    fn check_online(&self) -> Result<(), ChildError> {
+        match self.state() {
+            ChildState::Closed => Ok(()),
+            _ => Err(ChildError::ChildNotClosed {}),
+        }
+    }
+
+    /// Take the child out of the IO path on removal of its device. Returns
+    /// the state the child was found in and whether it is being destroyed.
+    fn on_remove(&self) -> (ChildState, bool) {
+        let mut state = self.state();
+        let destroying = state == ChildState::Destroying;
+        if destroying {
+            state = self.prev_state();
+        }
+
+        match state {
+            ChildState::Open
+            | ChildState::Faulted(Reason::OutOfSync)
+            | ChildState::Faulted(Reason::Standby) => {
+                // Change the state of the child to ensure it is taken out of
+                // the I/O path when the nexus is reconfigured.
+                self.set_state(ChildState::Closed)
+            }
+            // leave the state into whatever we found it as
+            _ => {
+                if destroying {
+                    // Restore the previous state
+                    info!(
+                        "Restoring previous child state {}",
+                        state.to_string()
+                    );
+                    self.set_state(state);
+                }
+            }
+        }
+        (state, destroying)
+    }
+
+    /// Apply the final state of the rebuild job of the child. The child is
+    /// only opened if it is still waiting for the rebuild, as it may have
+    /// been faulted or removed between the job completing and the nexus
+    /// being notified.
+    fn on_rebuild_done(&self, job: RebuildState) -> RebuildOutcome {
+        match job {
+            RebuildState::Completed => {
+                if self.state() == ChildState::Faulted(Reason::OutOfSync) {
+                    self.set_state(ChildState::Open);
+                    RebuildOutcome::Rebuilt
+                } else {
+                    RebuildOutcome::Stale
+                }
+            }
+            RebuildState::Stopped => RebuildOutcome::Stopped,
+            _ => RebuildOutcome::Failed,
+        }
+    }
+}
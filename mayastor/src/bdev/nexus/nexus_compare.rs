@@ -0,0 +1,223 @@
+//!
+//! Compare and write on the nexus.
+//!
+//! Clustered applications use compare and write, a fused NVMe compare and
+//! write command, as an atomic test-and-set of a block to implement locks.
+//! The write only takes place when the blocks hold the data given to compare
+//! with, and no other write to the blocks may slip in between.
+//!
+//! The nexus locks the range, so that writes to it from any channel wait,
+//! reads the blocks from all healthy children and compares the first copy
+//! with the data of the command. When it matches the new data is written to
+//! all the children the channels write to, the ones being rebuilt included,
+//! otherwise the command completes with a miscompare. Copies that differ from
+//! the first one mean that the children have diverged, for instance after a
+//! write failed on some of them. They are reconciled by the write, or by
+//! writing the first copy to them and to the children being rebuilt on a
+//! miscompare, so that the next command sees the same data on every child.
+//! Children a write fails on are retired. A plain compare is done the same
+//! way, without the write.
+//!
+//! Encrypted nexuses and nexuses with a write cache do not support these
+//! commands themselves, the bdev layer emulates them with reads and writes
+//! of the nexus instead.
+
+use spdk_rs::libspdk::spdk_bdev_io;
+
+use super::{
+    nexus_child_retire,
+    nexus_complete_miscompare,
+    nexus_complete_request,
+    nexus_lookup_mut,
+    ChildRole,
+};
+use crate::core::{BlockDeviceHandle, RangeContext, Reactors, UntypedBdev};
+
+/// Outcome of a compare and write.
+enum Compared {
+    Match,
+    Miscompare,
+    Failed,
+}
+
+/// A child of the nexus, its handle and how the channels submit IO to it.
+type Child = (String, Box<dyn BlockDeviceHandle>, ChildRole);
+
+/// Write the data to the given children, returning the names of the devices
+/// the write failed on.
+async fn write_children(
+    children: &[&Child],
+    offset: u64,
+    data: &[u8],
+) -> Vec<String> {
+    let mut failed = Vec::new();
+    for (name, hdl, _) in children {
+        let mut buf = match hdl.dma_malloc(data.len() as u64) {
+            Ok(buf) => buf,
+            Err(e) => {
+                error!("failed to write child {} at {}: {}", name, offset, e);
+                failed.push(hdl.get_device().device_name());
+                continue;
+            }
+        };
+        buf.as_mut_slice().copy_from_slice(data);
+        if let Err(e) = hdl.write_at(offset, &buf).await {
+            error!("failed to write child {} at {}: {}", name, offset, e);
+            failed.push(hdl.get_device().device_name());
+        }
+    }
+    failed
+}
+
+/// Compare the given blocks of the children with the data and write them
+/// with the range locked. Returns the outcome and the devices to retire.
+async fn compare_locked(
+    nexus_name: &str,
+    lba: u64,
+    num_blocks: u64,
+    compare: &[u8],
+    write: Option<&[u8]>,
+) -> (Compared, Vec<String>) {
+    let (offset, len, children) = match nexus_lookup_mut(nexus_name) {
+        Some(nexus) => {
+            let block_len = nexus.block_len();
+            (
+                (lba + nexus.data_ent_offset) * block_len,
+                num_blocks * block_len,
+                // the children the channels write to, as the writes do
+                nexus
+                    .child_snapshot()
+                    .children
+                    .iter()
+                    .filter_map(|e| {
+                        let child = nexus
+                            .children
+                            .get(e.index)
+                            .filter(|c| c.name == e.name)?;
+                        let hdl = child.get_io_handle().ok()?;
+                        Some((e.name.clone(), hdl, e.role))
+                    })
+                    .collect::<Vec<Child>>(),
+            )
+        }
+        None => return (Compared::Failed, Vec::new()),
+    };
+
+    // children being rebuilt are written to but not read from
+    let (readers, rebuilding): (Vec<&Child>, Vec<&Child>) = children
+        .iter()
+        .partition(|(_, _, role)| *role == ChildRole::ReadWrite);
+
+    let mut copies = Vec::with_capacity(readers.len());
+    for (name, hdl, _) in &readers {
+        let mut buf = match hdl.dma_malloc(len) {
+            Ok(buf) => buf,
+            Err(_) => return (Compared::Failed, Vec::new()),
+        };
+        match hdl.read_at(offset, &mut buf).await {
+            Ok(_) => copies.push(Some(buf.as_slice().to_vec())),
+            Err(e) => {
+                error!("failed to read child {} at {}: {}", name, offset, e);
+                copies.push(None);
+            }
+        }
+    }
+
+    let reference = match copies.iter().flatten().next() {
+        Some(copy) => copy.clone(),
+        None => return (Compared::Failed, Vec::new()),
+    };
+
+    let mut diverged = readers
+        .iter()
+        .zip(&copies)
+        .filter(|(_, copy)| copy.as_ref() != Some(&reference))
+        .map(|(child, _)| *child)
+        .collect::<Vec<_>>();
+    if !diverged.is_empty() {
+        warn!(
+            "{}: blocks {}..{} differ between children, reconciling {:?}",
+            nexus_name,
+            lba,
+            lba + num_blocks,
+            diverged.iter().map(|(name, ..)| name).collect::<Vec<_>>()
+        );
+        // the children being rebuilt may hold either copy
+        diverged.extend(rebuilding.iter().copied());
+    }
+
+    if reference != compare {
+        let failed = write_children(&diverged, offset, &reference).await;
+        return (Compared::Miscompare, failed);
+    }
+
+    let data = match write {
+        Some(data) => data,
+        None => {
+            let failed = write_children(&diverged, offset, &reference).await;
+            return (Compared::Match, failed);
+        }
+    };
+
+    let all = children.iter().collect::<Vec<_>>();
+    let failed = write_children(&all, offset, data).await;
+    if readers
+        .iter()
+        .all(|(_, hdl, _)| failed.contains(&hdl.get_device().device_name()))
+    {
+        return (Compared::Failed, failed);
+    }
+
+    if let Some(cs) =
+        nexus_lookup_mut(nexus_name).and_then(|n| n.checksum_store())
+    {
+        cs.record(lba, data);
+    }
+    (Compared::Match, failed)
+}
+
+/// Compare the given blocks of the nexus with the data, and write the new
+/// data if given and the blocks match. The IO is completed with the outcome.
+pub(crate) async fn compare_and_write(
+    nexus_name: String,
+    io: *mut spdk_bdev_io,
+    lba: u64,
+    num_blocks: u64,
+    compare: Vec<u8>,
+    write: Option<Vec<u8>>,
+) {
+    let outcome = async {
+        let desc = UntypedBdev::open_by_name(&nexus_name, false).ok()?;
+        let ch = desc.get_channel()?;
+        let mut ctx = RangeContext::new(lba, num_blocks);
+        desc.lock_lba_range(&mut ctx, &ch).await.ok()?;
+        let outcome = compare_locked(
+            &nexus_name,
+            lba,
+            num_blocks,
+            &compare,
+            write.as_deref(),
+        )
+        .await;
+        let _ = desc.unlock_lba_range(&mut ctx, &ch).await;
+        Some(outcome)
+    }
+    .await;
+
+    let (compared, failed) = outcome.unwrap_or((Compared::Failed, Vec::new()));
+    if let Some(journal) =
+        nexus_lookup_mut(&nexus_name).and_then(|n| n.journal.clone())
+    {
+        failed.iter().for_each(|device| journal.freeze(device));
+    }
+    match compared {
+        Compared::Match => nexus_complete_request(io, true),
+        Compared::Miscompare => nexus_complete_miscompare(io),
+        Compared::Failed => nexus_complete_request(io, false),
+    }
+
+    for device in failed {
+        Reactors::master()
+            .send_future(nexus_child_retire(nexus_name.clone(), device));
+    }
+}
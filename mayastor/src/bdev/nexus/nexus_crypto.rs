@@ -0,0 +1,344 @@
+//!
+//! Encryption of the data of a nexus.
+//!
+//! A nexus can encrypt the data of its volume with a key of its own, so that
+//! a volume is encrypted even when its replicas live on unencrypted pools,
+//! and the replicas, local or on other nodes, only ever see ciphertext. The
+//! key is handed to the nexus when it is created, with the
+//! `nexus_create_encrypted` json-rpc method, and only kept in memory, so it
+//! must be given again whenever the nexus is created anew, e.g. after a
+//! restart. It can be given as a reference to a key of the key management
+//! service. The key field of PublishNexus keeps its former meaning and does
+//! not encrypt the nexus.
+//!
+//! Blocks are encrypted with XTS-AES as described by IEEE 1619, as
+//! implemented by the xts-mode crate, the block number within the nexus being
+//! the tweak. The key is zeroed when the nexus is destroyed. Writes are
+//! encrypted into a bounce buffer, leaving the buffers of the writer untouched,
+//! and reads are decrypted in place when they complete. All children hold the
+//! same ciphertext at the same offset, so rebuilds copy ciphertext and do not
+//! need the key.
+//!
+//! The write cache, the read cache and checksums work on plaintext, none of
+//! them can be combined with encryption.
+
+use std::{pin::Pin, sync::Arc};
+
+use aes::{Aes128, Aes256, NewBlockCipher};
+use futures::FutureExt;
+use serde::Deserialize;
+use snafu::ResultExt;
+use spdk_rs::{DmaBuf, DmaError, IoVec};
+use xts_mode::{get_tweak_default, Xts128};
+use zeroize::Zeroizing;
+
+use super::{
+    default_size_policy,
+    nexus_create_internal,
+    nexus_lookup_mut,
+    Error,
+    Nexus,
+    NexusNvmeParams,
+    ResolveKey,
+};
+use crate::{jsonrpc::jsonrpc_register, key_manager::KeyManager};
+
+/// XTS-AES with keys of either size.
+enum XtsCipher {
+    Aes128(Xts128<Aes128>),
+    Aes256(Xts128<Aes256>),
+}
+
+/// Encryption state of a nexus, shared by all its channels.
+pub(crate) struct NexusCrypto {
+    cipher: XtsCipher,
+    /// the key as given, to tell whether the nexus is opened again with the
+    /// same key
+    key: Zeroizing<String>,
+    block_len: usize,
+}
+
+impl std::fmt::Debug for NexusCrypto {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NexusCrypto")
+            .field("block_len", &self.block_len)
+            .finish()
+    }
+}
+
+/// A key to encrypt the data of a nexus with, which is never printed.
+pub(crate) struct CryptoKey(Zeroizing<String>);
+
+impl std::fmt::Debug for CryptoKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CryptoKey(<redacted>)")
+    }
+}
+
+/// parse a hex encoded key
+fn parse_key(key: &str) -> Option<Zeroizing<Vec<u8>>> {
+    if key.len() % 2 != 0 {
+        return None;
+    }
+    (0 .. key.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(key.get(i .. i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()
+        .map(Zeroizing::new)
+}
+
+/// build the XTS mode of the cipher from the two halves of the key
+fn xts<C: NewBlockCipher + aes::BlockEncrypt + aes::BlockDecrypt>(
+    k1: &[u8],
+    k2: &[u8],
+) -> Result<Xts128<C>, Error> {
+    Ok(Xts128::new(
+        C::new_from_slice(k1).map_err(|_| Error::InvalidKey {})?,
+        C::new_from_slice(k2).map_err(|_| Error::InvalidKey {})?,
+    ))
+}
+
+impl NexusCrypto {
+    /// Set up the encryption of blocks of the given length with a hex encoded
+    /// XTS-AES key of 256 or 512 bits, the two halves of which must differ.
+    pub(crate) fn new(key: &str, block_len: u64) -> Result<Self, Error> {
+        let bytes = parse_key(key).ok_or(Error::InvalidKey {})?;
+        let (k1, k2) = bytes.split_at(bytes.len() / 2);
+        if k1 == k2 {
+            return Err(Error::InvalidKey {});
+        }
+
+        let cipher = match bytes.len() {
+            32 => XtsCipher::Aes128(xts(k1, k2)?),
+            64 => XtsCipher::Aes256(xts(k1, k2)?),
+            _ => return Err(Error::InvalidKey {}),
+        };
+
+        Ok(Self {
+            cipher,
+            key: Zeroizing::new(key.to_string()),
+            block_len: block_len as usize,
+        })
+    }
+
+    /// encrypt whole blocks in place, the first of which is at lba
+    pub(crate) fn encrypt(&self, lba: u64, data: &mut [u8]) {
+        let lba = lba as u128;
+        match &self.cipher {
+            XtsCipher::Aes128(x) => {
+                x.encrypt_area(data, self.block_len, lba, get_tweak_default)
+            }
+            XtsCipher::Aes256(x) => {
+                x.encrypt_area(data, self.block_len, lba, get_tweak_default)
+            }
+        }
+    }
+
+    /// decrypt whole blocks in place, the first of which is at lba
+    pub(crate) fn decrypt(&self, lba: u64, data: &mut [u8]) {
+        let lba = lba as u128;
+        match &self.cipher {
+            XtsCipher::Aes128(x) => {
+                x.decrypt_area(data, self.block_len, lba, get_tweak_default)
+            }
+            XtsCipher::Aes256(x) => {
+                x.decrypt_area(data, self.block_len, lba, get_tweak_default)
+            }
+        }
+    }
+}
+
+/// Bounce buffer holding the ciphertext of a write while it is submitted to
+/// the children.
+pub(crate) struct CryptBuf {
+    _buf: DmaBuf,
+    iov: IoVec,
+}
+
+impl CryptBuf {
+    /// encrypt the data of a write at lba into a new bounce buffer
+    pub(crate) fn new(
+        crypto: &NexusCrypto,
+        lba: u64,
+        data: &[u8],
+        alignment: u64,
+    ) -> Result<Box<Self>, DmaError> {
+        let mut buf = DmaBuf::new(data.len() as u64, alignment)?;
+        let slice = buf.as_mut_slice();
+        slice.copy_from_slice(data);
+        crypto.encrypt(lba, slice);
+
+        let iov = IoVec {
+            iov_base: slice.as_mut_ptr().cast(),
+            iov_len: data.len() as _,
+        };
+        Ok(Box::new(Self {
+            _buf: buf,
+            iov,
+        }))
+    }
+
+    /// the iov to submit to the children
+    pub(crate) fn iov(&mut self) -> *mut IoVec {
+        &mut self.iov
+    }
+}
+
+impl<'n> Nexus<'n> {
+    /// returns true if the data of this nexus is encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.crypto.lock().is_some()
+    }
+
+    /// returns the encryption state of this nexus, for newly created channels
+    pub(crate) fn crypto(&self) -> Option<Arc<NexusCrypto>> {
+        self.crypto.lock().clone()
+    }
+
+    /// set the key to encrypt the data with once the nexus is open
+    pub(crate) fn set_crypto_key(
+        self: Pin<&mut Self>,
+        key: Option<Zeroizing<String>>,
+    ) {
+        unsafe { self.get_unchecked_mut().crypto_key = key.map(CryptoKey) };
+    }
+
+    /// Set up the encryption of the data with the key the nexus was created
+    /// with, once the children are open and the block length is known but
+    /// before the nexus has any channel.
+    pub(crate) fn open_crypto(self: Pin<&mut Self>) -> Result<(), Error> {
+        let key = match unsafe {
+            self.as_mut().get_unchecked_mut().crypto_key.take()
+        } {
+            Some(CryptoKey(key)) => key,
+            None => return Ok(()),
+        };
+        let crypto = NexusCrypto::new(&key, self.block_len())?;
+        info!("{}: encrypting data", self.name);
+        *self.crypto.lock() = Some(Arc::new(crypto));
+        Ok(())
+    }
+
+    /// refuse to enable a feature working on plaintext on an encrypted nexus
+    pub(crate) fn check_plaintext(&self, feature: &str) -> Result<(), Error> {
+        if self.is_encrypted() {
+            Err(Error::CryptoConflict {
+                name: self.name.clone(),
+                feature: feature.to_string(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Create a nexus encrypting its data with the given key, a hex encoded
+/// XTS-AES key of 256 or 512 bits or a reference to a key of the KMS.
+/// Creating a nexus that already exists only succeeds if it is encrypted
+/// with the same key.
+pub async fn nexus_create_encrypted(
+    name: &str,
+    size: u64,
+    uuid: Option<&str>,
+    children: &[String],
+    key: &str,
+) -> Result<(), Error> {
+    let key = KeyManager::resolve(key).await.context(ResolveKey {
+        name: name.to_string(),
+    })?;
+    // refuse a bad key before any child is created
+    NexusCrypto::new(&key, 512)?;
+
+    if let Some(nexus) = nexus_lookup_mut(name) {
+        if nexus.crypto().map_or(true, |c| *c.key != *key) {
+            return Err(Error::InvalidKey {});
+        }
+    }
+
+    nexus_create_internal(
+        name,
+        size,
+        uuid,
+        None,
+        NexusNvmeParams::default(),
+        children,
+        None,
+        default_size_policy(),
+        Some(key),
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct CreateArgs {
+    name: String,
+    size: u64,
+    #[serde(default)]
+    uuid: Option<String>,
+    children: Vec<String>,
+    /// hex encoded XTS-AES key or kms:// reference
+    key: Zeroizing<String>,
+}
+
+async fn create(args: CreateArgs) -> Result<(), Error> {
+    nexus_create_encrypted(
+        &args.name,
+        args.size,
+        args.uuid.as_deref(),
+        &args.children,
+        &args.key,
+    )
+    .await
+}
+
+/// Register the json-rpc method creating an encrypted nexus.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_create_encrypted",
+        |args: CreateArgs| create(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// IEEE 1619 XTS-AES-128 test vector 4: the first 32 bytes of data unit
+    /// 0 encrypted with keys 2718... and 3141...
+    #[test]
+    fn xts_aes_128_vector() {
+        let key = "27182818284590452353602874713526\
+                   31415926535897932384626433832795";
+        let crypto = NexusCrypto::new(key, 512).unwrap();
+
+        let mut data: Vec<u8> = (0 .. 512).map(|i| i as u8).collect();
+        crypto.encrypt(0, &mut data);
+        assert_eq!(
+            &data[.. 16],
+            &[
+                0x27, 0xa7, 0x47, 0x9b, 0xef, 0xa1, 0xd4, 0x76, 0x48, 0x9f,
+                0x30, 0x8c, 0xd4, 0xcf, 0xa6, 0xe2
+            ]
+        );
+
+        crypto.decrypt(0, &mut data);
+        assert!(data.iter().enumerate().all(|(i, b)| *b == i as u8));
+    }
+
+    #[test]
+    fn invalid_keys() {
+        assert!(NexusCrypto::new("0123456789abcdef", 512).is_err());
+        assert!(NexusCrypto::new(&"00".repeat(32), 512).is_err());
+        assert!(NexusCrypto::new(&"zz".repeat(32), 512).is_err());
+    }
+
+    #[test]
+    fn key_not_printed() {
+        let key = "27182818284590452353602874713526\
+                   31415926535897932384626433832795";
+        let crypto = NexusCrypto::new(key, 512).unwrap();
+        assert!(!format!("{:?}", crypto).contains("2718"));
+        assert!(!format!("{:?}", CryptoKey(Zeroizing::new(key.to_string())))
+            .contains("2718"));
+    }
+}
@@ -0,0 +1,96 @@
+//!
+//! Direct IO path of a nexus with a single local replica.
+//!
+//! A volume with one replica on the node of its nexus gains nothing from
+//! the nexus on most IOs, which still go through the checks of the features
+//! of the nexus before they reach the replica. With the `direct` option of
+//! the nexus, a channel whose only child is local submits the reads,
+//! writes, write zeroes, unmaps and flushes straight to it, and completes
+//! them as the child does, leaving out the write cache, the read cache, the
+//! checksums, the encryption, the tracing, the tiering and the latencies.
+//! A child is local when its URI is not that of a target on another node,
+//! whatever driver its device has, so a local NVMe device takes the direct
+//! path and a child over iSCSI does not.
+//!
+//! The nexus is still there, so the volume keeps its share, and IO takes
+//! the full path again as soon as the channel has another child, e.g. when
+//! a second replica is added and rebuilt, or any of the features above is
+//! enabled, without the initiator noticing. The same goes while the nexus
+//! keeps a write journal or is replicated, as the direct path would neither
+//! journal its writes nor record them as changes to ship. IO that the child
+//! fails or can
+//! not take takes the full path as well, which retires the child, and so do
+//! the other IO types, IO needing a buffer, IO of a fenced nexus and IO
+//! while faults are injected.
+//!
+//! The option is set with `nexus_update_options` and is off by default.
+
+use super::{ChildSnapshot, NexusChannelInner};
+use crate::core::BlockDeviceHandle;
+
+/// schemes of the URIs of children on other nodes
+const REMOTE_SCHEMES: [&str; 2] = ["nvmf", "iscsi"];
+
+/// returns true if the child of the URI is on the node of the nexus
+fn is_local(uri: &str) -> bool {
+    match uri.split_once("://") {
+        Some((scheme, _)) => !REMOTE_SCHEMES
+            .iter()
+            .any(|s| s.eq_ignore_ascii_case(scheme)),
+        None => false,
+    }
+}
+
+/// Returns true if a channel with the children of the snapshot submits IO
+/// straight to its only child, which must be local.
+pub(crate) fn is_direct(
+    direct: bool,
+    snapshot: &ChildSnapshot,
+    writers: &[Box<dyn BlockDeviceHandle>],
+    readers: &[Box<dyn BlockDeviceHandle>],
+) -> bool {
+    direct
+        && writers.len() == 1
+        && readers.len() == 1
+        && snapshot.children.len() == 1
+        && is_local(&snapshot.children[0].name)
+}
+
+impl NexusChannelInner {
+    /// returns the child to submit IO straight to, if the channel is direct
+    /// and none of the features the direct path leaves out are on
+    pub(crate) fn direct_child(&self) -> Option<&dyn BlockDeviceHandle> {
+        if !self.direct
+            || self.cache.is_some()
+            || self.read_cache.is_some()
+            || self.checksums.is_some()
+            || self.crypto.is_some()
+            || self.trace.is_some()
+            || self.io_debug.is_some()
+            || self.tier.is_some()
+            || self.latencies.is_some()
+        {
+            return None;
+        }
+        let nexus = self.get_nexus();
+        if nexus.journal.is_some() || nexus.changes.is_enabled() {
+            return None;
+        }
+        self.writers.first().map(|w| &**w)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_local;
+
+    #[test]
+    fn local() {
+        assert!(is_local("malloc:///m0?size_mb=64"));
+        assert!(is_local("bdev:///lvol0"));
+        assert!(is_local("pcie:///0000:00:04.0"));
+        assert!(!is_local("nvmf://10.1.0.2:8420/nqn.2019-05.io.openebs:r0"));
+        assert!(!is_local("ISCSI://10.1.0.2/iqn.2019-05.io.openebs:r0/0"));
+        assert!(!is_local("r0"));
+    }
+}
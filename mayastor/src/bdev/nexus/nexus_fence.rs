@@ -0,0 +1,350 @@
+//!
+//! Fencing protects the replicas of a volume against two nexuses writing to
+//! them at the same time, which can happen when a nexus is recreated on
+//! another node while the old one is still alive.
+//!
+//! Every nexus claims a new epoch for its NexusInfo entry in the persistent
+//! store when it is created. A nexus that finds a newer epoch in the store, or
+//! that has its NVMe reservation preempted by the newer nexus, is superseded:
+//! it fences itself by failing all IO from then on, and stops touching the
+//! persistent entry which now belongs to the newer nexus.
+//!
+//! With a lease of `epoch_lease_ms`, a nexus that owns its entry only
+//! accepts a write once it has found its epoch current in the store, at most
+//! the lease before the write was submitted. Writes submitted past the lease
+//! wait in a FIFO queue of their channel while the epoch is checked again,
+//! and are failed if the nexus turns out to be superseded. A nexus claiming
+//! the entry of a previous nexus does not accept writes for the length of a
+//! lease, by which time the previous nexus has stopped accepting them.
+//!
+//! As writes stop while the store can not be read, the lease is off by
+//! default, and writes are then accepted without checking the epoch. At most
+//! `epoch_hold_depth` writes wait on a channel, and a write that waited for
+//! `epoch_hold_timeout_ms` fails, so that an outage of the store fails the
+//! writes of the nexus rather than hanging them.
+//!
+//! The destination nexus of a migration does not claim the entry when it is
+//! created, as the source nexus still serves the volume, but when the
+//! migration switches over to it. Until then it holds the writes it gets,
+//! when there is a lease.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Mutex,
+    time::Instant,
+};
+
+use crossbeam::atomic::AtomicCell;
+use once_cell::sync::Lazy;
+use spdk_rs::libspdk::spdk_bdev_io;
+
+use super::{
+    nexus_admit_checked,
+    nexus_complete_request,
+    nexus_lookup_mut,
+    nexus_resubmit_request,
+    Nexus,
+    NexusChannelInner,
+    NexusInfo,
+};
+use crate::{
+    core::{poller, Reactors},
+    persistent_store::PersistentStore,
+    subsys::Config,
+};
+
+/// Interval in usec at which the writes waiting for the epoch are admitted.
+const EPOCH_POLL_INTERVAL_US: u64 = 1000;
+
+/// start of the clock of the leases
+static LEASE_CLOCK: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// names of the nexuses to be created without claiming their entry
+static DEFERRED_CLAIMS: Lazy<Mutex<HashSet<String>>> =
+    Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// returns the time in usec on the clock of the leases, never 0
+fn now_us() -> u64 {
+    LEASE_CLOCK.elapsed().as_micros() as u64 + 1
+}
+
+/// returns the length of the lease in usec, 0 if there is none
+fn lease_us() -> u64 {
+    Config::get().nexus_opts.epoch_lease_ms * 1000
+}
+
+/// returns the maximum number of writes waiting on a channel and the time
+/// in usec after which they fail
+fn hold_limits() -> (usize, u64) {
+    let opts = &Config::get().nexus_opts;
+    (
+        opts.epoch_hold_depth as usize,
+        opts.epoch_hold_timeout_ms * 1000,
+    )
+}
+
+/// Have the nexus with the given name claim its persistent entry when the
+/// migration switches over to it, rather than when it is created.
+pub(crate) fn defer_epoch_claim(name: &str) {
+    DEFERRED_CLAIMS.lock().unwrap().insert(name.to_string());
+}
+
+/// Returns true if the nexus with the given name is not to claim its
+/// persistent entry when it is created, forgetting about it.
+pub(crate) fn take_deferred_claim(name: &str) -> bool {
+    DEFERRED_CLAIMS.lock().unwrap().remove(name)
+}
+
+/// Ownership of the persistent entry of a nexus and the last time its epoch
+/// was found current.
+#[derive(Debug, Default)]
+pub(crate) struct EpochLease {
+    /// the nexus owns its entry and checks its epoch before writes
+    owned: AtomicCell<bool>,
+    /// the nexus is to claim its entry later and holds the writes until then
+    deferred: AtomicCell<bool>,
+    /// start in usec of the last check that found the epoch current, 0 if
+    /// there was none
+    checked_us: AtomicCell<u64>,
+    /// writes are not accepted before this time in usec, when the lease of
+    /// the previous nexus has run out
+    not_before_us: AtomicCell<u64>,
+    /// a check of the epoch is in flight
+    checking: AtomicCell<bool>,
+    /// the last check could not read the epoch
+    unreadable: AtomicCell<bool>,
+}
+
+impl EpochLease {
+    /// the nexus claimed its entry, taking it over from a previous nexus
+    pub(crate) fn claim(&self, previous: bool) {
+        let now = now_us();
+        self.checked_us.store(now);
+        if previous {
+            self.not_before_us.store(now + lease_us());
+        }
+        self.deferred.store(false);
+        self.owned.store(true);
+    }
+
+    /// the nexus is to claim its entry later
+    pub(crate) fn defer(&self) {
+        self.deferred.store(true);
+    }
+
+    /// returns true if the nexus is to claim its entry later
+    pub(crate) fn is_deferred(&self) -> bool {
+        self.deferred.load()
+    }
+
+    /// returns true if writes are checked against the epoch
+    fn holds_writes(&self) -> bool {
+        lease_us() != 0 && (self.owned.load() || self.deferred.load())
+    }
+
+    /// returns true if a write submitted at the given time is accepted
+    fn admits(&self, since_us: u64) -> bool {
+        let checked = self.checked_us.load();
+        self.owned.load()
+            && checked != 0
+            && checked + lease_us() >= since_us
+            && now_us() >= self.not_before_us.load()
+    }
+}
+
+/// Writes of a channel waiting for the epoch of the nexus to be checked.
+#[derive(Default)]
+pub(crate) struct EpochQueue {
+    waiting: VecDeque<(*mut spdk_bdev_io, u64)>,
+    poller: Option<poller::Poller<'static>>,
+}
+
+impl NexusChannelInner {
+    /// Returns true if the write waits for the epoch of the nexus to be
+    /// checked or has been failed as too many writes wait, false if it is
+    /// accepted.
+    pub(crate) fn epoch_hold(&mut self, io: *mut spdk_bdev_io) -> bool {
+        let lease = &self.get_nexus().epoch_lease;
+        if !lease.holds_writes() {
+            return false;
+        }
+
+        let now = now_us();
+        if self.epoch.waiting.is_empty() && lease.admits(now) {
+            return false;
+        }
+
+        if self.epoch.waiting.len() >= hold_limits().0 {
+            nexus_complete_request(io, false);
+            return true;
+        }
+
+        if self.epoch.poller.is_none() {
+            let inner = self as *mut NexusChannelInner;
+            self.epoch.poller = Some(
+                poller::Builder::new()
+                    .with_name("nexus_epoch_poller")
+                    .with_interval(EPOCH_POLL_INTERVAL_US)
+                    .with_poll_fn(move || unsafe { (*inner).admit_checked() })
+                    .build(),
+            );
+        }
+        self.epoch.waiting.push_back((io, now));
+        self.get_nexus().check_epoch_lease();
+        true
+    }
+
+    /// returns the number of writes waiting for the epoch on the channel
+    pub(crate) fn epoch_held(&self) -> usize {
+        self.epoch.waiting.len()
+    }
+
+    /// admit the waiting writes the last check of the epoch covers, in order,
+    /// and fail those that waited too long, or fail them all if the nexus has
+    /// been fenced
+    fn admit_checked(&mut self) -> i32 {
+        if self.get_nexus().is_fenced() {
+            let waiting = std::mem::take(&mut self.epoch.waiting);
+            let count = waiting.len() as i32;
+            waiting
+                .into_iter()
+                .for_each(|(io, _)| nexus_resubmit_request(io));
+            return count;
+        }
+
+        let mut count = 0;
+        while let Some(&(io, since)) = self.epoch.waiting.front() {
+            if !self.get_nexus().epoch_lease.admits(since) {
+                break;
+            }
+            self.epoch.waiting.pop_front();
+            nexus_admit_checked(io);
+            count += 1;
+        }
+
+        let timeout = hold_limits().1;
+        let now = now_us();
+        while let Some(&(io, since)) = self.epoch.waiting.front() {
+            if since + timeout > now {
+                break;
+            }
+            self.epoch.waiting.pop_front();
+            nexus_complete_request(io, false);
+            count += 1;
+        }
+
+        if !self.epoch.waiting.is_empty() {
+            self.get_nexus().check_epoch_lease();
+        }
+        count
+    }
+
+    /// Stop holding writes on the channel, failing the waiting ones, as
+    /// their epoch has not been checked.
+    pub(crate) fn stop_epoch_hold(&mut self) {
+        if let Some(p) = self.epoch.poller.take() {
+            p.stop();
+        }
+        std::mem::take(&mut self.epoch.waiting)
+            .into_iter()
+            .for_each(|(io, _)| nexus_complete_request(io, false));
+    }
+}
+
+impl<'n> Nexus<'n> {
+    /// Returns true if this nexus has been superseded by a nexus with a newer
+    /// epoch and no longer accepts IO.
+    pub fn is_fenced(&self) -> bool {
+        self.fenced.load()
+    }
+
+    /// Fence the IO path of this nexus. All IO submitted from here on fails.
+    /// Fencing is final, the nexus must be destroyed.
+    pub(crate) fn fence(&self, reason: &str) {
+        if !self.fenced.swap(true) {
+            error!("{}: nexus fenced: {}", self.name, reason);
+        }
+    }
+
+    /// Returns the epoch stored under the given key in the persistent store,
+    /// or None if there is no (valid) entry.
+    pub(crate) async fn stored_epoch(key: &str) -> Option<u64> {
+        match PersistentStore::get(&key).await {
+            Ok(value) => serde_json::from_value::<NexusInfo>(value)
+                .ok()
+                .map(|info| info.epoch),
+            Err(_) => None,
+        }
+    }
+
+    /// Compare our epoch against the one in the persistent store and fence
+    /// the nexus if a newer nexus has claimed the entry.
+    /// Returns true if the nexus is (now) fenced.
+    pub(crate) async fn check_epoch(&self, key: &str, epoch: u64) -> bool {
+        if self.is_fenced() {
+            return true;
+        }
+
+        match Self::stored_epoch(key).await {
+            Some(stored) if stored > epoch => {
+                self.fence(&format!(
+                    "epoch {} superseded by epoch {}",
+                    epoch, stored
+                ));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Check the epoch of the nexus in the background, unless a check is in
+    /// flight already, renewing its lease if it is current. A nexus that has
+    /// not claimed its entry yet has no epoch to check.
+    pub(crate) fn check_epoch_lease(&self) {
+        if !self.epoch_lease.owned.load()
+            || self.epoch_lease.checking.swap(true)
+        {
+            return;
+        }
+        let name = self.name.clone();
+        Reactors::master().send_future(async move {
+            if let Some(nexus) = nexus_lookup_mut(&name) {
+                nexus.renew_epoch_lease().await;
+            }
+        });
+    }
+
+    /// Renew the lease of the nexus if its epoch is current in the store,
+    /// fencing it if it has been superseded. The lease is not renewed when
+    /// the epoch can not be read, the writes wait until it can.
+    async fn renew_epoch_lease(&self) {
+        let start = now_us();
+        let (key, epoch) = {
+            let info = self.nexus_info.lock().await;
+            (info.store_key(self), info.epoch())
+        };
+
+        match Self::stored_epoch(&key).await {
+            Some(stored) if stored > epoch => {
+                self.fence(&format!(
+                    "epoch {} superseded by epoch {}",
+                    epoch, stored
+                ));
+            }
+            Some(_) => {
+                self.epoch_lease.checked_us.store(start);
+                self.epoch_lease.unreadable.store(false);
+            }
+            None => {
+                if !self.epoch_lease.unreadable.swap(true) {
+                    warn!(
+                        "{}: failed to read the epoch of the nexus, writes \
+                        wait until they time out",
+                        self.name
+                    );
+                }
+            }
+        }
+        self.epoch_lease.checking.store(false);
+    }
+}
@@ -2,37 +2,61 @@ use std::{
     fmt::Debug,
     ops::{Deref, DerefMut},
     pin::Pin,
+    sync::atomic::Ordering,
+    time::Instant,
 };
 
 use libc::c_void;
 use nix::errno::Errno;
 
 use spdk_rs::{
-    libspdk::{spdk_bdev_io, spdk_io_channel},
+    libspdk::{
+        spdk_bdev_io,
+        spdk_bdev_io_complete,
+        spdk_io_channel,
+        SPDK_BDEV_IO_STATUS_MISCOMPARE,
+    },
     BdevIo,
+    IoVec,
 };
 
 use super::{
+    checksum_repair,
+    compare_and_write,
     nexus_lookup_mut,
+    Bounce,
+    BounceBuf,
+    CacheRead,
+    CacheWrite,
+    CryptBuf,
+    IoDebugKind,
     Nexus,
     NexusChannel,
     NexusChannelInner,
     NexusStatus,
+    ReadPolicy,
     NEXUS_PRODUCT_ID,
 };
 
-use crate::core::{
-    BlockDevice,
-    BlockDeviceHandle,
-    CoreError,
-    Cores,
-    GenericStatusCode,
-    IoCompletionStatus,
-    IoStatus,
-    IoType,
-    Mthread,
-    NvmeCommandStatus,
-    Reactors,
+use crate::{
+    core::{
+        fault_injection::{self, FaultAction, FaultDomain},
+        BlockDevice,
+        BlockDeviceHandle,
+        CoreError,
+        Cores,
+        GenericStatusCode,
+        IoCompletionCallback,
+        IoCompletionCallbackArg,
+        IoCompletionStatus,
+        IoStatus,
+        IoType,
+        Mthread,
+        NvmeCommandStatus,
+        Reactors,
+        SglCaps,
+    },
+    sleep::mayastor_sleep,
 };
 
 /// TODO
@@ -41,13 +65,34 @@ use crate::core::{
 pub(crate) struct NioCtx {
     /// number of IO's submitted. Nexus IO's may never be freed until this
     /// counter drops to zero.
-    in_flight: u8,
+    in_flight: u32,
     /// intermediate status of the IO
     status: IoStatus,
     /// a reference to  our channel
     channel: spdk_rs::IoChannel<NexusChannel>,
     /// the IO must fail regardless of when it completes
     must_fail: bool,
+    /// read cache generation when the read was submitted to a child
+    cache_gen: u64,
+    /// bounce buffer holding the ciphertext of a write, null if none
+    crypt_buf: *mut CryptBuf,
+    /// bounce buffer of a read or write whose SGL is not submitted to the
+    /// children, null if none
+    bounce_buf: *mut BounceBuf,
+    /// time the IO was received if it is traced
+    trace_start: Option<Instant>,
+    /// sequence number of the write in the write journal, 0 if none
+    journal_seq: u64,
+    /// the write went to the fast child of a tiered nexus only
+    tiered: bool,
+    /// time the IO was submitted to the children if their latencies are
+    /// tracked
+    submitted: Option<Instant>,
+    /// number of the IO in the IO debug log of the nexus, 0 if none
+    debug_io: u64,
+    /// the IO holds a slot under the limit of the outstanding IOs of the
+    /// nexus
+    limited: bool,
 }
 
 /// TODO
@@ -55,6 +100,13 @@ pub(crate) struct NioCtx {
 #[derive(Debug)]
 struct NexusBio<'n>(BdevIo<Nexus<'n>>);
 
+/// A child IO a fault is injected in, passed to its completion instead of
+/// the nexus IO.
+struct InjectedIo {
+    io: *mut spdk_bdev_io,
+    action: FaultAction,
+}
+
 impl<'n> Deref for NexusBio<'n> {
     type Target = BdevIo<Nexus<'n>>;
 
@@ -97,11 +149,48 @@ impl<'n> NexusBio<'n> {
         ctx.status = IoStatus::Pending;
         ctx.in_flight = 0;
         ctx.must_fail = false;
+        ctx.limited = false;
+        ctx.cache_gen = 0;
+        ctx.crypt_buf = std::ptr::null_mut();
+        ctx.bounce_buf = std::ptr::null_mut();
+        ctx.trace_start = None;
+        ctx.tiered = false;
+        ctx.submitted = None;
         bio
     }
 
     /// TODO
     fn submit_request(mut self) {
+        // a fenced nexus must not touch the replicas anymore
+        if self.nexus_as_ref().is_fenced() {
+            self.fail();
+            return;
+        }
+
+        if !self.write_lock() {
+            return;
+        }
+        self.journal_begin();
+
+        if matches!(
+            self.io_type(),
+            IoType::Write
+                | IoType::WriteZeros
+                | IoType::Unmap
+                | IoType::CompareAndWrite
+        ) {
+            self.read_cache_invalidate();
+            self.nexus_as_ref().read_ahead.invalidate();
+            if matches!(self.io_type(), IoType::WriteZeros | IoType::Unmap) {
+                self.checksum_forget();
+            }
+        }
+
+        if self.cache_submit() {
+            return;
+        }
+
+        self.latency_sample();
         if let Err(_e) = match self.io_type() {
             IoType::Read => self.readv(),
             // these IOs are submitted to all the underlying children
@@ -109,9 +198,9 @@ impl<'n> NexusBio<'n> {
             | IoType::WriteZeros
             | IoType::Reset
             | IoType::Unmap => self.submit_all(),
-            IoType::Flush => {
-                self.ok();
-                Ok(())
+            IoType::Flush => self.flush(),
+            IoType::Compare | IoType::CompareAndWrite => {
+                self.compare_and_write()
             }
             IoType::NvmeAdmin => {
                 self.fail();
@@ -131,12 +220,775 @@ impl<'n> NexusBio<'n> {
         }
     }
 
+    /// Returns true if the IO has been submitted straight to the only child
+    /// of a channel in direct mode, or queued behind an overlapping write.
+    /// Any other IO, and IO that could not be submitted, takes the full path.
+    fn direct_submit(&mut self) -> bool {
+        if fault_injection::is_active() || self.nexus_as_ref().is_fenced() {
+            return false;
+        }
+        let io_type = self.io_type();
+        match io_type {
+            IoType::Read if self.need_buf() => return false,
+            IoType::Read
+            | IoType::Write
+            | IoType::WriteZeros
+            | IoType::Unmap
+            | IoType::Flush => {}
+            _ => return false,
+        }
+        match self.inner_channel().direct_child() {
+            Some(hdl) if self.is_passthrough(hdl.get_device().sgl_caps()) => {}
+            _ => return false,
+        }
+        if !self.write_lock() {
+            return true;
+        }
+
+        let hdl = self.inner_channel().direct_child().unwrap();
+        let r = match io_type {
+            IoType::Read => self.submit_read(hdl),
+            IoType::Write => self.submit_write(hdl),
+            IoType::WriteZeros => self.submit_write_zeroes(hdl),
+            IoType::Unmap => self.submit_unmap(hdl),
+            _ => self.submit_flush(hdl),
+        };
+        if r.is_err() {
+            self.write_unlock();
+            return false;
+        }
+        if matches!(io_type, IoType::Read | IoType::Write) {
+            self.nexus_as_ref().sgl_counters.record(Bounce::ZeroCopy);
+        }
+        self.ctx_mut().in_flight = 1;
+        true
+    }
+
+    /// Returns true if the IO has been pushed back to the initiator,
+    /// completed with NOMEM, for too many IOs being held back on the channel.
+    fn push_back(&mut self) -> bool {
+        if !self.inner_channel_mut().push_back() {
+            return false;
+        }
+        self.no_mem();
+        true
+    }
+
+    /// Returns true if the write waits for the epoch of the nexus to be
+    /// checked, in which case it is admitted later by the poller.
+    fn epoch_hold(&mut self) -> bool {
+        if !matches!(
+            self.io_type(),
+            IoType::Write
+                | IoType::WriteZeros
+                | IoType::Unmap
+                | IoType::CompareAndWrite
+        ) {
+            return false;
+        }
+        let io = self.as_ptr();
+        self.inner_channel_mut().epoch_hold(io)
+    }
+
+    /// Returns true if the IO waits for the nexus to be under its limit of
+    /// outstanding IOs, in which case it is admitted later by the poller.
+    fn limit_hold(&mut self) -> bool {
+        let io = self.as_ptr();
+        let mut admitted = false;
+        let held = self.inner_channel_mut().limit_hold(io, &mut admitted);
+        self.ctx_mut().limited = admitted;
+        held
+    }
+
+    /// Returns true if the IO is held back by the QoS limits of the nexus.
+    /// Only reads and writes are subject to the limits.
+    fn qos_hold(&mut self) -> bool {
+        if !matches!(self.io_type(), IoType::Read | IoType::Write) {
+            return false;
+        }
+
+        let bytes = self.num_blocks() * self.nexus_as_ref().block_len();
+        let io = self.as_ptr();
+        match self.inner_channel_mut().qos.as_mut() {
+            Some(qos) => qos.hold(io, bytes),
+            None => false,
+        }
+    }
+
+    /// Returns true if the IO has been taken by the write cache of the nexus,
+    /// either completed or waiting to be resubmitted. Reads are looked up in
+    /// the cache when they are submitted to a child.
+    fn cache_submit(&mut self) -> bool {
+        if self.inner_channel().cache.is_none() {
+            return false;
+        }
+
+        let io = self.as_ptr();
+        let lba = self.offset();
+        let num_blocks = self.num_blocks();
+
+        match self.io_type() {
+            IoType::Write => {
+                let data = self.gather();
+                let cache = self.inner_channel_mut().cache.as_mut().unwrap();
+                match cache.write(io, lba, &data) {
+                    CacheWrite::Cached => {
+                        if let Some(cs) =
+                            self.inner_channel().checksums.as_ref()
+                        {
+                            cs.record(lba, &data);
+                        }
+                        self.ok();
+                        true
+                    }
+                    CacheWrite::Waiting => true,
+                    CacheWrite::PassThrough => false,
+                }
+            }
+            IoType::Unmap | IoType::WriteZeros => self
+                .inner_channel_mut()
+                .cache
+                .as_mut()
+                .unwrap()
+                .discard(io, lba, num_blocks),
+            IoType::Flush => {
+                self.inner_channel_mut().cache.as_mut().unwrap().flush(io);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if the read has been taken by the write cache of the
+    /// nexus, either served from it or waiting for dirty data to be written
+    /// back.
+    fn cache_read(&mut self) -> bool {
+        if self.inner_channel().cache.is_none() {
+            return false;
+        }
+
+        let io = self.as_ptr();
+        let lba = self.offset();
+        let num_blocks = self.num_blocks();
+        let mut data =
+            vec![0u8; (num_blocks * self.nexus_as_ref().block_len()) as usize];
+
+        let cache = self.inner_channel_mut().cache.as_mut().unwrap();
+        match cache.read(io, lba, num_blocks, &mut data) {
+            CacheRead::Hit => {
+                self.scatter(&data);
+                self.ok();
+                true
+            }
+            CacheRead::Waiting => true,
+            CacheRead::Miss => false,
+        }
+    }
+
+    /// drop the blocks of the IO from the read cache of the nexus
+    fn read_cache_invalidate(&self) {
+        if let Some(rc) = self.inner_channel().read_cache.as_ref() {
+            rc.invalidate(self.offset(), self.num_blocks());
+        }
+    }
+
+    /// Returns true if the read has been submitted to the read cache device
+    /// of the nexus. Otherwise the read goes to a child, and the read cache
+    /// generation is recorded to fill the cache with its data later.
+    fn read_cache_hit(&mut self) -> bool {
+        let lba = self.offset();
+        let num_blocks = self.num_blocks();
+
+        let gen = match self.inner_channel().read_cache.as_ref() {
+            Some(rc) => {
+                if let Some(offset) = rc.lookup(lba, num_blocks) {
+                    if rc
+                        .handle()
+                        .readv_blocks(
+                            self.iovs(),
+                            self.iov_count(),
+                            offset,
+                            num_blocks,
+                            Self::read_cache_completion,
+                            self.as_ptr().cast(),
+                        )
+                        .is_ok()
+                    {
+                        return true;
+                    }
+                    rc.release(lba, num_blocks);
+                }
+                rc.gen()
+            }
+            None => return false,
+        };
+
+        self.ctx_mut().cache_gen = gen;
+        false
+    }
+
+    /// invoked when a read from the read cache device completes, a failed
+    /// read is retried on a child
+    fn read_cache_completion(
+        _device: &dyn BlockDevice,
+        status: IoCompletionStatus,
+        ctx: *mut c_void,
+    ) {
+        let mut bio = NexusBio::from(ctx as *mut spdk_bdev_io);
+        let lba = bio.offset();
+        let num_blocks = bio.num_blocks();
+        let success = status == IoCompletionStatus::Success;
+
+        if let Some(rc) = bio.inner_channel().read_cache.as_ref() {
+            rc.release(lba, num_blocks);
+            if !success {
+                rc.invalidate(lba, num_blocks);
+            }
+        }
+
+        if success {
+            bio.ok();
+        } else {
+            warn!(?bio, "read from read cache failed: {:?}", status);
+            let _ = bio.do_readv();
+        }
+    }
+
+    /// Returns true if the read has been served from the extents read ahead
+    /// on the channel, otherwise sets `ahead` if the read continues a stream
+    /// to read ahead of once it is submitted.
+    fn read_ahead_hit(&mut self, ahead: &mut bool) -> bool {
+        let (lba, num_blocks) = (self.offset(), self.num_blocks());
+        let data = match self
+            .inner_channel_mut()
+            .read_ahead_lookup(lba, num_blocks, ahead)
+        {
+            Some(data) => data,
+            None => return false,
+        };
+
+        if std::mem::take(ahead) {
+            if let Some(i) = self.select_reader() {
+                self.inner_channel().read_ahead_issue(i, lba + num_blocks);
+            }
+        }
+        self.scatter(&data);
+        self.ok();
+        true
+    }
+
+    /// fill the read cache of the nexus with the data of a completed read
+    fn read_cache_fill(&self) {
+        let bytes = self.num_blocks() * self.nexus_as_ref().block_len();
+        if let Some(rc) = self.inner_channel().read_cache.as_ref() {
+            if rc.fills(bytes) {
+                rc.fill(self.offset(), self.gather(), self.ctx().cache_gen);
+            }
+        }
+    }
+
+    /// record the checksums of the data of a completed write
+    fn checksum_record(&self) {
+        if let Some(cs) = self.inner_channel().checksums.as_ref() {
+            cs.record(self.offset(), &self.gather());
+        }
+    }
+
+    /// forget the checksums of blocks that have been unmapped or zeroed
+    fn checksum_forget(&self) {
+        if let Some(cs) = self.inner_channel().checksums.as_ref() {
+            cs.forget(self.offset(), self.num_blocks());
+        }
+    }
+
+    /// Returns true if the data of a completed read does not match its
+    /// checksums, in which case the read is completed by a repair.
+    fn checksum_repair(&self) -> bool {
+        let cs = match self.inner_channel().checksums.as_ref() {
+            Some(cs) => cs,
+            None => return false,
+        };
+        if cs.verify(self.offset(), &self.gather()) {
+            return false;
+        }
+
+        Reactors::current()
+            .spawn_local(checksum_repair(
+                self.nexus_as_ref().name.clone(),
+                cs.clone(),
+                self.as_ptr(),
+                self.offset(),
+                self.num_blocks(),
+            ))
+            .detach();
+        true
+    }
+
+    /// Encrypt the data of a write into a bounce buffer when the nexus is
+    /// encrypted. Returns false if no buffer could be allocated, in which
+    /// case the IO has been completed with NOMEM.
+    fn crypt_encrypt(&mut self) -> bool {
+        let buf = match self.inner_channel().crypto.as_ref() {
+            Some(crypto) => CryptBuf::new(
+                crypto,
+                self.offset(),
+                &self.gather(),
+                self.nexus_as_ref().alignment(),
+            ),
+            None => return true,
+        };
+
+        match buf {
+            Ok(buf) => {
+                self.ctx_mut().crypt_buf = Box::into_raw(buf);
+                true
+            }
+            Err(_) => {
+                self.no_mem();
+                false
+            }
+        }
+    }
+
+    /// free the bounce buffer of a write once all child IOs have completed
+    fn crypt_release(&mut self) {
+        let buf = self.ctx().crypt_buf;
+        if !buf.is_null() {
+            drop(unsafe { Box::from_raw(buf) });
+            self.ctx_mut().crypt_buf = std::ptr::null_mut();
+        }
+    }
+
+    /// decrypt the data of a completed read in place
+    fn crypt_decrypt(&self) {
+        if let Some(crypto) = self.inner_channel().crypto.as_ref() {
+            let mut data = self.gather();
+            crypto.decrypt(self.offset(), &mut data);
+            self.scatter(&data);
+        }
+    }
+
+    /// returns true if the SGL of the IO is submitted as it is to a child with
+    /// the capabilities
+    fn is_passthrough(&self, caps: SglCaps) -> bool {
+        !matches!(self.io_type(), IoType::Read | IoType::Write)
+            || (self.inner_channel().opts.sgl_passthrough
+                && caps.accepts(self.iov_list()))
+    }
+
+    /// Decide how the data of a read or write goes to the children with the
+    /// capabilities, allocating a bounce buffer if it can not go in the
+    /// buffers of the initiator. Returns false if no buffer could be
+    /// allocated, in which case the IO has been completed with NOMEM.
+    fn bounce_prepare(&mut self, caps: Vec<SglCaps>) -> bool {
+        let bounce = if !self.ctx().crypt_buf.is_null() {
+            Bounce::Crypto
+        } else if !self.inner_channel().opts.sgl_passthrough {
+            Bounce::Forced
+        } else if caps.iter().all(|c| c.accepts(self.iov_list())) {
+            Bounce::ZeroCopy
+        } else {
+            Bounce::Unaligned
+        };
+        self.nexus_as_ref().sgl_counters.record(bounce);
+        if matches!(bounce, Bounce::ZeroCopy | Bounce::Crypto) {
+            return true;
+        }
+
+        let alignment = self.nexus_as_ref().alignment();
+        let buf = if self.io_type() == IoType::Write {
+            BounceBuf::copy(&self.gather(), alignment)
+        } else {
+            let len = self.num_blocks() * self.nexus_as_ref().block_len();
+            BounceBuf::new(len, alignment)
+        };
+        match buf {
+            Ok(buf) => {
+                self.ctx_mut().bounce_buf = Box::into_raw(buf);
+                true
+            }
+            Err(_) => {
+                self.no_mem();
+                false
+            }
+        }
+    }
+
+    /// Free the bounce buffer of a read or write once all child IOs have
+    /// completed, copying the data of a read that succeeded to the buffers of
+    /// the initiator.
+    fn bounce_release(&mut self, copy_out: bool) {
+        let buf = self.ctx().bounce_buf;
+        if buf.is_null() {
+            return;
+        }
+        let buf = unsafe { Box::from_raw(buf) };
+        self.ctx_mut().bounce_buf = std::ptr::null_mut();
+        if copy_out && self.io_type() == IoType::Read {
+            self.scatter(buf.data());
+        }
+    }
+
+    /// returns the iovs to submit to the children, those of the bounce buffer
+    /// if the IO has one
+    fn child_iovs(&self) -> (*mut IoVec, i32) {
+        let crypt = self.ctx().crypt_buf;
+        let bounce = self.ctx().bounce_buf;
+        if !crypt.is_null() {
+            (unsafe { &mut *crypt }.iov(), 1)
+        } else if !bounce.is_null() {
+            (unsafe { &mut *bounce }.iov(), 1)
+        } else {
+            (self.iovs(), self.iov_count())
+        }
+    }
+
+    /// copy the data of the IO out of its buffers
+    fn gather(&self) -> Vec<u8> {
+        let len =
+            (self.num_blocks() * self.nexus_as_ref().block_len()) as usize;
+        let mut data = Vec::with_capacity(len);
+        for iov in self.iov_list() {
+            data.extend_from_slice(unsafe {
+                std::slice::from_raw_parts(
+                    iov.iov_base as *const u8,
+                    iov.iov_len as usize,
+                )
+            });
+        }
+        data.truncate(len);
+        data
+    }
+
+    /// copy data into the buffers of the IO
+    fn scatter(&self, mut data: &[u8]) {
+        for iov in self.iov_list() {
+            let n = (iov.iov_len as usize).min(data.len());
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    data.as_ptr(),
+                    iov.iov_base as *mut u8,
+                    n,
+                );
+            }
+            data = &data[n ..];
+        }
+    }
+
+    /// gather the data to write of a compare and write
+    fn gather_fused(&self) -> Vec<u8> {
+        let len =
+            (self.num_blocks() * self.nexus_as_ref().block_len()) as usize;
+        let iovs = unsafe {
+            let bdev = &(*self.as_ptr()).u.bdev;
+            std::slice::from_raw_parts(
+                bdev.fused_iovs,
+                bdev.fused_iovcnt as usize,
+            )
+        };
+        let mut data = Vec::with_capacity(len);
+        for iov in iovs {
+            data.extend_from_slice(unsafe {
+                std::slice::from_raw_parts(
+                    iov.iov_base as *const u8,
+                    iov.iov_len as usize,
+                )
+            });
+        }
+        data.truncate(len);
+        data
+    }
+
+    /// Compare the blocks of the children with the data of the IO, and write
+    /// the data of a compare and write if they match, with the range locked.
+    /// The IO is completed once done.
+    fn compare_and_write(&mut self) -> Result<(), CoreError> {
+        let write = if self.io_type() == IoType::CompareAndWrite {
+            Some(self.gather_fused())
+        } else {
+            None
+        };
+
+        Reactors::current()
+            .spawn_local(compare_and_write(
+                self.nexus_as_ref().name.clone(),
+                self.as_ptr(),
+                self.offset(),
+                self.num_blocks(),
+                self.gather(),
+                write,
+            ))
+            .detach();
+        Ok(())
+    }
+
+    /// sample the IO for the trace of the nexus, if any
+    fn trace_sample(&mut self) {
+        let start =
+            self.inner_channel().trace.as_ref().and_then(|t| t.sample());
+        self.ctx_mut().trace_start = start;
+    }
+
+    /// record a traced IO once all its child IOs have completed
+    fn trace_record(&mut self, child: &str, ok: bool) {
+        let start = match self.ctx_mut().trace_start.take() {
+            Some(start) => start,
+            None => return,
+        };
+        if let Some(trace) = self.inner_channel().trace.as_ref() {
+            let block_len = self.nexus_as_ref().block_len();
+            let child = if self.io_type() == IoType::Read {
+                Some(child.to_string())
+            } else {
+                None
+            };
+            trace.record(
+                start,
+                self.io_type(),
+                self.offset() * block_len,
+                self.num_blocks() * block_len,
+                child,
+                ok,
+            );
+        }
+    }
+
+    /// record a routing decision in the IO debug log of the nexus, if the IO
+    /// is in it
+    fn debug_event(&self, kind: impl FnOnce() -> IoDebugKind) {
+        let io = self.ctx().debug_io;
+        if io == 0 {
+            return;
+        }
+        if let Some(log) = self.inner_channel().io_debug.as_ref() {
+            let block_len = self.nexus_as_ref().block_len();
+            log.record(
+                io,
+                self.io_type(),
+                self.offset() * block_len,
+                self.num_blocks() * block_len,
+                kind(),
+            );
+        }
+    }
+
+    /// record the submission of the IO to a child in the IO debug log
+    fn debug_submitted(
+        &self,
+        hdl: &dyn BlockDeviceHandle,
+        r: &Result<(), CoreError>,
+    ) {
+        self.debug_event(|| {
+            let child = hdl.get_device().device_name();
+            match r {
+                Ok(()) => IoDebugKind::Submitted {
+                    child,
+                },
+                Err(_) => IoDebugKind::SubmitFailed {
+                    child,
+                },
+            }
+        });
+    }
+
+    /// note the time reads and writes are submitted to the children when the
+    /// nexus tracks their latencies, and reads when it reads from the child
+    /// with the lowest latency
+    fn latency_sample(&mut self) {
+        let inner = self.inner_channel();
+        let by_latency = inner.opts.read_policy == ReadPolicy::LowestLatency;
+        if (inner.latencies.is_some()
+            && matches!(self.io_type(), IoType::Read | IoType::Write))
+            || (by_latency && self.io_type() == IoType::Read)
+        {
+            self.ctx_mut().submitted = Some(Instant::now());
+        }
+    }
+
+    /// record the latency of the IO of a child that completed successfully
+    fn latency_record(&mut self, child: &str) {
+        if let Some(submitted) = self.ctx().submitted {
+            let elapsed = submitted.elapsed();
+            if let Some(latencies) = self.inner_channel().latencies.as_ref() {
+                latencies.record(child, elapsed);
+            }
+            if self.io_type() == IoType::Read
+                && self.inner_channel().opts.read_policy
+                    == ReadPolicy::LowestLatency
+            {
+                let bytes = self.num_blocks() * self.nexus_as_ref().block_len();
+                self.inner_channel_mut()
+                    .latency_select_record(child, bytes, elapsed);
+            }
+        }
+    }
+
+    /// the iovs of the IO
+    fn iov_list(&self) -> &[IoVec] {
+        unsafe {
+            std::slice::from_raw_parts(self.iovs(), self.iov_count() as usize)
+        }
+    }
+
     /// assess the IO if we need to mark it failed or ok.
     /// obtain the Nexus struct embedded within the bdev
     pub(crate) fn nexus_as_ref(&self) -> Pin<&Nexus> {
         self.bdev_checked(NEXUS_PRODUCT_ID).data()
     }
 
+    /// returns true for the IO types that lock their range when overlapping
+    /// writes are serialized
+    fn is_locking(&self) -> bool {
+        matches!(
+            self.io_type(),
+            IoType::Write | IoType::WriteZeros | IoType::Unmap
+        )
+    }
+
+    /// Lock the range of a write, returning false if it has been queued
+    /// behind an overlapping write and is submitted again later.
+    fn write_lock(&self) -> bool {
+        if !self.is_locking() {
+            return true;
+        }
+        let range = self.offset() .. self.offset() + self.num_blocks();
+        self.nexus_as_ref().write_locks.lock(range, self.as_ptr())
+    }
+
+    /// release the range lock of a write that completes, if it holds one
+    fn write_unlock(&self) {
+        if self.is_locking() {
+            self.nexus_as_ref().write_locks.unlock(self.as_ptr());
+        }
+    }
+
+    /// record a write in the write journal of the nexus, once
+    fn journal_begin(&mut self) {
+        if self.ctx().journal_seq != 0
+            || !matches!(
+                self.io_type(),
+                IoType::Write
+                    | IoType::WriteZeros
+                    | IoType::Unmap
+                    | IoType::CompareAndWrite
+            )
+        {
+            return;
+        }
+        if let Some(journal) = self.nexus_as_ref().journal.as_ref() {
+            let seq = journal.begin(self.offset(), self.num_blocks());
+            self.ctx_mut().journal_seq = seq;
+        }
+    }
+
+    /// a journaled write completes
+    fn journal_end(&mut self) {
+        let seq = std::mem::take(&mut self.ctx_mut().journal_seq);
+        if seq != 0 {
+            if let Some(journal) = self.nexus_as_ref().journal.as_ref() {
+                journal.end(seq);
+            }
+        }
+    }
+
+    /// the child on the device may have missed a write, its journal must no
+    /// longer be updated
+    fn journal_freeze(&self, device: &str) {
+        if let Some(journal) = self.nexus_as_ref().journal.as_ref() {
+            journal.freeze(device);
+        }
+    }
+
+    /// mark the blocks of a completing write as changed, for replication
+    fn record_change(&self) {
+        if matches!(
+            self.io_type(),
+            IoType::Write
+                | IoType::WriteZeros
+                | IoType::Unmap
+                | IoType::CompareAndWrite
+        ) {
+            self.nexus_as_ref()
+                .changes
+                .record(self.offset(), self.num_blocks());
+        }
+    }
+
+    /// mark the blocks of a write to the fast child only as not destaged
+    fn tier_record(&self) {
+        if self.ctx().tiered {
+            if let Some(tier) = self.nexus_as_ref().tier.lock().as_ref() {
+                tier.written(self.offset(), self.num_blocks());
+            }
+        }
+    }
+
+    /// account for the completion of the IO on its channel
+    fn io_done(&mut self) {
+        self.inner_channel()
+            .io_in_flight
+            .fetch_sub(1, Ordering::Relaxed);
+        if std::mem::take(&mut self.ctx_mut().limited) {
+            self.nexus_as_ref().io_limit.release();
+        }
+        // a read ahead while the write was in flight may hold either data
+        if matches!(
+            self.io_type(),
+            IoType::Write
+                | IoType::WriteZeros
+                | IoType::Unmap
+                | IoType::CompareAndWrite
+        ) {
+            self.nexus_as_ref().read_ahead.invalidate();
+        }
+    }
+
+    /// complete the IO successfully
+    fn ok(&mut self) {
+        self.debug_event(|| IoDebugKind::Completed {
+            ok: true,
+        });
+        self.io_done();
+        self.record_change();
+        self.tier_record();
+        self.journal_end();
+        self.write_unlock();
+        self.0.ok();
+    }
+
+    /// complete the IO as failed
+    fn fail(&mut self) {
+        self.debug_event(|| IoDebugKind::Completed {
+            ok: false,
+        });
+        self.io_done();
+        self.record_change();
+        self.tier_record();
+        self.journal_end();
+        self.write_unlock();
+        self.0.fail();
+    }
+
+    /// Queue the IO to be submitted again once the child that had no memory
+    /// for it completed some IO, rather than failing it. If the queue of the
+    /// channel is full the IO is completed with NOMEM instead.
+    fn wait_for_memory(&mut self) {
+        let io = self.as_ptr();
+        if self.inner_channel_mut().wait_for_memory(io) {
+            self.debug_event(|| IoDebugKind::Deferred);
+        } else {
+            self.no_mem();
+        }
+    }
+
+    /// complete the IO with NOMEM, the bdev layer submits it again later
+    fn no_mem(&mut self) {
+        self.io_done();
+        self.journal_end();
+        self.write_unlock();
+        self.0.no_mem();
+    }
+
     /// invoked when a nexus IO completes
     fn child_completion(
         device: &dyn BlockDevice,
@@ -144,7 +996,84 @@ impl<'n> NexusBio<'n> {
         ctx: *mut c_void,
     ) {
         let mut nexus_io = NexusBio::from(ctx as *mut spdk_bdev_io);
-        nexus_io.complete(device, status);
+        nexus_io.complete(&device.device_name(), status);
+    }
+
+    /// invoked when a child IO a fault is injected in completes
+    fn injected_completion(
+        device: &dyn BlockDevice,
+        status: IoCompletionStatus,
+        ctx: *mut c_void,
+    ) {
+        let injected = unsafe { Box::from_raw(ctx as *mut InjectedIo) };
+        let child = device.device_name();
+        let io = injected.io;
+
+        match injected.action.delay() {
+            None => NexusBio::from(io).complete(
+                &child,
+                IoCompletionStatus::NvmeError(
+                    NvmeCommandStatus::GenericCommandStatus(
+                        GenericStatusCode::InternalDeviceError,
+                    ),
+                ),
+            ),
+            Some(delay) => Reactors::current().send_future(async move {
+                let _ = mayastor_sleep(delay).await;
+                NexusBio::from(io).complete(&child, status);
+            }),
+        }
+    }
+
+    /// Submit an IO to a child with the completion callback and argument
+    /// given to submit, diverting the completion when a fault is to be
+    /// injected in the IO.
+    fn submit_child(
+        &self,
+        hdl: &dyn BlockDeviceHandle,
+        submit: impl FnOnce(
+            IoCompletionCallback,
+            IoCompletionCallbackArg,
+        ) -> Result<(), CoreError>,
+    ) -> Result<(), CoreError> {
+        let r = self.submit_child_io(hdl, submit);
+        self.debug_submitted(hdl, &r);
+        r
+    }
+
+    /// submit an IO to a child, see submit_child
+    fn submit_child_io(
+        &self,
+        hdl: &dyn BlockDeviceHandle,
+        submit: impl FnOnce(
+            IoCompletionCallback,
+            IoCompletionCallbackArg,
+        ) -> Result<(), CoreError>,
+    ) -> Result<(), CoreError> {
+        if !fault_injection::is_active() {
+            return submit(Self::child_completion, self.as_ptr().cast());
+        }
+
+        let action = fault_injection::inject(
+            FaultDomain::Child,
+            &hdl.get_device().device_name(),
+            self.io_type(),
+            self.num_blocks() * self.nexus_as_ref().block_len(),
+        );
+        match action {
+            Some(action) => {
+                let arg = Box::into_raw(Box::new(InjectedIo {
+                    io: self.as_ptr(),
+                    action,
+                }));
+                let r = submit(Self::injected_completion, arg.cast());
+                if r.is_err() {
+                    drop(unsafe { Box::from_raw(arg) });
+                }
+                r
+            }
+            None => submit(Self::child_completion, self.as_ptr().cast()),
+        }
     }
 
     #[inline(always)]
@@ -160,25 +1089,39 @@ impl<'n> NexusBio<'n> {
     }
 
     /// completion handler for the nexus when a child IO completes
-    fn complete(
-        &mut self,
-        child: &dyn BlockDevice,
-        status: IoCompletionStatus,
-    ) {
+    fn complete(&mut self, child: &str, status: IoCompletionStatus) {
         let success = status == IoCompletionStatus::Success;
 
         self.ctx_mut().in_flight -= 1;
+        if self.ctx().in_flight == 0 {
+            self.bounce_release(success && !self.ctx().must_fail);
+            self.crypt_release();
+            self.trace_record(child, success && !self.ctx().must_fail);
+        }
 
         if success {
+            self.latency_record(child);
+            if self.ctx().in_flight == 0 && !self.ctx().must_fail {
+                match self.io_type() {
+                    IoType::Read => {
+                        self.crypt_decrypt();
+                        if self.checksum_repair() {
+                            return;
+                        }
+                        self.read_cache_fill();
+                    }
+                    IoType::Write => self.checksum_record(),
+                    _ => {}
+                }
+            }
             self.ok_checked();
         } else {
             // IO failure, mark the IO failed and take the child out
-            error!(
-                ?self,
-                "{} IO completion failed: {:?}",
-                child.device_name(),
-                self.ctx()
-            );
+            error!(?self, "{} IO completion failed: {:?}", child, self.ctx());
+            self.debug_event(|| IoDebugKind::ChildFailed {
+                child: child.to_string(),
+                status: format!("{:?}", status),
+            });
             self.ctx_mut().status = IoStatus::Failed;
             self.ctx_mut().must_fail = true;
             self.handle_failure(child, status);
@@ -214,6 +1157,9 @@ impl<'n> NexusBio<'n> {
     fn retry_checked(&mut self) {
         if self.ctx().in_flight == 0 {
             debug!(?self, "resubmitting IO");
+            self.debug_event(|| IoDebugKind::Retried);
+            self.ctx_mut().must_fail = false;
+            self.ctx_mut().status = IoStatus::Pending;
             self.clone().submit_request();
         }
     }
@@ -248,23 +1194,74 @@ impl<'n> NexusBio<'n> {
         &self,
         hdl: &dyn BlockDeviceHandle,
     ) -> Result<(), CoreError> {
-        hdl.readv_blocks(
-            self.iovs(),
-            self.iov_count(),
-            self.offset() + self.data_ent_offset(),
-            self.num_blocks(),
-            Self::child_completion,
-            self.as_ptr().cast(),
-        )
+        let (iovs, iov_count) = self.child_iovs();
+        self.submit_child(hdl, |cb, arg| {
+            hdl.readv_blocks(
+                iovs,
+                iov_count,
+                self.offset() + self.data_ent_offset(),
+                self.num_blocks(),
+                cb,
+                arg,
+            )
+        })
+    }
+
+    /// select the child to read from, by the location of the data when the
+    /// nexus is tiered
+    fn select_reader(&mut self) -> Option<usize> {
+        let (lba, num_blocks) = (self.offset(), self.num_blocks());
+        let bytes = num_blocks * self.nexus_as_ref().block_len();
+        let inner = self.inner_channel_mut();
+        match inner.tier.clone() {
+            Some(tier) => {
+                inner.tier_select(&tier, tier.on_fast(lba, num_blocks), bytes)
+            }
+            None => inner.child_select(bytes),
+        }
+    }
+
+    /// Returns the index of the only writer a write goes to, the fast child
+    /// when the nexus is tiered and it is there.
+    fn tier_writer(&self) -> Option<usize> {
+        if !matches!(
+            self.io_type(),
+            IoType::Write | IoType::WriteZeros | IoType::Unmap
+        ) {
+            return None;
+        }
+        let inner = self.inner_channel();
+        let tier = inner.tier.as_ref()?;
+        inner
+            .writers
+            .iter()
+            .position(|w| tier.is_fast(&w.get_device().device_name()))
     }
 
     /// submit a read operation
     fn do_readv(&mut self) -> Result<(), CoreError> {
-        if let Some(i) = self.inner_channel_mut().child_select() {
+        let mut ahead = false;
+        if self.cache_read()
+            || self.read_cache_hit()
+            || self.read_ahead_hit(&mut ahead)
+        {
+            return Ok(());
+        }
+
+        if let Some(i) = self.select_reader() {
+            let caps = self.read_channel_at_index(i).get_device().sgl_caps();
+            if !self.bounce_prepare(vec![caps]) {
+                return Ok(());
+            }
             let hdl = self.read_channel_at_index(i);
             let r = self.submit_read(hdl);
 
-            if r.is_err() {
+            if matches!(&r, Err(e) if is_nomem(e)) {
+                // the child is fine, it only has to complete some IO first
+                self.bounce_release(false);
+                self.wait_for_memory();
+                Ok(())
+            } else if r.is_err() {
                 // Such a situation can happen when there is no active I/O in
                 // the queues, but error on qpair is observed
                 // due to network timeout, which initiates
@@ -272,8 +1269,6 @@ impl<'n> NexusBio<'n> {
                 // I/O channels are de-initialized, so no I/O
                 // submission is possible (spdk returns -6/ENXIO), so we have to
                 // start device retire.
-                // TODO: ENOMEM and ENXIO should be handled differently and
-                // device should not be retired in case of ENOMEM.
 
                 let device = hdl.get_device().device_name();
                 trace!(
@@ -286,12 +1281,22 @@ impl<'n> NexusBio<'n> {
                     self.do_retire(device);
                 }
 
+                self.bounce_release(false);
                 self.fail();
             } else {
                 self.ctx_mut().in_flight = 1;
+                if ahead {
+                    let from = self.offset() + self.num_blocks();
+                    self.inner_channel().read_ahead_issue(i, from);
+                }
             }
             r
         } else {
+            let io = self.as_ptr();
+            if self.inner_channel_mut().defer(io) {
+                self.debug_event(|| IoDebugKind::Deferred);
+                return Ok(());
+            }
             trace!(
                 "(core: {} thread: {}): read IO submission failed no children available",
                 Cores::current(), Mthread::current().unwrap().name());
@@ -336,14 +1341,19 @@ impl<'n> NexusBio<'n> {
         &self,
         hdl: &dyn BlockDeviceHandle,
     ) -> Result<(), CoreError> {
-        hdl.writev_blocks(
-            self.iovs(),
-            self.iov_count(),
-            self.offset() + self.data_ent_offset(),
-            self.num_blocks(),
-            Self::child_completion,
-            self.as_ptr().cast(),
-        )
+        // an encrypted nexus writes the ciphertext in the bounce buffer
+        let (iovs, iov_count) = self.child_iovs();
+
+        self.submit_child(hdl, |cb, arg| {
+            hdl.writev_blocks(
+                iovs,
+                iov_count,
+                self.offset() + self.data_ent_offset(),
+                self.num_blocks(),
+                cb,
+                arg,
+            )
+        })
     }
 
     #[inline]
@@ -351,12 +1361,14 @@ impl<'n> NexusBio<'n> {
         &self,
         hdl: &dyn BlockDeviceHandle,
     ) -> Result<(), CoreError> {
-        hdl.unmap_blocks(
-            self.offset() + self.data_ent_offset(),
-            self.num_blocks(),
-            Self::child_completion,
-            self.as_ptr().cast(),
-        )
+        self.submit_child(hdl, |cb, arg| {
+            hdl.unmap_blocks(
+                self.offset() + self.data_ent_offset(),
+                self.num_blocks(),
+                cb,
+                arg,
+            )
+        })
     }
 
     #[inline]
@@ -364,12 +1376,14 @@ impl<'n> NexusBio<'n> {
         &self,
         hdl: &dyn BlockDeviceHandle,
     ) -> Result<(), CoreError> {
-        hdl.write_zeroes(
-            self.offset() + self.data_ent_offset(),
-            self.num_blocks(),
-            Self::child_completion,
-            self.as_ptr().cast(),
-        )
+        self.submit_child(hdl, |cb, arg| {
+            hdl.write_zeroes(
+                self.offset() + self.data_ent_offset(),
+                self.num_blocks(),
+                cb,
+                arg,
+            )
+        })
     }
 
     #[inline]
@@ -377,7 +1391,35 @@ impl<'n> NexusBio<'n> {
         &self,
         hdl: &dyn BlockDeviceHandle,
     ) -> Result<(), CoreError> {
-        hdl.reset(Self::child_completion, self.as_ptr().cast())
+        let r = hdl.reset(Self::child_completion, self.as_ptr().cast());
+        self.debug_submitted(hdl, &r);
+        r
+    }
+
+    #[inline]
+    fn submit_flush(
+        &self,
+        hdl: &dyn BlockDeviceHandle,
+    ) -> Result<(), CoreError> {
+        self.submit_child(hdl, |cb, arg| hdl.flush_io(cb, arg))
+    }
+
+    /// Flush all children that have a write cache, the flush completes once
+    /// all of them have acknowledged it. Children that can not flush
+    /// complete their writes on stable media and are skipped. The bdev layer
+    /// does not pass FUA down to us, so this is the only durability barrier
+    /// the frontend can ask for.
+    fn flush(&mut self) -> Result<(), CoreError> {
+        let writers = &self.inner_channel().writers;
+        if !writers.is_empty()
+            && !writers
+                .iter()
+                .any(|h| h.get_device().io_type_supported(IoType::Flush))
+        {
+            self.ok();
+            return Ok(());
+        }
+        self.submit_all()
     }
 
     /// Submit the IO to all underlying children, failing on the first error we
@@ -390,12 +1432,56 @@ impl<'n> NexusBio<'n> {
         // Name of the device which experiences I/O submission failures.
         let mut failed_device = None;
 
-        let result = self.inner_channel().writers.iter().try_for_each(|h| {
+        // wait for the channel to be refreshed rather than failing the IO
+        if self.inner_channel().writers.is_empty() {
+            let io = self.as_ptr();
+            if self.inner_channel_mut().defer(io) {
+                self.debug_event(|| IoDebugKind::Deferred);
+                return Ok(());
+            }
+        }
+
+        if self.io_type() == IoType::Write && !self.crypt_encrypt() {
+            return Ok(());
+        }
+
+        let only = self.tier_writer();
+        self.ctx_mut().tiered = only.is_some();
+
+        if self.io_type() == IoType::Write {
+            let caps = self
+                .inner_channel()
+                .writers
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| only.map_or(true, |o| o == *i))
+                .map(|(_, h)| h.get_device().sgl_caps())
+                .collect();
+            if !self.bounce_prepare(caps) {
+                self.crypt_release();
+                return Ok(());
+            }
+        }
+
+        let result = self
+            .inner_channel()
+            .writers
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| only.map_or(true, |o| o == *i))
+            .try_for_each(|(_, h)| {
             match self.io_type() {
                 IoType::Write => self.submit_write(h.as_ref()),
                 IoType::Unmap => self.submit_unmap(h.as_ref()),
                 IoType::WriteZeros => self.submit_write_zeroes(h.as_ref()),
                 IoType::Reset => self.submit_reset(h.as_ref()),
+                // children without a write cache have nothing to flush
+                IoType::Flush
+                    if !h.get_device().io_type_supported(IoType::Flush) =>
+                {
+                    return Ok(())
+                }
+                IoType::Flush => self.submit_flush(h.as_ref()),
                 // we should never reach here, if we do it is a bug.
                 _ => unreachable!(),
             }
@@ -414,6 +1500,16 @@ impl<'n> NexusBio<'n> {
                 })
         });
 
+        // A child without memory for the IO is fine, if none of the IO has
+        // been submitted yet the whole IO is submitted again later.
+        let nomem = matches!(&result, Err(e) if is_nomem(e));
+        if nomem && inflight == 0 {
+            self.bounce_release(false);
+            self.crypt_release();
+            self.wait_for_memory();
+            return Ok(());
+        }
+
         // Submission errors can also trigger device retire.
         // Such a situation can happen when there is no active I/O in the
         // queues, but error on qpair is observed due to network
@@ -421,10 +1517,14 @@ impl<'n> NexusBio<'n> {
         // reset all I/O channels are de-initialized, so no I/O
         // submission is possible (spdk returns -6/ENXIO), so we have to
         // start device retire.
-        // TODO: ENOMEM and ENXIO should be handled differently and
-        // device should not be retired in case of ENOMEM.
-        if result.is_err() {
+        if nomem {
+            // submitted again as a whole once the children that have it
+            // completed it
+            self.inner_channel_mut().record_nomem();
+            self.ctx_mut().must_fail = true;
+        } else if result.is_err() {
             let device = failed_device.unwrap();
+            self.journal_freeze(&device);
             // set the IO as failed in the submission stage.
             self.ctx_mut().must_fail = true;
             if self.inner_channel_mut().remove_child(&device) {
@@ -442,6 +1542,8 @@ impl<'n> NexusBio<'n> {
             return result;
         }
 
+        self.bounce_release(false);
+        self.crypt_release();
         self.fail_checked();
 
         result
@@ -454,11 +1556,7 @@ impl<'n> NexusBio<'n> {
         ));
     }
 
-    fn handle_failure(
-        &mut self,
-        child: &dyn BlockDevice,
-        status: IoCompletionStatus,
-    ) {
+    fn handle_failure(&mut self, child: &str, status: IoCompletionStatus) {
         // We have experienced a failure on one of the child devices. We need to
         // ensure we do not submit more IOs to this child. We do not
         // need to tell other cores about this because
@@ -481,11 +1579,26 @@ impl<'n> NexusBio<'n> {
         ) {
             debug!(
                 "Device {} experienced invalid opcode error: retiring skipped",
-                child.device_name()
+                child
             );
             return;
         }
 
+        // Our reservation has been preempted by a nexus with a newer epoch,
+        // the child itself is fine so it must not be retired.
+        if matches!(
+            status,
+            IoCompletionStatus::NvmeError(
+                NvmeCommandStatus::GenericCommandStatus(
+                    GenericStatusCode::ReservationConflict
+                )
+            )
+        ) {
+            self.nexus_as_ref()
+                .fence(&format!("reservation conflict on child {}", child));
+            return self.fail_checked();
+        }
+
         let retry = matches!(
             status,
             IoCompletionStatus::NvmeError(
@@ -495,7 +1608,8 @@ impl<'n> NexusBio<'n> {
             )
         );
 
-        let child = child.device_name();
+        let child = child.to_string();
+        self.journal_freeze(&child);
         // check if this child needs to be retired
         let needs_retire = self.inner_channel_mut().fault_child(&child);
         // The child state was not faulted yet, so this is the first IO
@@ -513,17 +1627,114 @@ impl<'n> NexusBio<'n> {
     }
 }
 
+/// returns true if a child IO could not be submitted for the lack of memory
+fn is_nomem(e: &CoreError) -> bool {
+    matches!(
+        e,
+        CoreError::ReadDispatch {
+            source: Errno::ENOMEM,
+            ..
+        } | CoreError::WriteDispatch {
+            source: Errno::ENOMEM,
+            ..
+        } | CoreError::UnmapDispatch {
+            source: Errno::ENOMEM,
+            ..
+        } | CoreError::WriteZeroesDispatch {
+            source: Errno::ENOMEM,
+            ..
+        } | CoreError::FlushDispatch {
+            source: Errno::ENOMEM,
+        } | CoreError::ResetDispatch {
+            source: Errno::ENOMEM,
+        }
+    )
+}
+
 /// TODO
 pub(crate) fn nexus_submit_request(
     chan: spdk_rs::IoChannel<NexusChannel>,
     bio: BdevIo<Nexus>,
 ) {
-    let io = NexusBio::new(chan, bio);
+    let mut io = NexusBio::new(chan, bio);
+    io.inner_channel()
+        .io_in_flight
+        .fetch_add(1, Ordering::Relaxed);
+    io.ctx_mut().journal_seq = 0;
+    io.ctx_mut().debug_io = io
+        .inner_channel()
+        .io_debug
+        .as_ref()
+        .map_or(0, |l| l.next_io());
+    if io.push_back() {
+        return;
+    }
+    io.trace_sample();
+    if io.epoch_hold() {
+        return;
+    }
+    nexus_admit_checked(io.as_ptr());
+}
+
+/// Submit a write that waited for the epoch of the nexus to be checked, or
+/// an IO that did not have to.
+pub(crate) fn nexus_admit_checked(io: *mut spdk_bdev_io) {
+    let mut io = NexusBio::from(io);
+    if io.limit_hold() || io.qos_hold() || io.direct_submit() {
+        return;
+    }
     io.submit_request();
 }
 
+/// Submit an IO that waited for the nexus to be under its limit of
+/// outstanding IOs, holding a slot if it is limited.
+pub(crate) fn nexus_admit_request(io: *mut spdk_bdev_io, limited: bool) {
+    let mut io = NexusBio::from(io);
+    io.ctx_mut().limited = limited;
+    if io.qos_hold() || io.direct_submit() {
+        return;
+    }
+    io.submit_request();
+}
+
+/// Submit an IO that has been held back by the QoS limits or the write cache
+/// of the nexus.
+pub(crate) fn nexus_resubmit_request(io: *mut spdk_bdev_io) {
+    NexusBio::from(io).submit_request();
+}
+
+/// Flush the children for a flush that has been held by the nexus.
+pub(crate) fn nexus_flush_request(io: *mut spdk_bdev_io) {
+    let _ = NexusBio::from(io).flush();
+}
+
+/// Complete an IO that has been held by the nexus.
+pub(crate) fn nexus_complete_request(io: *mut spdk_bdev_io, success: bool) {
+    let mut bio = NexusBio::from(io);
+    if success {
+        bio.ok();
+    } else {
+        bio.fail();
+    }
+}
+
+/// Complete a compare that has been held by the nexus with a miscompare.
+pub(crate) fn nexus_complete_miscompare(io: *mut spdk_bdev_io) {
+    let mut bio = NexusBio::from(io);
+    bio.io_done();
+    bio.journal_end();
+    unsafe { spdk_bdev_io_complete(io, SPDK_BDEV_IO_STATUS_MISCOMPARE) }
+}
+
+/// Complete a read that has been held by the nexus with the given data.
+pub(crate) fn nexus_complete_read(io: *mut spdk_bdev_io, data: &[u8]) {
+    let mut bio = NexusBio::from(io);
+    bio.scatter(data);
+    bio.ok();
+}
+
 /// Retire a child for this nexus.
-async fn nexus_child_retire(nexus_name: String, device: String) {
+pub(crate) async fn nexus_child_retire(nexus_name: String, device: String) {
     if let Some(mut nexus) = nexus_lookup_mut(&nexus_name) {
         warn!(?nexus, ?device, "retiring child");
 
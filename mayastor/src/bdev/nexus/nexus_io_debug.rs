@@ -0,0 +1,412 @@
+//!
+//! Debug log of the routing of the IOs of a nexus.
+//!
+//! Following why an IO of a nexus took the path it took meant running the
+//! whole of mayastor with trace logging compiled in and turned on for every
+//! nexus. The IO debug log of a nexus can be turned on and off while it
+//! runs: every IO submitted while it is on is given a number, and the
+//! decisions taken for it are recorded, each as an event:
+//!
+//! - `submitted`: the IO was submitted to a child, once per child for writes
+//! - `submit_failed`: submitting the IO to a child failed
+//! - `deferred`: the channel had no child, the IO waits for it to have some
+//! - `child_failed`: the IO failed on a child, with the status it failed with
+//! - `retried`: the IO is submitted again once all its child IOs are done
+//! - `completed`: the nexus completed the IO, successfully or not
+//!
+//! The events are logged at trace level and kept in a ring buffer, returned
+//! by the `nexus_io_debug_get` json-rpc method. IOs completed by the nexus
+//! without a child, from its caches for instance, only have their
+//! completion recorded. IOs submitted before the log was turned on are not
+//! recorded.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use crossbeam::atomic::AtomicCell;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use spdk_rs::ChannelTraverseStatus;
+
+use super::{nexus_lookup_any_mut, Error, Nexus, NexusChannel, TraceOp};
+use crate::{
+    core::{for_each_channel, IoType},
+    jsonrpc::jsonrpc_register,
+};
+
+/// A routing decision taken for an IO.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum IoDebugKind {
+    Submitted { child: String },
+    SubmitFailed { child: String },
+    Deferred,
+    ChildFailed { child: String, status: String },
+    Retried,
+    Completed { ok: bool },
+}
+
+/// An event of the IO debug log of a nexus.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IoDebugEvent {
+    /// time of the event, in microseconds since the log was turned on
+    pub ts_us: u64,
+    /// number of the IO in the log
+    pub io: u64,
+    pub op: TraceOp,
+    /// offset of the IO in bytes
+    pub offset: u64,
+    /// length of the IO in bytes
+    pub len: u64,
+    #[serde(flatten)]
+    pub kind: IoDebugKind,
+}
+
+/// Counters of the IO debug log of a nexus.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IoDebugStats {
+    pub enabled: bool,
+    /// number of events kept in memory
+    pub capacity: usize,
+    /// IOs given a number
+    pub ios: u64,
+    pub events: u64,
+}
+
+/// The IO debug log of a nexus, shared by all its channels.
+pub(crate) struct IoDebugLog {
+    name: String,
+    capacity: usize,
+    started: Instant,
+    enabled: AtomicCell<bool>,
+    ios: AtomicU64,
+    events: AtomicU64,
+    ring: parking_lot::Mutex<VecDeque<IoDebugEvent>>,
+}
+
+impl std::fmt::Debug for IoDebugLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IoDebugLog")
+            .field("capacity", &self.capacity)
+            .field("enabled", &self.enabled.load())
+            .field("ios", &self.ios)
+            .field("events", &self.events)
+            .finish()
+    }
+}
+
+impl IoDebugLog {
+    fn new(name: &str, capacity: usize) -> Result<Self, Error> {
+        if capacity == 0 {
+            return Err(Error::InvalidArguments {
+                name: name.to_string(),
+                args: "IO debug log capacity of 0".to_string(),
+            });
+        }
+        Ok(Self {
+            name: name.to_string(),
+            capacity,
+            started: Instant::now(),
+            enabled: AtomicCell::new(true),
+            ios: AtomicU64::new(0),
+            events: AtomicU64::new(0),
+            ring: parking_lot::Mutex::new(VecDeque::with_capacity(
+                capacity.min(4096),
+            )),
+        })
+    }
+
+    /// Returns the number of a newly submitted IO, never 0.
+    pub(crate) fn next_io(&self) -> u64 {
+        self.ios.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// record an event of the IO with the given number
+    pub(crate) fn record(
+        &self,
+        io: u64,
+        io_type: IoType,
+        offset: u64,
+        len: u64,
+        kind: IoDebugKind,
+    ) {
+        let op = match TraceOp::from_io_type(io_type) {
+            Some(op) => op,
+            None => return,
+        };
+        trace!(
+            "{}: IO {} {:?} offset {} len {}: {:?}",
+            self.name,
+            io,
+            op,
+            offset,
+            len,
+            kind
+        );
+        let event = IoDebugEvent {
+            ts_us: self.started.elapsed().as_micros() as u64,
+            io,
+            op,
+            offset,
+            len,
+            kind,
+        };
+
+        let mut ring = self.ring.lock();
+        if ring.len() == self.capacity {
+            ring.pop_front();
+        }
+        ring.push_back(event);
+        self.events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.load()
+    }
+
+    /// the last events, oldest first
+    fn events(&self, max_entries: Option<usize>) -> Vec<IoDebugEvent> {
+        let ring = self.ring.lock();
+        let skip = max_entries
+            .map(|max| ring.len().saturating_sub(max))
+            .unwrap_or(0);
+        ring.iter().skip(skip).cloned().collect()
+    }
+
+    fn stats(&self) -> IoDebugStats {
+        IoDebugStats {
+            enabled: self.is_enabled(),
+            capacity: self.capacity,
+            ios: self.ios.load(Ordering::Relaxed),
+            events: self.events.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Context to install an IO debug log on all channels of a nexus.
+struct SetIoDebugCtx {
+    log: Option<Arc<IoDebugLog>>,
+}
+
+fn set_io_debug_cb(
+    channel: &mut NexusChannel,
+    ctx: &mut SetIoDebugCtx,
+) -> ChannelTraverseStatus {
+    channel.inner_mut().io_debug = ctx.log.clone();
+    ChannelTraverseStatus::Ok
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the IO debug log of this nexus if it is on, for newly created
+    /// channels
+    pub(crate) fn io_debug(&self) -> Option<Arc<IoDebugLog>> {
+        self.io_debug.lock().clone().filter(|l| l.is_enabled())
+    }
+
+    /// Turn the IO debug log of this nexus on, starting a new log that keeps
+    /// the last capacity events.
+    pub async fn enable_io_debug(&self, capacity: usize) -> Result<(), Error> {
+        let log = Arc::new(IoDebugLog::new(&self.name, capacity)?);
+        info!(
+            "{}: IO debug log on, keeping {} events",
+            self.name, capacity
+        );
+
+        if let Some(previous) = self.io_debug.lock().replace(log.clone()) {
+            previous.enabled.store(false);
+        }
+        self.install_io_debug(Some(log)).await;
+        Ok(())
+    }
+
+    /// Turn the IO debug log of this nexus off. Its events remain available
+    /// until it is turned on again.
+    pub async fn disable_io_debug(&self) {
+        let log = match self.io_debug() {
+            Some(log) => log,
+            None => return,
+        };
+        info!("{}: IO debug log off", self.name);
+        self.install_io_debug(None).await;
+        log.enabled.store(false);
+    }
+
+    /// Returns the counters of the IO debug log of this nexus.
+    pub fn io_debug_stats(&self) -> Option<IoDebugStats> {
+        self.io_debug.lock().as_ref().map(|l| l.stats())
+    }
+
+    /// Returns the last events of the IO debug log of this nexus, oldest
+    /// first.
+    pub fn io_debug_events(
+        &self,
+        max_entries: Option<usize>,
+    ) -> Vec<IoDebugEvent> {
+        self.io_debug
+            .lock()
+            .as_ref()
+            .map(|l| l.events(max_entries))
+            .unwrap_or_default()
+    }
+
+    /// install the IO debug log on all channels
+    async fn install_io_debug(&self, log: Option<Arc<IoDebugLog>>) {
+        if self.has_io_device {
+            let r = for_each_channel(
+                self,
+                SetIoDebugCtx {
+                    log,
+                },
+                set_io_debug_cb,
+            );
+            r.await.expect("set IO debug sender already dropped");
+        }
+    }
+}
+
+fn default_capacity() -> usize {
+    16384
+}
+
+#[derive(Debug, Deserialize)]
+struct EnableArgs {
+    /// name or uuid of the nexus
+    name: String,
+    /// number of events kept in memory
+    #[serde(default = "default_capacity")]
+    capacity: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct IoDebugArgs {
+    /// name or uuid of the nexus
+    name: String,
+    /// number of events to return, the most recent ones
+    #[serde(default)]
+    max_entries: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct IoDebugReply {
+    stats: Option<IoDebugStats>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    events: Vec<IoDebugEvent>,
+}
+
+async fn enable(args: EnableArgs) -> Result<IoDebugReply, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.enable_io_debug(args.capacity).await?;
+    Ok(IoDebugReply {
+        stats: nexus.io_debug_stats(),
+        events: Vec::new(),
+    })
+}
+
+async fn disable(args: IoDebugArgs) -> Result<IoDebugReply, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.disable_io_debug().await;
+    Ok(IoDebugReply {
+        stats: nexus.io_debug_stats(),
+        events: Vec::new(),
+    })
+}
+
+async fn get(args: IoDebugArgs) -> Result<IoDebugReply, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    Ok(IoDebugReply {
+        stats: nexus.io_debug_stats(),
+        events: nexus.io_debug_events(args.max_entries),
+    })
+}
+
+/// Register the json-rpc methods to turn the IO debug log of a nexus on and
+/// off and to get its events.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_io_debug_enable",
+        |args: EnableArgs| enable(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_io_debug_disable",
+        |args: IoDebugArgs| disable(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_io_debug_get",
+        |args: IoDebugArgs| get(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring() {
+        let log = IoDebugLog::new("nexus0", 3).unwrap();
+        let io = log.next_io();
+        assert_eq!(io, 1);
+
+        for child in &["a", "b"] {
+            log.record(
+                io,
+                IoType::Write,
+                4096,
+                512,
+                IoDebugKind::Submitted {
+                    child: child.to_string(),
+                },
+            );
+        }
+        log.record(
+            io,
+            IoType::Write,
+            4096,
+            512,
+            IoDebugKind::ChildFailed {
+                child: "b".into(),
+                status: "NvmeError".into(),
+            },
+        );
+        log.record(io, IoType::Write, 4096, 512, IoDebugKind::Retried);
+        // other IO types are not recorded
+        log.record(io, IoType::Flush, 0, 0, IoDebugKind::Deferred);
+
+        let events = log.events(None);
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0].kind,
+            IoDebugKind::Submitted {
+                child: "b".into()
+            }
+        );
+        assert_eq!(log.events(Some(1))[0].kind, IoDebugKind::Retried);
+
+        let stats = log.stats();
+        assert_eq!((stats.ios, stats.events), (1, 4));
+
+        let json = serde_json::to_value(&events[2]).unwrap();
+        assert_eq!(json["event"], "retried");
+        assert!(IoDebugLog::new("nexus0", 0).is_err());
+    }
+}
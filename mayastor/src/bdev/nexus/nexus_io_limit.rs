@@ -0,0 +1,222 @@
+//!
+//! Limit of the IO outstanding on a nexus.
+//!
+//! The children of the nexuses of a node share the pools of bdev IOs and of
+//! the IO contexts of the NVMe controllers, so a single busy volume can take
+//! all of them and starve the others. A nexus can be given a maximum number
+//! of IOs outstanding on it, over all its channels, as set by
+//! `nexus_update_options`. An IO is admitted when it is submitted to the
+//! nexus and counts until it is completed to the initiator, whatever the
+//! nexus does with it in between. IO submitted while the nexus is at its
+//! limit waits in a FIFO queue of its channel, which a poller on the thread
+//! of the channel admits as the outstanding IO of the nexus completes. IO is
+//! queued as long as earlier IO of the channel is queued, to keep its order.
+//! A maximum of 0, the default, does not limit the nexus.
+//!
+//! `nexus_io_limit` reports the limit and the IO outstanding on a nexus,
+//! along with the number of IOs that had to wait and the time they waited.
+
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use spdk_rs::libspdk::spdk_bdev_io;
+
+use super::{
+    nexus_admit_request,
+    nexus_lookup_any_mut,
+    nexus_resubmit_request,
+    Error,
+    Nexus,
+    NexusChannelInner,
+};
+use crate::{core::poller, jsonrpc::jsonrpc_register};
+
+/// Interval in usec at which queued IO is admitted.
+const LIMIT_POLL_INTERVAL_US: u64 = 100;
+
+/// IO outstanding on a nexus, and the IO that waited for the limit.
+#[derive(Debug, Default)]
+pub(crate) struct IoLimit {
+    outstanding: AtomicU64,
+    /// number of IOs waiting to be admitted right now
+    waiting: AtomicU64,
+    /// number of IOs that waited to be admitted
+    queued: AtomicU64,
+    /// time in usec the IOs waited to be admitted, in total and at most
+    queued_us: AtomicU64,
+    queued_max_us: AtomicU64,
+}
+
+impl IoLimit {
+    /// Returns true if an IO is admitted, taking a slot, under the maximum
+    /// number of outstanding IOs.
+    fn acquire(&self, max: u64) -> bool {
+        self.outstanding
+            .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |n| {
+                if n < max {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+
+    /// the slot of an admitted IO that completes is freed
+    pub(crate) fn release(&self) {
+        self.outstanding.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// account for an IO that waited since the time given to be admitted
+    fn record_wait(&self, since: Instant) {
+        let us = since.elapsed().as_micros() as u64;
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        self.queued_us.fetch_add(us, Ordering::Relaxed);
+        self.queued_max_us.fetch_max(us, Ordering::Relaxed);
+    }
+}
+
+/// IO of a channel waiting for the nexus to be under its limit.
+#[derive(Default)]
+pub(crate) struct LimitQueue {
+    waiting: VecDeque<(*mut spdk_bdev_io, Instant)>,
+    poller: Option<poller::Poller<'static>>,
+}
+
+/// The limit of the IO outstanding on a nexus, and the IO that waited for it.
+#[derive(Debug, Clone, Serialize)]
+pub struct IoLimitStats {
+    pub name: String,
+    /// maximum number of IOs outstanding, 0 if the nexus is not limited
+    pub max_io_outstanding: u32,
+    /// number of IOs outstanding right now that were admitted under the limit
+    pub outstanding: u64,
+    /// number of IOs waiting to be admitted right now
+    pub waiting: u64,
+    /// number of IOs that waited to be admitted
+    pub queued: u64,
+    /// time in usec the IOs waited to be admitted, in total and at most
+    pub queued_us: u64,
+    pub queued_max_us: u64,
+}
+
+impl NexusChannelInner {
+    /// Returns true if the IO waits to be admitted under the limit of the
+    /// nexus, false if it is admitted, taking a slot, or the nexus is not
+    /// limited. Only an IO that returns false with `admitted` set has a slot
+    /// to free when it completes.
+    pub(crate) fn limit_hold(
+        &mut self,
+        io: *mut spdk_bdev_io,
+        admitted: &mut bool,
+    ) -> bool {
+        let max = self.opts.max_io_outstanding as u64;
+        if max == 0 && self.limit.waiting.is_empty() {
+            return false;
+        }
+
+        if self.limit.waiting.is_empty()
+            && (max == 0 || self.get_nexus().io_limit.acquire(max))
+        {
+            *admitted = max != 0;
+            return false;
+        }
+
+        self.get_nexus()
+            .io_limit
+            .waiting
+            .fetch_add(1, Ordering::Relaxed);
+        if self.limit.poller.is_none() {
+            let inner = self as *mut NexusChannelInner;
+            self.limit.poller = Some(
+                poller::Builder::new()
+                    .with_name("nexus_limit_poller")
+                    .with_interval(LIMIT_POLL_INTERVAL_US)
+                    .with_poll_fn(move || unsafe { (*inner).admit_queued() })
+                    .build(),
+            );
+        }
+        self.limit.waiting.push_back((io, Instant::now()));
+        true
+    }
+
+    /// returns the number of IOs waiting to be admitted on the channel
+    pub(crate) fn limit_held(&self) -> usize {
+        self.limit.waiting.len()
+    }
+
+    /// admit the queued IO the limit of the nexus allows, in order
+    fn admit_queued(&mut self) -> i32 {
+        let max = self.opts.max_io_outstanding as u64;
+        let mut count = 0;
+        while let Some(&(io, since)) = self.limit.waiting.front() {
+            if max != 0 && !self.get_nexus().io_limit.acquire(max) {
+                break;
+            }
+            self.limit.waiting.pop_front();
+            self.get_nexus().io_limit.record_wait(since);
+            nexus_admit_request(io, max != 0);
+            count += 1;
+        }
+        count
+    }
+
+    /// Stop limiting the channel, resubmitting the queued IO without a slot.
+    pub(crate) fn stop_limiting(&mut self) {
+        if let Some(p) = self.limit.poller.take() {
+            p.stop();
+        }
+        let waiting = std::mem::take(&mut self.limit.waiting);
+        for (io, since) in waiting {
+            self.get_nexus().io_limit.record_wait(since);
+            nexus_resubmit_request(io);
+        }
+    }
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the limit of the IO outstanding on this nexus and the IO that
+    /// waited for it
+    pub fn io_limit_stats(&self) -> IoLimitStats {
+        let l = &self.io_limit;
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        IoLimitStats {
+            name: self.name.clone(),
+            max_io_outstanding: self.io_opts().max_io_outstanding,
+            outstanding: load(&l.outstanding),
+            waiting: load(&l.waiting),
+            queued: load(&l.queued),
+            queued_us: load(&l.queued_us),
+            queued_max_us: load(&l.queued_max_us),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsArgs {
+    /// name or uuid of the nexus
+    name: String,
+}
+
+async fn stats(args: StatsArgs) -> Result<IoLimitStats, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    Ok(nexus.io_limit_stats())
+}
+
+/// Register the json-rpc method reporting the limit of the IO outstanding on
+/// a nexus.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>("nexus_io_limit", |args: StatsArgs| {
+        stats(args).boxed_local()
+    });
+}
@@ -1,6 +1,24 @@
 use crate::bdev::{nexus::nexus_module::NexusModule, Nexus};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use spdk_rs::BdevModuleIter;
-use std::pin::Pin;
+use std::{collections::HashMap, pin::Pin};
+use uuid::Uuid;
+
+/// Names of the nexus instances by their uuid, so that looking a nexus up by
+/// uuid does not have to go over all of them.
+static UUID_INDEX: Lazy<Mutex<HashMap<Uuid, String>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Adds a nexus to the uuid index once it has been registered.
+pub(crate) fn nexus_index_uuid(uuid: Uuid, name: &str) {
+    UUID_INDEX.lock().insert(uuid, name.to_string());
+}
+
+/// Removes a nexus from the uuid index once it has been destroyed.
+pub(crate) fn nexus_unindex_uuid(uuid: Uuid) {
+    UUID_INDEX.lock().remove(&uuid);
+}
 
 /// Returns an immutable iterator for Nexus instances.
 pub fn nexus_iter<'n>() -> NexusIter<'n> {
@@ -41,7 +59,21 @@ pub fn nexus_lookup_name_uuid<'n>(
 pub fn nexus_lookup_uuid_mut<'n>(
     uuid: &str,
 ) -> Option<<NexusIterMut<'n> as Iterator>::Item> {
-    NexusIterMut::new().find(|n| n.uuid().to_string() == uuid)
+    let uuid = Uuid::parse_str(uuid).ok()?;
+    let name = UUID_INDEX.lock().get(&uuid).cloned();
+    match name.and_then(|name| nexus_lookup_mut(&name)) {
+        Some(nexus) if nexus.uuid() == uuid => Some(nexus),
+        _ => NexusIterMut::new().find(|n| n.uuid() == uuid),
+    }
+}
+
+/// Looks up a Nexus by its uuid or, failing that, by its name, and returns
+/// a mutable reference to it. An id which is the uuid of a nexus and the
+/// name of another refers to the former.
+pub fn nexus_lookup_any_mut<'n>(
+    id: &str,
+) -> Option<<NexusIterMut<'n> as Iterator>::Item> {
+    nexus_lookup_uuid_mut(id).or_else(|| nexus_lookup_mut(id))
 }
 
 /// TODO
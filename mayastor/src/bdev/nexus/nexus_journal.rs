@@ -0,0 +1,515 @@
+//!
+//! Write journal kept on the children for a fast resync.
+//!
+//! A child that drops out of the nexus for a moment, for instance when the
+//! connection to its replica is lost, is rebuilt in full once it is back even
+//! though it only missed the writes of that moment. With the write journal,
+//! turned on for all nexuses in the nexus options, every write, write zeroes,
+//! unmap and compare and write gets a sequence number when it is submitted,
+//! and the nexus keeps a record of the blocks it covers in a ring in the
+//! reserved metadata area of every healthy child. The header of the journal
+//! of each child holds the stable sequence number of the child: all writes up
+//! to it have been applied to the child.
+//!
+//! The records are written to the children in the background, along with the
+//! headers, which are no longer updated once an IO to the child has failed.
+//! When the child is rebuilt, the nexus reads its stable sequence number and
+//! the records of the writes after it from a healthy child, and only copies
+//! the segments these writes touched. A child is rebuilt in full when it has
+//! no header of this journal, or when the ring of the healthy children no
+//! longer holds all the writes it missed.
+//!
+//! The header holds the version of the journal after its magic, a header
+//! without one predates it. A child whose header is of a version this
+//! mayastor can not read is rebuilt in full, as if it had no header.
+//!
+//! Each nexus instance starts a new journal, the children of a nexus that
+//! has been created again are rebuilt in full. The journal can not be used
+//! together with a write cache, which writes the data back to the children
+//! after the writes have completed.
+
+use std::{
+    cmp::max,
+    collections::{BTreeSet, HashSet},
+    convert::TryInto,
+    ops::Range,
+    sync::Arc,
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+
+use super::{nexus_lookup_mut, ChildState};
+use crate::{
+    core::{partition::METADATA_RESERVATION_OFFSET, BlockDeviceHandle},
+    disk_format::Structure,
+    sleep::mayastor_sleep,
+};
+
+/// Interval at which the records are written to the children.
+const JOURNAL_WRITEBACK_MS: u64 = 100;
+
+/// Identifies the header of a write journal, "MSJOURNL".
+const JOURNAL_MAGIC: u64 = 0x4d53_4a4f_5552_4e4c;
+
+/// Size of the header and of each page of the ring, in bytes.
+const JOURNAL_PAGE_SIZE: u64 = 4096;
+
+/// Size of a record in the ring, in bytes.
+const RECORD_SIZE: u64 = 32;
+
+/// Number of records in a page of the ring.
+const RECORDS_PER_PAGE: u64 = JOURNAL_PAGE_SIZE / RECORD_SIZE;
+
+/// Number of pages of the ring, which takes the rest of the 4MiB metadata
+/// reservation after the header.
+const RING_PAGES: u64 = 1023;
+
+/// Number of records the ring holds.
+const RING_RECORDS: u64 = RING_PAGES * RECORDS_PER_PAGE;
+
+/// Offset of the ring on the children, in bytes.
+const RING_OFFSET: u64 = METADATA_RESERVATION_OFFSET + JOURNAL_PAGE_SIZE;
+
+/// The blocks covered by a write.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Record {
+    seq: u64,
+    lba: u64,
+    num_blocks: u64,
+}
+
+impl Record {
+    fn encode(&self, buf: &mut [u8]) {
+        buf[0 .. 8].copy_from_slice(&self.seq.to_le_bytes());
+        buf[8 .. 16].copy_from_slice(&self.lba.to_le_bytes());
+        buf[16 .. 24].copy_from_slice(&self.num_blocks.to_le_bytes());
+        buf[24 .. 32].iter_mut().for_each(|b| *b = 0);
+    }
+
+    fn decode(buf: &[u8]) -> Self {
+        Self {
+            seq: u64::from_le_bytes(buf[0 .. 8].try_into().unwrap()),
+            lba: u64::from_le_bytes(buf[8 .. 16].try_into().unwrap()),
+            num_blocks: u64::from_le_bytes(buf[16 .. 24].try_into().unwrap()),
+        }
+    }
+}
+
+/// The header of the journal of a child.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Header {
+    /// journal the header belongs to
+    generation: u64,
+    /// all writes up to this one have been applied to the child
+    stable: u64,
+    /// last write recorded in the ring of the child
+    last: u64,
+}
+
+impl Header {
+    fn encode(&self, buf: &mut [u8]) {
+        buf.iter_mut().for_each(|b| *b = 0);
+        buf[0 .. 8].copy_from_slice(&JOURNAL_MAGIC.to_le_bytes());
+        buf[8 .. 16].copy_from_slice(&self.generation.to_le_bytes());
+        buf[16 .. 24].copy_from_slice(&self.stable.to_le_bytes());
+        buf[24 .. 32].copy_from_slice(&self.last.to_le_bytes());
+        buf[32 .. 40].copy_from_slice(
+            &u64::from(Structure::Journal.current()).to_le_bytes(),
+        );
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        let field = |i: usize| {
+            u64::from_le_bytes(buf[i * 8 .. (i + 1) * 8].try_into().unwrap())
+        };
+        if field(0) != JOURNAL_MAGIC {
+            return None;
+        }
+        let version = field(4);
+        if version > u64::from(u32::MAX)
+            || !Structure::Journal.reads(version as u32)
+        {
+            warn!("journal header of version {} can not be read", version);
+            return None;
+        }
+        Some(Self {
+            generation: field(1),
+            stable: field(2),
+            last: field(3),
+        })
+    }
+}
+
+/// offset on the children of the page of the ring holding the record
+fn page_offset(seq: u64) -> u64 {
+    RING_OFFSET + (seq % RING_RECORDS) / RECORDS_PER_PAGE * JOURNAL_PAGE_SIZE
+}
+
+#[derive(Debug)]
+struct JournalState {
+    /// sequence number of the next write
+    next_seq: u64,
+    /// writes that have not completed yet
+    in_flight: BTreeSet<u64>,
+    /// records that have not been written to the children yet
+    pending: Vec<Record>,
+    /// records of the last page written, which is written again as it fills
+    tail: Option<(u64, Vec<Record>)>,
+    /// last write recorded in the pages written
+    written: u64,
+    /// stable sequence number in the headers written
+    stable: u64,
+}
+
+impl JournalState {
+    /// all writes up to the returned one have completed
+    fn stable(&self) -> u64 {
+        match self.in_flight.iter().next() {
+            Some(seq) => seq - 1,
+            None => self.next_seq - 1,
+        }
+    }
+}
+
+/// The write journal of a nexus, shared by all channels.
+#[derive(Debug)]
+pub(crate) struct WriteJournal {
+    /// identifies this journal in the headers
+    generation: u64,
+    state: Mutex<JournalState>,
+    /// devices of the children an IO failed on, whose header is no longer
+    /// updated
+    frozen: Mutex<HashSet<String>>,
+    /// serializes the writes to the children
+    writing: futures::lock::Mutex<()>,
+}
+
+impl WriteJournal {
+    pub(crate) fn new() -> Self {
+        Self {
+            generation: max(uuid::Uuid::new_v4().as_u128() as u64, 1),
+            state: Mutex::new(JournalState {
+                next_seq: 1,
+                in_flight: BTreeSet::new(),
+                pending: Vec::new(),
+                tail: None,
+                written: 0,
+                stable: 0,
+            }),
+            frozen: Mutex::new(HashSet::new()),
+            writing: futures::lock::Mutex::new(()),
+        }
+    }
+
+    /// Record a write of the given blocks that is being submitted, returning
+    /// its sequence number.
+    pub(crate) fn begin(&self, lba: u64, num_blocks: u64) -> u64 {
+        let mut state = self.state.lock();
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.in_flight.insert(seq);
+        state.pending.push(Record {
+            seq,
+            lba,
+            num_blocks,
+        });
+        seq
+    }
+
+    /// a write has completed, whether it succeeded or not
+    pub(crate) fn end(&self, seq: u64) {
+        self.state.lock().in_flight.remove(&seq);
+    }
+
+    /// Stop updating the header of the child on the device, which may have
+    /// missed a write. This must happen before the write completes.
+    pub(crate) fn freeze(&self, device: &str) {
+        self.frozen.lock().insert(device.to_string());
+    }
+
+    /// update the header of the child on the device again once it is rebuilt
+    pub(crate) fn thaw(&self, device: &str) {
+        self.frozen.lock().remove(device);
+    }
+
+    fn is_frozen(&self, device: &str) -> bool {
+        self.frozen.lock().contains(device)
+    }
+
+    /// Build the pages of the ring holding the pending records, returning
+    /// them with the header to write after them.
+    fn take_pages(&self) -> Option<(Vec<(u64, Vec<Record>)>, Header)> {
+        let mut state = self.state.lock();
+        let stable = state.stable();
+        let records = std::mem::take(&mut state.pending);
+        if records.is_empty() && stable == state.stable {
+            return None;
+        }
+
+        let mut pages: Vec<(u64, Vec<Record>)> = Vec::new();
+        for r in records {
+            let base = r.seq - r.seq % RECORDS_PER_PAGE;
+            if pages.last().map(|(b, _)| *b) != Some(base) {
+                let records = match state.tail.take() {
+                    Some((b, records)) if b == base => records,
+                    _ => vec![Record::default(); RECORDS_PER_PAGE as usize],
+                };
+                pages.push((base, records));
+            }
+            pages.last_mut().unwrap().1[(r.seq % RECORDS_PER_PAGE) as usize] =
+                r;
+            state.written = r.seq;
+        }
+        if let Some(page) = pages.last() {
+            state.tail = Some(page.clone());
+        }
+        state.stable = stable;
+
+        Some((
+            pages,
+            Header {
+                generation: self.generation,
+                stable,
+                last: state.written,
+            },
+        ))
+    }
+
+    /// Returns the handles of the healthy children whose header is updated,
+    /// with the names of their devices.
+    fn healthy_children(
+        &self,
+        nexus_name: &str,
+    ) -> Vec<(String, Box<dyn BlockDeviceHandle>)> {
+        let nexus = match nexus_lookup_mut(nexus_name) {
+            Some(nexus) => nexus,
+            None => return Vec::new(),
+        };
+        nexus
+            .children
+            .iter()
+            .filter(|c| c.state() == ChildState::Open)
+            .filter_map(|c| c.get_io_handle().ok())
+            .map(|h| (h.get_device().device_name(), h))
+            .filter(|(device, _)| !self.is_frozen(device))
+            .collect()
+    }
+
+    /// write the pending records and the headers to the healthy children
+    async fn write_locked(&self, nexus_name: &str) {
+        // the stable sequence number is taken before the state of the
+        // children is looked at, a child that is healthy by then has applied
+        // all the writes up to it
+        let (pages, header) = match self.take_pages() {
+            Some(update) => update,
+            None => return,
+        };
+
+        for (device, hdl) in self.healthy_children(nexus_name) {
+            if let Err(e) = write_journal(&*hdl, &pages, &header).await {
+                error!(
+                    "{}: failed to write the journal of {}: {}",
+                    nexus_name, device, e
+                );
+            }
+        }
+    }
+
+    /// write the pending records and the headers to the healthy children
+    pub(crate) async fn write_out(&self, nexus_name: &str) {
+        let _guard = self.writing.lock().await;
+        self.write_locked(nexus_name).await;
+    }
+
+    /// Returns the ranges of blocks of the nexus written since the given
+    /// child on the device last had a header updated, None when the child
+    /// must be rebuilt in full.
+    pub(crate) async fn resync_ranges(
+        &self,
+        nexus_name: &str,
+        child: &str,
+    ) -> Option<Vec<Range<u64>>> {
+        let _guard = self.writing.lock().await;
+        self.write_locked(nexus_name).await;
+        let written = self.state.lock().written;
+
+        let dst = nexus_lookup_mut(nexus_name)?
+            .children
+            .iter()
+            .find(|c| c.get_name() == child)?
+            .get_io_handle()
+            .ok()?;
+        let dst_device = dst.get_device().device_name();
+        let sources = self
+            .healthy_children(nexus_name)
+            .into_iter()
+            .filter(|(device, _)| *device != dst_device);
+
+        let header = read_header(&*dst).await?;
+        if header.generation != self.generation
+            || header.stable > written
+            || written - header.stable > RING_RECORDS - RECORDS_PER_PAGE
+        {
+            return None;
+        }
+
+        for (device, hdl) in sources {
+            match read_header(&*hdl).await {
+                Some(h)
+                    if h.generation == self.generation && h.last >= written => {
+                }
+                _ => continue,
+            }
+            match read_ranges(&*hdl, header.stable, written).await {
+                Some(ranges) => {
+                    info!(
+                        "{}: resyncing {} from the journal of {}, {} writes \
+                        since {}",
+                        nexus_name,
+                        child,
+                        device,
+                        ranges.len(),
+                        header.stable
+                    );
+                    return Some(ranges);
+                }
+                None => continue,
+            }
+        }
+        None
+    }
+}
+
+/// write the pages of the ring to a child, then its header
+async fn write_journal(
+    hdl: &dyn BlockDeviceHandle,
+    pages: &[(u64, Vec<Record>)],
+    header: &Header,
+) -> Result<(), String> {
+    let mut buf = hdl
+        .dma_malloc(JOURNAL_PAGE_SIZE)
+        .map_err(|e| e.to_string())?;
+
+    for (base, records) in pages {
+        for (r, chunk) in records
+            .iter()
+            .zip(buf.as_mut_slice().chunks_exact_mut(RECORD_SIZE as usize))
+        {
+            r.encode(chunk);
+        }
+        hdl.write_at(page_offset(*base), &buf)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    header.encode(buf.as_mut_slice());
+    hdl.write_at(METADATA_RESERVATION_OFFSET, &buf)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// read the header of the journal of a child
+async fn read_header(hdl: &dyn BlockDeviceHandle) -> Option<Header> {
+    let mut buf = hdl.dma_malloc(JOURNAL_PAGE_SIZE).ok()?;
+    hdl.read_at(METADATA_RESERVATION_OFFSET, &mut buf)
+        .await
+        .ok()?;
+    Header::decode(buf.as_slice())
+}
+
+/// Read the records of the writes after `since` up to `until` from the ring
+/// of a child, returning None when any of them is missing.
+async fn read_ranges(
+    hdl: &dyn BlockDeviceHandle,
+    since: u64,
+    until: u64,
+) -> Option<Vec<Range<u64>>> {
+    let mut buf = hdl.dma_malloc(JOURNAL_PAGE_SIZE).ok()?;
+    let mut page = None;
+    let mut ranges = Vec::new();
+
+    for seq in since + 1 ..= until {
+        let offset = page_offset(seq);
+        if page != Some(offset) {
+            hdl.read_at(offset, &mut buf).await.ok()?;
+            page = Some(offset);
+        }
+        let i = (seq % RECORDS_PER_PAGE * RECORD_SIZE) as usize;
+        let r = Record::decode(&buf.as_slice()[i .. i + RECORD_SIZE as usize]);
+        if r.seq != seq {
+            return None;
+        }
+        ranges.push(r.lba .. r.lba + r.num_blocks);
+    }
+    Some(ranges)
+}
+
+/// write the records to the children of the nexus until it is gone
+pub(crate) async fn journal_writeback(
+    nexus_name: String,
+    journal: Arc<WriteJournal>,
+) {
+    while Arc::strong_count(&journal) > 1 {
+        let _ =
+            mayastor_sleep(Duration::from_millis(JOURNAL_WRITEBACK_MS)).await;
+        journal.write_out(&nexus_name).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        let mut buf = vec![0xffu8; JOURNAL_PAGE_SIZE as usize];
+        let header = Header {
+            generation: 7,
+            stable: 41,
+            last: 44,
+        };
+        header.encode(&mut buf);
+        assert_eq!(Header::decode(&buf), Some(header));
+        assert_eq!(Header::decode(&[0u8; 32]), None);
+        buf[32 .. 40].copy_from_slice(&99u64.to_le_bytes());
+        assert_eq!(Header::decode(&buf), None);
+
+        let r = Record {
+            seq: 3,
+            lba: 1024,
+            num_blocks: 8,
+        };
+        r.encode(&mut buf[.. RECORD_SIZE as usize]);
+        assert_eq!(Record::decode(&buf), r);
+    }
+
+    #[test]
+    fn stable_sequence() {
+        let journal = WriteJournal::new();
+        let a = journal.begin(0, 8);
+        let b = journal.begin(8, 8);
+        let c = journal.begin(16, 8);
+        assert_eq!(journal.state.lock().stable(), 0);
+
+        journal.end(b);
+        journal.end(c);
+        assert_eq!(journal.state.lock().stable(), a - 1);
+        journal.end(a);
+        assert_eq!(journal.state.lock().stable(), c);
+
+        // the last page is kept to be written again with the next records
+        let (pages, header) = journal.take_pages().unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(header.stable, c);
+        assert_eq!(header.last, c);
+        assert!(journal.take_pages().is_none());
+
+        let d = journal.begin(24, 8);
+        journal.end(d);
+        let (pages, _) = journal.take_pages().unwrap();
+        assert_eq!(pages[0].1[a as usize].lba, 0);
+        assert_eq!(pages[0].1[d as usize].lba, 24);
+    }
+}
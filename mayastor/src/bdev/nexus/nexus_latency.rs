@@ -0,0 +1,532 @@
+//!
+//! Detection of the children of a nexus that are much slower than their
+//! siblings.
+//!
+//! A sick disk or a congested network path to a replica drags the latency of
+//! the whole volume down, as every write waits for the slowest child and the
+//! reads are spread over all children. When detection is on, the latency of
+//! the IOs of each child is kept in a histogram, and at the end of every
+//! window the p99 latency of each child is compared to the median p99 of its
+//! siblings. A child that is slower than its siblings by the configured
+//! factor for the configured number of windows in a row is reported as slow,
+//! and is no longer read from if the nexus is configured so; it keeps
+//! getting the writes, so its data stays in sync. It is reported as healthy
+//! again, and read from again, once it has been within the factor for as many
+//! windows. The reads are never taken away from the last child that has them.
+//!
+//! Latencies are kept with a power of two resolution, in microseconds, and
+//! windows in which a child completed too few IOs are not taken into
+//! account. Detection is set with the `nexus_set_slow_child_detection`
+//! json-rpc method and the latencies of the children, as well as the changes
+//! of their state, are reported by `nexus_child_latencies`.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+        Weak,
+    },
+    time::Duration,
+};
+
+use chrono::{SecondsFormat, Utc};
+use futures::FutureExt;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use spdk_rs::ChannelTraverseStatus;
+
+use super::{
+    nexus_lookup_any_mut,
+    nexus_lookup_mut,
+    DrEvent,
+    Error,
+    Nexus,
+    NexusChannel,
+};
+use crate::{
+    core::{for_each_channel, Reactors},
+    jsonrpc::jsonrpc_register,
+    sleep::mayastor_sleep,
+};
+
+/// number of buckets of a latency histogram, the last one holds all IOs
+/// slower than about half an hour
+const BUCKETS: usize = 32;
+
+/// minimum number of IOs of a child in a window for its latency to count
+const MIN_SAMPLES: u64 = 32;
+
+/// number of changes of the state of the children that are kept
+const MAX_EVENTS: usize = 64;
+
+/// Options of the detection of slow children.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlowChildOpts {
+    /// how many times the p99 latency of a child must exceed the median p99
+    /// latency of its siblings for it to be slow
+    pub factor: u32,
+    /// number of windows in a row a child must be slow, or healthy again,
+    /// for its state to change
+    pub windows: u32,
+    /// length of a window in milliseconds
+    pub window_ms: u64,
+    /// stop reading from the slow children
+    pub eject_reads: bool,
+}
+
+impl Default for SlowChildOpts {
+    fn default() -> Self {
+        Self {
+            factor: 4,
+            windows: 5,
+            window_ms: 1000,
+            eject_reads: false,
+        }
+    }
+}
+
+/// Latency of a child of a nexus over the last window.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChildLatency {
+    /// device name of the child
+    pub child: String,
+    /// p99 latency in microseconds, None if the child completed too few IOs
+    pub p99_us: Option<u64>,
+    /// number of IOs completed in the window
+    pub samples: u64,
+    /// number of windows in a row the state of the child is contradicted
+    pub streak: u32,
+    /// the child is slower than its siblings
+    pub slow: bool,
+    /// the child is no longer read from
+    pub ejected: bool,
+}
+
+/// A child found slow, or healthy again.
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowChildEvent {
+    pub child: String,
+    pub slow: bool,
+    /// p99 latency of the child in microseconds
+    pub p99_us: u64,
+    /// median p99 latency of its siblings in microseconds
+    pub siblings_p99_us: u64,
+    pub time: String,
+}
+
+/// State of the detection of slow children of a nexus.
+#[derive(Debug, Clone, Serialize)]
+pub struct SlowChildStatus {
+    pub opts: SlowChildOpts,
+    pub children: Vec<ChildLatency>,
+    /// the last changes of the state of the children, oldest first
+    pub events: Vec<SlowChildEvent>,
+}
+
+/// Histogram of the latencies of the IOs of a child, by power of two of
+/// microseconds.
+#[derive(Default)]
+struct LatencyHistogram {
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl LatencyHistogram {
+    fn record(&self, latency: Duration) {
+        let us = latency.as_micros() as u64;
+        let bucket = (64 - us.leading_zeros() as usize).min(BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the p99 latency in microseconds and the number of IOs since
+    /// the last call, starting over.
+    fn take_p99(&self) -> (Option<u64>, u64) {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.swap(0, Ordering::Relaxed))
+            .collect();
+        let samples = counts.iter().sum::<u64>();
+        if samples < MIN_SAMPLES {
+            return (None, samples);
+        }
+        let rank = (samples * 99 + 99) / 100;
+        let mut seen = 0;
+        for (bucket, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                // upper bound of the bucket
+                return (Some((1u64 << bucket) - 1), samples);
+            }
+        }
+        (None, samples)
+    }
+}
+
+/// Latencies of the children of a nexus, shared by all channels.
+pub(crate) struct ChildLatencies {
+    opts: SlowChildOpts,
+    histograms: RwLock<HashMap<String, LatencyHistogram>>,
+    children: Mutex<HashMap<String, ChildLatency>>,
+    events: Mutex<VecDeque<SlowChildEvent>>,
+}
+
+impl std::fmt::Debug for ChildLatencies {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChildLatencies")
+            .field("opts", &self.opts)
+            .finish()
+    }
+}
+
+impl ChildLatencies {
+    fn new(opts: SlowChildOpts) -> Self {
+        Self {
+            opts,
+            histograms: RwLock::new(HashMap::new()),
+            children: Mutex::new(HashMap::new()),
+            events: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// record the latency of an IO completed by the child on the device
+    pub(crate) fn record(&self, device: &str, latency: Duration) {
+        if let Some(h) = self.histograms.read().get(device) {
+            h.record(latency);
+            return;
+        }
+        self.histograms
+            .write()
+            .entry(device.to_string())
+            .or_default()
+            .record(latency);
+    }
+
+    /// device names of the children that are no longer read from
+    pub(crate) fn ejected(&self) -> HashSet<String> {
+        self.children
+            .lock()
+            .values()
+            .filter(|c| c.ejected)
+            .map(|c| c.child.clone())
+            .collect()
+    }
+
+    /// Compare the latencies of the children over the window that ended,
+    /// returning true if a child was ejected or is read from again.
+    fn evaluate(&self, nexus_name: &str) -> bool {
+        let window: Vec<(String, Option<u64>, u64)> = self
+            .histograms
+            .read()
+            .iter()
+            .map(|(name, h)| {
+                let (p99, samples) = h.take_p99();
+                (name.clone(), p99, samples)
+            })
+            .collect();
+
+        let mut children = self.children.lock();
+        let mut changed = false;
+
+        for (name, p99, samples) in &window {
+            let mut siblings: Vec<u64> = window
+                .iter()
+                .filter(|(n, ..)| n != name)
+                .filter_map(|(_, p, _)| *p)
+                .collect();
+
+            let child =
+                children
+                    .entry(name.clone())
+                    .or_insert_with(|| ChildLatency {
+                        child: name.clone(),
+                        ..Default::default()
+                    });
+            child.p99_us = *p99;
+            child.samples = *samples;
+
+            let p99 = match p99 {
+                Some(p99) if !siblings.is_empty() => *p99,
+                _ => continue,
+            };
+            siblings.sort_unstable();
+            let median = siblings[siblings.len() / 2];
+
+            let slow = p99 > median.max(1) * self.opts.factor as u64;
+            if slow == child.slow {
+                child.streak = 0;
+                continue;
+            }
+            child.streak += 1;
+            if child.streak < self.opts.windows.max(1) {
+                continue;
+            }
+
+            child.streak = 0;
+            child.slow = slow;
+            if slow {
+                warn!(
+                    "{}: child {} is slow, p99 latency {}us against {}us \
+                    for its siblings",
+                    nexus_name, name, p99, median
+                );
+            } else {
+                info!(
+                    "{}: child {} is no longer slow, p99 latency {}us \
+                    against {}us for its siblings",
+                    nexus_name, name, p99, median
+                );
+            }
+            if self.opts.eject_reads && child.ejected != slow {
+                child.ejected = slow;
+                changed = true;
+            }
+
+            let mut events = self.events.lock();
+            if events.len() == MAX_EVENTS {
+                events.pop_front();
+            }
+            events.push_back(SlowChildEvent {
+                child: name.clone(),
+                slow,
+                p99_us: p99,
+                siblings_p99_us: median,
+                time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            });
+        }
+        changed
+    }
+
+    fn status(&self) -> SlowChildStatus {
+        let mut children: Vec<ChildLatency> =
+            self.children.lock().values().cloned().collect();
+        children.sort_by(|a, b| a.child.cmp(&b.child));
+        SlowChildStatus {
+            opts: self.opts.clone(),
+            children,
+            events: self.events.lock().iter().cloned().collect(),
+        }
+    }
+}
+
+/// Context to install the detection of slow children on all channels.
+struct SetLatenciesCtx {
+    latencies: Option<Arc<ChildLatencies>>,
+}
+
+fn set_latencies_cb(
+    channel: &mut NexusChannel,
+    ctx: &mut SetLatenciesCtx,
+) -> ChannelTraverseStatus {
+    channel.inner_mut().latencies = ctx.latencies.clone();
+    ChannelTraverseStatus::Ok
+}
+
+/// Compare the latencies of the children at the end of every window until
+/// detection is turned off, refreshing the readers of the channels when a
+/// child is ejected or read from again.
+async fn detect_loop(nexus_name: String, latencies: Weak<ChildLatencies>) {
+    loop {
+        let window = match latencies.upgrade() {
+            Some(l) => Duration::from_millis(l.opts.window_ms.max(1)),
+            None => return,
+        };
+        let _ = mayastor_sleep(window).await;
+
+        let latencies = match latencies.upgrade() {
+            Some(latencies) => latencies,
+            None => return,
+        };
+        let nexus = match nexus_lookup_mut(&nexus_name) {
+            Some(nexus) => nexus,
+            None => return,
+        };
+        if !nexus
+            .child_latencies
+            .lock()
+            .as_ref()
+            .map_or(false, |l| Arc::ptr_eq(l, &latencies))
+        {
+            return;
+        }
+        if latencies.evaluate(&nexus_name) {
+            nexus.reconfigure(DrEvent::ChildSlow).await;
+        }
+    }
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the detection of slow children of this nexus, for newly
+    /// created channels
+    pub(crate) fn child_latencies(&self) -> Option<Arc<ChildLatencies>> {
+        self.child_latencies.lock().clone()
+    }
+
+    /// returns the latencies of the children and the changes of their state,
+    /// None if detection is off
+    pub fn slow_child_status(&self) -> Option<SlowChildStatus> {
+        self.child_latencies.lock().as_ref().map(|l| l.status())
+    }
+
+    /// install the detection on all channels
+    async fn install_latencies(&self, latencies: Option<Arc<ChildLatencies>>) {
+        if self.has_io_device {
+            let r = for_each_channel(
+                self,
+                SetLatenciesCtx {
+                    latencies,
+                },
+                set_latencies_cb,
+            );
+            r.await.expect("set latencies sender already dropped");
+        }
+    }
+
+    /// Detect the children that are slower than their siblings with the
+    /// options, replacing the current detection. None turns detection off,
+    /// and the ejected children are read from again.
+    pub async fn set_slow_child_detection(
+        &self,
+        opts: Option<SlowChildOpts>,
+    ) -> Result<(), Error> {
+        if let Some(opts) = &opts {
+            if opts.factor < 2 {
+                return Err(Error::InvalidArguments {
+                    name: self.name.clone(),
+                    args: format!(
+                        "the slow child factor must be at least 2, not {}",
+                        opts.factor
+                    ),
+                });
+            }
+        }
+
+        let latencies = opts.map(|opts| {
+            info!("{}: detecting slow children {:?}", self.name, opts);
+            Arc::new(ChildLatencies::new(opts))
+        });
+        let previous = std::mem::replace(
+            &mut *self.child_latencies.lock(),
+            latencies.clone(),
+        );
+        self.install_latencies(latencies.clone()).await;
+
+        if previous.map_or(false, |p| !p.ejected().is_empty()) {
+            self.reconfigure(DrEvent::ChildSlow).await;
+        }
+        if let Some(latencies) = latencies {
+            Reactors::master().send_future(detect_loop(
+                self.name.clone(),
+                Arc::downgrade(&latencies),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetDetectionArgs {
+    /// name or uuid of the nexus
+    name: String,
+    /// the detection options, omit to turn detection off
+    #[serde(default)]
+    detection: Option<SlowChildOpts>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LatenciesArgs {
+    /// name or uuid of the nexus
+    name: String,
+}
+
+async fn set_detection(
+    args: SetDetectionArgs,
+) -> Result<Option<SlowChildStatus>, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.set_slow_child_detection(args.detection).await?;
+    Ok(nexus.slow_child_status())
+}
+
+async fn latencies(
+    args: LatenciesArgs,
+) -> Result<Option<SlowChildStatus>, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    Ok(nexus.slow_child_status())
+}
+
+/// Register the json-rpc methods to detect slow children and to report on
+/// their latencies.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_set_slow_child_detection",
+        |args: SetDetectionArgs| set_detection(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_child_latencies",
+        |args: LatenciesArgs| latencies(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(latencies: &ChildLatencies, slow_us: u64) {
+        for _ in 0 .. MIN_SAMPLES {
+            latencies.record("a", Duration::from_micros(100));
+            latencies.record("b", Duration::from_micros(120));
+            latencies.record("c", Duration::from_micros(slow_us));
+        }
+    }
+
+    #[test]
+    fn slow_child() {
+        let latencies = ChildLatencies::new(SlowChildOpts {
+            windows: 2,
+            eject_reads: true,
+            ..Default::default()
+        });
+
+        // a single slow window is not enough
+        window(&latencies, 10_000);
+        assert!(!latencies.evaluate("n"));
+        window(&latencies, 100);
+        assert!(!latencies.evaluate("n"));
+        window(&latencies, 10_000);
+        assert!(!latencies.evaluate("n"));
+
+        window(&latencies, 10_000);
+        assert!(latencies.evaluate("n"));
+        assert_eq!(
+            latencies.ejected(),
+            vec!["c".to_string()].into_iter().collect::<HashSet<_>>()
+        );
+        let status = latencies.status();
+        assert!(status.children[2].slow);
+        assert_eq!(status.children[2].p99_us, Some(16383));
+        assert_eq!(status.children[0].p99_us, Some(127));
+        assert_eq!(status.events.len(), 1);
+
+        // too few IOs do not count
+        latencies.record("c", Duration::from_micros(100));
+        assert!(!latencies.evaluate("n"));
+        assert_eq!(latencies.status().children[2].p99_us, None);
+
+        window(&latencies, 150);
+        assert!(!latencies.evaluate("n"));
+        window(&latencies, 150);
+        assert!(latencies.evaluate("n"));
+        assert!(latencies.ejected().is_empty());
+        assert_eq!(latencies.status().events.len(), 2);
+    }
+}
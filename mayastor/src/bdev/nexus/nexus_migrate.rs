@@ -0,0 +1,250 @@
+//!
+//! Primitives to move a published nexus from one node to another without
+//! taking the volume offline.
+//!
+//! The migration is driven by the control plane, which calls the steps below
+//! on the source and destination nodes in order:
+//!
+//! 1. `prepare` (destination): create a nexus with the same name and uuid over
+//!    the same replicas, share it over NVMf and mark the path inaccessible.
+//!    Because the nexus name is the same on both nodes so is the NQN, and the
+//!    initiator sees the new controller as an additional path.
+//! 2. `switchover` (source, then destination): the source path is made
+//!    inaccessible, which drains and stops its IO, after which the destination
+//!    path is made optimized and the initiator moves its IO over.
+//! 3. `finalize` (source): the source nexus is unshared and destroyed.
+//!
+//! The replicas are opened by both nexuses in between `prepare` and
+//! `finalize`. Write exclusive (all registrants) reservations allow this, as
+//! the destination registers its key but does not preempt the source. The
+//! destination does not claim the persistent entry of the nexus when it is
+//! created either, which would fence the source, but when the switchover
+//! makes it optimized, by which time the IO of the source has stopped.
+
+use std::pin::Pin;
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    defer_epoch_claim,
+    nexus_create_v2,
+    nexus_lookup_any_mut,
+    take_deferred_claim,
+    Error,
+    Nexus,
+    NexusNvmeParams,
+    NvmeAnaState,
+};
+
+use crate::{
+    core::{Protocol, Share},
+    jsonrpc::jsonrpc_register,
+    persistent_store::PersistentStore,
+};
+
+/// Role a node plays in the switchover step of a migration.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationRole {
+    /// the nexus IO is migrated away from
+    Source,
+    /// the nexus IO is migrated to
+    Destination,
+}
+
+/// Arguments to prepare the destination nexus of a migration.
+#[derive(Debug, Deserialize)]
+struct MigratePrepareArgs {
+    /// name of the nexus, must match the source nexus
+    name: String,
+    /// uuid of the nexus, must match the source nexus
+    uuid: String,
+    /// size of the nexus in bytes
+    size: u64,
+    /// replica URIs used by the source nexus
+    children: Vec<String>,
+    /// NVMe controller ID range, must not overlap with the source
+    min_cntlid: u16,
+    max_cntlid: u16,
+    /// NVMe reservation key of the destination nexus
+    resv_key: u64,
+}
+
+/// Arguments for the switchover and finalize steps of a migration.
+#[derive(Debug, Deserialize)]
+struct MigrateArgs {
+    /// name of the nexus
+    name: String,
+    /// role of this node, only used by switchover
+    #[serde(default)]
+    role: Option<MigrationRole>,
+}
+
+/// Reply of the prepare step.
+#[derive(Debug, Serialize)]
+struct MigratePrepareReply {
+    /// URI under which the destination nexus is shared
+    uri: String,
+}
+
+impl<'n> Nexus<'n> {
+    /// Switch the NVMf path of this nexus to the given migration role. The
+    /// source path becomes inaccessible, the destination path optimized.
+    /// Setting the ANA state pauses the subsystem, so in-flight IO to the
+    /// source path has completed when this returns. The destination claims
+    /// the persistent entry of the nexus before its path is optimized.
+    pub async fn migrate_switchover(
+        &self,
+        role: MigrationRole,
+    ) -> Result<(), Error> {
+        if self.shared() != Some(Protocol::Nvmf) {
+            return Err(Error::NotSharedNvmf {
+                name: self.name.clone(),
+            });
+        }
+
+        let ana_state = match role {
+            MigrationRole::Source => NvmeAnaState::InaccessibleState,
+            MigrationRole::Destination => NvmeAnaState::OptimizedState,
+        };
+
+        info!(
+            "{}: migration switchover as {:?}, setting ANA state {:?}",
+            self.name, role, ana_state
+        );
+        if role == MigrationRole::Destination {
+            self.claim_epoch().await;
+        }
+        self.set_ana_state(ana_state).await
+    }
+
+    /// Finish the migration on the source node: the nexus must have been
+    /// switched away from, after which it is unshared and destroyed.
+    pub async fn migrate_finalize(
+        mut self: Pin<&mut Self>,
+    ) -> Result<(), Error> {
+        if self.shared() == Some(Protocol::Nvmf)
+            && self.get_ana_state().await? != NvmeAnaState::InaccessibleState
+        {
+            return Err(Error::InvalidArguments {
+                name: self.name.clone(),
+                args: "nexus path is still accessible, switchover first"
+                    .to_string(),
+            });
+        }
+
+        info!("{}: finalizing migration", self.name);
+        self.as_mut().unshare_nexus().await?;
+        self.destroy().await
+    }
+}
+
+/// Share the destination nexus of a migration over NVMf with an inaccessible
+/// ANA state, returning the URI it is shared under.
+async fn share_inaccessible(
+    mut nexus: Pin<&mut Nexus<'_>>,
+) -> Result<String, Error> {
+    let uri = nexus.as_mut().share(Protocol::Nvmf, None).await?;
+    nexus.set_ana_state(NvmeAnaState::InaccessibleState).await?;
+    Ok(uri)
+}
+
+/// Create the destination nexus of a migration over the replicas of the
+/// source nexus. The nexus is shared over NVMf with an inaccessible ANA state
+/// so that initiators can connect the path without sending IO to it yet. The
+/// nexus is destroyed again if it can not be shared so.
+pub async fn nexus_migrate_prepare(
+    name: &str,
+    uuid: &str,
+    size: u64,
+    nvme_params: NexusNvmeParams,
+    children: &[String],
+) -> Result<String, Error> {
+    if PersistentStore::enabled() {
+        defer_epoch_claim(name);
+    }
+    let created =
+        nexus_create_v2(name, size, uuid, nvme_params, children, None).await;
+    take_deferred_claim(name);
+    created?;
+
+    let mut nexus = lookup(name)?;
+
+    match share_inaccessible(nexus.as_mut()).await {
+        Ok(uri) => {
+            info!("{}: prepared as migration destination under {}", name, uri);
+            Ok(uri)
+        }
+        Err(e) => {
+            error!(
+                "{}: failed to share the migration destination: {}",
+                name, e
+            );
+            if let Err(e) = nexus.destroy().await {
+                error!(
+                    "{}: failed to destroy the migration destination: {}",
+                    name, e
+                );
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Look up a nexus by name for one of the migration json-rpc methods.
+fn lookup<'n>(name: &str) -> Result<Pin<&'n mut Nexus<'n>>, Error> {
+    nexus_lookup_any_mut(name).ok_or_else(|| Error::NexusNotFound {
+        name: name.to_string(),
+    })
+}
+
+async fn migrate_prepare(
+    args: MigratePrepareArgs,
+) -> Result<MigratePrepareReply, Error> {
+    let mut params = NexusNvmeParams::default();
+    params.set_min_cntlid(args.min_cntlid);
+    params.set_max_cntlid(args.max_cntlid);
+    params.set_resv_key(args.resv_key);
+
+    let uri = nexus_migrate_prepare(
+        &args.name,
+        &args.uuid,
+        args.size,
+        params,
+        &args.children,
+    )
+    .await?;
+
+    Ok(MigratePrepareReply {
+        uri,
+    })
+}
+
+async fn migrate_switchover(args: MigrateArgs) -> Result<(), Error> {
+    let role = args.role.ok_or_else(|| Error::InvalidArguments {
+        name: args.name.clone(),
+        args: "missing migration role".to_string(),
+    })?;
+    lookup(&args.name)?.migrate_switchover(role).await
+}
+
+async fn migrate_finalize(args: MigrateArgs) -> Result<(), Error> {
+    lookup(&args.name)?.migrate_finalize().await
+}
+
+/// Register the json-rpc methods to drive a nexus migration.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_migrate_prepare",
+        |args: MigratePrepareArgs| migrate_prepare(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_migrate_switchover",
+        |args: MigrateArgs| migrate_switchover(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_migrate_finalize",
+        |args: MigrateArgs| migrate_finalize(args).boxed_local(),
+    );
+}
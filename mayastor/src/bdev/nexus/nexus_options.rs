@@ -0,0 +1,262 @@
+//!
+//! Options of a nexus that can be changed while it runs.
+//!
+//! Changing how a nexus reads, retries or is limited used to mean destroying
+//! it and creating it again, which interrupts the IO of its initiators. The
+//! `nexus_update_options` json-rpc method changes the options given to it
+//! and keeps the others:
+//!
+//! - the read policy, spreading the reads over all children round robin or
+//!   reading from the first readable child, e.g. a local replica, or reading
+//!   from the child with the lowest latency
+//! - the depth and timeout of the queue of IO deferred while a channel has no
+//!   children, which default to the nexus options of the configuration
+//! - the number of IOs held back on a channel before new IO is pushed back to
+//!   the initiator
+//! - the maximum number of IOs outstanding on the nexus
+//! - the size of the extents read ahead of sequential reads, and the number of
+//!   sequential reads before reading ahead
+//! - the QoS limits, as set by `nexus_set_qos`
+//! - the detection of slow children, as set by `nexus_set_slow_child_detection`
+//! - the direct mode, submitting the IO of a nexus with a single local child
+//!   straight to it
+//! - the SGL pass-through mode, bouncing only the IO a child would not take
+//!
+//! The options of the IO path are copied to every channel with a
+//! reconfiguration of the channels, so that the submission path does not
+//! take a lock to read them. The options of a nexus are reported by
+//! `nexus_options`.
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    nexus_lookup_any_mut,
+    DrEvent,
+    Error,
+    Nexus,
+    NexusQos,
+    SlowChildOpts,
+};
+use crate::{jsonrpc::jsonrpc_register, subsys::Config};
+
+/// How the reads of a nexus are spread over its children.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadPolicy {
+    /// read from each child in turn
+    RoundRobin,
+    /// read from the first child that can be read from, in the order of the
+    /// children of the nexus
+    Preferred,
+    /// read from the child with the lowest latency for the size of the read
+    LowestLatency,
+}
+
+impl Default for ReadPolicy {
+    fn default() -> Self {
+        Self::RoundRobin
+    }
+}
+
+/// Options of the IO path of a nexus, copied to every channel.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct NexusIoOpts {
+    pub read_policy: ReadPolicy,
+    /// maximum number of IOs per channel deferred while the channel has no
+    /// children, 0 fails such IO right away
+    pub io_retry_queue_depth: u32,
+    /// time in milliseconds after which deferred IO is failed
+    pub io_retry_timeout_ms: u64,
+    /// maximum number of IOs per channel held back before new IO is pushed
+    /// back, 0 never pushes back
+    pub io_backpressure_depth: u32,
+    /// maximum number of IOs outstanding on the nexus, over all channels, 0
+    /// does not limit it
+    pub max_io_outstanding: u32,
+    /// size in KiB of the extents read ahead of sequential reads, 0 does not
+    /// read ahead
+    pub read_ahead_kb: u32,
+    /// number of sequential reads in a row before reading ahead
+    pub read_ahead_trigger: u32,
+    /// submit IO straight to the only child of a channel when it is local
+    pub direct: bool,
+    /// submit the scatter gather lists of the initiators to the children
+    /// that take them, rather than bouncing all reads and writes
+    pub sgl_passthrough: bool,
+}
+
+impl Default for NexusIoOpts {
+    fn default() -> Self {
+        let opts = &Config::get().nexus_opts;
+        Self {
+            read_policy: ReadPolicy::default(),
+            io_retry_queue_depth: opts.io_retry_queue_depth,
+            io_retry_timeout_ms: opts.io_retry_timeout_ms,
+            io_backpressure_depth: opts.io_backpressure_depth,
+            max_io_outstanding: opts.max_io_outstanding,
+            read_ahead_kb: opts.read_ahead_kb,
+            read_ahead_trigger: opts.read_ahead_trigger,
+            direct: false,
+            sgl_passthrough: true,
+        }
+    }
+}
+
+/// Changes to the options of a nexus, the options not given are kept.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NexusOptionsUpdate {
+    pub read_policy: Option<ReadPolicy>,
+    pub io_retry_queue_depth: Option<u32>,
+    pub io_retry_timeout_ms: Option<u64>,
+    pub io_backpressure_depth: Option<u32>,
+    pub max_io_outstanding: Option<u32>,
+    pub read_ahead_kb: Option<u32>,
+    pub read_ahead_trigger: Option<u32>,
+    pub direct: Option<bool>,
+    pub sgl_passthrough: Option<bool>,
+    pub qos: Option<NexusQos>,
+    pub slow_child_detection: Option<SlowChildOpts>,
+    /// turn the detection of slow children off
+    pub no_slow_child_detection: bool,
+}
+
+/// The options of a nexus.
+#[derive(Debug, Clone, Serialize)]
+pub struct NexusOptions {
+    #[serde(flatten)]
+    pub io: NexusIoOpts,
+    pub qos: NexusQos,
+    /// None if slow children are not detected
+    pub slow_child_detection: Option<SlowChildOpts>,
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the options of the IO path of this nexus, for newly created
+    /// and refreshed channels
+    pub fn io_opts(&self) -> NexusIoOpts {
+        *self.io_opts.lock()
+    }
+
+    /// returns the options of this nexus
+    pub fn options(&self) -> NexusOptions {
+        NexusOptions {
+            io: self.io_opts(),
+            qos: self.qos(),
+            slow_child_detection: self.slow_child_status().map(|s| s.opts),
+        }
+    }
+
+    /// Change the options of this nexus given by the update, keeping the
+    /// others. The options are changed in turn, so when changing one fails
+    /// the ones changed before are kept.
+    pub async fn update_options(
+        &self,
+        update: NexusOptionsUpdate,
+    ) -> Result<NexusOptions, Error> {
+        if update.no_slow_child_detection
+            && update.slow_child_detection.is_some()
+        {
+            return Err(Error::InvalidArguments {
+                name: self.name.clone(),
+                args: "slow child detection both set and turned off"
+                    .to_string(),
+            });
+        }
+        info!("{}: updating the options {:?}", self.name, update);
+
+        if let Some(qos) = update.qos {
+            self.set_qos(qos).await?;
+        }
+        if update.no_slow_child_detection {
+            self.set_slow_child_detection(None).await?;
+        } else if let Some(detection) = update.slow_child_detection {
+            self.set_slow_child_detection(Some(detection)).await?;
+        }
+
+        let previous = self.io_opts();
+        let mut io = previous;
+        if let Some(policy) = update.read_policy {
+            io.read_policy = policy;
+        }
+        if let Some(depth) = update.io_retry_queue_depth {
+            io.io_retry_queue_depth = depth;
+        }
+        if let Some(timeout) = update.io_retry_timeout_ms {
+            io.io_retry_timeout_ms = timeout;
+        }
+        if let Some(depth) = update.io_backpressure_depth {
+            io.io_backpressure_depth = depth;
+        }
+        if let Some(max) = update.max_io_outstanding {
+            io.max_io_outstanding = max;
+        }
+        if let Some(kb) = update.read_ahead_kb {
+            io.read_ahead_kb = kb;
+        }
+        if let Some(trigger) = update.read_ahead_trigger {
+            io.read_ahead_trigger = trigger;
+        }
+        if let Some(direct) = update.direct {
+            io.direct = direct;
+        }
+        if let Some(passthrough) = update.sgl_passthrough {
+            io.sgl_passthrough = passthrough;
+        }
+        if io != previous {
+            *self.io_opts.lock() = io;
+            if self.has_io_device {
+                self.reconfigure(DrEvent::Options).await;
+            }
+        }
+
+        Ok(self.options())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateOptionsArgs {
+    /// name or uuid of the nexus
+    name: String,
+    #[serde(flatten)]
+    update: NexusOptionsUpdate,
+}
+
+#[derive(Debug, Deserialize)]
+struct OptionsArgs {
+    /// name or uuid of the nexus
+    name: String,
+}
+
+async fn update_options(
+    args: UpdateOptionsArgs,
+) -> Result<NexusOptions, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    nexus.update_options(args.update).await
+}
+
+async fn options(args: OptionsArgs) -> Result<NexusOptions, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    Ok(nexus.options())
+}
+
+/// Register the json-rpc methods to change and report the options of a
+/// nexus.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_update_options",
+        |args: UpdateOptionsArgs| update_options(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>("nexus_options", |args: OptionsArgs| {
+        options(args).boxed_local()
+    });
+}
@@ -1,4 +1,4 @@
-use super::{ChildState, Nexus, NexusChild};
+use super::{take_deferred_claim, ChildState, Nexus, NexusChild};
 use crate::{persistent_store::PersistentStore, sleep::mayastor_sleep};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
@@ -27,6 +27,20 @@ impl PersistentNexusInfo {
     fn inner_mut(&mut self) -> &mut NexusInfo {
         &mut self.inner
     }
+
+    /// Get the epoch the nexus claimed.
+    pub(super) fn epoch(&self) -> u64 {
+        self.inner.epoch
+    }
+
+    /// Key under which the NexusInfo structure is persisted. If a key has been
+    /// provided use this to store the NexusInfo, otherwise use the nexus uuid.
+    pub(super) fn store_key(&self, nexus: &Nexus) -> String {
+        match &self.key {
+            Some(k) => k.clone(),
+            None => nexus.uuid().to_string(),
+        }
+    }
 }
 
 /// Definition of the nexus information that gets saved in the persistent
@@ -35,6 +49,10 @@ impl PersistentNexusInfo {
 pub struct NexusInfo {
     /// Nexus destroyed successfully.
     pub clean_shutdown: bool,
+    /// Epoch of the nexus owning this entry. Every nexus created for the
+    /// volume claims a higher epoch than the previous one.
+    #[serde(default)]
+    pub epoch: u64,
     /// Information about children.
     pub children: Vec<ChildInfo>,
 }
@@ -74,6 +92,30 @@ impl<'n> Nexus<'n> {
         }
 
         let mut persistent_nexus_info = self.nexus_info.lock().await;
+        let key = persistent_nexus_info.store_key(self);
+
+        // The entry belongs to another nexus until this one claims it.
+        if !matches!(op, PersistOp::Create) && self.epoch_lease.is_deferred() {
+            debug!(
+                "{}: entry not claimed yet, not persisting nexus information",
+                self.name
+            );
+            return;
+        }
+
+        // A fenced nexus no longer owns the persistent entry.
+        if !matches!(op, PersistOp::Create)
+            && self
+                .check_epoch(&key, persistent_nexus_info.inner.epoch)
+                .await
+        {
+            warn!(
+                "{}: nexus is fenced, not persisting nexus information",
+                self.name
+            );
+            return;
+        }
+
         let mut nexus_info = persistent_nexus_info.inner_mut();
 
         match op {
@@ -84,14 +126,23 @@ impl<'n> Nexus<'n> {
                 // expect the NexusInfo structure to contain default values.
                 assert!(nexus_info.children.is_empty());
                 assert!(!nexus_info.clean_shutdown);
-                self.children.iter().for_each(|c| {
-                    let child_info = ChildInfo {
-                        uuid: NexusChild::uuid(&c.name)
-                            .expect("Failed to get child UUID."),
-                        healthy: Self::child_healthy(&c.state()),
-                    };
-                    nexus_info.children.push(child_info);
-                });
+                // The destination of a migration claims the entry when the
+                // migration switches over to it.
+                if take_deferred_claim(&self.name) {
+                    info!(
+                        "{}: not claiming the persistent entry until the \
+                        migration switches over",
+                        self.name
+                    );
+                    self.epoch_lease.defer();
+                    return;
+                }
+                // Claim a newer epoch than any previous nexus of the volume,
+                // which fences that nexus.
+                let stored = Self::stored_epoch(&key).await;
+                nexus_info.epoch = stored.map_or(0, |e| e + 1);
+                nexus_info.children = self.children_info();
+                self.epoch_lease.claim(stored.is_some());
             }
             PersistOp::AddChild((uri, state)) => {
                 // Add the state of a new child.
@@ -141,6 +192,38 @@ impl<'n> Nexus<'n> {
         self.save(&persistent_nexus_info).await;
     }
 
+    /// Claim the persistent entry for a nexus created without claiming it,
+    /// with a newer epoch than the nexus it belonged to, which fences that
+    /// nexus.
+    pub(crate) async fn claim_epoch(&self) {
+        if !PersistentStore::enabled() || !self.epoch_lease.is_deferred() {
+            return;
+        }
+
+        let mut persistent_nexus_info = self.nexus_info.lock().await;
+        let key = persistent_nexus_info.store_key(self);
+        let stored = Self::stored_epoch(&key).await;
+        let nexus_info = persistent_nexus_info.inner_mut();
+        nexus_info.epoch = stored.map_or(0, |e| e + 1);
+        nexus_info.clean_shutdown = false;
+        nexus_info.children = self.children_info();
+        info!("{}: claimed epoch {}", self.name, nexus_info.epoch);
+        self.epoch_lease.claim(stored.is_some());
+        self.save(&persistent_nexus_info).await;
+    }
+
+    /// Returns the information of the children to persist.
+    fn children_info(&self) -> Vec<ChildInfo> {
+        self.children
+            .iter()
+            .map(|c| ChildInfo {
+                uuid: NexusChild::uuid(&c.name)
+                    .expect("Failed to get child UUID."),
+                healthy: Self::child_healthy(&c.state()),
+            })
+            .collect()
+    }
+
     /// Determine child health.
     fn child_healthy(state: &ChildState) -> bool {
         state == &ChildState::Open
@@ -153,12 +236,7 @@ impl<'n> Nexus<'n> {
     async fn save(&self, info: &PersistentNexusInfo) {
         let mut output_err = true;
         let nexus_uuid = self.uuid().to_string();
-        // If a key has been provided use this to store the NexusInfo.
-        // If a key is not provided, use the nexus uuid as the key.
-        let key = match &info.key {
-            Some(k) => k.clone(),
-            None => self.uuid().to_string(),
-        };
+        let key = info.store_key(self);
 
         loop {
             match PersistentStore::put(&key, &info.inner).await {
@@ -0,0 +1,106 @@
+//!
+//! Delete protection of a nexus.
+//!
+//! A protected nexus can only be destroyed with force, so that a control
+//! plane or a script destroying the wrong volume by mistake gets an error
+//! instead of taking a production volume down. The destroy gRPC methods have
+//! no way to pass force, they always fail for a protected nexus: the
+//! protection must be lifted first, or the nexus destroyed with the
+//! `nexus_destroy` json-rpc method and force set.
+//!
+//! The protection is kept in memory, a nexus that is created again after a
+//! restart is not protected until it is set again.
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use super::{nexus_lookup_any_mut, Error, Nexus};
+use crate::jsonrpc::jsonrpc_register;
+
+impl<'n> Nexus<'n> {
+    /// Returns true if the nexus can only be destroyed with force.
+    pub fn is_protected(&self) -> bool {
+        self.protected.load()
+    }
+
+    /// Protect the nexus against being destroyed, or lift the protection.
+    pub fn set_protected(&self, protected: bool) {
+        if self.protected.swap(protected) != protected {
+            info!(
+                "{}: delete protection {}",
+                self.name,
+                if protected { "set" } else { "lifted" }
+            );
+        }
+    }
+
+    /// Returns an error if the nexus is protected and may not be destroyed
+    /// without force.
+    pub fn check_protected(&self, force: bool) -> Result<(), Error> {
+        if self.is_protected() {
+            if !force {
+                return Err(Error::Protected {
+                    name: self.name.clone(),
+                });
+            }
+            warn!("{}: destroying protected nexus with force", self.name);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ProtectArgs {
+    /// name or uuid of the nexus
+    name: String,
+    protected: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct DestroyArgs {
+    /// name or uuid of the nexus
+    name: String,
+    /// destroy the nexus even if it is protected
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ProtectReply {
+    protected: bool,
+}
+
+async fn set_protected(args: ProtectArgs) -> Result<ProtectReply, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.set_protected(args.protected);
+    Ok(ProtectReply {
+        protected: nexus.is_protected(),
+    })
+}
+
+async fn destroy(args: DestroyArgs) -> Result<(), Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.check_protected(args.force)?;
+    nexus.destroy().await
+}
+
+/// Register the json-rpc methods to protect a nexus against deletion.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_set_protected",
+        |args: ProtectArgs| set_protected(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>("nexus_destroy", |args: DestroyArgs| {
+        destroy(args).boxed_local()
+    });
+}
@@ -0,0 +1,597 @@
+//!
+//! Per nexus IOPS and bandwidth limits.
+//!
+//! The limits are enforced by token buckets which are shared by all channels
+//! of a nexus, so they apply to the nexus as a whole no matter on how many
+//! cores the IO is submitted. Read and write IO that does not fit in the
+//! buckets is held back in a per channel queue, which a poller on the
+//! channel's thread drains as tokens are added again. The size of the buckets
+//! determines how long a nexus that has been idle can burst above the limits.
+//!
+//! The limits can be changed at any time. Changing them replaces the buckets
+//! on every channel; held back IO is submitted under the new limits.
+//!
+//! Nexuses that share backing storage can be put in a QoS group, which
+//! divides the capacity of the storage among its members by weight. Each
+//! member is guaranteed its share, and IO beyond the share is admitted as
+//! long as the group as a whole has capacity left, so the bandwidth of idle
+//! members is not wasted. Shares are recalculated whenever a member joins or
+//! leaves the group or the group capacity changes.
+
+use std::{
+    cmp::{max, min},
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+        Mutex,
+    },
+    time::Instant,
+};
+
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use spdk_rs::{libspdk::spdk_bdev_io, ChannelTraverseStatus};
+
+use super::{
+    nexus_iter,
+    nexus_lookup_any_mut,
+    nexus_lookup_mut,
+    nexus_resubmit_request,
+    Error,
+    Nexus,
+    NexusChannel,
+};
+
+use crate::{
+    core::{for_each_channel, poller},
+    jsonrpc::jsonrpc_register,
+};
+
+/// Interval in usec at which held back IO is resubmitted.
+const QOS_POLL_INTERVAL_US: u64 = 1000;
+
+/// Burst window used when none is configured.
+const QOS_DEFAULT_BURST_MS: u64 = 100;
+
+/// QoS limits of a nexus, a limit of 0 means unlimited.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NexusQos {
+    /// maximum number of read and write IOs per second
+    pub iops: u64,
+    /// maximum read and write bandwidth in bytes per second
+    pub bandwidth: u64,
+    /// time window in ms worth of IO the nexus may burst above the limits
+    /// after being idle, 0 selects the default
+    pub burst_ms: u64,
+    /// QoS group the nexus is a member of
+    pub group: Option<String>,
+    /// weight of the nexus within its group, 0 selects the default of 1
+    pub weight: u32,
+}
+
+impl NexusQos {
+    /// returns true if no limit is set and the nexus is not in a group
+    pub fn is_unlimited(&self) -> bool {
+        self.iops == 0 && self.bandwidth == 0 && self.group.is_none()
+    }
+
+    /// returns the weight of the nexus within its group
+    fn weight(&self) -> u64 {
+        max(self.weight, 1) as u64
+    }
+}
+
+/// Capacity of the storage shared by the nexuses of a QoS group, a capacity
+/// of 0 is not divided.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QosGroup {
+    /// read and write IOs per second the storage can sustain
+    pub iops: u64,
+    /// read and write bandwidth in bytes per second the storage can sustain
+    pub bandwidth: u64,
+}
+
+/// A QoS group and the buckets holding its capacity.
+struct QosGroupState {
+    group: QosGroup,
+    buckets: Arc<QosBuckets>,
+}
+
+/// All QoS groups by name.
+static QOS_GROUPS: Lazy<Mutex<HashMap<String, QosGroupState>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static QOS_EPOCH: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// monotonic time in ns
+fn now_ns() -> u64 {
+    QOS_EPOCH.elapsed().as_nanos() as u64
+}
+
+/// Token bucket that is shared between the cores.
+#[derive(Debug)]
+struct TokenBucket {
+    /// tokens added per second
+    rate: u64,
+    /// maximum number of tokens
+    size: i64,
+    /// tokens available, negative when an IO larger than the available
+    /// tokens has been admitted
+    tokens: AtomicI64,
+    /// time in ns up to which tokens have been added
+    refilled: AtomicU64,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, burst_ms: u64) -> Self {
+        let size = max(rate * burst_ms / 1000, 1) as i64;
+        Self {
+            rate,
+            size,
+            tokens: AtomicI64::new(size),
+            refilled: AtomicU64::new(now_ns()),
+        }
+    }
+
+    /// add the tokens for the time passed since the last refill, only the
+    /// core that wins the update of the refill time adds them
+    fn refill(&self) {
+        let last = self.refilled.load(Ordering::Relaxed);
+        let elapsed = now_ns().saturating_sub(last) as u128;
+        let add = (elapsed * self.rate as u128 / 1_000_000_000) as u64;
+        if add == 0 {
+            return;
+        }
+
+        // advance by the time the tokens represent to not lose fractions
+        let used = (add as u128 * 1_000_000_000 / self.rate as u128) as u64;
+        if self
+            .refilled
+            .compare_exchange(
+                last,
+                last + used,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            let size = self.size;
+            let _ = self.tokens.fetch_update(
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+                |t| Some(min(t.saturating_add(add as i64), size)),
+            );
+        }
+    }
+
+    /// take n tokens, which succeeds as long as the bucket is not empty
+    fn take(&self, n: u64) -> bool {
+        self.refill();
+        self.tokens
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |t| {
+                if t > 0 {
+                    Some(t - n as i64)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+
+    /// take n tokens even if that empties the bucket
+    fn force(&self, n: u64) {
+        self.refill();
+        self.tokens.fetch_sub(n as i64, Ordering::Relaxed);
+    }
+
+    /// return n tokens taken before
+    fn give(&self, n: u64) {
+        self.tokens.fetch_add(n as i64, Ordering::Relaxed);
+    }
+}
+
+/// IOPS and bandwidth buckets, either of which may be unlimited.
+#[derive(Debug)]
+struct QosBuckets {
+    iops: Option<TokenBucket>,
+    bandwidth: Option<TokenBucket>,
+}
+
+impl QosBuckets {
+    /// returns the buckets for the given rates, or None if unlimited
+    fn new(iops: u64, bandwidth: u64, burst_ms: u64) -> Option<Self> {
+        let bucket = |rate| {
+            if rate == 0 {
+                None
+            } else {
+                Some(TokenBucket::new(rate, burst_ms))
+            }
+        };
+
+        if iops == 0 && bandwidth == 0 {
+            None
+        } else {
+            Some(Self {
+                iops: bucket(iops),
+                bandwidth: bucket(bandwidth),
+            })
+        }
+    }
+
+    /// take the tokens for an IO of the given size from both buckets, or
+    /// from neither
+    fn take(&self, bytes: u64) -> bool {
+        if let Some(iops) = &self.iops {
+            if !iops.take(1) {
+                return false;
+            }
+        }
+
+        if let Some(bandwidth) = &self.bandwidth {
+            if !bandwidth.take(bytes) {
+                if let Some(iops) = &self.iops {
+                    iops.give(1);
+                }
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// take the tokens for an IO of the given size regardless
+    fn force(&self, bytes: u64) {
+        if let Some(iops) = &self.iops {
+            iops.force(1);
+        }
+        if let Some(bandwidth) = &self.bandwidth {
+            bandwidth.force(bytes);
+        }
+    }
+
+    /// return the tokens of an IO of the given size
+    fn give(&self, bytes: u64) {
+        if let Some(iops) = &self.iops {
+            iops.give(1);
+        }
+        if let Some(bandwidth) = &self.bandwidth {
+            bandwidth.give(bytes);
+        }
+    }
+}
+
+/// Limiter shared by all channels of a nexus.
+#[derive(Debug)]
+pub(crate) struct QosLimiter {
+    /// upper limits of the nexus
+    limits: Option<QosBuckets>,
+    /// guaranteed share of the group capacity, and the group capacity
+    share: Option<(QosBuckets, Arc<QosBuckets>)>,
+}
+
+impl QosLimiter {
+    /// returns a limiter for the given limits, or None if unlimited
+    fn new(qos: &NexusQos) -> Option<Arc<Self>> {
+        if qos.is_unlimited() {
+            return None;
+        }
+
+        let burst_ms = if qos.burst_ms == 0 {
+            QOS_DEFAULT_BURST_MS
+        } else {
+            qos.burst_ms
+        };
+
+        Some(Arc::new(Self {
+            limits: QosBuckets::new(qos.iops, qos.bandwidth, burst_ms),
+            share: qos.group.as_ref().and_then(|g| Self::share(g, qos)),
+        }))
+    }
+
+    /// returns the share of the group capacity for a member with the given
+    /// limits, which is proportional to its weight
+    fn share(
+        name: &str,
+        qos: &NexusQos,
+    ) -> Option<(QosBuckets, Arc<QosBuckets>)> {
+        let groups = QOS_GROUPS.lock().unwrap();
+        let state = groups.get(name)?;
+
+        let total = max(
+            nexus_iter()
+                .map(|n| n.qos())
+                .filter(|q| q.group.as_deref() == Some(name))
+                .map(|q| q.weight())
+                .sum::<u64>(),
+            qos.weight(),
+        );
+
+        let share = |rate: u64| {
+            if rate == 0 {
+                0
+            } else {
+                max(rate as u128 * qos.weight() as u128 / total as u128, 1)
+                    as u64
+            }
+        };
+
+        QosBuckets::new(
+            share(state.group.iops),
+            share(state.group.bandwidth),
+            QOS_DEFAULT_BURST_MS,
+        )
+        .map(|share| (share, state.buckets.clone()))
+    }
+
+    /// admit an IO of the given size if the limits allow it
+    fn admit(&self, bytes: u64) -> bool {
+        if let Some(limits) = &self.limits {
+            if !limits.take(bytes) {
+                return false;
+            }
+        }
+
+        match &self.share {
+            None => true,
+            // IO within the share is always admitted, the group capacity
+            // it uses is not available to other members anymore
+            Some((share, group)) if share.take(bytes) => {
+                group.force(bytes);
+                true
+            }
+            // beyond its share a member may use what the group has left
+            Some((_, group)) if group.take(bytes) => true,
+            Some(_) => {
+                if let Some(limits) = &self.limits {
+                    limits.give(bytes);
+                }
+                false
+            }
+        }
+    }
+}
+
+/// Per channel QoS state: the limiter of the nexus and the IO held back on
+/// this channel.
+pub(crate) struct QosChannel {
+    limiter: Arc<QosLimiter>,
+    held: VecDeque<(*mut spdk_bdev_io, u64)>,
+    poller: Option<poller::Poller<'static>>,
+}
+
+impl QosChannel {
+    /// Create the QoS state of a channel. Must be called on the thread of the
+    /// channel, where the poller that resubmits held back IO runs.
+    pub(crate) fn new(limiter: Arc<QosLimiter>) -> Box<Self> {
+        let mut qos = Box::new(Self {
+            limiter,
+            held: VecDeque::new(),
+            poller: None,
+        });
+
+        let qos_ptr = &mut *qos as *mut QosChannel;
+        qos.poller = Some(
+            poller::Builder::new()
+                .with_name("nexus_qos_poller")
+                .with_interval(QOS_POLL_INTERVAL_US)
+                .with_poll_fn(move || unsafe { (*qos_ptr).resubmit() })
+                .build(),
+        );
+
+        qos
+    }
+
+    /// Returns true if the IO has been held back, in which case it is
+    /// submitted later by the poller. IO is held back as long as earlier IO
+    /// is held back to preserve the submission order.
+    pub(crate) fn hold(&mut self, io: *mut spdk_bdev_io, bytes: u64) -> bool {
+        if self.held.is_empty() && self.limiter.admit(bytes) {
+            return false;
+        }
+
+        self.hold_back((io, bytes));
+        true
+    }
+
+    /// returns the number of IOs held back on the channel
+    pub(crate) fn held(&self) -> usize {
+        self.held.len()
+    }
+
+    /// hold back an IO behind the IO already held back
+    pub(crate) fn hold_back(&mut self, io: (*mut spdk_bdev_io, u64)) {
+        self.held.push_back(io);
+    }
+
+    /// submit the held back IO the limits allow
+    fn resubmit(&mut self) -> i32 {
+        let mut count = 0;
+        while let Some(&(io, bytes)) = self.held.front() {
+            if !self.limiter.admit(bytes) {
+                break;
+            }
+            self.held.pop_front();
+            nexus_resubmit_request(io);
+            count += 1;
+        }
+        count
+    }
+
+    /// Stop limiting the channel, returning the held back IO which must be
+    /// submitted by the caller.
+    pub(crate) fn stop(mut self: Box<Self>) -> Vec<(*mut spdk_bdev_io, u64)> {
+        if let Some(p) = self.poller.take() {
+            p.stop();
+        }
+        self.held.drain(..).collect()
+    }
+}
+
+/// Context to install a new limiter on all channels of a nexus.
+struct SetQosCtx {
+    limiter: Option<Arc<QosLimiter>>,
+}
+
+fn set_qos_cb(
+    channel: &mut NexusChannel,
+    ctx: &mut SetQosCtx,
+) -> ChannelTraverseStatus {
+    channel.inner_mut().set_qos(ctx.limiter.clone());
+    ChannelTraverseStatus::Ok
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the QoS limits of this nexus
+    pub fn qos(&self) -> NexusQos {
+        self.qos.lock().clone()
+    }
+
+    /// returns the limiter of this nexus, for newly created channels
+    pub(crate) fn qos_limiter(&self) -> Option<Arc<QosLimiter>> {
+        self.qos_limiter.lock().clone()
+    }
+
+    /// Change the QoS limits of this nexus. The new limits apply to all
+    /// channels when this returns. If the nexus joins or leaves a QoS group,
+    /// the shares of the other members are updated as well.
+    pub async fn set_qos(&self, qos: NexusQos) -> Result<(), Error> {
+        if let Some(group) = qos.group.as_ref() {
+            if !QOS_GROUPS.lock().unwrap().contains_key(group) {
+                return Err(Error::InvalidArguments {
+                    name: self.name.clone(),
+                    args: format!("QoS group {} does not exist", group),
+                });
+            }
+        }
+
+        info!("{}: setting QoS limits {:?}", self.name, qos);
+        let old = std::mem::replace(&mut *self.qos.lock(), qos.clone());
+
+        if let Some(group) = old.group.filter(|g| Some(g) != qos.group.as_ref())
+        {
+            qos_group_refresh(&group).await;
+        }
+
+        match qos.group {
+            Some(group) => qos_group_refresh(&group).await,
+            None => self.apply_qos().await,
+        }
+
+        Ok(())
+    }
+
+    /// Install a limiter for the current QoS limits on all channels.
+    pub(crate) async fn apply_qos(&self) {
+        let limiter = QosLimiter::new(&self.qos());
+        *self.qos_limiter.lock() = limiter.clone();
+
+        if self.has_io_device {
+            let r = for_each_channel(
+                self,
+                SetQosCtx {
+                    limiter,
+                },
+                set_qos_cb,
+            );
+            r.await.expect("set QoS sender already dropped");
+        }
+    }
+}
+
+/// Recalculate the shares of all members of a QoS group.
+pub(crate) async fn qos_group_refresh(name: &str) {
+    let members = nexus_iter()
+        .filter(|n| n.qos().group.as_deref() == Some(name))
+        .map(|n| n.name.clone())
+        .collect::<Vec<_>>();
+
+    for member in members {
+        if let Some(nexus) = nexus_lookup_mut(&member) {
+            nexus.apply_qos().await;
+        }
+    }
+}
+
+/// Create a QoS group or change its capacity.
+pub async fn qos_group_set(name: &str, group: QosGroup) {
+    info!("setting QoS group {} capacity {:?}", name, group);
+    let buckets =
+        QosBuckets::new(group.iops, group.bandwidth, QOS_DEFAULT_BURST_MS);
+    match buckets {
+        Some(buckets) => {
+            QOS_GROUPS.lock().unwrap().insert(
+                name.to_string(),
+                QosGroupState {
+                    group,
+                    buckets: Arc::new(buckets),
+                },
+            );
+        }
+        None => {
+            QOS_GROUPS.lock().unwrap().remove(name);
+        }
+    }
+    qos_group_refresh(name).await;
+}
+
+/// Returns all QoS groups.
+pub fn qos_groups() -> HashMap<String, QosGroup> {
+    QOS_GROUPS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, state)| (name.clone(), state.group.clone()))
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct SetQosArgs {
+    /// name of the nexus
+    name: String,
+    /// new limits, omitted limits are unlimited
+    #[serde(flatten)]
+    qos: NexusQos,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetGroupArgs {
+    /// name of the QoS group
+    name: String,
+    /// capacity of the group, an unlimited capacity removes the group
+    #[serde(flatten)]
+    group: QosGroup,
+}
+
+async fn set_qos(args: SetQosArgs) -> Result<NexusQos, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.set_qos(args.qos).await?;
+    Ok(nexus.qos())
+}
+
+async fn set_group(
+    args: SetGroupArgs,
+) -> Result<HashMap<String, QosGroup>, Error> {
+    qos_group_set(&args.name, args.group).await;
+    Ok(qos_groups())
+}
+
+/// Register the json-rpc methods to change the QoS limits of a nexus and
+/// the capacity of QoS groups.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>("nexus_set_qos", |args: SetQosArgs| {
+        set_qos(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_set_qos_group",
+        |args: SetGroupArgs| set_group(args).boxed_local(),
+    );
+}
@@ -0,0 +1,345 @@
+//!
+//! Read-ahead of sequential reads.
+//!
+//! A backup or a scan reads a volume from start to end in IOs of a few
+//! hundred KiB, one or two at a time, so over remote children it spends most
+//! of its time waiting for the network. A channel that sees a stream of
+//! reads, each starting where the previous one ended, reads ahead of it: once
+//! the stream is `read_ahead_trigger` reads long, the blocks following the
+//! last read are read from the child the read went to, in extents of
+//! `read_ahead_kb`, into a small cache of the channel holding two extents.
+//! Reads the cache holds are served from it, and read ahead further.
+//!
+//! Read-ahead is not shared between channels, so the data it holds may be
+//! overwritten through any of them. Every write submitted to the nexus and
+//! every write completing bumps the data generation of the nexus, and the
+//! extents read ahead at an older generation are dropped unused. Read-ahead
+//! therefore only pays off for volumes that are read rather than written,
+//! which are the ones scanned sequentially. Nexuses with a write cache,
+//! encryption or checksums do not read ahead, as their reads do more than
+//! copy the data of a child. A `read_ahead_kb` of 0, the default, turns
+//! read-ahead off.
+//!
+//! `nexus_read_ahead` reports the extents read ahead on a nexus and how many
+//! were used.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    ffi::c_void,
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use spdk_rs::{DmaBuf, IoVec};
+
+use super::{nexus_lookup_any_mut, Error, Nexus, NexusChannelInner};
+use crate::{
+    core::{BlockDevice, IoCompletionStatus},
+    jsonrpc::jsonrpc_register,
+};
+
+/// Number of extents read ahead of a stream.
+const READ_AHEAD_EXTENTS: usize = 2;
+
+/// Data generation of a nexus and the extents read ahead on it, over all
+/// channels.
+#[derive(Debug, Default)]
+pub(crate) struct ReadAheadCounters {
+    /// generation of the data of the nexus, bumped by every write submitted
+    /// or completed
+    gen: AtomicU64,
+    /// number of extents read ahead
+    prefetched: AtomicU64,
+    /// number of reads served from extents read ahead
+    hits: AtomicU64,
+    /// number of extents dropped for being older than the data
+    discarded: AtomicU64,
+}
+
+impl ReadAheadCounters {
+    /// the data of the nexus changes, what was read ahead is stale
+    pub(crate) fn invalidate(&self) {
+        self.gen.fetch_add(1, Ordering::AcqRel);
+    }
+
+    fn gen(&self) -> u64 {
+        self.gen.load(Ordering::Acquire)
+    }
+}
+
+/// An extent read ahead, whose data is None while its read is in flight.
+struct Extent {
+    lba: u64,
+    num_blocks: u64,
+    gen: u64,
+    buf: Option<DmaBuf>,
+}
+
+impl Extent {
+    fn end(&self) -> u64 {
+        self.lba + self.num_blocks
+    }
+}
+
+/// Read-ahead state of a channel: the stream of reads seen and the extents
+/// read ahead of it.
+#[derive(Default)]
+pub(crate) struct ReadAhead {
+    /// first block after the last read
+    next_lba: u64,
+    /// number of reads in a row that started where the previous one ended
+    run: u32,
+    /// extents read ahead, shared with the reads filling them
+    extents: Rc<RefCell<VecDeque<Extent>>>,
+}
+
+impl ReadAhead {
+    /// Account for a read, returns true if it continues a stream long
+    /// enough to read ahead of.
+    fn observe(&mut self, lba: u64, num_blocks: u64, trigger: u32) -> bool {
+        if lba == self.next_lba {
+            self.run = self.run.saturating_add(1);
+        } else {
+            self.run = 0;
+        }
+        self.next_lba = lba + num_blocks;
+        self.run >= trigger
+    }
+
+    /// drop what was read ahead, the reads in flight complete into nothing
+    pub(crate) fn clear(&mut self) {
+        self.extents.borrow_mut().clear();
+        self.run = 0;
+    }
+}
+
+/// A read ahead in flight.
+struct Prefetch {
+    extents: Rc<RefCell<VecDeque<Extent>>>,
+    lba: u64,
+    gen: u64,
+    buf: DmaBuf,
+    iov: IoVec,
+}
+
+/// the read of an extent completes, its data is kept unless the extent was
+/// dropped meanwhile
+fn prefetch_done(
+    _device: &dyn BlockDevice,
+    status: IoCompletionStatus,
+    ctx: *mut c_void,
+) {
+    let Prefetch {
+        extents,
+        lba,
+        gen,
+        buf,
+        ..
+    } = *unsafe { Box::from_raw(ctx as *mut Prefetch) };
+
+    let mut extents = extents.borrow_mut();
+    if let Some(pos) = extents
+        .iter()
+        .position(|e| e.lba == lba && e.gen == gen && e.buf.is_none())
+    {
+        if status == IoCompletionStatus::Success {
+            extents[pos].buf = Some(buf);
+        } else {
+            extents.remove(pos);
+        }
+    }
+}
+
+impl NexusChannelInner {
+    /// returns true if the channel reads ahead
+    fn reads_ahead(&self) -> bool {
+        self.opts.read_ahead_kb != 0
+            && self.cache.is_none()
+            && self.crypto.is_none()
+            && self.checksums.is_none()
+    }
+
+    /// Returns the data of the read if the extents read ahead hold all of
+    /// it, and sets `ahead` if the read continues a stream to read ahead of.
+    pub(crate) fn read_ahead_lookup(
+        &mut self,
+        lba: u64,
+        num_blocks: u64,
+        ahead: &mut bool,
+    ) -> Option<Vec<u8>> {
+        if !self.reads_ahead() {
+            return None;
+        }
+        let trigger = self.opts.read_ahead_trigger;
+        *ahead = self.read_ahead.observe(lba, num_blocks, trigger);
+
+        let nexus = self.get_nexus();
+        let counters = &nexus.read_ahead;
+        let block_len = nexus.block_len();
+        let gen = counters.gen();
+
+        let mut extents = self.read_ahead.extents.borrow_mut();
+        let before = extents.len();
+        extents.retain(|e| e.gen == gen);
+        counters
+            .discarded
+            .fetch_add((before - extents.len()) as u64, Ordering::Relaxed);
+
+        let end = lba + num_blocks;
+        let mut data = Vec::with_capacity((num_blocks * block_len) as usize);
+        let mut cursor = lba;
+        while cursor < end {
+            let e = extents
+                .iter()
+                .find(|e| e.lba <= cursor && cursor < e.end())?;
+            let to = end.min(e.end());
+            let from_byte = ((cursor - e.lba) * block_len) as usize;
+            let to_byte = ((to - e.lba) * block_len) as usize;
+            data.extend_from_slice(
+                &e.buf.as_ref()?.as_slice()[from_byte .. to_byte],
+            );
+            cursor = to;
+        }
+        counters.hits.fetch_add(1, Ordering::Relaxed);
+        Some(data)
+    }
+
+    /// Read ahead of a stream whose last read ends at the given block, from
+    /// the reader at the given index, until the channel holds as many
+    /// extents ahead of the stream as it may.
+    pub(crate) fn read_ahead_issue(&self, reader: usize, from: u64) {
+        let nexus = self.get_nexus();
+        let counters = &nexus.read_ahead;
+        let block_len = nexus.block_len();
+        let nexus_blocks = nexus.num_blocks();
+        let gen = counters.gen();
+        let extent_blocks =
+            (self.opts.read_ahead_kb as u64 * 1024 / block_len).max(1);
+        let hdl = match self.readers.get(reader) {
+            Some(hdl) => hdl,
+            None => return,
+        };
+
+        let mut extents = self.read_ahead.extents.borrow_mut();
+        extents.retain(|e| e.end() > from && e.gen == gen);
+        let mut start = extents.back().map_or(from, |e| e.end()).max(from);
+
+        while extents.len() < READ_AHEAD_EXTENTS && start < nexus_blocks {
+            let num_blocks = extent_blocks.min(nexus_blocks - start);
+            let mut buf = match hdl.dma_malloc(num_blocks * block_len) {
+                Ok(buf) => buf,
+                Err(_) => break,
+            };
+            let iov = IoVec {
+                iov_base: buf.as_mut_slice().as_mut_ptr().cast(),
+                iov_len: (num_blocks * block_len) as _,
+            };
+            let ptr = Box::into_raw(Box::new(Prefetch {
+                extents: Rc::clone(&self.read_ahead.extents),
+                lba: start,
+                gen,
+                buf,
+                iov,
+            }));
+            let submitted = hdl.readv_blocks(
+                unsafe { &mut (*ptr).iov },
+                1,
+                start + nexus.data_ent_offset,
+                num_blocks,
+                prefetch_done,
+                ptr.cast(),
+            );
+            if submitted.is_err() {
+                drop(unsafe { Box::from_raw(ptr) });
+                break;
+            }
+
+            extents.push_back(Extent {
+                lba: start,
+                num_blocks,
+                gen,
+                buf: None,
+            });
+            counters.prefetched.fetch_add(1, Ordering::Relaxed);
+            start += num_blocks;
+        }
+    }
+}
+
+/// The extents read ahead on a nexus and how many were used.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReadAheadStats {
+    pub name: String,
+    /// size in KiB of the extents read ahead, 0 if the nexus does not read
+    /// ahead
+    pub read_ahead_kb: u32,
+    /// number of sequential reads in a row before reading ahead
+    pub read_ahead_trigger: u32,
+    /// number of extents read ahead
+    pub prefetched: u64,
+    /// number of reads served from extents read ahead
+    pub hits: u64,
+    /// number of extents dropped as the data of the nexus changed
+    pub discarded: u64,
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the extents read ahead on this nexus and how many were used
+    pub fn read_ahead_stats(&self) -> ReadAheadStats {
+        let opts = self.io_opts();
+        let c = &self.read_ahead;
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        ReadAheadStats {
+            name: self.name.clone(),
+            read_ahead_kb: opts.read_ahead_kb,
+            read_ahead_trigger: opts.read_ahead_trigger,
+            prefetched: load(&c.prefetched),
+            hits: load(&c.hits),
+            discarded: load(&c.discarded),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsArgs {
+    /// name or uuid of the nexus
+    name: String,
+}
+
+async fn stats(args: StatsArgs) -> Result<ReadAheadStats, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    Ok(nexus.read_ahead_stats())
+}
+
+/// Register the json-rpc method reporting the read-ahead of a nexus.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_read_ahead",
+        |args: StatsArgs| stats(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observe() {
+        let mut ra = ReadAhead::default();
+        assert!(!ra.observe(100, 8, 2));
+        assert!(!ra.observe(108, 8, 2));
+        assert!(ra.observe(116, 8, 2));
+
+        // a read elsewhere ends the stream
+        assert!(!ra.observe(0, 8, 2));
+        assert!(!ra.observe(8, 8, 2));
+        assert!(ra.observe(16, 8, 2));
+    }
+}
@@ -0,0 +1,530 @@
+//!
+//! Optional read cache of a nexus on a local device.
+//!
+//! A nexus whose children are all remote pays a network round trip for every
+//! read. The read cache keeps data that has been read on a fast local
+//! device, typically an NVMe namespace, and serves repeated reads from there.
+//!
+//! The cache is direct mapped: the nexus is divided into lines of
+//! `READ_CACHE_LINE_BYTES`, each of which maps to one slot of the cache
+//! device, with a bitmap of the blocks of the line that are valid. Reads that
+//! are completely valid in the cache are read from the cache device; other
+//! reads go to the children as usual and fill the cache when they complete.
+//! Writes, unmaps and write zeroes invalidate the blocks they touch before
+//! they are submitted, and a fill is dropped when the blocks it covers were
+//! invalidated after its read was submitted, so the cache never returns data
+//! older than the last completed write.
+//!
+//! The map of what is cached lives in memory. The volume may have been
+//! written through another nexus while this one was gone, so the cache
+//! always starts cold: it survives on the device but is not trusted after
+//! the nexus or the process restarts.
+
+use std::{cmp::min, sync::Arc, time::Duration};
+
+use crossbeam::atomic::AtomicCell;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use spdk_rs::ChannelTraverseStatus;
+
+use super::{
+    nexus_lookup_any_mut,
+    CreateReadCache,
+    Error,
+    Nexus,
+    NexusChannel,
+};
+
+use crate::{
+    bdev::{device_create, device_destroy, device_open},
+    core::{
+        for_each_channel,
+        BlockDeviceDescriptor,
+        BlockDeviceHandle,
+        Reactors,
+    },
+    jsonrpc::jsonrpc_register,
+    sleep::mayastor_sleep,
+};
+
+/// Size of a cache line.
+const READ_CACHE_LINE_BYTES: u64 = 64 * 1024;
+
+/// Reads larger than this do not fill the cache, so that a sequential scan
+/// does not wipe it.
+const READ_CACHE_FILL_MAX_BYTES: u64 = 128 * 1024;
+
+/// A slot of the cache device.
+#[derive(Clone, Default)]
+struct Line {
+    /// line of the nexus held in the slot
+    tag: Option<u64>,
+    /// blocks of the line that are valid
+    valid: u128,
+    /// generation of the last invalidation of any line mapping to the slot
+    gen: u64,
+    /// set while data is written to the slot
+    filling: bool,
+    /// number of reads of the slot in flight
+    readers: u32,
+}
+
+/// bitmap of the blocks from..to of a line
+fn mask(from: u64, to: u64) -> u128 {
+    (!0u128 >> (128 - (to - from))) << from
+}
+
+struct ReadCacheState {
+    lines: Vec<Line>,
+    /// generation of the last invalidation
+    gen: u64,
+}
+
+/// The read cache of a nexus, shared by all its channels.
+pub(crate) struct ReadCache {
+    uri: String,
+    desc: Box<dyn BlockDeviceDescriptor>,
+    /// blocks per line
+    line_blocks: u64,
+    /// set when the cache is being removed, it neither serves nor fills
+    /// reads anymore then
+    disabled: AtomicCell<bool>,
+    state: parking_lot::Mutex<ReadCacheState>,
+}
+
+impl std::fmt::Debug for ReadCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReadCache")
+            .field("uri", &self.uri)
+            .field("line_blocks", &self.line_blocks)
+            .field("disabled", &self.disabled.load())
+            .finish()
+    }
+}
+
+impl ReadCache {
+    fn new(
+        uri: &str,
+        desc: Box<dyn BlockDeviceDescriptor>,
+        block_len: u64,
+    ) -> Self {
+        let line_blocks = READ_CACHE_LINE_BYTES / block_len;
+        let slots = desc.get_device().num_blocks() / line_blocks;
+        Self {
+            uri: uri.to_string(),
+            desc,
+            line_blocks,
+            disabled: AtomicCell::new(false),
+            state: parking_lot::Mutex::new(ReadCacheState {
+                lines: vec![Line::default(); slots as usize],
+                gen: 0,
+            }),
+        }
+    }
+
+    /// Iterate over the lines covering the given blocks, with the slot of
+    /// each line and the bitmap of the blocks within it. Returns None if the
+    /// slots of the lines are not contiguous on the cache device.
+    fn lines(
+        &self,
+        num_slots: usize,
+        lba: u64,
+        num_blocks: u64,
+    ) -> Option<Vec<(usize, u64, u128)>> {
+        let first = lba / self.line_blocks;
+        let last = (lba + num_blocks - 1) / self.line_blocks;
+        if (first % num_slots as u64) + (last - first) >= num_slots as u64 {
+            return None;
+        }
+
+        Some(
+            (first ..= last)
+                .map(|line| {
+                    let start = line * self.line_blocks;
+                    let from = lba.max(start) - start;
+                    let to =
+                        min(lba + num_blocks, start + self.line_blocks) - start;
+                    ((line % num_slots as u64) as usize, line, mask(from, to))
+                })
+                .collect(),
+        )
+    }
+
+    /// offset on the cache device of the given block
+    fn device_offset(&self, num_slots: usize, lba: u64) -> u64 {
+        let line = lba / self.line_blocks;
+        (line % num_slots as u64) * self.line_blocks + lba % self.line_blocks
+    }
+
+    /// Returns the offset on the cache device if the given blocks are all
+    /// valid, and marks their slots as being read.
+    fn lookup(&self, lba: u64, num_blocks: u64) -> Option<u64> {
+        if self.disabled.load() {
+            return None;
+        }
+
+        let mut state = self.state.lock();
+        let num_slots = state.lines.len();
+        let lines = self.lines(num_slots, lba, num_blocks)?;
+        if !lines.iter().all(|(slot, line, mask)| {
+            let l = &state.lines[*slot];
+            l.tag == Some(*line) && l.valid & mask == *mask
+        }) {
+            return None;
+        }
+
+        lines
+            .iter()
+            .for_each(|(slot, _, _)| state.lines[*slot].readers += 1);
+        Some(self.device_offset(num_slots, lba))
+    }
+
+    /// release the slots marked by lookup
+    fn release(&self, lba: u64, num_blocks: u64) {
+        let mut state = self.state.lock();
+        let num_slots = state.lines.len();
+        if let Some(lines) = self.lines(num_slots, lba, num_blocks) {
+            lines
+                .iter()
+                .for_each(|(slot, _, _)| state.lines[*slot].readers -= 1);
+        }
+    }
+
+    /// drop the given blocks from the cache
+    fn invalidate(&self, lba: u64, num_blocks: u64) {
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+        let num_slots = state.lines.len();
+        state.gen += 1;
+
+        let first = lba / self.line_blocks;
+        let last = (lba + num_blocks - 1) / self.line_blocks;
+        for line in first ..= last {
+            let l = &mut state.lines[(line % num_slots as u64) as usize];
+            // a fill of the line may be on its way to the slot while it
+            // holds another line, so the slot is marked either way
+            l.gen = state.gen;
+            if l.tag == Some(line) {
+                let start = line * self.line_blocks;
+                let from = lba.max(start) - start;
+                let to =
+                    min(lba + num_blocks, start + self.line_blocks) - start;
+                l.valid &= !mask(from, to);
+            }
+        }
+    }
+
+    /// generation to compare fills of reads submitted now against
+    fn gen(&self) -> u64 {
+        self.state.lock().gen
+    }
+
+    /// Reserve the slots for the data of a read that was submitted at the
+    /// given generation, returns the offset on the cache device to write the
+    /// data to. Slots that hold other lines are taken over unless they are
+    /// in use.
+    fn reserve(&self, lba: u64, num_blocks: u64, gen: u64) -> Option<u64> {
+        if self.disabled.load() {
+            return None;
+        }
+
+        let mut state = self.state.lock();
+        let num_slots = state.lines.len();
+        let lines = self.lines(num_slots, lba, num_blocks)?;
+        if !lines.iter().all(|(slot, line, _)| {
+            let l = &state.lines[*slot];
+            !l.filling
+                && l.gen <= gen
+                && (l.tag == Some(*line) || l.readers == 0)
+        }) {
+            return None;
+        }
+
+        for (slot, line, _) in lines {
+            let l = &mut state.lines[slot];
+            if l.tag != Some(line) {
+                l.tag = Some(line);
+                l.valid = 0;
+            }
+            l.filling = true;
+        }
+        Some(self.device_offset(num_slots, lba))
+    }
+
+    /// Mark the blocks of a fill valid, unless the fill failed or the blocks
+    /// were invalidated after its read was submitted.
+    fn filled(&self, lba: u64, num_blocks: u64, gen: u64, ok: bool) {
+        let mut state = self.state.lock();
+        let num_slots = state.lines.len();
+        if let Some(lines) = self.lines(num_slots, lba, num_blocks) {
+            for (slot, line, mask) in lines {
+                let l = &mut state.lines[slot];
+                l.filling = false;
+                if ok && l.tag == Some(line) && l.gen <= gen {
+                    l.valid |= mask;
+                }
+            }
+        }
+    }
+
+    /// returns true if no slot is being read or filled
+    fn is_idle(&self) -> bool {
+        self.state
+            .lock()
+            .lines
+            .iter()
+            .all(|l| !l.filling && l.readers == 0)
+    }
+}
+
+/// Per channel read cache state: the cache of the nexus and the handle of
+/// the cache device on this channel.
+pub(crate) struct ReadCacheChannel {
+    cache: Arc<ReadCache>,
+    handle: Box<dyn BlockDeviceHandle>,
+}
+
+impl ReadCacheChannel {
+    /// Create the read cache state of a channel, None if the cache device
+    /// cannot be used on this channel.
+    pub(crate) fn new(cache: Arc<ReadCache>) -> Option<Self> {
+        match cache.desc.get_io_handle() {
+            Ok(handle) => Some(Self {
+                cache,
+                handle,
+            }),
+            Err(e) => {
+                error!(
+                    "failed to get handle of read cache {}: {}",
+                    cache.uri, e
+                );
+                None
+            }
+        }
+    }
+
+    /// handle of the cache device, to read cached data from
+    pub(crate) fn handle(&self) -> &dyn BlockDeviceHandle {
+        &*self.handle
+    }
+
+    /// Returns the offset on the cache device if the given blocks are
+    /// cached. The caller must call release when the read completes.
+    pub(crate) fn lookup(&self, lba: u64, num_blocks: u64) -> Option<u64> {
+        self.cache.lookup(lba, num_blocks)
+    }
+
+    /// release the blocks of a read returned by lookup
+    pub(crate) fn release(&self, lba: u64, num_blocks: u64) {
+        self.cache.release(lba, num_blocks)
+    }
+
+    /// drop the given blocks from the cache
+    pub(crate) fn invalidate(&self, lba: u64, num_blocks: u64) {
+        self.cache.invalidate(lba, num_blocks)
+    }
+
+    /// generation to pass to fill for a read submitted now
+    pub(crate) fn gen(&self) -> u64 {
+        self.cache.gen()
+    }
+
+    /// returns true if a read of the given size fills the cache
+    pub(crate) fn fills(&self, bytes: u64) -> bool {
+        bytes <= READ_CACHE_FILL_MAX_BYTES
+    }
+
+    /// Write the data of a completed read submitted at the given generation
+    /// to the cache device, in the background.
+    pub(crate) fn fill(&self, lba: u64, data: Vec<u8>, gen: u64) {
+        let num_blocks =
+            data.len() as u64 / self.handle.get_device().block_len();
+        let offset = match self.cache.reserve(lba, num_blocks, gen) {
+            Some(offset) => offset,
+            None => return,
+        };
+
+        let cache = self.cache.clone();
+        Reactors::current()
+            .spawn_local(async move {
+                let ok = async {
+                    let hdl = cache.desc.get_io_handle().ok()?;
+                    let mut buf = hdl.dma_malloc(data.len() as u64).ok()?;
+                    buf.as_mut_slice().copy_from_slice(&data);
+                    let block_len = hdl.get_device().block_len();
+                    hdl.write_at(offset * block_len, &buf).await.ok()
+                }
+                .await
+                .is_some();
+                cache.filled(lba, num_blocks, gen, ok);
+            })
+            .detach();
+    }
+}
+
+/// Context to install a new read cache on all channels of a nexus.
+struct SetReadCacheCtx {
+    cache: Option<Arc<ReadCache>>,
+}
+
+fn set_read_cache_cb(
+    channel: &mut NexusChannel,
+    ctx: &mut SetReadCacheCtx,
+) -> ChannelTraverseStatus {
+    channel.inner_mut().read_cache =
+        ctx.cache.clone().and_then(ReadCacheChannel::new);
+    ChannelTraverseStatus::Ok
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the uri of the read cache device of this nexus
+    pub fn read_cache_uri(&self) -> Option<String> {
+        self.read_cache.lock().as_ref().map(|c| c.uri.clone())
+    }
+
+    /// returns the read cache of this nexus, for newly created channels
+    pub(crate) fn read_cache(&self) -> Option<Arc<ReadCache>> {
+        self.read_cache.lock().clone()
+    }
+
+    /// Use the local device at the given uri as read cache of this nexus,
+    /// replacing the current one. None removes the read cache. The children
+    /// of the nexus must all be remote, and the device must have the block
+    /// size of the nexus.
+    pub async fn set_read_cache(&self, uri: Option<&str>) -> Result<(), Error> {
+        if let Some(uri) = uri {
+            self.check_plaintext("read cache")?;
+            if self.children.iter().any(|c| c.is_local() != Some(false)) {
+                return Err(Error::InvalidArguments {
+                    name: self.name.clone(),
+                    args: "a read cache requires all children to be remote"
+                        .to_string(),
+                });
+            }
+            if uri.starts_with("nvmf://") {
+                return Err(Error::InvalidArguments {
+                    name: self.name.clone(),
+                    args: format!("read cache device {} is not local", uri),
+                });
+            }
+        }
+
+        if let Some(cache) = self.read_cache.lock().take() {
+            self.remove_read_cache(cache).await;
+        }
+
+        let uri = match uri {
+            Some(uri) => uri,
+            None => return Ok(()),
+        };
+
+        let device = device_create(uri).await.context(CreateReadCache {
+            name: self.name.clone(),
+        })?;
+        let desc = match device_open(&device, true) {
+            Ok(desc) => desc,
+            Err(source) => {
+                let _ = device_destroy(uri).await;
+                return Err(Error::OpenReadCache {
+                    source,
+                    name: self.name.clone(),
+                });
+            }
+        };
+
+        let dev = desc.get_device();
+        if dev.block_len() != self.block_len()
+            || dev.num_blocks() * dev.block_len() < READ_CACHE_LINE_BYTES
+        {
+            drop(desc);
+            let _ = device_destroy(uri).await;
+            return Err(Error::InvalidArguments {
+                name: self.name.clone(),
+                args: format!(
+                    "read cache device {} must have a block size of {} and \
+                    hold at least one cache line",
+                    uri,
+                    self.block_len()
+                ),
+            });
+        }
+
+        info!("{}: using {} as read cache", self.name, uri);
+        let cache = Arc::new(ReadCache::new(uri, desc, self.block_len()));
+        *self.read_cache.lock() = Some(cache.clone());
+        self.install_read_cache(Some(cache)).await;
+        Ok(())
+    }
+
+    /// Stop using the read cache, and destroy its device once no IO to it is
+    /// in flight anymore.
+    async fn remove_read_cache(&self, cache: Arc<ReadCache>) {
+        info!("{}: removing read cache {}", self.name, cache.uri);
+        cache.disabled.store(true);
+        while !cache.is_idle() {
+            let _ = mayastor_sleep(Duration::from_millis(1)).await;
+        }
+
+        self.install_read_cache(None).await;
+        let uri = cache.uri.clone();
+        drop(cache);
+        if let Err(e) = device_destroy(&uri).await {
+            error!(
+                "{}: failed to destroy read cache {}: {}",
+                self.name, uri, e
+            );
+        }
+    }
+
+    /// install the read cache on all channels
+    async fn install_read_cache(&self, cache: Option<Arc<ReadCache>>) {
+        if self.has_io_device {
+            let r = for_each_channel(
+                self,
+                SetReadCacheCtx {
+                    cache,
+                },
+                set_read_cache_cb,
+            );
+            r.await.expect("set read cache sender already dropped");
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetReadCacheArgs {
+    /// name of the nexus
+    name: String,
+    /// uri of the local cache device, omit to remove the read cache
+    #[serde(default)]
+    uri: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct SetReadCacheReply {
+    uri: Option<String>,
+}
+
+async fn set_read_cache(
+    args: SetReadCacheArgs,
+) -> Result<SetReadCacheReply, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.set_read_cache(args.uri.as_deref()).await?;
+    Ok(SetReadCacheReply {
+        uri: nexus.read_cache_uri(),
+    })
+}
+
+/// Register the json-rpc method to configure the read cache of a nexus.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_set_read_cache",
+        |args: SetReadCacheArgs| set_read_cache(args).boxed_local(),
+    );
+}
@@ -0,0 +1,154 @@
+//!
+//! Selection of the child to read from by its latency.
+//!
+//! Round robin spreads the reads evenly over the children, so a nexus with a
+//! local NVMe replica and a remote one reads half of its data over the
+//! network. With the `lowest_latency` read policy every channel keeps an
+//! exponentially weighted moving average of the latency of the reads of each
+//! child, by class of IO size, and reads from the child with the lowest
+//! average for the size of the read. Small reads are dominated by the round
+//! trip to the child and large ones by its bandwidth, so a child may be the
+//! best for one and not for the other.
+//!
+//! A child that has not been read from yet is read from first, and one read
+//! out of `EXPLORE_INTERVAL` goes round robin, so that the averages of the
+//! children that are not picked follow their latency as it changes. The
+//! averages are kept per channel and are lost when the channel is
+//! reconfigured.
+
+use std::{collections::HashMap, time::Duration};
+
+use super::NexusChannelInner;
+
+/// Number of classes of IO sizes: up to 4KiB, 16KiB, 64KiB and above.
+const SIZE_CLASSES: usize = 4;
+
+/// Weight of a new sample in the average, as a shift: 1/8.
+const EWMA_SHIFT: u32 = 3;
+
+/// One read out of this many goes round robin.
+const EXPLORE_INTERVAL: u32 = 64;
+
+/// returns the class of the size of a read
+fn size_class(bytes: u64) -> usize {
+    match bytes {
+        0 ..= 4096 => 0,
+        4097 ..= 16384 => 1,
+        16385 ..= 65536 => 2,
+        _ => 3,
+    }
+}
+
+/// Moving averages of the latency of the reads of the children of a
+/// channel.
+#[derive(Debug, Default)]
+pub(crate) struct ReadLatencies {
+    /// average latency in nsec of the reads of each child by size class, 0
+    /// until the child has been read from
+    ewma: HashMap<String, [u64; SIZE_CLASSES]>,
+    /// names of the readers of the channel, in their order, empty when they
+    /// have to be looked up again
+    readers: Vec<String>,
+    /// number of reads selected, to go round robin now and then
+    reads: u32,
+}
+
+impl ReadLatencies {
+    /// add the latency of a read of the given size from a child
+    fn record(&mut self, child: &str, bytes: u64, latency: Duration) {
+        let sample = latency.as_nanos().min(u64::MAX as u128) as u64;
+        let class = size_class(bytes);
+        let avg = match self.ewma.get_mut(child) {
+            Some(ewma) => &mut ewma[class],
+            None => {
+                self.ewma.insert(child.to_string(), [0; SIZE_CLASSES]);
+                &mut self.ewma.get_mut(child).unwrap()[class]
+            }
+        };
+        *avg = if *avg == 0 {
+            sample.max(1)
+        } else {
+            (*avg - (*avg >> EWMA_SHIFT) + (sample >> EWMA_SHIFT)).max(1)
+        };
+    }
+
+    /// the readers of the channel changed, their names are looked up again
+    pub(crate) fn forget_readers(&mut self) {
+        self.readers.clear();
+    }
+
+    /// returns the average latency of the reads of the size from a child
+    fn average(&self, child: &str, bytes: u64) -> u64 {
+        self.ewma.get(child).map_or(0, |e| e[size_class(bytes)])
+    }
+}
+
+impl NexusChannelInner {
+    /// Returns the index of the reader with the lowest average latency for
+    /// reads of the given size, other than `exclude` unless it is the only
+    /// one.
+    pub(crate) fn latency_select(
+        &mut self,
+        bytes: u64,
+        exclude: Option<usize>,
+    ) -> Option<usize> {
+        if self.readers.is_empty() {
+            return None;
+        }
+        if self.read_latencies.readers.len() != self.readers.len() {
+            let names = self
+                .readers
+                .iter()
+                .map(|r| r.get_device().device_name())
+                .collect::<Vec<_>>();
+            let l = &mut self.read_latencies;
+            l.ewma.retain(|name, _| names.contains(name));
+            l.readers = names;
+        }
+
+        self.read_latencies.reads = self.read_latencies.reads.wrapping_add(1);
+        if self.read_latencies.reads % EXPLORE_INTERVAL == 0 {
+            self.previous = (self.previous + 1) % self.readers.len();
+            if Some(self.previous) != exclude {
+                return Some(self.previous);
+            }
+        }
+
+        let l = &self.read_latencies;
+        (0 .. self.readers.len())
+            .filter(|i| Some(*i) != exclude)
+            .min_by_key(|i| l.average(&l.readers[*i], bytes))
+            .or(exclude)
+    }
+
+    /// add the latency of a read of the given size from a child
+    pub(crate) fn latency_select_record(
+        &mut self,
+        child: &str,
+        bytes: u64,
+        latency: Duration,
+    ) {
+        self.read_latencies.record(child, bytes, latency);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ewma() {
+        let mut l = ReadLatencies::default();
+        l.record("local", 4096, Duration::from_micros(100));
+        l.record("remote", 4096, Duration::from_micros(800));
+        assert!(l.average("local", 512) < l.average("remote", 512));
+        // the averages are kept by size class
+        assert_eq!(l.average("local", 1 << 20), 0);
+
+        // the average follows the latency of the child
+        for _ in 0 .. 64 {
+            l.record("local", 4096, Duration::from_micros(1600));
+        }
+        assert!(l.average("local", 4096) > l.average("remote", 4096));
+    }
+}
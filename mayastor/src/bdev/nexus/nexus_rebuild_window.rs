@@ -0,0 +1,369 @@
+//!
+//! Time windows in which rebuilds run.
+//!
+//! The rebuild of a large child reads all of a healthy child and writes all
+//! of the rebuilt one, which competes with the IO of the volume for hours.
+//! Rebuilds can be restricted to windows of the day, in UTC, given as
+//! `HH:MM-HH:MM` in the nexus options of the configuration or set with
+//! `rebuild_windows_set`; a window may wrap over midnight and a window that
+//! ends when it starts lasts all day. No windows, the default, lets rebuilds
+//! run at any time.
+//!
+//! A rebuild requested outside of the windows is created, so that the child
+//! receives the writes to the nexus, but is only started once a window
+//! opens. A running rebuild is paused when the windows close, and resumed
+//! when one opens again; rebuilds paused by the user are left alone. The
+//! rebuilds of a nexus can be let to run right away, whatever the windows,
+//! with `nexus_rebuild_override`.
+
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    convert::TryFrom,
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::{nexus_lookup_any_mut, Error, Nexus};
+use crate::{
+    core::poller,
+    jsonrpc::jsonrpc_register,
+    rebuild::{ClientOperations, RebuildJob, RebuildState},
+    subsys::Config,
+};
+
+/// Interval in usec at which the rebuilds are checked against the windows.
+const WINDOW_POLL_INTERVAL_US: u64 = 10_000_000;
+
+/// Number of minutes in a day.
+const DAY_MINUTES: u32 = 24 * 60;
+
+/// A window of the day, in minutes since midnight UTC, in which rebuilds
+/// run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct RebuildWindow {
+    start: u32,
+    end: u32,
+}
+
+impl RebuildWindow {
+    /// returns true if the minute of the day is in the window
+    fn contains(&self, minute: u32) -> bool {
+        if self.start == self.end {
+            true
+        } else if self.start < self.end {
+            self.start <= minute && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+/// parse a time of the day, HH:MM, into minutes since midnight
+fn parse_minute(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then(|| hours * 60 + minutes)
+}
+
+impl FromStr for RebuildWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_once('-')
+            .and_then(|(start, end)| {
+                Some(Self {
+                    start: parse_minute(start)?,
+                    end: parse_minute(end)?,
+                })
+            })
+            .ok_or_else(|| format!("invalid rebuild window {}", s))
+    }
+}
+
+impl TryFrom<String> for RebuildWindow {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for RebuildWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+impl From<RebuildWindow> for String {
+    fn from(window: RebuildWindow) -> Self {
+        window.to_string()
+    }
+}
+
+/// The windows in effect and the rebuilds they hold back.
+#[derive(Default)]
+struct Windows {
+    /// windows set at runtime, those of the configuration if none
+    windows: Option<Vec<RebuildWindow>>,
+    /// names of the nexuses whose rebuilds run regardless of the windows
+    overrides: HashSet<String>,
+    /// destinations of the rebuilds deferred or paused by the windows
+    held: HashSet<String>,
+}
+
+impl Windows {
+    fn windows(&self) -> Vec<RebuildWindow> {
+        self.windows
+            .clone()
+            .unwrap_or_else(|| Config::get().nexus_opts.rebuild_windows.clone())
+    }
+
+    /// returns true if the rebuilds of the nexus may run now
+    fn allows(&self, nexus: &str) -> bool {
+        self.overrides.contains(nexus) || window_open(&self.windows())
+    }
+}
+
+static WINDOWS: Lazy<Mutex<Windows>> = Lazy::new(Default::default);
+
+thread_local! {
+    static WINDOW_POLLER: RefCell<Option<poller::Poller<'static>>> =
+        RefCell::new(None);
+}
+
+/// returns the current minute of the day, UTC
+fn minute_of_day() -> u32 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    ((secs / 60) % DAY_MINUTES as u64) as u32
+}
+
+/// returns true if rebuilds may run now under the windows
+fn window_open(windows: &[RebuildWindow]) -> bool {
+    let minute = minute_of_day();
+    windows.is_empty() || windows.iter().any(|w| w.contains(minute))
+}
+
+/// start checking the rebuilds against the windows, if not already
+fn start_poller() {
+    WINDOW_POLLER.with(|p| {
+        if p.borrow().is_none() {
+            *p.borrow_mut() = Some(
+                poller::Builder::new()
+                    .with_name("rebuild_window_poller")
+                    .with_interval(WINDOW_POLL_INTERVAL_US)
+                    .with_poll_fn(|| {
+                        apply_windows();
+                        0
+                    })
+                    .build(),
+            );
+        }
+    });
+}
+
+/// Start or resume the rebuilds the windows or overrides allow, and pause
+/// the running rebuilds they do not.
+fn apply_windows() {
+    let jobs = RebuildJob::list()
+        .iter()
+        .map(|j| (j.nexus.clone(), j.destination.clone()))
+        .collect::<Vec<_>>();
+
+    let mut w = WINDOWS.lock();
+    w.held
+        .retain(|dst| jobs.iter().any(|(_, destination)| destination == dst));
+
+    for (nexus, destination) in jobs {
+        let job = match RebuildJob::lookup(&destination) {
+            Ok(job) => job,
+            Err(_) => continue,
+        };
+        let state = job.state();
+        if w.allows(&nexus) {
+            if !w.held.remove(&destination) {
+                continue;
+            }
+            info!("{}: rebuild window open for {}", nexus, destination);
+            let result = match state {
+                RebuildState::Init => job.as_client().start().map(|_| ()),
+                RebuildState::Paused => job.as_client().resume(),
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                error!(
+                    "{}: failed to run rebuild {}: {}",
+                    nexus, destination, e
+                );
+            }
+        } else if state == RebuildState::Running
+            && !w.held.contains(&destination)
+        {
+            info!("{}: rebuild window closed for {}", nexus, destination);
+            match job.as_client().pause() {
+                Ok(()) => {
+                    w.held.insert(destination);
+                }
+                Err(e) => {
+                    warn!(
+                        "{}: failed to pause rebuild {}: {}",
+                        nexus, destination, e
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Returns true if the rebuild of the destination may start now, otherwise
+/// holds it back until a window opens.
+pub(crate) fn rebuild_admit(nexus: &str, destination: &str) -> bool {
+    let mut w = WINDOWS.lock();
+    if w.windows().is_empty() {
+        return true;
+    }
+    start_poller();
+    if w.allows(nexus) {
+        return true;
+    }
+    info!(
+        "{}: rebuild of {} deferred to the next rebuild window",
+        nexus, destination
+    );
+    w.held.insert(destination.to_string());
+    false
+}
+
+/// The rebuild windows and the rebuilds they hold back.
+#[derive(Debug, Clone, Serialize)]
+pub struct RebuildWindowStatus {
+    pub windows: Vec<RebuildWindow>,
+    /// rebuilds may run now
+    pub open: bool,
+    /// names of the nexuses whose rebuilds run regardless of the windows
+    pub overrides: Vec<String>,
+    /// destinations of the rebuilds waiting for a window
+    pub held: Vec<String>,
+}
+
+/// returns the rebuild windows and the rebuilds they hold back
+pub fn rebuild_windows() -> RebuildWindowStatus {
+    let w = WINDOWS.lock();
+    let windows = w.windows();
+    RebuildWindowStatus {
+        open: window_open(&windows),
+        windows,
+        overrides: w.overrides.iter().cloned().collect(),
+        held: w.held.iter().cloned().collect(),
+    }
+}
+
+/// Set the windows rebuilds run in, none to let them run at any time, and
+/// apply them to the current rebuilds.
+pub fn set_rebuild_windows(windows: Vec<RebuildWindow>) {
+    WINDOWS.lock().windows = Some(windows);
+    start_poller();
+    apply_windows();
+}
+
+impl<'n> Nexus<'n> {
+    /// Let the rebuilds of the nexus run regardless of the windows, or
+    /// subject them to the windows again.
+    pub fn set_rebuild_override(&self, enable: bool) {
+        {
+            let mut w = WINDOWS.lock();
+            if enable {
+                w.overrides.insert(self.name.clone());
+            } else {
+                w.overrides.remove(&self.name);
+            }
+        }
+        apply_windows();
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetArgs {
+    windows: Vec<RebuildWindow>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OverrideArgs {
+    /// name or uuid of the nexus
+    name: String,
+    enable: bool,
+}
+
+async fn get(_: ()) -> Result<RebuildWindowStatus, Error> {
+    Ok(rebuild_windows())
+}
+
+async fn set(args: SetArgs) -> Result<RebuildWindowStatus, Error> {
+    set_rebuild_windows(args.windows);
+    Ok(rebuild_windows())
+}
+
+async fn set_override(args: OverrideArgs) -> Result<(), Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    nexus.set_rebuild_override(args.enable);
+    Ok(())
+}
+
+/// Register the json-rpc methods of the rebuild windows.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>("rebuild_windows_get", |args: ()| {
+        get(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, Error>(
+        "rebuild_windows_set",
+        |args: SetArgs| set(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_rebuild_override",
+        |args: OverrideArgs| set_override(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows() {
+        let w: RebuildWindow = "01:00-05:30".parse().unwrap();
+        assert!(!w.contains(59));
+        assert!(w.contains(60));
+        assert!(!w.contains(330));
+        assert_eq!(w.to_string(), "01:00-05:30");
+
+        // a window may wrap over midnight
+        let w: RebuildWindow = "22:00-02:00".parse().unwrap();
+        assert!(w.contains(23 * 60));
+        assert!(w.contains(30));
+        assert!(!w.contains(12 * 60));
+
+        assert!("00:00-00:00".parse::<RebuildWindow>().unwrap().contains(0));
+        assert!("24:00-01:00".parse::<RebuildWindow>().is_err());
+        assert!("01:00".parse::<RebuildWindow>().is_err());
+    }
+}
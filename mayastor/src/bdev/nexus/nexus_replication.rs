@@ -0,0 +1,498 @@
+//!
+//! Asynchronous replication of a nexus to a remote replica.
+//!
+//! Synchronous mirroring keeps all children of a nexus up to date with every
+//! write, which is too slow over the distance a disaster recovery copy is
+//! kept at. A nexus can instead ship its changes to a replica on another
+//! mayastor node, shared over NVMe-oF, at regular intervals.
+//!
+//! While replication is on, the nexus tracks which segments of its blocks
+//! have been written to, as a bitmap of 1MiB segments that is set when a
+//! write completes. Every interval the changed segments are added to the
+//! segments still to be shipped, and each is read from the nexus and written
+//! to the target at the same offset as on a child, so that a nexus can be
+//! created over the target on the remote node when it is needed. Segments
+//! that failed to ship remain pending, and the transfer resumes with them in
+//! the next interval. The whole nexus is shipped first unless the target is
+//! known to hold a copy already.
+//!
+//! The target is brought up to date one segment at a time and only holds a
+//! consistent image of the nexus once a cycle completes without writes
+//! having taken place during it. The changes tracked are not persisted, a
+//! nexus created again starts with a full transfer.
+
+use std::{
+    cmp::{max, min},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+        Weak,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures::FutureExt;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+
+use super::{
+    nexus_lookup_any_mut,
+    nexus_lookup_mut,
+    CreateReplication,
+    Error,
+    Nexus,
+};
+use crate::{
+    bdev::{device_create, device_destroy, device_open},
+    core::{BlockDeviceDescriptor, CoreError, Reactors, UntypedBdev},
+    jsonrpc::jsonrpc_register,
+    sleep::mayastor_sleep,
+};
+
+/// Size of the segments changes are tracked and shipped in, in bytes.
+const REPLICATION_SEGMENT_SIZE: u64 = 1024 * 1024;
+
+/// Interval used when none is configured, in seconds.
+const REPLICATION_DEFAULT_INTERVAL_SECS: u64 = 60;
+
+/// Segments of a nexus that have been written to, shared by all channels.
+#[derive(Debug, Default)]
+pub(crate) struct ChangeTracker {
+    enabled: AtomicBool,
+    /// number of blocks of a segment
+    segment_blks: Mutex<u64>,
+    /// a bit for each segment that has changed
+    bitmap: Mutex<Vec<u64>>,
+}
+
+impl ChangeTracker {
+    /// Start tracking the changes to a nexus with the given number of
+    /// blocks, with all segments changed or none.
+    fn start(&self, block_len: u64, num_blocks: u64, changed: bool) {
+        let segment_blks = max(REPLICATION_SEGMENT_SIZE / block_len, 1);
+        let segments = (num_blocks + segment_blks - 1) / segment_blks;
+        let mut bitmap = vec![0u64; ((segments + 63) / 64) as usize];
+        if changed {
+            (0 .. segments)
+                .for_each(|s| bitmap[s as usize / 64] |= 1 << (s % 64));
+        }
+        *self.segment_blks.lock() = segment_blks;
+        *self.bitmap.lock() = bitmap;
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    fn stop(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+        self.bitmap.lock().clear();
+    }
+
+    /// returns true while the changes are tracked
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// record a write of the given blocks
+    pub(crate) fn record(&self, lba: u64, num_blocks: u64) {
+        if !self.enabled.load(Ordering::Relaxed) || num_blocks == 0 {
+            return;
+        }
+        let segment_blks = *self.segment_blks.lock();
+        let mut bitmap = self.bitmap.lock();
+        for s in lba / segment_blks ..= (lba + num_blocks - 1) / segment_blks {
+            if let Some(word) = bitmap.get_mut(s as usize / 64) {
+                *word |= 1 << (s % 64);
+            }
+        }
+    }
+
+    /// Returns the segments that changed since the last call, with the
+    /// number of blocks of a segment.
+    fn take(&self) -> (Vec<u64>, u64) {
+        let mut bitmap = self.bitmap.lock();
+        let mut segments = Vec::new();
+        for (i, word) in bitmap.iter_mut().enumerate() {
+            let mut w = std::mem::take(word);
+            while w != 0 {
+                let bit = w.trailing_zeros() as u64;
+                segments.push(i as u64 * 64 + bit);
+                w &= w - 1;
+            }
+        }
+        (segments, *self.segment_blks.lock())
+    }
+}
+
+/// Replication options of a nexus.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplicationOpts {
+    /// uri of the remote replica the nexus is replicated to
+    pub target: String,
+    /// interval in seconds at which the changes are shipped, 0 selects the
+    /// default
+    pub interval_secs: u64,
+    /// the target already holds a copy of the nexus, only changes made from
+    /// now on are shipped
+    pub skip_initial_sync: bool,
+}
+
+/// State of the replication of a nexus.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplicationState {
+    /// waiting for the next interval
+    Idle,
+    /// shipping the changes
+    Syncing,
+    /// the last cycle failed, the pending segments are shipped in the next
+    Failed,
+}
+
+/// Status of the replication of a nexus.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicationStatus {
+    /// uri of the remote replica
+    pub target: String,
+    pub state: ReplicationState,
+    /// number of cycles that shipped all pending changes
+    pub cycles: u64,
+    /// time the last complete cycle started, in seconds since the epoch
+    pub last_sync: Option<u64>,
+    /// bytes shipped since replication was turned on
+    pub bytes_shipped: u64,
+    /// segments that remain to be shipped in the current or next cycle
+    pub segments_pending: u64,
+    /// size of a segment in bytes
+    pub segment_size: u64,
+    /// error of the last failed cycle
+    pub error: Option<String>,
+}
+
+/// The replication of a nexus to its target.
+pub(crate) struct Replication {
+    opts: ReplicationOpts,
+    desc: Box<dyn BlockDeviceDescriptor>,
+    /// segments that remain to be shipped, in order
+    pending: Mutex<std::collections::BTreeSet<u64>>,
+    status: Mutex<ReplicationStatus>,
+}
+
+impl std::fmt::Debug for Replication {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Replication")
+            .field("opts", &self.opts)
+            .finish()
+    }
+}
+
+/// seconds since the unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl Replication {
+    /// Ship the pending segments to the target, in order. A segment that
+    /// has been shipped is no longer pending.
+    async fn ship(
+        &self,
+        nexus_name: &str,
+        segment_blks: u64,
+    ) -> Result<(), CoreError> {
+        let (block_len, num_blocks, data_ent_offset) =
+            match nexus_lookup_mut(nexus_name) {
+                Some(nexus) => (
+                    nexus.block_len(),
+                    nexus.num_blocks(),
+                    nexus.data_ent_offset,
+                ),
+                None => return Ok(()),
+            };
+        let src =
+            UntypedBdev::open_by_name(nexus_name, false)?.into_handle()?;
+        let dst = self.desc.get_io_handle()?;
+        let mut buf =
+            src.dma_malloc(segment_blks * block_len).map_err(|_| {
+                CoreError::DmaAllocationError {
+                    size: segment_blks * block_len,
+                }
+            })?;
+
+        loop {
+            let segment = match self.pending.lock().iter().next() {
+                Some(segment) => *segment,
+                None => return Ok(()),
+            };
+            let lba = segment * segment_blks;
+            let blocks = min(segment_blks, num_blocks.saturating_sub(lba));
+            if blocks > 0 {
+                let len = blocks * block_len;
+                if blocks < segment_blks {
+                    buf = src.dma_malloc(len).map_err(|_| {
+                        CoreError::DmaAllocationError {
+                            size: len,
+                        }
+                    })?;
+                }
+                src.read_at(lba * block_len, &mut buf).await?;
+                dst.write_at((lba + data_ent_offset) * block_len, &buf)
+                    .await?;
+                self.status.lock().bytes_shipped += len;
+            }
+            self.pending.lock().remove(&segment);
+        }
+    }
+
+    /// ship the changes made since the last cycle
+    async fn cycle(&self, nexus_name: &str) {
+        let segment_blks = match nexus_lookup_mut(nexus_name) {
+            Some(nexus) => {
+                let (segments, segment_blks) = nexus.changes.take();
+                self.pending.lock().extend(segments);
+                segment_blks
+            }
+            None => return,
+        };
+
+        let start = now();
+        self.status.lock().state = ReplicationState::Syncing;
+        let result = self.ship(nexus_name, segment_blks).await;
+
+        let mut status = self.status.lock();
+        status.segments_pending = self.pending.lock().len() as u64;
+        match result {
+            Ok(_) => {
+                status.state = ReplicationState::Idle;
+                status.cycles += 1;
+                status.last_sync = Some(start);
+                status.error = None;
+            }
+            Err(e) => {
+                error!(
+                    "{}: failed to replicate to {}: {}",
+                    nexus_name, self.opts.target, e
+                );
+                status.state = ReplicationState::Failed;
+                status.error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// Ship the changes of the nexus until replication is turned off. The loop
+/// only holds on to the replication during a cycle, so that turning it off
+/// closes the target.
+async fn replication_loop(nexus_name: String, replication: Weak<Replication>) {
+    loop {
+        let replication = match replication.upgrade() {
+            Some(replication) => replication,
+            None => return,
+        };
+        let current = match nexus_lookup_mut(&nexus_name) {
+            Some(nexus) => nexus
+                .replication
+                .lock()
+                .as_ref()
+                .map_or(false, |r| Arc::ptr_eq(r, &replication)),
+            None => false,
+        };
+        if !current {
+            return;
+        }
+        replication.cycle(&nexus_name).await;
+        let interval = match replication.opts.interval_secs {
+            0 => REPLICATION_DEFAULT_INTERVAL_SECS,
+            secs => secs,
+        };
+        drop(replication);
+        let _ = mayastor_sleep(Duration::from_secs(interval)).await;
+    }
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the replication status of the nexus
+    pub fn replication_status(&self) -> Option<ReplicationStatus> {
+        self.replication.lock().as_ref().map(|r| {
+            let mut status = r.status.lock().clone();
+            if status.state != ReplicationState::Syncing {
+                status.segments_pending = r.pending.lock().len() as u64;
+            }
+            status
+        })
+    }
+
+    /// Replicate the nexus to the remote replica of the options, replacing
+    /// the current target. None turns replication off.
+    pub async fn set_replication(
+        &self,
+        opts: Option<ReplicationOpts>,
+    ) -> Result<(), Error> {
+        if let Some(replication) = self.replication.lock().take() {
+            info!(
+                "{}: no longer replicating to {}",
+                self.name, replication.opts.target
+            );
+            self.changes.stop();
+            let target = replication.opts.target.clone();
+            drop(replication);
+            if let Err(e) = device_destroy(&target).await {
+                error!(
+                    "{}: failed to release replication target {}: {}",
+                    self.name, target, e
+                );
+            }
+        }
+
+        let opts = match opts {
+            Some(opts) => opts,
+            None => return Ok(()),
+        };
+
+        let device =
+            device_create(&opts.target)
+                .await
+                .context(CreateReplication {
+                    name: self.name.clone(),
+                })?;
+        let desc = match self.open_replication_target(&device) {
+            Ok(desc) => desc,
+            Err(e) => {
+                let _ = device_destroy(&opts.target).await;
+                return Err(e);
+            }
+        };
+
+        info!("{}: replicating to {}", self.name, opts.target);
+        self.changes.start(
+            self.block_len(),
+            self.num_blocks(),
+            !opts.skip_initial_sync,
+        );
+        let replication = Arc::new(Replication {
+            status: Mutex::new(ReplicationStatus {
+                target: opts.target.clone(),
+                state: ReplicationState::Idle,
+                cycles: 0,
+                last_sync: None,
+                bytes_shipped: 0,
+                segments_pending: 0,
+                segment_size: REPLICATION_SEGMENT_SIZE,
+                error: None,
+            }),
+            opts,
+            desc,
+            pending: Mutex::new(Default::default()),
+        });
+        *self.replication.lock() = Some(replication.clone());
+        Reactors::master().send_future(replication_loop(
+            self.name.clone(),
+            Arc::downgrade(&replication),
+        ));
+        Ok(())
+    }
+
+    /// open the replication target, which must be laid out as a child
+    fn open_replication_target(
+        &self,
+        device: &str,
+    ) -> Result<Box<dyn BlockDeviceDescriptor>, Error> {
+        let desc = device_open(device, true).map_err(|source| {
+            Error::OpenReplication {
+                source,
+                name: self.name.clone(),
+            }
+        })?;
+        let dev = desc.get_device();
+        let needed =
+            (self.data_ent_offset + self.num_blocks()) * self.block_len();
+        if dev.block_len() != self.block_len() || dev.size_in_bytes() < needed {
+            return Err(Error::InvalidArguments {
+                name: self.name.clone(),
+                args: format!(
+                    "replication target must have {} byte blocks and hold \
+                    at least {} bytes",
+                    self.block_len(),
+                    needed
+                ),
+            });
+        }
+        Ok(desc)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetReplicationArgs {
+    /// name or uuid of the nexus
+    name: String,
+    /// the replication options, omit to turn replication off
+    #[serde(default)]
+    replication: Option<ReplicationOpts>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReplicationStatusArgs {
+    /// name or uuid of the nexus
+    name: String,
+}
+
+async fn set_replication(
+    args: SetReplicationArgs,
+) -> Result<Option<ReplicationStatus>, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.set_replication(args.replication).await?;
+    Ok(nexus.replication_status())
+}
+
+async fn replication_status(
+    args: ReplicationStatusArgs,
+) -> Result<Option<ReplicationStatus>, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    Ok(nexus.replication_status())
+}
+
+/// Register the json-rpc methods to replicate a nexus and to report on it.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_set_replication",
+        |args: SetReplicationArgs| set_replication(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_replication_status",
+        |args: ReplicationStatusArgs| replication_status(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn change_tracking() {
+        let changes = ChangeTracker::default();
+        changes.record(0, 8);
+        assert!(changes.take().0.is_empty());
+
+        // 512 byte blocks, 2048 blocks per segment
+        changes.start(512, 100 * 2048, false);
+        changes.record(0, 8);
+        changes.record(2047, 2);
+        changes.record(99 * 2048, 1);
+        assert_eq!(changes.take(), (vec![0, 1, 99], 2048));
+        assert!(changes.take().0.is_empty());
+
+        changes.start(512, 65 * 2048 + 1, true);
+        assert_eq!(changes.take().0.len(), 66);
+    }
+}
@@ -0,0 +1,257 @@
+//!
+//! Deferring IO while the channels of a nexus are reconfigured.
+//!
+//! A channel is refreshed on its own thread some time after a child has been
+//! added, removed or faulted. In between, a channel can find itself without
+//! a child to read from or write to while the nexus does have open children,
+//! and IO submitted in that window would fail although nothing is wrong with
+//! the nexus. Instead such IO is put in a bounded per channel queue and
+//! submitted again once the channel has been refreshed. A poller on the
+//! channel's thread resubmits the queue as soon as the channel has children
+//! again, and fails IO that has waited longer than the configured timeout.
+//!
+//! IO is only deferred while the nexus has at least one open child, a nexus
+//! without any fails IO right away as before. The depth of the queue and the
+//! timeout are set in the nexus options and can be changed per nexus, a depth
+//! of 0 disables deferring.
+//!
+//! A child can also refuse IO for the lack of memory, when its pool of IO
+//! contexts or its queue pair is exhausted. That says nothing about the
+//! health of the child, so rather than failing the IO and retiring the child
+//! the IO waits in a second queue of the channel, which the same poller
+//! submits again on its next run, by which time IO of the child will have
+//! completed and freed its memory. The number of times the children of a
+//! channel ran out of memory is counted per channel, and reported by
+//! `nexus_memory_pressure`.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use spdk_rs::{libspdk::spdk_bdev_io, ChannelTraverseStatus};
+
+use super::{
+    nexus_complete_request,
+    nexus_lookup_any_mut,
+    nexus_resubmit_request,
+    ChildState,
+    Error,
+    Nexus,
+    NexusChannelInner,
+};
+use crate::{
+    core::{for_each_channel, poller, Cores},
+    jsonrpc::jsonrpc_register,
+};
+
+/// Interval in usec at which deferred IO is looked at.
+const RETRY_POLL_INTERVAL_US: u64 = 1000;
+
+/// IO of a channel waiting for the channel to have children again, or for
+/// its children to have memory again.
+#[derive(Default)]
+pub(crate) struct RetryQueue {
+    deferred: VecDeque<(*mut spdk_bdev_io, Instant)>,
+    /// IO a child had no memory for
+    nomem: VecDeque<*mut spdk_bdev_io>,
+    /// number of times a child had no memory for an IO of the channel
+    nomem_count: u64,
+    /// number of IOs pushed back to the initiator
+    pub(crate) pushed_back: u64,
+    poller: Option<poller::Poller<'static>>,
+}
+
+/// Memory pressure on the children of a nexus, as seen by one channel.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelMemoryPressure {
+    /// core of the channel
+    pub core: u32,
+    /// number of times a child had no memory for an IO of the channel
+    pub nomem_count: u64,
+    /// number of IOs waiting for memory right now
+    pub waiting: u64,
+    /// number of IOs held back inside the nexus right now
+    pub held: u64,
+    /// number of IOs pushed back to the initiator for too many IOs being
+    /// held back
+    pub pushed_back: u64,
+}
+
+impl NexusChannelInner {
+    /// Returns true if the IO has been deferred until the channel has
+    /// children to submit it to, in which case it is resubmitted or failed
+    /// later. IO that needs readers is deferred while there are none, other
+    /// IO while there are no writers.
+    pub(crate) fn defer(&mut self, io: *mut spdk_bdev_io) -> bool {
+        let depth = self.opts.io_retry_queue_depth as usize;
+        if depth == 0 || self.retry.deferred.len() >= depth {
+            return false;
+        }
+
+        // nothing to wait for if no child will ever be added back
+        if !self
+            .get_nexus()
+            .children
+            .iter()
+            .any(|c| c.state() == ChildState::Open)
+        {
+            return false;
+        }
+
+        self.start_retry_poller();
+        self.retry.deferred.push_back((io, Instant::now()));
+        true
+    }
+
+    /// Returns true if the IO, which a child had no memory for, has been
+    /// queued to be submitted again, false if the queue is full or deferring
+    /// is disabled.
+    pub(crate) fn wait_for_memory(&mut self, io: *mut spdk_bdev_io) -> bool {
+        self.record_nomem();
+        let depth = self.opts.io_retry_queue_depth as usize;
+        if depth == 0 || self.retry.nomem.len() >= depth {
+            return false;
+        }
+
+        self.start_retry_poller();
+        self.retry.nomem.push_back(io);
+        true
+    }
+
+    /// returns the number of IOs deferred or waiting for memory
+    pub(crate) fn retry_held(&self) -> usize {
+        self.retry.deferred.len() + self.retry.nomem.len()
+    }
+
+    /// count a child having had no memory for an IO of the channel
+    pub(crate) fn record_nomem(&mut self) {
+        self.retry.nomem_count += 1;
+    }
+
+    /// start the poller resubmitting the IO of the channel, once
+    fn start_retry_poller(&mut self) {
+        if self.retry.poller.is_some() {
+            return;
+        }
+        let inner = self as *mut NexusChannelInner;
+        self.retry.poller = Some(
+            poller::Builder::new()
+                .with_name("nexus_retry_poller")
+                .with_interval(RETRY_POLL_INTERVAL_US)
+                .with_poll_fn(move || unsafe { (*inner).resubmit_deferred() })
+                .build(),
+        );
+    }
+
+    /// Resubmit the IO waiting for memory, and the deferred IO if the channel
+    /// has children again, failing the IO that has waited for too long
+    /// otherwise.
+    pub(crate) fn resubmit_deferred(&mut self) -> i32 {
+        // IO that still finds no memory waits again
+        let nomem = std::mem::take(&mut self.retry.nomem);
+        let mut count = nomem.len() as i32;
+        nomem.into_iter().for_each(nexus_resubmit_request);
+
+        if self.retry.deferred.is_empty() {
+            return count;
+        }
+
+        // a channel with readers has writers as well
+        if !self.readers.is_empty() {
+            // IO that can not be submitted is deferred again, behind the
+            // IO taken here
+            let deferred = std::mem::take(&mut self.retry.deferred);
+            for (io, _) in deferred {
+                nexus_resubmit_request(io);
+                count += 1;
+            }
+            return count;
+        }
+
+        let timeout = Duration::from_millis(self.opts.io_retry_timeout_ms);
+        while let Some(&(io, since)) = self.retry.deferred.front() {
+            if since.elapsed() < timeout {
+                break;
+            }
+            self.retry.deferred.pop_front();
+            nexus_complete_request(io, false);
+            count += 1;
+        }
+        count
+    }
+
+    /// Stop deferring IO, failing the IO that is still deferred.
+    pub(crate) fn stop_deferring(&mut self) {
+        if let Some(p) = self.retry.poller.take() {
+            p.stop();
+        }
+        self.retry
+            .deferred
+            .drain(..)
+            .for_each(|(io, _)| nexus_complete_request(io, false));
+        self.retry
+            .nomem
+            .drain(..)
+            .for_each(|io| nexus_complete_request(io, false));
+    }
+}
+
+/// add the memory pressure seen by a channel
+fn memory_pressure_cb(
+    inner: &NexusChannelInner,
+    ctx: &mut Vec<ChannelMemoryPressure>,
+) -> ChannelTraverseStatus {
+    ctx.push(ChannelMemoryPressure {
+        core: Cores::current(),
+        nomem_count: inner.retry.nomem_count,
+        waiting: inner.retry.nomem.len() as u64,
+        held: inner.held() as u64,
+        pushed_back: inner.retry.pushed_back,
+    });
+    ChannelTraverseStatus::Ok
+}
+
+impl Nexus {
+    /// Returns the memory pressure on the children of the nexus, per channel.
+    pub async fn memory_pressure(&self) -> Vec<ChannelMemoryPressure> {
+        if !self.has_io_device {
+            return Vec::new();
+        }
+        let mut pressure = for_each_channel(self, Vec::new(), |chan, ctx| {
+            memory_pressure_cb(chan.inner(), ctx)
+        })
+        .await
+        .unwrap_or_default();
+        pressure.sort_by_key(|p| p.core);
+        pressure
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MemoryPressureArgs {
+    /// name or uuid of the nexus
+    name: String,
+}
+
+async fn memory_pressure(
+    args: MemoryPressureArgs,
+) -> Result<Vec<ChannelMemoryPressure>, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    Ok(nexus.memory_pressure().await)
+}
+
+/// Register the json-rpc method reporting the memory pressure on the children
+/// of a nexus.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_memory_pressure",
+        |args: MemoryPressureArgs| memory_pressure(args).boxed_local(),
+    );
+}
@@ -0,0 +1,222 @@
+//!
+//! Scatter gather lists of the IO of a nexus.
+//!
+//! The reads and writes of a nexus are submitted to its children with the
+//! scatter gather list (SGL) of the initiator, the children reading into and
+//! writing from the buffers of the initiator directly. The nexus only copies
+//! the data of an IO for the features that need a copy of it:
+//!
+//! - writes of an encrypted nexus, whose ciphertext is written from a bounce
+//!   buffer, reads being decrypted in place
+//! - the write cache, the read cache and the checksums, which keep a copy of
+//!   the data next to the IO rather than in its path
+//! - children of a larger block size, whose unaligned IO goes through the
+//!   bounce buffer of their emulated handle
+//!
+//! Not every child takes every SGL though: an NVMe controller without SGL
+//! support takes PRPs, in which all segments but the first start on a page
+//! and all but the last end on one, and bdevs need their buffers aligned.
+//! The SGL pass-through mode, on by default, checks the SGL of every read
+//! and write against the capabilities of the children it goes to, which are
+//! detected per child, and bounces the IO through a buffer of a single
+//! segment only when one of them would not take it. With the mode off, as
+//! set by `nexus_update_options`, all reads and writes are bounced, for
+//! children that mishandle the SGLs they claim to take.
+//!
+//! `nexus_sgl_stats` reports the number of reads and writes that went to
+//! the children zero-copy and the number that were bounced, by reason,
+//! along with the capabilities of the children, so that a regression of the
+//! IO path shows as bounced IO.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use spdk_rs::{DmaBuf, DmaError, IoVec};
+
+use super::{nexus_lookup_any_mut, Error, Nexus};
+use crate::{core::SglCaps, jsonrpc::jsonrpc_register};
+
+/// How the data of a read or write goes to the children.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Bounce {
+    /// in the buffers of the initiator
+    ZeroCopy,
+    /// encrypted into a bounce buffer
+    Crypto,
+    /// through a bounce buffer as a child does not take the SGL
+    Unaligned,
+    /// through a bounce buffer as the SGL pass-through mode is off
+    Forced,
+}
+
+/// Number of reads and writes of a nexus by how their data went to the
+/// children.
+#[derive(Debug, Default)]
+pub(crate) struct SglCounters {
+    zero_copy: AtomicU64,
+    crypto: AtomicU64,
+    unaligned: AtomicU64,
+    forced: AtomicU64,
+}
+
+impl SglCounters {
+    /// count a read or write
+    pub(crate) fn record(&self, bounce: Bounce) {
+        match bounce {
+            Bounce::ZeroCopy => &self.zero_copy,
+            Bounce::Crypto => &self.crypto,
+            Bounce::Unaligned => &self.unaligned,
+            Bounce::Forced => &self.forced,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Bounce buffer holding the data of a read or write while it is submitted
+/// to the children.
+pub(crate) struct BounceBuf {
+    buf: DmaBuf,
+    iov: IoVec,
+}
+
+impl BounceBuf {
+    /// allocate a bounce buffer for a read of len bytes
+    pub(crate) fn new(len: u64, alignment: u64) -> Result<Box<Self>, DmaError> {
+        let mut buf = DmaBuf::new(len, alignment)?;
+        let iov = IoVec {
+            iov_base: buf.as_mut_slice().as_mut_ptr().cast(),
+            iov_len: len as _,
+        };
+        Ok(Box::new(Self {
+            buf,
+            iov,
+        }))
+    }
+
+    /// copy the data of a write into a new bounce buffer
+    pub(crate) fn copy(
+        data: &[u8],
+        alignment: u64,
+    ) -> Result<Box<Self>, DmaError> {
+        let mut buf = Self::new(data.len() as u64, alignment)?;
+        buf.buf.as_mut_slice()[.. data.len()].copy_from_slice(data);
+        Ok(buf)
+    }
+
+    /// the iov to submit to the children
+    pub(crate) fn iov(&mut self) -> *mut IoVec {
+        &mut self.iov
+    }
+
+    /// the data in the buffer
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.buf.as_slice()[.. self.iov.iov_len as usize]
+    }
+}
+
+/// Capabilities of a child of a nexus.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChildSglCaps {
+    pub name: String,
+    /// None if the child is not open
+    pub caps: Option<SglCaps>,
+}
+
+/// Number of reads and writes of a nexus by how their data went to the
+/// children.
+#[derive(Debug, Clone, Serialize)]
+pub struct SglStats {
+    pub name: String,
+    pub sgl_passthrough: bool,
+    pub zero_copy: u64,
+    /// sum of the bounced IOs below
+    pub bounced: u64,
+    pub bounced_crypto: u64,
+    pub bounced_unaligned: u64,
+    pub bounced_forced: u64,
+    pub children: Vec<ChildSglCaps>,
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the number of reads and writes of this nexus by how their data
+    /// went to the children
+    pub fn sgl_stats(&self) -> SglStats {
+        let c = &self.sgl_counters;
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let (crypto, unaligned, forced) =
+            (load(&c.crypto), load(&c.unaligned), load(&c.forced));
+        SglStats {
+            name: self.name.clone(),
+            sgl_passthrough: self.io_opts().sgl_passthrough,
+            zero_copy: load(&c.zero_copy),
+            bounced: crypto + unaligned + forced,
+            bounced_crypto: crypto,
+            bounced_unaligned: unaligned,
+            bounced_forced: forced,
+            children: self
+                .children
+                .iter()
+                .map(|child| ChildSglCaps {
+                    name: child.get_name().to_string(),
+                    caps: child.get_device().ok().map(|d| d.sgl_caps()),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsArgs {
+    /// name or uuid of the nexus
+    name: String,
+}
+
+async fn stats(args: StatsArgs) -> Result<SglStats, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    Ok(nexus.sgl_stats())
+}
+
+/// Register the json-rpc method reporting the zero-copy and bounced IOs of
+/// nexuses.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>("nexus_sgl_stats", |args: StatsArgs| {
+        stats(args).boxed_local()
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn iov(base: u64, len: u64) -> IoVec {
+        IoVec {
+            iov_base: base as *mut _,
+            iov_len: len as _,
+        }
+    }
+
+    #[test]
+    fn accepts() {
+        let prp = SglCaps {
+            sgl: false,
+            buf_align: 4,
+        };
+        let sgl = SglCaps::default();
+
+        let pages = [iov(0x1200, 0xe00), iov(0x3000, 0x1000), iov(0x5000, 8)];
+        assert!(prp.accepts(&pages));
+
+        let split = [iov(0x1000, 0x200), iov(0x3000, 0x1000)];
+        assert!(!prp.accepts(&split));
+        assert!(sgl.accepts(&split));
+
+        let odd = [iov(0x1001, 0x1000)];
+        assert!(!prp.accepts(&odd));
+        assert!(sgl.accepts(&odd));
+    }
+}
@@ -1,18 +1,69 @@
 use async_trait::async_trait;
+use serde::Deserialize;
 use snafu::ResultExt;
-use std::pin::Pin;
+use spdk_rs::ChannelTraverseStatus;
+use std::{
+    pin::Pin,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
 
 use super::{
+    block_len_compatible,
+    DrEvent,
     Error,
     NbdDisk,
     Nexus,
+    NexusChannel,
     NexusTarget,
+    NvmeAnaState,
     ShareNbdNexus,
     ShareNvmfNexus,
     UnshareNexus,
 };
 
-use crate::core::{Bdev, Protocol, Share};
+use crate::{
+    core::{for_each_channel, Bdev, Protocol, Share},
+    rebuild::RebuildJob,
+    sleep::mayastor_sleep,
+    subsys::{apply_share_token, Config, NvmfSubsystem},
+};
+
+/// interval at which the IOs in flight are counted while draining
+const DRAIN_POLL: Duration = Duration::from_millis(10);
+
+/// How an nvmf share is drained before it is torn down.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct DrainOpts {
+    /// time in milliseconds to wait for the IOs in flight to complete
+    pub timeout_ms: u64,
+    /// unshare when the IOs have not completed in time, rather than failing
+    /// and serving the initiators again
+    pub force: bool,
+}
+
+impl Default for DrainOpts {
+    fn default() -> Self {
+        Self {
+            timeout_ms: Config::get().nexus_opts.unshare_drain_timeout_ms,
+            force: true,
+        }
+    }
+}
+
+/// Context to count the IOs in flight on all channels of a nexus.
+struct InFlightCtx {
+    in_flight: u64,
+}
+
+fn in_flight_cb(
+    channel: &mut NexusChannel,
+    ctx: &mut InFlightCtx,
+) -> ChannelTraverseStatus {
+    ctx.in_flight += channel.inner().io_in_flight.load(Ordering::Relaxed);
+    ChannelTraverseStatus::Ok
+}
 
 #[async_trait(? Send)]
 ///
@@ -150,6 +201,96 @@ impl<'n> Nexus<'n> {
         }
     }
 
+    /// Change the logical block size the nexus exposes to initiators, 512 or
+    /// 4096, independent of the block sizes of its children. Children with a
+    /// larger block size than the nexus emulate the smaller one with
+    /// read-modify-writes. The nexus keeps its data at the same byte offsets
+    /// on the children, so the data of the volume is unaffected, but it must
+    /// not be shared as initiators would see the device change under them.
+    pub async fn set_logical_block_size(
+        mut self: Pin<&mut Self>,
+        blk_size: u32,
+    ) -> Result<(), Error> {
+        let name = self.name.clone();
+        if blk_size != 512 && blk_size != 4096 {
+            return Err(Error::InvalidBlockSize {
+                name,
+                blk_size,
+            });
+        }
+
+        let old = self.block_len();
+        let new = blk_size as u64;
+        if old == new {
+            return Ok(());
+        }
+
+        if self.nexus_target.is_some()
+            || matches!(self.shared(), Some(Protocol::Nvmf))
+        {
+            return Err(Error::AlreadyShared {
+                name,
+            });
+        }
+
+        // the block numbers of the tweak, the caches and the rebuild jobs
+        // are in blocks of the current size
+        if self.is_encrypted()
+            || self.write_cache.lock().is_some()
+            || self.read_cache.lock().is_some()
+            || self
+                .children
+                .iter()
+                .any(|c| RebuildJob::lookup(&c.name).is_ok())
+        {
+            return Err(Error::InvalidArguments {
+                name,
+                args: "block size can not be changed while the nexus is \
+                    encrypted, cached or rebuilding"
+                    .to_string(),
+            });
+        }
+
+        let mut max_blk_size = new;
+        for child in self.children.iter() {
+            if let Ok(dev) = child.get_device() {
+                if !block_len_compatible(dev.block_len(), new) {
+                    return Err(Error::MixedBlockSizes {
+                        name,
+                    });
+                }
+                max_blk_size = max_blk_size.max(dev.block_len());
+            }
+        }
+
+        let start = self.data_ent_offset * old;
+        let mut end = start + self.num_blocks() * old;
+        end -= end % max_blk_size;
+        if start % max_blk_size != 0 || end <= start {
+            return Err(Error::MixedBlockSizes {
+                name,
+            });
+        }
+
+        info!(
+            "{}: changing the exposed block size from {} to {}",
+            name, old, new
+        );
+
+        unsafe {
+            self.as_mut().set_data_ent_offset(start / new);
+            self.as_mut().set_block_len(blk_size);
+            self.as_mut().set_num_blocks((end - start) / new);
+            for child in self.as_mut().get_unchecked_mut().children.iter_mut() {
+                child.set_block_emulation(new);
+            }
+        }
+
+        // pick up the handles with the new emulation on all channels
+        self.reconfigure(DrEvent::BlockSize).await;
+        Ok(())
+    }
+
     /// TODO
     pub async fn unshare_nexus(mut self: Pin<&mut Self>) -> Result<(), Error> {
         unsafe {
@@ -169,6 +310,105 @@ impl<'n> Nexus<'n> {
         Ok(())
     }
 
+    /// Returns the number of IOs submitted to the nexus that have not
+    /// completed yet.
+    pub async fn io_in_flight(&self) -> u64 {
+        if !self.has_io_device {
+            return 0;
+        }
+        let r = for_each_channel(
+            self,
+            InFlightCtx {
+                in_flight: 0,
+            },
+            in_flight_cb,
+        );
+        r.await.expect("in flight sender already dropped").in_flight
+    }
+
+    /// Unshare the nexus once its nvmf share is drained: initiators can no
+    /// longer connect, nor add queues, the path is reported inaccessible to
+    /// the initiators if ANA is reported, and the IOs in flight are waited
+    /// for. When they do not complete in time the nexus is unshared
+    /// regardless if forced, otherwise it serves the initiators again and
+    /// the drain fails.
+    pub async fn unshare_nexus_drained(
+        mut self: Pin<&mut Self>,
+        opts: DrainOpts,
+    ) -> Result<(), Error> {
+        let subsystem = match self.nexus_target {
+            Some(NexusTarget::NexusNvmfTarget) => {
+                NvmfSubsystem::nqn_lookup(&self.name)
+            }
+            _ => None,
+        };
+        if let Some(subsystem) = subsystem {
+            self.drain(&subsystem, opts).await?;
+        }
+        self.as_mut().unshare_nexus().await
+    }
+
+    /// stop new connections to the subsystem and wait for the IOs in flight
+    async fn drain(
+        &self,
+        subsystem: &NvmfSubsystem,
+        opts: DrainOpts,
+    ) -> Result<(), Error> {
+        info!("{}: draining {:?}", self.name, opts);
+        subsystem.allow_any(false);
+
+        let ana_state = if subsystem.ana_reporting() {
+            let state = self.get_ana_state().await.ok();
+            if let Err(e) =
+                self.set_ana_state(NvmeAnaState::InaccessibleState).await
+            {
+                warn!(
+                    "{}: failed to make the path inaccessible: {}",
+                    self.name, e
+                );
+            }
+            state
+        } else {
+            None
+        };
+
+        let timeout = Duration::from_millis(opts.timeout_ms);
+        let start = Instant::now();
+        loop {
+            let in_flight = self.io_in_flight().await;
+            if in_flight == 0 {
+                info!("{}: drained in {:?}", self.name, start.elapsed());
+                return Ok(());
+            }
+            if start.elapsed() >= timeout {
+                if opts.force {
+                    warn!(
+                        "{}: unsharing with {} IOs in flight",
+                        self.name, in_flight
+                    );
+                    return Ok(());
+                }
+
+                if let Err(e) = apply_share_token(&self.name, subsystem) {
+                    error!("{}: failed to reopen the share: {}", self.name, e);
+                }
+                if let Some(state) = ana_state {
+                    if let Err(e) = self.set_ana_state(state).await {
+                        error!(
+                            "{}: failed to restore the ANA state {:?}: {}",
+                            self.name, state, e
+                        );
+                    }
+                }
+                return Err(Error::DrainTimeout {
+                    name: self.name.clone(),
+                    in_flight,
+                });
+            }
+            let _ = mayastor_sleep(DRAIN_POLL).await;
+        }
+    }
+
     /// Shutdowns all shares.
     pub(crate) async fn destroy_shares(mut self: Pin<&mut Self>) {
         let _ = self.as_mut().unshare_nexus().await;
@@ -0,0 +1,445 @@
+//!
+//! Deterministic simulation of a nexus rebuilding one of its children.
+//!
+//! The children and the rebuild job run the same state machines as the real
+//! ones, on top of fake block devices keeping the generation of every block
+//! written and failing writes from a scripted point in virtual time on. Every
+//! interleaving of the client events of a script with the steps of the
+//! rebuild job and the notifications of the nexus is explored, checking after
+//! each step that:
+//!
+//! - an open child holds the data written by the nexus
+//! - a rebuild job never leaves a final state
+//!
+//! and once no more steps are possible that the job is not left running.
+
+use std::cell::Cell;
+
+use super::{ChildState, ChildStates, Reason, RebuildOutcome};
+use crate::rebuild::{
+    rebuild_state::{RebuildAction, RebuildOperation, RebuildStates},
+    RebuildState,
+};
+
+/// number of blocks of the nexus, each one copied by a rebuild segment
+const BLOCKS: usize = 3;
+
+#[derive(Debug, Clone)]
+struct SimBdev {
+    /// generation of each block
+    data: Vec<u64>,
+    /// virtual time from which on all writes fail
+    fail_writes_at: Option<u64>,
+}
+
+impl SimBdev {
+    fn new(fail_writes_at: Option<u64>) -> Self {
+        Self {
+            data: vec![0; BLOCKS],
+            fail_writes_at,
+        }
+    }
+
+    fn write(&mut self, now: u64, blk: usize, gen: u64) -> Result<(), ()> {
+        match self.fail_writes_at {
+            Some(at) if now >= at => Err(()),
+            _ => {
+                self.data[blk] = gen;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SimChild {
+    state: Cell<ChildState>,
+    prev_state: Cell<ChildState>,
+    bdev: SimBdev,
+}
+
+impl SimChild {
+    fn new(state: ChildState, bdev: SimBdev) -> Self {
+        Self {
+            state: Cell::new(state),
+            prev_state: Cell::new(ChildState::Init),
+            bdev,
+        }
+    }
+}
+
+impl ChildStates for SimChild {
+    fn state(&self) -> ChildState {
+        self.state.get()
+    }
+
+    fn prev_state(&self) -> ChildState {
+        self.prev_state.get()
+    }
+
+    fn set_state(&self, state: ChildState) {
+        self.prev_state.set(self.state.replace(state));
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SimRebuild {
+    states: RebuildStates,
+    /// next block to copy
+    next: usize,
+    /// the copy loop is running
+    running: bool,
+    /// runs of the copy loop sent to the reactor
+    scheduled: u32,
+    /// final state the job reached, if any
+    done: Option<RebuildState>,
+}
+
+/// Events a script is made of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Event {
+    Start,
+    Pause,
+    Resume,
+    Stop,
+    /// the nexus writes a block
+    Write(usize),
+    /// the rebuilding child is removed from the nexus
+    Remove,
+}
+
+/// Steps the simulation can take next.
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    /// the next event of the script
+    Client(Event),
+    /// a scheduled run of the copy loop
+    Run,
+    /// the copy loop copies its next segment
+    Copy,
+    /// the nexus handles a notification of the job
+    Notify,
+}
+
+#[derive(Debug, Clone)]
+struct Sim {
+    /// virtual time, one tick per step
+    now: u64,
+    /// generation of each block written by the nexus
+    expected: Vec<u64>,
+    /// the healthy child the rebuild copies from
+    source: SimChild,
+    /// the child being rebuilt
+    dest: SimChild,
+    job: Option<SimRebuild>,
+    /// notifications of the job yet to be handled by the nexus
+    notifications: u32,
+    script: Vec<Event>,
+    /// open the child on completion whatever its state, as the nexus used to
+    legacy: bool,
+    steps: Vec<Step>,
+}
+
+impl Sim {
+    fn new(script: &[Event], fail_writes_at: Option<u64>) -> Self {
+        let mut source = SimBdev::new(None);
+        source.data.iter_mut().for_each(|d| *d = 1);
+        Self {
+            now: 0,
+            expected: vec![1; BLOCKS],
+            source: SimChild::new(ChildState::Open, source),
+            dest: SimChild::new(
+                ChildState::Faulted(Reason::OutOfSync),
+                SimBdev::new(fail_writes_at),
+            ),
+            job: Some(SimRebuild {
+                states: RebuildStates::default(),
+                next: 0,
+                running: false,
+                scheduled: 0,
+                done: None,
+            }),
+            notifications: 0,
+            script: script.iter().rev().cloned().collect(),
+            legacy: false,
+            steps: Vec::new(),
+        }
+    }
+
+    fn enabled(&self) -> Vec<Step> {
+        let mut steps = Vec::new();
+        if let Some(event) = self.script.last() {
+            steps.push(Step::Client(*event));
+        }
+        if let Some(job) = &self.job {
+            if job.scheduled > 0 {
+                steps.push(Step::Run);
+            }
+            if job.running {
+                steps.push(Step::Copy);
+            }
+        }
+        if self.notifications > 0 {
+            steps.push(Step::Notify);
+        }
+        steps
+    }
+
+    fn exec(&mut self, op: RebuildOperation, override_pending: bool) {
+        let job = match self.job.as_mut() {
+            Some(job) => job,
+            None => return,
+        };
+        let current = job.states.current;
+        match job.states.exec(op, override_pending) {
+            Ok(RebuildAction::Schedule) => {
+                if matches!(current, RebuildState::Paused | RebuildState::Init)
+                {
+                    job.scheduled += 1;
+                }
+            }
+            Ok(RebuildAction::Reconcile) => self.reconcile(),
+            Ok(RebuildAction::None) | Err(_) => {}
+        }
+    }
+
+    fn reconcile(&mut self) {
+        let job = self.job.as_mut().unwrap();
+        let old = job.states.current;
+        if job.states.reconcile() != old {
+            self.notifications += 1;
+        }
+    }
+
+    /// the copy loop ends, reconciling whatever state is pending
+    fn stop_running(&mut self) {
+        self.job.as_mut().unwrap().running = false;
+        self.reconcile();
+    }
+
+    fn write(&mut self, blk: usize) {
+        self.expected[blk] += 1;
+        let gen = self.expected[blk];
+        let rebuilding = self.job.is_some();
+        let now = self.now;
+        for child in [&mut self.source, &mut self.dest] {
+            let state = child.state();
+            let writer = state == ChildState::Open
+                || (rebuilding
+                    && state == ChildState::Faulted(Reason::OutOfSync));
+            if writer && child.bdev.write(now, blk, gen).is_err() {
+                child.set_state(ChildState::Faulted(Reason::IoError));
+            }
+        }
+    }
+
+    fn step(&mut self, step: Step) {
+        self.now += 1;
+        self.steps.push(step);
+        match step {
+            Step::Client(event) => {
+                self.script.pop();
+                match event {
+                    Event::Start => self.exec(RebuildOperation::Start, false),
+                    Event::Pause => self.exec(RebuildOperation::Pause, false),
+                    Event::Resume => self.exec(RebuildOperation::Resume, false),
+                    Event::Stop => self.exec(RebuildOperation::Stop, false),
+                    Event::Write(blk) => self.write(blk),
+                    Event::Remove => {
+                        self.exec(RebuildOperation::Stop, true);
+                        self.dest.on_remove();
+                    }
+                }
+            }
+            Step::Run => {
+                let job = self.job.as_mut().unwrap();
+                job.scheduled -= 1;
+                if job.states.pending_equals(RebuildState::Running) {
+                    self.reconcile();
+                    let job = self.job.as_mut().unwrap();
+                    job.running = true;
+                    if job.next >= BLOCKS {
+                        // nothing left to copy
+                        self.exec(RebuildOperation::Complete, true);
+                        self.stop_running();
+                    }
+                }
+            }
+            Step::Copy => {
+                let now = self.now;
+                let job = self.job.as_mut().unwrap();
+                let blk = job.next;
+                let gen = self.source.bdev.data[blk];
+                if self.dest.bdev.write(now, blk, gen).is_err() {
+                    self.exec(RebuildOperation::Fail, true);
+                    self.stop_running();
+                    return;
+                }
+                job.next += 1;
+                if !job.states.keep_running() {
+                    self.stop_running();
+                } else if job.next >= BLOCKS {
+                    self.exec(RebuildOperation::Complete, true);
+                    self.stop_running();
+                }
+            }
+            Step::Notify => {
+                self.notifications -= 1;
+                let state = match &self.job {
+                    Some(job) if job.states.current.done() => {
+                        job.states.current
+                    }
+                    _ => return,
+                };
+                let outcome = if self.legacy && state == RebuildState::Completed
+                {
+                    self.dest.set_state(ChildState::Open);
+                    RebuildOutcome::Rebuilt
+                } else {
+                    self.dest.on_rebuild_done(state)
+                };
+                if outcome == RebuildOutcome::Failed {
+                    self.dest
+                        .set_state(ChildState::Faulted(Reason::RebuildFailed));
+                }
+                // the job is removed once done
+                self.job = None;
+                self.notifications = 0;
+            }
+        }
+    }
+
+    fn check(&mut self) -> Result<(), String> {
+        for (name, child) in [("source", &self.source), ("dest", &self.dest)] {
+            if child.state() == ChildState::Open
+                && child.bdev.data != self.expected
+            {
+                return Err(format!(
+                    "{} child is open but holds {:?} instead of {:?}",
+                    name, child.bdev.data, self.expected
+                ));
+            }
+        }
+        if let Some(job) = self.job.as_mut() {
+            let current = job.states.current;
+            match job.done {
+                Some(done) if done != current => {
+                    return Err(format!(
+                        "rebuild job went from {:?} to {:?}",
+                        done, current
+                    ));
+                }
+                None if current.done() => job.done = Some(current),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn check_final(&self) -> Result<(), String> {
+        match &self.job {
+            Some(job) if job.states.current == RebuildState::Running => {
+                Err("rebuild job left running without copying".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Outcome of exploring all interleavings of a simulation.
+#[derive(Debug, Default)]
+struct Explored {
+    /// number of interleavings run to the end
+    runs: u64,
+    /// final states of the rebuilt child
+    states: Vec<ChildState>,
+}
+
+fn explore(sim: &Sim, explored: &mut Explored) -> Result<(), String> {
+    let steps = sim.enabled();
+    if steps.is_empty() {
+        explored.runs += 1;
+        if !explored.states.contains(&sim.dest.state()) {
+            explored.states.push(sim.dest.state());
+        }
+        return sim
+            .check_final()
+            .map_err(|e| format!("{} after {:?}", e, sim.steps));
+    }
+
+    for step in steps {
+        let mut next = sim.clone();
+        next.step(step);
+        next.check()
+            .map_err(|e| format!("{} after {:?}", e, next.steps))?;
+        explore(&next, explored)?;
+    }
+    Ok(())
+}
+
+fn explore_script(
+    script: &[Event],
+    fail_writes_at: Option<u64>,
+    legacy: bool,
+) -> Result<Explored, String> {
+    let mut sim = Sim::new(script, fail_writes_at);
+    sim.legacy = legacy;
+    let mut explored = Explored::default();
+    explore(&sim, &mut explored)?;
+    Ok(explored)
+}
+
+#[test]
+fn rebuild_with_writes() {
+    let script = [Event::Start, Event::Write(0), Event::Write(2)];
+    let explored = explore_script(&script, None, false).unwrap();
+    assert!(explored.runs > 1);
+    assert_eq!(explored.states, vec![ChildState::Open]);
+}
+
+#[test]
+fn pause_resume_stop() {
+    let scripts = [
+        vec![Event::Start, Event::Pause, Event::Resume],
+        vec![Event::Start, Event::Pause, Event::Write(1), Event::Resume],
+        vec![Event::Start, Event::Pause, Event::Stop, Event::Resume],
+        vec![Event::Start, Event::Stop, Event::Write(0)],
+    ];
+    for script in &scripts {
+        explore_script(script, None, false).unwrap();
+    }
+}
+
+#[test]
+fn remove_while_rebuilding() {
+    let script = [Event::Start, Event::Write(1), Event::Remove];
+    let explored = explore_script(&script, None, false).unwrap();
+    assert!(explored.states.contains(&ChildState::Closed));
+}
+
+#[test]
+fn write_errors_while_rebuilding() {
+    let script = [Event::Start, Event::Write(1), Event::Write(2)];
+    let mut states = Vec::new();
+    for at in 1 .. 10 {
+        let explored = explore_script(&script, Some(at), false).unwrap();
+        states.extend(explored.states);
+    }
+    assert!(states.contains(&ChildState::Faulted(Reason::IoError)));
+    assert!(states.contains(&ChildState::Faulted(Reason::RebuildFailed)));
+    assert!(states.contains(&ChildState::Open));
+}
+
+#[test]
+fn completion_racing_child_fault() {
+    // the rebuild completes, the child is faulted by a failed write and only
+    // then the nexus handles the completion of the job
+    let script = [Event::Start, Event::Write(1)];
+    let found =
+        (1 .. 10).any(|at| explore_script(&script, Some(at), true).is_err());
+    assert!(found, "the race of opening a faulted child was not found");
+
+    for at in 1 .. 10 {
+        explore_script(&script, Some(at), false).unwrap();
+    }
+}
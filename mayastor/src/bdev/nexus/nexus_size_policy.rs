@@ -0,0 +1,182 @@
+//!
+//! Children of a nexus smaller than its requested size.
+//!
+//! Replicas of the same volume placed on pools of different cluster sizes,
+//! or created by different versions, can differ in size by a few clusters,
+//! and a nexus fails to open on a child smaller than the size it is asked
+//! for. The size policy of a nexus says what happens instead:
+//!
+//! - `strict`, the default, keeps failing the creation
+//! - `use-minimum` adopts the size of the smallest child, so the nexus is
+//!   smaller than requested
+//! - `expand-thin-children` grows the thin replicas of the local pools to the
+//!   requested size, which costs no space up front; thick replicas and replicas
+//!   of other nodes can not be grown here, and fail the creation as with
+//!   `strict`
+//!
+//! The default policy is the one of the nexus options of the configuration,
+//! and `nexus_create_with_size_policy` creates a nexus with a policy of its
+//! own.
+
+use std::{convert::TryFrom, pin::Pin};
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use super::{nexus_create_internal, Error, Nexus, NexusNvmeParams};
+use crate::{
+    core::UntypedBdev,
+    jsonrpc::jsonrpc_register,
+    lvs::Lvol,
+    subsys::Config,
+};
+
+/// What a nexus does with children smaller than its requested size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChildSizePolicy {
+    /// the nexus fails to open
+    Strict,
+    /// the nexus takes the size of its smallest child
+    UseMinimum,
+    /// the thin local replicas are grown to the size of the nexus
+    ExpandThinChildren,
+}
+
+impl Default for ChildSizePolicy {
+    fn default() -> Self {
+        Self::Strict
+    }
+}
+
+impl<'n> Nexus<'n> {
+    /// set the policy for children smaller than the nexus
+    pub(crate) fn set_size_policy(
+        self: Pin<&mut Self>,
+        policy: ChildSizePolicy,
+    ) {
+        unsafe { self.get_unchecked_mut().size_policy = policy };
+    }
+
+    /// Apply the size policy of the nexus to the children smaller than its
+    /// requested size, before they are opened. Children that remain too
+    /// small fail to open.
+    pub(crate) async fn adopt_child_sizes(
+        mut self: Pin<&mut Self>,
+    ) -> Result<(), Error> {
+        let req_size = self.req_size;
+        let small: Vec<(String, String, u64)> = self
+            .children
+            .iter()
+            .filter_map(|c| {
+                let dev = c.get_device().ok()?;
+                let size = dev.size_in_bytes();
+                (size < req_size).then(|| {
+                    (c.get_name().to_string(), dev.device_name(), size)
+                })
+            })
+            .collect();
+        if small.is_empty() {
+            return Ok(());
+        }
+
+        match self.size_policy {
+            ChildSizePolicy::Strict => {}
+            ChildSizePolicy::UseMinimum => {
+                let size = small.iter().map(|(_, _, s)| *s).min().unwrap();
+                warn!(
+                    "{}: adopting the size {} of the smallest child rather \
+                    than the requested size {}",
+                    self.name, size, req_size
+                );
+                unsafe { self.as_mut().get_unchecked_mut().req_size = size };
+            }
+            ChildSizePolicy::ExpandThinChildren => {
+                for (child, device, size) in small {
+                    let lvol = match UntypedBdev::lookup_by_name(&device)
+                        .and_then(|b| Lvol::try_from(b).ok())
+                    {
+                        Some(lvol) if lvol.is_thin() => lvol,
+                        _ => {
+                            warn!(
+                                "{}: child {} of size {} is not a thin local \
+                                replica and can not be grown",
+                                self.name, child, size
+                            );
+                            continue;
+                        }
+                    };
+                    info!(
+                        "{}: growing thin child {} from {} to {} bytes",
+                        self.name, child, size, req_size
+                    );
+                    lvol.resize(req_size).await.map_err(|source| {
+                        Error::ChildResize {
+                            source,
+                            child,
+                            name: self.name.clone(),
+                        }
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Create a nexus with a policy of its own for children smaller than its
+/// size, rather than the policy of the configuration.
+pub async fn nexus_create_with_size_policy(
+    name: &str,
+    size: u64,
+    uuid: Option<&str>,
+    children: &[String],
+    policy: ChildSizePolicy,
+) -> Result<(), Error> {
+    nexus_create_internal(
+        name,
+        size,
+        uuid,
+        None,
+        NexusNvmeParams::default(),
+        children,
+        None,
+        policy,
+        None,
+    )
+    .await
+}
+
+/// returns the default policy for children smaller than a nexus
+pub(crate) fn default_size_policy() -> ChildSizePolicy {
+    Config::get().nexus_opts.child_size_policy
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateArgs {
+    name: String,
+    size: u64,
+    #[serde(default)]
+    uuid: Option<String>,
+    children: Vec<String>,
+    policy: ChildSizePolicy,
+}
+
+async fn create(args: CreateArgs) -> Result<(), Error> {
+    nexus_create_with_size_policy(
+        &args.name,
+        args.size,
+        args.uuid.as_deref(),
+        &args.children,
+        args.policy,
+    )
+    .await
+}
+
+/// Register the json-rpc method creating a nexus with a size policy.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_create_with_size_policy",
+        |args: CreateArgs| create(args).boxed_local(),
+    );
+}
@@ -0,0 +1,472 @@
+//!
+//! Scheduled snapshots of a nexus.
+//!
+//! Without a control plane nothing takes snapshots of a volume at regular
+//! times, or removes the old ones. A nexus can be given a schedule, a cron
+//! expression evaluated in UTC, at which a snapshot of the nexus is taken on
+//! all its replicas at once, the same way as when one is requested over
+//! gRPC. The expression has the usual five fields, minute, hour, day of the
+//! month, month and day of the week, each a `*` or a list of values and
+//! ranges with an optional step, or is one of `@hourly`, `@daily`, `@weekly`
+//! and `@monthly`.
+//!
+//! The retention policy keeps the last snapshots, and the last snapshot of
+//! each of the most recent hours and days. Snapshots outside of it are
+//! destroyed on the replicas that are lvols of a pool of this node; those on
+//! other nodes are left to the node they are on. A policy that keeps nothing
+//! keeps all snapshots. Only the snapshots taken by the schedule are
+//! subject to the retention policy, and the schedule only knows about the
+//! snapshots it has taken since it was set: the schedule is not persisted.
+
+use std::{
+    collections::BTreeSet,
+    convert::TryFrom,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{Datelike, TimeZone, Timelike, Utc};
+use futures::FutureExt;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::{nexus_lookup_any_mut, nexus_lookup_mut, Error, Nexus};
+use crate::{
+    core::{Reactors, UntypedBdev},
+    jsonrpc::jsonrpc_register,
+    lvs::{Lvol, Lvs},
+    sleep::mayastor_sleep,
+};
+
+/// Schedule of the snapshots of a nexus and their retention policy.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotSchedule {
+    /// cron expression of the times a snapshot is taken at, in UTC
+    pub cron: String,
+    /// number of most recent snapshots to keep
+    pub keep_last: u32,
+    /// number of most recent hours to keep the last snapshot of
+    pub keep_hourly: u32,
+    /// number of most recent days to keep the last snapshot of
+    pub keep_daily: u32,
+}
+
+impl SnapshotSchedule {
+    /// returns true if all snapshots are kept
+    fn keeps_all(&self) -> bool {
+        self.keep_last == 0 && self.keep_hourly == 0 && self.keep_daily == 0
+    }
+
+    /// Returns the times of the snapshots the policy keeps out of the
+    /// snapshots taken at the given times.
+    fn retained(&self, times: &BTreeSet<u64>) -> BTreeSet<u64> {
+        if self.keeps_all() {
+            return times.clone();
+        }
+
+        let mut kept = times
+            .iter()
+            .rev()
+            .take(self.keep_last as usize)
+            .copied()
+            .collect::<BTreeSet<_>>();
+
+        for (period, count) in
+            [(3600, self.keep_hourly), (86400, self.keep_daily)]
+        {
+            let mut periods = BTreeSet::new();
+            for t in times.iter().rev() {
+                if periods.len() == count as usize {
+                    break;
+                }
+                if periods.insert(t / period) {
+                    kept.insert(*t);
+                }
+            }
+        }
+        kept
+    }
+}
+
+/// A parsed cron expression, with a bit set for each value of each field.
+#[derive(Debug, Clone, PartialEq)]
+struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// the day of the month is restricted
+    day_restricted: bool,
+    /// the day of the week is restricted
+    weekday_restricted: bool,
+}
+
+/// parse a field of a cron expression with values from min to max
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut set = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok()?),
+            None => (item, 1),
+        };
+        if step == 0 {
+            return None;
+        }
+
+        let (first, last) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (a.parse().ok()?, b.parse().ok()?)
+        } else {
+            let v = range.parse().ok()?;
+            // a single value with a step runs to the end of the range
+            (v, if item.contains('/') { max } else { v })
+        };
+        if first < min || last > max || first > last {
+            return None;
+        }
+
+        set |= (first ..= last)
+            .step_by(step as usize)
+            .fold(0, |s, v| s | 1 << v);
+    }
+    Some(set)
+}
+
+impl CronExpr {
+    fn parse(expr: &str) -> Option<Self> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            expr => expr,
+        };
+        let fields = expr.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return None;
+        }
+
+        // both 0 and 7 stand for sunday
+        let weekdays = parse_field(fields[4], 0, 7)?;
+        let weekdays = (weekdays | weekdays >> 7) & 0x7f;
+        Some(Self {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            day_restricted: fields[2] != "*",
+            weekday_restricted: fields[4] != "*",
+        })
+    }
+
+    /// returns true if the expression matches the minute of the given time
+    fn matches(&self, secs: u64) -> bool {
+        let t = Utc.timestamp(secs as i64, 0);
+        let day = (self.days & 1 << t.day()) != 0;
+        let weekday =
+            (self.weekdays & 1 << t.weekday().num_days_from_sunday()) != 0;
+        // as in cron, a day matches either restriction when both are given
+        let day = match (self.day_restricted, self.weekday_restricted) {
+            (true, true) => day || weekday,
+            (_, true) => weekday,
+            _ => day,
+        };
+        (self.minutes & 1 << t.minute()) != 0
+            && (self.hours & 1 << t.hour()) != 0
+            && (self.months & 1 << t.month()) != 0
+            && day
+    }
+}
+
+/// The schedule of a nexus, with the snapshots it has taken.
+#[derive(Debug)]
+pub(crate) struct SnapshotScheduler {
+    schedule: SnapshotSchedule,
+    cron: CronExpr,
+    /// times of the snapshots taken by the schedule that have not been
+    /// destroyed
+    taken: Mutex<BTreeSet<u64>>,
+    /// minute the last snapshot was taken at
+    last: AtomicU64,
+}
+
+/// seconds since the unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Returns the local replicas of the nexus.
+fn local_replicas(nexus: &Nexus) -> Vec<Lvol> {
+    nexus
+        .children
+        .iter()
+        .filter_map(|c| c.get_device().ok())
+        .filter_map(|d| UntypedBdev::lookup_by_name(&d.device_name()))
+        .filter_map(|b| Lvol::try_from(b).ok())
+        .collect()
+}
+
+impl SnapshotScheduler {
+    /// Take a snapshot of the nexus if one is due at the given minute.
+    /// Returns false once the schedule of the nexus has been replaced.
+    async fn run_once(self: &Arc<Self>, nexus_name: &str, minute: u64) -> bool {
+        let nexus = match nexus_lookup_mut(nexus_name) {
+            Some(nexus) => nexus,
+            None => return false,
+        };
+        match nexus.snapshot_schedule.lock().as_ref() {
+            Some(s) if Arc::ptr_eq(s, self) => {}
+            _ => return false,
+        }
+
+        if self.last.swap(minute, Ordering::Relaxed) == minute
+            || !self.cron.matches(minute)
+        {
+            return true;
+        }
+
+        match nexus.create_snapshot().await {
+            Ok(reply) => {
+                info!("{}: scheduled snapshot {}", nexus_name, reply.name);
+                if let Some(t) = reply
+                    .name
+                    .rsplit("-snap-")
+                    .next()
+                    .and_then(|t| t.parse().ok())
+                {
+                    self.taken.lock().insert(t);
+                }
+            }
+            Err(e) => {
+                error!(
+                    "{}: failed to take a scheduled snapshot: {}",
+                    nexus_name, e
+                );
+            }
+        }
+
+        self.prune(nexus_name).await;
+        true
+    }
+
+    /// destroy the snapshots the retention policy does not keep
+    async fn prune(&self, nexus_name: &str) {
+        let expired = {
+            let taken = self.taken.lock();
+            let kept = self.schedule.retained(&taken);
+            taken.difference(&kept).copied().collect::<Vec<_>>()
+        };
+        if expired.is_empty() {
+            return;
+        }
+
+        let replicas = match nexus_lookup_mut(nexus_name) {
+            Some(nexus) => local_replicas(&nexus),
+            None => return,
+        };
+
+        for t in expired {
+            let mut destroyed = true;
+            for replica in &replicas {
+                let name = Lvol::format_snapshot_name(&replica.name(), t);
+                let snapshot = Lvs::lookup(&replica.pool())
+                    .and_then(|lvs| lvs.lvols())
+                    .and_then(|mut lvols| lvols.find(|l| l.name() == name));
+                if let Some(snapshot) = snapshot {
+                    if let Err(e) = snapshot.destroy().await {
+                        error!(
+                            "{}: failed to destroy snapshot {}: {}",
+                            nexus_name, name, e
+                        );
+                        destroyed = false;
+                    }
+                }
+            }
+            if destroyed {
+                self.taken.lock().remove(&t);
+            }
+        }
+    }
+}
+
+/// take the snapshots of the schedule until it is replaced
+async fn snapshot_scheduler(
+    nexus_name: String,
+    scheduler: Arc<SnapshotScheduler>,
+) {
+    loop {
+        let now = now();
+        let minute = (now / 60 + 1) * 60;
+        let _ = mayastor_sleep(Duration::from_secs(minute - now)).await;
+        if !scheduler.run_once(&nexus_name, minute).await {
+            return;
+        }
+    }
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the snapshot schedule of the nexus
+    pub fn snapshot_schedule(&self) -> Option<SnapshotSchedule> {
+        self.snapshot_schedule
+            .lock()
+            .as_ref()
+            .map(|s| s.schedule.clone())
+    }
+
+    /// Take snapshots of the nexus on the given schedule, replacing the
+    /// current one. None stops taking snapshots, the snapshots taken are
+    /// kept.
+    pub fn set_snapshot_schedule(
+        &self,
+        schedule: Option<SnapshotSchedule>,
+    ) -> Result<(), Error> {
+        let scheduler = match schedule {
+            Some(schedule) => {
+                let cron =
+                    CronExpr::parse(&schedule.cron).ok_or_else(|| {
+                        Error::InvalidArguments {
+                            name: self.name.clone(),
+                            args: format!(
+                                "invalid snapshot schedule {}",
+                                schedule.cron
+                            ),
+                        }
+                    })?;
+                Some(Arc::new(SnapshotScheduler {
+                    schedule,
+                    cron,
+                    taken: Mutex::new(BTreeSet::new()),
+                    last: AtomicU64::new(0),
+                }))
+            }
+            None => None,
+        };
+
+        let previous = std::mem::replace(
+            &mut *self.snapshot_schedule.lock(),
+            scheduler.clone(),
+        );
+        // the snapshots taken so far remain subject to the retention policy
+        if let (Some(previous), Some(scheduler)) = (previous, &scheduler) {
+            scheduler.taken.lock().extend(previous.taken.lock().iter());
+        }
+
+        match scheduler {
+            Some(scheduler) => {
+                info!(
+                    "{}: taking snapshots on schedule {:?}",
+                    self.name, scheduler.schedule
+                );
+                Reactors::master().send_future(snapshot_scheduler(
+                    self.name.clone(),
+                    scheduler,
+                ));
+            }
+            None => {
+                info!("{}: no longer taking scheduled snapshots", self.name)
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetScheduleArgs {
+    /// name or uuid of the nexus
+    name: String,
+    /// the schedule, omit to stop taking snapshots
+    #[serde(default)]
+    schedule: Option<SnapshotSchedule>,
+}
+
+#[derive(Debug, Serialize)]
+struct ScheduleReply {
+    schedule: Option<SnapshotSchedule>,
+}
+
+async fn set_snapshot_schedule(
+    args: SetScheduleArgs,
+) -> Result<ScheduleReply, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.set_snapshot_schedule(args.schedule)?;
+    Ok(ScheduleReply {
+        schedule: nexus.snapshot_schedule(),
+    })
+}
+
+/// Register the json-rpc method to set the snapshot schedule of a nexus.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_set_snapshot_schedule",
+        |args: SetScheduleArgs| set_snapshot_schedule(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2021-03-01 (a monday) 00:00:00 UTC
+    const MONDAY: u64 = 1614556800;
+
+    #[test]
+    fn cron_expressions() {
+        let every_15 = CronExpr::parse("*/15 * * * *").unwrap();
+        assert!(every_15.matches(MONDAY));
+        assert!(every_15.matches(MONDAY + 45 * 60));
+        assert!(!every_15.matches(MONDAY + 50 * 60));
+
+        let weekdays = CronExpr::parse("30 2 * * 1-5").unwrap();
+        assert!(weekdays.matches(MONDAY + 2 * 3600 + 30 * 60));
+        assert!(!weekdays.matches(MONDAY - 86400 + 2 * 3600 + 30 * 60));
+
+        let sunday = CronExpr::parse("@weekly").unwrap();
+        assert!(sunday.matches(MONDAY - 86400));
+        assert_eq!(sunday, CronExpr::parse("0 0 * * 7").unwrap());
+
+        assert!(CronExpr::parse("60 * * * *").is_none());
+        assert!(CronExpr::parse("* * * *").is_none());
+        assert!(CronExpr::parse("*/0 * * * *").is_none());
+    }
+
+    #[test]
+    fn retention() {
+        let schedule = SnapshotSchedule {
+            cron: "@hourly".into(),
+            keep_last: 2,
+            keep_hourly: 3,
+            keep_daily: 2,
+        };
+        // a snapshot every 30 minutes for two days
+        let times = (0 .. 96).map(|i| MONDAY + i * 1800).collect();
+        let kept = schedule.retained(&times);
+
+        let last = MONDAY + 95 * 1800;
+        let expected = [
+            last,
+            last - 1800,
+            last - 3600,
+            last - 2 * 3600,
+            MONDAY + 47 * 1800,
+        ];
+        assert_eq!(kept, expected.iter().copied().collect());
+
+        let all = SnapshotSchedule::default();
+        assert_eq!(all.retained(&times), times);
+    }
+}
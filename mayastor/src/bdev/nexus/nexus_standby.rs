@@ -0,0 +1,109 @@
+//!
+//! Children of a nexus on standby.
+//!
+//! A replacement for a child that is to be taken out for planned maintenance
+//! can be staged ahead of time by adding it on standby. A standby child is
+//! created and opened as any child added to the nexus, so that problems with
+//! its device show up before it is needed, and it is faulted on removal of
+//! its device as any other child. It is however kept out of the IO path and
+//! is not rebuilt, nor does it degrade the nexus, until it is activated.
+//! Activating the child makes it out of sync and starts its rebuild, after
+//! which it goes online as any child added to the nexus.
+
+use std::pin::Pin;
+
+use futures::FutureExt;
+use serde::Deserialize;
+
+use super::{
+    nexus_lookup_any_mut,
+    ChildState,
+    ChildStates,
+    Error,
+    Nexus,
+    NexusStatus,
+    Reason,
+    VerboseError,
+};
+use crate::jsonrpc::jsonrpc_register;
+
+impl<'n> Nexus<'n> {
+    /// Add a child on standby, opened but without IO nor rebuild until it is
+    /// activated.
+    pub async fn add_child_standby(
+        self: Pin<&mut Self>,
+        uri: &str,
+    ) -> Result<NexusStatus, Error> {
+        self.add_child_only(uri, Reason::Standby).await
+    }
+
+    /// Activate a child on standby, starting its rebuild. The child is
+    /// faulted if the rebuild fails to start, as for a child added without
+    /// standby.
+    pub async fn activate_child(
+        mut self: Pin<&mut Self>,
+        uri: &str,
+    ) -> Result<NexusStatus, Error> {
+        let nexus_name = self.name.clone();
+        let child = self.as_mut().get_child_by_name(uri)?;
+        if child.state() != ChildState::Faulted(Reason::Standby) {
+            return Err(Error::ChildNotStandby {
+                child: uri.to_string(),
+                name: nexus_name,
+            });
+        }
+        child.set_state(ChildState::Faulted(Reason::OutOfSync));
+        info!("{}: activating standby child {}", nexus_name, uri);
+
+        if let Err(e) = self.as_mut().start_rebuild(uri).await {
+            error!(
+                "{}: standby child {} failed to start its rebuild: {}",
+                nexus_name,
+                uri,
+                e.verbose()
+            );
+            if let Ok(child) = self.as_mut().get_child_by_name(uri) {
+                child.fault(Reason::RebuildFailed).await;
+            }
+            return Err(e);
+        }
+        Ok(self.status())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ChildArgs {
+    /// name or uuid of the nexus
+    name: String,
+    uri: String,
+}
+
+async fn add_standby(args: ChildArgs) -> Result<NexusStatus, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    nexus.add_child_standby(&args.uri).await
+}
+
+async fn activate(args: ChildArgs) -> Result<NexusStatus, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    nexus.activate_child(&args.uri).await
+}
+
+/// Register the json-rpc methods adding and activating standby children.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_add_child_standby",
+        |args: ChildArgs| add_standby(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_activate_child",
+        |args: ChildArgs| activate(args).boxed_local(),
+    );
+}
@@ -0,0 +1,506 @@
+//!
+//! Tiering of the data of a nexus between a fast child and capacity children.
+//!
+//! A tiered nexus writes only to its fast child, typically a replica on a
+//! local NVMe device, and copies the data to its other children, the
+//! capacity tier, in the background. The fast child must be a full copy of
+//! the nexus, it holds all data while the capacity children catch up with
+//! it.
+//!
+//! The nexus keeps the location of the latest data of each extent of its
+//! blocks in memory: on the capacity children, only on the fast child, or
+//! being destaged. A write marks the extents it covers as being only on the
+//! fast child as it completes, and reads of these extents are served by the
+//! fast child while other reads are spread over the capacity children. The
+//! extents only on the fast child are destaged at regular intervals: each is
+//! read from the fast child and written to all capacity children, and is
+//! back on the capacity tier unless it has been written to meanwhile.
+//!
+//! Turning tiering off pauses the nexus while the remaining extents are
+//! destaged. The location map is not persisted, and the extents only on the
+//! fast child are lost to the capacity children when the fast child fails,
+//! their reads fail until it is back. Compare and write and children being
+//! rebuilt are not tiered, tiering should be turned off while a child is
+//! added.
+
+use std::{
+    ops::RangeInclusive,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+        Weak,
+    },
+    time::Duration,
+};
+
+use futures::FutureExt;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use spdk_rs::ChannelTraverseStatus;
+
+use super::{
+    nexus_lookup_any_mut,
+    nexus_lookup_mut,
+    ChildState,
+    Error,
+    Nexus,
+    NexusChannel,
+};
+use crate::{
+    core::{for_each_channel, BlockDeviceHandle, CoreError, Reactors},
+    jsonrpc::jsonrpc_register,
+    sleep::mayastor_sleep,
+};
+
+/// Extent size used when none is configured, in KiB.
+const TIER_DEFAULT_EXTENT_KB: u64 = 1024;
+
+/// Destage interval used when none is configured, in milliseconds.
+const TIER_DEFAULT_DESTAGE_MS: u64 = 1000;
+
+/// the latest data of the extent is on the capacity children
+const ON_CAPACITY: u8 = 0;
+/// the latest data of the extent is only on the fast child
+const ON_FAST: u8 = 1;
+/// the extent is being copied to the capacity children
+const DESTAGING: u8 = 2;
+
+/// Tiering options of a nexus.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TieringOpts {
+    /// uri of the child writes go to
+    pub fast: String,
+    /// size of the extents the location of the data is kept for, in KiB, 0
+    /// selects the default
+    pub extent_kb: u64,
+    /// interval at which the extents are destaged, in milliseconds, 0
+    /// selects the default
+    pub destage_interval_ms: u64,
+}
+
+/// State of the tiering of a nexus.
+#[derive(Debug, Clone, Serialize)]
+pub struct TieringStatus {
+    pub opts: TieringOpts,
+    /// size of an extent in bytes
+    pub extent_size: u64,
+    /// extents whose latest data is only on the fast child
+    pub extents_on_fast: u64,
+    /// bytes copied to the capacity children
+    pub bytes_destaged: u64,
+    /// error of the last failed destage
+    pub error: Option<String>,
+}
+
+/// Location map of the extents of a tiered nexus, shared by all channels.
+pub(crate) struct NexusTier {
+    opts: TieringOpts,
+    /// device name of the fast child
+    device: String,
+    /// number of blocks of an extent
+    extent_blks: u64,
+    extents: Vec<AtomicU8>,
+    status: Mutex<TieringStatus>,
+}
+
+impl std::fmt::Debug for NexusTier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NexusTier")
+            .field("opts", &self.opts)
+            .field("device", &self.device)
+            .finish()
+    }
+}
+
+impl NexusTier {
+    fn new(
+        opts: TieringOpts,
+        device: String,
+        block_len: u64,
+        num_blocks: u64,
+    ) -> Self {
+        let extent_kb = match opts.extent_kb {
+            0 => TIER_DEFAULT_EXTENT_KB,
+            kb => kb,
+        };
+        let extent_blks = std::cmp::max(extent_kb * 1024 / block_len, 1);
+        let count = (num_blocks + extent_blks - 1) / extent_blks;
+        Self {
+            status: Mutex::new(TieringStatus {
+                opts: opts.clone(),
+                extent_size: extent_blks * block_len,
+                extents_on_fast: 0,
+                bytes_destaged: 0,
+                error: None,
+            }),
+            opts,
+            device,
+            extent_blks,
+            extents: (0 .. count).map(|_| AtomicU8::new(ON_CAPACITY)).collect(),
+        }
+    }
+
+    /// extents covered by the blocks
+    fn range(&self, lba: u64, num_blocks: u64) -> RangeInclusive<usize> {
+        let last = lba + std::cmp::max(num_blocks, 1) - 1;
+        (lba / self.extent_blks) as usize ..= (last / self.extent_blks) as usize
+    }
+
+    /// returns true if the child on the device is the fast child
+    pub(crate) fn is_fast(&self, device: &str) -> bool {
+        self.device == device
+    }
+
+    /// returns true if the latest data of any of the blocks is only on the
+    /// fast child
+    pub(crate) fn on_fast(&self, lba: u64, num_blocks: u64) -> bool {
+        self.range(lba, num_blocks).any(|e| {
+            self.extents
+                .get(e)
+                .map_or(false, |s| s.load(Ordering::Acquire) != ON_CAPACITY)
+        })
+    }
+
+    /// record a write of the blocks to the fast child only
+    pub(crate) fn written(&self, lba: u64, num_blocks: u64) {
+        for e in self.range(lba, num_blocks) {
+            if let Some(s) = self.extents.get(e) {
+                s.store(ON_FAST, Ordering::Release);
+            }
+        }
+    }
+
+    /// returns the extents whose latest data is only on the fast child
+    fn extents_on_fast(&self) -> Vec<usize> {
+        self.extents
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.load(Ordering::Acquire) == ON_FAST)
+            .map(|(e, _)| e)
+            .collect()
+    }
+
+    /// Copy the extents only on the fast child to the capacity children,
+    /// returning the number of bytes copied.
+    async fn destage(
+        &self,
+        fast: &dyn BlockDeviceHandle,
+        capacity: &[Box<dyn BlockDeviceHandle>],
+        num_blocks: u64,
+        data_ent_offset: u64,
+    ) -> Result<u64, CoreError> {
+        let block_len = fast.get_device().block_len();
+        let mut copied = 0;
+
+        for e in self.extents_on_fast() {
+            let state = &self.extents[e];
+            if state
+                .compare_exchange(
+                    ON_FAST,
+                    DESTAGING,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_err()
+            {
+                continue;
+            }
+
+            let lba = e as u64 * self.extent_blks;
+            let len =
+                std::cmp::min(self.extent_blks, num_blocks - lba) * block_len;
+            let offset = (lba + data_ent_offset) * block_len;
+
+            let result = async {
+                let mut buf = fast.dma_malloc(len).map_err(|_| {
+                    CoreError::DmaAllocationError {
+                        size: len,
+                    }
+                })?;
+                fast.read_at(offset, &mut buf).await?;
+                for hdl in capacity {
+                    hdl.write_at(offset, &buf).await?;
+                }
+                Ok(())
+            }
+            .await;
+
+            // an extent written to meanwhile stays on the fast child
+            let done = if result.is_ok() { ON_CAPACITY } else { ON_FAST };
+            let _ = state.compare_exchange(
+                DESTAGING,
+                done,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+            result?;
+
+            copied += len;
+            self.status.lock().bytes_destaged += len;
+        }
+        Ok(copied)
+    }
+}
+
+/// Context to install the tiering of a nexus on all its channels.
+struct SetTierCtx {
+    tier: Option<Arc<NexusTier>>,
+}
+
+fn set_tier_cb(
+    channel: &mut NexusChannel,
+    ctx: &mut SetTierCtx,
+) -> ChannelTraverseStatus {
+    channel.inner_mut().tier = ctx.tier.clone();
+    ChannelTraverseStatus::Ok
+}
+
+/// Destage the extents of the nexus until tiering is turned off. The loop
+/// only holds on to the tiering while it destages.
+async fn destage_loop(nexus_name: String, tier: Weak<NexusTier>) {
+    loop {
+        let tier = match tier.upgrade() {
+            Some(tier) => tier,
+            None => return,
+        };
+        let nexus = match nexus_lookup_mut(&nexus_name) {
+            Some(nexus) => nexus,
+            None => return,
+        };
+        if !nexus
+            .tier
+            .lock()
+            .as_ref()
+            .map_or(false, |t| Arc::ptr_eq(t, &tier))
+        {
+            return;
+        }
+        if let Err(e) = nexus.destage(&tier).await {
+            error!("{}: failed to destage: {}", nexus_name, e);
+            tier.status.lock().error = Some(e.to_string());
+        }
+
+        let interval = match tier.opts.destage_interval_ms {
+            0 => TIER_DEFAULT_DESTAGE_MS,
+            ms => ms,
+        };
+        drop(tier);
+        let _ = mayastor_sleep(Duration::from_millis(interval)).await;
+    }
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the tiering of this nexus, for newly created channels
+    pub(crate) fn tier(&self) -> Option<Arc<NexusTier>> {
+        self.tier.lock().clone()
+    }
+
+    /// returns the state of the tiering of the nexus
+    pub fn tiering_status(&self) -> Option<TieringStatus> {
+        self.tier.lock().as_ref().map(|t| {
+            let mut status = t.status.lock().clone();
+            status.extents_on_fast = t
+                .extents
+                .iter()
+                .filter(|s| s.load(Ordering::Acquire) != ON_CAPACITY)
+                .count() as u64;
+            status
+        })
+    }
+
+    /// copy the extents only on the fast child to the open capacity children
+    async fn destage(&self, tier: &NexusTier) -> Result<u64, CoreError> {
+        let mut fast = None;
+        let mut capacity = Vec::new();
+        for child in self
+            .children
+            .iter()
+            .filter(|c| c.state() == ChildState::Open)
+        {
+            let hdl = child.get_io_handle()?;
+            if tier.is_fast(&hdl.get_device().device_name()) {
+                fast = Some(hdl);
+            } else {
+                capacity.push(hdl);
+            }
+        }
+        match fast {
+            Some(fast) if !capacity.is_empty() => {
+                tier.destage(
+                    fast.as_ref(),
+                    &capacity,
+                    self.num_blocks(),
+                    self.data_ent_offset,
+                )
+                .await
+            }
+            _ => Ok(0),
+        }
+    }
+
+    /// install the tiering on all channels
+    async fn install_tier(&self, tier: Option<Arc<NexusTier>>) {
+        if self.has_io_device {
+            let r = for_each_channel(
+                self,
+                SetTierCtx {
+                    tier,
+                },
+                set_tier_cb,
+            );
+            r.await.expect("set tier sender already dropped");
+        }
+    }
+
+    /// Write to the fast child of the options and destage to the other
+    /// children, replacing the current tiering. None turns tiering off,
+    /// pausing the nexus while the data only on the fast child is destaged.
+    pub async fn set_tiering(
+        mut self: Pin<&mut Self>,
+        opts: Option<TieringOpts>,
+    ) -> Result<(), Error> {
+        if let Some(tier) = self.tier() {
+            info!("{}: no longer tiering to {}", self.name, tier.opts.fast);
+            self.pause().await?;
+            self.install_tier(None).await;
+            let result = self.destage(&tier).await;
+            *self.tier.lock() = None;
+            self.as_mut().resume().await?;
+            if let Err(e) = result {
+                error!(
+                    "{}: failed to destage when turning tiering off: {}",
+                    self.name, e
+                );
+            }
+        }
+
+        let opts = match opts {
+            Some(opts) => opts,
+            None => return Ok(()),
+        };
+
+        let name = self.child_uri(&opts.fast);
+        let child = self
+            .children
+            .iter()
+            .find(|c| c.get_name() == name)
+            .ok_or_else(|| Error::ChildNotFound {
+                child: opts.fast.clone(),
+                name: self.name.clone(),
+            })?;
+        if child.state() != ChildState::Open || self.children.len() < 2 {
+            return Err(Error::InvalidArguments {
+                name: self.name.clone(),
+                args: format!(
+                    "the fast child {} must be open and the nexus must have \
+                    another child",
+                    opts.fast
+                ),
+            });
+        }
+        let device =
+            child.get_device().map(|d| d.device_name()).map_err(|_| {
+                Error::InvalidArguments {
+                    name: self.name.clone(),
+                    args: format!("child {} has no device", opts.fast),
+                }
+            })?;
+
+        info!("{}: tiering to {} {:?}", self.name, device, opts);
+        let tier = Arc::new(NexusTier::new(
+            opts,
+            device,
+            self.block_len(),
+            self.num_blocks(),
+        ));
+        *self.tier.lock() = Some(tier.clone());
+        self.install_tier(Some(tier.clone())).await;
+        Reactors::master().send_future(destage_loop(
+            self.name.clone(),
+            Arc::downgrade(&tier),
+        ));
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetTieringArgs {
+    /// name or uuid of the nexus
+    name: String,
+    /// the tiering options, omit to turn tiering off
+    #[serde(default)]
+    tiering: Option<TieringOpts>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TieringStatusArgs {
+    /// name or uuid of the nexus
+    name: String,
+}
+
+async fn set_tiering(
+    args: SetTieringArgs,
+) -> Result<Option<TieringStatus>, Error> {
+    let mut nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.as_mut().set_tiering(args.tiering).await?;
+    Ok(nexus.tiering_status())
+}
+
+async fn tiering_status(
+    args: TieringStatusArgs,
+) -> Result<Option<TieringStatus>, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    Ok(nexus.tiering_status())
+}
+
+/// Register the json-rpc methods to tier a nexus and to report on it.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_set_tiering",
+        |args: SetTieringArgs| set_tiering(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_tiering_status",
+        |args: TieringStatusArgs| tiering_status(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extent_locations() {
+        // 512 byte blocks, 2048 blocks per extent
+        let tier = NexusTier::new(
+            TieringOpts::default(),
+            "fast".into(),
+            512,
+            10 * 2048,
+        );
+        assert!(!tier.on_fast(0, 8));
+
+        tier.written(2047, 2);
+        assert!(tier.on_fast(0, 1));
+        assert!(tier.on_fast(2048, 1));
+        assert!(!tier.on_fast(2 * 2048, 2048));
+        assert_eq!(tier.extents_on_fast(), vec![0, 1]);
+
+        // a write while an extent is destaged keeps it on the fast child
+        tier.extents[0].store(DESTAGING, Ordering::Release);
+        assert!(tier.on_fast(0, 1));
+        tier.written(0, 1);
+        assert_eq!(tier.extents_on_fast(), vec![0, 1]);
+    }
+}
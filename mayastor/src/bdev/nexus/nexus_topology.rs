@@ -0,0 +1,260 @@
+//!
+//! Topology of the stack of devices of a nexus.
+//!
+//! What actually backs a volume is spread over the nexus, its children, the
+//! bdevs they open, the lvol stores of local replicas and the disks of the
+//! pools, and finding it meant piecing `nexus_list`, `bdev_get_bdevs` and
+//! `list_pools` together. The `nexus_topology` json-rpc method returns it as
+//! a tree, from the nexus down:
+//!
+//! ```text
+//! nexus ─┬─ child ── bdev (lvol) ── pool ── bdev (aio) ── disk /dev/sdb
+//!        ├─ child ── bdev (nvme) ── remote 10.1.0.5:8420/nqn...
+//!        └─ read cache ── bdev (malloc)
+//! ```
+//!
+//! Every layer has its name, driver, size and the module claiming it, so a
+//! layer claimed by another than the one above it stands out.
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use url::Url;
+
+use super::{nexus_lookup_any_mut, Error, Nexus};
+use crate::{
+    core::{Share, UntypedBdev},
+    jsonrpc::jsonrpc_register,
+    lvs::{Lvol, Lvs},
+};
+
+/// layers of bdevs followed below a child, bdevs do not stack deeper
+const MAX_DEPTH: usize = 8;
+
+/// The kind of a layer of the topology of a nexus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerKind {
+    Nexus,
+    Child,
+    ReadCache,
+    Checksums,
+    Bdev,
+    Pool,
+    /// a local disk, file or PCIe device
+    Disk,
+    /// a device on another node
+    Remote,
+    /// a device in memory
+    Memory,
+}
+
+/// A layer of the topology of a nexus, with the layers below it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Layer {
+    pub kind: LayerKind,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub driver: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_len: Option<u64>,
+    /// the module which claimed the layer, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub claimed_by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    pub below: Vec<Layer>,
+}
+
+impl Layer {
+    fn new(kind: LayerKind, name: &str) -> Self {
+        Self {
+            kind,
+            name: name.to_string(),
+            driver: None,
+            product: None,
+            uri: None,
+            size: None,
+            block_len: None,
+            claimed_by: None,
+            state: None,
+            below: Vec::new(),
+        }
+    }
+
+    /// the layer of a bdev and of what is below it
+    fn bdev(bdev: UntypedBdev, depth: usize) -> Self {
+        let uri = bdev.bdev_uri_original();
+        let mut layer = Self {
+            driver: Some(bdev.driver().to_string()),
+            product: Some(bdev.product_name().to_string()),
+            uri: uri.clone(),
+            size: Some(bdev.size_in_bytes()),
+            block_len: Some(bdev.block_len() as u64),
+            claimed_by: bdev.claimed_by(),
+            ..Self::new(LayerKind::Bdev, bdev.name())
+        };
+        if depth >= MAX_DEPTH {
+            return layer;
+        }
+
+        if let Ok(lvol) = Lvol::try_from(bdev) {
+            if let Some(lvs) = Lvs::lookup(&lvol.pool()) {
+                let mut pool = Self::new(LayerKind::Pool, lvs.name());
+                pool.size = Some(lvs.capacity());
+                pool.below.push(Self::bdev(lvs.base_bdev(), depth + 1));
+                layer.below.push(pool);
+            }
+        } else if let Some(device) = uri.as_deref().and_then(Self::device) {
+            layer.below.push(device);
+        }
+        layer
+    }
+
+    /// the device a bdev was created on, from the URI it was created with
+    fn device(uri: &str) -> Option<Self> {
+        let url = Url::parse(uri).ok()?;
+        let host = url.host_str().unwrap_or_default();
+        let (kind, name) = match url.scheme() {
+            "aio" | "uring" => (LayerKind::Disk, url.path().to_string()),
+            "pcie" if !host.is_empty() => (LayerKind::Disk, host.to_string()),
+            "pcie" => (
+                LayerKind::Disk,
+                url.path().trim_start_matches('/').to_string(),
+            ),
+            "nvmf" | "iscsi" => (
+                LayerKind::Remote,
+                format!(
+                    "{}:{}{}",
+                    host,
+                    url.port().unwrap_or_default(),
+                    url.path()
+                ),
+            ),
+            "malloc" | "null" => (
+                LayerKind::Memory,
+                url.path().trim_start_matches('/').to_string(),
+            ),
+            _ => return None,
+        };
+        Some(Self {
+            uri: Some(uri.to_string()),
+            ..Self::new(kind, &name)
+        })
+    }
+
+    /// the layer of a sidecar device of a nexus
+    fn sidecar(kind: LayerKind, uri: String) -> Self {
+        let mut layer = Self::new(kind, &uri);
+        layer.below.extend(
+            UntypedBdev::lookup_by_name(&uri)
+                .or_else(|| Self::bdev_of_uri(&uri))
+                .map(|b| Self::bdev(b, 1)),
+        );
+        layer.uri = Some(uri);
+        layer
+    }
+
+    /// returns the bdev created with the given URI
+    fn bdev_of_uri(uri: &str) -> Option<UntypedBdev> {
+        UntypedBdev::bdev_first()?.into_iter().find(|b| {
+            b.bdev_uri_original().as_deref() == Some(uri)
+                || b.aliases().iter().any(|a| a == uri)
+        })
+    }
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the topology of the devices of this nexus
+    pub fn topology(&self) -> Layer {
+        let mut nexus = self.layer();
+
+        for child in &self.children {
+            let mut layer = Layer::new(LayerKind::Child, &child.name);
+            layer.state = Some(child.state().to_string());
+            if let Ok(device) = child.get_device() {
+                layer.size = Some(device.size_in_bytes());
+                layer.block_len = Some(device.block_len());
+                layer.below.extend(
+                    UntypedBdev::lookup_by_name(&device.device_name())
+                        .map(|b| Layer::bdev(b, 1)),
+                );
+            }
+            nexus.below.push(layer);
+        }
+        if let Some(uri) = self.read_cache_uri() {
+            nexus.below.push(Layer::sidecar(LayerKind::ReadCache, uri));
+        }
+        if let Some(uri) = self.checksums_uri() {
+            nexus.below.push(Layer::sidecar(LayerKind::Checksums, uri));
+        }
+        nexus
+    }
+
+    /// the layer of the nexus itself
+    fn layer(&self) -> Layer {
+        let bdev = UntypedBdev::lookup_by_name(&self.bdev_name());
+        Layer {
+            driver: Some("nexus".to_string()),
+            uri: self.get_share_uri(),
+            size: Some(self.size_in_bytes()),
+            block_len: Some(self.block_len()),
+            claimed_by: bdev.and_then(|b| b.claimed_by()),
+            state: Some(self.status().to_string()),
+            ..Layer::new(LayerKind::Nexus, &self.name)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TopologyArgs {
+    /// name or uuid of the nexus
+    name: String,
+}
+
+async fn topology(args: TopologyArgs) -> Result<Layer, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+    Ok(nexus.topology())
+}
+
+/// Register the json-rpc method returning the topology of a nexus.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_topology",
+        |args: TopologyArgs| topology(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Layer, LayerKind};
+
+    #[test]
+    fn devices() {
+        let disk = Layer::device("aio:///dev/sdb?blk_size=4096").unwrap();
+        assert_eq!(disk.kind, LayerKind::Disk);
+        assert_eq!(disk.name, "/dev/sdb");
+
+        let remote =
+            Layer::device("nvmf://10.1.0.5:8420/nqn.2019-05.io.openebs:r1")
+                .unwrap();
+        assert_eq!(remote.kind, LayerKind::Remote);
+        assert_eq!(remote.name, "10.1.0.5:8420/nqn.2019-05.io.openebs:r1");
+
+        assert_eq!(
+            Layer::device("malloc:///m0?size_mb=64").unwrap().kind,
+            LayerKind::Memory
+        );
+        assert!(Layer::device("bdev:///lvol0").is_none());
+    }
+}
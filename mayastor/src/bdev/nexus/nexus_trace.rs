@@ -0,0 +1,512 @@
+//!
+//! Capture of the IOs of a nexus, to reproduce a workload elsewhere.
+//!
+//! While a trace runs, a sample of the IOs the nexus submits to its children
+//! is recorded with the time it was received relative to the start of the
+//! trace, its type, its range, its latency and, for reads, the child that
+//! served it. The last records are kept in a ring buffer in memory and can
+//! be streamed to a file as json lines, written by a thread of its own so
+//! that the reactors never wait on the file. Records that can not be queued
+//! to the writer are lost, not waited for.
+//!
+//! IOs completed by the nexus itself, from its write cache for instance, are
+//! not recorded. An IO failing on a child is recorded as failed even when
+//! the nexus retries it.
+//!
+//! A trace file can be replayed against any bdev with the `perf_replay`
+//! json-rpc method.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{sync_channel, SyncSender, TrySendError},
+        Arc,
+    },
+    time::Instant,
+};
+
+use crossbeam::atomic::AtomicCell;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use spdk_rs::ChannelTraverseStatus;
+
+use super::{nexus_lookup_any_mut, Error, Nexus, NexusChannel, TraceFile};
+use crate::{
+    core::{for_each_channel, IoType},
+    jsonrpc::jsonrpc_register,
+};
+
+/// Type of a traced IO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceOp {
+    Read,
+    Write,
+    Unmap,
+    WriteZeroes,
+    Reset,
+}
+
+impl TraceOp {
+    pub(crate) fn from_io_type(io_type: IoType) -> Option<Self> {
+        match io_type {
+            IoType::Read => Some(Self::Read),
+            IoType::Write => Some(Self::Write),
+            IoType::Unmap => Some(Self::Unmap),
+            IoType::WriteZeros => Some(Self::WriteZeroes),
+            IoType::Reset => Some(Self::Reset),
+            _ => None,
+        }
+    }
+}
+
+/// A traced IO.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceRecord {
+    /// time the nexus received the IO, in microseconds since the start of
+    /// the trace
+    pub ts_us: u64,
+    pub op: TraceOp,
+    /// offset of the IO in bytes
+    pub offset: u64,
+    /// length of the IO in bytes
+    pub len: u64,
+    pub latency_us: u64,
+    /// child which served a read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub child: Option<String>,
+    /// false if the IO failed
+    pub ok: bool,
+}
+
+/// Options of a trace.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceOpts {
+    /// record one IO out of sample
+    #[serde(default = "default_sample")]
+    pub sample: u32,
+    /// number of records kept in memory
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    /// file to stream the records to, if any
+    #[serde(default)]
+    pub file: Option<String>,
+}
+
+fn default_sample() -> u32 {
+    1
+}
+fn default_capacity() -> usize {
+    65536
+}
+
+impl Default for TraceOpts {
+    fn default() -> Self {
+        Self {
+            sample: default_sample(),
+            capacity: default_capacity(),
+            file: None,
+        }
+    }
+}
+
+/// Counters of a trace.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceStats {
+    pub active: bool,
+    pub opts: TraceOpts,
+    /// IOs considered for sampling
+    pub seen: u64,
+    pub recorded: u64,
+    /// records that could not be queued to the file
+    pub lost: u64,
+}
+
+struct TraceState {
+    ring: VecDeque<TraceRecord>,
+    writer: Option<SyncSender<TraceRecord>>,
+}
+
+/// A trace of the IOs of a nexus, shared by all its channels.
+pub(crate) struct NexusTrace {
+    opts: TraceOpts,
+    started: Instant,
+    active: AtomicCell<bool>,
+    seen: AtomicU64,
+    recorded: AtomicU64,
+    lost: AtomicU64,
+    state: parking_lot::Mutex<TraceState>,
+}
+
+impl std::fmt::Debug for NexusTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NexusTrace")
+            .field("opts", &self.opts)
+            .field("active", &self.active.load())
+            .field("seen", &self.seen)
+            .field("recorded", &self.recorded)
+            .field("lost", &self.lost)
+            .finish()
+    }
+}
+
+/// write the records queued to the writer of a trace file until the trace
+/// stops
+fn write_records(
+    file: File,
+    records: std::sync::mpsc::Receiver<TraceRecord>,
+) -> std::io::Result<()> {
+    let mut out = BufWriter::new(file);
+    for record in records {
+        serde_json::to_writer(&mut out, &record)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()
+}
+
+impl NexusTrace {
+    fn new(name: &str, opts: TraceOpts) -> Result<Self, Error> {
+        if opts.sample == 0 || opts.capacity == 0 {
+            return Err(Error::InvalidArguments {
+                name: name.to_string(),
+                args: format!("{:?}", opts),
+            });
+        }
+
+        let writer = match &opts.file {
+            Some(path) => {
+                let file = File::create(path).context(TraceFile {
+                    name: name.to_string(),
+                    path: path.clone(),
+                })?;
+                let (sender, receiver) = sync_channel(opts.capacity);
+                let thread_path = path.clone();
+                std::thread::Builder::new()
+                    .name(format!("trace-{}", name))
+                    .spawn(move || {
+                        if let Err(e) = write_records(file, receiver) {
+                            error!(
+                                "failed to write trace {}: {}",
+                                thread_path, e
+                            );
+                        }
+                    })
+                    .context(TraceFile {
+                        name: name.to_string(),
+                        path: path.clone(),
+                    })?;
+                Some(sender)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            started: Instant::now(),
+            active: AtomicCell::new(true),
+            seen: AtomicU64::new(0),
+            recorded: AtomicU64::new(0),
+            lost: AtomicU64::new(0),
+            state: parking_lot::Mutex::new(TraceState {
+                ring: VecDeque::with_capacity(opts.capacity.min(4096)),
+                writer,
+            }),
+            opts,
+        })
+    }
+
+    /// Returns the time an IO is received if it is part of the sample.
+    pub(crate) fn sample(&self) -> Option<Instant> {
+        let n = self.seen.fetch_add(1, Ordering::Relaxed);
+        if n % self.opts.sample as u64 == 0 {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    /// record a completed IO received at start
+    pub(crate) fn record(
+        &self,
+        start: Instant,
+        io_type: IoType,
+        offset: u64,
+        len: u64,
+        child: Option<String>,
+        ok: bool,
+    ) {
+        let op = match TraceOp::from_io_type(io_type) {
+            Some(op) => op,
+            None => return,
+        };
+        let record = TraceRecord {
+            ts_us: start.saturating_duration_since(self.started).as_micros()
+                as u64,
+            op,
+            offset,
+            len,
+            latency_us: start.elapsed().as_micros() as u64,
+            child,
+            ok,
+        };
+
+        let mut state = self.state.lock();
+        if let Some(writer) = state.writer.as_ref() {
+            match writer.try_send(record.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    self.lost.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    self.lost.fetch_add(1, Ordering::Relaxed);
+                    state.writer = None;
+                }
+            }
+        }
+        if state.ring.len() == self.opts.capacity {
+            state.ring.pop_front();
+        }
+        state.ring.push_back(record);
+        self.recorded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// stop recording and close the trace file
+    fn stop(&self) {
+        self.active.store(false);
+        self.state.lock().writer.take();
+    }
+
+    fn is_active(&self) -> bool {
+        self.active.load()
+    }
+
+    /// the last records, oldest first
+    fn records(&self, max_entries: Option<usize>) -> Vec<TraceRecord> {
+        let state = self.state.lock();
+        let skip = max_entries
+            .map(|max| state.ring.len().saturating_sub(max))
+            .unwrap_or(0);
+        state.ring.iter().skip(skip).cloned().collect()
+    }
+
+    fn stats(&self) -> TraceStats {
+        TraceStats {
+            active: self.is_active(),
+            opts: self.opts.clone(),
+            seen: self.seen.load(Ordering::Relaxed),
+            recorded: self.recorded.load(Ordering::Relaxed),
+            lost: self.lost.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Context to install a trace on all channels of a nexus.
+struct SetTraceCtx {
+    trace: Option<Arc<NexusTrace>>,
+}
+
+fn set_trace_cb(
+    channel: &mut NexusChannel,
+    ctx: &mut SetTraceCtx,
+) -> ChannelTraverseStatus {
+    channel.inner_mut().trace = ctx.trace.clone();
+    ChannelTraverseStatus::Ok
+}
+
+impl<'n> Nexus<'n> {
+    /// returns the running trace of this nexus, for newly created channels
+    pub(crate) fn trace(&self) -> Option<Arc<NexusTrace>> {
+        self.trace.lock().clone().filter(|t| t.is_active())
+    }
+
+    /// Start tracing the IOs of this nexus, replacing the previous trace.
+    pub async fn start_trace(&self, opts: TraceOpts) -> Result<(), Error> {
+        let trace = Arc::new(NexusTrace::new(&self.name, opts)?);
+        info!("{}: tracing IOs {:?}", self.name, trace.opts);
+
+        if let Some(previous) = self.trace.lock().replace(trace.clone()) {
+            previous.stop();
+        }
+        self.install_trace(Some(trace)).await;
+        Ok(())
+    }
+
+    /// Stop tracing the IOs of this nexus. The records of the trace remain
+    /// available until the next one starts.
+    pub async fn stop_trace(&self) -> Option<TraceStats> {
+        let trace = self.trace()?;
+        info!("{}: stopped tracing IOs", self.name);
+        self.install_trace(None).await;
+        trace.stop();
+        Some(trace.stats())
+    }
+
+    /// Returns the counters of the last trace of this nexus.
+    pub fn trace_stats(&self) -> Option<TraceStats> {
+        self.trace.lock().as_ref().map(|t| t.stats())
+    }
+
+    /// Returns the last records of the last trace of this nexus, oldest
+    /// first.
+    pub fn trace_records(
+        &self,
+        max_entries: Option<usize>,
+    ) -> Vec<TraceRecord> {
+        self.trace
+            .lock()
+            .as_ref()
+            .map(|t| t.records(max_entries))
+            .unwrap_or_default()
+    }
+
+    /// install the trace on all channels
+    async fn install_trace(&self, trace: Option<Arc<NexusTrace>>) {
+        if self.has_io_device {
+            let r = for_each_channel(
+                self,
+                SetTraceCtx {
+                    trace,
+                },
+                set_trace_cb,
+            );
+            r.await.expect("set trace sender already dropped");
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct StartTraceArgs {
+    /// name of the nexus
+    name: String,
+    #[serde(flatten)]
+    opts: TraceOpts,
+}
+
+#[derive(Debug, Deserialize)]
+struct TraceArgs {
+    /// name of the nexus
+    name: String,
+    /// number of records to return, the most recent ones
+    #[serde(default)]
+    max_entries: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct TraceReply {
+    stats: Option<TraceStats>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    records: Vec<TraceRecord>,
+}
+
+async fn start_trace(args: StartTraceArgs) -> Result<TraceReply, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.start_trace(args.opts).await?;
+    Ok(TraceReply {
+        stats: nexus.trace_stats(),
+        records: Vec::new(),
+    })
+}
+
+async fn stop_trace(args: TraceArgs) -> Result<TraceReply, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.stop_trace().await;
+    Ok(TraceReply {
+        stats: nexus.trace_stats(),
+        records: Vec::new(),
+    })
+}
+
+async fn get_trace(args: TraceArgs) -> Result<TraceReply, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    Ok(TraceReply {
+        stats: nexus.trace_stats(),
+        records: nexus.trace_records(args.max_entries),
+    })
+}
+
+/// Register the json-rpc methods to trace the IOs of a nexus.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_trace_start",
+        |args: StartTraceArgs| start_trace(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_trace_stop",
+        |args: TraceArgs| stop_trace(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>("nexus_trace_get", |args: TraceArgs| {
+        get_trace(args).boxed_local()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_and_sampling() {
+        let trace = NexusTrace::new(
+            "nexus0",
+            TraceOpts {
+                sample: 2,
+                capacity: 3,
+                file: None,
+            },
+        )
+        .unwrap();
+
+        let sampled = (0 .. 10).filter_map(|_| trace.sample()).count();
+        assert_eq!(sampled, 5);
+
+        (0 .. 5).for_each(|i| {
+            trace.record(
+                Instant::now(),
+                IoType::Read,
+                i * 4096,
+                4096,
+                Some("child".into()),
+                true,
+            )
+        });
+        // other IO types are not traced
+        trace.record(Instant::now(), IoType::Flush, 0, 0, None, true);
+
+        let records = trace.records(None);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].offset, 2 * 4096);
+        assert_eq!(trace.records(Some(1))[0].offset, 4 * 4096);
+
+        let stats = trace.stats();
+        assert_eq!((stats.seen, stats.recorded, stats.lost), (10, 5, 0));
+    }
+
+    #[test]
+    fn invalid_opts() {
+        assert!(NexusTrace::new(
+            "nexus0",
+            TraceOpts {
+                sample: 0,
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,333 @@
+//!
+//! Validation of the creation of a nexus without creating it.
+//!
+//! A control plane placing a volume only learns that its children do not go
+//! together when the creation of the nexus fails, after the children have
+//! been connected to and the persistent entry of the nexus has been claimed.
+//! `nexus_validate_create` takes the arguments of a nexus creation and runs
+//! the checks the creation would run, reporting every problem it finds
+//! rather than the first one:
+//!
+//! - the name and uuid are not those of an existing nexus, and there are not
+//!   more children than a nexus takes
+//! - every child can be opened, a child that does not exist yet is created and
+//!   destroyed again, and is not a child of another nexus
+//! - the children have compatible block sizes and the same data partition, and
+//!   hold the requested size, and those that interleave protection information
+//!   with their data have the same format as the others
+//! - the persistent entry of the nexus, if there is one, knows the children and
+//!   has them healthy, as a nexus created on children the entry has as
+//!   unhealthy comes up on stale data
+//!
+//! Nothing is left behind: the devices created for the check are destroyed
+//! and the persistent entry is only read, so its epoch is not claimed.
+
+use std::cmp::{max, min};
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{
+    block_len_compatible,
+    max_children,
+    nexus_iter,
+    nexus_lookup_name_uuid,
+    Error,
+    NexusChild,
+    NexusInfo,
+};
+use crate::{
+    bdev::{device_create, device_destroy, device_lookup, uri, GetName},
+    core::{partition, ProtectionInfo},
+    jsonrpc::jsonrpc_register,
+    persistent_store::PersistentStore,
+};
+
+/// What the validation found out about a child.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChildValidation {
+    pub uri: String,
+    /// the device of the child could be opened
+    pub reachable: bool,
+    pub block_size: u64,
+    pub num_blocks: u64,
+    /// protection information format of the device, which the nexus does
+    /// not pass through
+    pub protection_info: Option<ProtectionInfo>,
+    /// name of the nexus the child belongs to already, if any
+    pub in_use_by: Option<String>,
+    /// health of the child in the persistent entry of the nexus, None if the
+    /// entry does not have it
+    pub persisted_healthy: Option<bool>,
+}
+
+/// Outcome of the validation of the creation of a nexus.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NexusValidation {
+    pub name: String,
+    /// the nexus can be created with these arguments
+    pub valid: bool,
+    /// block size and number of blocks the nexus would have, 0 if they can
+    /// not be worked out
+    pub block_size: u64,
+    pub num_blocks: u64,
+    pub children: Vec<ChildValidation>,
+    /// epoch of the persistent entry of the nexus, None if it has none
+    pub persisted_epoch: Option<u64>,
+    /// what prevents the creation, empty if it is valid
+    pub problems: Vec<String>,
+}
+
+/// Open the device of a child, creating it for the time of the check if it
+/// does not exist, and fill in its geometry.
+async fn probe_child(child: &mut ChildValidation, problems: &mut Vec<String>) {
+    let name = match uri::parse(&child.uri) {
+        Ok(device) => device.get_name(),
+        Err(e) => {
+            problems.push(format!("child {}: {}", child.uri, e));
+            return;
+        }
+    };
+
+    let created = device_lookup(&name).is_none();
+    if created {
+        if let Err(e) = device_create(&child.uri).await {
+            problems.push(format!("child {} unreachable: {}", child.uri, e));
+            return;
+        }
+    }
+
+    match device_lookup(&name) {
+        Some(dev) => {
+            child.reachable = true;
+            child.block_size = dev.block_len();
+            child.num_blocks = dev.num_blocks();
+            child.protection_info = dev.protection_info();
+        }
+        None => problems.push(format!("child {} has no device", child.uri)),
+    }
+
+    if created {
+        if let Err(e) = device_destroy(&child.uri).await {
+            warn!(
+                "failed to destroy device {} created for validation: {}",
+                child.uri, e
+            );
+        }
+    }
+}
+
+/// Returns the block size and number of blocks of a nexus of the size on
+/// the children, as `try_open_children` works them out.
+fn geometry(
+    size: u64,
+    children: &[ChildValidation],
+    problems: &mut Vec<String>,
+) -> Option<(u64, u64)> {
+    let mut start_byte = 0;
+    let mut end_byte = 0;
+    let mut blk_size = 0;
+    let mut max_blk_size = 0;
+
+    for child in children.iter().filter(|c| c.reachable) {
+        let (nb, bs) = (child.num_blocks, child.block_size);
+        if blk_size != 0 && !block_len_compatible(bs, blk_size) {
+            problems.push(format!(
+                "child {} has an incompatible block size {}",
+                child.uri, bs
+            ));
+            return None;
+        }
+        blk_size = if blk_size == 0 { bs } else { min(blk_size, bs) };
+        max_blk_size = max(max_blk_size, bs);
+
+        match partition::calc_data_partition(size, nb, bs) {
+            Some((start, end)) if start_byte == 0 => {
+                start_byte = start * bs;
+                end_byte = end * bs;
+            }
+            Some((start, end)) => {
+                end_byte = min(end_byte, end * bs);
+                if start_byte != start * bs {
+                    problems.push(format!(
+                        "child {} has a different data partition",
+                        child.uri
+                    ));
+                    return None;
+                }
+            }
+            None => {
+                problems.push(format!(
+                    "child {} of {} blocks of {} bytes is too small",
+                    child.uri, nb, bs
+                ));
+                return None;
+            }
+        }
+    }
+
+    if blk_size == 0 {
+        return None;
+    }
+    end_byte -= end_byte % max_blk_size;
+    if start_byte % max_blk_size != 0 || end_byte <= start_byte {
+        problems.push("the block sizes of the children do not align".into());
+        return None;
+    }
+    Some((blk_size, (end_byte - start_byte) / blk_size))
+}
+
+/// Check that a nexus can be created with the arguments, without creating
+/// it. The persistent entry is looked up under the key given, or under the
+/// uuid of the nexus.
+pub async fn nexus_validate_create(
+    name: &str,
+    size: u64,
+    uuid: Option<&str>,
+    children: &[String],
+    nexus_info_key: Option<String>,
+) -> Result<NexusValidation, Error> {
+    let nexus_uuid = match uuid {
+        Some(uuid) => {
+            Some(Uuid::parse_str(uuid).map_err(|_| Error::InvalidUuid {
+                uuid: uuid.to_string(),
+            })?)
+        }
+        None => None,
+    };
+
+    let mut v = NexusValidation {
+        name: name.to_string(),
+        ..Default::default()
+    };
+
+    if let Some(nexus) = nexus_lookup_name_uuid(name, nexus_uuid) {
+        v.problems.push(format!(
+            "nexus {} with uuid {} exists",
+            nexus.name,
+            nexus.uuid()
+        ));
+    }
+    if children.is_empty() {
+        v.problems.push("no children".into());
+    }
+    if children.len() > max_children() {
+        v.problems.push(format!(
+            "{} children, more than the maximum of {}",
+            children.len(),
+            max_children()
+        ));
+    }
+
+    for (i, uri) in children.iter().enumerate() {
+        let mut child = ChildValidation {
+            uri: uri.clone(),
+            ..Default::default()
+        };
+        if children[.. i].contains(uri) {
+            v.problems.push(format!("child {} given twice", uri));
+        }
+        child.in_use_by = nexus_iter()
+            .find(|n| n.children.iter().any(|c| c.get_name() == uri))
+            .map(|n| n.name.clone());
+        if let Some(nexus) = &child.in_use_by {
+            v.problems
+                .push(format!("child {} belongs to nexus {}", uri, nexus));
+        }
+
+        probe_child(&mut child, &mut v.problems).await;
+        v.children.push(child);
+    }
+    let pi = v
+        .children
+        .iter()
+        .map(|c| c.protection_info)
+        .collect::<Vec<_>>();
+    if ProtectionInfo::mixed_interleave(&pi) {
+        v.problems.push(
+            "children interleave protection information with their data \
+             differently"
+                .to_string(),
+        );
+    }
+
+    if let Some((block_size, num_blocks)) =
+        geometry(size, &v.children, &mut v.problems)
+    {
+        v.block_size = block_size;
+        v.num_blocks = num_blocks;
+    }
+
+    let key = nexus_info_key.or_else(|| nexus_uuid.map(|u| u.to_string()));
+    if let Some(key) = key.filter(|_| PersistentStore::enabled()) {
+        if let Some(info) = PersistentStore::get(&key)
+            .await
+            .ok()
+            .and_then(|value| serde_json::from_value::<NexusInfo>(value).ok())
+        {
+            v.persisted_epoch = Some(info.epoch);
+            for child in &mut v.children {
+                let uuid = match NexusChild::uuid(&child.uri) {
+                    Some(uuid) => uuid,
+                    None => continue,
+                };
+                child.persisted_healthy = info
+                    .children
+                    .iter()
+                    .find(|c| c.uuid == uuid)
+                    .map(|c| c.healthy);
+                if child.persisted_healthy == Some(false) {
+                    v.problems.push(format!(
+                        "child {} is unhealthy in the persistent entry {}",
+                        child.uri, key
+                    ));
+                }
+            }
+            if !info.children.is_empty()
+                && v.children.iter().all(|c| c.persisted_healthy.is_none())
+            {
+                v.problems.push(format!(
+                    "none of the children is known to the persistent entry {}",
+                    key
+                ));
+            }
+        }
+    }
+
+    v.valid = v.problems.is_empty();
+    Ok(v)
+}
+
+#[derive(Debug, Deserialize)]
+struct ValidateCreateArgs {
+    name: String,
+    size: u64,
+    #[serde(default)]
+    uuid: Option<String>,
+    children: Vec<String>,
+    /// key of the persistent entry of the nexus, its uuid if omitted
+    #[serde(default)]
+    nexus_info_key: Option<String>,
+}
+
+async fn validate_create(
+    args: ValidateCreateArgs,
+) -> Result<NexusValidation, Error> {
+    nexus_validate_create(
+        &args.name,
+        args.size,
+        args.uuid.as_deref(),
+        &args.children,
+        args.nexus_info_key,
+    )
+    .await
+}
+
+/// Register the json-rpc method validating the creation of a nexus.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_validate_create",
+        |args: ValidateCreateArgs| validate_create(args).boxed_local(),
+    );
+}
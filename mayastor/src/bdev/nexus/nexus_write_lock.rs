@@ -0,0 +1,225 @@
+//!
+//! Serialization of overlapping writes.
+//!
+//! Writes are submitted to all children at once, and nothing keeps two
+//! concurrent writes to the same blocks from being applied in a different
+//! order on each child. The initiator can not tell which one won until it
+//! reads the blocks back, but the children then hold different data and
+//! reads return either depending on the child they are served from. Well
+//! behaved initiators never have overlapping writes in flight, some do.
+//!
+//! When enabled, every write, write zeroes and unmap locks the range of
+//! blocks it covers on the nexus, across all cores, until it completes. A
+//! write overlapping one in flight is queued and submitted again on its own
+//! thread once the range is free, in the order it was submitted in. This
+//! costs a lock per write, so it is off by default. It is enabled for all
+//! nexuses in the nexus options, or per nexus with the
+//! `nexus_set_serialize_writes` json-rpc method, which is not persisted.
+
+use std::{
+    collections::VecDeque,
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use futures::FutureExt;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use spdk_rs::libspdk::spdk_bdev_io;
+
+use super::{nexus_lookup_any_mut, nexus_resubmit_request, Error, Nexus};
+use crate::{core::Mthread, jsonrpc::jsonrpc_register, subsys::Config};
+
+fn overlaps(a: &Range<u64>, b: &Range<u64>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// A write holding or waiting for the blocks it covers.
+struct Locker {
+    range: Range<u64>,
+    thread: Mthread,
+    io: *mut spdk_bdev_io,
+}
+
+// the IO is only ever touched on the thread it was submitted on
+unsafe impl Send for Locker {}
+
+#[derive(Debug)]
+struct IoPtr(*mut spdk_bdev_io);
+
+#[derive(Default)]
+struct Locks {
+    locked: Vec<Locker>,
+    waiting: VecDeque<Locker>,
+}
+
+/// Range locks of the writes of a nexus, shared by all channels.
+pub(crate) struct WriteLocks {
+    enabled: AtomicBool,
+    /// number of writes holding or waiting for a lock
+    count: AtomicUsize,
+    locks: Mutex<Locks>,
+}
+
+impl std::fmt::Debug for WriteLocks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "WriteLocks {{ enabled: {}, count: {} }}",
+            self.enabled.load(Ordering::Relaxed),
+            self.count.load(Ordering::Relaxed)
+        )
+    }
+}
+
+impl Default for WriteLocks {
+    fn default() -> Self {
+        Self {
+            enabled: AtomicBool::new(Config::get().nexus_opts.serialize_writes),
+            count: AtomicUsize::new(0),
+            locks: Mutex::new(Locks::default()),
+        }
+    }
+}
+
+impl WriteLocks {
+    /// Lock the range for the IO, returning false if the IO has been queued
+    /// behind an overlapping one, in which case it is resubmitted once it
+    /// holds the lock. An IO that is submitted again while holding the lock
+    /// keeps it.
+    pub(crate) fn lock(
+        &self,
+        range: Range<u64>,
+        io: *mut spdk_bdev_io,
+    ) -> bool {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        let mut locks = self.locks.lock();
+        if locks.locked.iter().any(|l| l.io == io) {
+            return true;
+        }
+
+        let locker = Locker {
+            range,
+            thread: Mthread::current().unwrap(),
+            io,
+        };
+        self.count.fetch_add(1, Ordering::Relaxed);
+        if locks
+            .locked
+            .iter()
+            .any(|l| overlaps(&l.range, &locker.range))
+            || locks
+                .waiting
+                .iter()
+                .any(|w| overlaps(&w.range, &locker.range))
+        {
+            locks.waiting.push_back(locker);
+            false
+        } else {
+            locks.locked.push(locker);
+            true
+        }
+    }
+
+    /// Release the lock held by the IO, if any, and resubmit the queued IOs
+    /// that may go now, each on the thread it was submitted on.
+    pub(crate) fn unlock(&self, io: *mut spdk_bdev_io) {
+        if self.count.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let granted = {
+            let mut locks = self.locks.lock();
+            match locks.locked.iter().position(|l| l.io == io) {
+                Some(i) => {
+                    locks.locked.swap_remove(i);
+                    self.count.fetch_sub(1, Ordering::Relaxed);
+                }
+                None => return,
+            }
+
+            let mut granted = Vec::new();
+            let mut waiting = VecDeque::new();
+            while let Some(w) = locks.waiting.pop_front() {
+                if locks.locked.iter().any(|l| overlaps(&l.range, &w.range))
+                    || waiting
+                        .iter()
+                        .any(|o: &Locker| overlaps(&o.range, &w.range))
+                {
+                    waiting.push_back(w);
+                } else {
+                    granted.push((w.thread, w.io));
+                    locks.locked.push(w);
+                }
+            }
+            locks.waiting = waiting;
+            granted
+        };
+
+        for (thread, io) in granted {
+            thread.msg(IoPtr(io), |p| nexus_resubmit_request(p.0));
+        }
+    }
+}
+
+impl<'n> Nexus<'n> {
+    /// Returns true if overlapping writes to the nexus are serialized.
+    pub fn serializes_writes(&self) -> bool {
+        self.write_locks.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Serialize overlapping writes to the nexus, or stop doing so. Writes
+    /// that hold a lock keep it until they complete.
+    pub fn set_serialize_writes(&self, enabled: bool) {
+        if self.write_locks.enabled.swap(enabled, Ordering::Relaxed) != enabled
+        {
+            info!(
+                "{}: overlapping writes {}",
+                self.name,
+                if enabled {
+                    "serialized"
+                } else {
+                    "no longer serialized"
+                }
+            );
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SerializeArgs {
+    /// name or uuid of the nexus
+    name: String,
+    enabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct SerializeReply {
+    enabled: bool,
+}
+
+async fn set_serialize_writes(
+    args: SerializeArgs,
+) -> Result<SerializeReply, Error> {
+    let nexus = nexus_lookup_any_mut(&args.name).ok_or_else(|| {
+        Error::NexusNotFound {
+            name: args.name.clone(),
+        }
+    })?;
+
+    nexus.set_serialize_writes(args.enabled);
+    Ok(SerializeReply {
+        enabled: nexus.serializes_writes(),
+    })
+}
+
+/// Register the json-rpc method to serialize overlapping writes.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nexus_set_serialize_writes",
+        |args: SerializeArgs| set_serialize_writes(args).boxed_local(),
+    );
+}
@@ -2,6 +2,7 @@ use std::{
     collections::HashMap,
     convert::TryFrom,
     ffi::{CStr, CString},
+    net::Ipv6Addr,
     os::raw::{c_char, c_int, c_ulong, c_void},
     ptr::copy_nonoverlapping,
 };
@@ -19,6 +20,7 @@ use spdk_rs::libspdk::{
     SPDK_NVME_IO_FLAGS_PRCHK_REFTAG,
     SPDK_NVME_TRANSPORT_TCP,
     SPDK_NVMF_ADRFAM_IPV4,
+    SPDK_NVMF_ADRFAM_IPV6,
 };
 
 use crate::{
@@ -55,7 +57,7 @@ impl TryFrom<&Url> for Nvmf {
 
     fn try_from(url: &Url) -> Result<Self, Self::Error> {
         let host =
-            url.host_str().ok_or_else(|| NexusBdevError::UriInvalid {
+            uri::host(url).ok_or_else(|| NexusBdevError::UriInvalid {
                 uri: url.to_string(),
                 message: String::from("missing host"),
             })?;
@@ -117,7 +119,7 @@ impl TryFrom<&Url> for Nvmf {
             name: url[url::Position::BeforeHost .. url::Position::AfterPath]
                 .into(),
             alias: url.to_string(),
-            host: host.to_string(),
+            host,
             port: url.port().unwrap_or(DEFAULT_NVMF_PORT),
             subnqn: segments[0].to_string(),
             prchk_flags,
@@ -302,7 +304,11 @@ impl NvmeCreateContext {
         }
 
         trid.trtype = SPDK_NVME_TRANSPORT_TCP;
-        trid.adrfam = SPDK_NVMF_ADRFAM_IPV4;
+        trid.adrfam = if nvmf.host.parse::<Ipv6Addr>().is_ok() {
+            SPDK_NVMF_ADRFAM_IPV6
+        } else {
+            SPDK_NVMF_ADRFAM_IPV4
+        };
 
         NvmeCreateContext {
             trid,
@@ -29,10 +29,18 @@ use crate::{
             controller_inner::SpdkNvmeController,
             nvme_bdev_running_config,
             NvmeControllerState,
+            PollState,
             NVME_CONTROLLERS,
         },
     },
-    core::{poller, BlockDevice, BlockDeviceIoStats, CoreError, IoType},
+    core::{
+        poller,
+        BlockDevice,
+        BlockDeviceIoStats,
+        CoreError,
+        IoChannelData,
+        IoType,
+    },
 };
 
 #[repr(C)]
@@ -55,14 +63,7 @@ impl<'a> NvmeIoChannel<'a> {
     pub fn inner_from_channel(
         io_channel: *mut spdk_io_channel,
     ) -> &'a mut NvmeIoChannelInner<'a> {
-        NvmeIoChannel::from_raw(Self::io_channel_ctx(io_channel)).inner_mut()
-    }
-
-    #[inline]
-    fn io_channel_ctx(ch: *mut spdk_io_channel) -> *mut c_void {
-        unsafe {
-            (ch as *mut u8).add(size_of::<spdk_io_channel>()) as *mut c_void
-        }
+        NvmeIoChannel::from_channel(io_channel).inner_mut()
     }
 }
 
@@ -132,6 +133,12 @@ impl IoQpair {
         opts.io_queue_requests =
             max(opts.io_queue_requests, default_opts.io_queue_requests);
         opts.create_only = true;
+        // queue the commands submitted during a poll iteration and submit
+        // them in a batch, with a single doorbell write, when the poller
+        // processes the completions of the qpair; with a polling period they
+        // would wait for the next poll, so they are submitted right away
+        opts.delay_cmd_submit = default_opts.delay_cmd_submit
+            && default_opts.nvme_ioq_poll_period_us == 0;
 
         opts
     }
@@ -272,6 +279,11 @@ pub struct NvmeIoChannelInner<'a> {
         std::sync::Arc<parking_lot::Mutex<crate::bdev::NvmeController<'a>>>,
     >,
     num_pending_ios: u64,
+    /// number of I/O operations submitted since the last poll, which are
+    /// queued on the qpair when command submission is delayed
+    num_queued_ios: u64,
+    /// when the qpair is polled next
+    poll_state: PollState,
 
     // Flag to indicate the shutdown state of the channel.
     // We need such a flag to differentiate between channel reset and shutdown.
@@ -325,6 +337,7 @@ impl NvmeIoChannelInner<'_> {
     #[inline]
     pub fn account_io(&mut self) {
         self.num_pending_ios += 1;
+        self.num_queued_ios += 1;
     }
 
     /// Discard active I/O operation for channel.
@@ -435,7 +448,7 @@ impl IoStatsController {
                 self.io_stats.num_unmap_ops += num_ops;
                 self.io_stats.bytes_unmapped += num_blocks;
             }
-            IoType::WriteZeros => {}
+            IoType::WriteZeros | IoType::Flush => {}
             _ => {
                 warn!("Unsupported I/O type for I/O statistics: {:?}", op);
             }
@@ -479,6 +492,11 @@ extern "C" fn disconnected_qpair_cb(
 extern "C" fn nvme_poll(ctx: *mut c_void) -> i32 {
     let inner = NvmeIoChannel::from_raw(ctx).inner_mut();
 
+    // processing the completions also submits the queued commands
+    let num_queued = std::mem::take(&mut inner.num_queued_ios);
+    if !inner.poll_state.poll_due(num_queued, inner.num_pending_ios) {
+        return 0;
+    }
     let num_completions = unsafe {
         spdk_nvme_poll_group_process_completions(
             inner.poll_group.as_ptr(),
@@ -487,23 +505,26 @@ extern "C" fn nvme_poll(ctx: *mut c_void) -> i32 {
         )
     };
 
-    if num_completions > 0 {
+    if num_completions > 0 || num_queued > 0 {
         1
     } else {
         0
     }
 }
 
-impl NvmeControllerIoChannel {
-    pub extern "C" fn create(device: *mut c_void, ctx: *mut c_void) -> i32 {
-        let id = device as u64;
+impl<'a> IoChannelData for NvmeIoChannel<'a> {
+    /// Create the qpair and the poller of a new I/O channel of an NVMe
+    /// controller.
+    fn create(device: NonNull<c_void>, ctx: NonNull<c_void>) -> Option<Self> {
+        let id = device.as_ptr() as u64;
+        let ctx = ctx.as_ptr();
 
         debug!("Creating IO channel for controller ID 0x{:X}", id);
 
         let carc = match NVME_CONTROLLERS.lookup_by_name(id.to_string()) {
             None => {
                 error!("No NVMe controller found for ID 0x{:X}", id);
-                return 1;
+                return None;
             }
             Some(c) => c,
         };
@@ -517,7 +538,7 @@ impl NvmeControllerIoChannel {
                     controller.get_name(),
                     controller.get_state()
                 );
-                return 1;
+                return None;
             }
             // Release controller's lock before proceeding to avoid deadlocks,
             // as qpair-related operations might hang in case of
@@ -536,8 +557,6 @@ impl NvmeControllerIoChannel {
             )
         };
 
-        let nvme_channel = NvmeIoChannel::from_raw(ctx);
-
         // Get a block device that corresponds to the controller.
         let device = match device_lookup(&cname) {
             Some(device) => device,
@@ -546,7 +565,7 @@ impl NvmeControllerIoChannel {
                     "{} no block device exists for controller, I/O channel creation not possible",
                     cname,
                 );
-                return 1;
+                return None;
             }
         };
 
@@ -555,7 +574,7 @@ impl NvmeControllerIoChannel {
             Ok(qpair) => qpair,
             Err(e) => {
                 error!(?cname, ?e, "Failed to allocate qpair");
-                return 1;
+                return None;
             }
         };
         debug!(?cname, "I/O qpair successfully created");
@@ -565,7 +584,7 @@ impl NvmeControllerIoChannel {
             Ok(poll_group) => poll_group,
             Err(e) => {
                 error!(?cname, ?e, "Failed to create a poll group");
-                return 1;
+                return None;
             }
         };
 
@@ -573,11 +592,12 @@ impl NvmeControllerIoChannel {
         let mut rc = poll_group.add_qpair(&qpair);
         if rc != 0 {
             error!(?cname, ?rc, "failed to add qpair to poll group");
-            return 1;
+            return None;
         }
 
         // Create poller.
         let poller = poller::Builder::new()
+            .with_name("nvme_poll_ioq")
             .with_interval(nvme_bdev_running_config().nvme_ioq_poll_period_us)
             .with_poll_fn(move || nvme_poll(ctx))
             .build();
@@ -587,7 +607,7 @@ impl NvmeControllerIoChannel {
         if rc != 0 {
             error!(?cname, ?rc, "failed to connect qpair");
             poll_group.remove_qpair(&qpair);
-            return 1;
+            return None;
         }
 
         let inner = Box::new(NvmeIoChannelInner {
@@ -599,24 +619,26 @@ impl NvmeControllerIoChannel {
             device,
             ctrl: Some(carc),
             num_pending_ios: 0,
+            num_queued_ios: 0,
+            poll_state: PollState::new(),
         });
 
-        nvme_channel.inner = Box::into_raw(inner);
         debug!(?cname, ?ctx, "I/O channel successfully initialized");
-        0
+        Some(Self {
+            inner: Box::into_raw(inner),
+        })
     }
 
-    /// Callback function to be invoked by SPDK to deinitialize I/O channel for
-    /// NVMe controller.
-    pub extern "C" fn destroy(device: *mut c_void, ctx: *mut c_void) {
+    /// Deinitialize an I/O channel of an NVMe controller.
+    fn destroy(self, device: NonNull<c_void>) {
+        let device = device.as_ptr();
         debug!(
             "Destroying IO channel for controller ID 0x{:X}",
             device as u64
         );
 
         {
-            let ch = NvmeIoChannel::from_raw(ctx);
-            let mut inner = unsafe { Box::from_raw(ch.inner) };
+            let mut inner = unsafe { Box::from_raw(self.inner) };
 
             // Stop the poller and do extra handling for I/O qpair, as it needs
             // to be detached from the poller prior poller
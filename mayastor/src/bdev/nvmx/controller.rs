@@ -19,15 +19,17 @@ use spdk_rs::libspdk::{
     spdk_nvme_ctrlr,
     spdk_nvme_ctrlr_fail,
     spdk_nvme_ctrlr_get_ns,
+    spdk_nvme_ctrlr_get_transport_id,
     spdk_nvme_ctrlr_is_active_ns,
     spdk_nvme_ctrlr_register_aer_callback,
     spdk_nvme_ctrlr_reset,
+    spdk_nvme_ctrlr_set_trid,
     spdk_nvme_detach,
 };
 
 use crate::{
     bdev::nvmx::{
-        channel::{NvmeControllerIoChannel, NvmeIoChannel, NvmeIoChannelInner},
+        channel::{NvmeIoChannel, NvmeIoChannelInner},
         controller_inner::{SpdkNvmeController, TimeoutConfig},
         controller_state::{
             ControllerFailureReason,
@@ -38,6 +40,7 @@ use crate::{
         uri::NvmeControllerContext,
         utils::{
             nvme_cpl_succeeded,
+            resolve_host,
             NvmeAerInfoNotice,
             NvmeAerInfoNvmCommandSet,
             NvmeAerType,
@@ -58,7 +61,7 @@ use crate::{
         OpCompletionCallback,
         OpCompletionCallbackArg,
     },
-    ffihelper::{cb_arg, done_cb},
+    ffihelper::{cb_arg, done_cb, AsStr},
     nexus_uri::NexusBdevError,
     sleep::mayastor_sleep,
 };
@@ -71,6 +74,7 @@ struct ResetCtx {
     spdk_handle: SpdkNvmeController,
     io_device: Arc<IoDevice>,
     shutdown_in_progress: bool,
+    hostname: Option<String>,
 }
 
 struct ShutdownCtx {
@@ -85,11 +89,9 @@ impl<'a> NvmeControllerInner<'a> {
         name: String,
         cfg: NonNull<TimeoutConfig>,
     ) -> Self {
-        let io_device = Arc::new(IoDevice::new::<NvmeIoChannel>(
+        let io_device = Arc::new(IoDevice::register::<NvmeIoChannel>(
             NonNull::new(ctrlr.as_ptr().cast()).unwrap(),
             &name,
-            Some(NvmeControllerIoChannel::create),
-            Some(NvmeControllerIoChannel::destroy),
         ));
 
         let adminq_poller = poller::Builder::new()
@@ -126,6 +128,8 @@ pub struct NvmeController<'a> {
     id: u64,
     prchk_flags: u32,
     inner: Option<NvmeControllerInner<'a>>,
+    /// hostname of the target, re-resolved before every reset
+    hostname: Option<String>,
     state_machine: ControllerStateMachine,
     event_dispatcher: Mutex<DeviceEventDispatcher>,
     /// Timeout config is accessed by SPDK-driven timeout callback handlers,
@@ -156,6 +160,7 @@ impl<'a> NvmeController<'a> {
             prchk_flags,
             state_machine: ControllerStateMachine::new(name),
             inner: None,
+            hostname: None,
             event_dispatcher: Mutex::new(DeviceEventDispatcher::new()),
             timeout_config: NonNull::new(Box::into_raw(Box::new(
                 TimeoutConfig::new(name),
@@ -336,6 +341,7 @@ impl<'a> NvmeController<'a> {
                 .expect("controller is may not be NULL"),
             io_device,
             shutdown_in_progress: false,
+            hostname: self.hostname.clone(),
         };
 
         debug!("{}: starting reset", self.name);
@@ -610,6 +616,7 @@ impl<'a> NvmeController<'a> {
             spdk_handle: self.controller().expect("controller may not be NULL"),
             io_device,
             shutdown_in_progress: false,
+            hostname: None,
         };
 
         let inner = self.inner.as_mut().unwrap();
@@ -644,6 +651,14 @@ impl<'a> NvmeController<'a> {
             return;
         }
 
+        if let Some(hostname) = &reset_ctx.hostname {
+            NvmeController::_reset_resolve(
+                &reset_ctx.name,
+                hostname,
+                reset_ctx.spdk_handle,
+            );
+        }
+
         let rc =
             unsafe { spdk_nvme_ctrlr_reset(reset_ctx.spdk_handle.as_ptr()) };
         if rc != 0 {
@@ -671,6 +686,58 @@ impl<'a> NvmeController<'a> {
         }
     }
 
+    /// Resolve the hostname of the target again before reconnecting to it,
+    /// and retarget the controller when the address changed. The controller
+    /// reconnects to its current address when the hostname does not resolve.
+    fn _reset_resolve(
+        name: &str,
+        hostname: &str,
+        spdk_handle: SpdkNvmeController,
+    ) {
+        let trid =
+            unsafe { &*spdk_nvme_ctrlr_get_transport_id(spdk_handle.as_ptr()) };
+        let current = trid.traddr.as_str().to_string();
+        let port = trid.trsvcid.as_str().parse::<u16>().unwrap_or_default();
+
+        let address = match resolve_host(hostname, port) {
+            Ok(address) if address == current => return,
+            Ok(address) => address,
+            Err(e) => {
+                warn!(
+                    "{} failed to resolve {}: {}, reconnecting to {}",
+                    name, hostname, e, current
+                );
+                return;
+            }
+        };
+
+        let new_trid = transport::Builder::new()
+            .with_subnqn(trid.subnqn.as_str())
+            .with_svcid(trid.trsvcid.as_str())
+            .with_traddr(&address)
+            .build();
+
+        // the transport ID of a controller can only be changed once it failed
+        spdk_handle.fail();
+        let rc = unsafe {
+            spdk_nvme_ctrlr_set_trid(
+                spdk_handle.as_ptr(),
+                new_trid.as_ptr() as *mut _,
+            )
+        };
+        if rc != 0 {
+            error!(
+                "{} failed to retarget from {} to {}, rc = {}",
+                name, current, address, rc
+            );
+        } else {
+            info!(
+                "{}: {} moved from {} to {}, reconnecting to it",
+                name, hostname, current, address
+            );
+        }
+    }
+
     fn _reset_create_channels(
         channel: &mut NvmeIoChannelInner,
         reset_ctx: &mut ResetCtx,
@@ -984,6 +1051,7 @@ pub(crate) fn connected_attached_cb(
     // set the controller as a pointer within the context of the time out config
     unsafe { controller.timeout_config.as_mut().set_controller(ctrlr) };
     controller.set_id(cid);
+    controller.hostname = ctx.hostname();
     controller.inner = Some(NvmeControllerInner::new(
         ctrlr,
         controller.get_name(),
@@ -1160,7 +1228,7 @@ pub(crate) mod options {
 }
 
 pub(crate) mod transport {
-    use std::{ffi::CStr, fmt::Debug, ptr::copy_nonoverlapping};
+    use std::{ffi::CStr, fmt::Debug, net::Ipv6Addr, ptr::copy_nonoverlapping};
 
     use libc::c_void;
 
@@ -1289,11 +1357,15 @@ pub(crate) mod transport {
             self
         }
 
-        /// builder for transportID currently defaults to TCP IPv4
-        pub fn build(self) -> NvmeTransportId {
+        /// builder for transportID currently defaults to TCP, over IPv6 when
+        /// the address is an IPv6 literal and IPv4 otherwise
+        pub fn build(mut self) -> NvmeTransportId {
+            if self.traddr.parse::<Ipv6Addr>().is_ok() {
+                self.adrfam = AdressFamily::NvmfAdrfamIpv6;
+            }
             let trtype = String::from(TransportId::TCP);
             let mut trid = spdk_nvme_transport_id {
-                adrfam: AdressFamily::NvmfAdrfamIpv4 as u32,
+                adrfam: self.adrfam as u32,
                 trtype: TransportId::TCP as u32,
                 ..Default::default()
             };
@@ -1341,6 +1413,19 @@ pub(crate) mod transport {
             assert_eq!(transport.traddr(), "127.0.0.1");
             assert_eq!(transport.subnqn(), "nqn.2021-01-01:test.nqn");
             assert_eq!(transport.svcid(), "4420");
+            assert_eq!(transport.0.adrfam, 0x1);
+        }
+
+        #[test]
+        fn test_transport_id_ipv6() {
+            let transport = transport::Builder::new()
+                .with_subnqn("nqn.2021-01-01:test.nqn")
+                .with_svcid("4420")
+                .with_traddr("fd00::1")
+                .build();
+
+            assert_eq!(transport.traddr(), "fd00::1");
+            assert_eq!(transport.0.adrfam, 0x2);
         }
     }
 }
@@ -24,6 +24,8 @@ use crate::{
         DeviceIoController,
         DeviceTimeoutAction,
         IoType,
+        ProtectionInfo,
+        SglCaps,
     },
     ffihelper::{cb_arg, done_cb},
 };
@@ -189,6 +191,26 @@ impl BlockDevice for NvmeBlockDevice {
         }
     }
 
+    fn sgl_caps(&self) -> SglCaps {
+        // the data blocks of SGLs must be dword aligned
+        SglCaps {
+            sgl: self.ns.supports_sgl(),
+            buf_align: 4,
+        }
+    }
+
+    fn protection_info(&self) -> Option<ProtectionInfo> {
+        if self.ns.md_size() == 0 || self.ns.pi_type() == 0 {
+            return None;
+        }
+
+        Some(ProtectionInfo {
+            md_size: self.ns.md_size(),
+            pi_type: self.ns.pi_type(),
+            md_interleave: self.ns.supports_extended_lba(),
+        })
+    }
+
     async fn io_stats(&self) -> Result<BlockDeviceIoStats, CoreError> {
         let carc = NVME_CONTROLLERS.lookup_by_name(&self.name).ok_or(
             CoreError::BdevNotFound {
@@ -17,6 +17,7 @@ use spdk_rs::{
         spdk_nvme_ctrlr_cmd_io_raw,
         spdk_nvme_dsm_range,
         spdk_nvme_ns_cmd_dataset_management,
+        spdk_nvme_ns_cmd_flush,
         spdk_nvme_ns_cmd_read,
         spdk_nvme_ns_cmd_readv,
         spdk_nvme_ns_cmd_write,
@@ -45,7 +46,7 @@ use crate::{
         NVME_CONTROLLERS,
     },
     core::{
-        mempool::MemoryPool,
+        mempool::{MemoryPool, MemoryPoolStats},
         BlockDevice,
         BlockDeviceHandle,
         CoreError,
@@ -361,6 +362,9 @@ fn io_type_to_err(
             offset: offset_blocks,
             len: num_blocks,
         },
+        IoType::Flush => CoreError::FlushDispatch {
+            source,
+        },
         IoType::NvmeIo => CoreError::NvmeIoPassthruDispatch {
             source,
             opcode: 0xff,
@@ -383,6 +387,11 @@ pub fn nvme_io_ctx_pool_init(size: u64) {
     });
 }
 
+/// Returns the usage of the memory pool of NVMe controller I/O contexts.
+pub(crate) fn nvme_io_ctx_pool_stats() -> Option<MemoryPoolStats> {
+    NVME_IOCTX_POOL.get().map(|p| p.stats())
+}
+
 /// Allocate an NVMe controller I/O context from the pool.
 fn alloc_nvme_io_ctx(
     op: IoType,
@@ -943,6 +952,53 @@ impl BlockDeviceHandle for NvmeDeviceHandle {
         }
     }
 
+    fn flush_io(
+        &self,
+        cb: IoCompletionCallback,
+        cb_arg: IoCompletionCallbackArg,
+    ) -> Result<(), CoreError> {
+        let channel = self.io_channel.as_ptr();
+        let inner = NvmeIoChannel::inner_from_channel(channel);
+
+        // Make sure channel allows I/O
+        check_channel_for_io(IoType::Flush, inner, 0, 0)?;
+
+        let bio = alloc_nvme_io_ctx(
+            IoType::Flush,
+            NvmeIoCtx {
+                cb,
+                cb_arg,
+                iov: std::ptr::null_mut() as *mut iovec, // No I/O vec involved.
+                iovcnt: 0,
+                iovpos: 0,
+                iov_offset: 0,
+                channel,
+                op: IoType::Flush,
+                num_blocks: 0,
+            },
+            0,
+            0,
+        )?;
+
+        let rc = unsafe {
+            spdk_nvme_ns_cmd_flush(
+                self.ns.as_ptr(),
+                inner.qpair.as_mut().unwrap().as_ptr(),
+                Some(nvme_io_done),
+                bio as *mut c_void,
+            )
+        };
+
+        if rc < 0 {
+            Err(CoreError::FlushDispatch {
+                source: Errno::from_i32(-rc),
+            })
+        } else {
+            inner.account_io();
+            Ok(())
+        }
+    }
+
     async fn create_snapshot(&self) -> Result<u64, CoreError> {
         let mut cmd = spdk_nvme_cmd::default();
         cmd.set_opc(nvme_admin_opc::CREATE_SNAPSHOT.into());
@@ -7,8 +7,11 @@ pub use channel::{NvmeControllerIoChannel, NvmeIoChannel, NvmeIoChannelInner};
 pub use controller::NvmeController;
 pub use controller_state::NvmeControllerState;
 pub use device::{lookup_by_name, open_by_name, NvmeBlockDevice};
+pub(crate) use handle::nvme_io_ctx_pool_stats;
 pub use handle::{nvme_io_ctx_pool_init, NvmeDeviceHandle};
 pub use namespace::NvmeNamespace;
+pub use poll::NvmePollStatus;
+pub(crate) use poll::{poll_opts, PollState};
 pub(crate) use uri::NvmfDeviceTemplate;
 
 use crate::{
@@ -23,6 +26,7 @@ mod controller_state;
 mod device;
 mod handle;
 mod namespace;
+mod poll;
 mod uri;
 pub mod utils;
 
@@ -118,3 +122,8 @@ pub static NVME_CONTROLLERS: Lazy<NVMeCtlrList> =
 pub fn nvme_bdev_running_config() -> &'static NvmeBdevOpts {
     &Config::get().nvme_bdev_opts
 }
+
+/// Register the json-rpc methods of the NVMe children.
+pub(crate) fn register_jsonrpc_methods() {
+    poll::register_jsonrpc_methods();
+}
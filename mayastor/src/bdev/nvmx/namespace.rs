@@ -1,15 +1,20 @@
 use std::ptr::NonNull;
 
 use spdk_rs::libspdk::{
+    spdk_nvme_ctrlr_get_flags,
     spdk_nvme_ns,
+    spdk_nvme_ns_get_ctrlr,
     spdk_nvme_ns_get_extended_sector_size,
     spdk_nvme_ns_get_flags,
     spdk_nvme_ns_get_md_size,
     spdk_nvme_ns_get_num_sectors,
     spdk_nvme_ns_get_optimal_io_boundary,
+    spdk_nvme_ns_get_pi_type,
     spdk_nvme_ns_get_size,
     spdk_nvme_ns_get_uuid,
     spdk_nvme_ns_supports_compare,
+    spdk_nvme_ns_supports_extended_lba,
+    SPDK_NVME_CTRLR_SGL_SUPPORTED,
     SPDK_NVME_NS_DEALLOCATE_SUPPORTED,
     SPDK_NVME_NS_WRITE_ZEROES_SUPPORTED,
 };
@@ -61,6 +66,16 @@ impl NvmeNamespace {
         }
     }
 
+    /// returns true if the controller of the namespace takes SGLs, rather
+    /// than PRPs only
+    pub fn supports_sgl(&self) -> bool {
+        unsafe {
+            spdk_nvme_ctrlr_get_flags(spdk_nvme_ns_get_ctrlr(self.0.as_ptr()))
+                & SPDK_NVME_CTRLR_SGL_SUPPORTED as u64
+                > 0
+        }
+    }
+
     pub fn alignment(&self) -> u64 {
         unsafe { spdk_nvme_ns_get_optimal_io_boundary(self.0.as_ptr()) as u64 }
     }
@@ -69,6 +84,16 @@ impl NvmeNamespace {
         unsafe { spdk_nvme_ns_get_md_size(self.0.as_ptr()) as u64 }
     }
 
+    /// returns the PI type of the namespace, 0 if PI is disabled
+    pub fn pi_type(&self) -> u8 {
+        unsafe { spdk_nvme_ns_get_pi_type(self.0.as_ptr()) as u8 }
+    }
+
+    /// returns true if the metadata is part of the extended block
+    pub fn supports_extended_lba(&self) -> bool {
+        unsafe { spdk_nvme_ns_supports_extended_lba(self.0.as_ptr()) }
+    }
+
     pub fn from_ptr(ns: *mut spdk_nvme_ns) -> NvmeNamespace {
         NonNull::new(ns)
             .map(NvmeNamespace)
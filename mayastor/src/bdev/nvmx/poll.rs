@@ -0,0 +1,210 @@
+//!
+//! Polling of the IO qpairs of NVMe children adapted to their load.
+//!
+//! The IO qpairs of the NVMe children are polled for completions on every
+//! iteration of their reactor by default. At high IOPS every poll finds
+//! completions, but at low IOPS most polls find none and only burn the CPU
+//! of the reactor, which its other pollers could use. With adaptive polling,
+//! each channel measures the IOPS of its qpair and sets the period of its
+//! next poll from it:
+//!
+//! - a qpair IO was submitted to since its last poll is polled right away, as
+//!   polling it also submits the commands queued on it
+//! - a qpair without IO outstanding is polled every `idle_period_us`, to notice
+//!   that it is disconnected
+//! - the completions of a qpair under `low_load_iops` are left to coalesce for
+//!   up to `max_coalesce_us`, the less the closer it is to it
+//! - a qpair at or over `low_load_iops` is polled on every iteration, so that
+//!   the latency at high IOPS is that of polling
+//!
+//! Adaptive polling is off by default: coalescing adds up to
+//! `max_coalesce_us` to the latency of an IO at low load, which is a trade
+//! for CPU a deployment has to opt in to, by setting `NVME_ADAPTIVE_POLL` or
+//! `adaptive` in the `nvme_poll_opts` of the configuration.
+//!
+//! The options start with the `nvme_poll_opts` of the configuration and are
+//! changed at runtime with the `nvme_set_poll_opts` json-rpc method. The
+//! `nvme_poll_opts` method reports them along with the number of polls done
+//! and skipped by all channels.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crossbeam::atomic::AtomicCell;
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    jsonrpc::{jsonrpc_register, JsonRpcError},
+    subsys::{Config, NvmePollOpts},
+};
+
+/// options of the polling of all channels
+static OPTS: Lazy<AtomicCell<NvmePollOpts>> =
+    Lazy::new(|| AtomicCell::new(Config::get().nvme_poll_opts));
+
+/// number of polls done by all channels
+static POLLS: AtomicU64 = AtomicU64::new(0);
+
+/// number of polls skipped by all channels
+static SKIPPED: AtomicU64 = AtomicU64::new(0);
+
+/// time over which the IOPS of a qpair are measured
+const WINDOW: Duration = Duration::from_millis(100);
+
+/// returns the options of the polling of the NVMe children
+pub(crate) fn poll_opts() -> NvmePollOpts {
+    OPTS.load()
+}
+
+/// Returns the period of the next poll of a qpair at the IOPS, with IO
+/// outstanding or not.
+fn period(opts: &NvmePollOpts, iops: u64, pending: u64) -> Duration {
+    let us = if pending == 0 {
+        opts.idle_period_us
+    } else if iops >= opts.low_load_iops {
+        0
+    } else {
+        opts.max_coalesce_us * (opts.low_load_iops - iops) / opts.low_load_iops
+    };
+    Duration::from_micros(us)
+}
+
+/// Polling state of the qpair of a channel.
+#[derive(Debug)]
+pub(crate) struct PollState {
+    /// time the next poll is due
+    next: Instant,
+    /// start of the current measurement window
+    window_start: Instant,
+    /// number of IOs submitted in the current window
+    window_ios: u64,
+    /// IOPS measured over the last window
+    iops: u64,
+}
+
+impl PollState {
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            next: now,
+            window_start: now,
+            window_ios: 0,
+            iops: 0,
+        }
+    }
+
+    /// Returns true if the qpair is to be polled now, given the number of
+    /// IOs submitted to it since the last poll and outstanding on it.
+    pub(crate) fn poll_due(&mut self, submitted: u64, pending: u64) -> bool {
+        let now = Instant::now();
+        self.window_ios += submitted;
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= WINDOW {
+            self.iops =
+                self.window_ios * 1_000_000 / elapsed.as_micros().max(1) as u64;
+            self.window_ios = 0;
+            self.window_start = now;
+        }
+
+        let opts = poll_opts();
+        if !opts.adaptive || submitted > 0 || now >= self.next {
+            self.next = now + period(&opts, self.iops, pending);
+            POLLS.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        SKIPPED.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+}
+
+/// The options of the polling of the NVMe children and its counters.
+#[derive(Debug, Clone, Serialize)]
+pub struct NvmePollStatus {
+    #[serde(flatten)]
+    pub opts: NvmePollOpts,
+    /// number of polls done by all channels
+    pub polls: u64,
+    /// number of polls skipped by all channels
+    pub skipped: u64,
+}
+
+/// Changes to the options, the options not given are kept.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PollOptsUpdate {
+    adaptive: Option<bool>,
+    idle_period_us: Option<u64>,
+    max_coalesce_us: Option<u64>,
+    low_load_iops: Option<u64>,
+}
+
+fn status() -> NvmePollStatus {
+    NvmePollStatus {
+        opts: poll_opts(),
+        polls: POLLS.load(Ordering::Relaxed),
+        skipped: SKIPPED.load(Ordering::Relaxed),
+    }
+}
+
+async fn get_opts(_: ()) -> Result<NvmePollStatus, JsonRpcError> {
+    Ok(status())
+}
+
+async fn set_opts(
+    update: PollOptsUpdate,
+) -> Result<NvmePollStatus, JsonRpcError> {
+    let mut opts = poll_opts();
+    if let Some(adaptive) = update.adaptive {
+        opts.adaptive = adaptive;
+    }
+    if let Some(period) = update.idle_period_us {
+        opts.idle_period_us = period;
+    }
+    if let Some(coalesce) = update.max_coalesce_us {
+        opts.max_coalesce_us = coalesce;
+    }
+    if let Some(iops) = update.low_load_iops {
+        opts.low_load_iops = iops;
+    }
+    OPTS.store(opts);
+    info!("NVMe poll options set to {:?}", opts);
+    Ok(status())
+}
+
+/// Register the json-rpc methods reporting and setting the options of the
+/// polling of the NVMe children.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, JsonRpcError>("nvme_poll_opts", |args: ()| {
+        get_opts(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, JsonRpcError>(
+        "nvme_set_poll_opts",
+        |args: PollOptsUpdate| set_opts(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn period() {
+        let opts = NvmePollOpts {
+            adaptive: true,
+            idle_period_us: 1000,
+            max_coalesce_us: 50,
+            low_load_iops: 2000,
+        };
+        let us =
+            |iops, pending| super::period(&opts, iops, pending).as_micros();
+        assert_eq!(us(0, 0), 1000);
+        assert_eq!(us(0, 1), 50);
+        assert_eq!(us(1000, 4), 25);
+        assert_eq!(us(2000, 4), 0);
+        assert_eq!(us(100_000, 32), 0);
+    }
+}
@@ -13,6 +13,7 @@ use std::{
     collections::HashMap,
     convert::{From, TryFrom},
     ffi::c_void,
+    net::IpAddr,
     ptr::NonNull,
     sync::Arc,
 };
@@ -35,6 +36,7 @@ use crate::{
         nvmx::{
             controller,
             controller_inner::SpdkNvmeController,
+            utils,
             NvmeControllerState,
             NVME_CONTROLLERS,
         },
@@ -45,7 +47,7 @@ use crate::{
     core::poller,
     ffihelper::ErrnoResult,
     nexus_uri::{self, NexusBdevError},
-    subsys::Config,
+    subsys::{connect_host_nqn, Config},
 };
 
 use super::controller::transport::NvmeTransportId;
@@ -104,7 +106,7 @@ pub struct NvmfDeviceTemplate {
     subnqn: String,
     /// Enable protection information checking (reftag, guard)
     prchk_flags: u32,
-    /// uuid of the spdk bdev
+    /// uuid the namespace connected to must have, i.e. that of the replica
     uuid: Option<uuid::Uuid>,
 }
 
@@ -113,7 +115,7 @@ impl TryFrom<&Url> for NvmfDeviceTemplate {
 
     fn try_from(url: &Url) -> Result<Self, Self::Error> {
         let host =
-            url.host_str().ok_or_else(|| NexusBdevError::UriInvalid {
+            uri::host(url).ok_or_else(|| NexusBdevError::UriInvalid {
                 uri: url.to_string(),
                 message: String::from("missing host"),
             })?;
@@ -170,11 +172,22 @@ impl TryFrom<&Url> for NvmfDeviceTemplate {
             },
         )?;
 
+        // the URI is listed, logged and persisted, so the token of the share
+        // is set apart, and the URI is not repeated in the error
+        if parameters.contains_key("token") {
+            return Err(NexusBdevError::UriInvalid {
+                uri: url[.. url::Position::AfterPath].to_string(),
+                message: String::from(
+                    "the token of a share is set with nvmf_set_connect_token",
+                ),
+            });
+        }
+
         Ok(NvmfDeviceTemplate {
             name: url[url::Position::BeforeHost .. url::Position::AfterPath]
                 .to_string(),
             alias: url.to_string(),
-            host: host.to_string(),
+            host,
             port: url.port().unwrap_or(DEFAULT_NVMF_PORT),
             subnqn: segments[0].to_string(),
             prchk_flags,
@@ -194,6 +207,8 @@ pub(crate) struct NvmeControllerContext<'probe> {
     opts: NvmeControllerOpts,
     name: String,
     trid: NvmeTransportId,
+    /// hostname of the target, None if it was given by address
+    hostname: Option<String>,
     sender: Option<oneshot::Sender<Result<(), Errno>>>,
     receiver: oneshot::Receiver<Result<(), Errno>>,
     poller: Option<Poller<'probe>>,
@@ -201,13 +216,22 @@ pub(crate) struct NvmeControllerContext<'probe> {
 }
 
 impl<'probe> NvmeControllerContext<'probe> {
-    pub fn new(template: &NvmfDeviceTemplate) -> NvmeControllerContext {
+    pub fn new(
+        template: &NvmfDeviceTemplate,
+        address: &str,
+    ) -> NvmeControllerContext {
         let trid = controller::transport::Builder::new()
             .with_subnqn(&template.subnqn)
             .with_svcid(&template.port.to_string())
-            .with_traddr(&template.host)
+            .with_traddr(address)
             .build();
 
+        let hostname = if template.host.parse::<IpAddr>().is_ok() {
+            None
+        } else {
+            Some(template.host.clone())
+        };
+
         // setting the HOSTNQN allows tracking who is connected to what. These
         // makes debugging connections easier in certain cases. If no
         // HOSTNQN is provided.
@@ -236,12 +260,18 @@ impl<'probe> NvmeControllerContext<'probe> {
             opts = opts.with_hostnqn(host_nqn);
         }
 
+        // a share with a token only lets in the host NQN derived from it
+        if let Some(host_nqn) = connect_host_nqn(&template.subnqn) {
+            opts = opts.with_hostnqn(host_nqn);
+        }
+
         let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
         let opts = opts.build();
 
         NvmeControllerContext {
             opts,
             trid,
+            hostname,
             name: template.get_name(),
             sender: Some(sender),
             receiver,
@@ -259,6 +289,10 @@ impl<'probe> NvmeControllerContext<'probe> {
         self.name.clone()
     }
 
+    pub fn hostname(&self) -> Option<String> {
+        self.hostname.clone()
+    }
+
     pub fn sender(&mut self) -> Sender<Result<(), Errno>> {
         self.sender.take().expect("no sender available")
     }
@@ -276,6 +310,16 @@ impl CreateDestroy for NvmfDeviceTemplate {
             });
         }
 
+        // A hostname is resolved on every connect, the address it resolves
+        // to is the one connected to.
+        let address =
+            utils::resolve_host(&self.host, self.port).map_err(|source| {
+                NexusBdevError::CreateBdev {
+                    name: cname.clone(),
+                    source,
+                }
+            })?;
+
         // Insert a new controller instance (uninitialized) as a guard, and
         // release the lock to keep the write path as short, as
         // possible.
@@ -286,7 +330,7 @@ impl CreateDestroy for NvmfDeviceTemplate {
 
         NVME_CONTROLLERS.insert_controller(cname.clone(), rc);
 
-        let mut context = NvmeControllerContext::new(self);
+        let mut context = NvmeControllerContext::new(self, &address);
 
         // Initiate connection with remote NVMe target.
         let probe_ctx = match NonNull::new(unsafe {
@@ -372,18 +416,41 @@ impl CreateDestroy for NvmfDeviceTemplate {
                     })
             }
             Ok(_) => {
-                let controller = NVME_CONTROLLERS
-                    .lookup_by_name(&cname)
-                    .expect("no controller in the list");
-
-                let controller = controller.lock();
-
-                // Successfully attached controllers must be in Running state.
-                assert_eq!(
-                    controller.get_state(),
-                    NvmeControllerState::Running,
-                    "NVMe controller is not fully initialized"
-                );
+                let ns_uuid = {
+                    let controller = NVME_CONTROLLERS
+                        .lookup_by_name(&cname)
+                        .expect("no controller in the list");
+
+                    let controller = controller.lock();
+
+                    // Successfully attached controllers must be in Running
+                    // state.
+                    assert_eq!(
+                        controller.get_state(),
+                        NvmeControllerState::Running,
+                        "NVMe controller is not fully initialized"
+                    );
+                    controller.namespace().map(|ns| ns.uuid())
+                };
+
+                // The NQN only names the subsystem, the UUID of its namespace
+                // tells which replica it exports.
+                if let Some(expected) = self.uuid {
+                    if ns_uuid != Some(expected) {
+                        let found =
+                            ns_uuid.map_or_else(String::new, |u| u.to_string());
+                        error!(
+                            "{} namespace has UUID '{}', expected '{}'",
+                            cname, found, expected
+                        );
+                        controller::destroy_device(self.get_name()).await?;
+                        return Err(NexusBdevError::NamespaceWrongUuid {
+                            name: cname,
+                            expected: expected.to_string(),
+                            found,
+                        });
+                    }
+                }
 
                 info!("{} NVMe controller successfully initialized", cname);
                 Ok(cname)
@@ -1,3 +1,6 @@
+use std::net::{IpAddr, ToSocketAddrs};
+
+use nix::errno::Errno;
 use spdk_rs::libspdk::spdk_nvme_cpl;
 
 use crate::core::NvmeCommandStatus;
@@ -102,3 +105,18 @@ pub enum NvmeDsmAttribute {
     IntegralWrite = 0x2,
     Deallocate = 0x4,
 }
+
+/// Resolve the host of an NVMe-oF target to the address to connect to, IP
+/// literals are returned as they are. Hostnames are resolved on every call
+/// so that targets behind a service survive a change of their address.
+pub(crate) fn resolve_host(host: &str, port: u16) -> Result<String, Errno> {
+    if host.parse::<IpAddr>().is_ok() {
+        return Ok(host.to_string());
+    }
+    (host, port)
+        .to_socket_addrs()
+        .map_err(|_| Errno::EHOSTUNREACH)?
+        .next()
+        .map(|a| a.ip().to_string())
+        .ok_or(Errno::EHOSTUNREACH)
+}
@@ -0,0 +1,209 @@
+//! Devices on persistent memory, for pools holding latency critical replicas.
+//!
+//! A `pmem:///<path>` uri refers to a pmemblk pool on a DAX file system or
+//! device dax, created beforehand with `pmempool create blk 4096 <path>`.
+//! The device is named after the file of the pool, or after the `name`
+//! parameter of the uri.
+//! Writes are persisted to the media before they complete and each block is
+//! written atomically, so flushes complete right away and no write is torn by
+//! a power failure. This requires SPDK built with PMDK support.
+
+use std::{collections::HashMap, convert::TryFrom, ffi::CString, ptr};
+
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use nix::errno::Errno;
+use snafu::ResultExt;
+use url::Url;
+
+use spdk_rs::libspdk::{create_pmem_disk, delete_pmem_disk, spdk_bdev};
+
+use crate::{
+    bdev::{dev::reject_unknown_parameters, util::uri, CreateDestroy, GetName},
+    core::UntypedBdev,
+    ffihelper::{cb_arg, done_errno_cb, ErrnoResult},
+    nexus_uri::{self, NexusBdevError},
+};
+
+#[derive(Debug)]
+pub(super) struct Pmem {
+    name: String,
+    /// path of the pmemblk pool
+    path: String,
+    alias: String,
+    uuid: Option<uuid::Uuid>,
+}
+
+/// Convert a URI to a Pmem "object"
+impl TryFrom<&Url> for Pmem {
+    type Error = NexusBdevError;
+
+    fn try_from(url: &Url) -> Result<Self, Self::Error> {
+        let segments = uri::segments(url);
+
+        if segments.is_empty() {
+            return Err(NexusBdevError::UriInvalid {
+                uri: url.to_string(),
+                message: String::from("no path segments"),
+            });
+        }
+
+        let mut parameters: HashMap<String, String> =
+            url.query_pairs().into_owned().collect();
+
+        let name = match parameters.remove("name") {
+            Some(name) if name.is_empty() => {
+                return Err(NexusBdevError::UriInvalid {
+                    uri: url.to_string(),
+                    message: String::from("empty name"),
+                });
+            }
+            Some(name) => name,
+            None => match segments.last() {
+                Some(file) if !file.is_empty() => file.to_string(),
+                _ => {
+                    return Err(NexusBdevError::UriInvalid {
+                        uri: url.to_string(),
+                        message: String::from("no file name"),
+                    });
+                }
+            },
+        };
+
+        let uuid = uri::uuid(parameters.remove("uuid")).context(
+            nexus_uri::UuidParamParseError {
+                uri: url.to_string(),
+            },
+        )?;
+
+        reject_unknown_parameters(url, parameters)?;
+
+        Ok(Pmem {
+            name,
+            path: url.path().into(),
+            alias: url.to_string(),
+            uuid,
+        })
+    }
+}
+
+impl GetName for Pmem {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl CreateDestroy for Pmem {
+    type Error = NexusBdevError;
+
+    /// Create a pmem bdev on the pmemblk pool
+    async fn create(&self) -> Result<String, Self::Error> {
+        if UntypedBdev::lookup_by_name(&self.name).is_some() {
+            return Err(NexusBdevError::BdevExists {
+                name: self.get_name(),
+            });
+        }
+
+        let cpath = CString::new(self.path.clone()).unwrap();
+        let cname = CString::new(self.get_name()).unwrap();
+        let mut bdev: *mut spdk_bdev = ptr::null_mut();
+
+        let errno = unsafe {
+            create_pmem_disk(cpath.as_ptr(), cname.as_ptr(), &mut bdev)
+        };
+
+        if errno != 0 {
+            return Err(NexusBdevError::CreateBdev {
+                source: Errno::from_i32(errno.abs()),
+                name: self.get_name(),
+            });
+        }
+
+        if let Some(mut bdev) = UntypedBdev::lookup_by_name(&self.name) {
+            if let Some(uuid) = self.uuid {
+                unsafe { bdev.set_raw_uuid(uuid.into()) };
+            }
+
+            if !bdev.add_alias(&self.alias) {
+                error!(
+                    "failed to add alias {} to device {}",
+                    self.alias,
+                    self.get_name()
+                );
+            }
+
+            return Ok(self.get_name());
+        }
+
+        Err(NexusBdevError::BdevNotFound {
+            name: self.get_name(),
+        })
+    }
+
+    /// Destroy the given pmem bdev, leaving the pmemblk pool in place
+    async fn destroy(self: Box<Self>) -> Result<(), Self::Error> {
+        match UntypedBdev::lookup_by_name(&self.name) {
+            Some(mut bdev) => {
+                bdev.remove_alias(&self.alias);
+                let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
+                unsafe {
+                    delete_pmem_disk(
+                        bdev.unsafe_inner_mut_ptr(),
+                        Some(done_errno_cb),
+                        cb_arg(sender),
+                    );
+                }
+                receiver
+                    .await
+                    .context(nexus_uri::CancelBdev {
+                        name: self.get_name(),
+                    })?
+                    .context(nexus_uri::DestroyBdev {
+                        name: self.get_name(),
+                    })
+            }
+            None => Err(NexusBdevError::BdevNotFound {
+                name: self.get_name(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+
+    use url::Url;
+
+    use super::Pmem;
+
+    #[test]
+    fn pmem_uri() {
+        let url = Url::parse("pmem:///mnt/pmem0/pool0").unwrap();
+        let pmem = Pmem::try_from(&url).unwrap();
+        assert_eq!(pmem.path, "/mnt/pmem0/pool0");
+        assert_eq!(pmem.name, "pool0");
+        assert_eq!(pmem.uuid, None);
+
+        let url = Url::parse(
+            "pmem:///mnt/pmem0/pool0?name=fast0&\
+             uuid=9d5c5a4e-5d9e-4b0a-8d3c-0c6a1c2b3d4e",
+        )
+        .unwrap();
+        let pmem = Pmem::try_from(&url).unwrap();
+        assert_eq!(pmem.path, "/mnt/pmem0/pool0");
+        assert_eq!(pmem.name, "fast0");
+        assert!(pmem.uuid.is_some());
+
+        for uri in &[
+            "pmem:///",
+            "pmem:///mnt/pmem0/",
+            "pmem:///mnt/pmem0/pool0?name=",
+            "pmem:///mnt/pmem0/pool0?blk_size=512",
+            "pmem:///mnt/pmem0/pool0?uuid=1",
+        ] {
+            assert!(Pmem::try_from(&Url::parse(uri).unwrap()).is_err());
+        }
+    }
+}
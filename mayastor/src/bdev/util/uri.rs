@@ -2,7 +2,7 @@
 
 use std::str::ParseBoolError;
 
-use url::Url;
+use url::{Host, Url};
 
 pub(crate) fn segments(url: &Url) -> Vec<&str> {
     if let Some(iter) = url.path_segments() {
@@ -18,6 +18,15 @@ pub(crate) fn segments(url: &Url) -> Vec<&str> {
     Vec::new()
 }
 
+/// Returns the host of the URL, IPv6 literals without their brackets as
+/// expected in a transport address.
+pub(crate) fn host(url: &Url) -> Option<String> {
+    match url.host()? {
+        Host::Ipv6(address) => Some(address.to_string()),
+        host => Some(host.to_string()),
+    }
+}
+
 /// Parse a value that represents a boolean
 /// Acceptable values are: true, false, yes, no, on, off
 /// Also accept an (unsigned) integer, where 0 represents false
@@ -1,4 +1,4 @@
-use std::{cell::RefCell, os::raw::c_void, ptr::NonNull};
+use std::{cell::RefCell, ptr::NonNull};
 
 use clap::{value_t, App, AppSettings, Arg};
 use rand::Rng;
@@ -6,6 +6,7 @@ use rand::Rng;
 use mayastor::{
     core::{
         mayastor_env_stop,
+        poller,
         Cores,
         Descriptor,
         IoChannel,
@@ -25,9 +26,6 @@ use spdk_rs::{
         spdk_bdev_io,
         spdk_bdev_read,
         spdk_bdev_write,
-        spdk_poller,
-        spdk_poller_register,
-        spdk_poller_unregister,
     },
     DmaBuf,
 };
@@ -83,7 +81,7 @@ struct Job {
 thread_local! {
     #[allow(clippy::vec_box)]
     static JOBLIST: RefCell<Vec<Box<Job>>> = RefCell::new(Vec::new());
-    static PERF_TICK: RefCell<Option<NonNull<spdk_poller>>> = RefCell::new(None);
+    static PERF_TICK: RefCell<Option<poller::Poller<'static>>> = RefCell::new(None);
 }
 
 impl Job {
@@ -274,7 +272,7 @@ fn sig_override() {
         Mthread::get_init().msg((), |_| {
             PERF_TICK.with(|t| {
                 let ticker = t.borrow_mut().take().unwrap();
-                unsafe { spdk_poller_unregister(&mut ticker.as_ptr()) }
+                ticker.stop();
             });
 
             println!("Draining jobs....");
@@ -293,7 +291,7 @@ fn sig_override() {
 }
 
 /// prints the performance statistics to stdout on every tick (1s)
-extern "C" fn perf_tick(_: *mut c_void) -> i32 {
+fn perf_tick() -> i32 {
     let mut total_io_per_second = 0;
     let mut total_mb_per_second = 0;
     JOBLIST.with(|l| {
@@ -389,15 +387,15 @@ fn main() {
             });
         }
 
-        unsafe {
-            PERF_TICK.with(|p| {
-                *p.borrow_mut() = NonNull::new(spdk_poller_register(
-                    Some(perf_tick),
-                    std::ptr::null_mut(),
-                    1_000_000,
-                ))
-            });
-        }
+        PERF_TICK.with(|p| {
+            *p.borrow_mut() = Some(
+                poller::Builder::new()
+                    .with_name("perf_tick")
+                    .with_interval(1_000_000)
+                    .with_poll_fn(perf_tick)
+                    .build(),
+            )
+        });
     });
 
     Reactors::master().running();
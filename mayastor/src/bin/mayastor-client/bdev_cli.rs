@@ -6,9 +6,16 @@ use crate::{
     Error,
     GrpcStatus,
 };
-use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use byte_unit::Byte;
+use clap::{value_t, App, AppSettings, Arg, ArgMatches, SubCommand};
 use colored_json::prelude::*;
-use rpc::mayastor::{BdevShareRequest, BdevUri, CreateReply, Null};
+use rpc::mayastor::{
+    BdevShareRequest,
+    BdevUri,
+    CreateReply,
+    JsonRpcRequest,
+    Null,
+};
 use snafu::ResultExt;
 use tonic::Status;
 
@@ -22,6 +29,8 @@ pub async fn handler(
         ("share", Some(args)) => share(ctx, args).await,
         ("destroy", Some(args)) => destroy(ctx, args).await,
         ("unshare", Some(args)) => unshare(ctx, args).await,
+        ("export", Some(args)) => export(ctx, args).await,
+        ("exports", Some(args)) => exports(ctx, args).await,
         (cmd, _) => {
             Err(Status::not_found(format!("command {} does not exist", cmd)))
                 .context(GrpcStatus)
@@ -55,6 +64,38 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
         .about("unshare the given bdev")
         .arg(Arg::with_name("name").required(true).index(1));
 
+    let export = SubCommand::with_name("export")
+        .about("Export the data of a bdev to a file on the node")
+        .arg(
+            Arg::with_name("name")
+                .required(true)
+                .index(1)
+                .help("Name of the bdev or nexus to export"),
+        )
+        .arg(
+            Arg::with_name("file")
+                .required(true)
+                .index(2)
+                .help("File on the node running mayastor to export to"),
+        )
+        .arg(
+            Arg::with_name("rate")
+                .short("r")
+                .long("rate")
+                .takes_value(true)
+                .default_value("0")
+                .help("Maximum rate in MiB/s, 0 for no limit"),
+        )
+        .arg(
+            Arg::with_name("overwrite")
+                .long("overwrite")
+                .takes_value(false)
+                .help("Replace the file if it exists"),
+        );
+
+    let exports = SubCommand::with_name("exports")
+        .about("List the exports of bdevs and their progress");
+
     SubCommand::with_name("bdev")
         .settings(&[
             AppSettings::SubcommandRequiredElseHelp,
@@ -67,20 +108,16 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
         .subcommand(unshare)
         .subcommand(create)
         .subcommand(destroy)
+        .subcommand(export)
+        .subcommand(exports)
 }
 
 async fn list(mut ctx: Context, _args: &ArgMatches<'_>) -> crate::Result<()> {
-    let response = ctx.bdev.list(Null {}).await.context(GrpcStatus)?;
+    let response = retry!(ctx, bdev.list(Null {})).context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
-            println!(
-                "{}",
-                serde_json::to_string_pretty(response.get_ref())
-                    .unwrap()
-                    .to_colored_json_auto()
-                    .unwrap()
-            );
+            ctx.print_json(response.get_ref());
         }
         OutputFormat::Default => {
             let bdevs = &response.get_ref().bdevs;
@@ -157,10 +194,7 @@ async fn destroy(mut ctx: Context, args: &ArgMatches<'_>) -> crate::Result<()> {
             field: "name".to_string(),
         })?
         .to_owned();
-    let bdevs = ctx
-        .bdev
-        .list(Null {})
-        .await
+    let bdevs = retry!(ctx, bdev.list(Null {}))
         .context(GrpcStatus)?
         .into_inner();
 
@@ -278,3 +312,97 @@ async fn unshare(mut ctx: Context, args: &ArgMatches<'_>) -> crate::Result<()> {
     }
     Ok(())
 }
+
+/// print exports as returned by the bdev_export(s) json-rpc methods
+fn print_exports(ctx: &Context, exports: serde_json::Value) {
+    match ctx.output {
+        OutputFormat::Json => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&exports)
+                    .unwrap()
+                    .to_colored_json_auto()
+                    .unwrap()
+            );
+        }
+        OutputFormat::Default => {
+            let exports = match exports {
+                serde_json::Value::Array(exports) => exports,
+                export => vec![export],
+            };
+            if exports.is_empty() {
+                ctx.v1("No exports found");
+                return;
+            }
+            let header = vec!["BDEV", "FILE", "STATE", ">SIZE", ">EXPORTED"];
+            let table = exports
+                .iter()
+                .map(|e| {
+                    let bytes = |name: &str| {
+                        ctx.units(Byte::from_bytes(
+                            e[name].as_u64().unwrap_or_default().into(),
+                        ))
+                    };
+                    vec![
+                        e["bdev"].as_str().unwrap_or_default().to_string(),
+                        e["file"].as_str().unwrap_or_default().to_string(),
+                        e["state"].as_str().unwrap_or_default().to_string(),
+                        bytes("size"),
+                        bytes("exported"),
+                    ]
+                })
+                .collect();
+            ctx.print_list(header, table);
+        }
+    }
+}
+
+async fn export(mut ctx: Context, args: &ArgMatches<'_>) -> crate::Result<()> {
+    let name = args.value_of("name").unwrap().to_string();
+    let file = args.value_of("file").unwrap().to_string();
+    let rate =
+        value_t!(args.value_of("rate"), u64).unwrap_or_else(|e| e.exit());
+
+    ctx.v2(&format!("Exporting bdev {} to {}", name, file));
+
+    let params = serde_json::json!({
+        "bdev": name,
+        "file": file,
+        "rate_mb": rate,
+        "overwrite": args.is_present("overwrite"),
+    });
+    let response = ctx
+        .json
+        .json_rpc_call(JsonRpcRequest {
+            method: "bdev_export".to_string(),
+            params: params.to_string(),
+        })
+        .await
+        .context(GrpcStatus)?;
+
+    let export = serde_json::from_str(&response.get_ref().result)
+        .map_err(|e| Status::internal(format!("Bad result: {}", e)))
+        .context(GrpcStatus)?;
+    print_exports(&ctx, export);
+    Ok(())
+}
+
+async fn exports(
+    mut ctx: Context,
+    _args: &ArgMatches<'_>,
+) -> crate::Result<()> {
+    let response = ctx
+        .json
+        .json_rpc_call(JsonRpcRequest {
+            method: "bdev_exports".to_string(),
+            params: String::new(),
+        })
+        .await
+        .context(GrpcStatus)?;
+
+    let exports = serde_json::from_str(&response.get_ref().result)
+        .map_err(|e| Status::internal(format!("Bad result: {}", e)))
+        .context(GrpcStatus)?;
+    print_exports(&ctx, exports);
+    Ok(())
+}
@@ -1,11 +1,40 @@
 use crate::{BdevClient, JsonClient, MayaClient};
+use ::rpc::mayastor::{
+    bdev_rpc_client::BdevRpcClient,
+    json_rpc_client::JsonRpcClient,
+    mayastor_client::MayastorClient,
+};
 use byte_unit::Byte;
 use bytes::Bytes;
 use clap::ArgMatches;
+use colored_json::ToColoredJson;
+use futures::Future;
 use http::uri::{Authority, PathAndQuery, Scheme, Uri};
+use rand::Rng;
+use serde::Serialize;
 use snafu::{Backtrace, ResultExt, Snafu};
-use std::{cmp::max, str::FromStr};
-use tonic::transport::Endpoint;
+use std::{
+    cmp::{max, min},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::{interval_at, sleep, Instant};
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    service::Interceptor,
+    transport::Endpoint,
+    Code,
+    Request,
+    Status,
+};
+
+/// interval at which calls still running are reported
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+/// delay before the first retry of a call
+const BACKOFF_BASE_MS: u64 = 100;
+/// longest delay between two retries of a call
+const BACKOFF_MAX_MS: u64 = 5000;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -31,6 +60,137 @@ pub enum Error {
     },
     #[snafu(display("Invalid output format: {}", format))]
     OutputFormatInvalid { format: String },
+    #[snafu(display("Invalid token"))]
+    InvalidToken {
+        source: tonic::metadata::errors::InvalidMetadataValue,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Invalid value {} for {}", value, field))]
+    InvalidValue { field: String, value: String },
+    #[snafu(display("Invalid endpoint"))]
+    InvalidEndpoint {
+        source: tonic::transport::Error,
+        backtrace: Backtrace,
+    },
+}
+
+/// Adds the bearer token, if any, to the metadata of every request.
+#[derive(Clone)]
+pub(crate) struct TokenInterceptor(Option<MetadataValue<Ascii>>);
+
+impl Interceptor for TokenInterceptor {
+    fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(token) = &self.0 {
+            req.metadata_mut().insert("authorization", token.clone());
+        }
+        Ok(req)
+    }
+}
+
+/// How calls are retried and reported.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetryOpts {
+    /// times a call is retried
+    retries: u32,
+    /// time after which a call is given up
+    timeout: Option<Duration>,
+    /// report retries and calls still running on stderr
+    verbose: bool,
+}
+
+/// returns the delay before the given retry, doubling with each retry up to
+/// a maximum, of which the upper half is randomized by the jitter in [0, 1]
+/// so that clients started together do not retry together
+fn backoff_delay(attempt: u32, jitter: f64) -> Duration {
+    let exp = BACKOFF_BASE_MS.saturating_mul(1 << min(attempt.max(1) - 1, 16));
+    let cap = min(exp, BACKOFF_MAX_MS);
+    Duration::from_millis(cap / 2 + ((cap / 2) as f64 * jitter) as u64)
+}
+
+impl RetryOpts {
+    /// returns true if a call failed with the status may be retried
+    pub(crate) fn should_retry(&self, attempt: u32, status: &Status) -> bool {
+        attempt < self.retries
+            && matches!(
+                status.code(),
+                Code::Unavailable
+                    | Code::DeadlineExceeded
+                    | Code::ResourceExhausted
+            )
+    }
+
+    /// wait before the given retry of a call
+    pub(crate) async fn backoff(
+        &self,
+        method: &str,
+        attempt: u32,
+        status: &Status,
+    ) {
+        let delay = backoff_delay(attempt, rand::thread_rng().gen());
+        if self.verbose {
+            eprintln!(
+                "{}: {}, retrying in {:?} ({}/{})",
+                method,
+                status.message(),
+                delay,
+                attempt,
+                self.retries
+            );
+        }
+        sleep(delay).await;
+    }
+
+    /// Run a call, reporting it while it runs and failing it with
+    /// DEADLINE_EXCEEDED when it takes longer than the timeout.
+    pub(crate) async fn call<T>(
+        &self,
+        method: &str,
+        call: impl Future<Output = Result<T, Status>>,
+    ) -> Result<T, Status> {
+        tokio::pin!(call);
+        let start = Instant::now();
+        let mut progress =
+            interval_at(start + PROGRESS_INTERVAL, PROGRESS_INTERVAL);
+        let timeout = sleep(self.timeout.unwrap_or_default());
+        tokio::pin!(timeout);
+
+        loop {
+            tokio::select! {
+                result = &mut call => return result,
+                _ = &mut timeout, if self.timeout.is_some() => {
+                    return Err(Status::deadline_exceeded(format!(
+                        "no reply after {:?}",
+                        start.elapsed()
+                    )));
+                }
+                _ = progress.tick() => {
+                    if self.verbose {
+                        eprintln!(
+                            "{}: still running after {}s",
+                            method,
+                            start.elapsed().as_secs()
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// parse the value of an argument, if given
+fn parse_value<T: FromStr>(
+    matches: &ArgMatches<'_>,
+    field: &str,
+) -> Result<Option<T>, Error> {
+    matches
+        .value_of(field)
+        .map(|v| {
+            v.parse().map_err(|_| Error::InvalidValue {
+                field: field.to_string(),
+                value: v.to_string(),
+            })
+        })
+        .transpose()
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -53,6 +213,15 @@ impl FromStr for OutputFormat {
     }
 }
 
+/// Output of a command on one of several nodes, collected to be merged with
+/// the output of the other nodes.
+#[derive(Debug, Default)]
+pub(crate) struct Collected {
+    pub(crate) headers: Vec<String>,
+    pub(crate) rows: Vec<Vec<String>>,
+    pub(crate) json: Option<serde_json::Value>,
+}
+
 pub struct Context {
     pub(crate) client: MayaClient,
     pub(crate) bdev: BdevClient,
@@ -60,10 +229,33 @@ pub struct Context {
     verbosity: u64,
     units: char,
     pub(crate) output: OutputFormat,
+    retry: RetryOpts,
+    /// name of the node when running a command on several nodes
+    node: Option<String>,
+    collected: Option<Arc<Mutex<Collected>>>,
 }
 
 impl Context {
     pub(crate) async fn new(matches: &ArgMatches<'_>) -> Result<Self, Error> {
+        Self::connect(matches, matches.value_of("bind"), None).await
+    }
+
+    /// Context of a command run on one of several nodes, collecting its
+    /// output rather than printing it.
+    pub(crate) async fn for_node(
+        matches: &ArgMatches<'_>,
+        node: &str,
+        endpoint: &str,
+        collected: Arc<Mutex<Collected>>,
+    ) -> Result<Self, Error> {
+        Self::connect(matches, Some(endpoint), Some((node, collected))).await
+    }
+
+    async fn connect(
+        matches: &ArgMatches<'_>,
+        endpoint: Option<&str>,
+        node: Option<(&str, Arc<Mutex<Collected>>)>,
+    ) -> Result<Self, Error> {
         let verbosity = if matches.is_present("quiet") {
             0
         } else {
@@ -74,7 +266,7 @@ impl Context {
             .and_then(|u| u.chars().next())
             .unwrap_or('b');
         // Ensure the provided host is defaulted & normalized to what we expect.
-        let host = if let Some(host) = matches.value_of("bind") {
+        let host = if let Some(host) = endpoint {
             let uri = host.parse::<Uri>().context(InvalidUri)?;
             let mut parts = uri.into_parts();
             if parts.scheme.is_none() {
@@ -108,9 +300,41 @@ impl Context {
         })?;
         let output = output.parse()?;
 
-        let client = MayaClient::connect(host.clone()).await.unwrap();
-        let bdev = BdevClient::connect(host.clone()).await.unwrap();
-        let json = JsonClient::connect(host).await.unwrap();
+        let token = matches
+            .value_of("token")
+            .map(|t| t.to_string())
+            .or_else(|| std::env::var("MAYASTOR_TOKEN").ok())
+            .map(|t| MetadataValue::from_str(&format!("Bearer {}", t)))
+            .transpose()
+            .context(InvalidToken)?;
+        let interceptor = TokenInterceptor(token);
+
+        let retries = parse_value(matches, "retries")?.unwrap_or(0);
+        let timeout = parse_value(matches, "timeout")?.map(Duration::from_secs);
+        let retry = RetryOpts {
+            retries,
+            timeout,
+            verbose: verbosity > 0,
+        };
+
+        // connect on the first call, which fails with UNAVAILABLE and is
+        // retried while mayastor is not up
+        let channel = host.connect_lazy().context(InvalidEndpoint)?;
+        let client = MayastorClient::with_interceptor(
+            channel.clone(),
+            interceptor.clone(),
+        );
+        let bdev = BdevRpcClient::with_interceptor(
+            channel.clone(),
+            interceptor.clone(),
+        );
+        let json = JsonRpcClient::with_interceptor(channel, interceptor);
+        let (node, collected) = match node {
+            Some((node, collected)) => {
+                (Some(node.to_string()), Some(collected))
+            }
+            None => (None, None),
+        };
 
         Ok(Context {
             client,
@@ -119,17 +343,49 @@ impl Context {
             verbosity,
             units,
             output,
+            retry,
+            node,
+            collected,
         })
     }
+
+    pub(crate) fn retry_opts(&self) -> RetryOpts {
+        self.retry
+    }
+
+    fn print(&self, s: &str) {
+        match &self.node {
+            Some(node) => println!("{}: {}", node, s),
+            None => println!("{}", s),
+        }
+    }
+
     pub(crate) fn v1(&self, s: &str) {
         if self.verbosity > 0 {
-            println!("{}", s)
+            self.print(s)
         }
     }
 
     pub(crate) fn v2(&self, s: &str) {
         if self.verbosity > 1 {
-            println!("{}", s)
+            self.print(s)
+        }
+    }
+
+    /// print a reply as JSON, or collect it when running on several nodes
+    pub(crate) fn print_json<T: Serialize>(&self, value: &T) {
+        match &self.collected {
+            Some(collected) => {
+                collected.lock().unwrap().json =
+                    Some(serde_json::to_value(value).unwrap());
+            }
+            None => println!(
+                "{}",
+                serde_json::to_string_pretty(value)
+                    .unwrap()
+                    .to_colored_json_auto()
+                    .unwrap()
+            ),
         }
     }
 
@@ -141,57 +397,92 @@ impl Context {
         }
     }
 
+    /// print a table, or collect its rows when running on several nodes
     pub(crate) fn print_list(
         &self,
         headers: Vec<&str>,
-        mut data: Vec<Vec<String>>,
+        data: Vec<Vec<String>>,
     ) {
-        assert_ne!(data.len(), 0);
-        let ncols = data.first().unwrap().len();
-        assert_eq!(headers.len(), ncols);
-
-        let columns = if self.verbosity > 0 {
-            data.insert(
-                0,
-                headers
-                    .iter()
-                    .map(|h| {
-                        if let Some(stripped) = h.strip_prefix('>') {
-                            stripped.to_string()
-                        } else {
-                            h.to_string()
-                        }
-                    })
-                    .collect(),
-            );
+        match &self.collected {
+            Some(collected) => {
+                let mut collected = collected.lock().unwrap();
+                collected.headers =
+                    headers.iter().map(|h| h.to_string()).collect();
+                collected.rows.extend(data);
+            }
+            None => print_table(headers, data, self.verbosity > 0),
+        }
+    }
+}
 
-            data.iter().fold(
-                headers
+/// Print the rows of a table in aligned columns, under the headers if
+/// asked for. A header starting with '>' right aligns its column.
+pub(crate) fn print_table(
+    headers: Vec<&str>,
+    mut data: Vec<Vec<String>>,
+    with_headers: bool,
+) {
+    assert_ne!(data.len(), 0);
+    let ncols = data.first().unwrap().len();
+    assert_eq!(headers.len(), ncols);
+
+    let columns = if with_headers {
+        data.insert(
+            0,
+            headers
+                .iter()
+                .map(|h| {
+                    if let Some(stripped) = h.strip_prefix('>') {
+                        stripped.to_string()
+                    } else {
+                        h.to_string()
+                    }
+                })
+                .collect(),
+        );
+
+        data.iter().fold(
+            headers
+                .iter()
+                .map(|h| (h.starts_with('>'), 0usize))
+                .collect(),
+            |thus_far: Vec<(bool, usize)>, elem| {
+                thus_far
                     .iter()
-                    .map(|h| (h.starts_with('>'), 0usize))
-                    .collect(),
-                |thus_far: Vec<(bool, usize)>, elem| {
-                    thus_far
-                        .iter()
-                        .zip(elem)
-                        .map(|((a, l), s)| (*a, max(*l, s.len())))
-                        .collect()
-                },
-            )
-        } else {
-            vec![(false, 0usize); ncols]
-        };
+                    .zip(elem)
+                    .map(|((a, l), s)| (*a, max(*l, s.len())))
+                    .collect()
+            },
+        )
+    } else {
+        vec![(false, 0usize); ncols]
+    };
 
-        for row in data {
-            let vals = row.iter().enumerate().map(|(idx, s)| {
-                if columns[idx].0 {
-                    format!("{:>1$}", s, columns[idx].1)
-                } else {
-                    format!("{:<1$}", s, columns[idx].1)
-                }
-            });
+    for row in data {
+        let vals = row.iter().enumerate().map(|(idx, s)| {
+            if columns[idx].0 {
+                format!("{:>1$}", s, columns[idx].1)
+            } else {
+                format!("{:<1$}", s, columns[idx].1)
+            }
+        });
 
-            println!("{}", vals.collect::<Vec<String>>().join(" "));
-        }
+        println!("{}", vals.collect::<Vec<String>>().join(" "));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::backoff_delay;
+
+    #[test]
+    fn backoff() {
+        assert_eq!(backoff_delay(1, 0.0), Duration::from_millis(50));
+        assert_eq!(backoff_delay(1, 1.0), Duration::from_millis(100));
+        assert_eq!(backoff_delay(3, 1.0), Duration::from_millis(400));
+        assert_eq!(backoff_delay(10, 0.0), Duration::from_millis(2500));
+        assert_eq!(backoff_delay(40, 1.0), Duration::from_millis(5000));
     }
 }
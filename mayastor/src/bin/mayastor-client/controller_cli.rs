@@ -5,7 +5,6 @@ use super::context::Context;
 use crate::{context::OutputFormat, GrpcStatus};
 use ::rpc::mayastor as rpc;
 use clap::{App, AppSettings, ArgMatches, SubCommand};
-use colored_json::ToColoredJson;
 use snafu::ResultExt;
 use tonic::Status;
 
@@ -56,21 +55,12 @@ async fn controller_stats(
     mut ctx: Context,
     _matches: &ArgMatches<'_>,
 ) -> crate::Result<()> {
-    let response = ctx
-        .client
-        .stat_nvme_controllers(rpc::Null {})
-        .await
+    let response = retry!(ctx, client.stat_nvme_controllers(rpc::Null {}))
         .context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
-            println!(
-                "{}",
-                serde_json::to_string_pretty(&response.get_ref())
-                    .unwrap()
-                    .to_colored_json_auto()
-                    .unwrap()
-            );
+            ctx.print_json(response.get_ref());
         }
         OutputFormat::Default => {
             let controllers = &response.get_ref().controllers;
@@ -111,21 +101,12 @@ async fn list_controllers(
     mut ctx: Context,
     _matches: &ArgMatches<'_>,
 ) -> crate::Result<()> {
-    let response = ctx
-        .client
-        .list_nvme_controllers(rpc::Null {})
-        .await
+    let response = retry!(ctx, client.list_nvme_controllers(rpc::Null {}))
         .context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
-            println!(
-                "{}",
-                serde_json::to_string_pretty(&response.get_ref())
-                    .unwrap()
-                    .to_colored_json_auto()
-                    .unwrap()
-            );
+            ctx.print_json(response.get_ref());
         }
         OutputFormat::Default => {
             let controllers = &response.get_ref().controllers;
@@ -5,7 +5,6 @@ use super::context::Context;
 use crate::{context::OutputFormat, GrpcStatus};
 use ::rpc::mayastor as rpc;
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
-use colored_json::ToColoredJson;
 use snafu::ResultExt;
 use tonic::Status;
 
@@ -57,23 +56,17 @@ async fn list_block_devices(
 ) -> crate::Result<()> {
     let all = matches.is_present("all");
 
-    let response = ctx
-        .client
-        .list_block_devices(rpc::ListBlockDevicesRequest {
+    let response = retry!(
+        ctx,
+        client.list_block_devices(rpc::ListBlockDevicesRequest {
             all,
         })
-        .await
-        .context(GrpcStatus)?;
+    )
+    .context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
-            println!(
-                "{}",
-                serde_json::to_string_pretty(&response.into_inner())
-                    .unwrap()
-                    .to_colored_json_auto()
-                    .unwrap()
-            )
+            ctx.print_json(&response.into_inner());
         }
         OutputFormat::Default => {
             let devices: &Vec<rpc::BlockDevice> = &response.get_ref().devices;
@@ -0,0 +1,204 @@
+//!
+//! Running the list and stat commands on several nodes at once.
+//!
+//! With `--nodes` listing the endpoints of nodes, or `--nodes-from` giving
+//! the URL of the list of nodes of the control plane, a list or stat command
+//! runs on every node in parallel. The tables of the nodes are merged into
+//! one with a NODE column, and the JSON replies into an object keyed by node.
+//! A node failing does not stop the others; its error is printed and the
+//! command fails once all nodes are done.
+
+use std::{
+    iter::once,
+    sync::{Arc, Mutex},
+};
+
+use clap::ArgMatches;
+use colored_json::ToColoredJson;
+use futures::future::join_all;
+use hyper::{body, Client, Uri};
+use serde_json::Value;
+use snafu::ResultExt;
+
+use crate::{
+    context::{print_table, Collected, Context, OutputFormat},
+    dispatch,
+    ContextError,
+    Error,
+};
+
+/// the commands that can run on several nodes
+const COMMANDS: [(&str, &str); 10] = [
+    ("bdev", "list"),
+    ("controller", "list"),
+    ("controller", "stats"),
+    ("device", "list"),
+    ("nexus", "list"),
+    ("nexus", "list2"),
+    ("pool", "list"),
+    ("replica", "list"),
+    ("replica", "list2"),
+    ("replica", "stats"),
+];
+
+/// returns true if the command is to run on several nodes
+pub(crate) fn requested(matches: &ArgMatches<'_>) -> bool {
+    matches.is_present("nodes") || matches.is_present("nodes-from")
+}
+
+/// returns the name and gRPC endpoint of the nodes in a list of nodes of the
+/// control plane, each with its gRPC endpoint in it or in its spec or state
+fn parse_nodes(value: &Value) -> Vec<(String, String)> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|node| {
+            let endpoint = [node, &node["spec"], &node["state"]]
+                .iter()
+                .find_map(|v| v["grpcEndpoint"].as_str())?;
+            let name = node["id"].as_str().unwrap_or(endpoint);
+            Some((name.to_string(), endpoint.to_string()))
+        })
+        .collect()
+}
+
+/// get the nodes from the control plane
+async fn discover(url: &str) -> Result<Vec<(String, String)>, Error> {
+    let error = |message: String| Error::NodeDiscovery {
+        url: url.to_string(),
+        message,
+    };
+    let uri = url.parse::<Uri>().map_err(|e| error(e.to_string()))?;
+    let response = Client::new()
+        .get(uri)
+        .await
+        .map_err(|e| error(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(error(response.status().to_string()));
+    }
+    let bytes = body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| error(e.to_string()))?;
+    let value = serde_json::from_slice::<Value>(&bytes)
+        .map_err(|e| error(e.to_string()))?;
+    Ok(parse_nodes(&value))
+}
+
+/// Run the command on all the nodes and print their merged output.
+pub(crate) async fn run(matches: &ArgMatches<'_>) -> crate::Result<()> {
+    let command = match matches.subcommand() {
+        (command, Some(args)) => {
+            (command, args.subcommand_name().unwrap_or_default())
+        }
+        (command, None) => (command, ""),
+    };
+    if !COMMANDS.contains(&command) {
+        return Err(Error::FanOutUnsupported {
+            command: format!("{} {}", command.0, command.1),
+        });
+    }
+
+    let mut nodes = matches
+        .values_of("nodes")
+        .into_iter()
+        .flatten()
+        .map(|n| (n.to_string(), n.to_string()))
+        .collect::<Vec<_>>();
+    if let Some(url) = matches.value_of("nodes-from") {
+        nodes.extend(discover(url).await?);
+    }
+    let output = matches
+        .value_of("output")
+        .unwrap_or("default")
+        .parse::<OutputFormat>()
+        .context(ContextError)?;
+
+    let mut runs = Vec::new();
+    for (node, endpoint) in &nodes {
+        let collected = Arc::new(Mutex::new(Collected::default()));
+        let ctx = Context::for_node(matches, node, endpoint, collected.clone())
+            .await
+            .context(ContextError)?;
+        runs.push(
+            async move { (node, collected, dispatch(ctx, matches).await) },
+        );
+    }
+
+    let mut failed = Vec::new();
+    let mut headers = None;
+    let mut rows = Vec::new();
+    let mut json = serde_json::Map::new();
+    for (node, collected, result) in join_all(runs).await {
+        if let Err(error) = result {
+            eprintln!("{}: {}", node, error);
+            failed.push(node.clone());
+            continue;
+        }
+        let collected = std::mem::take(&mut *collected.lock().unwrap());
+        if !collected.rows.is_empty() {
+            headers.get_or_insert(collected.headers);
+            rows.extend(
+                collected
+                    .rows
+                    .into_iter()
+                    .map(|row| once(node.clone()).chain(row).collect()),
+            );
+        }
+        if let Some(value) = collected.json {
+            json.insert(node.clone(), value);
+        }
+    }
+
+    match output {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&Value::Object(json))
+                .unwrap()
+                .to_colored_json_auto()
+                .unwrap()
+        ),
+        OutputFormat::Default => {
+            if let Some(headers) = headers {
+                let headers = once("NODE")
+                    .chain(headers.iter().map(String::as_str))
+                    .collect();
+                print_table(headers, rows, !matches.is_present("quiet"));
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::FanOut {
+            nodes: failed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::parse_nodes;
+
+    #[test]
+    fn nodes() {
+        let nodes = json!([
+            {"id": "node-1", "grpcEndpoint": "10.0.0.1:10124"},
+            {"id": "node-2", "spec": {"grpcEndpoint": "10.0.0.2:10124"}},
+            {"state": {"grpcEndpoint": "10.0.0.3:10124"}},
+            {"id": "node-4"},
+        ]);
+        assert_eq!(
+            parse_nodes(&nodes),
+            vec![
+                ("node-1".to_string(), "10.0.0.1:10124".to_string()),
+                ("node-2".to_string(), "10.0.0.2:10124".to_string()),
+                ("10.0.0.3:10124".to_string(), "10.0.0.3:10124".to_string()),
+            ]
+        );
+        assert!(parse_nodes(&json!({})).is_empty());
+    }
+}
@@ -1,19 +1,50 @@
 use byte_unit::Byte;
-use clap::{App, AppSettings, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches};
 use snafu::{Backtrace, ResultExt, Snafu};
-use tonic::transport::Channel;
+use tonic::{codegen::InterceptedService, transport::Channel};
 
-use crate::context::Context;
+use crate::context::{Context, TokenInterceptor};
 use ::rpc::mayastor::{
     bdev_rpc_client::BdevRpcClient,
     json_rpc_client::JsonRpcClient,
     mayastor_client::MayastorClient,
 };
 
+/// Call an idempotent method of a client of the context, retrying it when
+/// the call fails in a way a later call may not, as while mayastor restarts.
+macro_rules! retry {
+    ($ctx:expr, $client:ident.$method:ident($req:expr)) => {{
+        let opts = $ctx.retry_opts();
+        let req = $req;
+        let mut attempt = 0;
+        loop {
+            let call = $ctx.$client.$method(req.clone());
+            match opts.call(stringify!($method), call).await {
+                Err(status) if opts.should_retry(attempt, &status) => {
+                    attempt += 1;
+                    opts.backoff(stringify!($method), attempt, &status).await;
+                }
+                result => break result,
+            }
+        }
+    }};
+}
+
+/// Call a method of a client of the context that is not idempotent, such as
+/// one creating or destroying an object, once: a call that timed out may
+/// still have taken effect, and repeating it would then fail or do it twice.
+macro_rules! call_once {
+    ($ctx:expr, $client:ident.$method:ident($req:expr)) => {{
+        let call = $ctx.$client.$method($req);
+        $ctx.retry_opts().call(stringify!($method), call).await
+    }};
+}
+
 mod bdev_cli;
 mod context;
 mod controller_cli;
 mod device_cli;
+mod fan_out;
 mod jsonrpc_cli;
 mod nexus_child_cli;
 mod nexus_cli;
@@ -23,9 +54,10 @@ mod rebuild_cli;
 mod replica_cli;
 mod snapshot_cli;
 
-type MayaClient = MayastorClient<Channel>;
-type BdevClient = BdevRpcClient<Channel>;
-type JsonClient = JsonRpcClient<Channel>;
+type AuthChannel = InterceptedService<Channel, TokenInterceptor>;
+type MayaClient = MayastorClient<AuthChannel>;
+type BdevClient = BdevRpcClient<AuthChannel>;
+type JsonClient = JsonRpcClient<AuthChannel>;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -41,6 +73,12 @@ pub enum Error {
     },
     #[snafu(display("Missing value for {}", field))]
     MissingValue { field: String },
+    #[snafu(display("Failed to get the nodes from {}: {}", url, message))]
+    NodeDiscovery { url: String, message: String },
+    #[snafu(display("{} can not run on several nodes", command))]
+    FanOutUnsupported { command: String },
+    #[snafu(display("Failed on nodes {}", nodes.join(", ")))]
+    FanOut { nodes: Vec<String> },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -68,6 +106,40 @@ async fn main() -> crate::Result<()> {
                 .value_name("HOST")
                 .help("The URI of mayastor instance")
                 .global(true))
+        .arg(
+            Arg::with_name("token")
+                .short("t")
+                .long("token")
+                .value_name("TOKEN")
+                .help("Bearer token for the gRPC API, defaults to $MAYASTOR_TOKEN")
+                .global(true))
+        .arg(
+            Arg::with_name("retries")
+                .long("retries")
+                .value_name("NUMBER")
+                .default_value("5")
+                .help("Times idempotent calls are retried while mayastor is unavailable")
+                .global(true))
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .value_name("SECONDS")
+                .help("Time after which a call is given up and retried if idempotent")
+                .global(true))
+        .arg(
+            Arg::with_name("nodes")
+                .long("nodes")
+                .value_name("HOSTS")
+                .multiple(true)
+                .require_delimiter(true)
+                .help("Comma separated URIs of nodes to run a list or stats command on")
+                .global(true))
+        .arg(
+            Arg::with_name("nodes-from")
+                .long("nodes-from")
+                .value_name("URL")
+                .help("URL of the list of nodes of the control plane to run a list or stats command on")
+                .global(true))
         .arg(
             Arg::with_name("quiet")
                 .short("q")
@@ -112,9 +184,19 @@ async fn main() -> crate::Result<()> {
         .subcommand(controller_cli::subcommands())
         .get_matches();
 
+    if fan_out::requested(&matches) {
+        return fan_out::run(&matches).await;
+    }
     let ctx = Context::new(&matches).await.context(ContextError)?;
+    dispatch(ctx, &matches).await
+}
 
-    let status = match matches.subcommand() {
+/// run the command with the context
+pub(crate) async fn dispatch(
+    ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> crate::Result<()> {
+    match matches.subcommand() {
         ("bdev", Some(args)) => bdev_cli::handler(ctx, args).await,
         ("device", Some(args)) => device_cli::handler(ctx, args).await,
         ("nexus", Some(args)) => nexus_cli::handler(ctx, args).await,
@@ -126,6 +208,5 @@ async fn main() -> crate::Result<()> {
         ("controller", Some(args)) => controller_cli::handler(ctx, args).await,
         ("jsonrpc", Some(args)) => jsonrpc_cli::json_rpc_call(ctx, args).await,
         _ => panic!("Command not found"),
-    };
-    status
+    }
 }
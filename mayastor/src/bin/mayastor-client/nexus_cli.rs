@@ -359,13 +359,13 @@ async fn nexus_destroy(
 ) -> crate::Result<()> {
     let uuid = matches.value_of("uuid").unwrap().to_string();
 
-    let response = ctx
-        .client
-        .destroy_nexus(rpc::DestroyNexusRequest {
+    let response = call_once!(
+        ctx,
+        client.destroy_nexus(rpc::DestroyNexusRequest {
             uuid: uuid.clone(),
         })
-        .await
-        .context(GrpcStatus)?;
+    )
+    .context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
@@ -389,21 +389,12 @@ async fn nexus_list(
     mut ctx: Context,
     matches: &ArgMatches<'_>,
 ) -> crate::Result<()> {
-    let response = ctx
-        .client
-        .list_nexus(rpc::Null {})
-        .await
-        .context(GrpcStatus)?;
+    let response =
+        retry!(ctx, client.list_nexus(rpc::Null {})).context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
-            println!(
-                "{}",
-                serde_json::to_string_pretty(&response.get_ref())
-                    .unwrap()
-                    .to_colored_json_auto()
-                    .unwrap()
-            );
+            ctx.print_json(response.get_ref());
         }
         OutputFormat::Default => {
             let nexus = &response.get_ref().nexus_list;
@@ -454,21 +445,12 @@ async fn nexus_list_v2(
     mut ctx: Context,
     matches: &ArgMatches<'_>,
 ) -> crate::Result<()> {
-    let response = ctx
-        .client
-        .list_nexus_v2(rpc::Null {})
-        .await
-        .context(GrpcStatus)?;
+    let response =
+        retry!(ctx, client.list_nexus_v2(rpc::Null {})).context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
-            println!(
-                "{}",
-                serde_json::to_string_pretty(&response.get_ref())
-                    .unwrap()
-                    .to_colored_json_auto()
-                    .unwrap()
-            );
+            ctx.print_json(response.get_ref());
         }
         OutputFormat::Default => {
             let nexus = &response.get_ref().nexus_list;
@@ -528,11 +510,8 @@ async fn nexus_children(
         })?
         .to_string();
 
-    let response = ctx
-        .client
-        .list_nexus(rpc::Null {})
-        .await
-        .context(GrpcStatus)?;
+    let response =
+        retry!(ctx, client.list_nexus(rpc::Null {})).context(GrpcStatus)?;
 
     let nexus = response
         .get_ref()
@@ -678,13 +657,13 @@ async fn nexus_get_nvme_ana_state(
     mut ctx: Context,
     uuid: String,
 ) -> crate::Result<()> {
-    let resp = ctx
-        .client
-        .get_nvme_ana_state(rpc::GetNvmeAnaStateRequest {
+    let resp = retry!(
+        ctx,
+        client.get_nvme_ana_state(rpc::GetNvmeAnaStateRequest {
             uuid: uuid.clone(),
         })
-        .await
-        .context(GrpcStatus)?;
+    )
+    .context(GrpcStatus)?;
     ctx.v1(ana_state_idx_to_str(resp.get_ref().ana_state));
     Ok(())
 }
@@ -1,15 +1,18 @@
 //!
 //! Methods related to the gathering of performance statistics.
 //!
-//! At present we only have get_resource_usage() which is
-//! essentially the result of a getrusage(2) system call.
+//! get_resource_usage() is essentially the result of a getrusage(2) system
+//! call, test runs the built-in IO benchmark against a bdev or nexus and
+//! replay replays a trace of the IOs of a nexus against one.
 
 use super::{
     context::{Context, OutputFormat},
+    parse_size,
     GrpcStatus,
 };
 use ::rpc::mayastor as rpc;
-use clap::{App, AppSettings, ArgMatches, SubCommand};
+use byte_unit::Byte;
+use clap::{value_t, App, AppSettings, Arg, ArgMatches, SubCommand};
 use colored_json::ToColoredJson;
 use snafu::ResultExt;
 use tonic::Status;
@@ -18,6 +21,104 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
     let resource =
         SubCommand::with_name("resource").about("Resource usage statistics");
 
+    let test = SubCommand::with_name("test")
+        .about("Run an IO benchmark against a bdev or nexus")
+        .arg(
+            Arg::with_name("bdev")
+                .required(true)
+                .index(1)
+                .help("Name of the bdev or nexus to test"),
+        )
+        .arg(
+            Arg::with_name("pattern")
+                .short("p")
+                .long("pattern")
+                .takes_value(true)
+                .possible_values(&[
+                    "read",
+                    "write",
+                    "randread",
+                    "randwrite",
+                    "randrw",
+                ])
+                .default_value("randread")
+                .help("IO pattern"),
+        )
+        .arg(
+            Arg::with_name("block-size")
+                .short("b")
+                .long("block-size")
+                .takes_value(true)
+                .default_value("4KiB")
+                .help("Size of each IO"),
+        )
+        .arg(
+            Arg::with_name("queue-depth")
+                .short("q")
+                .long("queue-depth")
+                .takes_value(true)
+                .default_value("32")
+                .help("Number of IOs kept in flight"),
+        )
+        .arg(
+            Arg::with_name("runtime")
+                .short("r")
+                .long("runtime")
+                .takes_value(true)
+                .default_value("10")
+                .help("Runtime in seconds"),
+        )
+        .arg(
+            Arg::with_name("read-percent")
+                .long("read-percent")
+                .takes_value(true)
+                .default_value("50")
+                .help("Percentage of reads of the randrw pattern"),
+        )
+        .arg(
+            Arg::with_name("allow-writes")
+                .long("allow-writes")
+                .takes_value(false)
+                .help("Allow writing to the bdev, destroying its data"),
+        );
+
+    let replay = SubCommand::with_name("replay")
+        .about("Replay a trace of the IOs of a nexus against a bdev")
+        .arg(
+            Arg::with_name("bdev")
+                .required(true)
+                .index(1)
+                .help("Name of the bdev or nexus to replay the trace against"),
+        )
+        .arg(
+            Arg::with_name("file")
+                .required(true)
+                .index(2)
+                .help("Trace file on the node running mayastor"),
+        )
+        .arg(
+            Arg::with_name("queue-depth")
+                .short("q")
+                .long("queue-depth")
+                .takes_value(true)
+                .default_value("32")
+                .help("Maximum number of IOs kept in flight"),
+        )
+        .arg(
+            Arg::with_name("untimed")
+                .long("untimed")
+                .takes_value(false)
+                .help(
+                    "Replay as fast as possible instead of at the traced pace",
+                ),
+        )
+        .arg(
+            Arg::with_name("allow-writes")
+                .long("allow-writes")
+                .takes_value(false)
+                .help("Allow writing to the bdev, destroying its data"),
+        );
+
     SubCommand::with_name("perf")
         .settings(&[
             AppSettings::SubcommandRequiredElseHelp,
@@ -26,6 +127,8 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
         ])
         .about("Performance statistics")
         .subcommand(resource)
+        .subcommand(test)
+        .subcommand(replay)
 }
 
 pub async fn handler(
@@ -34,6 +137,8 @@ pub async fn handler(
 ) -> crate::Result<()> {
     match matches.subcommand() {
         ("resource", Some(args)) => get_resource_usage(ctx, args).await,
+        ("test", Some(args)) => perf_test(ctx, args).await,
+        ("replay", Some(args)) => perf_replay(ctx, args).await,
         (cmd, _) => {
             Err(Status::not_found(format!("command {} does not exist", cmd)))
                 .context(GrpcStatus)
@@ -49,10 +154,7 @@ async fn get_resource_usage(
 
     let mut table: Vec<Vec<String>> = Vec::new();
 
-    let response = ctx
-        .client
-        .get_resource_usage(rpc::Null {})
-        .await
+    let response = retry!(ctx, client.get_resource_usage(rpc::Null {}))
         .context(GrpcStatus)?;
 
     match ctx.output {
@@ -89,3 +191,175 @@ async fn get_resource_usage(
 
     Ok(())
 }
+
+async fn perf_test(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> crate::Result<()> {
+    let bdev = matches.value_of("bdev").unwrap().to_string();
+    let pattern = matches.value_of("pattern").unwrap().to_string();
+    let block_size = parse_size(matches.value_of("block-size").unwrap())
+        .map_err(|s| Status::invalid_argument(format!("Bad size '{}'", s)))
+        .context(GrpcStatus)?;
+    let queue_depth = value_t!(matches.value_of("queue-depth"), u32)
+        .unwrap_or_else(|e| e.exit());
+    let runtime =
+        value_t!(matches.value_of("runtime"), u64).unwrap_or_else(|e| e.exit());
+    let read_percent = value_t!(matches.value_of("read-percent"), u8)
+        .unwrap_or_else(|e| e.exit());
+
+    ctx.v2(&format!(
+        "Running {} perf test against {} for {}s",
+        pattern, bdev, runtime
+    ));
+
+    let params = serde_json::json!({
+        "bdev": bdev,
+        "pattern": pattern,
+        "block_size": block_size.get_bytes() as u64,
+        "queue_depth": queue_depth,
+        "runtime_secs": runtime,
+        "read_percent": read_percent,
+        "allow_writes": matches.is_present("allow-writes"),
+    });
+
+    let response = ctx
+        .json
+        .json_rpc_call(rpc::JsonRpcRequest {
+            method: "perf_test".to_string(),
+            params: params.to_string(),
+        })
+        .await
+        .context(GrpcStatus)?;
+
+    let result: serde_json::Value =
+        serde_json::from_str(&response.get_ref().result)
+            .map_err(|e| Status::internal(format!("Bad result: {}", e)))
+            .context(GrpcStatus)?;
+
+    match ctx.output {
+        OutputFormat::Json => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&result)
+                    .unwrap()
+                    .to_colored_json_auto()
+                    .unwrap()
+            );
+        }
+        OutputFormat::Default => {
+            let field = |name: &str| result[name].as_u64().unwrap_or_default();
+            let latency = |name: &str| {
+                result["latency_us"][name].as_u64().unwrap_or_default()
+            };
+            let bandwidth =
+                ctx.units(Byte::from_bytes(field("bandwidth").into()));
+
+            ctx.print_list(
+                vec![
+                    "BDEV",
+                    "PATTERN",
+                    ">IOPS",
+                    ">BANDWIDTH",
+                    ">ERRORS",
+                    ">AVG_US",
+                    ">P50_US",
+                    ">P99_US",
+                    ">P999_US",
+                    ">MAX_US",
+                ],
+                vec![vec![
+                    bdev,
+                    pattern,
+                    field("iops").to_string(),
+                    format!("{}/s", bandwidth),
+                    field("errors").to_string(),
+                    latency("avg").to_string(),
+                    latency("p50").to_string(),
+                    latency("p99").to_string(),
+                    latency("p999").to_string(),
+                    latency("max").to_string(),
+                ]],
+            );
+        }
+    };
+
+    Ok(())
+}
+
+async fn perf_replay(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> crate::Result<()> {
+    let bdev = matches.value_of("bdev").unwrap().to_string();
+    let file = matches.value_of("file").unwrap().to_string();
+    let queue_depth = value_t!(matches.value_of("queue-depth"), u32)
+        .unwrap_or_else(|e| e.exit());
+
+    ctx.v2(&format!("Replaying trace {} against {}", file, bdev));
+
+    let params = serde_json::json!({
+        "bdev": bdev,
+        "file": file,
+        "queue_depth": queue_depth,
+        "timed": !matches.is_present("untimed"),
+        "allow_writes": matches.is_present("allow-writes"),
+    });
+
+    let response = ctx
+        .json
+        .json_rpc_call(rpc::JsonRpcRequest {
+            method: "perf_replay".to_string(),
+            params: params.to_string(),
+        })
+        .await
+        .context(GrpcStatus)?;
+
+    let result: serde_json::Value =
+        serde_json::from_str(&response.get_ref().result)
+            .map_err(|e| Status::internal(format!("Bad result: {}", e)))
+            .context(GrpcStatus)?;
+
+    match ctx.output {
+        OutputFormat::Json => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&result)
+                    .unwrap()
+                    .to_colored_json_auto()
+                    .unwrap()
+            );
+        }
+        OutputFormat::Default => {
+            let field = |name: &str| result[name].as_u64().unwrap_or_default();
+            let latency = |name: &str| {
+                result["latency_us"][name].as_u64().unwrap_or_default()
+            };
+
+            ctx.print_list(
+                vec![
+                    "BDEV",
+                    ">IOS",
+                    ">ERRORS",
+                    ">SKIPPED",
+                    ">RUNTIME_MS",
+                    ">AVG_US",
+                    ">P99_US",
+                    ">MAX_US",
+                ],
+                vec![vec![
+                    bdev,
+                    field("ios").to_string(),
+                    field("errors").to_string(),
+                    field("skipped").to_string(),
+                    field("runtime_ms").to_string(),
+                    latency("avg").to_string(),
+                    latency("p99").to_string(),
+                    latency("max").to_string(),
+                ]],
+            );
+        }
+    };
+
+    Ok(())
+}
@@ -79,14 +79,14 @@ async fn create(
         .map(|dev| dev.to_owned())
         .collect();
 
-    let response = ctx
-        .client
-        .create_pool(rpc::CreatePoolRequest {
+    let response = call_once!(
+        ctx,
+        client.create_pool(rpc::CreatePoolRequest {
             name: name.clone(),
             disks,
         })
-        .await
-        .context(GrpcStatus)?;
+    )
+    .context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
@@ -117,13 +117,13 @@ async fn destroy(
         })?
         .to_owned();
 
-    let response = ctx
-        .client
-        .destroy_pool(rpc::DestroyPoolRequest {
+    let response = call_once!(
+        ctx,
+        client.destroy_pool(rpc::DestroyPoolRequest {
             name: name.clone(),
         })
-        .await
-        .context(GrpcStatus)?;
+    )
+    .context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
@@ -149,21 +149,12 @@ async fn list(
 ) -> crate::Result<()> {
     ctx.v2("Requesting a list of pools");
 
-    let response = ctx
-        .client
-        .list_pools(rpc::Null {})
-        .await
-        .context(GrpcStatus)?;
+    let response =
+        retry!(ctx, client.list_pools(rpc::Null {})).context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
-            println!(
-                "{}",
-                serde_json::to_string_pretty(response.get_ref())
-                    .unwrap()
-                    .to_colored_json_auto()
-                    .unwrap()
-            );
+            ctx.print_json(response.get_ref());
         }
         OutputFormat::Default => {
             let pools: &Vec<rpc::Pool> = &response.get_ref().pools;
@@ -343,14 +343,14 @@ async fn state(
         })?
         .to_string();
 
-    let response = ctx
-        .client
-        .get_rebuild_state(rpc::RebuildStateRequest {
+    let response = retry!(
+        ctx,
+        client.get_rebuild_state(rpc::RebuildStateRequest {
             uuid: uuid.clone(),
             uri: uri.clone(),
         })
-        .await
-        .context(GrpcStatus)?;
+    )
+    .context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
@@ -394,14 +394,14 @@ async fn stats(
         "Getting the rebuild stats of child {} on nexus {}",
         uri, uuid
     ));
-    let response = ctx
-        .client
-        .get_rebuild_stats(rpc::RebuildStatsRequest {
+    let response = retry!(
+        ctx,
+        client.get_rebuild_stats(rpc::RebuildStatsRequest {
             uuid: uuid.clone(),
             uri: uri.clone(),
         })
-        .await
-        .context(GrpcStatus)?;
+    )
+    .context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
@@ -461,14 +461,14 @@ async fn progress(
         })?
         .to_string();
 
-    let response = ctx
-        .client
-        .get_rebuild_progress(rpc::RebuildProgressRequest {
+    let response = retry!(
+        ctx,
+        client.get_rebuild_progress(rpc::RebuildProgressRequest {
             uuid: uuid.clone(),
             uri: uri.clone(),
         })
-        .await
-        .context(GrpcStatus)?;
+    )
+    .context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
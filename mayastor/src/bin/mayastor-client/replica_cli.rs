@@ -176,7 +176,8 @@ async fn replica_create(
         share,
         size: size.get_bytes() as u64,
     };
-    let response = ctx.client.create_replica(rq).await.context(GrpcStatus)?;
+    let response =
+        call_once!(ctx, client.create_replica(rq)).context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
@@ -238,7 +239,7 @@ async fn replica_create_v2(
         size: size.get_bytes() as u64,
     };
     let response =
-        ctx.client.create_replica_v2(rq).await.context(GrpcStatus)?;
+        call_once!(ctx, client.create_replica_v2(rq)).context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
@@ -269,13 +270,13 @@ async fn replica_destroy(
         })?
         .to_owned();
 
-    let response = ctx
-        .client
-        .destroy_replica(rpc::DestroyReplicaRequest {
+    let response = call_once!(
+        ctx,
+        client.destroy_replica(rpc::DestroyReplicaRequest {
             uuid: uuid.clone(),
         })
-        .await
-        .context(GrpcStatus)?;
+    )
+    .context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
@@ -299,21 +300,12 @@ async fn replica_list(
     mut ctx: Context,
     _matches: &ArgMatches<'_>,
 ) -> crate::Result<()> {
-    let response = ctx
-        .client
-        .list_replicas(rpc::Null {})
-        .await
-        .context(GrpcStatus)?;
+    let response =
+        retry!(ctx, client.list_replicas(rpc::Null {})).context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
-            println!(
-                "{}",
-                serde_json::to_string_pretty(&response.get_ref())
-                    .unwrap()
-                    .to_colored_json_auto()
-                    .unwrap()
-            );
+            ctx.print_json(response.get_ref());
         }
         OutputFormat::Default => {
             let replicas = &response.get_ref().replicas;
@@ -351,21 +343,12 @@ async fn replica_list2(
     mut ctx: Context,
     _matches: &ArgMatches<'_>,
 ) -> crate::Result<()> {
-    let response = ctx
-        .client
-        .list_replicas_v2(rpc::Null {})
-        .await
+    let response = retry!(ctx, client.list_replicas_v2(rpc::Null {}))
         .context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
-            println!(
-                "{}",
-                serde_json::to_string_pretty(&response.get_ref())
-                    .unwrap()
-                    .to_colored_json_auto()
-                    .unwrap()
-            );
+            ctx.print_json(response.get_ref());
         }
         OutputFormat::Default => {
             let replicas = &response.get_ref().replicas;
@@ -439,21 +422,12 @@ async fn replica_stat(
     mut ctx: Context,
     _matches: &ArgMatches<'_>,
 ) -> crate::Result<()> {
-    let response = ctx
-        .client
-        .stat_replicas(rpc::Null {})
-        .await
-        .context(GrpcStatus)?;
+    let response =
+        retry!(ctx, client.stat_replicas(rpc::Null {})).context(GrpcStatus)?;
 
     match ctx.output {
         OutputFormat::Json => {
-            println!(
-                "{}",
-                serde_json::to_string_pretty(response.get_ref())
-                    .unwrap()
-                    .to_colored_json_auto()
-                    .unwrap()
-            );
+            ctx.print_json(response.get_ref());
         }
         OutputFormat::Default => {
             let replicas = &response.get_ref().replicas;
@@ -18,6 +18,7 @@ use mayastor::{
         Reactors,
     },
     grpc,
+    health,
     logger,
     persistent_store::PersistentStore,
     subsys::Registration,
@@ -30,6 +31,7 @@ const PAGES_NEEDED: u32 = 1024;
 mayastor::CPS_INIT!();
 fn start_tokio_runtime(args: &MayastorCliArgs) {
     let grpc_address = grpc::endpoint(args.grpc_endpoint.clone());
+    let rest_address = args.rest_endpoint.clone().map(grpc::rest::endpoint);
     let registration_addr = args.registration_endpoint.clone();
     let rpc_address = args.rpc_address.clone();
     let node_name = args
@@ -59,6 +61,10 @@ fn start_tokio_runtime(args: &MayastorCliArgs) {
                 grpc::MayastorGrpcServer::run(grpc_address, rpc_address)
                     .boxed(),
             );
+            if let Some(rest_address) = rest_address {
+                futures
+                    .push(grpc::rest::run(rest_address, grpc_address).boxed());
+            }
 
             futures::future::try_join_all(futures)
                 .await
@@ -137,6 +143,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
     info!("kernel nvme initiator multipath support: {}", nvme_mp);
 
+    if let Some(endpoint) = args.health_endpoint.clone() {
+        health::start(health::endpoint(endpoint));
+    }
+
     let ms = MayastorEnvironment::new(args.clone()).init();
     start_tokio_runtime(&args);
 
@@ -6,9 +6,17 @@ use std::{
 
 use async_trait::async_trait;
 use nix::errno::Errno;
+use serde::Serialize;
 use snafu::ResultExt;
 
-use spdk_rs::libspdk::spdk_bdev;
+use spdk_rs::libspdk::{
+    spdk_bdev,
+    spdk_bdev_get_max_active_zones,
+    spdk_bdev_get_max_open_zones,
+    spdk_bdev_get_num_zones,
+    spdk_bdev_get_zone_size,
+    spdk_bdev_is_zoned,
+};
 
 use crate::{
     bdev::SpdkBlockDevice,
@@ -21,7 +29,7 @@ use crate::{
         UnshareNvmf,
     },
     nexus_uri::bdev_uri_eq,
-    subsys::NvmfSubsystem,
+    subsys::{unwatch_share, watch_share, NvmfSubsystem},
     target::nvmf,
 };
 
@@ -39,6 +47,18 @@ pub struct Bdev<T: spdk_rs::BdevOps> {
 /// TODO
 pub type UntypedBdev = Bdev<()>;
 
+/// Zone geometry of a zoned bdev, such as a ZNS namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ZoneInfo {
+    /// size of a zone in bytes
+    pub zone_size: u64,
+    pub num_zones: u64,
+    /// maximum number of zones open at the same time, 0 if unlimited
+    pub max_open_zones: u32,
+    /// maximum number of open or closed zones, 0 if unlimited
+    pub max_active_zones: u32,
+}
+
 /// Allow transparent use of `spdk_rs` methods.
 impl<T> Deref for Bdev<T>
 where
@@ -126,6 +146,16 @@ impl<T: spdk_rs::BdevOps> Bdev<T> {
         spdk_rs::Bdev::<T>::lookup_by_name(name).map(Self::new)
     }
 
+    /// Looks up a Bdev by its uuid or, failing that, by its name, for RPCs
+    /// which accept either. An id which is the uuid of a bdev and the name
+    /// of another refers to the former.
+    pub fn lookup_by_name_or_uuid(id: &str) -> Option<Self> {
+        uuid::Uuid::parse_str(id)
+            .ok()
+            .and_then(|_| Self::lookup_by_uuid_str(id))
+            .or_else(|| Self::lookup_by_name(id))
+    }
+
     /// Looks up a Bdev by its uuid.
     pub fn lookup_by_uuid_str(uuid: &str) -> Option<Self> {
         match Self::bdev_first() {
@@ -148,6 +178,29 @@ impl<T: spdk_rs::BdevOps> Bdev<T> {
         self.inner.module_name()
     }
 
+    /// Returns true if the bdev is a zoned device, which can only be written
+    /// sequentially within each zone.
+    pub fn is_zoned(&self) -> bool {
+        unsafe { spdk_bdev_is_zoned(self.unsafe_inner_mut_ptr()) }
+    }
+
+    /// Returns the zone geometry of a zoned bdev.
+    pub fn zone_info(&self) -> Option<ZoneInfo> {
+        if !self.is_zoned() {
+            return None;
+        }
+        let bdev = self.unsafe_inner_mut_ptr();
+        unsafe {
+            Some(ZoneInfo {
+                zone_size: spdk_bdev_get_zone_size(bdev)
+                    * self.block_len() as u64,
+                num_zones: spdk_bdev_get_num_zones(bdev),
+                max_open_zones: spdk_bdev_get_max_open_zones(bdev),
+                max_active_zones: spdk_bdev_get_max_active_zones(bdev),
+            })
+        }
+    }
+
     /// Returns the first bdev in the list.
     pub fn bdev_first() -> Option<Self> {
         BdevIter::<T>::new().next()
@@ -185,6 +238,7 @@ where
         cntlid_range: Option<(u16, u16)>,
     ) -> Result<Self::Output, Self::Error> {
         let me = unsafe { self.get_unchecked_mut() };
+        let name = me.name().to_string();
 
         let subsystem = NvmfSubsystem::try_from(me).context(ShareNvmf {})?;
         if let Some((cntlid_min, cntlid_max)) = cntlid_range {
@@ -192,7 +246,9 @@ where
                 .set_cntlid_range(cntlid_min, cntlid_max)
                 .context(ShareNvmf {})?;
         }
-        subsystem.start().await.context(ShareNvmf {})
+        let uri = subsystem.start().await.context(ShareNvmf {})?;
+        watch_share(&name);
+        Ok(uri)
     }
 
     /// unshare the bdev regardless of current active share
@@ -201,6 +257,7 @@ where
     ) -> Result<Self::Output, Self::Error> {
         match self.shared() {
             Some(Protocol::Nvmf) => {
+                unwatch_share(self.name());
                 if let Some(subsystem) = NvmfSubsystem::nqn_lookup(self.name())
                 {
                     subsystem.stop().await.context(UnshareNvmf {})?;
@@ -5,6 +5,7 @@ use spdk_rs::{DmaBuf, DmaError, IoVec};
 use async_trait::async_trait;
 use merge::Merge;
 use nix::errno::Errno;
+use serde::Serialize;
 use std::os::raw::c_void;
 use uuid::Uuid;
 
@@ -25,6 +26,84 @@ pub struct BlockDeviceIoStats {
     pub bytes_unmapped: u64,
 }
 
+/// End-to-end protection information (T10 PI) format of a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ProtectionInfo {
+    /// size of the metadata of a block in bytes
+    pub md_size: u64,
+    /// PI type, 1 to 3
+    pub pi_type: u8,
+    /// the metadata is transferred with the data of the block (DIF) rather
+    /// than in a separate buffer (DIX)
+    pub md_interleave: bool,
+}
+
+impl ProtectionInfo {
+    /// Returns the format shared by all the given devices, None if any of
+    /// them has none or they differ.
+    pub fn common(
+        formats: impl IntoIterator<Item = Option<Self>>,
+    ) -> Option<Self> {
+        let mut formats = formats.into_iter();
+        let first = formats.next()??;
+        if formats.all(|f| f == Some(first)) {
+            Some(first)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if some of the given devices interleave metadata with
+    /// their data and the others do not do so alike. The nexus copies the
+    /// blocks of a child to another as they are, so the metadata of such a
+    /// child would land in the data of the others, or the other way round.
+    pub fn mixed_interleave(formats: &[Option<Self>]) -> bool {
+        let interleaved =
+            |f: &Option<Self>| f.map_or(false, |f| f.md_interleave);
+        formats.iter().any(interleaved)
+            && formats.iter().any(|f| f != &formats[0])
+    }
+}
+
+/// How a device takes the scatter gather list of an IO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SglCaps {
+    /// the device takes segments of any length at any offset of a page,
+    /// rather than PRPs, which need all segments but the first to start on a
+    /// page and all but the last to end on one
+    pub sgl: bool,
+    /// alignment in bytes the segments must start at
+    pub buf_align: u64,
+}
+
+impl Default for SglCaps {
+    fn default() -> Self {
+        Self {
+            sgl: true,
+            buf_align: 1,
+        }
+    }
+}
+
+impl SglCaps {
+    /// size of a page for PRPs
+    const PAGE_SIZE: u64 = 4096;
+
+    /// returns true if the device takes the segments as they are
+    pub fn accepts(&self, iovs: &[IoVec]) -> bool {
+        let align = self.buf_align.max(1);
+        let last = iovs.len().saturating_sub(1);
+        iovs.iter().enumerate().all(|(i, iov)| {
+            let start = iov.iov_base as u64;
+            let end = start + iov.iov_len as u64;
+            start % align == 0
+                && (self.sgl
+                    || ((i == 0 || start % Self::PAGE_SIZE == 0)
+                        && (i == last || end % Self::PAGE_SIZE == 0)))
+        })
+    }
+}
+
 /// Core trait that represents a block device.
 /// TODO: Add text.
 #[async_trait(?Send)]
@@ -56,6 +135,17 @@ pub trait BlockDevice {
     /// Checks whether target I/O type is supported by the device.
     fn io_type_supported(&self, io_type: IoType) -> bool;
 
+    /// Returns the protection information format of the device, None if it
+    /// has no protection information or it is not known.
+    fn protection_info(&self) -> Option<ProtectionInfo> {
+        None
+    }
+
+    /// Returns how the device takes the scatter gather lists of IOs.
+    fn sgl_caps(&self) -> SglCaps {
+        SglCaps::default()
+    }
+
     /// Obtains I/O statistics for the device.
     async fn io_stats(&self) -> Result<BlockDeviceIoStats, CoreError>;
 
@@ -177,6 +267,14 @@ pub trait BlockDeviceHandle {
         cb_arg: IoCompletionCallbackArg,
     ) -> Result<(), CoreError>;
 
+    /// Flush the volatile write cache of the device, if any, so that all
+    /// writes completed so far are on stable media.
+    fn flush_io(
+        &self,
+        cb: IoCompletionCallback,
+        cb_arg: IoCompletionCallbackArg,
+    ) -> Result<(), CoreError>;
+
     /// TODO
     fn write_zeroes(
         &self,
@@ -299,3 +397,52 @@ pub trait DeviceIoController {
         action: DeviceTimeoutAction,
     ) -> Result<(), CoreError>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_protection_info() {
+        let pi = ProtectionInfo {
+            md_size: 8,
+            pi_type: 1,
+            md_interleave: false,
+        };
+        let other = ProtectionInfo {
+            pi_type: 3,
+            ..pi
+        };
+        assert_eq!(ProtectionInfo::common(vec![Some(pi), Some(pi)]), Some(pi));
+        assert_eq!(ProtectionInfo::common(vec![Some(pi), Some(other)]), None);
+        assert_eq!(ProtectionInfo::common(vec![Some(pi), None]), None);
+        assert_eq!(ProtectionInfo::common(vec![None, Some(pi)]), None);
+        assert_eq!(ProtectionInfo::common(vec![]), None);
+    }
+
+    #[test]
+    fn mixed_interleave() {
+        let dix = ProtectionInfo {
+            md_size: 8,
+            pi_type: 1,
+            md_interleave: false,
+        };
+        let dif = ProtectionInfo {
+            md_interleave: true,
+            ..dix
+        };
+        // separate metadata is not copied, so it may differ
+        assert!(!ProtectionInfo::mixed_interleave(&[Some(dix), None]));
+        assert!(!ProtectionInfo::mixed_interleave(&[Some(dif), Some(dif)]));
+        assert!(ProtectionInfo::mixed_interleave(&[Some(dif), None]));
+        assert!(ProtectionInfo::mixed_interleave(&[None, Some(dif)]));
+        assert!(ProtectionInfo::mixed_interleave(&[Some(dix), Some(dif)]));
+        assert!(ProtectionInfo::mixed_interleave(&[
+            Some(dif),
+            Some(ProtectionInfo {
+                md_size: 16,
+                ..dif
+            })
+        ]));
+    }
+}
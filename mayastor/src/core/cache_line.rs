@@ -0,0 +1,70 @@
+//!
+//! Keeping data written by several cores on a cache line of its own.
+//!
+//! A value written by a core invalidates the cache line it is on in the
+//! caches of the other cores, along with the data next to it that they only
+//! read. Aligning the value to a cache line is the usual cure, but the
+//! buffers SPDK places channel data in are only aligned to 8 bytes, which
+//! an over-aligned type can not live in. `Padded` surrounds the value with
+//! the size of a cache line less a word on both sides instead, so that no
+//! other data is on its cache line wherever it is placed.
+
+use std::{
+    fmt::{self, Debug},
+    ops::{Deref, DerefMut},
+};
+
+/// size of a cache line in bytes
+pub const CACHE_LINE: usize = 64;
+
+/// bytes of padding on each side of a value
+const PAD: usize = CACHE_LINE - 8;
+
+/// A value alone on its cache line, for values of up to a word.
+#[repr(C)]
+pub struct Padded<T> {
+    _before: [u8; PAD],
+    value: T,
+    _after: [u8; PAD],
+}
+
+impl<T> Padded<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            _before: [0; PAD],
+            value,
+            _after: [0; PAD],
+        }
+    }
+
+    /// returns the value
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: Default> Default for Padded<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> Deref for Padded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Padded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: Debug> Debug for Padded<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
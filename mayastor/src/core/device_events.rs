@@ -4,19 +4,22 @@ use std::{
     sync::{Arc, Mutex, Weak},
 };
 
-/// TODO
+use super::{BlockDevice, CoreError};
+
+/// Events raised by block devices, translated from the events of the SPDK
+/// bdevs and the NVMe controllers.
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum DeviceEventType {
-    /// TODO
+    /// the device is being removed, its descriptors must be closed
     DeviceRemoved,
 
-    /// TODO
+    /// the number of blocks of the device changed
     DeviceResized,
 
-    /// TODO
+    /// the device has media management events pending
     MediaManagement,
 
-    /// TODO
+    /// an admin command of the NVMe controller of the device failed
     AdminCommandCompletionFailed,
 }
 
@@ -113,6 +116,68 @@ impl DeviceEventSink {
     }
 }
 
+/// Listener calling a closure with the events of a device.
+struct FnListener {
+    name: String,
+    f: Box<dyn FnMut(DeviceEventType, &str)>,
+}
+
+impl DeviceEventListener for FnListener {
+    fn handle_device_event(
+        mut self: Pin<&mut Self>,
+        evt: DeviceEventType,
+        dev_name: &str,
+    ) {
+        (self.f)(evt, dev_name)
+    }
+
+    fn get_listener_name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+/// Subscription of a closure to the events of a device, for code that has
+/// no listener object of its own, like pools and shares. The closure is
+/// called with the events of the device, on the thread the device raises
+/// them on, until the subscription is dropped.
+pub struct DeviceEventSubscription {
+    sink: DeviceEventSink,
+    // the sink points into the listener, which must outlive it
+    _listener: Pin<Box<FnListener>>,
+}
+
+impl Debug for DeviceEventSubscription {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "device event subscription {}", self.name())
+    }
+}
+
+impl DeviceEventSubscription {
+    /// Subscribe the closure to the events of the device, naming the
+    /// subscriber for the logs.
+    pub fn new(
+        device: &dyn BlockDevice,
+        name: &str,
+        f: impl FnMut(DeviceEventType, &str) + 'static,
+    ) -> Result<Self, CoreError> {
+        let mut listener = Box::pin(FnListener {
+            name: name.to_string(),
+            f: Box::new(f),
+        });
+        let sink = DeviceEventSink::new(listener.as_mut());
+        device.add_event_listener(sink.clone())?;
+        Ok(Self {
+            sink,
+            _listener: listener,
+        })
+    }
+
+    /// returns the name of the subscriber
+    pub fn name(&self) -> String {
+        self.sink.get_listener_name()
+    }
+}
+
 /// TODO
 #[derive(Default)]
 pub struct DeviceEventDispatcher {
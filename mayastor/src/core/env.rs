@@ -39,14 +39,24 @@ use tokio::runtime::Builder;
 use crate::{
     bdev::{bdev_io_ctx_pool_init, nexus, nvme_io_ctx_pool_init},
     core::{
+        fault_injection,
         reactor::{Reactor, ReactorState, Reactors},
         Cores,
         MayastorFeatures,
         Mthread,
     },
-    grpc,
+    grpc::{
+        self,
+        audit::AuditLog,
+        limit::{self, LimitOpts},
+        rbac,
+    },
+    handoff,
+    key_manager::KeyManager,
     logger,
     persistent_store::PersistentStore,
+    startup,
+    state_dump,
     subsys::{self, Config, PoolConfig},
 };
 
@@ -126,6 +136,64 @@ pub struct MayastorCliArgs {
     #[structopt(short = "p")]
     /// Endpoint of the persistent store.
     pub persistent_store_endpoint: Option<String>,
+    #[structopt(long = "kms-endpoint")]
+    /// Endpoint of the HTTP key management service.
+    pub kms_endpoint: Option<String>,
+    #[structopt(long = "kms-token-file")]
+    /// Path to the file holding the token for the key management service.
+    pub kms_token_file: Option<String>,
+    #[structopt(long = "rest-endpoint")]
+    /// IP address and port for the REST gateway of the gRPC API to listen
+    /// on, the gateway is disabled if not given.
+    pub rest_endpoint: Option<String>,
+    #[structopt(long = "health-endpoint")]
+    /// IP address and port for the HTTP liveness and readiness probes to be
+    /// served on, they are not served if not given.
+    pub health_endpoint: Option<String>,
+    #[structopt(long = "handoff-from")]
+    /// Path to the rpc socket of the mayastor running on this node, which
+    /// this one takes the pools and shares over from once initialized.
+    pub handoff_from: Option<String>,
+    #[structopt(long = "audit-log")]
+    /// Path to the audit log of the gRPC calls changing state.
+    pub audit_log: Option<String>,
+    #[structopt(long = "audit-log-size", default_value = "64")]
+    /// Size of the audit log in MiB above which it is rotated.
+    pub audit_log_size: u64,
+    #[structopt(long = "audit-log-files", default_value = "4")]
+    /// Number of rotated audit logs kept.
+    pub audit_log_files: u32,
+    #[structopt(long = "grpc-tokens")]
+    /// Path to the file of tokens allowed to call the gRPC API, and their
+    /// roles.
+    pub grpc_tokens: Option<String>,
+    #[structopt(long = "grpc-rate-limit", default_value = "0")]
+    /// Number of gRPC calls admitted per second, 0 admits them all.
+    pub grpc_rate_limit: u32,
+    #[structopt(long = "grpc-heavy-concurrency", default_value = "1")]
+    /// Number of calls of a heavy gRPC method, such as creating a pool or
+    /// starting a rebuild, running at a time.
+    pub grpc_heavy_concurrency: usize,
+    #[structopt(long = "grpc-heavy-queue", default_value = "16")]
+    /// Number of calls of a heavy gRPC method waiting to run, above which
+    /// calls are rejected.
+    pub grpc_heavy_queue: usize,
+    #[structopt(long = "control-core")]
+    /// Core of the thread running the operations of the gRPC API, the init
+    /// thread runs them if not given.
+    pub control_core: Option<u32>,
+    #[structopt(long = "control-max-pending", default_value = "256")]
+    /// Number of gRPC operations pending on the control thread above which
+    /// calls are rejected.
+    pub control_max_pending: usize,
+    #[structopt(long = "crash-dir")]
+    /// Directory to dump the state to on panic, the temporary directory by
+    /// default.
+    pub crash_dir: Option<String>,
+    #[structopt(long = "enable-fault-injection")]
+    /// Allow fault injection rules to be installed, for resilience testing
+    /// only.
+    pub enable_fault_injection: bool,
     #[structopt(long = "bdev-pool-size", default_value = "65535")]
     /// Number of entries in memory pool for bdev I/O contexts
     pub bdev_io_ctx_pool_size: u64,
@@ -157,7 +225,23 @@ impl Default for MayastorCliArgs {
     fn default() -> Self {
         Self {
             grpc_endpoint: grpc::default_endpoint().to_string(),
+            rest_endpoint: None,
+            health_endpoint: None,
+            handoff_from: None,
             persistent_store_endpoint: None,
+            kms_endpoint: None,
+            kms_token_file: None,
+            audit_log: None,
+            audit_log_size: 64,
+            audit_log_files: 4,
+            grpc_tokens: None,
+            grpc_rate_limit: 0,
+            grpc_heavy_concurrency: 1,
+            grpc_heavy_queue: 16,
+            control_core: None,
+            control_max_pending: 256,
+            crash_dir: None,
+            enable_fault_injection: false,
             node_name: None,
             env_context: None,
             reactor_mask: "0x1".into(),
@@ -223,10 +307,23 @@ type Result<T, E = EnvError> = std::result::Result<T, E>;
 pub struct MayastorEnvironment {
     pub node_name: String,
     pub grpc_endpoint: Option<std::net::SocketAddr>,
+    pub rest_endpoint: Option<std::net::SocketAddr>,
     pub registration_endpoint: Option<Uri>,
     persistent_store_endpoint: Option<String>,
+    kms_endpoint: Option<String>,
+    kms_token_file: Option<String>,
+    audit_log: Option<String>,
+    audit_log_size: u64,
+    audit_log_files: u32,
+    grpc_tokens: Option<String>,
+    grpc_limits: LimitOpts,
+    control_core: Option<u32>,
+    control_max_pending: usize,
+    crash_dir: Option<String>,
+    enable_fault_injection: bool,
     mayastor_config: Option<String>,
     pool_config: Option<String>,
+    handoff_from: Option<String>,
     delay_subsystem_init: bool,
     enable_coredump: bool,
     env_context: Option<String>,
@@ -261,10 +358,23 @@ impl Default for MayastorEnvironment {
         Self {
             node_name: "mayastor-node".into(),
             grpc_endpoint: None,
+            rest_endpoint: None,
             registration_endpoint: None,
             persistent_store_endpoint: None,
+            kms_endpoint: None,
+            kms_token_file: None,
+            audit_log: None,
+            audit_log_size: 64,
+            audit_log_files: 4,
+            grpc_tokens: None,
+            grpc_limits: LimitOpts::default(),
+            control_core: None,
+            control_max_pending: 256,
+            crash_dir: None,
+            enable_fault_injection: false,
             mayastor_config: None,
             pool_config: None,
+            handoff_from: None,
             delay_subsystem_init: false,
             enable_coredump: true,
             env_context: None,
@@ -371,11 +481,28 @@ impl MayastorEnvironment {
     pub fn new(args: MayastorCliArgs) -> Self {
         Self {
             grpc_endpoint: Some(grpc::endpoint(args.grpc_endpoint)),
+            rest_endpoint: args.rest_endpoint.map(grpc::rest::endpoint),
             registration_endpoint: args.registration_endpoint,
             persistent_store_endpoint: args.persistent_store_endpoint,
+            kms_endpoint: args.kms_endpoint,
+            kms_token_file: args.kms_token_file,
+            audit_log: args.audit_log,
+            audit_log_size: args.audit_log_size,
+            audit_log_files: args.audit_log_files,
+            grpc_tokens: args.grpc_tokens,
+            grpc_limits: LimitOpts {
+                rate: args.grpc_rate_limit,
+                heavy_concurrency: args.grpc_heavy_concurrency,
+                heavy_queue: args.grpc_heavy_queue,
+            },
+            control_core: args.control_core,
+            control_max_pending: args.control_max_pending,
+            crash_dir: args.crash_dir,
+            enable_fault_injection: args.enable_fault_injection,
             node_name: args.node_name.unwrap_or_else(|| "mayastor-node".into()),
             mayastor_config: args.mayastor_config,
             pool_config: args.pool_config,
+            handoff_from: args.handoff_from,
             log_component: args.log_components,
             mem_size: args.mem_size,
             no_pci: args.no_pci,
@@ -630,6 +757,7 @@ impl MayastorEnvironment {
 
     /// initialize the core, call this before all else
     pub fn init(mut self) -> Self {
+        startup::starting();
         // setup the logger as soon as possible
         self.init_logger().unwrap();
 
@@ -657,6 +785,23 @@ impl MayastorEnvironment {
         // allocate a Reactor per core
         Reactors::init();
 
+        // move the operations of the control plane off the init thread
+        if let Some(core) = self.control_core {
+            if Cores::count().into_iter().any(|c| c == core) {
+                Mthread::init_control(core);
+            } else {
+                warn!("control core {} is not in the reactor mask", core);
+            }
+        }
+        grpc::set_rpc_max_pending(self.control_max_pending);
+
+        // dump the state on panic, now that there is state to dump
+        state_dump::install_panic_hook(self.crash_dir.clone());
+
+        if self.enable_fault_injection {
+            fault_injection::enable();
+        }
+
         // launch the remote cores if any. note that during init these have to
         // be running as during setup cross call will take place.
         Cores::count()
@@ -680,6 +825,11 @@ impl MayastorEnvironment {
         // ensure we are within the context of a spdk thread from here
         Mthread::get_init().enter();
 
+        // the target does not listen until the pools were handed over
+        if self.handoff_from.is_some() {
+            handoff::standby();
+        }
+
         Reactor::block_on(async {
             let (sender, receiver) = oneshot::channel::<bool>();
 
@@ -696,10 +846,20 @@ impl MayastorEnvironment {
             assert!(receiver.await.unwrap());
         });
 
+        // take the pools over from the mayastor running on this node, if any
+        let pool_config = match &self.handoff_from {
+            Some(from) => handoff::take_over(from).or(pool_config),
+            None => pool_config,
+        };
+
         // load any pools that need to be created
         if let Some(config) = pool_config {
             config.import_pools();
         }
+        if let Some(from) = &self.handoff_from {
+            handoff::complete(from);
+        }
+        startup::ready();
 
         self
     }
@@ -721,8 +881,22 @@ impl MayastorEnvironment {
     {
         type FutureResult = Result<(), ()>;
         let grpc_endpoint = self.grpc_endpoint;
+        let rest_endpoint = self.rest_endpoint;
         let rpc_addr = self.rpc_addr.clone();
         let persistent_store_endpoint = self.persistent_store_endpoint.clone();
+        KeyManager::init(
+            self.kms_endpoint.clone(),
+            self.kms_token_file.clone(),
+        )
+        .expect("Failed to configure the KMS");
+        AuditLog::init(
+            self.audit_log.clone(),
+            self.audit_log_size,
+            self.audit_log_files,
+        );
+        rbac::init(self.grpc_tokens.clone())
+            .expect("Failed to load gRPC tokens");
+        limit::init(self.grpc_limits);
         let ms = self.init();
 
         let rt = Builder::new_current_thread().enable_all().build().unwrap();
@@ -739,6 +913,12 @@ impl MayastorEnvironment {
                     grpc_endpoint,
                     rpc_addr,
                 )));
+                if let Some(rest_endpoint) = rest_endpoint {
+                    futures.push(Box::pin(grpc::rest::run(
+                        rest_endpoint,
+                        grpc_endpoint,
+                    )));
+                }
             }
             futures.push(Box::pin(subsys::Registration::run()));
             futures.push(Box::pin(master));
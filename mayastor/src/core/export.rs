@@ -0,0 +1,227 @@
+//!
+//! Export of the data of a bdev to a file.
+//!
+//! When a cluster is degraded the data of a volume may have to be evacuated
+//! from a node without attaching a client to it. An export copies the data
+//! of a bdev, typically a replica or a nexus, to a file on the node, from
+//! where it can be moved off. The bdev is opened read-only, the copy is only
+//! consistent if nothing writes to the bdev meanwhile, so a snapshot or a
+//! nexus that is not shared should be exported.
+//!
+//! Exports run in the background and can be throttled to spare the IO of the
+//! node. They are started with the `bdev_export` json-rpc method and their
+//! progress is reported by the `bdev_exports` one.
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+
+use crate::{
+    core::{BdevHandle, CoreError, Reactors, UntypedBdev},
+    jsonrpc::{jsonrpc_register, Code, RpcErrorCode},
+    sleep::mayastor_sleep,
+};
+
+/// number of bytes read by a single IO
+const EXPORT_CHUNK: u64 = 1024 * 1024;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum ExportError {
+    #[snafu(display("Failed to open bdev {}: {}", name, source))]
+    OpenBdev { name: String, source: CoreError },
+    #[snafu(display("Failed to create export file {}: {}", path, source))]
+    CreateFile {
+        path: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("An export to {} is already running", path))]
+    ExportRunning { path: String },
+}
+
+impl RpcErrorCode for ExportError {
+    fn rpc_error_code(&self) -> Code {
+        match self {
+            Self::OpenBdev {
+                ..
+            } => Code::NotFound,
+            Self::CreateFile {
+                ..
+            } => Code::InvalidParams,
+            Self::ExportRunning {
+                ..
+            } => Code::AlreadyExists,
+        }
+    }
+}
+
+/// Parameters of an export.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportSpec {
+    /// name of the bdev or nexus to export
+    pub bdev: String,
+    /// path of the file on the node to export to
+    pub file: String,
+    /// maximum rate in MiB/s, 0 for no limit
+    #[serde(default)]
+    pub rate_mb: u64,
+    /// replace the file if it exists
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+/// State of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportState {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// An export, running or finished.
+#[derive(Debug, Clone, Serialize)]
+pub struct BdevExport {
+    pub bdev: String,
+    pub file: String,
+    pub state: ExportState,
+    /// size of the bdev in bytes
+    pub size: u64,
+    /// number of bytes exported so far
+    pub exported: u64,
+    /// reason the export failed
+    pub error: Option<String>,
+}
+
+/// Exports by the file they write to.
+static EXPORTS: Lazy<Mutex<HashMap<String, BdevExport>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// returns the exports, running or finished
+pub fn bdev_exports() -> Vec<BdevExport> {
+    EXPORTS.lock().values().cloned().collect()
+}
+
+impl ExportSpec {
+    /// Start exporting the bdev to the file in the background.
+    pub fn start(self) -> Result<BdevExport, ExportError> {
+        if EXPORTS
+            .lock()
+            .get(&self.file)
+            .map_or(false, |e| e.state == ExportState::Running)
+        {
+            return Err(ExportError::ExportRunning {
+                path: self.file,
+            });
+        }
+
+        let hdl = UntypedBdev::open_by_name(&self.bdev, false)
+            .and_then(|desc| desc.into_handle())
+            .context(OpenBdev {
+                name: self.bdev.clone(),
+            })?;
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .create_new(!self.overwrite)
+            .truncate(true)
+            .open(&self.file)
+            .context(CreateFile {
+                path: self.file.clone(),
+            })?;
+
+        let export = BdevExport {
+            bdev: self.bdev.clone(),
+            file: self.file.clone(),
+            state: ExportState::Running,
+            size: hdl.get_bdev().size_in_bytes(),
+            exported: 0,
+            error: None,
+        };
+        EXPORTS.lock().insert(self.file.clone(), export.clone());
+
+        info!("exporting bdev {} to {}", self.bdev, self.file);
+        Reactors::current()
+            .spawn_local(async move {
+                let result = self.run(hdl, file).await;
+                if let Some(export) = EXPORTS.lock().get_mut(&self.file) {
+                    match result {
+                        Ok(_) => export.state = ExportState::Completed,
+                        Err(e) => {
+                            error!(
+                                "failed to export bdev {} to {}: {}",
+                                self.bdev, self.file, e
+                            );
+                            export.state = ExportState::Failed;
+                            export.error = Some(e);
+                        }
+                    }
+                }
+            })
+            .detach();
+
+        Ok(export)
+    }
+
+    /// copy the bdev to the file at the configured rate
+    async fn run(&self, hdl: BdevHandle, mut file: File) -> Result<(), String> {
+        let size = hdl.get_bdev().size_in_bytes();
+        let start = Instant::now();
+
+        let mut offset = 0;
+        while offset < size {
+            let len = std::cmp::min(EXPORT_CHUNK, size - offset);
+            let mut buf = hdl
+                .dma_malloc(len)
+                .map_err(|_| "out of memory".to_string())?;
+            hdl.read_at(offset, &mut buf)
+                .await
+                .map_err(|e| e.to_string())?;
+            file.write_all(buf.as_slice()).map_err(|e| e.to_string())?;
+            offset += len;
+
+            if let Some(export) = EXPORTS.lock().get_mut(&self.file) {
+                export.exported = offset;
+            }
+
+            if self.rate_mb > 0 {
+                let due = Duration::from_secs_f64(
+                    offset as f64 / (self.rate_mb * 1024 * 1024) as f64,
+                );
+                if let Some(ahead) = due.checked_sub(start.elapsed()) {
+                    let _ = mayastor_sleep(ahead).await;
+                }
+            }
+        }
+
+        file.sync_all().map_err(|e| e.to_string())
+    }
+}
+
+async fn export(args: ExportSpec) -> Result<BdevExport, ExportError> {
+    args.start()
+}
+
+async fn list(_: ()) -> Result<Vec<BdevExport>, ExportError> {
+    Ok(bdev_exports())
+}
+
+/// Register the json-rpc methods exporting bdevs and following the exports.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, ExportError>(
+        "bdev_export",
+        |args: ExportSpec| export(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, ExportError>("bdev_exports", |args: ()| {
+        list(args).boxed_local()
+    });
+}
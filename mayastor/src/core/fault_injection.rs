@@ -0,0 +1,408 @@
+//!
+//! Fault injection, to test how the data path copes with misbehaving
+//! devices.
+//!
+//! Rules installed at runtime make IOs to the children of nexuses, or the
+//! IOs of rebuild jobs, fail or complete late. A rule selects IOs by block
+//! device, type and minimum size, and acts on a percentage of them, on every
+//! Nth one, or both, optionally for a limited number of times:
+//!
+//! ```json
+//! {"id": "flaky", "device": "m1", "io": "write", "percent": 10,
+//!  "action": "fail"}
+//! {"id": "slow", "io": "read", "min_size": 1048576, "action": "delay",
+//!  "delay_ms": 50}
+//! {"id": "lossy", "domain": "rebuild", "every": 100, "action": "fail"}
+//! ```
+//!
+//! IOs of nexuses selected by a rule are still submitted to the child, it is
+//! their completion that is turned into a failure or held back, so that the
+//! child never sees an IO it did not get to complete. IOs of rebuild jobs are
+//! held back or failed before they are submitted. Fault injection must be
+//! enabled when mayastor starts, with `--enable-fault-injection`; until then
+//! rules are refused and the data path only checks a flag. Rules are managed
+//! with the `fault_inject_add`, `fault_inject_remove` and `fault_inject_list`
+//! json-rpc methods.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+
+use crate::{
+    core::IoType,
+    jsonrpc::{jsonrpc_register, Code, RpcErrorCode},
+};
+
+/// set once fault injection is enabled
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// set while there are rules, so that the data path only checks a flag
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static RULES: Lazy<RwLock<Vec<Arc<Rule>>>> =
+    Lazy::new(|| RwLock::new(Vec::new()));
+
+#[derive(Debug, Snafu)]
+pub enum FaultError {
+    #[snafu(display(
+        "Fault injection is not enabled, start with --enable-fault-injection"
+    ))]
+    NotEnabled {},
+    #[snafu(display("Fault rule {} already exists", id))]
+    RuleExists { id: String },
+    #[snafu(display("Fault rule {} not found", id))]
+    RuleNotFound { id: String },
+    #[snafu(display("Invalid fault rule {}: {}", id, msg))]
+    InvalidRule { id: String, msg: String },
+}
+
+impl RpcErrorCode for FaultError {
+    fn rpc_error_code(&self) -> Code {
+        match self {
+            Self::NotEnabled {} => Code::InvalidRequest,
+            Self::RuleExists {
+                ..
+            } => Code::AlreadyExists,
+            Self::RuleNotFound {
+                ..
+            } => Code::NotFound,
+            Self::InvalidRule {
+                ..
+            } => Code::InvalidParams,
+        }
+    }
+}
+
+/// IOs a rule applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultDomain {
+    /// IOs of nexuses to their children
+    Child,
+    /// IOs of rebuild jobs, to the source and the destination
+    Rebuild,
+}
+
+/// Types of IO a rule applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultIo {
+    Read,
+    /// writes, unmaps and write zeroes
+    Write,
+    Any,
+}
+
+/// What happens to an IO selected by a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum FaultAction {
+    /// the IO fails, whatever the device returned
+    Fail,
+    /// the IO completes with what the device returned, late
+    Delay { delay_ms: u64 },
+}
+
+impl FaultAction {
+    /// the time to hold the completion back for, if any
+    pub fn delay(&self) -> Option<Duration> {
+        match self {
+            Self::Fail => None,
+            Self::Delay {
+                delay_ms,
+            } => Some(Duration::from_millis(*delay_ms)),
+        }
+    }
+}
+
+/// A fault injection rule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FaultRule {
+    /// name identifying the rule
+    pub id: String,
+    #[serde(default = "default_domain")]
+    pub domain: FaultDomain,
+    /// name of the block device, any device if omitted
+    #[serde(default)]
+    pub device: Option<String>,
+    #[serde(default = "default_io")]
+    pub io: FaultIo,
+    /// smallest IO in bytes the rule applies to
+    #[serde(default)]
+    pub min_size: u64,
+    /// percentage of the matching IOs to act on
+    #[serde(default = "default_percent")]
+    pub percent: u32,
+    /// act on every Nth matching IO only
+    #[serde(default = "default_every")]
+    pub every: u64,
+    /// stop acting once the rule acted this many times
+    #[serde(default)]
+    pub max_faults: Option<u64>,
+    #[serde(flatten)]
+    pub action: FaultAction,
+}
+
+fn default_domain() -> FaultDomain {
+    FaultDomain::Child
+}
+fn default_io() -> FaultIo {
+    FaultIo::Any
+}
+fn default_percent() -> u32 {
+    100
+}
+fn default_every() -> u64 {
+    1
+}
+
+/// A rule with its counters.
+#[derive(Debug, Clone, Serialize)]
+pub struct FaultRuleStats {
+    #[serde(flatten)]
+    pub rule: FaultRule,
+    /// IOs the rule matched
+    pub matched: u64,
+    /// IOs the rule acted on
+    pub faults: u64,
+}
+
+struct Rule {
+    rule: FaultRule,
+    matched: AtomicU64,
+    faults: AtomicU64,
+}
+
+impl Rule {
+    fn matches(
+        &self,
+        domain: FaultDomain,
+        device: &str,
+        io_type: IoType,
+        len: u64,
+    ) -> bool {
+        let io = match io_type {
+            IoType::Read => FaultIo::Read,
+            IoType::Write | IoType::Unmap | IoType::WriteZeros => {
+                FaultIo::Write
+            }
+            _ => return false,
+        };
+        self.rule.domain == domain
+            && (self.rule.io == FaultIo::Any || self.rule.io == io)
+            && len >= self.rule.min_size
+            && self.rule.device.as_deref().map_or(true, |d| d == device)
+    }
+
+    /// Returns true if the rule acts on the IO it matched.
+    fn hit(&self) -> bool {
+        let n = self.matched.fetch_add(1, Ordering::Relaxed) + 1;
+        if n % self.rule.every != 0 {
+            return false;
+        }
+        if self.rule.percent < 100
+            && rand::random::<u32>() % 100 >= self.rule.percent
+        {
+            return false;
+        }
+        let faults = self.faults.fetch_add(1, Ordering::Relaxed);
+        match self.rule.max_faults {
+            Some(max) if faults >= max => {
+                self.faults.fetch_sub(1, Ordering::Relaxed);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn stats(&self) -> FaultRuleStats {
+        FaultRuleStats {
+            rule: self.rule.clone(),
+            matched: self.matched.load(Ordering::Relaxed),
+            faults: self.faults.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Enable fault injection, refused otherwise.
+pub fn enable() {
+    warn!("*** Fault injection enabled ***");
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Returns true if fault injection has been enabled.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns true if there are rules, for the data path to skip looking for
+/// a matching rule when there are none.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Install a rule.
+pub fn add_rule(rule: FaultRule) -> Result<(), FaultError> {
+    if !is_enabled() {
+        return Err(FaultError::NotEnabled {});
+    }
+    let invalid = |msg: &str| {
+        Err(FaultError::InvalidRule {
+            id: rule.id.clone(),
+            msg: msg.to_string(),
+        })
+    };
+    if rule.every == 0 {
+        return invalid("every must be at least 1");
+    }
+    if rule.percent == 0 || rule.percent > 100 {
+        return invalid("percent must be between 1 and 100");
+    }
+
+    let mut rules = RULES.write();
+    if rules.iter().any(|r| r.rule.id == rule.id) {
+        return Err(FaultError::RuleExists {
+            id: rule.id,
+        });
+    }
+    warn!("installing fault rule {:?}", rule);
+    rules.push(Arc::new(Rule {
+        rule,
+        matched: AtomicU64::new(0),
+        faults: AtomicU64::new(0),
+    }));
+    ACTIVE.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Remove a rule, returning its last counters.
+pub fn remove_rule(id: &str) -> Result<FaultRuleStats, FaultError> {
+    let mut rules = RULES.write();
+    let i = rules.iter().position(|r| r.rule.id == id).ok_or_else(|| {
+        FaultError::RuleNotFound {
+            id: id.to_string(),
+        }
+    })?;
+    let rule = rules.remove(i);
+    ACTIVE.store(!rules.is_empty(), Ordering::SeqCst);
+    info!("removed fault rule {}", id);
+    Ok(rule.stats())
+}
+
+/// List the rules with their counters.
+pub fn rules() -> Vec<FaultRuleStats> {
+    RULES.read().iter().map(|r| r.stats()).collect()
+}
+
+/// Returns the fault to inject in an IO of len bytes to the given device,
+/// if any. The first rule matching the IO decides.
+pub fn inject(
+    domain: FaultDomain,
+    device: &str,
+    io_type: IoType,
+    len: u64,
+) -> Option<FaultAction> {
+    if !is_active() {
+        return None;
+    }
+    let rules = RULES.read();
+    let rule = rules
+        .iter()
+        .find(|r| r.matches(domain, device, io_type, len))?;
+    if rule.hit() {
+        Some(rule.rule.action)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RemoveRuleArgs {
+    id: String,
+}
+
+async fn fault_inject_add(
+    rule: FaultRule,
+) -> Result<Vec<FaultRuleStats>, FaultError> {
+    add_rule(rule)?;
+    Ok(rules())
+}
+
+async fn fault_inject_remove(
+    args: RemoveRuleArgs,
+) -> Result<FaultRuleStats, FaultError> {
+    remove_rule(&args.id)
+}
+
+async fn fault_inject_list(
+    _args: (),
+) -> Result<Vec<FaultRuleStats>, FaultError> {
+    Ok(rules())
+}
+
+/// Register the json-rpc methods managing fault injection rules.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, FaultError>(
+        "fault_inject_add",
+        |rule: FaultRule| fault_inject_add(rule).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, FaultError>(
+        "fault_inject_remove",
+        |args: RemoveRuleArgs| fault_inject_remove(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, FaultError>("fault_inject_list", |args: ()| {
+        fault_inject_list(args).boxed_local()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(json: &str) -> Rule {
+        Rule {
+            rule: serde_json::from_str(json).unwrap(),
+            matched: AtomicU64::new(0),
+            faults: AtomicU64::new(0),
+        }
+    }
+
+    #[test]
+    fn matching() {
+        let r = rule(
+            r#"{"id": "a", "device": "m1", "io": "write", "min_size": 4096,
+                "action": "delay", "delay_ms": 5}"#,
+        );
+        assert_eq!(r.rule.action.delay(), Some(Duration::from_millis(5)));
+        assert!(r.matches(FaultDomain::Child, "m1", IoType::Write, 4096));
+        assert!(r.matches(FaultDomain::Child, "m1", IoType::Unmap, 8192));
+        assert!(!r.matches(FaultDomain::Child, "m1", IoType::Write, 512));
+        assert!(!r.matches(FaultDomain::Child, "m1", IoType::Read, 4096));
+        assert!(!r.matches(FaultDomain::Child, "m0", IoType::Write, 4096));
+        assert!(!r.matches(FaultDomain::Rebuild, "m1", IoType::Write, 4096));
+    }
+
+    #[test]
+    fn every_nth_limited() {
+        let r = rule(
+            r#"{"id": "b", "domain": "rebuild", "every": 3, "max_faults": 2,
+                "action": "fail"}"#,
+        );
+        let hits: Vec<bool> = (0 .. 12).map(|_| r.hit()).collect();
+        assert_eq!(
+            hits.iter().filter(|h| **h).count(),
+            2,
+            "limited to max_faults"
+        );
+        assert!(hits[2] && hits[5] && !hits[8]);
+        assert_eq!(r.stats().matched, 12);
+    }
+}
@@ -0,0 +1,65 @@
+//!
+//! Iteration over the channels of an IO device as a future.
+//!
+//! SPDK visits the channels of an IO device one after the other, each on
+//! the thread of the channel, and calls a completion callback on the thread
+//! that started the iteration once all were visited. `for_each_channel`
+//! boxes the context passed along with the closure visiting the channels,
+//! and resolves to that context once the iteration completed, or to the
+//! error it stopped with, so that the callers do not each pair a context
+//! type holding a sender with a completion callback.
+
+use std::future::Future;
+
+use futures::channel::oneshot;
+use snafu::Snafu;
+use spdk_rs::{ChannelTraverseStatus, IoDevice, IoDeviceChannelTraverse};
+
+/// Errors of an iteration over the channels of an IO device.
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub")]
+pub enum ForEachChannelError {
+    #[snafu(display("a channel cancelled the iteration"))]
+    Cancelled,
+    #[snafu(display("the iteration was dropped before it completed"))]
+    Dropped,
+}
+
+/// Visit the channels of the device with the context, resolving to the
+/// context once all channels were visited. A channel stops the iteration by
+/// returning anything but `ChannelTraverseStatus::Ok`, which fails it.
+pub fn for_each_channel<D, T, F>(
+    device: &D,
+    ctx: T,
+    mut visit: F,
+) -> impl Future<Output = Result<T, ForEachChannelError>>
+where
+    D: IoDeviceChannelTraverse,
+    T: 'static,
+    F: FnMut(
+            &mut <D as IoDevice>::ChannelData,
+            &mut T,
+        ) -> ChannelTraverseStatus
+        + 'static,
+{
+    let (sender, r) = oneshot::channel();
+    let mut sender = Some(sender);
+
+    device.traverse_io_channels(
+        move |channel, ctx: &mut T| visit(channel, ctx),
+        move |status, ctx| {
+            if let Some(sender) = sender.take() {
+                let _ = sender.send((status, ctx));
+            }
+        },
+        ctx,
+    );
+
+    async move {
+        match r.await {
+            Ok((ChannelTraverseStatus::Ok, ctx)) => Ok(ctx),
+            Ok(_) => Err(ForEachChannelError::Cancelled),
+            Err(_) => Err(ForEachChannelError::Dropped),
+        }
+    }
+}
@@ -10,12 +10,16 @@ use nix::errno::Errno;
 
 use spdk_rs::{
     libspdk::{
+        iovec,
+        spdk_bdev_comparev_and_writev_blocks,
         spdk_bdev_desc,
+        spdk_bdev_flush,
         spdk_bdev_free_io,
         spdk_bdev_io,
         spdk_bdev_nvme_admin_passthru_ro,
         spdk_bdev_read,
         spdk_bdev_reset,
+        spdk_bdev_unmap,
         spdk_bdev_write,
         spdk_bdev_write_zeroes,
         spdk_io_channel,
@@ -212,6 +216,86 @@ impl BdevHandle {
         }
     }
 
+    /// Write the ['DmaBuf'] to the given offset only if the bdev holds the
+    /// data of the compare buffer there, as one atomic operation. A
+    /// miscompare fails like any other error.
+    pub async fn compare_and_write_at(
+        &self,
+        offset: u64,
+        compare: &DmaBuf,
+        buffer: &DmaBuf,
+    ) -> Result<(), CoreError> {
+        let block_len = self.get_bdev().block_len() as u64;
+        let mut compare_iov = iovec {
+            iov_base: **compare,
+            iov_len: compare.len() as _,
+        };
+        let mut write_iov = iovec {
+            iov_base: **buffer,
+            iov_len: buffer.len() as _,
+        };
+
+        let (s, r) = oneshot::channel::<bool>();
+        let errno = unsafe {
+            spdk_bdev_comparev_and_writev_blocks(
+                self.desc.as_ptr(),
+                self.channel.as_ptr(),
+                &mut compare_iov,
+                1,
+                &mut write_iov,
+                1,
+                offset / block_len,
+                buffer.len() / block_len,
+                Some(Self::io_completion_cb),
+                cb_arg(s),
+            )
+        };
+
+        if errno != 0 {
+            return Err(CoreError::CompareAndWriteDispatch {
+                source: Errno::from_i32(errno.abs()),
+                offset,
+                len: buffer.len(),
+            });
+        }
+
+        if r.await.expect("Failed awaiting compare and write IO") {
+            Ok(())
+        } else {
+            Err(CoreError::CompareAndWriteFailed {
+                offset,
+                len: buffer.len(),
+            })
+        }
+    }
+
+    /// flush the write cache of the bdev
+    pub async fn flush(&self) -> Result<(), CoreError> {
+        let (s, r) = oneshot::channel::<bool>();
+        let errno = unsafe {
+            spdk_bdev_flush(
+                self.desc.as_ptr(),
+                self.channel.as_ptr(),
+                0,
+                self.get_bdev().size_in_bytes(),
+                Some(Self::io_completion_cb),
+                cb_arg(s),
+            )
+        };
+
+        if errno != 0 {
+            return Err(CoreError::FlushDispatch {
+                source: Errno::from_i32(errno.abs()),
+            });
+        }
+
+        if r.await.expect("Failed awaiting flush IO") {
+            Ok(())
+        } else {
+            Err(CoreError::FlushFailed {})
+        }
+    }
+
     pub async fn write_zeroes_at(
         &self,
         offset: u64,
@@ -247,6 +331,42 @@ impl BdevHandle {
         }
     }
 
+    /// deallocate len bytes starting at offset
+    pub async fn unmap_at(
+        &self,
+        offset: u64,
+        len: u64,
+    ) -> Result<(), CoreError> {
+        let (s, r) = oneshot::channel::<bool>();
+        let errno = unsafe {
+            spdk_bdev_unmap(
+                self.desc.as_ptr(),
+                self.channel.as_ptr(),
+                offset,
+                len,
+                Some(Self::io_completion_cb),
+                cb_arg(s),
+            )
+        };
+
+        if errno != 0 {
+            return Err(CoreError::UnmapDispatch {
+                source: Errno::from_i32(errno.abs()),
+                offset,
+                len,
+            });
+        }
+
+        if r.await.expect("Failed awaiting unmap IO") {
+            Ok(())
+        } else {
+            Err(CoreError::UnmapFailed {
+                offset,
+                len,
+            })
+        }
+    }
+
     /// create a snapshot, only works for nvme bdev
     /// returns snapshot time as u64 seconds since Unix epoch
     pub async fn create_snapshot(&self) -> Result<u64, CoreError> {
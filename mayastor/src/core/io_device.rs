@@ -5,6 +5,7 @@ use spdk_rs::libspdk::{
     spdk_for_each_channel,
     spdk_for_each_channel_continue,
     spdk_io_channel,
+    spdk_io_channel_get_ctx,
     spdk_io_channel_iter,
     spdk_io_channel_iter_get_channel,
     spdk_io_channel_iter_get_ctx,
@@ -26,6 +27,55 @@ type IoDeviceCreateCb = unsafe extern "C" fn(*mut c_void, *mut c_void) -> i32;
 /// TODO
 type IoDeviceDestroyCb = unsafe extern "C" fn(*mut c_void, *mut c_void);
 
+/// Data of the channels of an I/O device registered with
+/// `IoDevice::register`, which SPDK keeps in the buffer it allocates with
+/// every channel.
+pub trait IoChannelData: Sized {
+    /// Create the data of a new channel of the device, on the thread of the
+    /// channel. `ctx` is the address the data is moved to, which stays the
+    /// same for the lifetime of the channel. None fails the creation of the
+    /// channel.
+    fn create(device: NonNull<c_void>, ctx: NonNull<c_void>) -> Option<Self>;
+
+    /// Destroy the data of a channel of the device, on the thread of the
+    /// channel.
+    fn destroy(self, _device: NonNull<c_void>) {}
+
+    /// returns the data of a channel of a device registered with this type
+    fn from_channel<'c>(ch: *mut spdk_io_channel) -> &'c mut Self {
+        unsafe { &mut *(spdk_io_channel_get_ctx(ch) as *mut Self) }
+    }
+}
+
+/// Channel creation callback of the devices registered with
+/// `IoDevice::register`.
+unsafe extern "C" fn channel_create<C: IoChannelData>(
+    device: *mut c_void,
+    ctx: *mut c_void,
+) -> i32 {
+    let (device, ctx) = match (NonNull::new(device), NonNull::new(ctx)) {
+        (Some(device), Some(ctx)) => (device, ctx),
+        _ => return 1,
+    };
+    match C::create(device, ctx) {
+        Some(data) => {
+            std::ptr::write(ctx.as_ptr() as *mut C, data);
+            0
+        }
+        None => 1,
+    }
+}
+
+/// Channel destruction callback of the devices registered with
+/// `IoDevice::register`.
+unsafe extern "C" fn channel_destroy<C: IoChannelData>(
+    device: *mut c_void,
+    ctx: *mut c_void,
+) {
+    let data = std::ptr::read(ctx as *mut C);
+    data.destroy(NonNull::new_unchecked(device));
+}
+
 /// Abstraction around SPDK I/O device, which hides low-level SPDK
 /// API and provides high-level API for I/O channel traversal.
 impl IoDevice {
@@ -53,6 +103,22 @@ impl IoDevice {
         Self(devptr)
     }
 
+    /// Register an I/O device whose channels hold data of type C, created
+    /// and destroyed along with the channels. The data is moved into the
+    /// buffer SPDK allocates with every channel and dropped when the channel
+    /// is destroyed, so the device implements no callbacks of its own.
+    pub fn register<C: IoChannelData>(
+        devptr: NonNull<c_void>,
+        name: &str,
+    ) -> Self {
+        Self::new::<C>(
+            devptr,
+            name,
+            Some(channel_create::<C>),
+            Some(channel_destroy::<C>),
+        )
+    }
+
     /// Iterate over all I/O channels associated with this I/O device.
     pub fn traverse_io_channels<T, I: 'static>(
         &self,
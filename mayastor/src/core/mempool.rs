@@ -6,6 +6,8 @@
 
 use std::{marker::PhantomData, mem::size_of, os::raw::c_void, ptr::NonNull};
 
+use serde::Serialize;
+
 use spdk_rs::libspdk::{
     spdk_mempool,
     spdk_mempool_count,
@@ -17,6 +19,21 @@ use spdk_rs::libspdk::{
 
 use crate::ffihelper::IntoCString;
 
+/// Usage of a memory pool.
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryPoolStats {
+    pub name: String,
+    /// number of elements of the pool
+    pub capacity: u64,
+    /// size of an element in bytes
+    pub element_size: u64,
+    /// number of elements taken from the pool, None if not known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_use: Option<u64>,
+    /// memory the pool was created with, in bytes
+    pub bytes: u64,
+}
+
 pub struct MemoryPool<T: Sized> {
     pool: NonNull<spdk_mempool>,
     name: String,
@@ -82,6 +99,18 @@ impl<T: Sized> MemoryPool<T> {
             spdk_mempool_put(self.pool.as_ptr(), ptr as *mut c_void);
         }
     }
+
+    /// Returns the usage of the memory pool.
+    pub fn stats(&self) -> MemoryPoolStats {
+        let available = unsafe { spdk_mempool_count(self.pool.as_ptr()) };
+        MemoryPoolStats {
+            name: self.name.clone(),
+            capacity: self.capacity,
+            element_size: size_of::<T>() as u64,
+            in_use: Some(self.capacity.saturating_sub(available)),
+            bytes: self.capacity * size_of::<T>() as u64,
+        }
+    }
 }
 
 impl<T: Sized> Drop for MemoryPool<T> {
@@ -5,7 +5,7 @@ use std::{fmt::Debug, sync::atomic::AtomicUsize, time::Duration};
 use nix::errno::Errno;
 use snafu::Snafu;
 
-pub use bdev::{Bdev, BdevIter, UntypedBdev};
+pub use bdev::{Bdev, BdevIter, UntypedBdev, ZoneInfo};
 pub use block_device::{
     BlockDevice,
     BlockDeviceDescriptor,
@@ -18,7 +18,10 @@ pub use block_device::{
     LbaRangeController,
     OpCompletionCallback,
     OpCompletionCallbackArg,
+    ProtectionInfo,
+    SglCaps,
 };
+pub use cache_line::Padded;
 pub use channel::IoChannel;
 pub use cpu_cores::{Core, Cores};
 pub use descriptor::{Descriptor, RangeContext};
@@ -26,6 +29,7 @@ pub use device_events::{
     DeviceEventDispatcher,
     DeviceEventListener,
     DeviceEventSink,
+    DeviceEventSubscription,
     DeviceEventType,
 };
 pub use env::{
@@ -35,11 +39,13 @@ pub use env::{
     GLOBAL_RC,
     SIG_RECEIVED,
 };
+pub use for_each_channel::{for_each_channel, ForEachChannelError};
 pub use handle::BdevHandle;
-pub use io_device::IoDevice;
+pub use io_device::{IoChannelData, IoDevice};
 pub use reactor::{Reactor, ReactorState, Reactors, REACTOR_LIST};
 pub use runtime::spawn;
 pub use share::{Protocol, Share};
+pub use snapshot::Snapshot;
 pub use spdk_rs::{
     cpu_cores,
     GenericStatusCode,
@@ -54,19 +60,25 @@ use crate::subsys::NvmfError;
 
 mod bdev;
 mod block_device;
+pub mod cache_line;
 mod channel;
 mod descriptor;
 mod device_events;
 mod env;
+pub mod export;
+pub mod fault_injection;
+mod for_each_channel;
 mod handle;
 mod io_device;
 pub mod io_driver;
 pub mod mempool;
 pub mod partition;
+pub mod perf_test;
 pub mod poller;
 mod reactor;
 pub mod runtime;
 mod share;
+pub mod snapshot;
 pub(crate) mod thread;
 
 #[derive(Debug, Snafu, Clone)]
@@ -115,6 +127,10 @@ pub enum CoreError {
     ResetDispatch {
         source: Errno,
     },
+    #[snafu(display("Failed to dispatch flush: {}", source))]
+    FlushDispatch {
+        source: Errno,
+    },
     #[snafu(display(
         "Failed to dispatch NVMe Admin command {:x}h: {}",
         opcode,
@@ -153,6 +169,16 @@ pub enum CoreError {
         source: Errno,
         opcode: u16,
     },
+    #[snafu(display(
+        "Failed to dispatch compare and write at offset {} length {}",
+        offset,
+        len
+    ))]
+    CompareAndWriteDispatch {
+        source: Errno,
+        offset: u64,
+        len: u64,
+    },
     #[snafu(display("Write failed at offset {} length {}", offset, len))]
     WriteFailed {
         offset: u64,
@@ -163,8 +189,19 @@ pub enum CoreError {
         offset: u64,
         len: u64,
     },
+    #[snafu(display(
+        "Compare and write failed or miscompared at offset {} length {}",
+        offset,
+        len
+    ))]
+    CompareAndWriteFailed {
+        offset: u64,
+        len: u64,
+    },
     #[snafu(display("Reset failed"))]
     ResetFailed {},
+    #[snafu(display("Flush failed"))]
+    FlushFailed {},
     #[snafu(display(
         "Write zeroes failed at offset {} length {}",
         offset,
@@ -174,6 +211,11 @@ pub enum CoreError {
         offset: u64,
         len: u64,
     },
+    #[snafu(display("Unmap failed at offset {} length {}", offset, len))]
+    UnmapFailed {
+        offset: u64,
+        len: u64,
+    },
     #[snafu(display("NVMe Admin command {:x}h failed", opcode))]
     NvmeAdminFailed {
         opcode: u16,
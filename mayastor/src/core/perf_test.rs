@@ -0,0 +1,678 @@
+//!
+//! Built-in IO benchmark, to validate the performance of a bdev or nexus in
+//! place without installing fio.
+//!
+//! A perf test keeps a number of IOs of a given size in flight against a bdev
+//! for a given time, using an internal handle on the thread the test is
+//! started from, and reports the IOPS, the bandwidth and the distribution of
+//! the latencies. Tests writing to a bdev destroy its data, so they must be
+//! allowed explicitly. Tests are started with the `perf_test` json-rpc
+//! method.
+//!
+//! The `perf_replay` json-rpc method replays a trace of the IOs of a nexus
+//! against a bdev instead, at the pace they were traced or as fast as the
+//! queue depth allows.
+
+use std::{
+    cell::{Cell, RefCell},
+    io::{BufRead, BufReader},
+    time::{Duration, Instant},
+};
+
+use futures::{future::join_all, FutureExt};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+
+use crate::{
+    bdev::nexus::{TraceOp, TraceRecord},
+    core::{BdevHandle, CoreError},
+    jsonrpc::{jsonrpc_register, Code, RpcErrorCode},
+    sleep::mayastor_sleep,
+};
+
+/// longest test that can be run
+const MAX_RUNTIME_SECS: u64 = 600;
+/// deepest queue that can be kept
+const MAX_QUEUE_DEPTH: u32 = 1024;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum PerfError {
+    #[snafu(display("Failed to open bdev {}: {}", name, source))]
+    OpenBdev { name: String, source: CoreError },
+    #[snafu(display("Invalid perf test: {}", msg))]
+    InvalidTest { msg: String },
+    #[snafu(display(
+        "Writing to bdev {} destroys its data and must be allowed",
+        name
+    ))]
+    WriteNotAllowed { name: String },
+    #[snafu(display("Failed to allocate IO buffer"))]
+    NoMemory {},
+    #[snafu(display("Failed to read trace {}: {}", path, source))]
+    ReadTrace {
+        path: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("Invalid record {} of trace {}: {}", line, path, source))]
+    ParseTrace {
+        path: String,
+        line: usize,
+        source: serde_json::Error,
+    },
+}
+
+impl RpcErrorCode for PerfError {
+    fn rpc_error_code(&self) -> Code {
+        match self {
+            Self::OpenBdev {
+                ..
+            }
+            | Self::ReadTrace {
+                ..
+            } => Code::NotFound,
+            Self::InvalidTest {
+                ..
+            }
+            | Self::WriteNotAllowed {
+                ..
+            }
+            | Self::ParseTrace {
+                ..
+            } => Code::InvalidParams,
+            Self::NoMemory {} => Code::InternalError,
+        }
+    }
+}
+
+/// IO pattern of a perf test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pattern {
+    /// sequential reads
+    Read,
+    /// sequential writes
+    Write,
+    RandRead,
+    RandWrite,
+    /// random reads and writes, mixed according to the read percentage
+    RandRw,
+}
+
+impl Pattern {
+    fn writes(&self) -> bool {
+        !matches!(self, Self::Read | Self::RandRead)
+    }
+
+    fn random(&self) -> bool {
+        !matches!(self, Self::Read | Self::Write)
+    }
+}
+
+/// Parameters of a perf test.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PerfTest {
+    /// name of the bdev or nexus to test
+    pub bdev: String,
+    pub pattern: Pattern,
+    /// size of each IO in bytes, a multiple of the block size
+    #[serde(default = "default_block_size")]
+    pub block_size: u64,
+    /// number of IOs kept in flight
+    #[serde(default = "default_queue_depth")]
+    pub queue_depth: u32,
+    #[serde(default = "default_runtime_secs")]
+    pub runtime_secs: u64,
+    /// percentage of reads of the randrw pattern
+    #[serde(default = "default_read_percent")]
+    pub read_percent: u8,
+    /// allow writing to the bdev, destroying its data
+    #[serde(default)]
+    pub allow_writes: bool,
+}
+
+fn default_block_size() -> u64 {
+    4096
+}
+fn default_queue_depth() -> u32 {
+    32
+}
+fn default_runtime_secs() -> u64 {
+    10
+}
+fn default_read_percent() -> u8 {
+    50
+}
+
+/// Latencies in microseconds.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub min: u64,
+    pub avg: u64,
+    pub p50: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub max: u64,
+}
+
+/// Outcome of a perf test.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PerfResult {
+    pub bdev: String,
+    pub pattern: Pattern,
+    pub block_size: u64,
+    pub queue_depth: u32,
+    /// time the test actually ran
+    pub runtime_ms: u64,
+    pub reads: u64,
+    pub writes: u64,
+    /// number of IOs that failed
+    pub errors: u64,
+    pub iops: u64,
+    /// bandwidth in bytes per second
+    pub bandwidth: u64,
+    pub latency_us: LatencyStats,
+}
+
+/// number of linear sub-buckets of each power of two of the histogram
+const SUB_BUCKETS: u64 = 8;
+const SUB_BITS: u32 = 3;
+
+/// Histogram of latencies in nanoseconds. Buckets grow by powers of two,
+/// each split in linear sub-buckets, so that percentiles are within 12.5%.
+#[derive(Debug)]
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: u64,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: vec![0; (64 * SUB_BUCKETS) as usize],
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    fn index(value: u64) -> usize {
+        if value < SUB_BUCKETS {
+            return value as usize;
+        }
+        let exp = 63 - value.leading_zeros();
+        let sub = (value >> (exp - SUB_BITS)) & (SUB_BUCKETS - 1);
+        ((exp - SUB_BITS + 1) as u64 * SUB_BUCKETS + sub) as usize
+    }
+
+    /// lowest value of the bucket at the index
+    fn value(index: usize) -> u64 {
+        let index = index as u64;
+        if index < SUB_BUCKETS {
+            return index;
+        }
+        let exp = index / SUB_BUCKETS + SUB_BITS as u64 - 1;
+        let sub = index % SUB_BUCKETS;
+        (SUB_BUCKETS + sub) << (exp - SUB_BITS as u64)
+    }
+
+    fn record(&mut self, value: u64) {
+        self.buckets[Self::index(value)] += 1;
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    fn percentile(&self, p: f64) -> u64 {
+        let target = ((self.count as f64 * p).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= target {
+                return Self::value(i).clamp(self.min, self.max);
+            }
+        }
+        self.max
+    }
+
+    fn stats_us(&self) -> LatencyStats {
+        if self.count == 0 {
+            return LatencyStats::default();
+        }
+        LatencyStats {
+            min: self.min / 1000,
+            avg: self.sum / self.count / 1000,
+            p50: self.percentile(0.5) / 1000,
+            p99: self.percentile(0.99) / 1000,
+            p999: self.percentile(0.999) / 1000,
+            max: self.max / 1000,
+        }
+    }
+}
+
+/// state shared by the IO workers of a test, all running on one thread
+struct Run {
+    test: PerfTest,
+    /// number of IOs that fit in the bdev
+    slots: u64,
+    /// next slot of sequential patterns
+    next: Cell<u64>,
+    rng: RefCell<StdRng>,
+    deadline: Instant,
+    reads: Cell<u64>,
+    writes: Cell<u64>,
+    errors: Cell<u64>,
+    latencies: RefCell<Histogram>,
+}
+
+impl Run {
+    /// offset of the next IO and whether it is a write
+    fn next_io(&self) -> (u64, bool) {
+        let mut rng = self.rng.borrow_mut();
+        let slot = if self.test.pattern.random() {
+            rng.gen_range(0 .. self.slots)
+        } else {
+            let slot = self.next.get();
+            self.next.set((slot + 1) % self.slots);
+            slot
+        };
+        let write = match self.test.pattern {
+            Pattern::Read | Pattern::RandRead => false,
+            Pattern::Write | Pattern::RandWrite => true,
+            Pattern::RandRw => {
+                rng.gen_range(0 .. 100) >= self.test.read_percent as u32
+            }
+        };
+        (slot * self.test.block_size, write)
+    }
+
+    /// keep one IO in flight until the deadline
+    async fn worker(&self, hdl: &BdevHandle) -> Result<(), PerfError> {
+        let mut buf = hdl
+            .dma_malloc(self.test.block_size)
+            .map_err(|_| PerfError::NoMemory {})?;
+        buf.fill(0xa5);
+
+        while Instant::now() < self.deadline {
+            let (offset, write) = self.next_io();
+            let start = Instant::now();
+            let result = if write {
+                hdl.write_at(offset, &buf).await
+            } else {
+                hdl.read_at(offset, &mut buf).await
+            };
+
+            match result {
+                Ok(_) => {
+                    let counter =
+                        if write { &self.writes } else { &self.reads };
+                    counter.set(counter.get() + 1);
+                    self.latencies
+                        .borrow_mut()
+                        .record(start.elapsed().as_nanos() as u64);
+                }
+                Err(_) => self.errors.set(self.errors.get() + 1),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PerfTest {
+    fn validate(&self, block_len: u64, size: u64) -> Result<(), PerfError> {
+        let invalid = |msg: String| {
+            Err(PerfError::InvalidTest {
+                msg,
+            })
+        };
+
+        if self.block_size == 0 || self.block_size % block_len != 0 {
+            return invalid(format!(
+                "IO size {} is not a multiple of the block size {}",
+                self.block_size, block_len
+            ));
+        }
+        if self.block_size > size {
+            return invalid(format!(
+                "IO size {} exceeds the size of the bdev",
+                self.block_size
+            ));
+        }
+        if self.queue_depth == 0 || self.queue_depth > MAX_QUEUE_DEPTH {
+            return invalid(format!(
+                "queue depth must be between 1 and {}",
+                MAX_QUEUE_DEPTH
+            ));
+        }
+        if self.runtime_secs == 0 || self.runtime_secs > MAX_RUNTIME_SECS {
+            return invalid(format!(
+                "runtime must be between 1 and {} seconds",
+                MAX_RUNTIME_SECS
+            ));
+        }
+        if self.read_percent > 100 {
+            return invalid("read percentage exceeds 100".to_string());
+        }
+        if self.pattern.writes() && !self.allow_writes {
+            return Err(PerfError::WriteNotAllowed {
+                name: self.bdev.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Run the test on the current thread and report its outcome.
+    pub async fn run(self) -> Result<PerfResult, PerfError> {
+        let hdl = BdevHandle::open(&self.bdev, self.pattern.writes(), false)
+            .context(OpenBdev {
+                name: self.bdev.clone(),
+            })?;
+        let bdev = hdl.get_bdev();
+        let size = bdev.size_in_bytes();
+        self.validate(bdev.block_len() as u64, size)?;
+
+        info!("starting perf test {:?}", self);
+        let start = Instant::now();
+        let run = Run {
+            slots: size / self.block_size,
+            next: Cell::new(0),
+            rng: RefCell::new(StdRng::from_entropy()),
+            deadline: start + Duration::from_secs(self.runtime_secs),
+            reads: Cell::new(0),
+            writes: Cell::new(0),
+            errors: Cell::new(0),
+            latencies: RefCell::new(Histogram::new()),
+            test: self,
+        };
+
+        join_all((0 .. run.test.queue_depth).map(|_| run.worker(&hdl)))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let elapsed = start.elapsed();
+        let ios = run.reads.get() + run.writes.get();
+        let per_sec = |n: u64| {
+            (n as f64 / elapsed.as_secs_f64().max(f64::MIN_POSITIVE)) as u64
+        };
+
+        let result = PerfResult {
+            bdev: run.test.bdev.clone(),
+            pattern: run.test.pattern,
+            block_size: run.test.block_size,
+            queue_depth: run.test.queue_depth,
+            runtime_ms: elapsed.as_millis() as u64,
+            reads: run.reads.get(),
+            writes: run.writes.get(),
+            errors: run.errors.get(),
+            iops: per_sec(ios),
+            bandwidth: per_sec(ios * run.test.block_size),
+            latency_us: run.latencies.borrow().stats_us(),
+        };
+        info!("perf test done {:?}", result);
+        Ok(result)
+    }
+}
+
+/// Parameters of the replay of a trace.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PerfReplay {
+    /// name of the bdev or nexus to replay the trace against
+    pub bdev: String,
+    /// trace file, as written by a nexus trace
+    pub file: String,
+    /// maximum number of IOs kept in flight
+    #[serde(default = "default_queue_depth")]
+    pub queue_depth: u32,
+    /// submit the IOs at the pace they were traced, otherwise as fast as
+    /// possible
+    #[serde(default = "default_timed")]
+    pub timed: bool,
+    /// allow writing to the bdev, destroying its data
+    #[serde(default)]
+    pub allow_writes: bool,
+}
+
+fn default_timed() -> bool {
+    true
+}
+
+/// Outcome of the replay of a trace.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayResult {
+    pub bdev: String,
+    /// time the replay actually ran
+    pub runtime_ms: u64,
+    /// number of IOs replayed
+    pub ios: u64,
+    /// number of IOs that failed
+    pub errors: u64,
+    /// number of IOs that do not fit the bdev and were not replayed
+    pub skipped: u64,
+    pub latency_us: LatencyStats,
+}
+
+/// state shared by the IO workers of a replay, all running on one thread
+struct Replay {
+    records: Vec<TraceRecord>,
+    /// index of the next record to replay
+    next: Cell<usize>,
+    timed: bool,
+    start: Instant,
+    ios: Cell<u64>,
+    errors: Cell<u64>,
+    latencies: RefCell<Histogram>,
+}
+
+impl Replay {
+    /// submit the IO of a record, failures of the bdev being returned as the
+    /// inner result
+    async fn submit(
+        hdl: &BdevHandle,
+        record: &TraceRecord,
+    ) -> Result<Result<(), CoreError>, PerfError> {
+        Ok(match record.op {
+            TraceOp::Read => {
+                let mut buf = hdl
+                    .dma_malloc(record.len)
+                    .map_err(|_| PerfError::NoMemory {})?;
+                hdl.read_at(record.offset, &mut buf).await.map(|_| ())
+            }
+            TraceOp::Write => {
+                let mut buf = hdl
+                    .dma_malloc(record.len)
+                    .map_err(|_| PerfError::NoMemory {})?;
+                buf.fill(0xa5);
+                hdl.write_at(record.offset, &buf).await.map(|_| ())
+            }
+            TraceOp::Unmap => hdl.unmap_at(record.offset, record.len).await,
+            TraceOp::WriteZeroes => {
+                hdl.write_zeroes_at(record.offset, record.len).await
+            }
+            TraceOp::Reset => hdl.reset().await,
+        })
+    }
+
+    /// replay records until there are none left
+    async fn worker(&self, hdl: &BdevHandle) -> Result<(), PerfError> {
+        loop {
+            let i = self.next.get();
+            let record = match self.records.get(i) {
+                Some(record) => record,
+                None => return Ok(()),
+            };
+            self.next.set(i + 1);
+
+            if self.timed {
+                let due = self.start + Duration::from_micros(record.ts_us);
+                let now = Instant::now();
+                if due > now + Duration::from_millis(1) {
+                    let _ = mayastor_sleep(due - now).await;
+                }
+            }
+
+            let start = Instant::now();
+            let result = Self::submit(hdl, record).await?;
+            self.ios.set(self.ios.get() + 1);
+            match result {
+                Ok(()) => self
+                    .latencies
+                    .borrow_mut()
+                    .record(start.elapsed().as_nanos() as u64),
+                Err(_) => self.errors.set(self.errors.get() + 1),
+            }
+        }
+    }
+}
+
+impl PerfReplay {
+    /// read the records of the trace, oldest first
+    fn read_trace(&self) -> Result<Vec<TraceRecord>, PerfError> {
+        let file = std::fs::File::open(&self.file).context(ReadTrace {
+            path: self.file.clone(),
+        })?;
+        let mut records = BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| {
+                line.as_ref().map(|l| !l.trim().is_empty()).unwrap_or(true)
+            })
+            .map(|(i, line)| {
+                let line = line.context(ReadTrace {
+                    path: self.file.clone(),
+                })?;
+                serde_json::from_str::<TraceRecord>(&line).context(ParseTrace {
+                    path: self.file.clone(),
+                    line: i + 1,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        records.sort_by_key(|r| r.ts_us);
+        Ok(records)
+    }
+
+    /// Replay the trace on the current thread and report its outcome.
+    pub async fn run(self) -> Result<ReplayResult, PerfError> {
+        if self.queue_depth == 0 || self.queue_depth > MAX_QUEUE_DEPTH {
+            return Err(PerfError::InvalidTest {
+                msg: format!(
+                    "queue depth must be between 1 and {}",
+                    MAX_QUEUE_DEPTH
+                ),
+            });
+        }
+
+        let mut records = self.read_trace()?;
+        let writes = records.iter().any(|r| r.op != TraceOp::Read);
+        if writes && !self.allow_writes {
+            return Err(PerfError::WriteNotAllowed {
+                name: self.bdev.clone(),
+            });
+        }
+
+        let hdl =
+            BdevHandle::open(&self.bdev, writes, false).context(OpenBdev {
+                name: self.bdev.clone(),
+            })?;
+        let bdev = hdl.get_bdev();
+        let size = bdev.size_in_bytes();
+        let block_len = bdev.block_len() as u64;
+
+        // the trace may come from a larger volume or another block size
+        let total = records.len();
+        records.retain(|r| {
+            r.op == TraceOp::Reset
+                || (r.len != 0
+                    && r.offset % block_len == 0
+                    && r.len % block_len == 0
+                    && r.offset + r.len <= size)
+        });
+        let skipped = (total - records.len()) as u64;
+
+        info!(
+            "replaying {} IOs of trace {} against {}",
+            records.len(),
+            self.file,
+            self.bdev
+        );
+        let replay = Replay {
+            records,
+            next: Cell::new(0),
+            timed: self.timed,
+            start: Instant::now(),
+            ios: Cell::new(0),
+            errors: Cell::new(0),
+            latencies: RefCell::new(Histogram::new()),
+        };
+
+        join_all((0 .. self.queue_depth).map(|_| replay.worker(&hdl)))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let result = ReplayResult {
+            bdev: self.bdev.clone(),
+            runtime_ms: replay.start.elapsed().as_millis() as u64,
+            ios: replay.ios.get(),
+            errors: replay.errors.get(),
+            skipped,
+            latency_us: replay.latencies.borrow().stats_us(),
+        };
+        info!("replay done {:?}", result);
+        Ok(result)
+    }
+}
+
+async fn perf_test(args: PerfTest) -> Result<PerfResult, PerfError> {
+    args.run().await
+}
+
+async fn perf_replay(args: PerfReplay) -> Result<ReplayResult, PerfError> {
+    args.run().await
+}
+
+/// Register the json-rpc methods running perf tests and replaying traces.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, PerfError>("perf_test", |args: PerfTest| {
+        perf_test(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, PerfError>(
+        "perf_replay",
+        |args: PerfReplay| perf_replay(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets() {
+        // every value falls in the bucket starting at or below it
+        for v in (0 .. 100_000).step_by(7).chain([u64::MAX / 3]) {
+            let i = Histogram::index(v);
+            assert!(Histogram::value(i) <= v);
+            assert!(Histogram::value(i + 1) > v);
+        }
+    }
+
+    #[test]
+    fn histogram_percentiles() {
+        let mut h = Histogram::new();
+        (1 ..= 1000).for_each(|v| h.record(v * 1000));
+
+        let stats = h.stats_us();
+        assert_eq!(stats.min, 1);
+        assert_eq!(stats.max, 1000);
+        assert_eq!(stats.avg, 500);
+        // within the precision of the buckets
+        assert!((448 ..= 500).contains(&stats.p50));
+        assert!((896 ..= 990).contains(&stats.p99));
+    }
+}
@@ -1,11 +1,34 @@
+//!
+//! Pollers, i.e. functions SPDK calls periodically on a thread.
+//!
+//! A poller is created with the `Builder`, which names it, sets its period
+//! and the thread it runs on, the current one by default. Every poller is
+//! kept in a registry for as long as it runs, along with the number of times
+//! it ran, the number of runs in which it did work and the time it took, so
+//! that the pollers of the instance can be listed with `poller_list`. A
+//! poller can be paused and resumed through the registry with `poller_pause`
+//! and `poller_resume`, from any thread, in which case it keeps being called
+//! by SPDK but skips its function until resumed.
+
 use std::{
+    collections::HashMap,
     ffi::{c_void, CString},
     fmt,
     ptr::NonNull,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 use spdk_rs::libspdk::{
+    spdk_get_ticks,
+    spdk_get_ticks_hz,
     spdk_poller,
     spdk_poller_pause,
     spdk_poller_register,
@@ -14,14 +37,72 @@ use spdk_rs::libspdk::{
     spdk_poller_unregister,
 };
 
+use crate::{
+    core::Mthread,
+    jsonrpc::{jsonrpc_register, Code, JsonRpcError},
+};
+
+/// the pollers running, by id
+static POLLERS: Lazy<Mutex<HashMap<u64, Arc<PollerInfo>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Registry entry of a poller, updated by the poller as it runs.
+#[derive(Debug)]
+struct PollerInfo {
+    id: u64,
+    name: String,
+    thread: String,
+    interval: Duration,
+    /// skip the function of the poller
+    paused: AtomicBool,
+    runs: AtomicU64,
+    busy_runs: AtomicU64,
+    ticks: AtomicU64,
+}
+
+impl PollerInfo {
+    fn status(&self) -> PollerStatus {
+        let hz = unsafe { spdk_get_ticks_hz() }.max(1);
+        let ticks = self.ticks.load(Ordering::Relaxed);
+        PollerStatus {
+            id: self.id,
+            name: self.name.clone(),
+            thread: self.thread.clone(),
+            interval_us: self.interval.as_micros() as u64,
+            paused: self.paused.load(Ordering::Relaxed),
+            runs: self.runs.load(Ordering::Relaxed),
+            busy_runs: self.busy_runs.load(Ordering::Relaxed),
+            run_time_us: (ticks as u128 * 1_000_000 / hz as u128) as u64,
+        }
+    }
+}
+
 /// structure holding our function and context
-struct PollCtx<'a>(Box<dyn FnMut() -> i32 + 'a>);
+struct PollCtx<'a> {
+    poll_fn: Box<dyn FnMut() -> i32 + 'a>,
+    info: Arc<PollerInfo>,
+}
 
 /// indirection to avoid raw pointers at upper layers
 #[inline(always)]
 extern "C" fn _cb(ctx: *mut c_void) -> i32 {
     let poll = unsafe { &mut *(ctx as *mut PollCtx) };
-    (poll.0)()
+    let info = &poll.info;
+    if info.paused.load(Ordering::Relaxed) {
+        return 0;
+    }
+
+    let start = unsafe { spdk_get_ticks() };
+    let rc = (poll.poll_fn)();
+    let ticks = unsafe { spdk_get_ticks() } - start;
+
+    info.runs.fetch_add(1, Ordering::Relaxed);
+    if rc > 0 {
+        info.busy_runs.fetch_add(1, Ordering::Relaxed);
+    }
+    info.ticks.fetch_add(ticks, Ordering::Relaxed);
+    rc
 }
 
 /// Poller structure that allows us to pause, stop, resume periodic tasks
@@ -47,11 +128,25 @@ impl<'a> fmt::Debug for Poller<'a> {
 impl<'a> Poller<'a> {
     /// stop the given poller and consumes self
     pub fn stop(mut self) {
+        self.unregister();
+    }
+
+    /// returns the id of the poller in the registry
+    pub fn id(&self) -> u64 {
+        unsafe { self.ctx.as_ref() }.info.id
+    }
+
+    /// unregister the poller from SPDK and the registry
+    fn unregister(&mut self) {
+        if self.stopped {
+            return;
+        }
         unsafe {
             spdk_poller_unregister(&mut self.inner.as_ptr());
-            Box::from_raw(self.ctx.as_ptr());
-            self.stopped = true;
+            let ctx = Box::from_raw(self.ctx.as_ptr());
+            POLLERS.lock().remove(&ctx.info.id);
         }
+        self.stopped = true;
     }
 
     /// pause the given poller
@@ -71,12 +166,7 @@ impl<'a> Poller<'a> {
 
 impl<'a> Drop for Poller<'a> {
     fn drop(&mut self) {
-        if !self.stopped {
-            unsafe {
-                spdk_poller_unregister(&mut self.inner.as_ptr());
-                Box::from_raw(self.ctx.as_ptr());
-            }
-        }
+        self.unregister();
     }
 }
 
@@ -84,6 +174,7 @@ impl<'a> Drop for Poller<'a> {
 pub struct Builder<'a> {
     name: Option<CString>,
     interval: std::time::Duration,
+    thread: Option<Mthread>,
     poll_fn: Option<Box<dyn FnMut() -> i32 + 'a>>,
 }
 
@@ -100,6 +191,7 @@ impl<'a> Builder<'a> {
         Self {
             name: None,
             interval: Duration::from_micros(0),
+            thread: None,
             poll_fn: None,
         }
     }
@@ -119,6 +211,12 @@ impl<'a> Builder<'a> {
         self
     }
 
+    /// run the poller on the thread rather than the current one
+    pub fn with_thread(mut self, thread: Mthread) -> Self {
+        self.thread = Some(thread);
+        self
+    }
+
     /// set the function for this poller
     pub fn with_poll_fn(mut self, poll_fn: impl FnMut() -> i32 + 'a) -> Self {
         self.poll_fn = Some(Box::new(poll_fn));
@@ -132,32 +230,51 @@ impl<'a> Builder<'a> {
             .take()
             .expect("can not start poller without poll function");
 
-        let ctx = NonNull::new(Box::into_raw(Box::new(PollCtx(poll_fn))))
-            .expect("failed to allocate new poller context");
+        let name = self.name.as_ref().map_or("<unnamed>".to_string(), |n| {
+            n.to_string_lossy().into_owned()
+        });
+        let thread = self.thread.or_else(Mthread::current);
+        let info = Arc::new(PollerInfo {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            name,
+            thread: thread.map_or(String::new(), |t| t.name().to_string()),
+            interval: self.interval,
+            paused: AtomicBool::new(false),
+            runs: AtomicU64::new(0),
+            busy_runs: AtomicU64::new(0),
+            ticks: AtomicU64::new(0),
+        });
+
+        let ctx = NonNull::new(Box::into_raw(Box::new(PollCtx {
+            poll_fn,
+            info: info.clone(),
+        })))
+        .expect("failed to allocate new poller context");
 
-        let name;
-        let inner = NonNull::new(unsafe {
-            if self.name.is_none() {
-                name = "<unnamed>".to_string();
-                spdk_poller_register(
+        let register = || unsafe {
+            match self.name.as_ref() {
+                None => spdk_poller_register(
                     Some(_cb),
                     ctx.as_ptr().cast(),
                     self.interval.as_micros() as u64,
-                )
-            } else {
-                name =
-                    String::from(self.name.as_ref().unwrap().to_str().unwrap());
-
-                spdk_poller_register_named(
+                ),
+                Some(name) => spdk_poller_register_named(
                     Some(_cb),
                     ctx.as_ptr().cast(),
                     self.interval.as_micros() as u64,
-                    self.name.as_ref().unwrap().as_ptr(),
-                )
+                    name.as_ptr(),
+                ),
             }
+        };
+        let inner = NonNull::new(match self.thread {
+            Some(thread) => thread.with(register),
+            None => register(),
         })
         .expect("failed to register poller");
 
+        let name = info.name.clone();
+        POLLERS.lock().insert(info.id, info);
+
         Poller {
             inner,
             ctx,
@@ -166,3 +283,84 @@ impl<'a> Builder<'a> {
         }
     }
 }
+
+/// State and run statistics of a poller.
+#[derive(Debug, Clone, Serialize)]
+pub struct PollerStatus {
+    pub id: u64,
+    pub name: String,
+    /// name of the thread the poller runs on
+    pub thread: String,
+    pub interval_us: u64,
+    pub paused: bool,
+    /// number of times the poller ran while not paused
+    pub runs: u64,
+    /// number of runs in which the poller did work
+    pub busy_runs: u64,
+    /// time spent running the poller
+    pub run_time_us: u64,
+}
+
+/// returns the pollers running, ordered by id
+pub fn pollers() -> Vec<PollerStatus> {
+    let mut pollers = POLLERS
+        .lock()
+        .values()
+        .map(|p| p.status())
+        .collect::<Vec<_>>();
+    pollers.sort_by_key(|p| p.id);
+    pollers
+}
+
+/// Pause or resume the pollers with the id or name, returning the number of
+/// pollers selected.
+pub fn set_paused(id: Option<u64>, name: Option<&str>, paused: bool) -> usize {
+    POLLERS
+        .lock()
+        .values()
+        .filter(|p| id.map_or(true, |id| p.id == id))
+        .filter(|p| name.map_or(true, |name| p.name == name))
+        .map(|p| p.paused.store(paused, Ordering::Relaxed))
+        .count()
+}
+
+#[derive(Debug, Deserialize)]
+struct PauseArgs {
+    /// id of the poller
+    #[serde(default)]
+    id: Option<u64>,
+    /// name of the pollers
+    #[serde(default)]
+    name: Option<String>,
+}
+
+async fn pause(args: PauseArgs, paused: bool) -> Result<usize, JsonRpcError> {
+    if args.id.is_none() && args.name.is_none() {
+        return Err(JsonRpcError::new(
+            Code::InvalidParams,
+            "the id or the name of the pollers is required".to_string(),
+        ));
+    }
+    match set_paused(args.id, args.name.as_deref(), paused) {
+        0 => Err(JsonRpcError::new(
+            Code::NotFound,
+            format!("no poller matches {:?}", args),
+        )),
+        n => Ok(n),
+    }
+}
+
+/// Register the json-rpc methods listing, pausing and resuming pollers.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, JsonRpcError>("poller_list", |_: ()| {
+        async { Ok(pollers()) }.boxed_local()
+    });
+    jsonrpc_register::<_, _, _, JsonRpcError>(
+        "poller_pause",
+        |args: PauseArgs| pause(args, true).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, JsonRpcError>(
+        "poller_resume",
+        |args: PauseArgs| pause(args, false).boxed_local(),
+    );
+}
@@ -36,6 +36,7 @@ use std::{
     os::raw::c_void,
     pin::Pin,
     slice::Iter,
+    sync::atomic::{AtomicU64, Ordering},
     time::Duration,
 };
 
@@ -110,6 +111,8 @@ pub struct Reactor {
     lcore: u32,
     /// represents the state of the reactor
     flags: Cell<ReactorState>,
+    /// number of times the reactor was polled, for the liveness probe
+    polls: AtomicU64,
     /// sender and Receiver for sending futures across cores without going
     /// through FFI
     sx: Sender<Pin<Box<dyn Future<Output = ()> + 'static>>>,
@@ -276,6 +279,7 @@ impl Reactor {
             incoming: crossbeam::queue::SegQueue::new(),
             lcore: core,
             flags: Cell::new(ReactorState::Init),
+            polls: AtomicU64::new(0),
             sx,
             rx,
         }
@@ -420,6 +424,11 @@ impl Reactor {
         self.lcore
     }
 
+    /// returns the number of times this reactor was polled
+    pub fn polls(&self) -> u64 {
+        self.polls.load(Ordering::Relaxed)
+    }
+
     /// poll this reactor to complete any work that is pending
     pub fn poll_reactor(&self) {
         loop {
@@ -452,6 +461,7 @@ impl Reactor {
     /// now
     #[inline]
     pub fn poll_once(&self) {
+        self.polls.fetch_add(1, Ordering::Relaxed);
         self.receive_futures();
         self.run_futures();
         let threads = self.threads.borrow();
@@ -471,6 +481,7 @@ impl Reactor {
     /// We might want to set a flag that we need to run futures and or incoming
     /// queues
     pub fn poll_times(&self, times: u32) {
+        self.polls.fetch_add(1, Ordering::Relaxed);
         let threads = self.threads.borrow();
         for _ in 0 .. times {
             threads.iter().for_each(|t| {
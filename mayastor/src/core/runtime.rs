@@ -2,12 +2,114 @@
 //! This allows us to send futures from within mayastor to the tokio
 //! runtime to do whatever it needs to do. The tokio threads are
 //! unaffinitized such that they do not run on any of our reactors.
+//!
+//! Futures crossing between the two runtimes are bridged here as well:
+//! [`spawn_await`] runs a future on tokio from a reactor and
+//! [`spawn_on_core`] runs a future on a reactor from tokio, both returning a
+//! [`Completion`] to await the output of the future with.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
 
-use futures::Future;
+use futures::{channel::oneshot, Future};
+use hyper::client::HttpConnector;
+use hyper_rustls::HttpsConnector;
 use once_cell::sync::Lazy;
+use snafu::Snafu;
 use tokio::task::JoinHandle;
 
-use super::Mthread;
+use super::{Mthread, Reactors};
+
+#[derive(Debug, Snafu)]
+pub enum SpawnError {
+    #[snafu(display("No reactor running on core {}", core))]
+    NoReactor { core: u32 },
+}
+
+/// The output of a future spawned on the other runtime, an error if the
+/// future was dropped before it completed.
+#[derive(Debug)]
+pub struct Completion<T>(oneshot::Receiver<T>);
+
+impl<T> Future for Completion<T> {
+    type Output = Result<T, oneshot::Canceled>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+/// Returns a connector for the hyper clients of the tokio runtime speaking
+/// https, trusting the root certificates of the host, as well as plain http.
+pub fn https_connector() -> std::io::Result<HttpsConnector<HttpConnector>> {
+    let mut config = rustls::ClientConfig::new();
+    config.root_store = match rustls_native_certs::load_native_certs() {
+        Ok(store) => store,
+        Err((Some(store), e)) => {
+            warn!("Could not load all root certificates: {}", e);
+            store
+        }
+        Err((None, e)) => return Err(e),
+    };
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    Ok((http, config).into())
+}
+
+/// Run a future on the tokio runtime from a reactor and return its
+/// completion. The output is handed back on the init thread, as the wakers of
+/// the futures of a reactor must be called from a reactor.
+pub fn spawn_await<T>(
+    f: impl Future<Output = T> + Send + 'static,
+) -> Completion<T>
+where
+    T: Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel::<T>();
+    spawn(async move {
+        let output = f.await;
+        let rx = Mthread::get_init()
+            .spawn_local(async move {
+                if sender.send(output).is_err() {
+                    debug!("completion of a tokio future dropped");
+                }
+            })
+            .expect("Failed to send future to Mayastor thread");
+        let _ = rx.await;
+    });
+    Completion(receiver)
+}
+
+/// Run a future on the reactor of the given core, from tokio or any other
+/// thread, and return its completion. The future is made on the core by the
+/// given closure, as the SPDK state it refers to can not move across
+/// threads.
+pub fn spawn_on_core<C, F, R>(
+    core: u32,
+    make: C,
+) -> Result<Completion<R>, SpawnError>
+where
+    C: FnOnce() -> F + Send + 'static,
+    F: Future<Output = R> + 'static,
+    R: Send + 'static,
+{
+    let reactor = Reactors::get_by_core(core).ok_or(SpawnError::NoReactor {
+        core,
+    })?;
+    let (sender, receiver) = oneshot::channel::<R>();
+    reactor.send_future(async move {
+        let output = make().await;
+        if sender.send(output).is_err() {
+            debug!("completion of a future on core {} dropped", core);
+        }
+    });
+    Ok(Completion(receiver))
+}
 
 /// spawn a future on the tokio runtime.
 pub fn spawn(f: impl Future<Output = ()> + Send + 'static) {
@@ -0,0 +1,83 @@
+//!
+//! Values published to readers on any core without locks.
+//!
+//! A `Snapshot` holds the latest version of a value that one side, usually
+//! the control plane on the master reactor, replaces as a whole, and that
+//! readers on any core load without taking a lock the writer may hold. A
+//! reader gets the version current when it loads, which stays valid for as
+//! long as it keeps it, while the writer publishes newer versions. The
+//! versions replaced are freed once no reader can be loading them anymore,
+//! as in RCU.
+
+use std::sync::{atomic::Ordering, Arc};
+
+use crossbeam::epoch::{self, Atomic, Owned};
+
+/// The latest version of a value, published without locks.
+pub struct Snapshot<T> {
+    current: Atomic<Arc<T>>,
+}
+
+impl<T> Snapshot<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            current: Atomic::new(Arc::new(value)),
+        }
+    }
+
+    /// returns the latest version of the value
+    pub fn load(&self) -> Arc<T> {
+        let guard = epoch::pin();
+        let current = self.current.load(Ordering::Acquire, &guard);
+        // never null: a version is replaced, never taken out
+        unsafe { current.deref() }.clone()
+    }
+
+    /// Publish a new version of the value, the readers of the previous one
+    /// keep it until they drop it.
+    pub fn publish(&self, value: T) {
+        let guard = epoch::pin();
+        let previous = self.current.swap(
+            Owned::new(Arc::new(value)),
+            Ordering::AcqRel,
+            &guard,
+        );
+        unsafe { guard.defer_destroy(previous) };
+    }
+}
+
+impl<T: Default> Default for Snapshot<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> Drop for Snapshot<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let current =
+                self.current.load(Ordering::Relaxed, epoch::unprotected());
+            drop(current.into_owned());
+        }
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Snapshot<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.load().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn publish() {
+        let snapshot = Snapshot::new(vec![1]);
+        let first = snapshot.load();
+        snapshot.publish(vec![1, 2]);
+        assert_eq!(*first, vec![1]);
+        assert_eq!(*snapshot.load(), vec![1, 2]);
+    }
+}
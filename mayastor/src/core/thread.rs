@@ -19,6 +19,7 @@ use spdk_rs::libspdk::{
 use crate::core::{cpu_cores::CpuMask, CoreError, Cores, Reactors};
 use futures::channel::oneshot::{channel, Receiver, Sender};
 use nix::errno::Errno;
+use once_cell::sync::OnceCell;
 use std::{fmt::Debug, future::Future, ptr::NonNull};
 
 #[derive(Debug, Snafu)]
@@ -27,6 +28,10 @@ pub enum Error {
     InvalidThread {},
 }
 
+/// id of the thread running the operations of the control plane, when it is
+/// not the init thread
+static CONTROL_THREAD: OnceCell<u64> = OnceCell::new();
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 /// struct that wraps an SPDK thread. The name thread is chosen poorly and
 /// should not be confused with an actual thread. Consider it more to be
@@ -49,6 +54,30 @@ impl Mthread {
         )
     }
 
+    /// Create the control thread on the given core. From then on it runs the
+    /// operations of the control plane instead of the init thread, so that
+    /// they do not compete with the work of the init thread.
+    pub fn init_control(core: u32) -> Option<Mthread> {
+        if let Some(id) = CONTROL_THREAD.get() {
+            warn!("control thread {} already created", id);
+            return None;
+        }
+        let thread = Self::new("control_thread".into(), core)?;
+        info!("Control thread ID {} on core {}", thread.id(), core);
+        CONTROL_THREAD.set(thread.id()).ok()?;
+        Some(thread)
+    }
+
+    /// returns the thread running the operations of the control plane, the
+    /// init thread unless a control thread was created
+    pub fn get_control() -> Mthread {
+        CONTROL_THREAD
+            .get()
+            .and_then(|id| NonNull::new(unsafe { spdk_thread_get_by_id(*id) }))
+            .map(Mthread)
+            .unwrap_or_else(Self::get_init)
+    }
+
     ///
     /// With the given thread as context, execute the closure on that thread.
     ///
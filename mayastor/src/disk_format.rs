@@ -0,0 +1,357 @@
+//!
+//! Versions of the structures mayastor keeps on disk.
+//!
+//! The structures below outlive the mayastor that wrote them, and a rolling
+//! upgrade has a newer and an older mayastor read them in turn. Each has a
+//! version, the one this mayastor writes, and the oldest version it can
+//! read:
+//!
+//! - `journal`: the header of the write journal in the metadata reservation of
+//!   the children of a nexus, with its version next to its magic. A header of a
+//!   version that can not be read counts as missing, so the child is rebuilt in
+//!   full
+//! - `replica_properties`: the properties of a replica kept in the metadata of
+//!   its blob, with its version in the `format_version` property
+//! - `checksums`: the checksum sidecar of a nexus, which has no header to hold
+//!   a version and is at its first version for that reason
+//!
+//! Structures written before they had a version are at version 0. An older
+//! structure is brought to the current version by the migrations from its
+//! version to the next, in order: the properties of replicas are migrated as
+//! their pool is imported, journal headers as they are written again.
+//!
+//! The `check_compatibility` json-rpc method reports the versions this
+//! mayastor supports and those found on the replicas of its pools. Given the
+//! versions supported by another mayastor, the one to upgrade or roll back
+//! to, it also tells whether that one can read what is on disk, and whether
+//! this one could still read what that one would write.
+
+use std::{collections::BTreeMap, pin::Pin};
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    jsonrpc::{jsonrpc_register, JsonRpcError},
+    lvs::{Error, Lvol, Lvs, PropName, PropValue},
+};
+
+/// A structure mayastor keeps on disk.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Structure {
+    Journal,
+    ReplicaProperties,
+    Checksums,
+}
+
+/// The versions of a structure a mayastor supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormatSupport {
+    pub structure: Structure,
+    /// version written
+    pub current: u32,
+    /// oldest version read
+    pub oldest: u32,
+}
+
+impl FormatSupport {
+    /// returns true if the version can be read
+    pub fn reads(&self, version: u32) -> bool {
+        self.oldest <= version && version <= self.current
+    }
+}
+
+/// The versions of the structures this mayastor supports.
+pub const SUPPORTED: [FormatSupport; 3] = [
+    FormatSupport {
+        structure: Structure::Journal,
+        current: 1,
+        oldest: 0,
+    },
+    FormatSupport {
+        structure: Structure::ReplicaProperties,
+        current: 1,
+        oldest: 0,
+    },
+    FormatSupport {
+        structure: Structure::Checksums,
+        current: 1,
+        oldest: 1,
+    },
+];
+
+/// A migration of a structure from a version to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Migration {
+    pub structure: Structure,
+    pub from: u32,
+    pub to: u32,
+    pub description: &'static str,
+}
+
+/// The migrations, in order.
+pub const MIGRATIONS: [Migration; 2] = [
+    Migration {
+        structure: Structure::Journal,
+        from: 0,
+        to: 1,
+        description: "record the version in the header",
+    },
+    Migration {
+        structure: Structure::ReplicaProperties,
+        from: 0,
+        to: 1,
+        description: "record the version in the format_version property",
+    },
+];
+
+impl Structure {
+    /// returns the versions of the structure this mayastor supports
+    pub fn support(self) -> FormatSupport {
+        *SUPPORTED.iter().find(|s| s.structure == self).unwrap()
+    }
+
+    /// returns the version of the structure this mayastor writes
+    pub fn current(self) -> u32 {
+        self.support().current
+    }
+
+    /// returns true if this mayastor can read the version of the structure
+    pub fn reads(self, version: u32) -> bool {
+        self.support().reads(version)
+    }
+
+    /// Returns the migrations bringing the structure from the version to
+    /// the current one, in order.
+    pub fn migrations(self, from: u32) -> Vec<Migration> {
+        let mut version = from;
+        MIGRATIONS
+            .iter()
+            .filter(|m| m.structure == self)
+            .filter(|m| {
+                let next = m.from == version;
+                if next {
+                    version = m.to;
+                }
+                next
+            })
+            .copied()
+            .collect()
+    }
+}
+
+impl Lvol {
+    /// Returns the version of the properties of the lvol, 0 if they predate
+    /// the version being recorded.
+    pub async fn format_version(&self) -> u32 {
+        match self.get(PropName::FormatVersion).await {
+            Ok(PropValue::FormatVersion(version)) => version,
+            _ => 0,
+        }
+    }
+
+    /// record the current version of the properties of a new lvol
+    pub(crate) async fn set_format_version(&mut self) -> Result<(), Error> {
+        let version = Structure::ReplicaProperties.current();
+        Pin::new(self).set(PropValue::FormatVersion(version)).await
+    }
+
+    /// bring the properties of the lvol to the current version
+    async fn migrate(&mut self) -> Result<(), Error> {
+        let version = self.format_version().await;
+        if !Structure::ReplicaProperties.reads(version) {
+            warn!(
+                "{}: properties are of version {}, newer than {}",
+                self.name(),
+                version,
+                Structure::ReplicaProperties.current()
+            );
+            return Ok(());
+        }
+        for m in Structure::ReplicaProperties.migrations(version) {
+            info!(
+                "{}: migrating properties from version {} to {}: {}",
+                self.name(),
+                m.from,
+                m.to,
+                m.description
+            );
+            Pin::new(&mut *self)
+                .set(PropValue::FormatVersion(m.to))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+impl Lvs {
+    /// Bring the properties of the lvols of the pool to the current version,
+    /// which is done as the pool is imported.
+    pub(crate) async fn migrate_formats(&self) {
+        let lvols = match self.lvols() {
+            Some(lvols) => lvols.filter(|l| !l.is_snapshot()),
+            None => return,
+        };
+        for mut lvol in lvols {
+            if let Err(e) = lvol.migrate().await {
+                error!("{}: failed to migrate: {}", lvol.name(), e);
+            }
+        }
+    }
+}
+
+/// Number of structures of a version found on a pool.
+#[derive(Debug, Clone, Serialize)]
+pub struct FoundVersion {
+    pub structure: Structure,
+    pub pool: String,
+    pub version: u32,
+    pub count: u64,
+}
+
+/// Compatibility of the structures on disk with this mayastor and another.
+#[derive(Debug, Clone, Serialize)]
+pub struct Compatibility {
+    pub supported: Vec<FormatSupport>,
+    pub migrations: Vec<Migration>,
+    pub found: Vec<FoundVersion>,
+    /// the other mayastor can read all structures on disk
+    pub compatible: bool,
+    /// this mayastor can read all structures the other one writes
+    pub rollback_safe: bool,
+    /// why the other mayastor is not compatible or a rollback not safe
+    pub issues: Vec<String>,
+}
+
+/// returns the versions of the properties of the replicas of the pools
+async fn found_versions() -> Vec<FoundVersion> {
+    let mut found = BTreeMap::<(String, u32), u64>::new();
+    for pool in Lvs::iter() {
+        let lvols = pool.lvols().into_iter().flatten();
+        for lvol in lvols.filter(|l| !l.is_snapshot()) {
+            let version = lvol.format_version().await;
+            *found.entry((pool.name().to_string(), version)).or_default() += 1;
+        }
+    }
+    found
+        .into_iter()
+        .map(|((pool, version), count)| FoundVersion {
+            structure: Structure::ReplicaProperties,
+            pool,
+            version,
+            count,
+        })
+        .collect()
+}
+
+/// Check the structures found on disk against the versions another mayastor
+/// supports.
+pub fn check(
+    found: Vec<FoundVersion>,
+    other: &[FormatSupport],
+) -> Compatibility {
+    let mut issues = Vec::new();
+    let mut compatible = true;
+    let mut rollback_safe = true;
+
+    for f in &found {
+        if !f.structure.reads(f.version) {
+            issues.push(format!(
+                "pool {} has {} {:?} of version {}, which this mayastor can not read",
+                f.pool, f.count, f.structure, f.version
+            ));
+        }
+        match other.iter().find(|o| o.structure == f.structure) {
+            Some(o) if !o.reads(f.version) => {
+                compatible = false;
+                issues.push(format!(
+                    "pool {} has {} {:?} of version {}, outside of versions {} to {}",
+                    f.pool, f.count, f.structure, f.version, o.oldest, o.current
+                ));
+            }
+            _ => {}
+        }
+    }
+    for o in other {
+        if !o.structure.reads(o.current) {
+            rollback_safe = false;
+            issues.push(format!(
+                "{:?} of version {} can not be read back by this mayastor",
+                o.structure, o.current
+            ));
+        }
+    }
+
+    Compatibility {
+        supported: SUPPORTED.to_vec(),
+        migrations: MIGRATIONS.to_vec(),
+        found,
+        compatible,
+        rollback_safe,
+        issues,
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CheckArgs {
+    /// versions supported by the mayastor to upgrade or roll back to
+    #[serde(default)]
+    other: Vec<FormatSupport>,
+}
+
+async fn check_compatibility(
+    args: Option<CheckArgs>,
+) -> Result<Compatibility, JsonRpcError> {
+    let args = args.unwrap_or_default();
+    Ok(check(found_versions().await, &args.other))
+}
+
+/// Register the json-rpc method checking the compatibility of the structures
+/// on disk.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, JsonRpcError>(
+        "check_compatibility",
+        |args: Option<CheckArgs>| check_compatibility(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn migrations() {
+        let steps = Structure::ReplicaProperties.migrations(0);
+        assert_eq!(steps.len(), 1);
+        assert_eq!((steps[0].from, steps[0].to), (0, 1));
+        assert!(Structure::ReplicaProperties.migrations(1).is_empty());
+        assert!(Structure::Journal.reads(0));
+        assert!(!Structure::Checksums.reads(0));
+    }
+
+    #[test]
+    fn compatibility() {
+        let found = vec![FoundVersion {
+            structure: Structure::ReplicaProperties,
+            pool: "p0".into(),
+            version: 0,
+            count: 2,
+        }];
+        let newer = FormatSupport {
+            structure: Structure::ReplicaProperties,
+            current: 2,
+            oldest: 1,
+        };
+
+        let c = check(found.clone(), &[]);
+        assert!(c.compatible && c.rollback_safe && c.issues.is_empty());
+
+        let c = check(found, &[newer]);
+        assert!(!c.compatible);
+        assert!(!c.rollback_safe);
+        assert_eq!(c.issues.len(), 2);
+    }
+}
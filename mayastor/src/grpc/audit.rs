@@ -0,0 +1,558 @@
+//!
+//! Audit log of the control plane.
+//!
+//! Every gRPC call that changes the state of mayastor is recorded with the
+//! peer that made it, the object it applies to, its arguments and its result.
+//! Records are appended as json lines to the file given with `--audit-log`,
+//! which is rotated once it exceeds its maximum size, keeping a fixed number
+//! of rotated files (`<path>.1` being the most recent). The log is queried
+//! with the `audit_log` json-rpc method.
+//!
+//! Calls that only read state (`list_*`, `get_*` and `stat_*`) are not
+//! recorded. Secrets in the arguments, such as the keys of the nexuses, the
+//! tokens of the share URIs and the credentials passed to json-rpc methods,
+//! are replaced by `<redacted>` before the arguments are kept.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::{Future, FutureExt};
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use tonic::Status;
+
+use crate::{
+    grpc::GrpcClientContext,
+    jsonrpc::{jsonrpc_register, Code, RpcErrorCode},
+};
+
+static AUDIT_LOG: OnceCell<AuditLog> = OnceCell::new();
+
+/// number of records returned by a query unless asked otherwise
+const DEFAULT_QUERY_ENTRIES: usize = 1000;
+
+/// names of the fields of the arguments that hold secrets
+const SECRET_FIELDS: [&str; 5] =
+    ["key", "token", "access_key", "secret_key", "password"];
+
+/// what secrets are replaced by
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum AuditError {
+    #[snafu(display("The audit log is not enabled"))]
+    NotEnabled {},
+    #[snafu(display("Invalid timestamp {}: {}", timestamp, source))]
+    InvalidTimestamp {
+        timestamp: String,
+        source: chrono::ParseError,
+    },
+    #[snafu(display("Failed to read audit log {}: {}", path, source))]
+    ReadLog { path: String, source: io::Error },
+}
+
+impl RpcErrorCode for AuditError {
+    fn rpc_error_code(&self) -> Code {
+        match self {
+            Self::NotEnabled {} => Code::NotFound,
+            Self::InvalidTimestamp {
+                ..
+            } => Code::InvalidParams,
+            Self::ReadLog {
+                ..
+            } => Code::InternalError,
+        }
+    }
+}
+
+/// A mutating gRPC call.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// completion time of the call, RFC 3339 in UTC
+    pub timestamp: String,
+    /// address of the client
+    pub peer: String,
+    /// name of the gRPC method
+    pub method: String,
+    /// uuid or name of the object the call applies to, if any
+    pub object: Option<String>,
+    /// arguments of the call
+    pub args: String,
+    /// "ok" or the status the call failed with
+    pub result: String,
+}
+
+/// Selection of the records returned by a query.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    /// only records of this method
+    #[serde(default)]
+    pub method: Option<String>,
+    /// only records of this object
+    #[serde(default)]
+    pub object: Option<String>,
+    /// only records from this time on, RFC 3339
+    #[serde(default)]
+    pub since: Option<String>,
+    /// maximum number of records, the most recent ones are returned
+    #[serde(default)]
+    pub max_entries: Option<usize>,
+}
+
+/// Append only log of the mutating gRPC calls.
+pub struct AuditLog {
+    path: PathBuf,
+    /// size in bytes above which the log is rotated
+    max_size: u64,
+    /// number of rotated files kept
+    max_files: u32,
+    file: Mutex<Option<File>>,
+}
+
+/// returns true if calls to the method change state
+fn is_mutation(method: &str) -> bool {
+    !["list", "get_", "stat_"]
+        .iter()
+        .any(|prefix| method.starts_with(prefix))
+}
+
+/// Get the object a call applies to from the debug output of its arguments,
+/// which is the first uuid or, failing that, the first name.
+fn object(args: &str) -> Option<String> {
+    ["uuid: \"", "name: \""].iter().find_map(|field| {
+        let start = args.find(field)? + field.len();
+        let len = args[start ..].find('"')?;
+        Some(args[start .. start + len].to_string())
+    })
+}
+
+/// Replace the value after each occurrence of the pattern that does not
+/// continue a longer name by `REDACTED`, `end` returning the length of the
+/// value.
+fn redact_values(args: &str, pattern: &str, end: fn(&str) -> usize) -> String {
+    let mut redacted = String::with_capacity(args.len());
+    let mut rest = args;
+    while let Some(i) = rest.find(pattern) {
+        let (head, tail) = rest.split_at(i + pattern.len());
+        redacted.push_str(head);
+        rest = tail;
+        let in_name = head[.. i]
+            .chars()
+            .next_back()
+            .map_or(false, |c| c.is_alphanumeric() || c == '_');
+        if !in_name {
+            redacted.push_str(REDACTED);
+            rest = &tail[end(tail) ..];
+        }
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+/// length of a debug formatted string, up to its unescaped closing quote
+fn debug_str_len(s: &str) -> usize {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' if !escaped => return i,
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    s.len()
+}
+
+/// length of a json string within a debug formatted string
+fn json_str_len(s: &str) -> usize {
+    s.find("\\\"").unwrap_or(s.len())
+}
+
+/// length of the value of a URI query parameter
+fn uri_param_len(s: &str) -> usize {
+    s.find(|c: char| c == '&' || c == '"' || c == '\\' || c.is_whitespace())
+        .unwrap_or(s.len())
+}
+
+/// Replace the secrets in the debug output of the arguments of a call: the
+/// fields named after secrets, the same in the json parameters of json-rpc
+/// calls, and the tokens of URIs.
+pub(crate) fn redact(args: &str) -> String {
+    let mut args = args.to_string();
+    for field in SECRET_FIELDS.iter() {
+        args = redact_values(&args, &format!("{}: \"", field), debug_str_len);
+        for sep in [":", ": "].iter() {
+            args = redact_values(
+                &args,
+                &format!("\\\"{}\\\"{}\\\"", field, sep),
+                json_str_len,
+            );
+        }
+    }
+    redact_values(&args, "token=", uri_param_len)
+}
+
+impl AuditRecord {
+    fn new<T>(ctx: &GrpcClientContext, result: &Result<T, Status>) -> Self {
+        Self {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            peer: ctx.peer.clone(),
+            method: ctx.id.clone(),
+            object: object(&ctx.args),
+            args: ctx.args.clone(),
+            result: match result {
+                Ok(_) => "ok".to_string(),
+                Err(status) => {
+                    format!("{:?}: {}", status.code(), status.message())
+                }
+            },
+        }
+    }
+}
+
+impl AuditLog {
+    /// Create an audit log at the given path, rotated when it grows above
+    /// max_size bytes.
+    pub fn new(path: &str, max_size: u64, max_files: u32) -> Self {
+        Self {
+            path: PathBuf::from(path),
+            max_size,
+            max_files,
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Enable the audit log of the gRPC calls.
+    /// If the supplied path is 'None', calls are not recorded.
+    pub fn init(path: Option<String>, max_size_mb: u64, max_files: u32) {
+        if let Some(path) = path {
+            info!("Recording gRPC calls to audit log {}", path);
+            let log = Self::new(&path, max_size_mb * 1024 * 1024, max_files);
+            if AUDIT_LOG.set(log).is_err() {
+                warn!("Audit log already initialised");
+            }
+        }
+    }
+
+    /// returns the audit log, if enabled
+    pub fn get() -> Option<&'static AuditLog> {
+        AUDIT_LOG.get()
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    /// shift the rotated files by one and make the log the most recent one
+    fn rotate(&self) -> io::Result<()> {
+        for n in (1 .. self.max_files).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(from, self.rotated(n + 1))?;
+            }
+        }
+        if self.max_files > 0 {
+            fs::rename(&self.path, self.rotated(1))
+        } else {
+            fs::remove_file(&self.path)
+        }
+    }
+
+    /// Append a record to the log, rotating it first if it would grow above
+    /// its maximum size.
+    pub fn append(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock();
+        let size = match self.path.metadata() {
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        };
+        if size > 0 && size + line.len() as u64 > self.max_size {
+            *file = None;
+            self.rotate()?;
+        }
+
+        if file.is_none() {
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .mode(0o640)
+                    .open(&self.path)?,
+            );
+        }
+
+        let f = file.as_mut().unwrap();
+        f.write_all(&line)?;
+        f.sync_data()
+    }
+
+    /// Get the records matching the query, oldest first.
+    pub fn query(
+        &self,
+        query: &AuditQuery,
+    ) -> Result<Vec<AuditRecord>, AuditError> {
+        let since = query
+            .since
+            .as_ref()
+            .map(|s| {
+                DateTime::parse_from_rfc3339(s).context(InvalidTimestamp {
+                    timestamp: s.clone(),
+                })
+            })
+            .transpose()?;
+
+        // hold the lock so that the log is not rotated while it is read
+        let _file = self.file.lock();
+
+        let mut records = Vec::new();
+        let paths = (1 ..= self.max_files)
+            .rev()
+            .map(|n| self.rotated(n))
+            .chain(std::iter::once(self.path.clone()));
+        for path in paths.filter(|p| p.exists()) {
+            let file = File::open(&path).context(ReadLog {
+                path: path.display().to_string(),
+            })?;
+
+            for line in BufReader::new(file).lines() {
+                let line = line.context(ReadLog {
+                    path: path.display().to_string(),
+                })?;
+                let record = match serde_json::from_str::<AuditRecord>(&line) {
+                    Ok(record) => record,
+                    Err(e) => {
+                        warn!("skipping invalid audit record: {}", e);
+                        continue;
+                    }
+                };
+
+                if query.method.as_ref().map_or(false, |m| m != &record.method)
+                    || query
+                        .object
+                        .as_ref()
+                        .map_or(false, |o| Some(o) != record.object.as_ref())
+                {
+                    continue;
+                }
+                if let Some(since) = since {
+                    match DateTime::parse_from_rfc3339(&record.timestamp) {
+                        Ok(t) if t >= since => {}
+                        _ => continue,
+                    }
+                }
+                records.push(record);
+            }
+        }
+
+        let max = query.max_entries.unwrap_or(DEFAULT_QUERY_ENTRIES);
+        if records.len() > max {
+            records.drain(.. records.len() - max);
+        }
+        Ok(records)
+    }
+}
+
+/// Record a completed gRPC call in the audit log, if it is enabled and the
+/// call changes state.
+pub(crate) fn record<T>(ctx: &GrpcClientContext, result: &Result<T, Status>) {
+    if let Some(log) = AuditLog::get() {
+        if is_mutation(&ctx.id) {
+            if let Err(e) = log.append(&AuditRecord::new(ctx, result)) {
+                error!("{}: failed to write audit record: {}", ctx.id, e);
+            }
+        }
+    }
+}
+
+/// Run the future of a gRPC method which is not serialized and record it in
+/// the audit log.
+pub(crate) async fn audited<T, F>(
+    ctx: GrpcClientContext,
+    f: F,
+) -> Result<T, Status>
+where
+    F: Future<Output = Result<T, Status>>,
+{
+    let result = f.await;
+    record(&ctx, &result);
+    result
+}
+
+async fn query(query: AuditQuery) -> Result<Vec<AuditRecord>, AuditError> {
+    AuditLog::get()
+        .ok_or(AuditError::NotEnabled {})?
+        .query(&query)
+}
+
+/// Register the json-rpc method to query the audit log.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, AuditError>("audit_log", |args: AuditQuery| {
+        query(args).boxed_local()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpc::mayastor::{
+        CreateNexusRequest,
+        CreatePoolRequest,
+        JsonRpcRequest,
+        PublishNexusRequest,
+    };
+    use tonic::Request;
+
+    fn temp_log(name: &str, max_size: u64) -> AuditLog {
+        let path = std::env::temp_dir().join(format!(
+            "mayastor-audit-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let log = AuditLog::new(path.to_str().unwrap(), max_size, 2);
+        for n in 1 ..= 3 {
+            let _ = fs::remove_file(log.rotated(n));
+        }
+        let _ = fs::remove_file(&log.path);
+        log
+    }
+
+    fn create_pool(name: &str, result: Result<(), Status>) -> AuditRecord {
+        let request = Request::new(CreatePoolRequest {
+            name: name.to_string(),
+            disks: vec!["malloc:///disk0?size_mb=64".to_string()],
+        });
+        AuditRecord::new(
+            &GrpcClientContext::new(&request, "create_pool"),
+            &result,
+        )
+    }
+
+    #[test]
+    fn mutations() {
+        assert!(is_mutation("create_pool"));
+        assert!(is_mutation("unpublish_nexus"));
+        assert!(!is_mutation("list_nexus_v2"));
+        assert!(!is_mutation("get_rebuild_state"));
+        assert!(!is_mutation("stat_replicas"));
+    }
+
+    #[test]
+    fn redacted() {
+        fn args<T: std::fmt::Debug>(request: T) -> String {
+            GrpcClientContext::new(&Request::new(request), "test").args
+        }
+
+        let publish = args(PublishNexusRequest {
+            uuid: "n0".to_string(),
+            key: "0123456789abcdef".to_string(),
+            share: 1,
+        });
+        assert!(!publish.contains("0123456789abcdef"));
+        assert!(publish.contains("key: \"<redacted>\""));
+        assert_eq!(object(&publish), Some("n0".to_string()));
+
+        let create = args(CreateNexusRequest {
+            uuid: "n1".to_string(),
+            size: 1 << 20,
+            children: vec![
+                "nvmf://10.0.0.1:8420/nqn:r0?token=s3cret&uuid=u0".to_string()
+            ],
+        });
+        assert!(!create.contains("s3cret"));
+        assert!(create.contains("?token=<redacted>&uuid=u0"));
+
+        let json = args(JsonRpcRequest {
+            method: "replica_backup".to_string(),
+            params: serde_json::json!({
+                "replica": "r0",
+                "store": {"access_key": "AKID", "secret_key": "wJalr"},
+            })
+            .to_string(),
+        });
+        assert!(!json.contains("AKID") && !json.contains("wJalr"));
+        assert!(json.contains("r0"));
+
+        // fields that merely end in the name of a secret are kept
+        assert_eq!(redact("resv_key: \"7\""), "resv_key: \"7\"");
+    }
+
+    #[test]
+    fn append_and_query() {
+        let log = temp_log("query", 1024 * 1024);
+        let record = create_pool("p0", Ok(()));
+        assert_eq!(record.object, Some("p0".to_string()));
+        assert_eq!(record.result, "ok");
+
+        log.append(&record).unwrap();
+        log.append(&create_pool("p1", Err(Status::not_found("p1"))))
+            .unwrap();
+
+        let records = log.query(&AuditQuery::default()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], record);
+
+        let records = log
+            .query(&AuditQuery {
+                object: Some("p1".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].result.starts_with("NotFound"));
+
+        let records = log
+            .query(&AuditQuery {
+                since: Some("2999-01-01T00:00:00Z".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(records.is_empty());
+        assert!(log
+            .query(&AuditQuery {
+                since: Some("yesterday".to_string()),
+                ..Default::default()
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn rotation() {
+        // room for a couple of records per file
+        let log = temp_log("rotate", 600);
+        for i in 0 .. 20 {
+            log.append(&create_pool(&format!("p{}", i), Ok(())))
+                .unwrap();
+        }
+
+        assert!(log.rotated(1).exists());
+        assert!(log.rotated(2).exists());
+        assert!(!log.rotated(3).exists());
+
+        // the oldest records are gone, the most recent ones are in order
+        let records = log.query(&AuditQuery::default()).unwrap();
+        assert!(records.len() < 20);
+        assert_eq!(records.last().unwrap().object, Some("p19".to_string()));
+
+        let records = log
+            .query(&AuditQuery {
+                max_entries: Some(2),
+                ..Default::default()
+            })
+            .unwrap();
+        let objects: Vec<_> =
+            records.iter().map(|r| r.object.clone().unwrap()).collect();
+        assert_eq!(objects, vec!["p18", "p19"]);
+    }
+}
@@ -0,0 +1,266 @@
+//!
+//! Bdev operations of the gRPC bdev services.
+//!
+//! The services do not use the bdev layer directly but go through a
+//! `BdevBackend`: `SpdkBdevs` runs the operations on the reactor for real,
+//! while tests of the services plug in an in-memory `MockBdevs` instead,
+//! which needs neither SPDK nor hugepages.
+
+use std::{convert::TryFrom, fmt::Debug, pin::Pin};
+
+use tonic::Status;
+use url::Url;
+
+use crate::{
+    core::{CoreError, Share, UntypedBdev},
+    grpc::rpc_submit,
+    nexus_uri::{bdev_create, bdev_destroy, NexusBdevError},
+};
+
+/// Description of a bdev as returned by the services.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BdevInfo {
+    pub name: String,
+    pub uuid: String,
+    pub num_blocks: u64,
+    pub blk_size: u32,
+    pub claimed: bool,
+    pub claimed_by: String,
+    pub aliases: String,
+    pub product_name: String,
+    pub share_uri: String,
+    pub uri: String,
+}
+
+impl From<UntypedBdev> for BdevInfo {
+    fn from(b: UntypedBdev) -> Self {
+        Self {
+            name: b.name().to_string(),
+            uuid: b.uuid_as_string(),
+            num_blocks: b.num_blocks(),
+            blk_size: b.block_len(),
+            claimed: b.is_claimed(),
+            claimed_by: b.claimed_by().unwrap_or_else(|| "Orphaned".into()),
+            aliases: b.aliases().join(","),
+            product_name: b.product_name().to_string(),
+            share_uri: b.share_uri().unwrap_or_else(|| "".into()),
+            uri: Url::try_from(b).map_or("".into(), |u| u.to_string()),
+        }
+    }
+}
+
+/// Creation, lookup and sharing of bdevs, as used by the gRPC services.
+#[tonic::async_trait]
+pub trait BdevBackend: Debug + Send + Sync + 'static {
+    /// list all bdevs
+    async fn list(&self) -> Result<Vec<BdevInfo>, Status>;
+    /// look a bdev up by its name
+    async fn lookup(&self, name: String) -> Result<Option<BdevInfo>, Status>;
+    /// create a bdev from its URI, returning the name of the bdev
+    async fn create(&self, uri: String) -> Result<String, Status>;
+    /// destroy the bdev of the given URI
+    async fn destroy(&self, uri: String) -> Result<(), Status>;
+    /// share a bdev over nvmf
+    async fn share_nvmf(&self, name: String) -> Result<BdevInfo, Status>;
+    /// unshare a bdev, which is not an error if it does not exist
+    async fn unshare(&self, name: String) -> Result<(), Status>;
+}
+
+/// The bdevs of SPDK, all operations are run on the reactor.
+#[derive(Debug, Default)]
+pub struct SpdkBdevs {}
+
+#[tonic::async_trait]
+impl BdevBackend for SpdkBdevs {
+    async fn list(&self) -> Result<Vec<BdevInfo>, Status> {
+        let rx = rpc_submit::<_, _, NexusBdevError>(async {
+            let mut list = Vec::new();
+            if let Some(bdev) = UntypedBdev::bdev_first() {
+                bdev.into_iter().for_each(|bdev| list.push(bdev.into()))
+            }
+            Ok(list)
+        })?;
+
+        rx.await
+            .map_err(|_| Status::cancelled("cancelled"))?
+            .map_err(Status::from)
+    }
+
+    async fn lookup(&self, name: String) -> Result<Option<BdevInfo>, Status> {
+        let rx = rpc_submit::<_, _, NexusBdevError>(async move {
+            Ok(UntypedBdev::lookup_by_name(&name).map(BdevInfo::from))
+        })?;
+
+        rx.await
+            .map_err(|_| Status::cancelled("cancelled"))?
+            .map_err(Status::from)
+    }
+
+    async fn create(&self, uri: String) -> Result<String, Status> {
+        let rx = rpc_submit(async move { bdev_create(&uri).await })?;
+
+        rx.await
+            .map_err(|_| Status::cancelled("cancelled"))?
+            .map_err(Status::from)
+    }
+
+    async fn destroy(&self, uri: String) -> Result<(), Status> {
+        let rx = rpc_submit(async move { bdev_destroy(&uri).await })?;
+
+        rx.await
+            .map_err(|_| Status::cancelled("cancelled"))?
+            .map_err(Status::from)
+    }
+
+    async fn share_nvmf(&self, name: String) -> Result<BdevInfo, Status> {
+        let rx = rpc_submit::<_, _, CoreError>(async move {
+            let mut bdev = UntypedBdev::lookup_by_name(&name).ok_or(
+                CoreError::BdevNotFound {
+                    name: name.clone(),
+                },
+            )?;
+            let share = Pin::new(&mut bdev).share_nvmf(None).await?;
+            let mut info =
+                BdevInfo::from(UntypedBdev::lookup_by_name(&name).unwrap());
+            if info.share_uri.is_empty() {
+                info.share_uri = share;
+            }
+            Ok(info)
+        })?;
+
+        rx.await
+            .map_err(|_| Status::cancelled("cancelled"))?
+            .map_err(|e| Status::internal(e.to_string()))
+    }
+
+    async fn unshare(&self, name: String) -> Result<(), Status> {
+        let rx = rpc_submit::<_, _, CoreError>(async move {
+            if let Some(mut bdev) = UntypedBdev::lookup_by_name(&name) {
+                let _ = Pin::new(&mut bdev).unshare().await?;
+            }
+            Ok(())
+        })?;
+
+        rx.await
+            .map_err(|_| Status::cancelled("cancelled"))?
+            .map_err(Status::from)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use parking_lot::Mutex;
+    use snafu::ResultExt;
+
+    use super::*;
+    use crate::nexus_uri::UrlParseError;
+
+    /// In-memory bdevs, created from `malloc:///<name>?size_mb=<size>` URIs
+    /// and failing the way the bdevs of SPDK do.
+    #[derive(Debug, Default)]
+    pub(crate) struct MockBdevs {
+        bdevs: Mutex<Vec<BdevInfo>>,
+    }
+
+    impl MockBdevs {
+        fn parse(uri: &str) -> Result<(String, u64), NexusBdevError> {
+            let url = Url::parse(uri).context(UrlParseError {
+                uri: uri.to_string(),
+            })?;
+            if url.scheme() != "malloc" {
+                return Err(NexusBdevError::UriSchemeUnsupported {
+                    scheme: url.scheme().to_string(),
+                });
+            }
+            let name = url.path().trim_start_matches('/').to_string();
+            let size_mb = url
+                .query_pairs()
+                .find(|(k, _)| k == "size_mb")
+                .and_then(|(_, v)| v.parse::<u64>().ok())
+                .ok_or_else(|| NexusBdevError::UriInvalid {
+                    uri: uri.to_string(),
+                    message: "size_mb is required".to_string(),
+                })?;
+            Ok((name, size_mb))
+        }
+    }
+
+    #[tonic::async_trait]
+    impl BdevBackend for MockBdevs {
+        async fn list(&self) -> Result<Vec<BdevInfo>, Status> {
+            Ok(self.bdevs.lock().clone())
+        }
+
+        async fn lookup(
+            &self,
+            name: String,
+        ) -> Result<Option<BdevInfo>, Status> {
+            Ok(self.bdevs.lock().iter().find(|b| b.name == name).cloned())
+        }
+
+        async fn create(&self, uri: String) -> Result<String, Status> {
+            let (name, size_mb) = Self::parse(&uri)?;
+            let mut bdevs = self.bdevs.lock();
+            if bdevs.iter().any(|b| b.name == name) {
+                return Err(NexusBdevError::BdevExists {
+                    name,
+                }
+                .into());
+            }
+            bdevs.push(BdevInfo {
+                name: name.clone(),
+                uuid: uuid::Uuid::new_v4().to_string(),
+                num_blocks: size_mb * 2048,
+                blk_size: 512,
+                claimed_by: "Orphaned".into(),
+                product_name: "Malloc disk".into(),
+                uri,
+                ..Default::default()
+            });
+            Ok(name)
+        }
+
+        async fn destroy(&self, uri: String) -> Result<(), Status> {
+            let (name, _) = Self::parse(&uri)?;
+            let mut bdevs = self.bdevs.lock();
+            let before = bdevs.len();
+            bdevs.retain(|b| b.name != name);
+            if bdevs.len() == before {
+                return Err(NexusBdevError::BdevNotFound {
+                    name,
+                }
+                .into());
+            }
+            Ok(())
+        }
+
+        async fn share_nvmf(&self, name: String) -> Result<BdevInfo, Status> {
+            let mut bdevs = self.bdevs.lock();
+            let bdev =
+                bdevs.iter_mut().find(|b| b.name == name).ok_or_else(|| {
+                    Status::internal(
+                        CoreError::BdevNotFound {
+                            name: name.clone(),
+                        }
+                        .to_string(),
+                    )
+                })?;
+            if bdev.share_uri.is_empty() {
+                bdev.share_uri = format!(
+                    "nvmf://127.0.0.1:8420/nqn.2019-05.io.openebs:{}",
+                    name
+                );
+            }
+            Ok(bdev.clone())
+        }
+
+        async fn unshare(&self, name: String) -> Result<(), Status> {
+            if let Some(bdev) =
+                self.bdevs.lock().iter_mut().find(|b| b.name == name)
+            {
+                bdev.share_uri.clear();
+            }
+            Ok(())
+        }
+    }
+}
@@ -1,9 +1,6 @@
 use tonic::{Request, Response, Status};
 use tracing::instrument;
 
-use std::{convert::TryFrom, pin::Pin};
-use url::Url;
-
 use rpc::mayastor::{
     bdev_rpc_server::BdevRpc,
     Bdev as RpcBdev,
@@ -16,34 +13,55 @@ use rpc::mayastor::{
 };
 
 use crate::{
-    core::{CoreError, Share, UntypedBdev},
-    grpc::{rpc_submit, GrpcResult},
-    nexus_uri::{bdev_create, bdev_destroy, NexusBdevError},
+    core::UntypedBdev,
+    grpc::{
+        audit,
+        bdev_backend::{BdevBackend, BdevInfo, SpdkBdevs},
+        GrpcClientContext,
+        GrpcResult,
+    },
 };
 
-impl From<UntypedBdev> for RpcBdev {
-    fn from(b: UntypedBdev) -> Self {
+impl From<BdevInfo> for RpcBdev {
+    fn from(b: BdevInfo) -> Self {
         Self {
-            name: b.name().to_string(),
-            uuid: b.uuid_as_string(),
-            num_blocks: b.num_blocks(),
-            blk_size: b.block_len(),
-            claimed: b.is_claimed(),
-            claimed_by: b.claimed_by().unwrap_or_else(|| "Orphaned".into()),
-            aliases: b.aliases().join(","),
-            product_name: b.product_name().to_string(),
-            share_uri: b.share_uri().unwrap_or_else(|| "".into()),
-            uri: Url::try_from(b).map_or("".into(), |u| u.to_string()),
+            name: b.name,
+            uuid: b.uuid,
+            num_blocks: b.num_blocks,
+            blk_size: b.blk_size,
+            claimed: b.claimed,
+            claimed_by: b.claimed_by,
+            aliases: b.aliases,
+            product_name: b.product_name,
+            share_uri: b.share_uri,
+            uri: b.uri,
         }
     }
 }
 
+impl From<UntypedBdev> for RpcBdev {
+    fn from(b: UntypedBdev) -> Self {
+        BdevInfo::from(b).into()
+    }
+}
+
 #[derive(Debug)]
-pub struct BdevSvc {}
+pub struct BdevSvc<B = SpdkBdevs> {
+    backend: B,
+}
 
 impl BdevSvc {
     pub fn new() -> Self {
-        Self {}
+        Self::with_backend(SpdkBdevs::default())
+    }
+}
+
+impl<B: BdevBackend> BdevSvc<B> {
+    /// bdev service running its operations on the given backend
+    pub fn with_backend(backend: B) -> Self {
+        Self {
+            backend,
+        }
     }
 }
 
@@ -54,24 +72,13 @@ impl Default for BdevSvc {
 }
 
 #[tonic::async_trait]
-impl BdevRpc for BdevSvc {
+impl<B: BdevBackend> BdevRpc for BdevSvc<B> {
     #[instrument(level = "debug", err)]
     async fn list(&self, _request: Request<Null>) -> GrpcResult<Bdevs> {
-        let rx = rpc_submit::<_, _, NexusBdevError>(async {
-            let mut list: Vec<RpcBdev> = Vec::new();
-            if let Some(bdev) = UntypedBdev::bdev_first() {
-                bdev.into_iter().for_each(|bdev| list.push(bdev.into()))
-            }
-
-            Ok(Bdevs {
-                bdevs: list,
-            })
-        })?;
-
-        rx.await
-            .map_err(|_| Status::cancelled("cancelled"))?
-            .map_err(Status::from)
-            .map(Response::new)
+        let bdevs = self.backend.list().await?;
+        Ok(Response::new(Bdevs {
+            bdevs: bdevs.into_iter().map(RpcBdev::from).collect(),
+        }))
     }
 
     #[instrument(level = "debug", err)]
@@ -79,30 +86,26 @@ impl BdevRpc for BdevSvc {
         &self,
         request: Request<BdevUri>,
     ) -> Result<Response<CreateReply>, Status> {
-        let uri = request.into_inner().uri;
-
-        let rx = rpc_submit(async move { bdev_create(&uri).await })?;
-
-        rx.await
-            .map_err(|_| Status::cancelled("cancelled"))?
-            .map_err(Status::from)
-            .map(|name| {
-                Ok(Response::new(CreateReply {
-                    name,
-                }))
-            })?
+        let ctx = GrpcClientContext::new(&request, "create_bdev");
+        audit::audited(ctx, async move {
+            let uri = request.into_inner().uri;
+            let name = self.backend.create(uri).await?;
+            Ok(Response::new(CreateReply {
+                name,
+            }))
+        })
+        .await
     }
 
     #[instrument(level = "debug", err)]
     async fn destroy(&self, request: Request<BdevUri>) -> GrpcResult<Null> {
-        let uri = request.into_inner().uri;
-
-        let rx = rpc_submit(async move { bdev_destroy(&uri).await })?;
-
-        rx.await
-            .map_err(|_| Status::cancelled("cancelled"))?
-            .map_err(Status::from)
-            .map(|_| Ok(Response::new(Null {})))?
+        let ctx = GrpcClientContext::new(&request, "destroy_bdev");
+        audit::audited(ctx, async move {
+            let uri = request.into_inner().uri;
+            self.backend.destroy(uri).await?;
+            Ok(Response::new(Null {}))
+        })
+        .await
     }
 
     #[instrument(level = "debug", err)]
@@ -110,52 +113,140 @@ impl BdevRpc for BdevSvc {
         &self,
         request: Request<BdevShareRequest>,
     ) -> Result<Response<BdevShareReply>, Status> {
-        let r = request.into_inner();
-        let name = r.name;
-        let proto = r.proto;
+        let ctx = GrpcClientContext::new(&request, "share_bdev");
+        audit::audited(ctx, async move {
+            let r = request.into_inner();
+            let name = r.name;
+            let proto = r.proto;
+
+            if self.backend.lookup(name.clone()).await?.is_none() {
+                return Err(Status::not_found(name));
+            }
 
-        if UntypedBdev::lookup_by_name(&name).is_none() {
-            return Err(Status::not_found(name));
-        }
+            if proto != "nvmf" {
+                return Err(Status::invalid_argument(proto));
+            }
 
-        if proto != "nvmf" {
-            return Err(Status::invalid_argument(proto));
-        }
-        let bdev_name = name.clone();
-        let rx = match proto.as_str() {
-            "nvmf" => rpc_submit::<_, String, CoreError>(async move {
-                let mut bdev = UntypedBdev::lookup_by_name(&bdev_name).unwrap();
-                let share = Pin::new(&mut bdev).share_nvmf(None).await?;
-                let bdev = UntypedBdev::lookup_by_name(&name).unwrap();
-                Ok(bdev.share_uri().unwrap_or(share))
-            }),
-
-            _ => unreachable!(),
-        }?;
-
-        rx.await
-            .map_err(|_| Status::cancelled("cancelled"))?
-            .map_err(|e| Status::internal(e.to_string()))
-            .map(|uri| {
-                Ok(Response::new(BdevShareReply {
-                    uri,
-                }))
-            })?
+            let bdev = self.backend.share_nvmf(name).await?;
+            Ok(Response::new(BdevShareReply {
+                uri: bdev.share_uri,
+            }))
+        })
+        .await
     }
 
     #[instrument(level = "debug", err)]
     async fn unshare(&self, request: Request<CreateReply>) -> GrpcResult<Null> {
-        let rx = rpc_submit::<_, _, CoreError>(async {
+        let ctx = GrpcClientContext::new(&request, "unshare_bdev");
+        audit::audited(ctx, async move {
             let name = request.into_inner().name;
-            if let Some(mut bdev) = UntypedBdev::lookup_by_name(&name) {
-                let _ = Pin::new(&mut bdev).unshare().await?;
-            }
-            Ok(Null {})
-        })?;
+            self.backend.unshare(name).await?;
+            Ok(Response::new(Null {}))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc::bdev_backend::mock::MockBdevs;
+    use tonic::Code;
+
+    fn svc() -> BdevSvc<MockBdevs> {
+        BdevSvc::with_backend(MockBdevs::default())
+    }
+
+    #[tokio::test]
+    async fn create_list_destroy() {
+        let svc = svc();
+        let uri = "malloc:///disk0?size_mb=64".to_string();
+
+        let reply = svc
+            .create(Request::new(BdevUri {
+                uri: uri.clone(),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(reply.into_inner().name, "disk0");
+
+        let err = svc
+            .create(Request::new(BdevUri {
+                uri: uri.clone(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::Internal);
+
+        let bdevs = svc.list(Request::new(Null {})).await.unwrap().into_inner();
+        assert_eq!(bdevs.bdevs.len(), 1);
+        assert_eq!(bdevs.bdevs[0].num_blocks, 64 * 2048);
+        assert_eq!(bdevs.bdevs[0].uri, uri);
+
+        svc.destroy(Request::new(BdevUri {
+            uri: uri.clone(),
+        }))
+        .await
+        .unwrap();
+        let bdevs = svc.list(Request::new(Null {})).await.unwrap().into_inner();
+        assert!(bdevs.bdevs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalid_uri() {
+        let err = svc()
+            .create(Request::new(BdevUri {
+                uri: "bogus:///disk0".into(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+    }
 
-        rx.await
-            .map_err(|_| Status::cancelled("cancelled"))?
-            .map_err(Status::from)
-            .map(Response::new)
+    #[tokio::test]
+    async fn share_unshare() {
+        let svc = svc();
+        let err = svc
+            .share(Request::new(BdevShareRequest {
+                name: "disk0".into(),
+                proto: "nvmf".into(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+
+        svc.create(Request::new(BdevUri {
+            uri: "malloc:///disk0?size_mb=8".into(),
+        }))
+        .await
+        .unwrap();
+
+        let err = svc
+            .share(Request::new(BdevShareRequest {
+                name: "disk0".into(),
+                proto: "iscsi".into(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+
+        let uri = svc
+            .share(Request::new(BdevShareRequest {
+                name: "disk0".into(),
+                proto: "nvmf".into(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .uri;
+        assert!(uri.starts_with("nvmf://"));
+
+        svc.unshare(Request::new(CreateReply {
+            name: "disk0".into(),
+        }))
+        .await
+        .unwrap();
+        let bdevs = svc.list(Request::new(Null {})).await.unwrap().into_inner();
+        assert!(bdevs.bdevs[0].share_uri.is_empty());
     }
 }
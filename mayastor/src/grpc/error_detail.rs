@@ -0,0 +1,478 @@
+//!
+//! Structured details of the errors of the gRPC API.
+//!
+//! The message of a failed call says what went wrong to a person, but a
+//! control plane deciding whether to retry, move the volume elsewhere or
+//! give up had to match on it. The errors of the pool, replica and nexus
+//! calls therefore carry an `ErrorDetail` in the details of their status,
+//! encoded as the `google.rpc.Status` message of the richer error model with
+//! the detail as its only `Any`:
+//!
+//! - the subsystem that failed, `pool`, `replica` or `nexus`
+//! - the object it failed on, a pool, replica, nexus or child
+//! - the errno of the failure, 0 if there is none
+//! - whether the same call may succeed when retried as is
+//! - an action suggested to the caller, empty if there is none
+//!
+//! Clients without support for the details keep getting the code and the
+//! message as before; `ErrorDetail::from_status` decodes them.
+
+use nix::errno::Errno;
+use prost::Message;
+use prost_types::Any;
+use tonic::Status;
+
+use crate::{
+    bdev::nexus::Error as NexusError,
+    lvs::Error as LvsError,
+    replica::Error as ReplicaError,
+};
+
+/// type URL of the detail within the status details
+pub const TYPE_URL: &str = "type.googleapis.com/mayastor.v1.ErrorDetail";
+
+/// Structured cause of a failed call.
+#[derive(Clone, PartialEq, Message)]
+pub struct ErrorDetail {
+    #[prost(string, tag = "1")]
+    pub subsystem: String,
+    #[prost(string, tag = "2")]
+    pub object: String,
+    #[prost(int32, tag = "3")]
+    pub errno: i32,
+    #[prost(bool, tag = "4")]
+    pub retriable: bool,
+    #[prost(string, tag = "5")]
+    pub action: String,
+}
+
+/// google.rpc.Status, the encoding of the details of a status
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<Any>,
+}
+
+/// returns true for the errnos of failures that can go away by themselves
+fn errno_retriable(errno: Errno) -> bool {
+    matches!(
+        errno,
+        Errno::EAGAIN
+            | Errno::EBUSY
+            | Errno::EINTR
+            | Errno::ENOMEM
+            | Errno::ETIMEDOUT
+            | Errno::ECONNREFUSED
+            | Errno::ECONNRESET
+    )
+}
+
+impl ErrorDetail {
+    fn new(subsystem: &str, object: &str) -> Self {
+        Self {
+            subsystem: subsystem.to_string(),
+            object: object.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn errno(mut self, errno: Errno) -> Self {
+        self.errno = errno as i32;
+        self.retriable = errno_retriable(errno);
+        self
+    }
+
+    fn retriable(mut self) -> Self {
+        self.retriable = true;
+        self
+    }
+
+    fn action(mut self, action: &str) -> Self {
+        self.action = action.to_string();
+        self
+    }
+
+    /// Return the status with this detail attached.
+    pub fn attach(&self, status: Status) -> Status {
+        let details = RpcStatus {
+            code: status.code() as i32,
+            message: status.message().to_string(),
+            details: vec![Any {
+                type_url: TYPE_URL.to_string(),
+                value: self.encode_to_vec(),
+            }],
+        };
+        Status::with_details(
+            status.code(),
+            status.message(),
+            details.encode_to_vec().into(),
+        )
+    }
+
+    /// Return the detail attached to a status, if any.
+    pub fn from_status(status: &Status) -> Option<Self> {
+        RpcStatus::decode(status.details())
+            .ok()?
+            .details
+            .into_iter()
+            .find(|any| any.type_url == TYPE_URL)
+            .and_then(|any| Self::decode(any.value.as_slice()).ok())
+    }
+}
+
+impl From<&LvsError> for ErrorDetail {
+    fn from(e: &LvsError) -> Self {
+        match e {
+            LvsError::Import {
+                source,
+                name,
+            } => Self::new("pool", name)
+                .errno(*source)
+                .action("check the devices of the pool"),
+            LvsError::PoolCreate {
+                source,
+                name,
+            } => Self::new("pool", name)
+                .errno(*source)
+                .action("check the devices of the pool"),
+            LvsError::Export {
+                source,
+                name,
+            }
+            | LvsError::Rename {
+                source,
+                name,
+            } => Self::new("pool", name).errno(*source),
+            LvsError::Destroy {
+                name, ..
+            }
+            | LvsError::InvalidBdev {
+                name, ..
+            } => Self::new("pool", name),
+            LvsError::ZonedDevice {
+                name, ..
+            } => Self::new("pool", name)
+                .action("create the pool on a conventional device"),
+            LvsError::Invalid {
+                source, ..
+            } if *source == Errno::EEXIST => Self::new("pool", "")
+                .errno(*source)
+                .action("use another name"),
+            LvsError::Invalid {
+                source, ..
+            } => Self::new("pool", "").errno(*source),
+            LvsError::RepExists {
+                source,
+                name,
+            } => Self::new("replica", name)
+                .errno(*source)
+                .action("use another name"),
+            LvsError::RepCreate {
+                source,
+                name,
+            } if *source == Errno::ENOSPC => Self::new("replica", name)
+                .errno(*source)
+                .action("free space on the pool or use another pool"),
+            LvsError::RepCreate {
+                source,
+                name,
+            }
+            | LvsError::RepDestroy {
+                source,
+                name,
+            }
+            | LvsError::NotALvol {
+                source,
+                name,
+            }
+            | LvsError::SyncProperty {
+                source,
+                name,
+            }
+            | LvsError::Property {
+                source,
+                name,
+            }
+            | LvsError::GetProperty {
+                source,
+                name,
+                ..
+            }
+            | LvsError::SetProperty {
+                source,
+                name,
+                ..
+            } => Self::new("replica", name).errno(*source),
+            LvsError::RepProtected {
+                name,
+            } => Self::new("replica", name)
+                .action("unprotect the replica before destroying it"),
+            LvsError::LvolShare {
+                name, ..
+            }
+            | LvsError::LvolUnShare {
+                name, ..
+            } => Self::new("replica", name).retriable(),
+            LvsError::ReplicaShareProtocol {
+                ..
+            } => Self::new("replica", "").action("share the replica over nvmf"),
+            LvsError::ImportSource {
+                uri, ..
+            }
+            | LvsError::ImportSourceOpen {
+                uri, ..
+            } => Self::new("replica", uri).action("check the import source"),
+        }
+    }
+}
+
+impl From<&NexusError> for ErrorDetail {
+    fn from(e: &NexusError) -> Self {
+        match e {
+            NexusError::NexusInitialising {
+                name,
+            }
+            | NexusError::NexusIncomplete {
+                name,
+            }
+            | NexusError::DrainTimeout {
+                name, ..
+            }
+            | NexusError::Pause {
+                name, ..
+            } => Self::new("nexus", name).retriable(),
+            NexusError::NameExists {
+                name,
+            }
+            | NexusError::UuidExists {
+                nexus: name, ..
+            } => Self::new("nexus", name).action("use another name and uuid"),
+            NexusError::Protected {
+                name,
+            } => Self::new("nexus", name)
+                .action("unprotect the nexus before destroying it"),
+            NexusError::CreateCryptoBdev {
+                source,
+                name,
+            }
+            | NexusError::DestroyCryptoBdev {
+                source,
+                name,
+            }
+            | NexusError::RegisterNexus {
+                source,
+                name,
+            } => Self::new("nexus", name).errno(*source),
+            NexusError::ResolveKey {
+                name, ..
+            } => Self::new("nexus", name)
+                .retriable()
+                .action("check the key management service"),
+            NexusError::AlreadyShared {
+                name,
+            } => Self::new("nexus", name)
+                .action("unpublish the nexus before publishing it again"),
+            NexusError::NotShared {
+                name,
+            }
+            | NexusError::NotSharedNvmf {
+                name,
+            } => Self::new("nexus", name).action("publish the nexus first"),
+            NexusError::ShareNvmfNexus {
+                name, ..
+            }
+            | NexusError::UnshareNexus {
+                name, ..
+            } => Self::new("nexus", name).retriable(),
+            NexusError::CreateChild {
+                name, ..
+            } => Self::new("nexus", name).action("check the child URIs"),
+            NexusError::ChildTooSmall {
+                child, ..
+            }
+            | NexusError::ChildGeometry {
+                child, ..
+            } => Self::new("nexus", child)
+                .action("use a child of the size and block size of the nexus"),
+            NexusError::OpenChild {
+                child, ..
+            }
+            | NexusError::ChildWriteExclusiveResvFailed {
+                child, ..
+            }
+            | NexusError::PauseChild {
+                child, ..
+            } => Self::new("nexus", child).retriable(),
+            NexusError::DestroyLastChild {
+                child, ..
+            }
+            | NexusError::DestroyLastHealthyChild {
+                child, ..
+            }
+            | NexusError::RemoveLastChild {
+                child, ..
+            }
+            | NexusError::FaultingLastHealthyChild {
+                child, ..
+            } => Self::new("nexus", child)
+                .action("add a healthy child to the nexus first"),
+            NexusError::ChildNotFound {
+                child, ..
+            }
+            | NexusError::ChildMissing {
+                child, ..
+            }
+            | NexusError::ChildAlreadyExists {
+                child, ..
+            }
+            | NexusError::ChildMissingErrStore {
+                child, ..
+            }
+            | NexusError::CloseChild {
+                child, ..
+            }
+            | NexusError::DestroyChild {
+                child, ..
+            }
+            | NexusError::ChildNotDegraded {
+                child, ..
+            }
+            | NexusError::CreateRebuild {
+                child, ..
+            }
+            | NexusError::RebuildJobNotFound {
+                child, ..
+            }
+            | NexusError::RemoveRebuildJob {
+                child, ..
+            } => Self::new("nexus", child),
+            NexusError::TooManyChildren {
+                name, ..
+            } => Self::new("nexus", name)
+                .action("remove a child from the nexus first"),
+            NexusError::NoRebuildSource {
+                name,
+            } => Self::new("nexus", name)
+                .action("add a healthy child to the nexus first"),
+            NexusError::MixedProtectionInfo {
+                name,
+            } => Self::new("nexus", name).action(
+                "use children formatted alike, or without interleaved \
+                 metadata",
+            ),
+            NexusError::NexusNotFound {
+                name,
+            }
+            | NexusError::CryptoConflict {
+                name, ..
+            }
+            | NexusError::ShareNbdNexus {
+                name, ..
+            }
+            | NexusError::MixedBlockSizes {
+                name,
+            }
+            | NexusError::InvalidBlockSize {
+                name, ..
+            }
+            | NexusError::RebuildOperation {
+                name, ..
+            }
+            | NexusError::InvalidArguments {
+                name, ..
+            }
+            | NexusError::NexusCreate {
+                name,
+            }
+            | NexusError::CreateReadCache {
+                name, ..
+            }
+            | NexusError::OpenReadCache {
+                name, ..
+            }
+            | NexusError::CreateChecksums {
+                name, ..
+            }
+            | NexusError::OpenChecksums {
+                name, ..
+            }
+            | NexusError::CreateReplication {
+                name, ..
+            }
+            | NexusError::OpenReplication {
+                name, ..
+            }
+            | NexusError::TraceFile {
+                name, ..
+            }
+            | NexusError::NexusDestroy {
+                name,
+            }
+            | NexusError::FailedCreateSnapshot {
+                name, ..
+            } => Self::new("nexus", name),
+            NexusError::InvalidUuid {
+                uuid,
+            } => Self::new("nexus", uuid),
+            NexusError::InvalidKey {}
+            | NexusError::InvalidShareProtocol {
+                ..
+            }
+            | NexusError::InvalidNvmeAnaState {
+                ..
+            }
+            | NexusError::FailedGetHandle
+            | NexusError::SubsysNvmf {
+                ..
+            } => Self::new("nexus", ""),
+        }
+    }
+}
+
+impl From<&ReplicaError> for ErrorDetail {
+    fn from(e: &ReplicaError) -> Self {
+        match e {
+            ReplicaError::ReplicaShared {} => Self::new("replica", "")
+                .action("unshare the replica before sharing it again"),
+            ReplicaError::ShareNvmf {
+                ..
+            }
+            | ReplicaError::UnshareNvmf {
+                ..
+            } => Self::new("replica", "").retriable(),
+            ReplicaError::InvalidProtocol {
+                ..
+            } => Self::new("replica", "").action("share the replica over nvmf"),
+            ReplicaError::ReplicaNotFound {} => Self::new("replica", ""),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use nix::errno::Errno;
+    use tonic::{Code, Status};
+
+    use super::ErrorDetail;
+
+    #[test]
+    fn round_trip() {
+        let detail = ErrorDetail::new("replica", "r1")
+            .errno(Errno::EBUSY)
+            .action("retry later");
+        let status = detail.attach(Status::internal("busy"));
+        assert_eq!(status.code(), Code::Internal);
+        assert_eq!(status.message(), "busy");
+
+        let decoded = ErrorDetail::from_status(&status).unwrap();
+        assert_eq!(decoded, detail);
+        assert_eq!(decoded.errno, libc::EBUSY);
+        assert!(decoded.retriable);
+
+        assert_eq!(ErrorDetail::from_status(&Status::internal("x")), None);
+    }
+}
@@ -1,7 +1,7 @@
 //!
 //! gRPC method to proxy calls to (local) SPDK json-rpc service
 
-use crate::grpc::GrpcResult;
+use crate::grpc::{audit, GrpcClientContext, GrpcResult};
 use ::rpc::mayastor::{json_rpc_server::JsonRpc, JsonRpcReply, JsonRpcRequest};
 use jsonrpc::error::Error;
 use std::borrow::Cow;
@@ -30,15 +30,19 @@ impl JsonRpc for JsonRpcSvc {
         &self,
         request: Request<JsonRpcRequest>,
     ) -> GrpcResult<JsonRpcReply> {
-        let args = request.into_inner();
+        let ctx = GrpcClientContext::new(&request, "json_rpc_call");
+        audit::audited(ctx, async move {
+            let args = request.into_inner();
 
-        let result = self
-            .spdk_jsonrpc_call(&args.method, empty_as_none(&args.params))
-            .await?;
+            let result = self
+                .spdk_jsonrpc_call(&args.method, empty_as_none(&args.params))
+                .await?;
 
-        Ok(Response::new(JsonRpcReply {
-            result,
-        }))
+            Ok(Response::new(JsonRpcReply {
+                result,
+            }))
+        })
+        .await
     }
 }
 
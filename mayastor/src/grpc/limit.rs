@@ -0,0 +1,277 @@
+//!
+//! Rate limiting and concurrency control of the gRPC API.
+//!
+//! Every call of the gRPC API ends up as work on the init thread, so a
+//! control plane retrying in a loop can starve the data path of it. Calls are
+//! therefore admitted at most at `--grpc-rate-limit` calls per second, with
+//! bursts of as many, and calls above that rate fail with
+//! `RESOURCE_EXHAUSTED` for the caller to back off.
+//!
+//! Heavy calls, the ones creating, importing or destroying pools, starting
+//! rebuilds and taking snapshots, are also limited to
+//! `--grpc-heavy-concurrency` calls of the same method at a time. Further
+//! calls of the method wait in a queue of `--grpc-heavy-queue` calls, and
+//! fail with `RESOURCE_EXHAUSTED` when it is full.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use futures::future::BoxFuture;
+use hyper::Body;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use tokio::sync::Semaphore;
+use tonic::{body::BoxBody, transport::NamedService, Status};
+use tower::Service;
+
+/// methods limited in concurrency, whichever the service
+const HEAVY_METHODS: [&str; 5] = [
+    "CreatePool",
+    "ImportPool",
+    "DestroyPool",
+    "StartRebuild",
+    "CreateSnapshot",
+];
+
+static LIMITS: OnceCell<Limits> = OnceCell::new();
+
+/// Limits of the calls of the gRPC API.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimitOpts {
+    /// calls admitted per second, 0 admits them all
+    pub rate: u32,
+    /// heavy calls of a method running at a time
+    pub heavy_concurrency: usize,
+    /// heavy calls of a method waiting to run
+    pub heavy_queue: usize,
+}
+
+impl Default for LimitOpts {
+    fn default() -> Self {
+        Self {
+            rate: 0,
+            heavy_concurrency: 1,
+            heavy_queue: 16,
+        }
+    }
+}
+
+/// Token bucket of the calls admitted.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    /// take a token if there is one, refilling the bucket at the rate
+    fn take(&mut self, rate: u32, now: Instant) -> bool {
+        let rate = f64::from(rate);
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// calls of a heavy method running or waiting to
+#[derive(Debug)]
+struct Heavy {
+    running: Arc<Semaphore>,
+    queued: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Limits {
+    opts: LimitOpts,
+    bucket: Mutex<Bucket>,
+    heavy: Mutex<HashMap<String, Arc<Heavy>>>,
+}
+
+impl Limits {
+    fn get() -> &'static Limits {
+        LIMITS.get_or_init(|| Limits::new(LimitOpts::default()))
+    }
+
+    fn new(opts: LimitOpts) -> Self {
+        Self {
+            opts,
+            bucket: Mutex::new(Bucket {
+                tokens: f64::from(opts.rate),
+                last: Instant::now(),
+            }),
+            heavy: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// returns true if the rate allows one more call
+    fn admit(&self) -> bool {
+        self.opts.rate == 0
+            || self.bucket.lock().take(self.opts.rate, Instant::now())
+    }
+
+    /// returns the calls of the method if it is a heavy one
+    fn heavy(&self, method: &str) -> Option<Arc<Heavy>> {
+        if !HEAVY_METHODS.contains(&method) {
+            return None;
+        }
+        let concurrency = self.opts.heavy_concurrency.max(1);
+        Some(
+            self.heavy
+                .lock()
+                .entry(method.to_string())
+                .or_insert_with(|| {
+                    Arc::new(Heavy {
+                        running: Arc::new(Semaphore::new(concurrency)),
+                        queued: AtomicUsize::new(0),
+                    })
+                })
+                .clone(),
+        )
+    }
+}
+
+/// Set the limits of the calls of the gRPC API, which admits all calls and
+/// runs one heavy call of a method at a time otherwise.
+pub fn init(opts: LimitOpts) {
+    if opts.rate > 0 {
+        info!("Limiting gRPC calls to {} per second", opts.rate);
+    }
+    if LIMITS.set(Limits::new(opts)).is_err() {
+        warn!("gRPC limits already set");
+    }
+}
+
+/// removes a call from the queue of its method when it leaves it, whether it
+/// runs or is cancelled
+struct Queued(Arc<Heavy>);
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        self.0.queued.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// gRPC service only passing on the calls the limits allow.
+#[derive(Debug, Clone)]
+pub struct Limited<S> {
+    inner: S,
+}
+
+impl<S> Limited<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+        }
+    }
+}
+
+impl<S: NamedService> NamedService for Limited<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> Service<http::Request<Body>> for Limited<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<Body>) -> Self::Future {
+        let limits = Limits::get();
+        let path = req.uri().path().to_string();
+
+        if !limits.admit() {
+            warn!("{}: rejected, above the rate limit", path);
+            return Box::pin(async {
+                Ok(Status::resource_exhausted("gRPC rate limit exceeded")
+                    .to_http())
+            });
+        }
+
+        let method = path.rsplit('/').next().unwrap_or_default();
+        let heavy = match limits.heavy(method) {
+            Some(heavy) => heavy,
+            None => return Box::pin(self.inner.call(req)),
+        };
+
+        if heavy.running.available_permits() == 0
+            && heavy.queued.load(Ordering::SeqCst) >= limits.opts.heavy_queue
+        {
+            warn!("{}: rejected, too many calls queued", path);
+            return Box::pin(async move {
+                Ok(Status::resource_exhausted(format!(
+                    "too many {} calls queued",
+                    path
+                ))
+                .to_http())
+            });
+        }
+
+        heavy.queued.fetch_add(1, Ordering::SeqCst);
+        let queued = Queued(heavy);
+        // the call does not run until its future is polled
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let _permit = queued.0.running.clone().acquire_owned().await;
+            drop(queued);
+            fut.await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Bucket, LimitOpts, Limits};
+
+    #[test]
+    fn bucket() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 2.0,
+            last: start,
+        };
+        assert!(bucket.take(2, start));
+        assert!(bucket.take(2, start));
+        assert!(!bucket.take(2, start));
+        assert!(bucket.take(2, start + Duration::from_millis(500)));
+        assert!(!bucket.take(2, start + Duration::from_millis(500)));
+        // never more than a second worth of calls
+        assert!(bucket.take(2, start + Duration::from_secs(10)));
+        assert!(bucket.take(2, start + Duration::from_secs(10)));
+        assert!(!bucket.take(2, start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn heavy() {
+        let limits = Limits::new(LimitOpts::default());
+        assert!(limits.admit());
+        assert!(limits.heavy("CreatePool").is_some());
+        assert!(limits.heavy("StartRebuild").is_some());
+        assert!(limits.heavy("ListPools").is_none());
+    }
+}
@@ -0,0 +1,357 @@
+//!
+//! Paged and filtered listing of the pools, replicas and nexuses.
+//!
+//! The list calls of the gRPC API return every object of the node, which on
+//! nodes with thousands of replicas makes for multi-megabyte replies on each
+//! poll of the control plane. The `list_pools_paged`, `list_replicas_paged`
+//! and `list_nexus_paged` json-rpc methods return the same objects, as the
+//! v0 API encodes them, filtered by name, uuid and pool labels, a page at a
+//! time and with only the fields asked for.
+//!
+//! Objects are returned ordered by their key: the name of pools and nexuses,
+//! and the pool and name of replicas, as replicas on different pools may
+//! share a name. A page that is not the last one carries an opaque token to
+//! pass back to get the next page; it holds the key of the last object
+//! returned, so objects created or destroyed between two pages neither shift
+//! the pages nor make them repeat or skip an object.
+
+use std::{collections::BTreeMap, convert::TryFrom};
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::Snafu;
+
+use crate::{
+    bdev::nexus::{nexus_iter, NexusState},
+    core::UntypedBdev,
+    jsonrpc::{jsonrpc_register, Code, RpcErrorCode},
+    lvs::{Lvol, Lvs},
+};
+use rpc::mayastor::{Pool, ReplicaV2};
+
+/// the largest page that can be asked for
+const MAX_PAGE_SIZE: usize = 1000;
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum ListError {
+    #[snafu(display("Page size {} is larger than {}", size, MAX_PAGE_SIZE))]
+    PageSize { size: usize },
+    #[snafu(display("Unknown field {} in the field mask", field))]
+    UnknownField { field: String },
+    #[snafu(display("Nexuses can not be filtered by labels"))]
+    NoLabels {},
+    #[snafu(display("Invalid page token {}", token))]
+    PageToken { token: String },
+}
+
+impl RpcErrorCode for ListError {
+    fn rpc_error_code(&self) -> Code {
+        Code::InvalidParams
+    }
+}
+
+/// Filters, page and field mask of a list call.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ListArgs {
+    /// only list the object of that name
+    pub name: Option<String>,
+    /// only list the object of that uuid
+    pub uuid: Option<String>,
+    /// only list the pools, or the replicas on pools, having these labels
+    pub labels: BTreeMap<String, String>,
+    /// maximum number of objects returned, 0 returns them all
+    pub page_size: usize,
+    /// token of the page to return, as returned with the previous page
+    pub page_token: Option<String>,
+    /// fields of the objects returned, all of them if empty
+    pub fields: Vec<String>,
+}
+
+/// A page of a list call.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListPage {
+    pub items: Vec<Value>,
+    /// token of the next page, None for the last page
+    pub next_page_token: Option<String>,
+}
+
+/// An object that can be listed, with what it is filtered and ordered by.
+struct Listed<T> {
+    /// unique key of the object on the node, which orders the objects
+    key: Vec<String>,
+    name: String,
+    uuid: String,
+    item: T,
+}
+
+/// keep the listed fields of an object
+fn mask(value: Value, fields: &[String]) -> Result<Value, ListError> {
+    if fields.is_empty() {
+        return Ok(value);
+    }
+    match value {
+        Value::Object(mut object) => {
+            let mut masked = serde_json::Map::new();
+            for field in fields {
+                match object.remove(field) {
+                    Some(v) => masked.insert(field.clone(), v),
+                    None => {
+                        return Err(ListError::UnknownField {
+                            field: field.clone(),
+                        })
+                    }
+                };
+            }
+            Ok(Value::Object(masked))
+        }
+        value => Ok(value),
+    }
+}
+
+/// Filter the objects by name and uuid and return the page of the args.
+fn page<T: Serialize>(
+    mut listed: Vec<Listed<T>>,
+    args: &ListArgs,
+) -> Result<ListPage, ListError> {
+    if args.page_size > MAX_PAGE_SIZE {
+        return Err(ListError::PageSize {
+            size: args.page_size,
+        });
+    }
+
+    let after = match &args.page_token {
+        Some(token) => {
+            Some(serde_json::from_str::<Vec<String>>(token).map_err(|_| {
+                ListError::PageToken {
+                    token: token.clone(),
+                }
+            })?)
+        }
+        None => None,
+    };
+
+    listed.retain(|l| {
+        args.name.as_ref().map_or(true, |n| &l.name == n)
+            && args.uuid.as_ref().map_or(true, |u| &l.uuid == u)
+            && after.as_ref().map_or(true, |k| &l.key > k)
+    });
+    listed.sort_by(|a, b| a.key.cmp(&b.key));
+
+    let size = if args.page_size == 0 {
+        listed.len()
+    } else {
+        args.page_size
+    };
+    let next_page_token = if listed.len() > size {
+        listed.truncate(size);
+        listed
+            .last()
+            .map(|l| serde_json::to_string(&l.key).unwrap())
+    } else {
+        None
+    };
+
+    let items = listed
+        .into_iter()
+        .map(|l| mask(serde_json::to_value(l.item).unwrap(), &args.fields))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ListPage {
+        items,
+        next_page_token,
+    })
+}
+
+/// returns a page of the pools
+pub fn list_pools(args: &ListArgs) -> Result<ListPage, ListError> {
+    let pools = Lvs::iter()
+        .filter(|l| l.matches_labels(&args.labels))
+        .map(|l| Listed {
+            key: vec![l.name().to_string()],
+            name: l.name().to_string(),
+            uuid: l.uuid(),
+            item: Pool::from(l),
+        })
+        .collect();
+    page(pools, args)
+}
+
+/// returns a page of the replicas
+pub fn list_replicas(args: &ListArgs) -> Result<ListPage, ListError> {
+    let mut replicas = Vec::new();
+    if let Some(bdev) = UntypedBdev::bdev_first() {
+        replicas = bdev
+            .into_iter()
+            .filter(|b| b.driver() == "lvol")
+            .map(|b| Lvol::try_from(b).unwrap())
+            .filter(|l| {
+                args.labels.is_empty()
+                    || Lvs::lookup(&l.pool())
+                        .map_or(false, |p| p.matches_labels(&args.labels))
+            })
+            .map(|l| Listed {
+                key: vec![l.pool(), l.name()],
+                name: l.name(),
+                uuid: l.uuid(),
+                item: ReplicaV2::from(l),
+            })
+            .collect();
+    }
+    page(replicas, args)
+}
+
+/// returns a page of the nexuses
+pub async fn list_nexus(args: &ListArgs) -> Result<ListPage, ListError> {
+    if !args.labels.is_empty() {
+        return Err(ListError::NoLabels {});
+    }
+    let mut nexus_list = Vec::new();
+    for n in nexus_iter() {
+        if *n.state.lock() != NexusState::Init {
+            let item = n.to_grpc_v2().await;
+            nexus_list.push(Listed {
+                key: vec![item.name.clone()],
+                name: item.name.clone(),
+                uuid: item.uuid.clone(),
+                item,
+            });
+        }
+    }
+    page(nexus_list, args)
+}
+
+/// Register the json-rpc methods listing objects a page at a time.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, ListError>(
+        "list_pools_paged",
+        |args: ListArgs| async move { list_pools(&args) }.boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, ListError>(
+        "list_replicas_paged",
+        |args: ListArgs| async move { list_replicas(&args) }.boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, ListError>(
+        "list_nexus_paged",
+        |args: ListArgs| async move { list_nexus(&args).await }.boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Serialize;
+
+    use super::{page, ListArgs, Listed};
+
+    #[derive(Serialize)]
+    struct Item {
+        name: String,
+        size: u64,
+    }
+
+    fn items() -> Vec<Listed<Item>> {
+        ["c", "a", "d", "b"]
+            .iter()
+            .map(|n| Listed {
+                key: vec![n.to_string()],
+                name: n.to_string(),
+                uuid: format!("uuid-{}", n),
+                item: Item {
+                    name: n.to_string(),
+                    size: 1,
+                },
+            })
+            .collect()
+    }
+
+    /// replicas of the same name on two pools
+    fn replicas() -> Vec<Listed<Item>> {
+        [("p2", "r"), ("p1", "r"), ("p1", "s"), ("p2", "q")]
+            .iter()
+            .map(|(p, n)| Listed {
+                key: vec![p.to_string(), n.to_string()],
+                name: n.to_string(),
+                uuid: format!("uuid-{}-{}", p, n),
+                item: Item {
+                    name: n.to_string(),
+                    size: 1,
+                },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pages() {
+        let mut args = ListArgs {
+            page_size: 3,
+            fields: vec!["name".into()],
+            ..Default::default()
+        };
+        let first = page(items(), &args).unwrap();
+        assert_eq!(
+            first.items,
+            vec![
+                serde_json::json!({"name": "a"}),
+                serde_json::json!({"name": "b"}),
+                serde_json::json!({"name": "c"}),
+            ]
+        );
+        assert_eq!(first.next_page_token.as_deref(), Some(r#"["c"]"#));
+
+        args.page_token = first.next_page_token;
+        let last = page(items(), &args).unwrap();
+        assert_eq!(last.items, vec![serde_json::json!({"name": "d"})]);
+        assert_eq!(last.next_page_token, None);
+
+        let args = ListArgs {
+            uuid: Some("uuid-b".into()),
+            ..Default::default()
+        };
+        let one = page(items(), &args).unwrap();
+        assert_eq!(
+            one.items,
+            vec![serde_json::json!({"name": "b", "size": 1})]
+        );
+
+        let args = ListArgs {
+            fields: vec!["colour".into()],
+            ..Default::default()
+        };
+        assert!(page(items(), &args).is_err());
+
+        let args = ListArgs {
+            page_token: Some("c".into()),
+            ..Default::default()
+        };
+        assert!(page(items(), &args).is_err());
+    }
+
+    #[test]
+    fn pages_same_name() {
+        let mut args = ListArgs {
+            page_size: 1,
+            fields: vec!["name".into()],
+            ..Default::default()
+        };
+        let mut names = Vec::new();
+        loop {
+            let next = page(replicas(), &args).unwrap();
+            names.extend(next.items);
+            match next.next_page_token {
+                Some(token) => args.page_token = Some(token),
+                None => break,
+            }
+        }
+        // a replica sharing its name with the last one of a page is not
+        // skipped by the next page
+        assert_eq!(
+            names,
+            ["r", "s", "q", "r"]
+                .iter()
+                .map(|n| serde_json::json!({ "name": n }))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -19,18 +19,22 @@ use crate::{
         UntypedBdev,
     },
     grpc::{
+        audit,
         controller_grpc::{
             controller_stats,
             list_controllers,
             NvmeControllerInfo,
         },
+        error_detail::ErrorDetail,
         nexus_grpc::{
             nexus_add_child,
             nexus_destroy,
             nexus_lookup,
             uuid_to_name,
         },
+        rbac,
         rpc_submit,
+        validate::{self, Kind},
         GrpcClientContext,
         GrpcResult,
         Serializer,
@@ -40,7 +44,7 @@ use crate::{
     nexus_uri::NexusBdevError,
     pool::PoolArgs,
     rebuild::{RebuildState, RebuildStats},
-    subsys::PoolConfig,
+    subsys::{Config, PoolConfig},
 };
 
 use futures::FutureExt;
@@ -57,6 +61,22 @@ use ::function_name::named;
 use git_version::git_version;
 use std::{panic::AssertUnwindSafe, pin::Pin};
 
+/// address of the client, prefixed by the name of the token the call was
+/// authorized with, if any
+fn peer<T>(req: &Request<T>) -> String {
+    let addr = req
+        .remote_addr()
+        .map_or_else(|| "unknown".to_string(), |a| a.to_string());
+    match req
+        .metadata()
+        .get(rbac::IDENTITY_HEADER)
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(name) => format!("{}@{}", name, addr),
+        None => addr,
+    }
+}
+
 impl GrpcClientContext {
     #[track_caller]
     pub fn new<T>(req: &Request<T>, fid: &str) -> Self
@@ -64,8 +84,9 @@ impl GrpcClientContext {
         T: Debug,
     {
         Self {
-            args: format!("{:?}", req.get_ref()),
+            args: audit::redact(&format!("{:?}", req.get_ref())),
             id: fid.to_string(),
+            peer: peer(req),
         }
     }
 }
@@ -106,7 +127,7 @@ where
         // Request completed, remove the marker.
         let ctx = guard.take().expect("gRPC context disappeared");
 
-        match r {
+        let result = match r {
             Ok(r) => r,
             Err(_e) => {
                 warn!("{}: gRPC method panicked, args: {}", ctx.id, ctx.args);
@@ -115,7 +136,10 @@ where
                     ctx.id
                 )))
             }
-        }
+        };
+
+        audit::record(&ctx, &result);
+        result
     }
 }
 
@@ -141,6 +165,8 @@ impl TryFrom<CreatePoolRequest> for PoolArgs {
                 name: args.name,
                 disks: args.disks,
                 uuid: None,
+                labels: Default::default(),
+                cluster_size: None,
             }),
         }
     }
@@ -148,7 +174,8 @@ impl TryFrom<CreatePoolRequest> for PoolArgs {
 
 impl From<LvsError> for Status {
     fn from(e: LvsError) -> Self {
-        match e {
+        let detail = ErrorDetail::from(&e);
+        let status = match e {
             LvsError::Import {
                 ..
             } => Status::invalid_argument(e.to_string()),
@@ -164,10 +191,24 @@ impl From<LvsError> for Status {
             LvsError::ReplicaShareProtocol {
                 ..
             } => Status::invalid_argument(e.to_string()),
+            LvsError::RepProtected {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            LvsError::ZonedDevice {
+                ..
+            } => Status::invalid_argument(e.to_string()),
+            LvsError::QuotaExceeded {
+                ..
+            } => Status::resource_exhausted(e.to_string()),
 
             LvsError::Destroy {
                 source, ..
             } => source.into(),
+            LvsError::Invalid {
+                source, ..
+            } if source == Errno::EEXIST => {
+                Status::already_exists(e.to_string())
+            }
             LvsError::Invalid {
                 ..
             } => Status::invalid_argument(e.to_string()),
@@ -175,7 +216,8 @@ impl From<LvsError> for Status {
                 source, ..
             } => source.into(),
             _ => Status::internal(e.to_string()),
-        }
+        };
+        detail.attach(status)
     }
 }
 
@@ -380,6 +422,7 @@ impl mayastor_server::Mayastor for MayastorSvc {
             async move {
                 let args = request.into_inner();
 
+                validate::name(Kind::Pool, &args.name)?;
                 if args.disks.is_empty() {
                     return Err(Status::invalid_argument("Missing devices"));
                 }
@@ -413,6 +456,7 @@ impl mayastor_server::Mayastor for MayastorSvc {
                 info!("{:?}", args);
                 let rx = rpc_submit::<_, _, LvsError>(async move {
                     if let Some(pool) = Lvs::lookup(&args.name) {
+                        pool.check_protected().await?;
                         // Remove pool from current config and export to file.
                         // Do this BEFORE we actually destroy the pool.
                         let mut config = PoolConfig::capture();
@@ -464,8 +508,10 @@ impl mayastor_server::Mayastor for MayastorSvc {
         request: Request<CreateReplicaRequest>,
     ) -> GrpcResult<Replica> {
         self.locked(GrpcClientContext::new(&request, function_name!()), async move {
+        let args = request.into_inner();
+        validate::name(Kind::Pool, &args.pool)?;
+        validate::name(Kind::Replica, &args.uuid)?;
         let rx = rpc_submit(async move {
-            let args = request.into_inner();
 
             if Lvs::lookup(&args.pool).is_none() {
                 return Err(LvsError::Invalid {
@@ -530,8 +576,11 @@ impl mayastor_server::Mayastor for MayastorSvc {
         request: Request<CreateReplicaRequestV2>,
     ) -> GrpcResult<ReplicaV2> {
         self.locked(GrpcClientContext::new(&request, function_name!()), async move {
+        let mut args = request.into_inner();
+        validate::name(Kind::Pool, &args.pool)?;
+        validate::name(Kind::Replica, &args.name)?;
+        args.uuid = validate::uuid("replica", &args.uuid)?;
         let rx = rpc_submit(async move {
-            let args = request.into_inner();
 
             let lvs = match Lvs::lookup(&args.pool) {
                 Some(lvs) => lvs,
@@ -600,9 +649,15 @@ impl mayastor_server::Mayastor for MayastorSvc {
         self.locked(GrpcClientContext::new(&request, function_name!()), async {
             let args = request.into_inner();
             let rx = rpc_submit::<_, _, LvsError>(async move {
-                if let Some(bdev) = UntypedBdev::lookup_by_name(&args.uuid) {
+                if let Some(bdev) =
+                    UntypedBdev::lookup_by_name_or_uuid(&args.uuid)
+                {
                     let lvol = Lvol::try_from(bdev)?;
-                    lvol.destroy().await?;
+                    lvol.check_protected(false).await?;
+                    lvol.destroy_with_policy(
+                        Config::get().replica_opts.deletion_policy,
+                    )
+                    .await?;
                 }
                 Ok(Null {})
             })?;
@@ -721,7 +776,7 @@ impl mayastor_server::Mayastor for MayastorSvc {
             async move {
                 let args = request.into_inner();
                 let rx = rpc_submit(async move {
-                    match UntypedBdev::lookup_by_name(&args.uuid) {
+                    match UntypedBdev::lookup_by_name_or_uuid(&args.uuid) {
                         Some(bdev) => {
                             let mut lvol = Lvol::try_from(bdev)?;
 
@@ -775,7 +830,9 @@ impl mayastor_server::Mayastor for MayastorSvc {
         self.locked(
             GrpcClientContext::new(&request, function_name!()),
             async move {
-                let args = request.into_inner();
+                let mut args = request.into_inner();
+                args.uuid = validate::uuid("nexus", &args.uuid)?;
+                args.children = validate::child_uris(&args.children)?;
                 let rx = rpc_submit::<_, _, nexus::Error>(async move {
                     let uuid = args.uuid.clone();
                     let name = uuid_to_name(&args.uuid)?;
@@ -807,7 +864,10 @@ impl mayastor_server::Mayastor for MayastorSvc {
         self.locked(
             GrpcClientContext::new(&request, function_name!()),
             async move {
-                let args = request.into_inner();
+                let mut args = request.into_inner();
+                validate::name(Kind::Nexus, &args.name)?;
+                args.uuid = validate::uuid("nexus", &args.uuid)?;
+                args.children = validate::child_uris(&args.children)?;
 
                 // If the control plane has supplied a key, use it to store the
                 // NexusInfo.
@@ -927,7 +987,8 @@ impl mayastor_server::Mayastor for MayastorSvc {
         &self,
         request: Request<AddChildNexusRequest>,
     ) -> GrpcResult<Child> {
-        let args = request.into_inner();
+        let mut args = request.into_inner();
+        args.uri = validate::child_uri(&args.uri)?;
         let rx = rpc_submit::<_, _, nexus::Error>(async move {
             trace!("{:?}", args);
             let uuid = args.uuid.clone();
@@ -1038,7 +1099,9 @@ impl mayastor_server::Mayastor for MayastorSvc {
             trace!("{:?}", args);
             let uuid = args.uuid.clone();
             debug!("Unpublishing nexus {} ...", uuid);
-            nexus_lookup(&args.uuid)?.unshare_nexus().await?;
+            nexus_lookup(&args.uuid)?
+                .unshare_nexus_drained(nexus::DrainOpts::default())
+                .await?;
             info!("Unpublished nexus {}", uuid);
             Ok(Null {})
         })?;
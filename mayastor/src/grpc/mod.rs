@@ -1,6 +1,7 @@
 use std::{
     error::Error,
     fmt::{Debug, Display},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use futures::{channel::oneshot::Receiver, Future};
@@ -34,12 +35,20 @@ impl From<CoreError> for tonic::Status {
         Status::internal(e.to_string())
     }
 }
+pub mod audit;
+mod bdev_backend;
 mod bdev_grpc;
 mod controller_grpc;
+pub mod error_detail;
 mod json_grpc;
+pub mod limit;
+pub mod list_page;
 mod mayastor_grpc;
 mod nexus_grpc;
+pub mod rbac;
+pub mod rest;
 mod server;
+pub mod validate;
 pub mod v1 {
     pub mod bdev;
     pub mod host;
@@ -53,6 +62,8 @@ pub mod v1 {
 pub(crate) struct GrpcClientContext {
     pub args: String,
     pub id: String,
+    /// address of the client and the name of its token, if known
+    pub peer: String,
 }
 
 #[async_trait::async_trait]
@@ -79,6 +90,47 @@ where
         .map_err(|e| e.into())
 }
 
+/// number of submitted futures not completed yet
+static RPC_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// number of submitted futures above which submissions are rejected
+static RPC_MAX_PENDING: AtomicUsize = AtomicUsize::new(256);
+
+/// Set the number of submitted futures not completed yet above which
+/// further submissions are rejected.
+pub fn set_rpc_max_pending(max: usize) {
+    RPC_MAX_PENDING.store(max, Ordering::Relaxed);
+}
+
+/// counts a submitted future as pending until it is dropped
+struct RpcPending;
+
+impl RpcPending {
+    fn new() -> Option<Self> {
+        let max = RPC_MAX_PENDING.load(Ordering::Relaxed);
+        RPC_PENDING
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                if n < max {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()
+            .map(|_| RpcPending)
+    }
+}
+
+impl Drop for RpcPending {
+    fn drop(&mut self) {
+        RPC_PENDING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Submit the future to the control thread, the init thread unless a
+/// control core is given, returning a channel with its result. Submissions
+/// are rejected when too many futures are pending, so that callers back off
+/// rather than queueing work without bounds.
 pub fn rpc_submit<F, R, E>(
     future: F,
 ) -> Result<Receiver<Result<R, E>>, tonic::Status>
@@ -87,8 +139,14 @@ where
     F: Future<Output = Result<R, E>> + 'static,
     R: Send + Debug + 'static,
 {
-    Mthread::get_init()
-        .spawn_local(future)
+    let pending = RpcPending::new().ok_or_else(|| {
+        Status::resource_exhausted("too many operations pending")
+    })?;
+    Mthread::get_control()
+        .spawn_local(async move {
+            let _pending = pending;
+            future.await
+        })
         .map_err(|_| Status::resource_exhausted("ENOMEM"))
 }
 
@@ -32,7 +32,9 @@ impl From<ChildState> for rpc::ChildState {
             ChildState::Destroying => rpc::ChildState::ChildDegraded,
             ChildState::Closed => rpc::ChildState::ChildDegraded,
             ChildState::Faulted(reason) => match reason {
-                Reason::OutOfSync => rpc::ChildState::ChildDegraded,
+                Reason::OutOfSync | Reason::Standby => {
+                    rpc::ChildState::ChildDegraded
+                }
                 _ => rpc::ChildState::ChildFaulted,
             },
         }
@@ -195,6 +197,7 @@ pub async fn nexus_add_child(
 /// Idempotent destruction of the nexus.
 pub async fn nexus_destroy(uuid: &str) -> Result<(), nexus::Error> {
     if let Ok(n) = nexus_lookup(uuid) {
+        n.check_protected(false)?;
         let result = n.destroy().await;
         if result.is_ok() {
             info!("Nexus {} destroyed", uuid)
@@ -0,0 +1,276 @@
+//!
+//! Role based authorization of the gRPC API.
+//!
+//! When a token file is given with `--grpc-tokens`, every gRPC call must
+//! carry a bearer token of that file in its `authorization` metadata, and
+//! the role of the token must allow the method called:
+//!
+//! - `read-only` may only call methods reading state (`List*`, `Get*` and
+//!   `Stat*`), which is enough for monitoring tools
+//! - `operator` may in addition manage replicas, nexuses and bdevs
+//! - `admin` may call any method, including managing pools and proxying calls
+//!   to the json-rpc server
+//!
+//! The token file is a yaml list of tokens:
+//!
+//! ```yaml
+//! - name: monitoring
+//!   token: 6b1f2e0c
+//!   role: read-only
+//! ```
+//!
+//! The name of the token a call was made with is recorded in the audit log.
+//! Without a token file all calls are allowed.
+
+use std::{
+    fmt,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::future::BoxFuture;
+use http::{header::AUTHORIZATION, HeaderValue};
+use hyper::Body;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use snafu::{ResultExt, Snafu};
+use tonic::{body::BoxBody, transport::NamedService, Status};
+use tower::Service;
+
+static TOKENS: OnceCell<Vec<Token>> = OnceCell::new();
+
+/// metadata holding the name of the token a call was authorized with
+pub(crate) const IDENTITY_HEADER: &str = "x-mayastor-identity";
+
+#[derive(Debug, Snafu)]
+pub enum RbacError {
+    #[snafu(display("Failed to read token file {}: {}", path, source))]
+    ReadTokens {
+        path: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("Invalid token file {}: {}", path, source))]
+    ParseTokens {
+        path: String,
+        source: serde_yaml::Error,
+    },
+}
+
+/// Roles of the gRPC API, each one allowed what the previous ones are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    ReadOnly,
+    Operator,
+    Admin,
+}
+
+/// A token of the token file.
+#[derive(Clone, Deserialize)]
+pub struct Token {
+    /// name identifying the holder of the token
+    pub name: String,
+    pub token: String,
+    pub role: Role,
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("name", &self.name)
+            .field("token", &"<redacted>")
+            .field("role", &self.role)
+            .finish()
+    }
+}
+
+/// Get the role needed to call a method given by its path,
+/// /<package>.<service>/<method>
+pub fn required_role(path: &str) -> Role {
+    let (service, method) = path
+        .trim_start_matches('/')
+        .split_once('/')
+        .unwrap_or(("", path));
+
+    if ["List", "Get", "Stat"]
+        .iter()
+        .any(|p| method.starts_with(p))
+    {
+        Role::ReadOnly
+    } else if service.ends_with("JsonRpc")
+        || service.ends_with("PoolRpc")
+        || method.ends_with("Pool")
+    {
+        Role::Admin
+    } else {
+        Role::Operator
+    }
+}
+
+/// compare tokens in a time independent of the position of the first
+/// difference
+fn token_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Load the tokens allowed to call the gRPC API. If the supplied path is
+/// 'None', all calls are allowed.
+pub fn init(path: Option<String>) -> Result<(), RbacError> {
+    if let Some(path) = path {
+        let data = std::fs::read(&path).context(ReadTokens {
+            path: path.clone(),
+        })?;
+        let tokens: Vec<Token> =
+            serde_yaml::from_slice(&data).context(ParseTokens {
+                path: path.clone(),
+            })?;
+        info!("Authorizing gRPC calls with {} tokens", tokens.len());
+        if TOKENS.set(tokens).is_err() {
+            warn!("gRPC tokens already loaded");
+        }
+    }
+    Ok(())
+}
+
+/// Authorize a call, returning the token it was made with if authorization
+/// is enabled.
+fn authorize(req: &http::Request<Body>) -> Result<Option<&Token>, Status> {
+    let tokens = match TOKENS.get() {
+        Some(tokens) => tokens,
+        None => return Ok(None),
+    };
+
+    let bearer = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or_else(|| Status::unauthenticated("missing bearer token"))?;
+
+    let token = tokens
+        .iter()
+        .find(|t| token_eq(t.token.as_bytes(), bearer.as_bytes()))
+        .ok_or_else(|| Status::unauthenticated("invalid token"))?;
+
+    let path = req.uri().path();
+    if token.role < required_role(path) {
+        warn!("{}: denied to {} ({:?})", path, token.name, token.role);
+        return Err(Status::permission_denied(format!(
+            "{} not allowed to {:?}",
+            path, token.role
+        )));
+    }
+    Ok(Some(token))
+}
+
+/// gRPC service only passing on the calls the caller is authorized for.
+#[derive(Debug, Clone)]
+pub struct Authorized<S> {
+    inner: S,
+}
+
+impl<S> Authorized<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+        }
+    }
+}
+
+impl<S: NamedService> NamedService for Authorized<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> Service<http::Request<Body>> for Authorized<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: http::Request<Body>) -> Self::Future {
+        // the identity is ours to set, never the caller's
+        req.headers_mut().remove(IDENTITY_HEADER);
+
+        match authorize(&req) {
+            Ok(token) => {
+                if let Some(name) =
+                    token.and_then(|t| HeaderValue::from_str(&t.name).ok())
+                {
+                    req.headers_mut().insert(IDENTITY_HEADER, name);
+                }
+                Box::pin(self.inner.call(req))
+            }
+            Err(status) => {
+                // slow down guessing tokens
+                let delay = if status.code() == tonic::Code::Unauthenticated {
+                    Duration::from_millis(100)
+                } else {
+                    Duration::ZERO
+                };
+                Box::pin(async move {
+                    tokio::time::sleep(delay).await;
+                    Ok(status.to_http())
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles() {
+        assert_eq!(
+            required_role("/mayastor.Mayastor/ListPools"),
+            Role::ReadOnly
+        );
+        assert_eq!(
+            required_role("/mayastor.v1.nexus.NexusRpc/GetRebuildStats"),
+            Role::ReadOnly
+        );
+        assert_eq!(
+            required_role("/mayastor.Mayastor/PublishNexus"),
+            Role::Operator
+        );
+        assert_eq!(
+            required_role("/mayastor.v1.replica.ReplicaRpc/CreateReplica"),
+            Role::Operator
+        );
+        assert_eq!(required_role("/mayastor.Mayastor/CreatePool"), Role::Admin);
+        assert_eq!(
+            required_role("/mayastor.v1.pool.PoolRpc/ImportPool"),
+            Role::Admin
+        );
+        assert_eq!(required_role("/mayastor.JsonRpc/JsonRpcCall"), Role::Admin);
+        assert!(
+            Role::Admin > Role::Operator && Role::Operator > Role::ReadOnly
+        );
+    }
+
+    #[test]
+    fn tokens() {
+        assert!(token_eq(b"secret", b"secret"));
+        assert!(!token_eq(b"secret", b"secreT"));
+        assert!(!token_eq(b"secret", b"secret1"));
+
+        let tokens: Vec<Token> = serde_yaml::from_str(
+            "- name: monitoring\n  token: abc\n  role: read-only\n",
+        )
+        .unwrap();
+        assert_eq!(tokens[0].role, Role::ReadOnly);
+        assert!(!format!("{:?}", tokens[0]).contains("abc"));
+    }
+}
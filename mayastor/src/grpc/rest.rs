@@ -0,0 +1,285 @@
+//!
+//! REST gateway of the gRPC API.
+//!
+//! When `--rest-endpoint` is given, the methods of the mayastor service and
+//! the json-rpc proxy are also served as REST calls taking and returning the
+//! JSON form of their gRPC messages, for scripts and UIs without gRPC
+//! tooling:
+//!
+//! ```text
+//! GET  /v0                 lists the methods
+//! GET  /v0/ListNexus       calls a method without arguments
+//! POST /v0/CreateNexus     calls a method, the body is its request message
+//! ```
+//!
+//! The gateway calls the gRPC server of the process, passing on the
+//! `authorization` header of the request, so the calls are authorized and
+//! audited as gRPC calls are. gRPC errors are returned with the closest HTTP
+//! status and a JSON body holding the gRPC code and message.
+
+use std::{convert::Infallible, net::SocketAddr};
+
+use http::{header::CONTENT_TYPE, Method, StatusCode};
+use hyper::{
+    header::AUTHORIZATION,
+    service::{make_service_fn, service_fn},
+    Body,
+    Request,
+    Response,
+    Server,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tonic::{
+    metadata::{AsciiMetadataValue, MetadataValue},
+    transport::{Channel, Endpoint},
+    Code,
+    Status,
+};
+
+use rpc::mayastor::{
+    json_rpc_client::JsonRpcClient,
+    mayastor_client::MayastorClient,
+    *,
+};
+
+/// version prefix of the paths of the gateway
+const PREFIX: &str = "/v0";
+
+/// Dispatch a call to the client method serving it, with the request message
+/// decoded from the body. Generates the list of the methods served as well.
+macro_rules! routes {
+    ($method:expr, $body:expr, $token:expr, $clients:expr, {
+        $($name:literal => $client:ident.$fn:ident($req:ty)),* $(,)?
+    }) => {
+        match $method {
+            "" => Ok(serde_json::to_vec(&[$($name),*]).unwrap()),
+            $($name => reply(
+                $clients.$client.$fn(request::<$req>($body, $token)?).await
+            ),)*
+            method => Err(Status::unimplemented(format!(
+                "unknown method {}",
+                method
+            ))),
+        }
+    };
+}
+
+/// clients of the gRPC services of the process
+#[derive(Clone)]
+struct Clients {
+    mayastor: MayastorClient<Channel>,
+    json: JsonRpcClient<Channel>,
+}
+
+/// decode a request message, an empty body being the default message
+fn request<T: DeserializeOwned + Default>(
+    body: &[u8],
+    token: &Option<AsciiMetadataValue>,
+) -> Result<tonic::Request<T>, Status> {
+    let message = if body.is_empty() {
+        T::default()
+    } else {
+        serde_json::from_slice(body).map_err(|e| {
+            Status::invalid_argument(format!("invalid request body: {}", e))
+        })?
+    };
+    let mut request = tonic::Request::new(message);
+    if let Some(token) = token {
+        request
+            .metadata_mut()
+            .insert("authorization", token.clone());
+    }
+    Ok(request)
+}
+
+/// encode a reply message
+fn reply<T: Serialize>(
+    reply: Result<tonic::Response<T>, Status>,
+) -> Result<Vec<u8>, Status> {
+    serde_json::to_vec(reply?.get_ref())
+        .map_err(|e| Status::internal(format!("invalid reply: {}", e)))
+}
+
+/// returns the HTTP status closest to a gRPC code
+fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::InvalidArgument | Code::OutOfRange => StatusCode::BAD_REQUEST,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound | Code::Unimplemented => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::FailedPrecondition => StatusCode::PRECONDITION_FAILED,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// call the gRPC method of the path of the request
+async fn dispatch(
+    mut clients: Clients,
+    req: Request<Body>,
+) -> Result<Vec<u8>, Status> {
+    let method = match req.uri().path().strip_prefix(PREFIX) {
+        Some(method) => method.trim_matches('/').to_string(),
+        None => {
+            return Err(Status::not_found(format!(
+                "paths start with {}",
+                PREFIX
+            )))
+        }
+    };
+    if req.method() != Method::GET && req.method() != Method::POST {
+        return Err(Status::invalid_argument("only GET and POST are allowed"));
+    }
+
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .map(MetadataValue::from_str)
+        .transpose()
+        .map_err(|_| Status::unauthenticated("invalid authorization header"))?;
+    let body = hyper::body::to_bytes(req.into_body())
+        .await
+        .map_err(|e| Status::invalid_argument(e.to_string()))?;
+    let body = body.as_ref();
+
+    routes!(method.as_str(), body, &token, clients, {
+        "CreatePool" => mayastor.create_pool(CreatePoolRequest),
+        "DestroyPool" => mayastor.destroy_pool(DestroyPoolRequest),
+        "ListPools" => mayastor.list_pools(Null),
+        "CreateReplica" => mayastor.create_replica(CreateReplicaRequest),
+        "CreateReplicaV2" => mayastor.create_replica_v2(CreateReplicaRequestV2),
+        "DestroyReplica" => mayastor.destroy_replica(DestroyReplicaRequest),
+        "ListReplicas" => mayastor.list_replicas(Null),
+        "ListReplicasV2" => mayastor.list_replicas_v2(Null),
+        "StatReplicas" => mayastor.stat_replicas(Null),
+        "ShareReplica" => mayastor.share_replica(ShareReplicaRequest),
+        "CreateNexus" => mayastor.create_nexus(CreateNexusRequest),
+        "CreateNexusV2" => mayastor.create_nexus_v2(CreateNexusV2Request),
+        "DestroyNexus" => mayastor.destroy_nexus(DestroyNexusRequest),
+        "ListNexus" => mayastor.list_nexus(Null),
+        "ListNexusV2" => mayastor.list_nexus_v2(Null),
+        "AddChildNexus" => mayastor.add_child_nexus(AddChildNexusRequest),
+        "RemoveChildNexus" =>
+            mayastor.remove_child_nexus(RemoveChildNexusRequest),
+        "FaultNexusChild" => mayastor.fault_nexus_child(FaultNexusChildRequest),
+        "PublishNexus" => mayastor.publish_nexus(PublishNexusRequest),
+        "UnpublishNexus" => mayastor.unpublish_nexus(UnpublishNexusRequest),
+        "GetNvmeAnaState" =>
+            mayastor.get_nvme_ana_state(GetNvmeAnaStateRequest),
+        "SetNvmeAnaState" =>
+            mayastor.set_nvme_ana_state(SetNvmeAnaStateRequest),
+        "ChildOperation" => mayastor.child_operation(ChildNexusRequest),
+        "StartRebuild" => mayastor.start_rebuild(StartRebuildRequest),
+        "StopRebuild" => mayastor.stop_rebuild(StopRebuildRequest),
+        "PauseRebuild" => mayastor.pause_rebuild(PauseRebuildRequest),
+        "ResumeRebuild" => mayastor.resume_rebuild(ResumeRebuildRequest),
+        "GetRebuildState" => mayastor.get_rebuild_state(RebuildStateRequest),
+        "GetRebuildStats" => mayastor.get_rebuild_stats(RebuildStatsRequest),
+        "GetRebuildProgress" =>
+            mayastor.get_rebuild_progress(RebuildProgressRequest),
+        "CreateSnapshot" => mayastor.create_snapshot(CreateSnapshotRequest),
+        "ListBlockDevices" =>
+            mayastor.list_block_devices(ListBlockDevicesRequest),
+        "GetResourceUsage" => mayastor.get_resource_usage(Null),
+        "ListNvmeControllers" => mayastor.list_nvme_controllers(Null),
+        "StatNvmeControllers" => mayastor.stat_nvme_controllers(Null),
+        "GetMayastorInfo" => mayastor.get_mayastor_info(Null),
+        "JsonRpcCall" => json.json_rpc_call(JsonRpcRequest),
+    })
+}
+
+async fn handle(
+    clients: Clients,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let (status, body) = match dispatch(clients, req).await {
+        Ok(body) => (StatusCode::OK, body),
+        Err(status) => (
+            http_status(status.code()),
+            serde_json::to_vec(&serde_json::json!({
+                "code": format!("{:?}", status.code()),
+                "message": status.message(),
+            }))
+            .unwrap(),
+        ),
+    };
+
+    Ok(Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap())
+}
+
+/// Parse the endpoint of the REST gateway, ip:port.
+pub fn endpoint(endpoint: String) -> SocketAddr {
+    endpoint.parse().expect("Invalid REST endpoint")
+}
+
+/// Serve the REST gateway on the endpoint, calling the gRPC server at the
+/// given endpoint.
+pub async fn run(
+    endpoint: SocketAddr,
+    mut grpc_endpoint: SocketAddr,
+) -> Result<(), ()> {
+    info!("REST gateway configured at address {}", endpoint);
+
+    // the gRPC server may listen on all addresses
+    if grpc_endpoint.ip().is_unspecified() {
+        grpc_endpoint.set_ip([127, 0, 0, 1].into());
+    }
+    let channel = Endpoint::from_shared(format!("http://{}", grpc_endpoint))
+        .map_err(|e| error!("invalid gRPC endpoint {}: {}", grpc_endpoint, e))?
+        .connect_lazy()
+        .map_err(|e| {
+            error!("invalid gRPC endpoint {}: {}", grpc_endpoint, e)
+        })?;
+    let clients = Clients {
+        mayastor: MayastorClient::new(channel.clone()),
+        json: JsonRpcClient::new(channel),
+    };
+
+    let service = make_service_fn(move |_| {
+        let clients = clients.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                handle(clients.clone(), req)
+            }))
+        }
+    });
+
+    match Server::try_bind(&endpoint) {
+        Ok(server) => server.serve(service).await.map_err(|e| {
+            error!("REST gateway failed with error: {}", e);
+        }),
+        Err(e) => {
+            error!("REST gateway failed to bind {}: {}", endpoint, e);
+            Err(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http::StatusCode;
+    use tonic::Code;
+
+    use super::http_status;
+
+    #[test]
+    fn grpc_codes_to_http() {
+        assert_eq!(http_status(Code::Ok), StatusCode::OK);
+        assert_eq!(http_status(Code::InvalidArgument), StatusCode::BAD_REQUEST);
+        assert_eq!(http_status(Code::NotFound), StatusCode::NOT_FOUND);
+        assert_eq!(http_status(Code::PermissionDenied), StatusCode::FORBIDDEN);
+        assert_eq!(
+            http_status(Code::Internal),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
@@ -1,7 +1,9 @@
 use crate::grpc::{
     bdev_grpc::BdevSvc,
     json_grpc::JsonRpcSvc,
+    limit::Limited,
     mayastor_grpc::MayastorSvc,
+    rbac::Authorized,
     v1::{
         bdev::BdevService,
         host::HostService,
@@ -33,21 +35,33 @@ impl MayastorGrpcServer {
         info!("gRPC server configured at address {}", endpoint);
         let address = Cow::from(rpc_addr);
         let svc = Server::builder()
-            .add_service(MayastorRpcServer::new(MayastorSvc::new(
-                Duration::from_millis(4),
+            .add_service(Authorized::new(Limited::new(MayastorRpcServer::new(
+                MayastorSvc::new(Duration::from_millis(4)),
+            ))))
+            .add_service(Authorized::new(Limited::new(BdevRpcServer::new(
+                BdevSvc::new(),
+            ))))
+            .add_service(Authorized::new(Limited::new(
+                v1::bdev::BdevRpcServer::new(BdevService::new()),
             )))
-            .add_service(BdevRpcServer::new(BdevSvc::new()))
-            .add_service(v1::bdev::BdevRpcServer::new(BdevService::new()))
-            .add_service(JsonRpcServer::new(JsonRpcSvc::new(address.clone())))
-            .add_service(v1::json::JsonRpcServer::new(JsonService::new(
-                address.clone(),
+            .add_service(Authorized::new(Limited::new(JsonRpcServer::new(
+                JsonRpcSvc::new(address.clone()),
+            ))))
+            .add_service(Authorized::new(Limited::new(
+                v1::json::JsonRpcServer::new(JsonService::new(address.clone())),
+            )))
+            .add_service(Authorized::new(Limited::new(
+                v1::pool::PoolRpcServer::new(PoolService::new()),
+            )))
+            .add_service(Authorized::new(Limited::new(
+                v1::replica::ReplicaRpcServer::new(ReplicaService::new()),
+            )))
+            .add_service(Authorized::new(Limited::new(
+                v1::host::HostRpcServer::new(HostService::new()),
+            )))
+            .add_service(Authorized::new(Limited::new(
+                v1::nexus::NexusRpcServer::new(NexusService::new()),
             )))
-            .add_service(v1::pool::PoolRpcServer::new(PoolService::new()))
-            .add_service(v1::replica::ReplicaRpcServer::new(
-                ReplicaService::new(),
-            ))
-            .add_service(v1::host::HostRpcServer::new(HostService::new()))
-            .add_service(v1::nexus::NexusRpcServer::new(NexusService::new()))
             .serve(endpoint);
 
         match svc.await {
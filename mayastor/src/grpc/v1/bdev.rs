@@ -1,8 +1,13 @@
 use crate::{
     core,
-    core::{CoreError, Protocol, Share},
-    grpc::{rpc_submit, GrpcResult},
-    nexus_uri::{bdev_create, bdev_destroy, NexusBdevError},
+    core::{Protocol, Share},
+    grpc::{
+        audit,
+        bdev_backend::{BdevBackend, BdevInfo, SpdkBdevs},
+        GrpcClientContext,
+        GrpcResult,
+    },
+    nexus_uri::NexusBdevError,
 };
 use rpc::mayastor::v1::bdev::{
     Bdev,
@@ -16,7 +21,7 @@ use rpc::mayastor::v1::bdev::{
     ListBdevOptions,
     ListBdevResponse,
 };
-use std::{convert::TryFrom, pin::Pin};
+use std::convert::TryFrom;
 use tonic::{Request, Response, Status};
 use url::Url;
 
@@ -40,13 +45,41 @@ where
     }
 }
 
+impl From<BdevInfo> for Bdev {
+    fn from(b: BdevInfo) -> Self {
+        Self {
+            name: b.name,
+            uuid: b.uuid,
+            num_blocks: b.num_blocks,
+            blk_size: b.blk_size,
+            claimed: b.claimed,
+            claimed_by: b.claimed_by,
+            aliases: b.aliases,
+            product_name: b.product_name,
+            share_uri: b.share_uri,
+            uri: b.uri,
+        }
+    }
+}
+
 /// RPC service for spdk bdev operations
 #[derive(Debug)]
-pub struct BdevService {}
+pub struct BdevService<B = SpdkBdevs> {
+    backend: B,
+}
 
 impl BdevService {
     pub fn new() -> Self {
-        Self {}
+        Self::with_backend(SpdkBdevs::default())
+    }
+}
+
+impl<B: BdevBackend> BdevService<B> {
+    /// bdev service running its operations on the given backend
+    pub fn with_backend(backend: B) -> Self {
+        Self {
+            backend,
+        }
     }
 }
 
@@ -57,32 +90,22 @@ impl Default for BdevService {
 }
 
 #[tonic::async_trait]
-impl BdevRpc for BdevService {
+impl<B: BdevBackend> BdevRpc for BdevService<B> {
     #[tracing::instrument(skip(self))]
     async fn list(
         &self,
         request: Request<ListBdevOptions>,
     ) -> GrpcResult<ListBdevResponse> {
-        let rx = rpc_submit::<_, _, NexusBdevError>(async {
-            let mut bdevs = Vec::new();
-            let args = request.into_inner();
-            if let Some(name) = args.name {
-                if let Some(bdev) = core::UntypedBdev::lookup_by_name(&name) {
-                    bdevs.push(bdev.into());
-                }
-            } else if let Some(bdev) = core::UntypedBdev::bdev_first() {
-                bdev.into_iter().for_each(|bdev| bdevs.push(bdev.into()))
-            }
-
-            Ok(ListBdevResponse {
-                bdevs,
-            })
-        })?;
-
-        rx.await
-            .map_err(|_| Status::cancelled("cancelled"))?
-            .map_err(Status::from)
-            .map(Response::new)
+        let args = request.into_inner();
+        let bdevs = if let Some(name) = args.name {
+            self.backend.lookup(name).await?.into_iter().collect()
+        } else {
+            self.backend.list().await?
+        };
+
+        Ok(Response::new(ListBdevResponse {
+            bdevs: bdevs.into_iter().map(Bdev::from).collect(),
+        }))
     }
 
     #[tracing::instrument(skip(self))]
@@ -90,25 +113,22 @@ impl BdevRpc for BdevService {
         &self,
         request: Request<CreateBdevRequest>,
     ) -> Result<Response<CreateBdevResponse>, Status> {
-        let uri = request.into_inner().uri;
-
-        let rx = rpc_submit(async move {
-            let name = bdev_create(&uri).await?;
+        let ctx = GrpcClientContext::new(&request, "create_bdev");
+        audit::audited(ctx, async move {
+            let uri = request.into_inner().uri;
+            let name = self.backend.create(uri).await?;
 
-            if let Some(bdev) = core::UntypedBdev::lookup_by_name(&name) {
-                Ok(Response::new(CreateBdevResponse {
+            match self.backend.lookup(name.clone()).await? {
+                Some(bdev) => Ok(Response::new(CreateBdevResponse {
                     bdev: Some(bdev.into()),
-                }))
-            } else {
-                Err(NexusBdevError::BdevNotFound {
+                })),
+                None => Err(NexusBdevError::BdevNotFound {
                     name,
-                })
+                }
+                .into()),
             }
-        })?;
-
-        rx.await
-            .map_err(|_| Status::cancelled("cancelled"))?
-            .map_err(Status::from)
+        })
+        .await
     }
 
     #[tracing::instrument(skip(self))]
@@ -116,14 +136,13 @@ impl BdevRpc for BdevService {
         &self,
         request: Request<DestroyBdevRequest>,
     ) -> GrpcResult<()> {
-        let uri = request.into_inner().uri;
-
-        let rx = rpc_submit(async move { bdev_destroy(&uri).await })?;
-
-        rx.await
-            .map_err(|_| Status::cancelled("cancelled"))?
-            .map_err(Status::from)
-            .map(|_| Ok(Response::new(())))?
+        let ctx = GrpcClientContext::new(&request, "destroy_bdev");
+        audit::audited(ctx, async move {
+            let uri = request.into_inner().uri;
+            self.backend.destroy(uri).await?;
+            Ok(Response::new(()))
+        })
+        .await
     }
 
     #[tracing::instrument(skip(self))]
@@ -131,42 +150,29 @@ impl BdevRpc for BdevService {
         &self,
         request: Request<BdevShareRequest>,
     ) -> Result<Response<BdevShareResponse>, Status> {
-        let r = request.into_inner();
-        let name = r.name;
-        let protocol = r.protocol;
-
-        if core::UntypedBdev::lookup_by_name(&name).is_none() {
-            return Err(Status::not_found(name));
-        }
-
-        let bdev_name = name.clone();
-        let rx = match Protocol::try_from(protocol) {
-            Ok(Protocol::Nvmf) => {
-                rpc_submit::<_, Bdev, CoreError>(async move {
-                    let mut bdev =
-                        core::UntypedBdev::lookup_by_name(&bdev_name).unwrap();
-                    Pin::new(&mut bdev).share_nvmf(None).await?;
-                    let bdev =
-                        core::UntypedBdev::lookup_by_name(&name).unwrap();
-                    Ok(bdev.into())
-                })
-            }
-
-            Err(_) => {
-                return Err(Status::invalid_argument(protocol.to_string()))
+        let ctx = GrpcClientContext::new(&request, "share_bdev");
+        audit::audited(ctx, async move {
+            let r = request.into_inner();
+            let name = r.name;
+            let protocol = r.protocol;
+
+            if self.backend.lookup(name.clone()).await?.is_none() {
+                return Err(Status::not_found(name));
             }
 
-            _ => unreachable!(),
-        }?;
-
-        rx.await
-            .map_err(|_| Status::cancelled("cancelled"))?
-            .map_err(|e| Status::internal(e.to_string()))
-            .map(|bdev| {
-                Ok(Response::new(BdevShareResponse {
-                    bdev: Some(bdev),
-                }))
-            })?
+            let bdev = match Protocol::try_from(protocol) {
+                Ok(Protocol::Nvmf) => self.backend.share_nvmf(name).await?,
+                Err(_) => {
+                    return Err(Status::invalid_argument(protocol.to_string()))
+                }
+                _ => unreachable!(),
+            };
+
+            Ok(Response::new(BdevShareResponse {
+                bdev: Some(bdev.into()),
+            }))
+        })
+        .await
     }
 
     #[tracing::instrument(skip(self))]
@@ -174,17 +180,56 @@ impl BdevRpc for BdevService {
         &self,
         request: Request<BdevUnshareRequest>,
     ) -> GrpcResult<()> {
-        let rx = rpc_submit::<_, _, CoreError>(async {
+        let ctx = GrpcClientContext::new(&request, "unshare_bdev");
+        audit::audited(ctx, async move {
             let name = request.into_inner().name;
-            if let Some(mut bdev) = core::UntypedBdev::lookup_by_name(&name) {
-                let _ = Pin::new(&mut bdev).unshare().await?;
-            }
-            Ok(())
-        })?;
+            self.backend.unshare(name).await?;
+            Ok(Response::new(()))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc::bdev_backend::mock::MockBdevs;
+
+    #[tokio::test]
+    async fn create_and_list_by_name() {
+        let svc = BdevService::with_backend(MockBdevs::default());
+        for name in &["disk0", "disk1"] {
+            let bdev = svc
+                .create(Request::new(CreateBdevRequest {
+                    uri: format!("malloc:///{}?size_mb=8", name),
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+                .bdev
+                .unwrap();
+            assert_eq!(&bdev.name, name);
+        }
 
-        rx.await
-            .map_err(|_| Status::cancelled("cancelled"))?
-            .map_err(Status::from)
-            .map(Response::new)
+        let bdevs = svc
+            .list(Request::new(ListBdevOptions {
+                name: Some("disk1".into()),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .bdevs;
+        assert_eq!(bdevs.len(), 1);
+        assert_eq!(bdevs[0].name, "disk1");
+
+        let bdevs = svc
+            .list(Request::new(ListBdevOptions {
+                name: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .bdevs;
+        assert_eq!(bdevs.len(), 2);
     }
 }
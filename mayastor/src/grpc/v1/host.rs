@@ -2,6 +2,7 @@ use crate::{
     bdev::{nexus, NvmeControllerState},
     core::{BlockDeviceIoStats, CoreError, MayastorFeatures},
     grpc::{
+        audit,
         controller_grpc::{
             controller_stats,
             list_controllers,
@@ -59,7 +60,7 @@ where
         // Request completed, remove the marker.
         let ctx = context_guard.take().expect("gRPC context disappeared");
 
-        match r {
+        let result = match r {
             Ok(r) => r,
             Err(_e) => {
                 warn!("{}: gRPC method panicked, args: {}", ctx.id, ctx.args);
@@ -68,7 +69,10 @@ where
                     ctx.id
                 )))
             }
-        }
+        };
+
+        audit::record(&ctx, &result);
+        result
     }
 }
 
@@ -1,10 +1,16 @@
 use crate::{
     bdev::{
         nexus,
-        nexus::{nexus_lookup_uuid_mut, NexusChild, NexusStatus, Reason},
+        nexus::{
+            nexus_lookup_mut,
+            nexus_lookup_uuid_mut,
+            NexusChild,
+            NexusStatus,
+            Reason,
+        },
     },
     core::{Protocol, Share},
-    grpc::{rpc_submit, GrpcClientContext, GrpcResult, Serializer},
+    grpc::{audit, rpc_submit, GrpcClientContext, GrpcResult, Serializer},
     rebuild::{RebuildJob, RebuildState, RebuildStats},
 };
 use futures::FutureExt;
@@ -55,7 +61,7 @@ where
         // Request completed, remove the marker.
         let ctx = context_guard.take().expect("gRPC context disappeared");
 
-        match r {
+        let result = match r {
             Ok(r) => r,
             Err(_e) => {
                 warn!("{}: gRPC method panicked, args: {}", ctx.id, ctx.args);
@@ -64,7 +70,10 @@ where
                     ctx.id
                 )))
             }
-        }
+        };
+
+        audit::record(&ctx, &result);
+        result
     }
 }
 
@@ -104,7 +113,9 @@ impl From<nexus::ChildState> for ChildState {
             nexus::ChildState::Destroying => ChildState::ChildDegraded,
             nexus::ChildState::Closed => ChildState::ChildDegraded,
             nexus::ChildState::Faulted(reason) => match reason {
-                Reason::OutOfSync => ChildState::ChildDegraded,
+                Reason::OutOfSync | Reason::Standby => {
+                    ChildState::ChildDegraded
+                }
                 _ => ChildState::ChildFaulted,
             },
         }
@@ -143,11 +154,13 @@ impl From<RebuildStats> for RebuildStatsResponse {
     }
 }
 
-/// Look up a nexus by uuid
+/// Look up a nexus by uuid, or by name for clients which only know that
 pub fn nexus_lookup<'n>(
     uuid: &str,
 ) -> Result<Pin<&'n mut nexus::Nexus<'n>>, nexus::Error> {
-    if let Some(nexus) = nexus_lookup_uuid_mut(uuid) {
+    if let Some(nexus) =
+        nexus_lookup_uuid_mut(uuid).or_else(|| nexus_lookup_mut(uuid))
+    {
         Ok(nexus)
     } else {
         Err(nexus::Error::NexusNotFound {
@@ -159,6 +172,7 @@ pub fn nexus_lookup<'n>(
 /// Destruction of the nexus. Returns NotFound error for invalid uuid.
 pub async fn nexus_destroy(uuid: &str) -> Result<(), nexus::Error> {
     let n = nexus_lookup(uuid)?;
+    n.check_protected(false)?;
     n.destroy().await
 }
 
@@ -468,7 +482,9 @@ impl NexusRpc for NexusService {
             trace!("{:?}", args);
             let uuid = args.uuid.clone();
             debug!("Unpublishing nexus {} ...", uuid);
-            nexus_lookup(&args.uuid)?.unshare_nexus().await?;
+            nexus_lookup(&args.uuid)?
+                .unshare_nexus_drained(nexus::DrainOpts::default())
+                .await?;
             info!("Unpublished nexus {}", uuid);
             Ok(nexus_lookup(&args.uuid)?.into_grpc().await)
         })?;
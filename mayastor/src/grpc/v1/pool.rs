@@ -1,6 +1,6 @@
 use crate::{
     core::Share,
-    grpc::{rpc_submit, GrpcClientContext, GrpcResult, Serializer},
+    grpc::{audit, rpc_submit, GrpcClientContext, GrpcResult, Serializer},
     lvs::{Error as LvsError, Lvs},
     pool::{PoolArgs, PoolBackend},
 };
@@ -53,7 +53,7 @@ where
         // Request completed, remove the marker.
         let ctx = context_guard.take().expect("gRPC context disappeared");
 
-        match r {
+        let result = match r {
             Ok(r) => r,
             Err(_e) => {
                 warn!("{}: gRPC method panicked, args: {}", ctx.id, ctx.args);
@@ -62,7 +62,10 @@ where
                     ctx.id
                 )))
             }
-        }
+        };
+
+        audit::record(&ctx, &result);
+        result
     }
 }
 
@@ -89,6 +92,8 @@ impl TryFrom<CreatePoolRequest> for PoolArgs {
             name: args.name,
             disks: args.disks,
             uuid: args.uuid,
+            labels: Default::default(),
+            cluster_size: None,
         })
     }
 }
@@ -116,6 +121,8 @@ impl TryFrom<ImportPoolRequest> for PoolArgs {
             name: args.name,
             disks: args.disks,
             uuid: args.uuid,
+            labels: Default::default(),
+            cluster_size: None,
         })
     }
 }
@@ -205,6 +212,7 @@ impl PoolRpc for PoolService {
                                 ),
                             });
                         }
+                        pool.check_protected().await?;
                         pool.destroy().await?;
                     } else {
                         return Err(LvsError::Invalid {
@@ -1,6 +1,6 @@
 use crate::{
     core::{Bdev, Protocol, Share, UntypedBdev},
-    grpc::{rpc_submit, GrpcClientContext, GrpcResult, Serializer},
+    grpc::{audit, rpc_submit, GrpcClientContext, GrpcResult, Serializer},
     lvs::{Error as LvsError, Lvol, Lvs},
     nexus_uri::NexusBdevError,
 };
@@ -46,7 +46,7 @@ where
         // Request completed, remove the marker.
         let ctx = context_guard.take().expect("gRPC context disappeared");
 
-        match r {
+        let result = match r {
             Ok(r) => r,
             Err(_e) => {
                 warn!("{}: gRPC method panicked, args: {}", ctx.id, ctx.args);
@@ -55,7 +55,10 @@ where
                     ctx.id
                 )))
             }
-        }
+        };
+
+        audit::record(&ctx, &result);
+        result
     }
 }
 
@@ -163,9 +166,10 @@ impl ReplicaRpc for ReplicaService {
             let args = request.into_inner();
             info!("{:?}", args);
             let rx = rpc_submit::<_, _, LvsError>(async move {
-                if let Some(b) = Bdev::lookup_by_uuid_str(&args.uuid) {
+                if let Some(b) = Bdev::lookup_by_name_or_uuid(&args.uuid) {
                     return if b.driver() == "lvol" {
                         let lvol = Lvol::try_from(b)?;
+                        lvol.check_protected(false).await?;
                         lvol.destroy().await?;
                         Ok(())
                     } else {
@@ -251,7 +255,7 @@ impl ReplicaRpc for ReplicaService {
                 let args = request.into_inner();
                 info!("{:?}", args);
                 let rx = rpc_submit(async move {
-                    match Bdev::lookup_by_uuid_str(&args.uuid) {
+                    match Bdev::lookup_by_name_or_uuid(&args.uuid) {
                         Some(bdev) => {
                             let mut lvol = Lvol::try_from(bdev)?;
 
@@ -309,7 +313,7 @@ impl ReplicaRpc for ReplicaService {
                 let args = request.into_inner();
                 info!("{:?}", args);
                 let rx = rpc_submit(async move {
-                    match Bdev::lookup_by_uuid_str(&args.uuid) {
+                    match Bdev::lookup_by_name_or_uuid(&args.uuid) {
                         Some(bdev) => {
                             let mut lvol = Lvol::try_from(bdev)?;
                             if lvol.shared().is_some() {
@@ -0,0 +1,203 @@
+//!
+//! Validation and normalization of the names, uuids and child URIs of the
+//! gRPC API.
+//!
+//! Names and URIs used to be passed on as given, so a name SPDK could not
+//! hold failed deep in the creation of an object with an error that did not
+//! say what was wrong with it, and the same child given as
+//! `nvmf://Host:8420/nqn` and `nvmf://host:8420/nqn/` was taken for two
+//! different children. The arguments of the calls creating objects are
+//! checked before they are submitted, failing with `INVALID_ARGUMENT` and a
+//! message naming the argument and the rule it breaks, and normalized:
+//!
+//! - names are 1 to `max_len` characters of `[A-Za-z0-9._:-]`, not starting
+//!   with `-`, the limit being that of the SPDK object they name
+//! - uuids are returned in their lower case hyphenated form
+//! - child URIs have their scheme and host in lower case and no trailing `/`
+
+use std::collections::HashSet;
+
+use tonic::Status;
+use url::Url;
+use uuid::Uuid;
+
+/// What a name names, which sets its length limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Nexus,
+    Pool,
+    Replica,
+}
+
+impl Kind {
+    /// the longest name of the kind, the pool and replica names being the
+    /// names of the lvol stores and lvols of SPDK, and the nexus names being
+    /// part of the NQN of the nexus when shared
+    fn max_len(self) -> usize {
+        match self {
+            Kind::Nexus => 200,
+            Kind::Pool | Kind::Replica => 63,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Nexus => "nexus",
+            Kind::Pool => "pool",
+            Kind::Replica => "replica",
+        }
+    }
+}
+
+/// Check the name of an object of the given kind.
+pub fn name(kind: Kind, name: &str) -> Result<(), Status> {
+    let what = kind.as_str();
+    if name.is_empty() {
+        return Err(Status::invalid_argument(format!(
+            "{} name is empty",
+            what
+        )));
+    }
+    if name.len() > kind.max_len() {
+        return Err(Status::invalid_argument(format!(
+            "{} name {} is {} characters long, more than {}",
+            what,
+            name,
+            name.len(),
+            kind.max_len()
+        )));
+    }
+    if name.starts_with('-') {
+        return Err(Status::invalid_argument(format!(
+            "{} name {} starts with '-'",
+            what, name
+        )));
+    }
+    if let Some((pos, c)) = name.char_indices().find(|(_, c)| {
+        !(c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | ':' | '-'))
+    }) {
+        return Err(Status::invalid_argument(format!(
+            "{} name {:?} has the character {:?} at {}, only letters, \
+             digits, '.', '_', ':' and '-' are allowed",
+            what, name, c, pos
+        )));
+    }
+    Ok(())
+}
+
+/// Check a uuid, returning its lower case hyphenated form.
+pub fn uuid(what: &str, uuid: &str) -> Result<String, Status> {
+    Uuid::parse_str(uuid)
+        .map(|u| u.to_hyphenated().to_string())
+        .map_err(|e| {
+            Status::invalid_argument(format!(
+                "{} uuid {:?} is invalid: {}",
+                what, uuid, e
+            ))
+        })
+}
+
+/// Check a child URI, returning it with its scheme and host in lower case
+/// and without trailing `/`.
+pub fn child_uri(uri: &str) -> Result<String, Status> {
+    if uri.is_empty() {
+        return Err(Status::invalid_argument("child URI is empty"));
+    }
+    if let Some(c) = uri.chars().find(|c| c.is_whitespace() || c.is_control()) {
+        return Err(Status::invalid_argument(format!(
+            "child URI {:?} has the character {:?}",
+            uri, c
+        )));
+    }
+    let mut url = Url::parse(uri).map_err(|e| {
+        Status::invalid_argument(format!(
+            "child URI {:?} is invalid: {}",
+            uri, e
+        ))
+    })?;
+
+    // the host of schemes url does not know of is kept as given
+    if let Some(host) = url
+        .host_str()
+        .filter(|h| h.chars().any(|c| c.is_ascii_uppercase()))
+    {
+        let host = host.to_lowercase();
+        url.set_host(Some(&host)).map_err(|e| {
+            Status::invalid_argument(format!(
+                "child URI {:?} has an invalid host: {}",
+                uri, e
+            ))
+        })?;
+    }
+    let path = url.path();
+    if path.len() > 1 && path.ends_with('/') {
+        let path = path.trim_end_matches('/').to_string();
+        url.set_path(if path.is_empty() { "/" } else { &path });
+    }
+    Ok(url.to_string())
+}
+
+/// Check the URIs of the children of a nexus, returning them normalized. The
+/// same child given twice is rejected.
+pub fn child_uris(uris: &[String]) -> Result<Vec<String>, Status> {
+    let mut seen = HashSet::new();
+    uris.iter()
+        .map(|uri| {
+            let normalized = child_uri(uri)?;
+            if !seen.insert(normalized.clone()) {
+                return Err(Status::invalid_argument(format!(
+                    "child URI {:?} is given more than once",
+                    uri
+                )));
+            }
+            Ok(normalized)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{child_uri, child_uris, name, uuid, Kind};
+
+    #[test]
+    fn names() {
+        assert!(name(Kind::Pool, "pool-1").is_ok());
+        assert!(name(Kind::Nexus, "nexus-a.b_c:d").is_ok());
+        assert!(name(Kind::Pool, "").is_err());
+        assert!(name(Kind::Replica, "-r").is_err());
+        assert!(name(Kind::Replica, "r/1").is_err());
+        assert!(name(Kind::Replica, "r 1").is_err());
+        assert!(name(Kind::Replica, &"r".repeat(64)).is_err());
+        assert!(name(Kind::Nexus, &"n".repeat(64)).is_ok());
+    }
+
+    #[test]
+    fn uuids() {
+        assert_eq!(
+            uuid("nexus", "D6A2B8E4-6D6A-4B1A-9A4C-1F1E1D1C1B1A").unwrap(),
+            "d6a2b8e4-6d6a-4b1a-9a4c-1f1e1d1c1b1a"
+        );
+        assert!(uuid("nexus", "not-a-uuid").is_err());
+    }
+
+    #[test]
+    fn uris() {
+        assert_eq!(
+            child_uri("NVMF://Host-1:8420/nqn.2019-05.io.openebs:r1/").unwrap(),
+            "nvmf://host-1:8420/nqn.2019-05.io.openebs:r1"
+        );
+        assert_eq!(
+            child_uri("malloc:///m0?size_mb=64").unwrap(),
+            "malloc:///m0?size_mb=64"
+        );
+        assert_eq!(child_uri("aio:///dev/sdb//").unwrap(), "aio:///dev/sdb");
+        assert!(child_uri("").is_err());
+        assert!(child_uri("aio:///dev/sd b").is_err());
+        assert!(child_uri("/dev/sdb").is_err());
+        assert!(child_uris(&[
+            "aio:///dev/sdb".to_string(),
+            "AIO:///dev/sdb/".to_string()
+        ])
+        .is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//!
+//! Handing the pools and shares over to a new mayastor on the same node.
+//!
+//! Restarting mayastor to upgrade it leaves the replicas it shares
+//! unavailable from the moment the old process stops until the new one has
+//! set up SPDK, its devices and the target, and imported the pools again. A
+//! mayastor started with `--handoff-from <rpc socket of the old one>` does
+//! all but the import while the old one still serves IO, and then takes the
+//! pools over in a single switchover:
+//!
+//! - standby: the new mayastor initializes without listening on the NVMe-oF
+//!   addresses, which the old one holds, gets the pool configuration of the old
+//!   one with `handoff_prepare` and creates the devices of the pools. The pools
+//!   themselves are not loaded, as loading a pool writes to it and a pool is
+//!   only ever loaded by one process
+//! - release: with `handoff_release`, the old mayastor makes its published
+//!   nexuses inaccessible through ANA, so that the initiators with another path
+//!   move their IO to it, exports its pools, which unshares their replicas and
+//!   writes their metadata, and stops listening
+//! - take over: the new mayastor listens on the addresses and imports the
+//!   pools, which shares the replicas that were shared again, before it has the
+//!   old one exit with `handoff_complete`
+//!
+//! IO to the replicas is only interrupted from the release to the end of
+//! the import, which the initiators ride through by reconnecting. Nexuses
+//! are recreated on the new mayastor by the control plane, as after any
+//! restart. The new mayastor is given an rpc socket and a gRPC endpoint of
+//! its own, the ones it registers with the control plane.
+//!
+//! If the handoff fails before the release, the new mayastor exits and the
+//! old one goes on as if nothing happened. If it fails after, the new
+//! mayastor starts as any other, with the pools of its pool configuration.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::FutureExt;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    bdev::nexus::{nexus_iter, nexus_lookup, NvmeAnaState},
+    core::{mayastor_env_stop, Reactors},
+    jsonrpc::{jsonrpc_register, Code, JsonRpcError},
+    lvs::Lvs,
+    startup,
+    subsys::{NvmfTarget, PoolConfig},
+};
+
+/// set while this mayastor waits to take the pools over
+static STANDBY: AtomicBool = AtomicBool::new(false);
+
+/// set once this mayastor released its pools to another one
+static RELEASED: AtomicBool = AtomicBool::new(false);
+
+/// returns true if this mayastor waits to take the pools over from another
+pub fn is_standby() -> bool {
+    STANDBY.load(Ordering::SeqCst)
+}
+
+/// returns true if this mayastor released its pools to another one
+pub fn is_released() -> bool {
+    RELEASED.load(Ordering::SeqCst)
+}
+
+/// Record that this mayastor takes the pools over from another one once
+/// initialized.
+pub(crate) fn standby() {
+    STANDBY.store(true, Ordering::SeqCst);
+    startup::standby();
+}
+
+/// call the json-rpc method of the mayastor handing the pools over
+fn call<R>(from: &str, method: &str) -> Result<R, String>
+where
+    R: 'static + DeserializeOwned + Send,
+{
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| e.to_string())?
+        .block_on(jsonrpc::call::<(), R>(from, method, None))
+        .map_err(|e| format!("{} failed: {}", method, e))
+}
+
+/// Take the pools over from the mayastor listening on the rpc socket,
+/// returning the configuration of the pools to import. Exits if the pools
+/// could not be released.
+pub(crate) fn take_over(from: &str) -> Option<PoolConfig> {
+    info!("taking the pools over from the mayastor at {}", from);
+
+    let pools = call::<PoolConfig>(from, "handoff_prepare")
+        .map(|pools| {
+            pools.create_disks();
+            pools
+        })
+        .and_then(|_| call::<PoolConfig>(from, "handoff_release"));
+    let pools = match pools {
+        Ok(pools) => Some(pools),
+        Err(e) if !is_released_by(from) => {
+            error!("handoff from {} failed, exiting: {}", from, e);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            error!("handoff from {} failed after the release: {}", from, e);
+            None
+        }
+    };
+
+    STANDBY.store(false, Ordering::SeqCst);
+    if let Err(e) = NvmfTarget::resume_listening() {
+        error!("nvmf target failed to listen after the handoff: {}", e);
+    }
+    pools
+}
+
+/// returns true if the mayastor at the rpc socket released its pools, or
+/// is gone
+fn is_released_by(from: &str) -> bool {
+    call::<bool>(from, "handoff_released").unwrap_or(true)
+}
+
+/// Have the mayastor the pools were taken over from exit.
+pub(crate) fn complete(from: &str) {
+    match call::<()>(from, "handoff_complete") {
+        Ok(()) => info!("took the pools over from the mayastor at {}", from),
+        Err(e) => warn!("{}", e),
+    }
+}
+
+async fn prepare(_: ()) -> Result<PoolConfig, JsonRpcError> {
+    Ok(PoolConfig::capture())
+}
+
+async fn release(_: ()) -> Result<PoolConfig, JsonRpcError> {
+    if RELEASED.swap(true, Ordering::SeqCst) {
+        return Err(JsonRpcError::new(
+            Code::InvalidRequest,
+            "the pools were released already",
+        ));
+    }
+    let pools = PoolConfig::capture();
+    info!("releasing the pools to another mayastor");
+
+    let nexuses = nexus_iter().map(|n| n.name.clone()).collect::<Vec<_>>();
+    for name in nexuses {
+        if let Some(nexus) = nexus_lookup(&name) {
+            if let Err(e) =
+                nexus.set_ana_state(NvmeAnaState::InaccessibleState).await
+            {
+                debug!("{}: ANA state not changed: {}", name, e);
+            }
+        }
+    }
+    Lvs::export_all().await;
+    NvmfTarget::release_listeners();
+    Ok(pools)
+}
+
+async fn released(_: ()) -> Result<bool, JsonRpcError> {
+    Ok(is_released())
+}
+
+async fn complete_handoff(_: ()) -> Result<(), JsonRpcError> {
+    if !is_released() {
+        return Err(JsonRpcError::new(
+            Code::InvalidRequest,
+            "the pools were not released",
+        ));
+    }
+    info!("pools handed over, exiting");
+    // reply before shutting down
+    Reactors::master().send_future(async {
+        mayastor_env_stop(0);
+    });
+    Ok(())
+}
+
+/// Register the json-rpc methods handing the pools over to another mayastor.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, JsonRpcError>("handoff_prepare", |args: ()| {
+        prepare(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, JsonRpcError>("handoff_release", |args: ()| {
+        release(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, JsonRpcError>(
+        "handoff_released",
+        |args: ()| released(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, JsonRpcError>(
+        "handoff_complete",
+        |args: ()| complete_handoff(args).boxed_local(),
+    );
+}
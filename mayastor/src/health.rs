@@ -0,0 +1,246 @@
+//!
+//! Liveness and readiness probes.
+//!
+//! Whether the gRPC port of mayastor accepts connections says little about
+//! the state of mayastor: it accepts them while the pools are still being
+//! imported, and keeps accepting them when a reactor is wedged. When
+//! `--health-endpoint` is given, a plain HTTP server answers the probes of
+//! Kubernetes from the internal state of mayastor instead:
+//!
+//! ```text
+//! GET /livez   all reactors are polling
+//! GET /readyz  mayastor is live, it restored its pools and the NVMe-oF
+//!              target accepts connections
+//! ```
+//!
+//! A probe returns 200 when all its checks pass and 503 otherwise, with the
+//! checks in a JSON body. A reactor is wedged when it has not polled for
+//! [`STALL_TIMEOUT`]. The server runs on a thread of its own, started before
+//! the pools are imported, so that the probes are answered during a long
+//! startup; a startup probe should cover the time it takes.
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use http::{header::CONTENT_TYPE, StatusCode};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body,
+    Request,
+    Response,
+    Server,
+};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::{
+    core::{ReactorState, REACTOR_LIST},
+    startup::{startup_status, RestoreState, StartupPhase},
+    subsys::target_accepting,
+};
+
+/// time a reactor may go without polling before it is deemed wedged
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A check of a probe.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, failure: Option<String>) -> Self {
+        Self {
+            name: name.into(),
+            ok: failure.is_none(),
+            message: failure,
+        }
+    }
+}
+
+/// Result of a probe.
+#[derive(Debug, Clone, Serialize)]
+pub struct Probe {
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Probe {
+    fn new(checks: Vec<Check>) -> Self {
+        Self {
+            ok: checks.iter().all(|c| c.ok),
+            checks,
+        }
+    }
+}
+
+/// Number of polls of a reactor at the last probe and when it last changed.
+static POLLS: Lazy<Mutex<Vec<(u64, Instant)>>> =
+    Lazy::new(|| Mutex::new(Vec::new()));
+
+/// check the reactors polled since the previous probe, or within the stall
+/// timeout
+fn reactor_checks(now: Instant) -> Vec<Check> {
+    let reactors = match REACTOR_LIST.get() {
+        Some(reactors) => reactors,
+        // not started yet
+        None => return Vec::new(),
+    };
+
+    let mut polls = POLLS.lock().unwrap();
+    reactors
+        .into_iter()
+        .enumerate()
+        .map(|(i, reactor)| {
+            let count = reactor.polls();
+            if polls.len() <= i {
+                polls.push((count, now));
+            }
+            let (last, changed) = &mut polls[i];
+            if *last != count {
+                *last = count;
+                *changed = now;
+            }
+
+            let name = format!("reactor {}", reactor.core());
+            let failure = match reactor.get_state() {
+                ReactorState::Shutdown => Some("shut down".to_string()),
+                // not polling yet
+                ReactorState::Init => None,
+                _ if now.duration_since(*changed) > STALL_TIMEOUT => {
+                    Some(format!(
+                        "not polled for {}s",
+                        now.duration_since(*changed).as_secs()
+                    ))
+                }
+                _ => None,
+            };
+            Check::new(name, failure)
+        })
+        .collect()
+}
+
+/// returns the result of the liveness probe
+pub fn liveness() -> Probe {
+    Probe::new(reactor_checks(Instant::now()))
+}
+
+/// returns the result of the readiness probe
+pub fn readiness() -> Probe {
+    let mut checks = reactor_checks(Instant::now());
+
+    let startup = startup_status();
+    let count =
+        |state| startup.objects.iter().filter(|o| o.state == state).count();
+    checks.push(Check::new(
+        "pools",
+        match startup.phase {
+            StartupPhase::Ready => None,
+            phase => Some(format!(
+                "{:?}, {} of {} restored, {} failed",
+                phase,
+                count(RestoreState::Restored),
+                startup.objects.len(),
+                count(RestoreState::Failed)
+            )),
+        },
+    ));
+    checks.push(Check::new(
+        "nvmf target",
+        if target_accepting() {
+            None
+        } else {
+            Some("not accepting connections".to_string())
+        },
+    ));
+    Probe::new(checks)
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let probe = match req.uri().path() {
+        "/livez" => Some(liveness()),
+        "/readyz" => Some(readiness()),
+        _ => None,
+    };
+    let (status, body) = match probe {
+        Some(probe) if probe.ok => {
+            (StatusCode::OK, serde_json::to_vec(&probe).unwrap())
+        }
+        Some(probe) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            serde_json::to_vec(&probe).unwrap(),
+        ),
+        None => (StatusCode::NOT_FOUND, b"{}".to_vec()),
+    };
+
+    Ok(Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap())
+}
+
+/// Parse the endpoint of the probes, ip:port.
+pub fn endpoint(endpoint: String) -> SocketAddr {
+    endpoint.parse().expect("Invalid health endpoint")
+}
+
+/// serve the probes on the endpoint
+async fn run(endpoint: SocketAddr) {
+    let service =
+        make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+
+    match Server::try_bind(&endpoint) {
+        Ok(server) => {
+            info!("health probes served at address {}", endpoint);
+            if let Err(e) = server.serve(service).await {
+                error!("health probe server failed with error: {}", e);
+            }
+        }
+        Err(e) => {
+            error!("health probe server failed to bind {}: {}", endpoint, e)
+        }
+    }
+}
+
+/// Serve the probes on the endpoint from a thread of their own.
+pub fn start(endpoint: SocketAddr) {
+    std::thread::Builder::new()
+        .name("health".to_string())
+        .spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(run(endpoint))
+        })
+        .expect("failed to start the health probe thread");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn probe() {
+        let probe = Probe::new(vec![
+            Check::new("reactor 0", None),
+            Check::new("pools", Some("Restoring".to_string())),
+        ]);
+        assert!(!probe.ok);
+        let json = serde_json::to_value(&probe).unwrap();
+        assert_eq!(
+            json["checks"][0],
+            serde_json::json!({"name": "reactor 0", "ok": true})
+        );
+        assert_eq!(json["checks"][1]["message"], "Restoring");
+        assert!(Probe::new(Vec::new()).ok);
+    }
+}
@@ -0,0 +1,203 @@
+//! The key manager hands out the keys used to encrypt data, which are kept
+//! by a key management service (KMS) so that they never live in the config
+//! file.
+//!
+//! Wherever a key is expected, a reference to a key of the KMS can be given
+//! instead:
+//!
+//! - `kms://<id>` the latest version of a data key
+//! - `kms://<id>?version=<n>` the given version of a data key
+//! - `kms://<id>?wrapped=<hex>` a wrapped data key, unwrapped with the key
+//!   encryption key of that id
+//!
+//! The KMS is reached through the [`Kms`] trait. An HTTP implementation is
+//! used when an endpoint is configured, other implementations can be
+//! installed with [`KeyManager::init_with`]. Like the persistent store, the
+//! KMS clients run on the tokio runtime.
+use crate::{
+    core,
+    kms::{
+        http::HttpKms,
+        kms_defs::{DataKey, Kms, KmsError, OpWait},
+    },
+};
+use once_cell::sync::OnceCell;
+use snafu::ResultExt;
+use std::{future::Future, sync::Arc, time::Duration};
+use zeroize::Zeroizing;
+
+static KMS_OP_TIMEOUT: Duration = Duration::from_secs(30);
+static KEY_MANAGER: OnceCell<KeyManager> = OnceCell::new();
+
+/// Prefix of references to keys of the KMS.
+const KEY_REF_PREFIX: &str = "kms://";
+
+/// Key manager
+pub struct KeyManager {
+    kms: Arc<dyn Kms>,
+}
+
+/// Parsed reference to a key of the KMS.
+#[derive(Debug, PartialEq)]
+enum KeyRef {
+    Fetch { id: String, version: Option<u64> },
+    Unwrap { id: String, wrapped: String },
+}
+
+impl KeyRef {
+    fn parse(key_ref: &str) -> Result<Self, KmsError> {
+        let invalid = |reason: &str| KmsError::InvalidKeyRef {
+            key_ref: key_ref.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let rest = key_ref
+            .strip_prefix(KEY_REF_PREFIX)
+            .ok_or_else(|| invalid("not a kms:// reference"))?;
+        let (id, query) = match rest.split_once('?') {
+            Some((id, query)) => (id, Some(query)),
+            None => (rest, None),
+        };
+        if id.is_empty() {
+            return Err(invalid("missing key id"));
+        }
+
+        match query.map(|q| q.split_once('=')) {
+            None => Ok(Self::Fetch {
+                id: id.to_string(),
+                version: None,
+            }),
+            Some(Some(("version", v))) => Ok(Self::Fetch {
+                id: id.to_string(),
+                version: Some(v.parse().map_err(|_| invalid("bad version"))?),
+            }),
+            Some(Some(("wrapped", w))) if !w.is_empty() => Ok(Self::Unwrap {
+                id: id.to_string(),
+                wrapped: w.to_string(),
+            }),
+            _ => Err(invalid("unknown query")),
+        }
+    }
+}
+
+impl KeyManager {
+    /// Initialise the key manager with an HTTP KMS.
+    /// If the supplied endpoint is 'None', no KMS is used and only plain keys
+    /// are accepted.
+    pub fn init(
+        endpoint: Option<String>,
+        token_file: Option<String>,
+    ) -> Result<(), KmsError> {
+        match endpoint {
+            Some(endpoint) => {
+                info!("Using KMS on endpoint {}", endpoint);
+                Self::init_with(Arc::new(HttpKms::new(&endpoint, token_file)?));
+            }
+            None => debug!("No KMS configured"),
+        }
+        Ok(())
+    }
+
+    /// Initialise the key manager with the given KMS implementation.
+    pub fn init_with(kms: Arc<dyn Kms>) {
+        if KEY_MANAGER
+            .set(KeyManager {
+                kms,
+            })
+            .is_err()
+        {
+            warn!("Key manager already initialised");
+        }
+    }
+
+    /// Determine if a KMS has been configured.
+    pub fn enabled() -> bool {
+        KEY_MANAGER.get().is_some()
+    }
+
+    /// Returns true if the given key is a reference to a key of the KMS.
+    pub fn is_key_ref(key: &str) -> bool {
+        key.starts_with(KEY_REF_PREFIX)
+    }
+
+    /// Resolve a key that may be a reference to a key of the KMS into the
+    /// hex encoded key material. Keys that are not references are returned
+    /// as they are.
+    pub async fn resolve(key: &str) -> Result<Zeroizing<String>, KmsError> {
+        if !Self::is_key_ref(key) {
+            return Ok(Zeroizing::new(key.to_string()));
+        }
+
+        let key = match KeyRef::parse(key)? {
+            KeyRef::Fetch {
+                id,
+                version,
+            } => Self::fetch(&id, version).await?,
+            KeyRef::Unwrap {
+                id,
+                wrapped,
+            } => Self::unwrap(&id, &wrapped).await?,
+        };
+        Ok(Zeroizing::new(key.expose().to_string()))
+    }
+
+    /// Fetch a data encryption key from the KMS.
+    pub async fn fetch(
+        key_id: &str,
+        version: Option<u64>,
+    ) -> Result<DataKey, KmsError> {
+        let kms = Self::kms()?;
+        let id = key_id.to_string();
+        Self::execute_op(key_id, async move { kms.fetch(&id, version).await })
+            .await
+    }
+
+    /// Create a new version of a key of the KMS.
+    pub async fn rotate(key_id: &str) -> Result<u64, KmsError> {
+        let kms = Self::kms()?;
+        let id = key_id.to_string();
+        let version =
+            Self::execute_op(key_id, async move { kms.rotate(&id).await })
+                .await?;
+        info!("Rotated key {} to version {}", key_id, version);
+        Ok(version)
+    }
+
+    /// Unwrap a data encryption key with a key of the KMS.
+    pub async fn unwrap(
+        key_id: &str,
+        wrapped: &str,
+    ) -> Result<DataKey, KmsError> {
+        let kms = Self::kms()?;
+        let id = key_id.to_string();
+        let wrapped = wrapped.to_string();
+        Self::execute_op(key_id, async move { kms.unwrap(&id, &wrapped).await })
+            .await
+    }
+
+    /// Get the configured KMS.
+    fn kms() -> Result<Arc<dyn Kms>, KmsError> {
+        KEY_MANAGER
+            .get()
+            .map(|m| m.kms.clone())
+            .ok_or(KmsError::NotConfigured {})
+    }
+
+    /// Executes a future representing a KMS operation on the tokio runtime
+    /// and waits for its result.
+    async fn execute_op<T: 'static + Send>(
+        key_id: &str,
+        f: impl Future<Output = Result<T, KmsError>> + Send + 'static,
+    ) -> Result<T, KmsError> {
+        let rx = core::runtime::spawn_await(async move {
+            match tokio::time::timeout(KMS_OP_TIMEOUT, f).await {
+                Ok(result) => result,
+                Err(_) => Err(KmsError::OpTimeout {}),
+            }
+        });
+
+        rx.await.context(OpWait {
+            key_id,
+        })?
+    }
+}
@@ -0,0 +1,224 @@
+//! Implementation of a KMS reached over HTTP.
+//!
+//! The service is expected to implement the following JSON API, where a
+//! key reply is `{"version": <n>, "key": "<hex>"}`:
+//!
+//! - `GET /v1/keys/<id>` returns the latest version of a data key
+//! - `GET /v1/keys/<id>/versions/<n>` returns the given version of it
+//! - `POST /v1/keys/<id>/rotate` creates a new version and replies with
+//!   `{"version": <n>}`
+//! - `POST /v1/keys/<id>/unwrap` with `{"wrapped": "<hex>"}` unwraps a data key
+//!   with the key encryption key of that id
+//!
+//! Requests carry the token read from the token file as a bearer token. The
+//! file is read for every request so that the token can be renewed without
+//! a restart. The token and the keys must not cross the network in clear,
+//! so the endpoint is either an `https://` one, whose certificate is checked
+//! against the root certificates of the host, or an `http://` one on a
+//! loopback address, such as a sidecar forwarding to the actual service.
+//!
+//! Key ids are percent-encoded into the path, and replies are read into a
+//! buffer that is zeroed once the key has been parsed out of it.
+
+use std::net::IpAddr;
+
+use crate::{
+    core::runtime::https_connector,
+    kms::kms_defs::{
+        BuildRequest,
+        DataKey,
+        InvalidReply,
+        Kms,
+        KmsError,
+        Request as RequestError,
+        Token,
+    },
+};
+use async_trait::async_trait;
+use hyper::{
+    body::HttpBody,
+    client::HttpConnector,
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    Body,
+    Client,
+    Method,
+    Request,
+};
+use hyper_rustls::HttpsConnector;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::de::DeserializeOwned;
+use snafu::ResultExt;
+use url::{Host, Url};
+use zeroize::Zeroizing;
+
+/// HTTP KMS client
+pub struct HttpKms {
+    client: Client<HttpsConnector<HttpConnector>>,
+    /// endpoint of the service, without a trailing slash
+    endpoint: String,
+    /// path of the file holding the token
+    token_file: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RotateReply {
+    version: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct UnwrapRequest<'a> {
+    wrapped: &'a str,
+}
+
+/// Check that requests to the endpoint are encrypted or do not leave the
+/// host.
+fn check_endpoint(endpoint: &str) -> Result<(), KmsError> {
+    let invalid = |reason: &str| KmsError::InvalidEndpoint {
+        endpoint: endpoint.to_string(),
+        reason: reason.to_string(),
+    };
+    let url = Url::parse(endpoint).map_err(|e| invalid(&e.to_string()))?;
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" => match url.host() {
+            Some(Host::Domain("localhost")) => Ok(()),
+            Some(Host::Ipv4(ip)) if IpAddr::V4(ip).is_loopback() => Ok(()),
+            Some(Host::Ipv6(ip)) if IpAddr::V6(ip).is_loopback() => Ok(()),
+            _ => Err(invalid("plain http is only allowed on loopback")),
+        },
+        _ => Err(invalid("the scheme must be https or http")),
+    }
+}
+
+impl HttpKms {
+    /// Create a new client of the service at the given endpoint.
+    pub fn new(
+        endpoint: &str,
+        token_file: Option<String>,
+    ) -> Result<Self, KmsError> {
+        check_endpoint(endpoint)?;
+        let connector =
+            https_connector().map_err(|e| KmsError::InvalidEndpoint {
+                endpoint: endpoint.to_string(),
+                reason: format!("failed to load root certificates: {}", e),
+            })?;
+        Ok(Self {
+            client: Client::builder().build(connector),
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            token_file,
+        })
+    }
+
+    /// Send a request about the given key and parse the reply.
+    async fn send<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        key_id: &str,
+        path: &str,
+        body: Option<String>,
+    ) -> Result<T, KmsError> {
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(format!(
+                "{}/v1/keys/{}{}",
+                self.endpoint,
+                utf8_percent_encode(key_id, NON_ALPHANUMERIC),
+                path
+            ))
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(path) = &self.token_file {
+            let token =
+                tokio::fs::read_to_string(path).await.context(Token {
+                    path: path.clone(),
+                })?;
+            builder = builder
+                .header(AUTHORIZATION, format!("Bearer {}", token.trim()));
+        }
+        let request = builder
+            .body(body.map(Body::from).unwrap_or_else(Body::empty))
+            .context(BuildRequest {
+                key_id,
+            })?;
+
+        let reply =
+            self.client.request(request).await.context(RequestError {
+                key_id,
+            })?;
+        if !reply.status().is_success() {
+            return Err(KmsError::Refused {
+                key_id: key_id.to_string(),
+                status: reply.status().as_u16(),
+            });
+        }
+
+        // the reply holds the key
+        let mut body = reply.into_body();
+        let mut bytes = Zeroizing::new(Vec::new());
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.context(RequestError {
+                key_id,
+            })?);
+        }
+        serde_json::from_slice(&bytes).context(InvalidReply {
+            key_id,
+        })
+    }
+}
+
+#[async_trait]
+impl Kms for HttpKms {
+    async fn fetch(
+        &self,
+        key_id: &str,
+        version: Option<u64>,
+    ) -> Result<DataKey, KmsError> {
+        let path = match version {
+            Some(v) => format!("/versions/{}", v),
+            None => String::new(),
+        };
+        self.send(Method::GET, key_id, &path, None).await
+    }
+
+    async fn rotate(&self, key_id: &str) -> Result<u64, KmsError> {
+        let reply: RotateReply =
+            self.send(Method::POST, key_id, "/rotate", None).await?;
+        Ok(reply.version)
+    }
+
+    async fn unwrap(
+        &self,
+        key_id: &str,
+        wrapped: &str,
+    ) -> Result<DataKey, KmsError> {
+        let body = serde_json::to_string(&UnwrapRequest {
+            wrapped,
+        })
+        .expect("Failed to serialise unwrap request");
+        self.send(Method::POST, key_id, "/unwrap", Some(body)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_endpoint;
+
+    #[test]
+    fn endpoints() {
+        assert!(check_endpoint("https://kms.example.com:8200").is_ok());
+        assert!(check_endpoint("http://127.0.0.1:8200").is_ok());
+        assert!(check_endpoint("http://localhost:8200/").is_ok());
+        assert!(check_endpoint("http://[::1]:8200").is_ok());
+        assert!(check_endpoint("http://kms.example.com:8200").is_err());
+        assert!(check_endpoint("http://10.0.0.1:8200").is_err());
+        assert!(check_endpoint("ftp://127.0.0.1").is_err());
+        assert!(check_endpoint("kms:8200").is_err());
+    }
+}
+
+impl std::fmt::Debug for HttpKms {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpKms")
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
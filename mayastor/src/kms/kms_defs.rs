@@ -0,0 +1,131 @@
+//! Definition of a trait for a key management service (KMS) together with
+//! its error codes.
+
+use async_trait::async_trait;
+use snafu::Snafu;
+use zeroize::Zeroizing;
+
+/// Definition of errors that can be returned by the KMS.
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub enum KmsError {
+    /// No KMS has been configured.
+    #[snafu(display("No key management service configured"))]
+    NotConfigured {},
+    /// The endpoint of the KMS is not usable.
+    #[snafu(display("Invalid KMS endpoint {}: {}", endpoint, reason))]
+    InvalidEndpoint { endpoint: String, reason: String },
+    /// The key reference could not be parsed.
+    #[snafu(display("Invalid key reference {}: {}", key_ref, reason))]
+    InvalidKeyRef { key_ref: String, reason: String },
+    /// Failed to read the token to authenticate with.
+    #[snafu(display(
+        "Failed to read KMS token from {}. Error {}",
+        path,
+        source
+    ))]
+    Token {
+        path: String,
+        source: std::io::Error,
+    },
+    /// Failed to build the request.
+    #[snafu(display(
+        "Failed to build KMS request for key {}. Error {}",
+        key_id,
+        source
+    ))]
+    BuildRequest { key_id: String, source: http::Error },
+    /// Failed to send the request or to receive the reply.
+    #[snafu(display(
+        "Failed to reach KMS for key {}. Error {}",
+        key_id,
+        source
+    ))]
+    Request {
+        key_id: String,
+        source: hyper::Error,
+    },
+    /// The KMS refused the request.
+    #[snafu(display(
+        "KMS refused request for key {} with status {}",
+        key_id,
+        status
+    ))]
+    Refused { key_id: String, status: u16 },
+    /// The reply of the KMS could not be understood.
+    #[snafu(display(
+        "Invalid KMS reply for key {}. Error {}",
+        key_id,
+        source
+    ))]
+    InvalidReply {
+        key_id: String,
+        source: serde_json::Error,
+    },
+    /// Failed to wait for the operation.
+    #[snafu(display("Failed to wait for KMS operation on key {}", key_id))]
+    OpWait {
+        key_id: String,
+        source: futures::channel::oneshot::Canceled,
+    },
+    /// Operation timed out.
+    #[snafu(display("KMS operation timed out."))]
+    OpTimeout {},
+}
+
+/// A hex encoded data encryption key. The key is left out of its debug
+/// output so that it does not end up in the logs, and is zeroed when the
+/// data key is dropped.
+#[derive(Clone, Deserialize)]
+pub struct DataKey {
+    /// version of the key
+    pub version: u64,
+    key: Zeroizing<String>,
+}
+
+impl DataKey {
+    /// Create a data key from its hex encoded key material.
+    pub fn new(version: u64, key: String) -> Self {
+        Self {
+            version,
+            key: Zeroizing::new(key),
+        }
+    }
+
+    /// Returns the hex encoded key material.
+    pub fn expose(&self) -> &str {
+        &self.key
+    }
+}
+
+impl std::fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DataKey")
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+/// Trait defining the operations that can be performed on a KMS.
+#[async_trait]
+pub trait Kms: Send + Sync {
+    /// Fetch the given version of a data encryption key, the latest one if
+    /// no version is given.
+    async fn fetch(
+        &self,
+        key_id: &str,
+        version: Option<u64>,
+    ) -> Result<DataKey, KmsError>;
+
+    /// Create a new version of a key and return it. Earlier versions remain
+    /// available, data encrypted with them stays readable.
+    async fn rotate(&self, key_id: &str) -> Result<u64, KmsError>;
+
+    /// Unwrap a hex encoded data encryption key that has been wrapped with
+    /// the key encryption key of the given id.
+    async fn unwrap(
+        &self,
+        key_id: &str,
+        wrapped: &str,
+    ) -> Result<DataKey, KmsError>;
+}
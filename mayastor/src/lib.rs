@@ -12,20 +12,29 @@ extern crate spdk_rs;
 
 #[macro_use]
 pub mod core;
+pub mod backup;
 pub mod bdev;
 pub mod delay;
+pub mod disk_format;
 pub use spdk_rs::ffihelper;
 pub mod grpc;
+pub mod handoff;
+pub mod health;
 pub mod host;
 pub mod jsonrpc;
+pub mod key_manager;
+pub mod kms;
 pub mod logger;
 pub mod lvs;
+pub mod mem_stats;
 pub mod nexus_uri;
 pub mod persistent_store;
 pub mod pool;
 pub mod rebuild;
 pub mod replica;
 mod sleep;
+pub mod startup;
+pub mod state_dump;
 pub mod store;
 pub mod subsys;
 pub mod target;
@@ -44,4 +53,18 @@ pub extern "C" fn cps_init() {
     subsys::register_subsystem();
     bdev::nexus::register_module();
     bdev::null_ng::register();
+    bdev::nvmx::register_jsonrpc_methods();
+    lvs::register_jsonrpc_methods();
+    backup::register_jsonrpc_methods();
+    grpc::audit::register_jsonrpc_methods();
+    grpc::list_page::register_jsonrpc_methods();
+    state_dump::register_jsonrpc_methods();
+    mem_stats::register_jsonrpc_methods();
+    startup::register_jsonrpc_methods();
+    disk_format::register_jsonrpc_methods();
+    handoff::register_jsonrpc_methods();
+    core::perf_test::register_jsonrpc_methods();
+    core::export::register_jsonrpc_methods();
+    core::fault_injection::register_jsonrpc_methods();
+    core::poller::register_jsonrpc_methods();
 }
@@ -1,7 +1,12 @@
 use nix::errno::Errno;
 use snafu::Snafu;
 
-use crate::{core::CoreError, lvs::PropName, nexus_uri::NexusBdevError};
+use crate::{
+    core::CoreError,
+    jsonrpc::{Code, RpcErrorCode},
+    lvs::PropName,
+    nexus_uri::NexusBdevError,
+};
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility = "pub(crate)")]
@@ -15,6 +20,9 @@ pub enum Error {
     #[snafu(display("failed to export pool {}", name))]
     Export { source: Errno, name: String },
 
+    #[snafu(display("errno: {} failed to rename pool {}", source, name))]
+    Rename { source: Errno, name: String },
+
     #[snafu(display("failed to destroy pool {}", name))]
     Destroy {
         source: NexusBdevError,
@@ -38,6 +46,9 @@ pub enum Error {
     #[snafu(display("failed to destroy lvol {}", name))]
     RepDestroy { source: Errno, name: String },
 
+    #[snafu(display("lvol {} is protected against deletion", name))]
+    RepProtected { name: String },
+
     #[snafu(display("bdev {} is not a lvol", name))]
     NotALvol { source: Errno, name: String },
 
@@ -70,4 +81,68 @@ pub enum Error {
     Property { source: Errno, name: String },
     #[snafu(display("invalid replica share protocol value: {}", value))]
     ReplicaShareProtocol { value: i32 },
+    #[snafu(display("failed to create import source {}", uri))]
+    ImportSource { source: NexusBdevError, uri: String },
+    #[snafu(display("failed to open import source {}", uri))]
+    ImportSourceOpen { source: CoreError, uri: String },
+    #[snafu(display(
+        "{} is a zoned device of {} zones of {} bytes, pools can not be \
+            created on zoned devices",
+        name,
+        num_zones,
+        zone_size
+    ))]
+    ZonedDevice {
+        name: String,
+        num_zones: u64,
+        zone_size: u64,
+    },
+    #[snafu(display(
+        "owner {} uses {} of the {} bytes of its quota on pool {}, {} more \
+            bytes exceed it",
+        owner,
+        used,
+        limit,
+        pool,
+        size
+    ))]
+    QuotaExceeded {
+        owner: String,
+        pool: String,
+        used: u64,
+        size: u64,
+        limit: u64,
+    },
+}
+
+impl RpcErrorCode for Error {
+    fn rpc_error_code(&self) -> Code {
+        match self {
+            Self::Invalid {
+                source, ..
+            } if *source == Errno::ENOENT => Code::NotFound,
+            Self::Invalid {
+                source, ..
+            } if *source == Errno::EEXIST => Code::AlreadyExists,
+            Self::RepExists {
+                ..
+            } => Code::AlreadyExists,
+            Self::Invalid {
+                ..
+            }
+            | Self::Property {
+                ..
+            }
+            | Self::RepProtected {
+                ..
+            }
+            | Self::ZonedDevice {
+                ..
+            }
+            | Self::QuotaExceeded {
+                ..
+            } => Code::InvalidParams,
+            _ => Code::InternalError,
+        }
+    }
 }
@@ -50,18 +50,30 @@ use crate::{
 #[non_exhaustive]
 pub enum PropValue {
     Shared(bool),
+    /// the lvol can only be destroyed with force
+    Protected(bool),
+    /// version of the format of the properties of the lvol
+    FormatVersion(u32),
+    /// the share of the lvol only lets in the hosts that know its token
+    ShareToken(bool),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[non_exhaustive]
 pub enum PropName {
     Shared,
+    Protected,
+    FormatVersion,
+    ShareToken,
 }
 
 impl From<PropValue> for PropName {
     fn from(v: PropValue) -> Self {
         match v {
             PropValue::Shared(_) => Self::Shared,
+            PropValue::Protected(_) => Self::Protected,
+            PropValue::FormatVersion(_) => Self::FormatVersion,
+            PropValue::ShareToken(_) => Self::ShareToken,
         }
     }
 }
@@ -70,6 +82,9 @@ impl Display for PropName {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let name = match self {
             PropName::Shared => "shared",
+            PropName::Protected => "protected",
+            PropName::FormatVersion => "format_version",
+            PropName::ShareToken => "share_token",
         };
         write!(f, "{}", name)
     }
@@ -299,6 +314,10 @@ impl Lvol {
         let _ = Pin::new(&mut self).unshare().await;
 
         let name = self.name();
+        let pool =
+            unsafe { Lvs(NonNull::new_unchecked(self.0.as_ref().lvol_store)) };
+        pool.prepare_trim(&mut self);
+        let available = pool.available();
 
         let (s, r) = pair::<i32>();
         unsafe {
@@ -315,6 +334,7 @@ impl Lvol {
                 }
             })?;
 
+        pool.trim_freed(pool.available().saturating_sub(available));
         info!("destroyed lvol {}", name);
         Ok(name)
     }
@@ -341,25 +361,42 @@ impl Lvol {
         if self.is_read_only() {
             warn!("{} is read-only", self.name());
         }
-        match prop {
-            PropValue::Shared(val) => {
-                let name = PropName::from(prop).to_string().into_cstring();
-                let value = if val { "true" } else { "false" }.into_cstring();
-                unsafe {
-                    spdk_blob_set_xattr(
-                        blob,
-                        name.as_ptr(),
-                        value.as_bytes_with_nul().as_ptr() as *const _,
-                        value.as_bytes_with_nul().len() as u16,
-                    )
-                }
-                .to_result(|e| Error::SetProperty {
-                    source: Errno::from_i32(e),
-                    prop: prop.into(),
-                    name: self.name(),
-                })?;
+        let value = match prop {
+            PropValue::Shared(val)
+            | PropValue::Protected(val)
+            | PropValue::ShareToken(val) => {
+                if val { "true" } else { "false" }.to_string()
             }
+            PropValue::FormatVersion(version) => version.to_string(),
         };
+        let name = PropName::from(prop).to_string().into_cstring();
+        let value = value.into_cstring();
+        unsafe {
+            spdk_blob_set_xattr(
+                blob,
+                name.as_ptr(),
+                value.as_bytes_with_nul().as_ptr() as *const _,
+                value.as_bytes_with_nul().len() as u16,
+            )
+        }
+        .to_result(|e| Error::SetProperty {
+            source: Errno::from_i32(e),
+            prop: prop.into(),
+            name: self.name(),
+        })?;
+
+        let pool =
+            unsafe { Lvs(NonNull::new_unchecked(self.0.as_ref().lvol_store)) };
+        if pool.defer_sync(&self.name()) {
+            return Ok(());
+        }
+        self.sync_metadata().await
+    }
+
+    /// write the metadata of the lvol to disk
+    pub(crate) async fn sync_metadata(&self) -> Result<(), Error> {
+        let blob = unsafe { self.0.as_ref().blob };
+        assert!(!blob.is_null());
 
         let (s, r) = pair::<i32>();
         unsafe {
@@ -381,35 +418,42 @@ impl Lvol {
         let blob = unsafe { self.0.as_ref().blob };
         assert!(!blob.is_null());
 
-        match prop {
-            PropName::Shared => {
-                let name = prop.to_string().into_cstring();
-                let mut value: *const libc::c_char =
-                    std::ptr::null::<libc::c_char>();
-                let mut value_len: u64 = 0;
-                unsafe {
-                    spdk_blob_get_xattr_value(
-                        blob,
-                        name.as_ptr(),
-                        &mut value as *mut *const c_char as *mut *const c_void,
-                        &mut value_len,
-                    )
-                }
-                .to_result(|e| Error::GetProperty {
-                    source: Errno::from_i32(e),
-                    prop,
-                    name: self.name(),
-                })?;
-                match unsafe { CStr::from_ptr(value).to_str() } {
-                    Ok("true") => Ok(PropValue::Shared(true)),
-                    Ok("false") => Ok(PropValue::Shared(false)),
-                    _ => Err(Error::Property {
-                        source: Errno::EINVAL,
-                        name: self.name(),
-                    }),
-                }
-            }
+        let name = prop.to_string().into_cstring();
+        let mut value: *const libc::c_char = std::ptr::null::<libc::c_char>();
+        let mut value_len: u64 = 0;
+        unsafe {
+            spdk_blob_get_xattr_value(
+                blob,
+                name.as_ptr(),
+                &mut value as *mut *const c_char as *mut *const c_void,
+                &mut value_len,
+            )
         }
+        .to_result(|e| Error::GetProperty {
+            source: Errno::from_i32(e),
+            prop,
+            name: self.name(),
+        })?;
+
+        let invalid = || Error::Property {
+            source: Errno::EINVAL,
+            name: self.name(),
+        };
+        let value =
+            unsafe { CStr::from_ptr(value).to_str() }.map_err(|_| invalid())?;
+        let flag = || match value {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(invalid()),
+        };
+        Ok(match prop {
+            PropName::Shared => PropValue::Shared(flag()?),
+            PropName::Protected => PropValue::Protected(flag()?),
+            PropName::ShareToken => PropValue::ShareToken(flag()?),
+            PropName::FormatVersion => {
+                PropValue::FormatVersion(value.parse().map_err(|_| invalid())?)
+            }
+        })
     }
 
     /// Format snapshot name
@@ -0,0 +1,220 @@
+//!
+//! Erasing the data of replicas when they are destroyed.
+//!
+//! Destroying an lvol returns its clusters to the pool, from where they are
+//! handed out to the next replica created on it. Unless the data is erased
+//! first, that replica may read what the previous tenant left behind.
+//!
+//! The deletion policy selects how the data is erased. As erasing a large
+//! replica takes a while, it is done in the background: the replica is
+//! unshared right away, erased in chunks and destroyed once all of it has
+//! been erased. The progress of the erasures is reported by the
+//! `replica_erasures` json-rpc method. When erasing fails the replica is kept
+//! and destroying it again retries the erasure.
+
+use std::{collections::HashMap, convert::TryFrom, pin::Pin, sync::Mutex};
+
+use futures::FutureExt;
+use nix::errno::Errno;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{Bdev, CoreError, Reactors, Share, UntypedBdev},
+    jsonrpc::jsonrpc_register,
+    lvs::{Error, Lvol},
+    subsys::Config,
+};
+
+/// How the data of a replica is erased when the replica is destroyed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeletionPolicy {
+    /// the data is left as is
+    None,
+    /// the replica is unmapped, which erases the data only if the device
+    /// behind the pool returns zeroes for deallocated blocks
+    Unmap,
+    /// zeroes are written to the replica
+    WriteZeroes,
+    /// the key of the replica is destroyed. Pools are not encrypted yet, so
+    /// for now zeroes are written instead.
+    CryptoErase,
+}
+
+impl Default for DeletionPolicy {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+/// Erasure of a replica that is being destroyed.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicaErasure {
+    /// name (uuid) of the replica
+    pub name: String,
+    /// pool of the replica
+    pub pool: String,
+    pub policy: DeletionPolicy,
+    /// size of the replica in bytes
+    pub size: u64,
+    /// number of bytes erased so far
+    pub erased: u64,
+    /// reason the erasure failed, the replica is kept when it does
+    pub error: Option<String>,
+}
+
+/// Erasures in progress or failed, by replica name.
+static ERASURES: Lazy<Mutex<HashMap<String, ReplicaErasure>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// number of bytes erased by a single IO
+const ERASE_CHUNK: u64 = 16 * 1024 * 1024;
+
+/// returns the erasures of replicas in progress or failed
+pub fn replica_erasures() -> Vec<ReplicaErasure> {
+    ERASURES.lock().unwrap().values().cloned().collect()
+}
+
+impl Lvol {
+    /// Destroy the lvol after erasing its data according to the policy. With
+    /// no policy the lvol is destroyed right away, otherwise it is unshared
+    /// and erased in the background, and destroyed when done.
+    pub async fn destroy_with_policy(
+        mut self,
+        policy: DeletionPolicy,
+    ) -> Result<(), Error> {
+        let policy = match policy {
+            DeletionPolicy::None => {
+                return self.destroy().await.map(|_| ());
+            }
+            DeletionPolicy::CryptoErase => {
+                warn!(
+                    "pool {} is not encrypted, writing zeroes to {} instead",
+                    self.pool(),
+                    self.name()
+                );
+                DeletionPolicy::WriteZeroes
+            }
+            policy => policy,
+        };
+
+        let name = self.name();
+        {
+            let mut erasures = ERASURES.lock().unwrap();
+            if erasures.get(&name).map_or(false, |e| e.error.is_none()) {
+                // already being erased
+                return Ok(());
+            }
+            erasures.insert(
+                name.clone(),
+                ReplicaErasure {
+                    name: name.clone(),
+                    pool: self.pool(),
+                    policy,
+                    size: self.size(),
+                    erased: 0,
+                    error: None,
+                },
+            );
+        }
+
+        let _ = Pin::new(&mut self).unshare().await;
+
+        info!("erasing lvol {} with policy {:?}", name, policy);
+        Reactors::current()
+            .spawn_local(async move {
+                let result = match self.erase(policy).await {
+                    Ok(_) => self.destroy().await.map(|_| ()),
+                    Err(e) => {
+                        error!("failed to erase lvol {}: {}", name, e);
+                        Err(Error::RepDestroy {
+                            source: Errno::EIO,
+                            name: name.clone(),
+                        })
+                    }
+                };
+
+                let mut erasures = ERASURES.lock().unwrap();
+                match result {
+                    Ok(_) => {
+                        erasures.remove(&name);
+                    }
+                    Err(e) => {
+                        if let Some(erasure) = erasures.get_mut(&name) {
+                            erasure.error = Some(e.to_string());
+                        }
+                    }
+                }
+            })
+            .detach();
+
+        Ok(())
+    }
+
+    /// erase the data of the lvol in chunks, recording the progress
+    async fn erase(&self, policy: DeletionPolicy) -> Result<(), CoreError> {
+        let hdl = Bdev::open(&self.as_bdev(), true)
+            .and_then(|desc| desc.into_handle())?;
+        let size = self.size();
+        let name = self.name();
+
+        let mut offset = 0;
+        while offset < size {
+            let len = std::cmp::min(ERASE_CHUNK, size - offset);
+            if policy == DeletionPolicy::Unmap {
+                hdl.unmap_at(offset, len).await?;
+            } else {
+                hdl.write_zeroes_at(offset, len).await?;
+            }
+            offset += len;
+
+            if let Some(erasure) = ERASURES.lock().unwrap().get_mut(&name) {
+                erasure.erased = offset;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DestroyArgs {
+    /// name (uuid) of the replica
+    uuid: String,
+    /// deletion policy, the configured one when not given
+    #[serde(default)]
+    policy: Option<DeletionPolicy>,
+    /// destroy the replica even if it is protected
+    #[serde(default)]
+    force: bool,
+}
+
+async fn destroy(args: DestroyArgs) -> Result<(), Error> {
+    let policy = args
+        .policy
+        .unwrap_or(Config::get().replica_opts.deletion_policy);
+
+    if let Some(bdev) = UntypedBdev::lookup_by_name(&args.uuid) {
+        let lvol = Lvol::try_from(bdev)?;
+        lvol.check_protected(args.force).await?;
+        lvol.destroy_with_policy(policy).await?;
+    }
+    Ok(())
+}
+
+async fn list(_: ()) -> Result<Vec<ReplicaErasure>, Error> {
+    Ok(replica_erasures())
+}
+
+/// Register the json-rpc methods to destroy replicas with a deletion policy
+/// and to follow their erasure.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "replica_destroy",
+        |args: DestroyArgs| destroy(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>("replica_erasures", |args: ()| {
+        list(args).boxed_local()
+    });
+}
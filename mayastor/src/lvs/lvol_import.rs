@@ -0,0 +1,239 @@
+//!
+//! Importing the data of existing volumes into new replicas.
+//!
+//! Volumes that predate mayastor are migrated by copying their data into a
+//! replica. The source is given as a device uri, e.g. `aio:///dev/sdb` for a
+//! kernel device or a raw image file, or `bdev:///<name>` for a bdev that
+//! exists already. A replica of the size of the source is created on the
+//! pool and the source is copied into it in the background; chunks of zeroes
+//! are skipped for thin replicas. The source is opened read-only and
+//! released again when the copy is done, without being changed.
+//!
+//! The progress of the imports is reported by the `replica_imports`
+//! json-rpc method. When an import fails the replica is destroyed again.
+
+use std::{cmp::min, collections::HashMap, sync::Mutex};
+
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+
+use crate::{
+    bdev::{device_create, device_destroy, device_open},
+    core::{Bdev, BlockDeviceDescriptor, CoreError, Reactors},
+    jsonrpc::jsonrpc_register,
+    lvs::{
+        error::{ImportSource, ImportSourceOpen},
+        Error,
+        Lvol,
+        Lvs,
+    },
+};
+
+/// State of an import.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportState {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Import of the data of a device into a replica.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicaImport {
+    /// name of the replica
+    pub name: String,
+    /// pool of the replica
+    pub pool: String,
+    /// uri of the device the data is copied from
+    pub source: String,
+    pub state: ImportState,
+    /// size of the source in bytes
+    pub size: u64,
+    /// number of bytes copied so far
+    pub copied: u64,
+    /// reason the import failed, the replica is destroyed when it does
+    pub error: Option<String>,
+}
+
+/// Imports running or finished, by replica name.
+static IMPORTS: Lazy<Mutex<HashMap<String, ReplicaImport>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// number of bytes copied by a single IO
+const IMPORT_CHUNK: u64 = 4 * 1024 * 1024;
+
+/// returns the imports of data into replicas, running or finished
+pub fn replica_imports() -> Vec<ReplicaImport> {
+    IMPORTS.lock().unwrap().values().cloned().collect()
+}
+
+impl Lvs {
+    /// Create a replica on the pool holding a copy of the data of the device
+    /// at the uri. The replica is created right away and the data copied in
+    /// the background.
+    pub async fn import_lvol(
+        &self,
+        name: &str,
+        source: &str,
+        uuid: Option<&str>,
+        thin: bool,
+    ) -> Result<ReplicaImport, Error> {
+        if IMPORTS
+            .lock()
+            .unwrap()
+            .get(name)
+            .map_or(false, |i| i.state == ImportState::Running)
+        {
+            return Err(Error::RepExists {
+                source: nix::errno::Errno::EEXIST,
+                name: name.to_string(),
+            });
+        }
+
+        let device = device_create(source).await.context(ImportSource {
+            uri: source,
+        })?;
+        let desc = match device_open(&device, false).context(ImportSourceOpen {
+            uri: source,
+        }) {
+            Ok(desc) => desc,
+            Err(e) => {
+                let _ = device_destroy(source).await;
+                return Err(e);
+            }
+        };
+        let size = desc.get_device().size_in_bytes();
+
+        let lvol = match self.create_lvol(name, size, uuid, thin).await {
+            Ok(lvol) => lvol,
+            Err(e) => {
+                drop(desc);
+                let _ = device_destroy(source).await;
+                return Err(e);
+            }
+        };
+
+        let import = ReplicaImport {
+            name: name.to_string(),
+            pool: self.name().to_string(),
+            source: source.to_string(),
+            state: ImportState::Running,
+            size,
+            copied: 0,
+            error: None,
+        };
+        IMPORTS
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), import.clone());
+
+        info!("importing {} into lvol {}", source, name);
+        let source = source.to_string();
+        Reactors::current()
+            .spawn_local(async move {
+                let result = lvol.import(desc.as_ref(), thin).await;
+                drop(desc);
+                if let Err(e) = device_destroy(&source).await {
+                    error!("failed to release import source {}: {}", source, e);
+                }
+
+                let name = lvol.name();
+                if let Err(e) = &result {
+                    error!("failed to import {} into {}: {}", source, name, e);
+                    if let Err(e) = lvol.destroy().await {
+                        error!("failed to destroy lvol {}: {}", name, e);
+                    }
+                }
+
+                if let Some(import) = IMPORTS.lock().unwrap().get_mut(&name) {
+                    match result {
+                        Ok(_) => import.state = ImportState::Completed,
+                        Err(e) => {
+                            import.state = ImportState::Failed;
+                            import.error = Some(e.to_string());
+                        }
+                    }
+                }
+            })
+            .detach();
+
+        Ok(import)
+    }
+}
+
+impl Lvol {
+    /// copy the data of the source into the lvol, recording the progress
+    async fn import(
+        &self,
+        source: &dyn BlockDeviceDescriptor,
+        thin: bool,
+    ) -> Result<(), CoreError> {
+        let src = source.get_io_handle()?;
+        let dst = Bdev::open(&self.as_bdev(), true)
+            .and_then(|desc| desc.into_handle())?;
+        let size = source.get_device().size_in_bytes();
+        let name = self.name();
+
+        let mut offset = 0;
+        while offset < size {
+            let len = min(IMPORT_CHUNK, size - offset);
+            let mut buf = src.dma_malloc(len).map_err(|_| {
+                CoreError::DmaAllocationError {
+                    size: len,
+                }
+            })?;
+            src.read_at(offset, &mut buf).await?;
+            if !thin || buf.as_slice().iter().any(|b| *b != 0) {
+                dst.write_at(offset, &buf).await?;
+            }
+            offset += len;
+
+            if let Some(import) = IMPORTS.lock().unwrap().get_mut(&name) {
+                import.copied = offset;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportArgs {
+    /// uri of the device to copy the data from
+    source: String,
+    /// pool to create the replica on
+    pool: String,
+    /// name of the replica
+    name: String,
+    #[serde(default)]
+    uuid: Option<String>,
+    #[serde(default)]
+    thin: bool,
+}
+
+async fn import(args: ImportArgs) -> Result<ReplicaImport, Error> {
+    let pool = Lvs::lookup(&args.pool).ok_or_else(|| Error::Invalid {
+        source: nix::errno::Errno::ENOENT,
+        msg: format!("pool {} not found", args.pool),
+    })?;
+    pool.import_lvol(&args.name, &args.source, args.uuid.as_deref(), args.thin)
+        .await
+}
+
+async fn list(_: ()) -> Result<Vec<ReplicaImport>, Error> {
+    Ok(replica_imports())
+}
+
+/// Register the json-rpc methods to import data into replicas and to follow
+/// the imports.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>("replica_import", |args: ImportArgs| {
+        import(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, Error>("replica_imports", |args: ()| {
+        list(args).boxed_local()
+    });
+}
@@ -0,0 +1,112 @@
+//!
+//! Delete protection of replicas.
+//!
+//! A protected replica can only be destroyed with force, so that automation
+//! destroying the wrong replica by mistake gets an error instead of losing
+//! the data of a production volume. The protection is a property stored on
+//! the lvol, so it survives a restart of the node. A pool holding protected
+//! replicas can not be destroyed either.
+//!
+//! The destroy gRPC methods have no way to pass force, they always fail for a
+//! protected replica: the protection must be lifted first, or the replica
+//! destroyed with the `replica_destroy` json-rpc method and force set.
+
+use std::{convert::TryFrom, pin::Pin};
+
+use futures::FutureExt;
+use nix::errno::Errno;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::UntypedBdev,
+    jsonrpc::jsonrpc_register,
+    lvs::{Error, Lvol, Lvs, PropName, PropValue},
+};
+
+impl Lvol {
+    /// Returns true if the lvol can only be destroyed with force. An lvol
+    /// that never had the property set is not protected.
+    pub async fn is_protected(&self) -> bool {
+        matches!(
+            self.get(PropName::Protected).await,
+            Ok(PropValue::Protected(true))
+        )
+    }
+
+    /// Protect the lvol against being destroyed, or lift the protection.
+    pub async fn set_protected(
+        mut self: Pin<&mut Self>,
+        protected: bool,
+    ) -> Result<(), Error> {
+        self.as_mut().set(PropValue::Protected(protected)).await?;
+        info!(
+            "{}: delete protection {}",
+            self.name(),
+            if protected { "set" } else { "lifted" }
+        );
+        Ok(())
+    }
+
+    /// Returns an error if the lvol is protected and may not be destroyed
+    /// without force.
+    pub async fn check_protected(&self, force: bool) -> Result<(), Error> {
+        if self.is_protected().await {
+            if !force {
+                return Err(Error::RepProtected {
+                    name: self.name(),
+                });
+            }
+            warn!("{}: destroying protected lvol with force", self.name());
+        }
+        Ok(())
+    }
+}
+
+impl Lvs {
+    /// Returns an error if any lvol of the pool is protected, destroying the
+    /// pool would destroy it as well.
+    pub async fn check_protected(&self) -> Result<(), Error> {
+        if let Some(lvols) = self.lvols() {
+            for lvol in lvols {
+                lvol.check_protected(false).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ProtectArgs {
+    /// name (uuid) of the replica
+    uuid: String,
+    protected: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ProtectReply {
+    protected: bool,
+}
+
+async fn set_protected(args: ProtectArgs) -> Result<ProtectReply, Error> {
+    let bdev =
+        UntypedBdev::lookup_by_name_or_uuid(&args.uuid).ok_or_else(|| {
+            Error::Invalid {
+                source: Errno::ENOENT,
+                msg: format!("replica {} not found", args.uuid),
+            }
+        })?;
+
+    let mut lvol = Lvol::try_from(bdev)?;
+    Pin::new(&mut lvol).set_protected(args.protected).await?;
+    Ok(ProtectReply {
+        protected: lvol.is_protected().await,
+    })
+}
+
+/// Register the json-rpc method to protect a replica against deletion.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "replica_set_protected",
+        |args: ProtectArgs| set_protected(args).boxed_local(),
+    );
+}
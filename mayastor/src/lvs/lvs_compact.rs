@@ -0,0 +1,422 @@
+//!
+//! Compaction of the thin replicas of a pool.
+//!
+//! A thin replica is allocated a cluster when a block of it is first written
+//! to, so the clusters of a replica written to at random, or alongside other
+//! replicas, end up scattered over the pool and sequential reads of it turn
+//! into random reads of the device. Compacting a pool rewrites its thin
+//! replicas one after the other so that their clusters are allocated in
+//! order again:
+//!
+//! - the data of the replica is copied to a new thin replica named
+//!   `<name>.compact`, which is renamed to `<name>.compacted` once all of it
+//!   has been copied
+//! - the replica is shrunk to nothing, which returns all its clusters to the
+//!   pool, and grown back to its size
+//! - the data is copied back, allocating the clusters in order, and the copy is
+//!   destroyed
+//!
+//! The pool needs as much free space as the largest replica has allocated.
+//! Replicas that are open by a nexus or shared are skipped, and a replica is
+//! claimed while it is compacted so that it can not be used meanwhile. Should
+//! the compaction of a replica be interrupted, its `<name>.compacted` copy
+//! holds its data and the replica must be compacted again before it is used,
+//! which copies the data back. An unfinished `<name>.compact` copy is simply
+//! dropped.
+//!
+//! Compactions are throttled to a given rate and can be cancelled, which
+//! takes effect before the next replica or, for the replica being compacted,
+//! as long as its data has not been released yet. They are started with the
+//! `pool_compact` json-rpc method, cancelled with `pool_compact_cancel` and
+//! their progress is reported by `pool_compactions`.
+
+use std::{
+    cmp::min,
+    collections::HashMap,
+    convert::TryFrom,
+    ffi::c_void,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use futures::{channel::oneshot, FutureExt};
+use nix::errno::Errno;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use spdk_rs::libspdk::{vbdev_lvol_rename, vbdev_lvol_resize};
+
+use crate::{
+    core::{BdevHandle, Reactors, UntypedBdev},
+    ffihelper::{cb_arg, pair, FfiResult, IntoCString},
+    jsonrpc::jsonrpc_register,
+    lvs::{Error, Lvol, Lvs},
+    sleep::mayastor_sleep,
+};
+
+/// number of bytes copied by a single IO
+const COMPACT_CHUNK: u64 = 1024 * 1024;
+
+/// State of the compaction of a pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompactState {
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+/// Compaction of a pool, running or finished.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolCompaction {
+    pub pool: String,
+    pub state: CompactState,
+    /// maximum rate in MiB/s, 0 for no limit
+    pub rate_mb: u64,
+    /// replica being compacted
+    pub replica: Option<String>,
+    /// replicas compacted so far
+    pub compacted: Vec<String>,
+    /// replicas skipped as they are in use
+    pub skipped: Vec<String>,
+    /// number of bytes copied so far, twice the data of each replica
+    pub copied: u64,
+    /// reason the compaction failed
+    pub error: Option<String>,
+}
+
+struct Compaction {
+    status: Mutex<PoolCompaction>,
+    cancelled: AtomicBool,
+}
+
+/// Compactions by pool name.
+static COMPACTIONS: Lazy<Mutex<HashMap<String, Arc<Compaction>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// returns the compactions of pools, running or finished
+pub fn pool_compactions() -> Vec<PoolCompaction> {
+    COMPACTIONS
+        .lock()
+        .values()
+        .map(|c| c.status.lock().clone())
+        .collect()
+}
+
+extern "C" fn lvol_op_cb(sender: *mut c_void, errno: i32) {
+    let sender = unsafe { Box::from_raw(sender as *mut oneshot::Sender<i32>) };
+    sender.send(errno).unwrap();
+}
+
+impl Lvol {
+    /// resize the lvol to the given number of bytes
+    pub(crate) async fn resize(&self, size: u64) -> Result<(), Error> {
+        let (s, r) = pair::<i32>();
+        unsafe {
+            vbdev_lvol_resize(
+                self.0.as_ptr(),
+                size,
+                Some(lvol_op_cb),
+                cb_arg(s),
+            )
+        };
+        r.await
+            .expect("lvol resize callback is gone")
+            .to_result(|e| Error::Invalid {
+                source: Errno::from_i32(e),
+                msg: format!("failed to resize lvol {}", self.name()),
+            })
+    }
+
+    /// give the lvol a new name
+    async fn rename(&self, new_name: &str) -> Result<(), Error> {
+        let cname = new_name.into_cstring();
+        let (s, r) = pair::<i32>();
+        unsafe {
+            vbdev_lvol_rename(
+                self.0.as_ptr(),
+                cname.as_ptr(),
+                Some(lvol_op_cb),
+                cb_arg(s),
+            )
+        };
+        r.await
+            .expect("lvol rename callback is gone")
+            .to_result(|e| Error::Invalid {
+                source: Errno::from_i32(e),
+                msg: format!("failed to rename lvol {}", self.name()),
+            })
+    }
+}
+
+/// look up an lvol by name
+fn lookup_lvol(name: &str) -> Option<Lvol> {
+    UntypedBdev::lookup_by_name(name).and_then(|b| Lvol::try_from(b).ok())
+}
+
+/// open the lvol for exclusive use
+fn claim_lvol(lvol: &Lvol) -> Option<BdevHandle> {
+    BdevHandle::open(&lvol.name(), true, true).ok()
+}
+
+impl Compaction {
+    /// copy the data of one lvol to another, skipping chunks of zeroes, at
+    /// the rate of the compaction
+    async fn copy(
+        &self,
+        src: &BdevHandle,
+        dst: &BdevHandle,
+        cancellable: bool,
+    ) -> Result<(), String> {
+        let size = src.get_bdev().size_in_bytes();
+        let rate = self.status.lock().rate_mb * 1024 * 1024;
+        let start = Instant::now();
+
+        let mut offset = 0;
+        while offset < size {
+            if cancellable && self.cancelled.load(Ordering::Relaxed) {
+                return Err("cancelled".to_string());
+            }
+            let len = min(COMPACT_CHUNK, size - offset);
+            let mut buf = src
+                .dma_malloc(len)
+                .map_err(|_| "out of memory".to_string())?;
+            src.read_at(offset, &mut buf)
+                .await
+                .map_err(|e| e.to_string())?;
+            if buf.as_slice().iter().any(|b| *b != 0) {
+                dst.write_at(offset, &buf)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            offset += len;
+            self.status.lock().copied += len;
+
+            if rate > 0 {
+                let due = Duration::from_secs_f64(offset as f64 / rate as f64);
+                if let Some(ahead) = due.checked_sub(start.elapsed()) {
+                    let _ = mayastor_sleep(ahead).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Compact a replica, returning false if it is in use. The replica
+    /// holds its data again when this fails, unless its data has been
+    /// released, in which case the compacted copy holds it.
+    async fn compact_lvol(
+        &self,
+        pool: &Lvs,
+        lvol: Lvol,
+    ) -> Result<bool, String> {
+        let name = lvol.name();
+        let hdl = match claim_lvol(&lvol) {
+            Some(hdl) => hdl,
+            None => return Ok(false),
+        };
+        let staging = format!("{}.compact", name);
+        let staged = format!("{}.compacted", name);
+
+        if let Some(copy) = lookup_lvol(&staging) {
+            copy.destroy().await.map_err(|e| e.to_string())?;
+        }
+
+        // copy the data aside, unless an interrupted compaction did
+        let copy = match lookup_lvol(&staged) {
+            Some(copy) => {
+                warn!("resuming compaction of lvol {} from {}", name, staged);
+                copy
+            }
+            None => {
+                let copy = pool
+                    .create_lvol(&staging, lvol.size(), None, true)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let result = match BdevHandle::open(&staging, true, false) {
+                    Ok(dst) => self.copy(&hdl, &dst, true).await,
+                    Err(e) => Err(e.to_string()),
+                };
+                if let Err(e) = result {
+                    let _ = copy.destroy().await;
+                    return Err(e);
+                }
+                copy.rename(&staged).await.map_err(|e| e.to_string())?;
+                copy
+            }
+        };
+
+        // release the clusters of the replica and copy the data back
+        let size = lvol.size();
+        lvol.resize(0).await.map_err(|e| e.to_string())?;
+        lvol.resize(size).await.map_err(|e| e.to_string())?;
+        let src = BdevHandle::open(&staged, false, false)
+            .map_err(|e| e.to_string())?;
+        self.copy(&src, &hdl, false).await?;
+        drop(src);
+        drop(hdl);
+
+        copy.destroy().await.map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+
+    /// compact the thin replicas of the pool one after the other
+    async fn run(&self, pool: Lvs) -> Result<(), String> {
+        let names = pool
+            .lvols()
+            .map(|lvols| {
+                lvols
+                    .filter(|l| l.is_thin() && !l.is_snapshot())
+                    .map(|l| l.name())
+                    .filter(|n| !n.ends_with(".compact"))
+                    .filter(|n| !n.ends_with(".compacted"))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        for name in names {
+            if self.cancelled.load(Ordering::Relaxed) {
+                break;
+            }
+            let lvol = match lookup_lvol(&name) {
+                Some(lvol) => lvol,
+                None => continue,
+            };
+
+            self.status.lock().replica = Some(name.clone());
+            info!("compacting lvol {}", name);
+            let result = self.compact_lvol(&pool, lvol).await;
+
+            let mut status = self.status.lock();
+            status.replica = None;
+            match result {
+                Ok(true) => status.compacted.push(name),
+                Ok(false) => status.skipped.push(name),
+                Err(_) if self.cancelled.load(Ordering::Relaxed) => break,
+                Err(e) => return Err(format!("lvol {}: {}", name, e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Lvs {
+    /// Compact the thin replicas of the pool in the background, at the given
+    /// rate in MiB/s, 0 for no limit.
+    pub fn compact(&self, rate_mb: u64) -> Result<PoolCompaction, Error> {
+        let pool = self.name().to_string();
+        let mut compactions = COMPACTIONS.lock();
+        if compactions
+            .get(&pool)
+            .map_or(false, |c| c.status.lock().state == CompactState::Running)
+        {
+            return Err(Error::Invalid {
+                source: Errno::EEXIST,
+                msg: format!("pool {} is being compacted", pool),
+            });
+        }
+
+        let compaction = Arc::new(Compaction {
+            status: Mutex::new(PoolCompaction {
+                pool: pool.clone(),
+                state: CompactState::Running,
+                rate_mb,
+                replica: None,
+                compacted: Vec::new(),
+                skipped: Vec::new(),
+                copied: 0,
+                error: None,
+            }),
+            cancelled: AtomicBool::new(false),
+        });
+        compactions.insert(pool.clone(), compaction.clone());
+        let status = compaction.status.lock().clone();
+
+        info!("compacting pool {}", pool);
+        let lvs = Lvs(self.0);
+        Reactors::current()
+            .spawn_local(async move {
+                let result = compaction.run(lvs).await;
+                let mut status = compaction.status.lock();
+                status.state = match result {
+                    Ok(_) if compaction.cancelled.load(Ordering::Relaxed) => {
+                        CompactState::Cancelled
+                    }
+                    Ok(_) => CompactState::Completed,
+                    Err(e) => {
+                        error!("failed to compact pool {}: {}", pool, e);
+                        status.error = Some(e);
+                        CompactState::Failed
+                    }
+                };
+            })
+            .detach();
+
+        Ok(status)
+    }
+
+    /// Cancel the compaction of the pool, returns false if it is not being
+    /// compacted.
+    pub fn cancel_compaction(&self) -> bool {
+        match COMPACTIONS.lock().get(self.name()) {
+            Some(c) if c.status.lock().state == CompactState::Running => {
+                c.cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CompactArgs {
+    /// name of the pool
+    name: String,
+    /// maximum rate in MiB/s, 0 for no limit
+    #[serde(default)]
+    rate_mb: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct CancelArgs {
+    /// name of the pool
+    name: String,
+}
+
+fn lookup_pool(name: &str) -> Result<Lvs, Error> {
+    Lvs::lookup(name).ok_or_else(|| Error::Invalid {
+        source: Errno::ENOENT,
+        msg: format!("pool {} not found", name),
+    })
+}
+
+async fn compact(args: CompactArgs) -> Result<PoolCompaction, Error> {
+    lookup_pool(&args.name)?.compact(args.rate_mb)
+}
+
+async fn cancel(args: CancelArgs) -> Result<bool, Error> {
+    Ok(lookup_pool(&args.name)?.cancel_compaction())
+}
+
+async fn list(_: ()) -> Result<Vec<PoolCompaction>, Error> {
+    Ok(pool_compactions())
+}
+
+/// Register the json-rpc methods to compact pools, cancel the compactions
+/// and follow them.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>("pool_compact", |args: CompactArgs| {
+        compact(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, Error>(
+        "pool_compact_cancel",
+        |args: CancelArgs| cancel(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>("pool_compactions", |args: ()| {
+        list(args).boxed_local()
+    });
+}
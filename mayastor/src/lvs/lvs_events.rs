@@ -0,0 +1,174 @@
+//!
+//! Events of the base devices of pools.
+//!
+//! A pool subscribes to the events of its base device when it is created or
+//! imported, the same events the children of a nexus get. When the device
+//! is removed the lvol store unloads the pool on its own, and when it is
+//! resized the pool keeps its size until it is grown, so the pool only logs
+//! the events and keeps the last ones, which `pool_device_events` reports,
+//! so that a pool that vanished or did not grow with its device can be told
+//! from a pool that was exported.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+};
+
+use chrono::{SecondsFormat, Utc};
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bdev::device_lookup,
+    core::{DeviceEventSubscription, DeviceEventType},
+    jsonrpc::{jsonrpc_register, Code, JsonRpcError},
+    lvs::Lvs,
+};
+
+/// number of events kept per pool
+const MAX_EVENTS: usize = 16;
+
+thread_local! {
+    /// subscriptions of the pools to the events of their base devices, by
+    /// pool name, on the master core
+    static SUBSCRIPTIONS: RefCell<HashMap<String, DeviceEventSubscription>> =
+        RefCell::new(HashMap::new());
+}
+
+/// last events of the base devices of the pools, by pool name
+static EVENTS: Lazy<Mutex<HashMap<String, VecDeque<PoolDeviceEvent>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Event of the base device of a pool.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolDeviceEvent {
+    pub device: String,
+    pub event: String,
+    /// size of the device in bytes after the event, 0 once it is removed
+    pub device_size: u64,
+    /// time of the event, RFC 3339
+    pub time: String,
+}
+
+/// log and keep an event of the base device of a pool
+fn record(pool: &str, event: DeviceEventType, device: &str) {
+    let device_size = match event {
+        DeviceEventType::DeviceRemoved => {
+            error!(
+                "base device {} of pool {} removed, the pool is unloaded",
+                device, pool
+            );
+            0
+        }
+        _ => {
+            let size = device_lookup(device)
+                .map(|d| d.size_in_bytes())
+                .unwrap_or_default();
+            warn!(
+                "base device {} of pool {}: {:?}, device size {}",
+                device, pool, event, size
+            );
+            size
+        }
+    };
+
+    let mut events = EVENTS.lock();
+    let events = events.entry(pool.to_string()).or_default();
+    if events.len() == MAX_EVENTS {
+        events.pop_front();
+    }
+    events.push_back(PoolDeviceEvent {
+        device: device.to_string(),
+        event: format!("{:?}", event),
+        device_size,
+        time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+    });
+}
+
+impl Lvs {
+    /// subscribe the pool to the events of its base device
+    pub(crate) fn watch_base_device(&self) {
+        let pool = self.name().to_string();
+        let device = match device_lookup(self.base_bdev().name()) {
+            Some(device) => device,
+            None => return,
+        };
+        let name = pool.clone();
+        match DeviceEventSubscription::new(
+            device.as_ref(),
+            &format!("pool {}", pool),
+            move |event, device| record(&name, event, device),
+        ) {
+            Ok(subscription) => SUBSCRIPTIONS.with(|s| {
+                s.borrow_mut().insert(pool, subscription);
+            }),
+            Err(e) => {
+                error!("pool {} can not watch its base device: {}", pool, e)
+            }
+        }
+    }
+
+    /// stop watching the base device of the pool, forgetting its events
+    pub(crate) fn unwatch_base_device(&self) {
+        SUBSCRIPTIONS.with(|s| s.borrow_mut().remove(self.name()));
+        EVENTS.lock().remove(self.name());
+    }
+
+    /// watch the base device under the current name of the pool, moving the
+    /// events it had under its old name
+    pub(crate) fn move_base_device_watch(&self, old_name: &str) {
+        SUBSCRIPTIONS.with(|s| s.borrow_mut().remove(old_name));
+        {
+            let mut events = EVENTS.lock();
+            if let Some(e) = events.remove(old_name) {
+                events.insert(self.name().to_string(), e);
+            }
+        }
+        self.watch_base_device();
+    }
+
+    /// returns the last events of the base device of the pool
+    pub fn base_device_events(&self) -> Vec<PoolDeviceEvent> {
+        pool_device_events(self.name())
+    }
+}
+
+/// returns the last events of the base device of a pool, which remain after
+/// the pool was unloaded on the removal of its device
+pub fn pool_device_events(pool: &str) -> Vec<PoolDeviceEvent> {
+    EVENTS
+        .lock()
+        .get(pool)
+        .map(|e| e.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Deserialize)]
+struct EventsArgs {
+    /// name of the pool
+    name: String,
+}
+
+async fn events(
+    args: EventsArgs,
+) -> Result<Vec<PoolDeviceEvent>, JsonRpcError> {
+    let events = pool_device_events(&args.name);
+    if events.is_empty() && Lvs::lookup(&args.name).is_none() {
+        return Err(JsonRpcError::new(
+            Code::NotFound,
+            format!("pool {} not found", args.name),
+        ));
+    }
+    Ok(events)
+}
+
+/// Register the json-rpc method reporting the events of the base devices of
+/// pools.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, JsonRpcError>(
+        "pool_device_events",
+        |args: EventsArgs| events(args).boxed_local(),
+    );
+}
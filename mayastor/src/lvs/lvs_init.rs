@@ -0,0 +1,374 @@
+//!
+//! Initialization of the data area of pools in the background.
+//!
+//! A pool is created without clearing its data area, as unmapping a large
+//! device can take an hour, so a replica created on it may read what the
+//! device held before. Clearing the data area while the pool is created
+//! makes creating it that slow again, whereas a lazy initialization leaves
+//! the pool usable right away and clears the data area in the background:
+//!
+//! - all free clusters of the pool are allocated to a temporary replica named
+//!   `<pool uuid>.init`, so that the replicas created meanwhile are not handed
+//!   clusters that were not cleared yet
+//! - the temporary replica is cleared a chunk at a time from its end, with
+//!   unmaps if the device supports them and zeroes otherwise, and shrunk after
+//!   each chunk, which returns the cleared clusters to the pool
+//! - once all of it has been cleared, it is destroyed
+//!
+//! The pool thus gains free space as the initialization progresses. When a
+//! replica is created on the pool while it does not have enough free space
+//! yet, the temporary replica is shrunk by what is missing, without clearing
+//! it, and the new replica is cleared as it is created instead.
+//!
+//! The temporary replica is named after the uuid of the pool, which survives
+//! renaming it, and an initialization interrupted by exporting the pool or
+//! restarting is resumed when the pool is imported again.
+//!
+//! How new pools are initialized is set by the `init` of the pool options;
+//! the `pool_init` json-rpc method initializes the free space of an existing
+//! pool and `pool_inits` reports the progress of the initializations.
+
+use std::{
+    cmp::{max, min},
+    collections::HashMap,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::{lock::Mutex as AsyncMutex, FutureExt};
+use nix::errno::Errno;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{Bdev, CoreError, IoType, Reactors},
+    jsonrpc::jsonrpc_register,
+    lvs::{Error, Lvol, Lvs},
+    sleep::mayastor_sleep,
+};
+
+/// number of bytes cleared at once by an initialization
+const INIT_CHUNK: u64 = 1024 * 1024 * 1024;
+
+/// pause between the chunks of an initialization, to let the IO of the pool
+/// through
+const INIT_PAUSE: Duration = Duration::from_millis(10);
+
+/// How the data area of a new pool is initialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InitMode {
+    /// the data area is left as it is
+    None,
+    /// the data area is cleared in the background
+    Lazy,
+    /// the data area is cleared before the pool is created
+    Full,
+}
+
+impl Default for InitMode {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+/// State of the initialization of a pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InitState {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Initialization of a pool, running or finished.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolInit {
+    pub pool: String,
+    pub uuid: String,
+    pub state: InitState,
+    /// number of bytes to initialize
+    pub size: u64,
+    /// number of bytes initialized so far
+    pub initialized: u64,
+    /// number of bytes handed to replicas before they were initialized,
+    /// which were cleared with the replicas instead
+    pub yielded: u64,
+    /// reason the initialization failed
+    pub error: Option<String>,
+}
+
+struct Init {
+    status: Mutex<PoolInit>,
+    /// held while the temporary replica is cleared or shrunk
+    busy: AsyncMutex<()>,
+}
+
+/// Initializations by pool uuid.
+static INITS: Lazy<Mutex<HashMap<String, Arc<Init>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// returns the initializations of pools, running or finished
+pub fn pool_inits() -> Vec<PoolInit> {
+    INITS
+        .lock()
+        .values()
+        .map(|i| {
+            let mut status = i.status.lock().clone();
+            if let Some(lvs) = Lvs::lookup_by_uuid(&status.uuid) {
+                status.pool = lvs.name().to_string();
+            }
+            status
+        })
+        .collect()
+}
+
+impl Lvol {
+    /// clear part of the lvol, with unmaps if the pool supports them
+    async fn clear(&self, offset: u64, len: u64) -> Result<(), CoreError> {
+        let unmap = Lvs::lookup(&self.pool())
+            .map_or(false, |l| l.base_bdev().io_type_supported(IoType::Unmap));
+        let hdl = Bdev::open(&self.as_bdev(), true)
+            .and_then(|desc| desc.into_handle())?;
+        if unmap {
+            hdl.unmap_at(offset, len).await.map(|_| ())
+        } else {
+            hdl.write_zeroes_at(offset, len).await.map(|_| ())
+        }
+    }
+
+    /// Clear all of a newly created lvol which was handed clusters of its
+    /// pool that were not initialized yet.
+    pub(crate) async fn clear_uninitialized(&self) -> Result<(), Error> {
+        self.clear(0, self.size())
+            .await
+            .map_err(|e| Error::RepCreate {
+                source: Errno::EIO,
+                name: format!("{}: failed to clear: {}", self.name(), e),
+            })
+    }
+}
+
+impl Lvs {
+    /// name of the temporary lvol holding the clusters to initialize
+    fn init_lvol_name(&self) -> String {
+        format!("{}.init", self.uuid())
+    }
+
+    fn init_lvol(&self) -> Option<Lvol> {
+        let name = self.init_lvol_name();
+        self.lvols()?.find(|l| l.name() == name)
+    }
+
+    /// returns true if the data area of this pool is being initialized
+    pub fn is_initializing(&self) -> bool {
+        INITS
+            .lock()
+            .get(&self.uuid())
+            .map_or(false, |i| i.status.lock().state == InitState::Running)
+    }
+
+    /// Initialize the free space of this pool, in the background if lazy,
+    /// or before returning otherwise.
+    pub async fn init(&self, mode: InitMode) -> Result<(), Error> {
+        if mode == InitMode::None {
+            return Ok(());
+        }
+        if self.is_initializing() || self.init_lvol().is_some() {
+            return Err(Error::Invalid {
+                source: Errno::EBUSY,
+                msg: format!("pool {} is being initialized", self.name()),
+            });
+        }
+
+        let size = self.available();
+        info!(
+            "initializing {} bytes of pool {}, {:?}",
+            size,
+            self.name(),
+            mode
+        );
+        if size > 0 {
+            self.create_lvol(&self.init_lvol_name(), size, None, false)
+                .await?;
+        }
+        let init = self.track_init(size);
+
+        if mode == InitMode::Lazy {
+            let pool = Lvs(self.0);
+            Reactors::current()
+                .spawn_local(async move {
+                    let _ = pool.run_init(init).await;
+                })
+                .detach();
+            Ok(())
+        } else {
+            self.run_init(init).await
+        }
+    }
+
+    /// Resume the initialization of this pool if it was interrupted, which
+    /// is called as the pool is imported.
+    pub(crate) fn resume_init(&self) {
+        let lvol = match self.init_lvol() {
+            Some(lvol) if !self.is_initializing() => lvol,
+            _ => return,
+        };
+        info!(
+            "resuming the initialization of pool {}, {} bytes left",
+            self.name(),
+            lvol.size()
+        );
+        let init = self.track_init(lvol.size());
+        let pool = Lvs(self.0);
+        Reactors::current()
+            .spawn_local(async move {
+                let _ = pool.run_init(init).await;
+            })
+            .detach();
+    }
+
+    fn track_init(&self, size: u64) -> Arc<Init> {
+        let init = Arc::new(Init {
+            status: Mutex::new(PoolInit {
+                pool: self.name().to_string(),
+                uuid: self.uuid(),
+                state: InitState::Running,
+                size,
+                initialized: 0,
+                yielded: 0,
+                error: None,
+            }),
+            busy: AsyncMutex::new(()),
+        });
+        INITS.lock().insert(self.uuid(), init.clone());
+        init
+    }
+
+    /// Clear and shrink the temporary lvol a chunk at a time until none of
+    /// it is left. Stops without an error if the pool goes away.
+    async fn run_init(&self, init: Arc<Init>) -> Result<(), Error> {
+        let uuid = self.uuid();
+        let cluster_size = self.cluster_size().max(1);
+        let chunk = max(INIT_CHUNK / cluster_size, 1) * cluster_size;
+
+        let result = loop {
+            let pool = match Lvs::lookup_by_uuid(&uuid) {
+                Some(pool) => pool,
+                // exported, resumed as it is imported again
+                None => {
+                    INITS.lock().remove(&uuid);
+                    return Ok(());
+                }
+            };
+            let lvol = match pool.init_lvol() {
+                Some(lvol) => lvol,
+                None => break Ok(()),
+            };
+
+            let busy = init.busy.lock().await;
+            let remaining = lvol.size();
+            if remaining == 0 {
+                break lvol.destroy().await.map(|_| ());
+            }
+            let len = min(chunk, remaining);
+            if let Err(e) = lvol.clear(remaining - len, len).await {
+                break Err(Error::Invalid {
+                    source: Errno::EIO,
+                    msg: format!("failed to clear {}: {}", lvol.name(), e),
+                });
+            }
+            if let Err(e) = lvol.resize(remaining - len).await {
+                break Err(e);
+            }
+            drop(busy);
+
+            init.status.lock().initialized += len;
+            let _ = mayastor_sleep(INIT_PAUSE).await;
+        };
+
+        let mut status = init.status.lock();
+        match &result {
+            Ok(()) => {
+                info!("initialized pool {}", status.pool);
+                status.state = InitState::Completed;
+            }
+            Err(e) => {
+                error!("failed to initialize pool {}: {}", status.pool, e);
+                status.state = InitState::Failed;
+                status.error = Some(e.to_string());
+            }
+        }
+        result
+    }
+
+    /// Hand over to a replica about to be created the clusters it lacks out
+    /// of those not initialized yet. Returns true if it was handed any, in
+    /// which case the replica is to be cleared once created.
+    pub(crate) async fn yield_init(&self, bytes: u64) -> bool {
+        let init = match INITS.lock().get(&self.uuid()) {
+            Some(init) if init.status.lock().state == InitState::Running => {
+                init.clone()
+            }
+            _ => return false,
+        };
+        let _busy = init.busy.lock().await;
+        let lvol = match self.init_lvol() {
+            Some(lvol) => lvol,
+            None => return false,
+        };
+
+        let cluster_size = self.cluster_size().max(1);
+        let bytes = min(
+            (bytes + cluster_size - 1) / cluster_size * cluster_size,
+            lvol.size(),
+        );
+        if bytes == 0 || lvol.resize(lvol.size() - bytes).await.is_err() {
+            return false;
+        }
+        debug!(
+            "pool {}: handed {} bytes not initialized yet to a new replica",
+            self.name(),
+            bytes
+        );
+        init.status.lock().yielded += bytes;
+        true
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct InitArgs {
+    /// name of the pool
+    name: String,
+    /// lazy unless given
+    #[serde(default)]
+    mode: Option<InitMode>,
+}
+
+async fn init(args: InitArgs) -> Result<Vec<PoolInit>, Error> {
+    let pool = Lvs::lookup(&args.name).ok_or_else(|| Error::Invalid {
+        source: Errno::ENOENT,
+        msg: format!("pool {} not found", args.name),
+    })?;
+    pool.init(args.mode.unwrap_or(InitMode::Lazy)).await?;
+    Ok(pool_inits()
+        .into_iter()
+        .filter(|i| i.uuid == pool.uuid())
+        .collect())
+}
+
+async fn list(_: ()) -> Result<Vec<PoolInit>, Error> {
+    Ok(pool_inits())
+}
+
+/// Register the json-rpc methods to initialize pools and to report their
+/// progress.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>("pool_init", |args: InitArgs| {
+        init(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, Error>("pool_inits", |args: ()| {
+        list(args).boxed_local()
+    });
+}
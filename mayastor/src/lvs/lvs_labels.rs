@@ -0,0 +1,207 @@
+//!
+//! Pools carry labels, key/value pairs such as `media: ssd` or
+//! `zone: rack-1`, that describe the storage behind them and where it lives.
+//! The node reports them so that a control plane can make topology aware
+//! placement decisions, and replicas can be created on a pool selected by
+//! label.
+//!
+//! The lvol store has no room for the labels on disk, so they are kept in
+//! memory and saved with the pool configuration, from which they are restored
+//! when the pool is imported again.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    pin::Pin,
+    sync::Mutex,
+};
+
+use futures::FutureExt;
+use nix::errno::Errno;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::Share,
+    jsonrpc::jsonrpc_register,
+    lvs::{Error, Lvs},
+    subsys::PoolConfig,
+};
+
+/// Labels of a pool, ordered by key.
+pub type PoolLabels = BTreeMap<String, String>;
+
+/// Labels of all pools on this node, by pool name.
+static POOL_LABELS: Lazy<Mutex<HashMap<String, PoolLabels>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+impl Lvs {
+    /// returns the labels of this pool
+    pub fn labels(&self) -> PoolLabels {
+        POOL_LABELS
+            .lock()
+            .unwrap()
+            .get(self.name())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// replace the labels of this pool
+    pub fn set_labels(&self, labels: PoolLabels) {
+        let mut map = POOL_LABELS.lock().unwrap();
+        if labels.is_empty() {
+            map.remove(self.name());
+        } else {
+            map.insert(self.name().to_string(), labels);
+        }
+    }
+
+    /// move the labels the pool had under its old name to its current name
+    pub(crate) fn move_labels(&self, old_name: &str) {
+        let mut map = POOL_LABELS.lock().unwrap();
+        if let Some(labels) = map.remove(old_name) {
+            map.insert(self.name().to_string(), labels);
+        }
+    }
+
+    /// returns true if the pool has all labels of the selector with the same
+    /// value, an empty selector matches every pool
+    pub fn matches_labels(&self, selector: &PoolLabels) -> bool {
+        let labels = self.labels();
+        selector.iter().all(|(k, v)| labels.get(k) == Some(v))
+    }
+
+    /// select the pool matching the selector with the most free space that
+    /// can hold a replica of the given size
+    pub fn select_by_labels(selector: &PoolLabels, size: u64) -> Option<Lvs> {
+        Self::iter()
+            .filter(|p| p.available() >= size && p.matches_labels(selector))
+            .max_by_key(|p| p.available())
+    }
+}
+
+/// Pool as reported by the label json-rpc methods.
+#[derive(Debug, Serialize)]
+struct LabelledPool {
+    name: String,
+    uuid: String,
+    capacity: u64,
+    used: u64,
+    labels: PoolLabels,
+}
+
+impl From<Lvs> for LabelledPool {
+    fn from(lvs: Lvs) -> Self {
+        Self {
+            name: lvs.name().to_string(),
+            uuid: lvs.uuid(),
+            capacity: lvs.capacity(),
+            used: lvs.used(),
+            labels: lvs.labels(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetLabelsArgs {
+    /// name of the pool
+    name: String,
+    /// new labels of the pool, replacing the current ones
+    labels: PoolLabels,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListArgs {
+    /// only list pools having these labels
+    #[serde(default)]
+    selector: PoolLabels,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateReplicaArgs {
+    /// name (uuid) of the replica
+    uuid: String,
+    /// size of the replica in bytes
+    size: u64,
+    #[serde(default)]
+    thin: bool,
+    /// share the replica over NVMf
+    #[serde(default)]
+    share: bool,
+    /// labels the pool of the replica must have
+    #[serde(default)]
+    selector: PoolLabels,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateReplicaReply {
+    /// pool the replica was created on
+    pool: String,
+    uri: String,
+}
+
+async fn set_labels(args: SetLabelsArgs) -> Result<LabelledPool, Error> {
+    let pool = Lvs::lookup(&args.name).ok_or_else(|| Error::Invalid {
+        source: Errno::ENOENT,
+        msg: format!("pool {} not found", args.name),
+    })?;
+
+    info!("setting labels of pool {} to {:?}", args.name, args.labels);
+    pool.set_labels(args.labels);
+    PoolConfig::capture().export().await;
+
+    Ok(pool.into())
+}
+
+async fn list(args: ListArgs) -> Result<Vec<LabelledPool>, Error> {
+    Ok(Lvs::iter()
+        .filter(|p| p.matches_labels(&args.selector))
+        .map(LabelledPool::from)
+        .collect())
+}
+
+async fn create_replica(
+    args: CreateReplicaArgs,
+) -> Result<CreateReplicaReply, Error> {
+    let pool =
+        Lvs::select_by_labels(&args.selector, args.size).ok_or_else(|| {
+            Error::Invalid {
+                source: Errno::ENOSPC,
+                msg: format!(
+                    "no pool with labels {:?} and {} bytes free",
+                    args.selector, args.size
+                ),
+            }
+        })?;
+
+    let mut lvol = pool
+        .create_lvol(&args.uuid, args.size, None, args.thin)
+        .await?;
+
+    if args.share {
+        if let Err(e) = Pin::new(&mut lvol).share_nvmf(None).await {
+            let _ = lvol.destroy().await;
+            return Err(e);
+        }
+    }
+
+    Ok(CreateReplicaReply {
+        pool: pool.name().to_string(),
+        uri: lvol.share_uri().unwrap(),
+    })
+}
+
+/// Register the json-rpc methods to manage and select pools by label.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "pool_set_labels",
+        |args: SetLabelsArgs| set_labels(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "pool_list_labelled",
+        |args: ListArgs| list(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "replica_create_by_labels",
+        |args: CreateReplicaArgs| create_replica(args).boxed_local(),
+    );
+}
@@ -6,13 +6,16 @@ use std::{
     ptr::NonNull,
 };
 
-use futures::channel::oneshot;
+use futures::{channel::oneshot, FutureExt};
 use nix::errno::Errno;
 use pin_utils::core_reexport::fmt::Formatter;
+use serde::{Deserialize, Serialize};
 use spdk_rs::libspdk::{
     lvol_store_bdev,
     spdk_bs_free_cluster_count,
     spdk_bs_get_cluster_size,
+    spdk_bs_get_io_unit_size,
+    spdk_bs_get_page_size,
     spdk_bs_total_data_cluster_count,
     spdk_lvol,
     spdk_lvol_store,
@@ -27,6 +30,7 @@ use spdk_rs::libspdk::{
     vbdev_lvs_create_with_uuid,
     vbdev_lvs_destruct,
     vbdev_lvs_examine,
+    vbdev_lvs_rename,
     vbdev_lvs_unload,
     LVOL_CLEAR_WITH_NONE,
     LVOL_CLEAR_WITH_UNMAP,
@@ -38,9 +42,11 @@ use crate::{
     bdev::uri,
     core::{Bdev, IoType, Share, UntypedBdev},
     ffihelper::{cb_arg, pair, AsStr, ErrnoResult, FfiResult, IntoCString},
-    lvs::{Error, Lvol, PropName, PropValue},
+    jsonrpc::jsonrpc_register,
+    lvs::{replica_owner, Error, Lvol, PoolLabels, PropName, PropValue},
     nexus_uri::{bdev_destroy, NexusBdevError},
     pool::PoolArgs,
+    subsys::{lock_share, Config, PoolConfig},
 };
 
 impl From<*mut spdk_lvol_store> for Lvs {
@@ -49,6 +55,32 @@ impl From<*mut spdk_lvol_store> for Lvs {
     }
 }
 
+/// Size of the clusters of a pool created without a cluster size.
+pub const DEFAULT_CLUSTER_SIZE: u32 = 4 << 20;
+
+/// smallest cluster size, that of a metadata page of the blobstore
+const MIN_CLUSTER_SIZE: u32 = 4096;
+
+/// largest cluster size, beyond which replicas waste too much of the pool
+const MAX_CLUSTER_SIZE: u32 = 1 << 30;
+
+/// number of clusters a device must hold at least, the metadata of the
+/// blobstore taking some of them
+const MIN_CLUSTERS: u64 = 16;
+
+/// The layout of the blobstore of a pool.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolFormat {
+    pub name: String,
+    pub uuid: String,
+    pub capacity: u64,
+    pub cluster_size: u64,
+    /// size of a metadata page
+    pub md_page_size: u64,
+    /// unit of the IO of the blobstore, the block size of the device
+    pub io_unit_size: u64,
+}
+
 /// iterator over all lvol stores
 pub struct LvsIterator(*mut lvol_store_bdev);
 
@@ -180,6 +212,67 @@ impl Lvs {
         }
     }
 
+    /// returns the size of the clusters of the store in bytes
+    pub fn cluster_size(&self) -> u64 {
+        unsafe { spdk_bs_get_cluster_size(self.0.as_ref().blobstore) }
+    }
+
+    /// returns the layout of the blobstore of the store
+    pub fn format(&self) -> PoolFormat {
+        let blobs = unsafe { self.0.as_ref().blobstore };
+        PoolFormat {
+            name: self.name().to_string(),
+            uuid: self.uuid(),
+            capacity: self.capacity(),
+            cluster_size: self.cluster_size(),
+            md_page_size: unsafe { spdk_bs_get_page_size(blobs) },
+            io_unit_size: unsafe { spdk_bs_get_io_unit_size(blobs) } as u64,
+        }
+    }
+
+    /// Check that a pool with clusters of the given size can be created on
+    /// the bdev: the size is a power of two between the size of a metadata
+    /// page and 1GiB, a multiple of the block size of the bdev, and the
+    /// bdev holds enough clusters.
+    fn check_cluster_size(
+        name: &str,
+        bdev: &str,
+        cluster_size: u32,
+    ) -> Result<(), Error> {
+        let invalid = |msg: String| Error::Invalid {
+            source: Errno::EINVAL,
+            msg: format!("pool {}: {}", name, msg),
+        };
+        if !cluster_size.is_power_of_two()
+            || !(MIN_CLUSTER_SIZE ..= MAX_CLUSTER_SIZE).contains(&cluster_size)
+        {
+            return Err(invalid(format!(
+                "cluster size {} is not a power of two between {} and {}",
+                cluster_size, MIN_CLUSTER_SIZE, MAX_CLUSTER_SIZE
+            )));
+        }
+        let bdev = UntypedBdev::lookup_by_name(bdev)
+            .ok_or_else(|| invalid(format!("disk {} not found", bdev)))?;
+        if cluster_size % bdev.block_len() != 0 {
+            return Err(invalid(format!(
+                "cluster size {} is not a multiple of the block size {} of {}",
+                cluster_size,
+                bdev.block_len(),
+                bdev.name()
+            )));
+        }
+        if bdev.size_in_bytes() / (cluster_size as u64) < MIN_CLUSTERS {
+            return Err(invalid(format!(
+                "{} of {} bytes holds less than {} clusters of {} bytes",
+                bdev.name(),
+                bdev.size_in_bytes(),
+                MIN_CLUSTERS,
+                cluster_size
+            )));
+        }
+        Ok(())
+    }
+
     /// returns the available capacity
     pub fn available(&self) -> u64 {
         let blobs = unsafe { self.0.as_ref().blobstore };
@@ -285,7 +378,10 @@ impl Lvs {
                 name: name.into(),
             })
         } else {
+            lvs.migrate_formats().await;
             lvs.share_all().await;
+            lvs.resume_init();
+            lvs.watch_base_device();
             info!("The pool '{}' has been imported", name);
             Ok(lvs)
         }
@@ -331,6 +427,7 @@ impl Lvs {
         }?;
 
         let pool = Self::import(&args.name, &bdev).await?;
+        pool.set_labels(args.labels.clone());
 
         // if the uuid is provided for the import request check
         // for the pool uuid to make sure it is the correct one
@@ -349,12 +446,19 @@ impl Lvs {
         }
     }
 
-    /// Create a pool on base bdev
+    /// Create a pool on base bdev, with clusters of the given size or of the
+    /// default size
     pub async fn create(
         name: &str,
         bdev: &str,
         uuid: Option<String>,
+        cluster_size: Option<u32>,
     ) -> Result<Lvs, Error> {
+        if let Some(size) = cluster_size {
+            Self::check_cluster_size(name, bdev, size)?;
+        }
+        // 0 takes the default of the lvol store
+        let cluster_size = cluster_size.unwrap_or(0);
         let pool_name = name.into_cstring();
         let bdev_name = bdev.into_cstring();
 
@@ -366,7 +470,7 @@ impl Lvs {
                     bdev_name.as_ptr(),
                     pool_name.as_ptr(),
                     cuuid.as_ptr(),
-                    0,
+                    cluster_size,
                     // We used to clear a pool with UNMAP but that takes
                     // awfully long time on large SSDs (~
                     // can take an hour). Clearing the pool
@@ -381,7 +485,7 @@ impl Lvs {
                 vbdev_lvs_create(
                     bdev_name.as_ptr(),
                     pool_name.as_ptr(),
-                    0,
+                    cluster_size,
                     // We used to clear a pool with UNMAP but that takes
                     // awfully long time on large SSDs (~
                     // can take an hour). Clearing the pool
@@ -409,6 +513,7 @@ impl Lvs {
 
         match Self::lookup(name) {
             Some(pool) => {
+                pool.watch_base_device();
                 info!("The pool '{}' has been created on {}", name, bdev);
                 Ok(pool)
             }
@@ -443,19 +548,34 @@ impl Lvs {
             };
         }
 
-        let bdev = match parsed.create().await {
+        let (bdev, created) = match parsed.create().await {
             Err(e) => match e {
                 NexusBdevError::BdevExists {
                     ..
-                } => Ok(parsed.get_name()),
+                } => Ok((parsed.get_name(), false)),
                 _ => Err(Error::InvalidBdev {
                     source: e,
                     name: args.disks[0].clone(),
                 }),
             },
-            Ok(name) => Ok(name),
+            Ok(name) => Ok((name, true)),
         }?;
 
+        // the blobstore writes anywhere on the device, which a zoned device
+        // does not allow
+        if let Some(zones) =
+            UntypedBdev::lookup_by_name(&bdev).and_then(|b| b.zone_info())
+        {
+            if created {
+                let _ = parsed.destroy().await;
+            }
+            return Err(Error::ZonedDevice {
+                name: args.disks[0].clone(),
+                num_zones: zones.num_zones,
+                zone_size: zones.zone_size,
+            });
+        }
+
         match Self::import_from_args(args.clone()).await {
             Ok(pool) => Ok(pool),
             Err(Error::Import {
@@ -476,7 +596,14 @@ impl Lvs {
             Err(Error::Import {
                 source, ..
             }) if source == Errno::EILSEQ => {
-                match Self::create(&args.name, &bdev, args.uuid).await {
+                match Self::create(
+                    &args.name,
+                    &bdev,
+                    args.uuid.clone(),
+                    args.cluster_size,
+                )
+                .await
+                {
                     Err(create) => {
                         let _ = parsed.destroy().await.map_err(|_e| {
                             // we failed to delete the base_bdev be loud about it
@@ -486,7 +613,18 @@ impl Lvs {
                         });
                         Err(create)
                     }
-                    Ok(pool) => Ok(pool),
+                    Ok(pool) => {
+                        pool.set_labels(args.labels);
+                        let mode = Config::get().pool_opts.init;
+                        if let Err(e) = pool.init(mode).await {
+                            error!(
+                                "failed to initialize pool {}: {}",
+                                pool.name(),
+                                e
+                            );
+                        }
+                        Ok(pool)
+                    }
                 }
             }
             // some other error, bubble it back up
@@ -502,6 +640,14 @@ impl Lvs {
         let (s, r) = pair::<i32>();
 
         self.unshare_all().await;
+        if let Err(e) = self.sync().await {
+            error!("failed to sync pool {} before export: {}", pool, e);
+        }
+        self.drop_sync_policy();
+        self.set_labels(Default::default());
+        let _ = self.set_quota(Default::default());
+        self.set_trim_policy(Default::default());
+        self.unwatch_base_device();
 
         unsafe {
             vbdev_lvs_unload(self.0.as_ptr(), Some(Self::lvs_op_cb), cb_arg(s))
@@ -524,6 +670,52 @@ impl Lvs {
         Ok(())
     }
 
+    /// rename the pool, the new name is written to the super blob of the
+    /// lvol store and the aliases of its lvols change along with it, the
+    /// lvols themselves, and so their shares, are addressed by uuid and stay
+    /// as they are
+    #[tracing::instrument(level = "debug", err)]
+    pub async fn rename(&self, new_name: &str) -> Result<(), Error> {
+        let pool = self.name().to_string();
+        if pool == new_name {
+            return Ok(());
+        }
+
+        if Self::lookup(new_name).is_some() {
+            return Err(Error::Invalid {
+                source: Errno::EEXIST,
+                msg: format!("pool {} already exists", new_name),
+            });
+        }
+
+        let cname = new_name.into_cstring();
+        let (s, r) = pair::<i32>();
+
+        unsafe {
+            vbdev_lvs_rename(
+                self.0.as_ptr(),
+                cname.as_ptr(),
+                Some(Self::lvs_op_cb),
+                cb_arg(s),
+            )
+        };
+
+        r.await
+            .expect("callback gone while renaming lvs")
+            .to_result(|e| Error::Rename {
+                source: Errno::from_i32(e),
+                name: pool.clone(),
+            })?;
+
+        self.move_labels(&pool);
+        self.move_quota(&pool);
+        self.move_base_device_watch(&pool);
+        self.move_trim_policy(&pool);
+        self.move_sync_policy(&pool);
+        info!("pool {} renamed to {}", pool, new_name);
+        Ok(())
+    }
+
     /// unshare all lvols prior to export or destroy
     async fn unshare_all(&self) {
         for l in self.lvols().unwrap() {
@@ -541,6 +733,13 @@ impl Lvs {
     async fn share_all(&self) {
         if let Some(lvols) = self.lvols() {
             for mut l in lvols {
+                // the token of the share is not kept on disk, so the share
+                // lets in no host until it is set again
+                if let Ok(PropValue::ShareToken(true)) =
+                    l.get(PropName::ShareToken).await
+                {
+                    lock_share(&l.name());
+                }
                 if let Ok(prop) = l.get(PropName::Shared).await {
                     match prop {
                         PropValue::Shared(true) => {
@@ -555,7 +754,7 @@ impl Lvs {
                                 );
                             }
                         }
-                        PropValue::Shared(false) => {
+                        _ => {
                             debug!("{} not shared on disk", l.name())
                         }
                     }
@@ -573,6 +772,11 @@ impl Lvs {
 
         // when destroying a pool unshare all volumes
         self.unshare_all().await;
+        self.drop_sync_policy();
+        self.set_labels(Default::default());
+        let _ = self.set_quota(Default::default());
+        self.set_trim_policy(Default::default());
+        self.unwatch_base_device();
 
         let base_bdev = self.base_bdev();
 
@@ -653,6 +857,16 @@ impl Lvs {
             });
         };
 
+        if let Some(owner) = replica_owner(name) {
+            self.check_quota(&owner, size)?;
+        }
+
+        // a pool being initialized hands over clusters not initialized yet
+        // rather than failing the creation
+        let uncleared = !thin
+            && size > self.available()
+            && self.yield_init(size - self.available()).await;
+
         let (s, r) = pair::<ErrnoResult<*mut spdk_lvol>>();
 
         let cname = name.into_cstring();
@@ -688,7 +902,7 @@ impl Lvs {
             name: name.to_string(),
         })?;
 
-        let lvol = r
+        let mut lvol = r
             .await
             .expect("lvol creation callback dropped")
             .map_err(|e| Error::RepCreate {
@@ -697,9 +911,91 @@ impl Lvs {
             })
             .map(|lvol| Lvol(NonNull::new(lvol).unwrap()))?;
 
+        if uncleared {
+            lvol.clear_uninitialized().await?;
+        }
         lvol.wipe_super().await?;
+        lvol.set_format_version().await?;
 
         info!("created {}", lvol);
         Ok(lvol)
     }
 }
+
+#[derive(Debug, Deserialize)]
+struct RenameArgs {
+    /// current name of the pool
+    name: String,
+    /// name the pool is to be known by
+    new_name: String,
+}
+
+async fn rename(args: RenameArgs) -> Result<(), Error> {
+    let pool = Lvs::lookup(&args.name).ok_or_else(|| Error::Invalid {
+        source: Errno::ENOENT,
+        msg: format!("pool {} not found", args.name),
+    })?;
+
+    pool.rename(&args.new_name).await?;
+    // the saved configuration imports the pool under its new name
+    PoolConfig::capture().export().await;
+    Ok(())
+}
+
+/// Arguments of the pool_create json-rpc method, which creates a pool the
+/// way the CreatePool gRPC call does, with the layout of its blobstore. The
+/// metadata pages of the blobstore are of a fixed size and its IO unit is
+/// the block size of the disk, so the cluster size is the only part of the
+/// layout that can be chosen.
+#[derive(Debug, Deserialize)]
+struct CreateArgs {
+    name: String,
+    disks: Vec<String>,
+    #[serde(default)]
+    uuid: Option<String>,
+    #[serde(default)]
+    labels: PoolLabels,
+    /// size of the clusters in bytes
+    #[serde(default)]
+    cluster_size: Option<u32>,
+}
+
+async fn create(args: CreateArgs) -> Result<PoolFormat, Error> {
+    let pool = Lvs::create_or_import(PoolArgs {
+        name: args.name,
+        disks: args.disks,
+        uuid: args.uuid,
+        labels: args.labels,
+        cluster_size: args.cluster_size,
+    })
+    .await?;
+    PoolConfig::capture().export().await;
+    Ok(pool.format())
+}
+
+#[derive(Debug, Deserialize)]
+struct FormatArgs {
+    /// name of the pool
+    name: String,
+}
+
+async fn format(args: FormatArgs) -> Result<PoolFormat, Error> {
+    let pool = Lvs::lookup(&args.name).ok_or_else(|| Error::Invalid {
+        source: Errno::ENOENT,
+        msg: format!("pool {} not found", args.name),
+    })?;
+    Ok(pool.format())
+}
+
+/// Register the json-rpc methods to manage pools.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>("pool_rename", |args: RenameArgs| {
+        rename(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, Error>("pool_create", |args: CreateArgs| {
+        create(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, Error>("pool_format", |args: FormatArgs| {
+        format(args).boxed_local()
+    });
+}
@@ -0,0 +1,301 @@
+//!
+//! Quotas of the owners of the replicas of a pool.
+//!
+//! A pool shared by several tenants lets any of them take all of its space.
+//! Replicas can be given an owner, such as the tenant of their volume, and a
+//! pool can limit the space each owner takes on it to a percentage of its
+//! capacity: the same for every owner, and another for given owners. The
+//! space an owner takes on a pool is the size of its replicas there, thin
+//! replicas included, so that it does not grow past the quota as they fill.
+//! Replicas without an owner are not limited.
+//!
+//! The quota of the owner is checked as a replica is created, so an owner
+//! must be given to a replica before it is created, and as an owner is given
+//! to an existing replica. Changing the quota of a pool does not affect the
+//! replicas it already has.
+//!
+//! Owners and quotas are kept in memory and saved with the pool
+//! configuration, from which they are restored when the pool is imported
+//! again. They are set with the `replica_set_owner` and `pool_set_quota`
+//! json-rpc methods, and `pool_quota` reports the space each owner takes on
+//! a pool.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    sync::Mutex,
+};
+
+use futures::FutureExt;
+use nix::errno::Errno;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::UntypedBdev,
+    jsonrpc::jsonrpc_register,
+    lvs::{Error, Lvol, Lvs},
+    subsys::PoolConfig,
+};
+
+/// Space the owners of the replicas of a pool may take on it, as a
+/// percentage of its capacity.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoolQuota {
+    /// percentage of every owner, None for no limit
+    pub percent: Option<u8>,
+    /// percentages of given owners, in place of the one of every owner
+    pub owners: BTreeMap<String, u8>,
+}
+
+impl PoolQuota {
+    /// returns true if no owner is limited
+    pub fn is_empty(&self) -> bool {
+        self.percent.is_none() && self.owners.is_empty()
+    }
+
+    /// returns the percentage of the capacity of the pool the owner may take
+    fn percent_of(&self, owner: &str) -> Option<u8> {
+        self.owners.get(owner).copied().or(self.percent)
+    }
+}
+
+/// Quotas of the pools that have one, by pool name.
+static POOL_QUOTAS: Lazy<Mutex<HashMap<String, PoolQuota>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Owners of the replicas that have one, by replica name.
+static REPLICA_OWNERS: Lazy<Mutex<HashMap<String, String>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// returns the owner of the replica, created or about to be
+pub fn replica_owner(replica: &str) -> Option<String> {
+    REPLICA_OWNERS.lock().unwrap().get(replica).cloned()
+}
+
+/// Give the replica an owner, None to remove it. The replica may not exist
+/// yet, if it does its pool must have room for it in the quota of the owner.
+pub fn set_replica_owner(
+    replica: &str,
+    owner: Option<String>,
+) -> Result<(), Error> {
+    if let Some(owner) = &owner {
+        if owner.is_empty() {
+            return Err(Error::Invalid {
+                source: Errno::EINVAL,
+                msg: format!("empty owner for replica {}", replica),
+            });
+        }
+        if let Some(lvol) = UntypedBdev::lookup_by_name(replica)
+            .and_then(|b| Lvol::try_from(b).ok())
+        {
+            if let Some(pool) = Lvs::lookup(&lvol.pool()) {
+                if replica_owner(replica).as_ref() != Some(owner) {
+                    pool.check_quota(owner, lvol.size())?;
+                }
+            }
+        }
+    }
+
+    let mut owners = REPLICA_OWNERS.lock().unwrap();
+    match owner {
+        Some(owner) => owners.insert(replica.to_string(), owner),
+        None => owners.remove(replica),
+    };
+    Ok(())
+}
+
+impl Lvs {
+    /// returns the quota of this pool
+    pub fn quota(&self) -> PoolQuota {
+        POOL_QUOTAS
+            .lock()
+            .unwrap()
+            .get(self.name())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// replace the quota of this pool
+    pub fn set_quota(&self, quota: PoolQuota) -> Result<(), Error> {
+        let invalid = quota
+            .percent
+            .iter()
+            .chain(quota.owners.values())
+            .any(|p| !(1 ..= 100).contains(p));
+        if invalid {
+            return Err(Error::Invalid {
+                source: Errno::EINVAL,
+                msg: format!(
+                    "quota of pool {} must be 1 to 100 percent",
+                    self.name()
+                ),
+            });
+        }
+
+        let mut quotas = POOL_QUOTAS.lock().unwrap();
+        if quota.is_empty() {
+            quotas.remove(self.name());
+        } else {
+            quotas.insert(self.name().to_string(), quota);
+        }
+        Ok(())
+    }
+
+    /// move the quota the pool had under its old name to its current name
+    pub(crate) fn move_quota(&self, old_name: &str) {
+        let mut quotas = POOL_QUOTAS.lock().unwrap();
+        if let Some(quota) = quotas.remove(old_name) {
+            quotas.insert(self.name().to_string(), quota);
+        }
+    }
+
+    /// returns the owners of the replicas of this pool, by replica name
+    pub fn replica_owners(&self) -> BTreeMap<String, String> {
+        let owners = REPLICA_OWNERS.lock().unwrap();
+        self.lvols()
+            .into_iter()
+            .flatten()
+            .filter_map(|l| {
+                owners.get(&l.name()).map(|owner| (l.name(), owner.clone()))
+            })
+            .collect()
+    }
+
+    /// returns the space each owner takes on this pool, in bytes
+    pub fn owner_usage(&self) -> BTreeMap<String, u64> {
+        let owners = REPLICA_OWNERS.lock().unwrap();
+        let mut usage = BTreeMap::new();
+        for lvol in self.lvols().into_iter().flatten() {
+            if let Some(owner) = owners.get(&lvol.name()) {
+                *usage.entry(owner.clone()).or_insert(0) += lvol.size();
+            }
+        }
+        usage
+    }
+
+    /// Check that the owner may take the given number of bytes more on this
+    /// pool.
+    pub fn check_quota(&self, owner: &str, size: u64) -> Result<(), Error> {
+        let percent = match self.quota().percent_of(owner) {
+            Some(percent) => percent,
+            None => return Ok(()),
+        };
+        let limit = self.capacity() / 100 * percent as u64;
+        let used = self.owner_usage().get(owner).copied().unwrap_or(0);
+        if used + size > limit {
+            return Err(Error::QuotaExceeded {
+                owner: owner.to_string(),
+                pool: self.name().to_string(),
+                used,
+                size,
+                limit,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Quota of a pool and the space its owners take on it.
+#[derive(Debug, Serialize)]
+struct QuotaStatus {
+    pool: String,
+    capacity: u64,
+    quota: PoolQuota,
+    /// bytes taken by each owner
+    usage: BTreeMap<String, u64>,
+}
+
+impl From<Lvs> for QuotaStatus {
+    fn from(lvs: Lvs) -> Self {
+        Self {
+            pool: lvs.name().to_string(),
+            capacity: lvs.capacity(),
+            quota: lvs.quota(),
+            usage: lvs.owner_usage(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetQuotaArgs {
+    /// name of the pool
+    name: String,
+    /// new quota of the pool, replacing the current one
+    quota: PoolQuota,
+}
+
+#[derive(Debug, Deserialize)]
+struct QuotaArgs {
+    /// name of the pool
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetOwnerArgs {
+    /// name of the replica
+    name: String,
+    /// None removes the owner of the replica
+    owner: Option<String>,
+}
+
+/// look up a pool by name
+fn lookup(name: &str) -> Result<Lvs, Error> {
+    Lvs::lookup(name).ok_or_else(|| Error::Invalid {
+        source: Errno::ENOENT,
+        msg: format!("pool {} not found", name),
+    })
+}
+
+async fn set_quota(args: SetQuotaArgs) -> Result<QuotaStatus, Error> {
+    let pool = lookup(&args.name)?;
+    info!("setting quota of pool {} to {:?}", args.name, args.quota);
+    pool.set_quota(args.quota)?;
+    PoolConfig::capture().export().await;
+    Ok(pool.into())
+}
+
+async fn quota(args: QuotaArgs) -> Result<QuotaStatus, Error> {
+    Ok(lookup(&args.name)?.into())
+}
+
+async fn set_owner(args: SetOwnerArgs) -> Result<(), Error> {
+    info!("setting owner of replica {} to {:?}", args.name, args.owner);
+    set_replica_owner(&args.name, args.owner)?;
+    PoolConfig::capture().export().await;
+    Ok(())
+}
+
+/// Register the json-rpc methods to set the owners of the replicas and the
+/// quotas of the pools.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "pool_set_quota",
+        |args: SetQuotaArgs| set_quota(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>("pool_quota", |args: QuotaArgs| {
+        quota(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, Error>(
+        "replica_set_owner",
+        |args: SetOwnerArgs| set_owner(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_of() {
+        let quota = PoolQuota {
+            percent: Some(25),
+            owners: vec![("big".to_string(), 60)].into_iter().collect(),
+        };
+        assert_eq!(quota.percent_of("big"), Some(60));
+        assert_eq!(quota.percent_of("small"), Some(25));
+        assert_eq!(PoolQuota::default().percent_of("small"), None);
+        assert!(PoolQuota::default().is_empty());
+    }
+}
@@ -0,0 +1,307 @@
+//!
+//! Policy of the syncing of the metadata of the replicas of a pool.
+//!
+//! The properties of a replica, whether it is shared or protected, are kept
+//! in the metadata of its blob, which is written to disk every time one of
+//! them changes. Creating and sharing a replica thus waits for a metadata
+//! write on top of the creation itself. The sync policy of a pool trades
+//! this latency against the window in which a crash loses the change:
+//!
+//! - `write_through` writes the metadata as it changes, the default
+//! - `write_back` leaves the changed metadata in memory and writes that of all
+//!   changed replicas of the pool at the given interval
+//!
+//! The metadata of a write-back pool is also written by the `pool_sync`
+//! json-rpc method, before the pool is exported and when its policy is
+//! changed back to `write_through`. Creating, resizing and destroying
+//! replicas are synced by the blobstore itself whatever the policy.
+//!
+//! The policy is kept with the pool configuration and set with the
+//! `pool_set_sync_policy` json-rpc method, `pool_syncs` reports the pools
+//! with a write-back policy and their replicas waiting to be synced.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::{SecondsFormat, Utc};
+use futures::FutureExt;
+use nix::errno::Errno;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::Reactors,
+    jsonrpc::jsonrpc_register,
+    lvs::{Error, Lvs},
+    sleep::mayastor_sleep,
+    subsys::PoolConfig,
+};
+
+/// When the metadata of the replicas of a pool is written to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum SyncPolicy {
+    /// write the metadata as it changes
+    WriteThrough,
+    /// write the changed metadata at the given interval
+    WriteBack { interval_ms: u64 },
+}
+
+impl Default for SyncPolicy {
+    fn default() -> Self {
+        Self::WriteThrough
+    }
+}
+
+impl SyncPolicy {
+    /// returns true if the metadata is written as it changes
+    pub fn is_write_through(&self) -> bool {
+        *self == Self::WriteThrough
+    }
+}
+
+/// Sync policy of a pool and the replicas waiting to be synced.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PoolSync {
+    pub pool: String,
+    pub policy: SyncPolicy,
+    /// replicas whose metadata changed since it was last written
+    pub dirty: BTreeSet<String>,
+    /// number of replicas synced by the last sync
+    pub synced: u64,
+    /// time the last sync finished at
+    pub last_sync: Option<String>,
+    /// reason the last sync failed
+    pub error: Option<String>,
+    /// changed with the policy, stops the syncs of an old policy
+    #[serde(skip)]
+    generation: u64,
+}
+
+/// Sync state of the pools with a write-back policy, by name.
+static POOL_SYNCS: Lazy<Mutex<HashMap<String, PoolSync>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// returns the pools with a write-back policy and the state of their syncs
+pub fn pool_syncs() -> Vec<PoolSync> {
+    POOL_SYNCS.lock().unwrap().values().cloned().collect()
+}
+
+impl Lvs {
+    /// returns the sync policy of this pool
+    pub fn sync_policy(&self) -> SyncPolicy {
+        POOL_SYNCS
+            .lock()
+            .unwrap()
+            .get(self.name())
+            .map(|s| s.policy)
+            .unwrap_or_default()
+    }
+
+    /// Set the sync policy of this pool. Metadata left unwritten by a
+    /// write-back policy is written when the policy changes to
+    /// write-through.
+    pub async fn set_sync_policy(
+        &self,
+        policy: SyncPolicy,
+    ) -> Result<(), Error> {
+        let name = self.name().to_string();
+        if policy.is_write_through() {
+            if !POOL_SYNCS.lock().unwrap().contains_key(&name) {
+                return Ok(());
+            }
+            let result = self.sync().await;
+            POOL_SYNCS.lock().unwrap().remove(&name);
+            return result.map(|_| ());
+        }
+
+        let generation = {
+            let mut syncs = POOL_SYNCS.lock().unwrap();
+            let sync = syncs.entry(name.clone()).or_insert_with(|| PoolSync {
+                pool: name.clone(),
+                ..Default::default()
+            });
+            sync.policy = policy;
+            sync.generation += 1;
+            sync.generation
+        };
+
+        if let SyncPolicy::WriteBack {
+            interval_ms,
+        } = policy
+        {
+            let interval = Duration::from_millis(interval_ms.max(1));
+            Reactors::current()
+                .spawn_local(periodic_sync(name, generation, interval))
+                .detach();
+        }
+        Ok(())
+    }
+
+    /// forget the sync policy of this pool without writing its metadata
+    pub(crate) fn drop_sync_policy(&self) {
+        POOL_SYNCS.lock().unwrap().remove(self.name());
+    }
+
+    /// move the sync policy the pool had under its old name to its current
+    /// name
+    pub(crate) fn move_sync_policy(&self, old_name: &str) {
+        let mut syncs = POOL_SYNCS.lock().unwrap();
+        if let Some(mut sync) = syncs.remove(old_name) {
+            sync.pool = self.name().to_string();
+            syncs.insert(sync.pool.clone(), sync);
+        }
+    }
+
+    /// Returns true if the metadata of the replica, which has just changed,
+    /// is to be written later by the policy of the pool.
+    pub(crate) fn defer_sync(&self, replica: &str) -> bool {
+        match POOL_SYNCS.lock().unwrap().get_mut(self.name()) {
+            Some(sync) => {
+                sync.dirty.insert(replica.to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Write the metadata of the replicas of this pool which changed since
+    /// it was last written, returning the number of replicas synced.
+    pub async fn sync(&self) -> Result<u64, Error> {
+        let name = self.name().to_string();
+        let dirty = match POOL_SYNCS.lock().unwrap().get_mut(&name) {
+            Some(sync) => std::mem::take(&mut sync.dirty),
+            None => return Ok(0),
+        };
+        if dirty.is_empty() {
+            return Ok(0);
+        }
+
+        let mut synced = 0;
+        let mut result = Ok(());
+        let lvols = self.lvols().into_iter().flatten();
+        for lvol in lvols.filter(|l| dirty.contains(&l.name())) {
+            match lvol.sync_metadata().await {
+                Ok(()) => synced += 1,
+                Err(e) => {
+                    // written again by the next sync
+                    if let Some(sync) =
+                        POOL_SYNCS.lock().unwrap().get_mut(&name)
+                    {
+                        sync.dirty.insert(lvol.name());
+                    }
+                    result = Err(e);
+                }
+            }
+        }
+
+        if let Some(sync) = POOL_SYNCS.lock().unwrap().get_mut(&name) {
+            sync.synced = synced;
+            sync.last_sync =
+                Some(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true));
+            sync.error = result.as_ref().err().map(|e| e.to_string());
+        }
+        debug!("synced the metadata of {} replicas of {}", synced, name);
+        result.map(|_| synced)
+    }
+}
+
+/// sync the pool at the interval until its policy changes
+async fn periodic_sync(pool: String, generation: u64, interval: Duration) {
+    loop {
+        let _ = mayastor_sleep(interval).await;
+
+        match POOL_SYNCS.lock().unwrap().get(&pool) {
+            Some(sync) if sync.generation == generation => {}
+            _ => return,
+        }
+        match Lvs::lookup(&pool) {
+            Some(lvs) => {
+                if let Err(e) = lvs.sync().await {
+                    error!("failed to sync pool {}: {}", pool, e);
+                }
+            }
+            None => return,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetPolicyArgs {
+    /// name of the pool
+    name: String,
+    policy: SyncPolicy,
+}
+
+#[derive(Debug, Deserialize)]
+struct SyncArgs {
+    /// name of the pool
+    name: String,
+}
+
+fn lookup_pool(name: &str) -> Result<Lvs, Error> {
+    Lvs::lookup(name).ok_or_else(|| Error::Invalid {
+        source: Errno::ENOENT,
+        msg: format!("pool {} not found", name),
+    })
+}
+
+async fn set_policy(args: SetPolicyArgs) -> Result<SyncPolicy, Error> {
+    let pool = lookup_pool(&args.name)?;
+    info!(
+        "setting sync policy of pool {} to {:?}",
+        args.name, args.policy
+    );
+    pool.set_sync_policy(args.policy).await?;
+    PoolConfig::capture().export().await;
+    Ok(pool.sync_policy())
+}
+
+async fn sync(args: SyncArgs) -> Result<u64, Error> {
+    lookup_pool(&args.name)?.sync().await
+}
+
+async fn list(_: ()) -> Result<Vec<PoolSync>, Error> {
+    Ok(pool_syncs())
+}
+
+/// Register the json-rpc methods to set the sync policies of pools and to
+/// sync them.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "pool_set_sync_policy",
+        |args: SetPolicyArgs| set_policy(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>("pool_sync", |args: SyncArgs| {
+        sync(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, Error>("pool_syncs", |args: ()| {
+        list(args).boxed_local()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_format() {
+        let policy: SyncPolicy = serde_json::from_str(
+            r#"{"mode": "write_back", "interval_ms": 500}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            policy,
+            SyncPolicy::WriteBack {
+                interval_ms: 500
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&SyncPolicy::WriteThrough).unwrap(),
+            r#"{"mode":"write_through"}"#
+        );
+    }
+}
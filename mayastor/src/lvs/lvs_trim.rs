@@ -0,0 +1,377 @@
+//!
+//! Scheduling of the unmaps of the clusters freed on a pool.
+//!
+//! By default the clusters of a replica are unmapped as the replica is
+//! destroyed, which lets the SSD reclaim them right away but makes some
+//! models stall the IO of the other replicas of the pool while they erase.
+//! The trim policy of a pool moves these unmaps out of the way:
+//!
+//! - `immediate` unmaps the clusters as they are freed, the default
+//! - `batched` leaves the freed clusters mapped and trims the pool once the
+//!   given amount of data has been freed
+//! - `periodic` leaves the freed clusters mapped and trims the pool at the
+//!   given interval, like a periodic `fstrim`
+//!
+//! A trim allocates all free clusters of the pool to a temporary replica
+//! named `<pool>.trim`, unmaps it a chunk at a time with a pause between
+//! chunks and destroys it again, without unmapping it twice. The pool has no
+//! free space while it is being trimmed, so replicas can not be created on it
+//! meanwhile. Unmaps sent by the users of the replicas are always passed
+//! through, the policy only covers the clusters freed by destroying replicas.
+//!
+//! The policy is kept with the pool configuration and set with the
+//! `pool_set_trim_policy` json-rpc method. A pool is trimmed on demand with
+//! `pool_trim` and `pool_trims` reports when the pools were last trimmed.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use chrono::{SecondsFormat, Utc};
+use futures::FutureExt;
+use nix::errno::Errno;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use spdk_rs::libspdk::LVOL_CLEAR_WITH_NONE;
+
+use crate::{
+    core::{Bdev, Reactors},
+    jsonrpc::jsonrpc_register,
+    lvs::{Error, Lvol, Lvs},
+    sleep::mayastor_sleep,
+    subsys::PoolConfig,
+};
+
+/// number of bytes unmapped at once by a trim
+const TRIM_CHUNK: u64 = 64 * 1024 * 1024;
+
+/// pause between the unmaps of a trim, to let the IO of the pool through
+const TRIM_PAUSE: Duration = Duration::from_millis(10);
+
+/// When the clusters freed on a pool are unmapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum TrimPolicy {
+    /// unmap the clusters as they are freed
+    Immediate,
+    /// trim the pool once the given number of MiB have been freed
+    Batched { threshold_mb: u64 },
+    /// trim the pool at the given interval
+    Periodic { interval_secs: u64 },
+}
+
+impl Default for TrimPolicy {
+    fn default() -> Self {
+        Self::Immediate
+    }
+}
+
+impl TrimPolicy {
+    /// returns true if clusters are unmapped as they are freed
+    pub fn is_immediate(&self) -> bool {
+        *self == Self::Immediate
+    }
+}
+
+/// Trim policy of a pool and the state of its trims.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PoolTrim {
+    pub pool: String,
+    pub policy: TrimPolicy,
+    /// number of bytes freed but not unmapped since the last trim
+    pub pending: u64,
+    /// a trim of the pool is running
+    pub running: bool,
+    /// number of bytes unmapped by the last trim
+    pub trimmed: u64,
+    /// time the last trim finished at
+    pub last_trim: Option<String>,
+    /// reason the last trim failed
+    pub error: Option<String>,
+    /// changed with the policy, stops the periodic trims of an old policy
+    #[serde(skip)]
+    generation: u64,
+}
+
+/// Trim state of the pools with a policy other than the default, by name.
+static POOL_TRIMS: Lazy<Mutex<HashMap<String, PoolTrim>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// returns the trim policies of the pools and the state of their trims
+pub fn pool_trims() -> Vec<PoolTrim> {
+    POOL_TRIMS.lock().unwrap().values().cloned().collect()
+}
+
+impl Lvs {
+    /// returns the trim policy of this pool
+    pub fn trim_policy(&self) -> TrimPolicy {
+        POOL_TRIMS
+            .lock()
+            .unwrap()
+            .get(self.name())
+            .map(|t| t.policy)
+            .unwrap_or_default()
+    }
+
+    /// Set the trim policy of this pool. Clusters freed before are unmapped
+    /// by the next trim.
+    pub fn set_trim_policy(&self, policy: TrimPolicy) {
+        let name = self.name().to_string();
+        let generation = {
+            let mut trims = POOL_TRIMS.lock().unwrap();
+            if policy.is_immediate()
+                && trims.get(&name).map_or(true, |t| !t.running)
+            {
+                trims.remove(&name);
+                return;
+            }
+            let trim = trims.entry(name.clone()).or_insert_with(|| PoolTrim {
+                pool: name.clone(),
+                ..Default::default()
+            });
+            trim.policy = policy;
+            trim.generation += 1;
+            trim.generation
+        };
+
+        if let TrimPolicy::Periodic {
+            interval_secs,
+        } = policy
+        {
+            let interval = Duration::from_secs(interval_secs.max(1));
+            Reactors::current()
+                .spawn_local(periodic_trim(name, generation, interval))
+                .detach();
+        }
+    }
+
+    /// move the trim policy the pool had under its old name to its current
+    /// name
+    pub(crate) fn move_trim_policy(&self, old_name: &str) {
+        let mut trims = POOL_TRIMS.lock().unwrap();
+        if let Some(mut trim) = trims.remove(old_name) {
+            trim.pool = self.name().to_string();
+            trims.insert(trim.pool.clone(), trim);
+        }
+    }
+
+    /// Prepare an lvol of the pool to be destroyed, its clusters are left
+    /// mapped unless the policy of the pool unmaps them right away.
+    pub(crate) fn prepare_trim(&self, lvol: &mut Lvol) {
+        if !self.trim_policy().is_immediate() {
+            unsafe { lvol.0.as_mut().clear_method = LVOL_CLEAR_WITH_NONE };
+        }
+    }
+
+    /// Account for clusters freed on the pool, trimming it when the policy
+    /// says so.
+    pub(crate) fn trim_freed(&self, bytes: u64) {
+        let start = {
+            let mut trims = POOL_TRIMS.lock().unwrap();
+            match trims.get_mut(self.name()) {
+                Some(trim) => {
+                    trim.pending += bytes;
+                    match trim.policy {
+                        TrimPolicy::Batched {
+                            threshold_mb,
+                        } => {
+                            !trim.running
+                                && trim.pending >= threshold_mb * 1024 * 1024
+                        }
+                        _ => false,
+                    }
+                }
+                None => false,
+            }
+        };
+
+        if start {
+            let pool = Lvs(self.0);
+            Reactors::current()
+                .spawn_local(async move {
+                    let _ = pool.trim().await;
+                })
+                .detach();
+        }
+    }
+
+    /// Unmap all free clusters of the pool, returning the number of bytes
+    /// unmapped.
+    pub async fn trim(&self) -> Result<u64, Error> {
+        let name = self.name().to_string();
+        {
+            let mut trims = POOL_TRIMS.lock().unwrap();
+            let trim = trims.entry(name.clone()).or_insert_with(|| PoolTrim {
+                pool: name.clone(),
+                ..Default::default()
+            });
+            if trim.running {
+                return Err(Error::Invalid {
+                    source: Errno::EBUSY,
+                    msg: format!("pool {} is being trimmed", name),
+                });
+            }
+            trim.running = true;
+            trim.pending = 0;
+        }
+
+        info!("trimming pool {}", name);
+        let result = self.unmap_free().await;
+
+        let mut trims = POOL_TRIMS.lock().unwrap();
+        if let Some(trim) = trims.get_mut(&name) {
+            trim.running = false;
+            match &result {
+                Ok(trimmed) => {
+                    info!("trimmed {} bytes of pool {}", trimmed, name);
+                    trim.trimmed = *trimmed;
+                    // the temporary lvol freed what it unmapped
+                    trim.pending = trim.pending.saturating_sub(*trimmed);
+                    trim.error = None;
+                    trim.last_trim = Some(
+                        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+                    );
+                }
+                Err(e) => {
+                    error!("failed to trim pool {}: {}", name, e);
+                    trim.error = Some(e.to_string());
+                }
+            }
+            if trim.policy.is_immediate() {
+                trims.remove(&name);
+            }
+        }
+        result
+    }
+
+    /// allocate the free clusters of the pool to a temporary lvol and unmap
+    /// them
+    async fn unmap_free(&self) -> Result<u64, Error> {
+        let size = self.available();
+        if size == 0 {
+            return Ok(0);
+        }
+
+        let name = format!("{}.trim", self.name());
+        let mut lvol = self.create_lvol(&name, size, None, false).await?;
+        unsafe { lvol.0.as_mut().clear_method = LVOL_CLEAR_WITH_NONE };
+
+        let result = async {
+            let hdl = Bdev::open(&lvol.as_bdev(), true)
+                .and_then(|desc| desc.into_handle())?;
+            let mut offset = 0;
+            while offset < size {
+                let len = std::cmp::min(TRIM_CHUNK, size - offset);
+                hdl.unmap_at(offset, len).await?;
+                offset += len;
+                let _ = mayastor_sleep(TRIM_PAUSE).await;
+            }
+            Ok(size)
+        }
+        .await
+        .map_err(|e: crate::core::CoreError| Error::Invalid {
+            source: Errno::EIO,
+            msg: format!("failed to unmap {}: {}", name, e),
+        });
+
+        lvol.destroy().await?;
+        result
+    }
+}
+
+/// trim the pool at the interval until its policy changes
+async fn periodic_trim(pool: String, generation: u64, interval: Duration) {
+    loop {
+        let _ = mayastor_sleep(interval).await;
+
+        let due = match POOL_TRIMS.lock().unwrap().get(&pool) {
+            Some(trim) if trim.generation == generation => {
+                trim.pending > 0 || trim.last_trim.is_none()
+            }
+            _ => return,
+        };
+        if due {
+            match Lvs::lookup(&pool) {
+                Some(lvs) => {
+                    let _ = lvs.trim().await;
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetPolicyArgs {
+    /// name of the pool
+    name: String,
+    policy: TrimPolicy,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrimArgs {
+    /// name of the pool
+    name: String,
+}
+
+fn lookup_pool(name: &str) -> Result<Lvs, Error> {
+    Lvs::lookup(name).ok_or_else(|| Error::Invalid {
+        source: Errno::ENOENT,
+        msg: format!("pool {} not found", name),
+    })
+}
+
+async fn set_policy(args: SetPolicyArgs) -> Result<TrimPolicy, Error> {
+    let pool = lookup_pool(&args.name)?;
+    info!(
+        "setting trim policy of pool {} to {:?}",
+        args.name, args.policy
+    );
+    pool.set_trim_policy(args.policy);
+    PoolConfig::capture().export().await;
+    Ok(pool.trim_policy())
+}
+
+async fn trim(args: TrimArgs) -> Result<u64, Error> {
+    lookup_pool(&args.name)?.trim().await
+}
+
+async fn list(_: ()) -> Result<Vec<PoolTrim>, Error> {
+    Ok(pool_trims())
+}
+
+/// Register the json-rpc methods to set the trim policies of pools and to
+/// trim them.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "pool_set_trim_policy",
+        |args: SetPolicyArgs| set_policy(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>("pool_trim", |args: TrimArgs| {
+        trim(args).boxed_local()
+    });
+    jsonrpc_register::<_, _, _, Error>("pool_trims", |args: ()| {
+        list(args).boxed_local()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_format() {
+        let policy: TrimPolicy = serde_json::from_str(
+            r#"{"mode": "batched", "threshold_mb": 1024}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            policy,
+            TrimPolicy::Batched {
+                threshold_mb: 1024
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&TrimPolicy::Immediate).unwrap(),
+            r#"{"mode":"immediate"}"#
+        );
+    }
+}
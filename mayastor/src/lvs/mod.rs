@@ -1,7 +1,41 @@
 pub use error::Error;
 pub use lvol::{Lvol, PropName, PropValue};
-pub use lvs_pool::Lvs;
+pub use lvol_erase::{replica_erasures, DeletionPolicy, ReplicaErasure};
+pub use lvol_import::{replica_imports, ImportState, ReplicaImport};
+pub use lvs_compact::{pool_compactions, CompactState, PoolCompaction};
+pub use lvs_events::{pool_device_events, PoolDeviceEvent};
+pub use lvs_init::{pool_inits, InitMode, InitState, PoolInit};
+pub use lvs_labels::PoolLabels;
+pub use lvs_pool::{Lvs, PoolFormat, DEFAULT_CLUSTER_SIZE};
+pub use lvs_quota::{replica_owner, set_replica_owner, PoolQuota};
+pub use lvs_sync::{pool_syncs, PoolSync, SyncPolicy};
+pub use lvs_trim::{pool_trims, PoolTrim, TrimPolicy};
 
 mod error;
 mod lvol;
+mod lvol_erase;
+mod lvol_import;
+mod lvol_protect;
+mod lvs_compact;
+mod lvs_events;
+mod lvs_init;
+mod lvs_labels;
 mod lvs_pool;
+mod lvs_quota;
+mod lvs_sync;
+mod lvs_trim;
+
+/// Register the json-rpc methods of the pools and their replicas.
+pub(crate) fn register_jsonrpc_methods() {
+    lvs_pool::register_jsonrpc_methods();
+    lvs_labels::register_jsonrpc_methods();
+    lvs_quota::register_jsonrpc_methods();
+    lvol_erase::register_jsonrpc_methods();
+    lvol_import::register_jsonrpc_methods();
+    lvol_protect::register_jsonrpc_methods();
+    lvs_compact::register_jsonrpc_methods();
+    lvs_trim::register_jsonrpc_methods();
+    lvs_sync::register_jsonrpc_methods();
+    lvs_init::register_jsonrpc_methods();
+    lvs_events::register_jsonrpc_methods();
+}
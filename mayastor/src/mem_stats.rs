@@ -0,0 +1,344 @@
+//!
+//! Accounting of the memory used by mayastor, per subsystem.
+//!
+//! The memory limit of a mayastor pod used to be set by trial and error, as
+//! nothing told what the memory went to. The `mem_stats` json-rpc method
+//! returns the memory held by each of the major subsystems, computed from
+//! the sizes of the structures they hold when it is called:
+//!
+//! - the channels of every nexus and the handles of its children, one of each
+//!   per core, and the write cache and IO ring buffers of the nexus
+//! - the copy buffers of the running rebuild jobs
+//! - the memory pools of IO contexts and the IO and data buffer pools of the
+//!   bdev layer, which are allocated in hugepages
+//! - the cluster bitmap of every pool and the cluster maps of its replicas,
+//!   which the blobstore keeps in memory
+//!
+//! The figures are those of the structures themselves and leave out the
+//! allocator overhead, so they are a lower bound, next to which the resident
+//! and hugepage memory of the process are reported.
+
+use std::mem::{size_of, size_of_val};
+
+use futures::FutureExt;
+use serde::Serialize;
+use spdk_rs::{
+    libspdk::{
+        spdk_bdev_get_opts,
+        spdk_bdev_io,
+        spdk_bdev_opts,
+        SPDK_BDEV_LARGE_BUF_MAX_SIZE,
+        SPDK_BDEV_SMALL_BUF_MAX_SIZE,
+    },
+    ChannelTraverseStatus,
+};
+
+use crate::{
+    bdev::{
+        device::bdev_io_ctx_pool_stats,
+        nexus::{
+            nexus_iter,
+            nexus_lookup,
+            IoDebugEvent,
+            NexusChannel,
+            NexusChannelInner,
+            TraceRecord,
+        },
+        nvmx::nvme_io_ctx_pool_stats,
+    },
+    core::{for_each_channel, mempool::MemoryPoolStats, BlockDeviceHandle},
+    jsonrpc::{jsonrpc_register, JsonRpcError},
+    lvs::Lvs,
+    rebuild::{ClientOperations, RebuildJob},
+};
+
+/// Memory of the process as seen by the kernel.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ProcessMemory {
+    pub rss_bytes: u64,
+    /// highest resident memory since the process started
+    pub rss_peak_bytes: u64,
+    pub hugepage_bytes: u64,
+}
+
+/// Memory of a nexus, over all cores.
+#[derive(Debug, Default, Serialize)]
+pub struct NexusMemory {
+    pub name: String,
+    pub channels: u64,
+    pub channel_bytes: u64,
+    /// handles of the children held by the channels
+    pub handles: u64,
+    pub handle_bytes: u64,
+    pub write_cache_bytes: u64,
+    /// ring buffers of the IO trace and of the IO debug log, at their
+    /// capacity
+    pub ring_bytes: u64,
+    pub total_bytes: u64,
+}
+
+/// Memory of a rebuild job.
+#[derive(Debug, Serialize)]
+pub struct RebuildMemory {
+    pub nexus: String,
+    pub destination: String,
+    pub buffer_bytes: u64,
+}
+
+/// Memory the blobstore of a pool keeps for it.
+#[derive(Debug, Serialize)]
+pub struct PoolMemory {
+    pub name: String,
+    pub clusters: u64,
+    pub cluster_bitmap_bytes: u64,
+    pub replicas: u64,
+    /// the cluster maps of the replicas, which cover all their clusters
+    /// whether allocated or not
+    pub cluster_map_bytes: u64,
+    pub total_bytes: u64,
+}
+
+/// Memory held by mayastor, per subsystem.
+#[derive(Debug, Serialize)]
+pub struct MemStats {
+    /// None if it could not be read
+    pub process: Option<ProcessMemory>,
+    pub nexuses: Vec<NexusMemory>,
+    pub rebuilds: Vec<RebuildMemory>,
+    pub memory_pools: Vec<MemoryPoolStats>,
+    pub pools: Vec<PoolMemory>,
+    /// sum of the memory accounted for above
+    pub accounted_bytes: u64,
+}
+
+/// parse the memory of the process out of /proc/self/status
+fn parse_status(status: &str) -> ProcessMemory {
+    let mut mem = ProcessMemory::default();
+    for line in status.lines() {
+        let mut fields = line.split_whitespace();
+        let field = match fields.next() {
+            Some("VmRSS:") => &mut mem.rss_bytes,
+            Some("VmHWM:") => &mut mem.rss_peak_bytes,
+            Some("HugetlbPages:") => &mut mem.hugepage_bytes,
+            _ => continue,
+        };
+        *field = fields
+            .next()
+            .and_then(|kb| kb.parse::<u64>().ok())
+            .unwrap_or_default()
+            * 1024;
+    }
+    mem
+}
+
+fn process_memory() -> Option<ProcessMemory> {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .map(|s| parse_status(&s))
+}
+
+/// Context to add up the memory of the channels of a nexus.
+struct ChannelMemCtx {
+    mem: NexusMemory,
+}
+
+fn channel_mem_cb(
+    channel: &mut NexusChannel,
+    ctx: &mut ChannelMemCtx,
+) -> ChannelTraverseStatus {
+    let inner = channel.inner();
+    let handles = inner.readers.iter().chain(inner.writers.iter());
+    let mem = &mut ctx.mem;
+
+    mem.channels += 1;
+    mem.channel_bytes += (size_of::<NexusChannelInner>()
+        + (inner.readers.capacity() + inner.writers.capacity())
+            * size_of::<Box<dyn BlockDeviceHandle>>())
+        as u64;
+    for handle in handles {
+        mem.handles += 1;
+        mem.handle_bytes += size_of_val(&**handle) as u64;
+    }
+    ChannelTraverseStatus::Ok
+}
+
+/// add up the memory of a nexus on all cores
+async fn nexus_memory(name: &str) -> Option<NexusMemory> {
+    let nexus = nexus_lookup(name)?;
+    let mem = NexusMemory {
+        name: name.to_string(),
+        write_cache_bytes: nexus
+            .write_cache()
+            .map(|_| nexus.write_cache_opts().size)
+            .unwrap_or_default(),
+        ring_bytes: nexus
+            .trace_stats()
+            .map(|s| (s.opts.capacity * size_of::<TraceRecord>()) as u64)
+            .unwrap_or_default()
+            + nexus
+                .io_debug_stats()
+                .map(|s| (s.capacity * size_of::<IoDebugEvent>()) as u64)
+                .unwrap_or_default(),
+        ..Default::default()
+    };
+
+    let mut mem = if nexus.has_io_device {
+        let r = for_each_channel(
+            &*nexus,
+            ChannelMemCtx {
+                mem,
+            },
+            channel_mem_cb,
+        );
+        r.await.ok()?.mem
+    } else {
+        mem
+    };
+    mem.total_bytes = mem.channel_bytes
+        + mem.handle_bytes
+        + mem.write_cache_bytes
+        + mem.ring_bytes;
+    Some(mem)
+}
+
+/// the IO and data buffer pools of the bdev layer
+fn bdev_pools() -> Vec<MemoryPoolStats> {
+    let opts = spdk_bdev_opts::default();
+    unsafe {
+        spdk_bdev_get_opts(
+            &opts as *const _ as *mut spdk_bdev_opts,
+            size_of::<spdk_bdev_opts>() as u64,
+        )
+    };
+    [
+        (
+            "bdev_io",
+            opts.bdev_io_pool_size as u64,
+            size_of::<spdk_bdev_io>() as u64,
+        ),
+        (
+            "bdev_small_buf",
+            opts.small_buf_pool_size as u64,
+            SPDK_BDEV_SMALL_BUF_MAX_SIZE as u64,
+        ),
+        (
+            "bdev_large_buf",
+            opts.large_buf_pool_size as u64,
+            SPDK_BDEV_LARGE_BUF_MAX_SIZE as u64,
+        ),
+    ]
+    .iter()
+    .map(|&(name, capacity, element_size)| MemoryPoolStats {
+        name: name.to_string(),
+        capacity,
+        element_size,
+        in_use: None,
+        bytes: capacity * element_size,
+    })
+    .collect()
+}
+
+fn pool_memory(lvs: &Lvs) -> PoolMemory {
+    let cluster_size = lvs.cluster_size().max(1);
+    let clusters = lvs.capacity() / cluster_size;
+    let (replicas, replica_clusters) = lvs
+        .lvols()
+        .map(|lvols| {
+            lvols.fold((0, 0), |(n, c), l| {
+                (n + 1, c + (l.size() + cluster_size - 1) / cluster_size)
+            })
+        })
+        .unwrap_or_default();
+
+    let cluster_bitmap_bytes = (clusters + 7) / 8;
+    let cluster_map_bytes = replica_clusters * size_of::<u64>() as u64;
+    PoolMemory {
+        name: lvs.name().to_string(),
+        clusters,
+        cluster_bitmap_bytes,
+        replicas,
+        cluster_map_bytes,
+        total_bytes: cluster_bitmap_bytes + cluster_map_bytes,
+    }
+}
+
+impl MemStats {
+    /// Add up the memory held by the subsystems. This must be called from a
+    /// SPDK thread.
+    pub async fn collect() -> Self {
+        let names = nexus_iter().map(|n| n.name.clone()).collect::<Vec<_>>();
+        let mut nexuses = Vec::new();
+        for name in names {
+            nexuses.extend(nexus_memory(&name).await);
+        }
+
+        let rebuilds = RebuildJob::list()
+            .into_iter()
+            .map(|j| {
+                let stats = j.stats();
+                RebuildMemory {
+                    nexus: j.nexus.clone(),
+                    destination: j.destination.clone(),
+                    buffer_bytes: stats.tasks_total
+                        * stats.segment_size_blks
+                        * stats.block_size,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut memory_pools = bdev_pools();
+        memory_pools.extend(bdev_io_ctx_pool_stats());
+        memory_pools.extend(nvme_io_ctx_pool_stats());
+
+        let pools = Lvs::iter().map(|l| pool_memory(&l)).collect::<Vec<_>>();
+
+        let accounted_bytes =
+            nexuses.iter().map(|n| n.total_bytes).sum::<u64>()
+                + rebuilds.iter().map(|r| r.buffer_bytes).sum::<u64>()
+                + memory_pools.iter().map(|p| p.bytes).sum::<u64>()
+                + pools.iter().map(|p| p.total_bytes).sum::<u64>();
+
+        Self {
+            process: process_memory(),
+            nexuses,
+            rebuilds,
+            memory_pools,
+            pools,
+            accounted_bytes,
+        }
+    }
+}
+
+async fn mem_stats(_: ()) -> Result<MemStats, JsonRpcError> {
+    Ok(MemStats::collect().await)
+}
+
+/// Register the json-rpc method returning the memory used per subsystem.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, JsonRpcError>("mem_stats", |args: ()| {
+        mem_stats(args).boxed_local()
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_status, ProcessMemory};
+
+    #[test]
+    fn status() {
+        let status = "Name:\tmayastor\n\
+                      VmHWM:\t  204800 kB\n\
+                      VmRSS:\t  102400 kB\n\
+                      HugetlbPages:\t 2097152 kB\n\
+                      Threads:\t8\n";
+        assert_eq!(
+            parse_status(status),
+            ProcessMemory {
+                rss_bytes: 100 << 20,
+                rss_peak_bytes: 200 << 20,
+                hugepage_bytes: 2 << 30,
+            }
+        );
+        assert_eq!(parse_status(""), ProcessMemory::default());
+    }
+}
@@ -83,6 +83,19 @@ pub enum NexusBdevError {
     ))]
     BdevWrongUuid { name: String, uuid: String },
 
+    // Connected to a namespace with a different UUID.
+    #[snafu(display(
+        "Connected to '{}' but its namespace has UUID '{}' instead of '{}'",
+        name,
+        found,
+        expected
+    ))]
+    NamespaceWrongUuid {
+        name: String,
+        expected: String,
+        found: String,
+    },
+
     // BDEV is not found.
     #[snafu(display("BDEV '{}' could not be found", name))]
     BdevNotFound { name: String },
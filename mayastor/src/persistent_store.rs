@@ -5,7 +5,7 @@
 //! the etcd-client crate. This crate has a dependency on the tokio async
 //! runtime.
 use crate::{
-    core,
+    core::runtime::{self, Completion},
     store::{
         etcd::Etcd,
         store_defs::{
@@ -19,7 +19,6 @@ use crate::{
         },
     },
 };
-use futures::channel::oneshot;
 use once_cell::sync::OnceCell;
 use serde_json::Value;
 use snafu::ResultExt;
@@ -177,36 +176,22 @@ impl PersistentStore {
 
     /// Executes a future representing a store operation (i.e. put, get, delete)
     /// on the tokio runtime.
-    /// A channel is returned which is signalled when the operation completes.
+    /// A completion is returned which is signalled when the operation
+    /// completes.
     /// If an operation times out, reconnect to the backing store before failing
     /// the operation.
     fn execute_store_op<T: 'static + Send>(
         f: impl Future<Output = Result<T, StoreError>> + Send + 'static,
-    ) -> oneshot::Receiver<Result<T, StoreError>> {
-        let (tx, rx) = oneshot::channel::<Result<T, StoreError>>();
-        core::runtime::spawn(async move {
-            let result = match tokio::time::timeout(STORE_OP_TIMEOUT, f).await {
+    ) -> Completion<Result<T, StoreError>> {
+        runtime::spawn_await(async move {
+            match tokio::time::timeout(STORE_OP_TIMEOUT, f).await {
                 Ok(result) => result,
                 Err(_) => {
                     Self::reconnect().await;
                     Err(StoreError::OpTimeout {})
                 }
-            };
-
-            // Execute the sending of the result on a "Mayastor thread".
-            let thread = core::Mthread::get_init();
-            let rx = thread
-                .spawn_local(async move {
-                    if tx.send(result).is_err() {
-                        tracing::error!(
-                            "Failed to send completion for 'put' request."
-                        );
-                    }
-                })
-                .expect("Failed to send future to Mayastor thread");
-            let _ = rx.await;
-        });
-        rx
+            }
+        })
     }
 
     /// Determine if the persistent store has been enabled.
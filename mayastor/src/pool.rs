@@ -21,7 +21,10 @@ use spdk_rs::libspdk::{
     vbdev_lvol_store_next,
 };
 
-use crate::core::{Bdev, UntypedBdev};
+use crate::{
+    core::{Bdev, UntypedBdev},
+    lvs::PoolLabels,
+};
 
 /// Structure representing a pool which comprises lvol store and
 /// underlying bdev.
@@ -137,13 +140,18 @@ impl From<Pool> for rpc::Pool {
 }
 
 /// PoolArgs is used to translate the input for the grpc
-/// Create/Import requests which contains name, uuid & disks.
+/// Create/Import requests which contains name, uuid & disks, and the pool
+/// labels and cluster size restored from the pool configuration.
 /// This help us avoid importing grpc structs in the actual lvs mod
 #[derive(Clone, Debug)]
 pub struct PoolArgs {
     pub name: String,
     pub disks: Vec<String>,
     pub uuid: Option<String>,
+    pub labels: PoolLabels,
+    /// size of the clusters of a created pool in bytes, the default of the
+    /// lvol store if None
+    pub cluster_size: Option<u32>,
 }
 
 /// PoolBackend is the type of pool underneath Lvs, Lvm, etc
@@ -2,6 +2,8 @@
 mod rebuild_api;
 /// Rebuild implementation module
 pub mod rebuild_impl;
+/// Rebuild state machine module
+pub(crate) mod rebuild_state;
 
 pub use rebuild_api::*;
 // for the tests only
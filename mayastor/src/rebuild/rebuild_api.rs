@@ -1,6 +1,6 @@
 #![warn(missing_docs)]
 
-use std::fmt;
+use std::{collections::BTreeSet, fmt};
 
 use crossbeam::channel::{Receiver, Sender};
 use futures::channel::oneshot;
@@ -13,7 +13,7 @@ use crate::{
 };
 use spdk_rs::DmaError;
 
-use super::rebuild_impl::*;
+use super::{rebuild_impl::*, rebuild_state::RebuildStates};
 
 #[derive(Debug, Snafu, Clone)]
 #[snafu(visibility = "pub(crate)")]
@@ -122,6 +122,9 @@ pub struct RebuildJob {
     pub(super) range: std::ops::Range<u64>,
     pub(super) next: u64,
     pub(super) segment_size_blks: u64,
+    /// first block of each segment to copy, all segments of the range if
+    /// none
+    pub(super) segments: Option<BTreeSet<u64>>,
     pub(super) task_pool: RebuildTasks,
     pub(super) notify_fn: fn(String, String) -> (),
     /// channel used to signal rebuild update
@@ -239,6 +242,11 @@ impl RebuildJob {
         }
     }
 
+    /// All rebuild job instances
+    pub fn list() -> Vec<&'static Self> {
+        Self::get_instances().values().map(|j| j.as_ref()).collect()
+    }
+
     /// Number of rebuild job instances
     pub fn count() -> usize {
         Self::get_instances().len()
@@ -257,6 +265,34 @@ impl RebuildJob {
         }
     }
 
+    /// Restricts the rebuild to the segments holding the given ranges of
+    /// blocks, instead of the whole range of the job. Must be called before
+    /// the job is started.
+    pub fn restrict(&mut self, ranges: &[std::ops::Range<u64>]) {
+        let segments = self.segments.get_or_insert_with(BTreeSet::new);
+        for r in ranges {
+            let start = std::cmp::max(r.start, self.range.start);
+            let end = std::cmp::min(r.end, self.range.end);
+            if start >= end {
+                continue;
+            }
+            let mut blk =
+                start - (start - self.range.start) % self.segment_size_blks;
+            while blk < end {
+                segments.insert(blk);
+                blk += self.segment_size_blks;
+            }
+        }
+    }
+
+    /// Returns a channel which can be waited on for the completion of the
+    /// job, which may not have been started yet
+    pub fn completion(&mut self) -> oneshot::Receiver<RebuildState> {
+        let end_channel = oneshot::channel();
+        self.complete_chan.push(end_channel.0);
+        end_channel.1
+    }
+
     /// ClientOperations trait
     /// todo: nexus should use this for all interaction with the job
     pub fn as_client(&mut self) -> &mut impl ClientOperations {
@@ -18,17 +18,24 @@ use spdk_rs::{
 use crate::{
     bdev::{device_open, nexus::VerboseError},
     core::{
+        fault_injection::{self, FaultAction, FaultDomain},
         BlockDevice,
         BlockDeviceDescriptor,
         BlockDeviceHandle,
+        CoreError,
+        IoType,
         RangeContext,
         Reactors,
         UntypedBdev,
     },
     nexus_uri::bdev_get_name,
+    sleep::mayastor_sleep,
 };
 
-use super::rebuild_api::*;
+use super::{
+    rebuild_api::*,
+    rebuild_state::{RebuildAction, RebuildOperation, RebuildStates},
+};
 
 /// Global list of rebuild jobs using a static OnceCell
 pub(super) struct RebuildInstances {
@@ -145,16 +152,24 @@ impl RebuildJob {
         let source_hdl = Self::get_io_handle(&*src_descriptor)?;
         let destination_hdl = Self::get_io_handle(&*dst_descriptor)?;
 
+        let nexus_descriptor = UntypedBdev::open_by_name(nexus, false)
+            .context(BdevNotFound {
+                bdev: nexus.to_string(),
+            })?;
+
+        // the range is in blocks of the nexus, which may be smaller than the
+        // blocks of the children
+        let block_size = nexus_descriptor.get_bdev().block_len() as u64;
+
         if !Self::validate(
             source_hdl.get_device(),
             destination_hdl.get_device(),
             &range,
+            block_size,
         ) {
             return Err(RebuildError::InvalidParameters {});
         };
 
-        // validation passed, block size is the same for both
-        let block_size = destination_hdl.get_device().block_len();
         let segment_size_blks = SEGMENT_SIZE / block_size;
 
         let mut tasks = RebuildTasks {
@@ -184,11 +199,6 @@ impl RebuildJob {
             nexus.to_string(),
         );
 
-        let nexus_descriptor = UntypedBdev::open_by_name(&nexus, false)
-            .context(BdevNotFound {
-                bdev: nexus.to_string(),
-            })?;
-
         Ok(Self {
             nexus,
             nexus_descriptor,
@@ -198,6 +208,7 @@ impl RebuildJob {
             range,
             block_size,
             segment_size_blks,
+            segments: None,
             task_pool: tasks,
             notify_fn,
             notify_chan: unbounded::<RebuildState>(),
@@ -218,16 +229,13 @@ impl RebuildJob {
             match self.await_one_task().await {
                 Some(r) => match r.error {
                     None => {
-                        match self.states.pending {
-                            None | Some(RebuildState::Running) => {
-                                self.start_task_by_id(r.id);
-                            }
-                            _ => {
-                                // await all active tasks as we might still have
-                                // ongoing IO. do we need a timeout?
-                                self.await_all_tasks().await;
-                                break;
-                            }
+                        if self.states.keep_running() {
+                            self.start_task_by_id(r.id);
+                        } else {
+                            // await all active tasks as we might still have
+                            // ongoing IO. do we need a timeout?
+                            self.await_all_tasks().await;
+                            break;
                         }
                     }
                     Some(e) => {
@@ -343,15 +351,36 @@ impl RebuildJob {
             &mut copy_buffer
         };
 
+        let offset = blk * self.block_size;
+        Self::inject_fault(
+            &*self.src_descriptor,
+            IoType::Read,
+            offset,
+            copy_buffer.len(),
+        )
+        .await
+        .context(ReadIoError {
+            bdev: &self.source,
+        })?;
         source_hdl
-            .read_at(blk * self.block_size, copy_buffer)
+            .read_at(offset, copy_buffer)
             .await
             .context(ReadIoError {
                 bdev: &self.source,
             })?;
 
+        Self::inject_fault(
+            &*self.dst_descriptor,
+            IoType::Write,
+            offset,
+            copy_buffer.len(),
+        )
+        .await
+        .context(WriteIoError {
+            bdev: &self.destination,
+        })?;
         destination_hdl
-            .write_at(blk * self.block_size, copy_buffer)
+            .write_at(offset, copy_buffer)
             .await
             .context(WriteIoError {
                 bdev: &self.destination,
@@ -360,6 +389,43 @@ impl RebuildJob {
         Ok(())
     }
 
+    /// Hold back or fail a copy IO a fault is injected in, if any.
+    async fn inject_fault(
+        descriptor: &dyn BlockDeviceDescriptor,
+        io_type: IoType,
+        offset: u64,
+        len: u64,
+    ) -> Result<(), CoreError> {
+        if !fault_injection::is_active() {
+            return Ok(());
+        }
+        let action = fault_injection::inject(
+            FaultDomain::Rebuild,
+            &descriptor.get_device().device_name(),
+            io_type,
+            len,
+        );
+        match action {
+            Some(FaultAction::Fail) if io_type == IoType::Read => {
+                Err(CoreError::ReadFailed {
+                    offset,
+                    len,
+                })
+            }
+            Some(FaultAction::Fail) => Err(CoreError::WriteFailed {
+                offset,
+                len,
+            }),
+            Some(action) => {
+                if let Some(delay) = action.delay() {
+                    let _ = mayastor_sleep(delay).await;
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
     fn get_io_handle(
         descriptor: &dyn BlockDeviceDescriptor,
     ) -> Result<Box<dyn BlockDeviceHandle>, RebuildError> {
@@ -386,17 +452,22 @@ impl RebuildJob {
     }
 
     /// Check if the source and destination block devices are compatible for
-    /// rebuild
+    /// rebuild, ie the range, in blocks of the given size, is within both
+    /// devices and made of whole blocks of either of them
     fn validate(
         source: &dyn BlockDevice,
         destination: &dyn BlockDevice,
         range: &std::ops::Range<u64>,
+        block_size: u64,
     ) -> bool {
         // todo: make sure we don't overwrite the labels
         let data_partition_start = 0;
-        range.within(data_partition_start .. source.num_blocks())
-            && range.within(data_partition_start .. destination.num_blocks())
-            && source.block_len() == destination.block_len()
+        let bytes = range.start * block_size .. range.end * block_size;
+        [source, destination].iter().all(|dev| {
+            bytes.within(data_partition_start .. dev.size_in_bytes())
+                && bytes.start % dev.block_len() == 0
+                && bytes.end % dev.block_len() == 0
+        })
     }
 
     /// reconcile the pending state to the current and clear the pending
@@ -465,36 +536,15 @@ impl RebuildJob {
     }
 }
 
-#[derive(Debug)]
-/// Operations used to control the state of the job
-enum RebuildOperation {
-    /// Client Operations
-    ///
-    /// Starts the job for the first time
-    Start,
-    /// Stops the job (eg, child being removed)
-    Stop,
-    /// Pauses the job
-    Pause,
-    /// Resumes the previously paused job
-    Resume,
-    /// Internal Operations
-    ///
-    /// an IO error has occurred
-    Fail,
-    /// rebuild completed successfully
-    Complete,
-}
-
-impl std::fmt::Display for RebuildOperation {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
 impl ClientOperations for RebuildJob {
     fn stats(&self) -> RebuildStats {
-        let blocks_total = self.range.end - self.range.start;
+        let blocks_total = match &self.segments {
+            Some(segments) => std::cmp::min(
+                segments.len() as u64 * self.segment_size_blks,
+                self.range.end - self.range.start,
+            ),
+            None => self.range.end - self.range.start,
+        };
 
         // segment size may not be aligned to the total size
         let blocks_recovered = std::cmp::min(
@@ -502,7 +552,11 @@ impl ClientOperations for RebuildJob {
             blocks_total,
         );
 
-        let progress = (blocks_recovered * 100) / blocks_total;
+        let progress = if blocks_total == 0 {
+            100
+        } else {
+            (blocks_recovered * 100) / blocks_total
+        };
 
         info!(
             "State: {}, Src: {}, Dst: {}, range: {:?}, next: {}, \
@@ -594,6 +648,12 @@ impl RebuildJob {
                                 * the bdev */
             };
         }
+
+        // a job paused after its last segment was copied has nothing left to
+        // do once resumed
+        if self.task_pool.active == 0 {
+            self.complete();
+        }
     }
 
     fn start_task_by_id(&mut self, id: usize) {
@@ -643,14 +703,17 @@ impl RebuildJob {
     /// Sends one segment worth of data in a reactor future and notifies the
     /// management channel. Returns the next segment offset to rebuild, if any
     fn send_segment_task(&self, id: usize) -> Option<u64> {
-        if self.next >= self.range.end {
+        // skip the segments the rebuild is not restricted to
+        let blk = match &self.segments {
+            Some(segments) => *segments.range(self.next ..).next()?,
+            None => self.next,
+        };
+
+        if blk >= self.range.end {
             None
         } else {
-            let blk = self.next;
-            let next = std::cmp::min(
-                self.next + self.segment_size_blks,
-                self.range.end,
-            );
+            let next =
+                std::cmp::min(blk + self.segment_size_blks, self.range.end);
             let name = self.destination.clone();
 
             Reactors::current().send_future(async move {
@@ -673,68 +736,6 @@ impl RebuildJob {
     }
 }
 
-#[derive(Debug, Default)]
-pub(super) struct RebuildStates {
-    /// Current state of the rebuild job
-    pub current: RebuildState,
-
-    /// Pending state for the rebuild job
-    pending: Option<RebuildState>,
-}
-
-impl std::fmt::Display for RebuildStates {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
-impl Default for RebuildState {
-    fn default() -> Self {
-        RebuildState::Init
-    }
-}
-
-impl RebuildStates {
-    /// Set's the next pending state
-    /// if one is already set then override only if flag is set
-    pub(self) fn set_pending(
-        &mut self,
-        state: RebuildState,
-        override_pending: bool,
-    ) -> Result<(), RebuildError> {
-        match self.pending {
-            Some(pending) if !override_pending && (pending != state) => {
-                Err(RebuildError::StatePending {
-                    state: pending.to_string(),
-                })
-            }
-            _ => {
-                if self.current != state {
-                    self.pending = Some(state);
-                } else {
-                    self.pending = None;
-                }
-                Ok(())
-            }
-        }
-    }
-
-    /// a change to `state` is pending
-    fn pending_equals(&self, state: RebuildState) -> bool {
-        self.pending == Some(state)
-    }
-
-    /// reconcile the pending state into the current state
-    fn reconcile(&mut self) -> RebuildState {
-        if let Some(pending) = self.pending {
-            self.current = pending;
-            self.pending = None;
-        }
-
-        self.current
-    }
-}
-
 impl RebuildJob {
     /// Client operations are now allowed to skip over previous operations
     fn exec_client_op(
@@ -756,86 +757,17 @@ impl RebuildJob {
         op: RebuildOperation,
         override_pending: bool,
     ) -> Result<(), RebuildError> {
-        type S = RebuildState;
-        let e = RebuildError::OpError {
-            operation: op.to_string(),
-            state: self.states.to_string(),
-        };
-
         trace!(
             "Executing operation {} with override {}",
             op,
             override_pending
         );
 
-        match op {
-            RebuildOperation::Start => {
-                match self.state() {
-                    // start only allowed when... starting
-                    S::Stopped | S::Paused | S::Failed | S::Completed => Err(e),
-                    // for idempotence sake
-                    S::Running => Ok(()),
-                    S::Init => {
-                        self.states.set_pending(S::Running, false)?;
-                        self.schedule();
-                        Ok(())
-                    }
-                }
-            }
-            RebuildOperation::Stop => {
-                match self.state() {
-                    // We're already stopping anyway, so all is well
-                    S::Failed | S::Completed => Err(e),
-                    // for idempotence sake
-                    S::Stopped => Ok(()),
-                    S::Running => {
-                        self.states
-                            .set_pending(S::Stopped, override_pending)?;
-                        Ok(())
-                    }
-                    S::Init | S::Paused => {
-                        self.states
-                            .set_pending(S::Stopped, override_pending)?;
-
-                        // The rebuild is not running so we need to reconcile
-                        self.reconcile();
-                        Ok(())
-                    }
-                }
-            }
-            RebuildOperation::Pause => match self.state() {
-                S::Stopped | S::Failed | S::Completed => Err(e),
-                S::Init | S::Running | S::Paused => {
-                    self.states.set_pending(S::Paused, false)?;
-                    Ok(())
-                }
-            },
-            RebuildOperation::Resume => match self.state() {
-                S::Init | S::Stopped | S::Failed | S::Completed => Err(e),
-                S::Running | S::Paused => {
-                    self.states.set_pending(S::Running, false)?;
-                    self.schedule();
-                    Ok(())
-                }
-            },
-            RebuildOperation::Fail => match self.state() {
-                S::Init | S::Stopped | S::Paused | S::Completed => Err(e),
-                // for idempotence sake
-                S::Failed => Ok(()),
-                S::Running => {
-                    self.states.set_pending(S::Failed, override_pending)?;
-                    Ok(())
-                }
-            },
-            RebuildOperation::Complete => match self.state() {
-                S::Init | S::Paused | S::Stopped | S::Failed | S::Completed => {
-                    Err(e)
-                }
-                S::Running => {
-                    self.states.set_pending(S::Completed, override_pending)?;
-                    Ok(())
-                }
-            },
+        match self.states.exec(op, override_pending)? {
+            RebuildAction::None => {}
+            RebuildAction::Schedule => self.schedule(),
+            RebuildAction::Reconcile => self.reconcile(),
         }
+        Ok(())
     }
 }
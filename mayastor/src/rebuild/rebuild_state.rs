@@ -0,0 +1,229 @@
+#![warn(missing_docs)]
+//! State machine of a rebuild job, kept free of any IO so that it can be
+//! driven by the simulation tests of the nexus as well as by the job itself.
+
+use super::{RebuildError, RebuildState};
+
+#[derive(Debug, Clone, Copy)]
+/// Operations used to control the state of the job
+pub(crate) enum RebuildOperation {
+    /// Client Operations
+    ///
+    /// Starts the job for the first time
+    Start,
+    /// Stops the job (eg, child being removed)
+    Stop,
+    /// Pauses the job
+    Pause,
+    /// Resumes the previously paused job
+    Resume,
+    /// Internal Operations
+    ///
+    /// an IO error has occurred
+    Fail,
+    /// rebuild completed successfully
+    Complete,
+}
+
+impl std::fmt::Display for RebuildOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// What the job has to do after an operation was accepted
+pub(crate) enum RebuildAction {
+    /// nothing, the pending state is picked up by the running job
+    None,
+    /// the copy loop has to be (re)started
+    Schedule,
+    /// the job is not running so the pending state must be reconciled now
+    Reconcile,
+}
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct RebuildStates {
+    /// Current state of the rebuild job
+    pub current: RebuildState,
+
+    /// Pending state for the rebuild job
+    pending: Option<RebuildState>,
+}
+
+impl std::fmt::Display for RebuildStates {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Default for RebuildState {
+    fn default() -> Self {
+        RebuildState::Init
+    }
+}
+
+impl RebuildStates {
+    /// Set's the next pending state
+    /// if one is already set then override only if flag is set
+    fn set_pending(
+        &mut self,
+        state: RebuildState,
+        override_pending: bool,
+    ) -> Result<(), RebuildError> {
+        match self.pending {
+            Some(pending) if !override_pending && (pending != state) => {
+                Err(RebuildError::StatePending {
+                    state: pending.to_string(),
+                })
+            }
+            _ => {
+                if self.current != state {
+                    self.pending = Some(state);
+                } else {
+                    self.pending = None;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// a change to `state` is pending
+    pub(crate) fn pending_equals(&self, state: RebuildState) -> bool {
+        self.pending == Some(state)
+    }
+
+    /// the copy loop may start another segment, ie no other state than
+    /// running is pending
+    pub(crate) fn keep_running(&self) -> bool {
+        matches!(self.pending, None | Some(RebuildState::Running))
+    }
+
+    /// reconcile the pending state into the current state
+    pub(crate) fn reconcile(&mut self) -> RebuildState {
+        if let Some(pending) = self.pending {
+            self.current = pending;
+            self.pending = None;
+        }
+
+        self.current
+    }
+
+    /// Single state machine where all operations are handled, returning
+    /// what the job has to do for the operation to take effect
+    pub(crate) fn exec(
+        &mut self,
+        op: RebuildOperation,
+        override_pending: bool,
+    ) -> Result<RebuildAction, RebuildError> {
+        type S = RebuildState;
+        let e = RebuildError::OpError {
+            operation: op.to_string(),
+            state: self.to_string(),
+        };
+
+        match op {
+            RebuildOperation::Start => {
+                match self.current {
+                    // start only allowed when... starting
+                    S::Stopped | S::Paused | S::Failed | S::Completed => Err(e),
+                    // for idempotence sake
+                    S::Running => Ok(RebuildAction::None),
+                    S::Init => {
+                        self.set_pending(S::Running, false)?;
+                        Ok(RebuildAction::Schedule)
+                    }
+                }
+            }
+            RebuildOperation::Stop => {
+                match self.current {
+                    // We're already stopping anyway, so all is well
+                    S::Failed | S::Completed => Err(e),
+                    // for idempotence sake
+                    S::Stopped => Ok(RebuildAction::None),
+                    S::Running => {
+                        self.set_pending(S::Stopped, override_pending)?;
+                        Ok(RebuildAction::None)
+                    }
+                    S::Init | S::Paused => {
+                        self.set_pending(S::Stopped, override_pending)?;
+                        // The rebuild is not running so we need to reconcile
+                        Ok(RebuildAction::Reconcile)
+                    }
+                }
+            }
+            RebuildOperation::Pause => match self.current {
+                S::Stopped | S::Failed | S::Completed => Err(e),
+                S::Init | S::Running | S::Paused => {
+                    self.set_pending(S::Paused, false)?;
+                    Ok(RebuildAction::None)
+                }
+            },
+            RebuildOperation::Resume => match self.current {
+                S::Init | S::Stopped | S::Failed | S::Completed => Err(e),
+                S::Running | S::Paused => {
+                    self.set_pending(S::Running, false)?;
+                    Ok(RebuildAction::Schedule)
+                }
+            },
+            RebuildOperation::Fail => match self.current {
+                S::Init | S::Stopped | S::Paused | S::Completed => Err(e),
+                // for idempotence sake
+                S::Failed => Ok(RebuildAction::None),
+                S::Running => {
+                    self.set_pending(S::Failed, override_pending)?;
+                    Ok(RebuildAction::None)
+                }
+            },
+            RebuildOperation::Complete => match self.current {
+                S::Init | S::Paused | S::Stopped | S::Failed | S::Completed => {
+                    Err(e)
+                }
+                S::Running => {
+                    self.set_pending(S::Completed, override_pending)?;
+                    Ok(RebuildAction::None)
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pause_resume() {
+        let mut states = RebuildStates::default();
+        assert_eq!(
+            states.exec(RebuildOperation::Start, false).unwrap(),
+            RebuildAction::Schedule
+        );
+        assert!(states.pending_equals(RebuildState::Running));
+        assert_eq!(states.reconcile(), RebuildState::Running);
+
+        states.exec(RebuildOperation::Pause, false).unwrap();
+        assert!(!states.keep_running());
+        // a client operation may not skip over a pending one
+        assert!(states.exec(RebuildOperation::Stop, false).is_err());
+        assert_eq!(states.reconcile(), RebuildState::Paused);
+
+        assert_eq!(
+            states.exec(RebuildOperation::Resume, false).unwrap(),
+            RebuildAction::Schedule
+        );
+        assert!(states.keep_running());
+    }
+
+    #[test]
+    fn internal_ops_override() {
+        let mut states = RebuildStates::default();
+        states.exec(RebuildOperation::Start, false).unwrap();
+        states.reconcile();
+        states.exec(RebuildOperation::Pause, false).unwrap();
+        states.exec(RebuildOperation::Fail, true).unwrap();
+        assert_eq!(states.reconcile(), RebuildState::Failed);
+        assert!(states.exec(RebuildOperation::Resume, false).is_err());
+        assert!(states.exec(RebuildOperation::Complete, true).is_err());
+    }
+}
@@ -12,6 +12,7 @@ use spdk_rs::libspdk::{spdk_lvol, vbdev_lvol_get_from_bdev};
 
 use crate::{
     core::{Bdev, UntypedBdev},
+    grpc::error_detail::ErrorDetail,
     subsys::NvmfError,
     target,
 };
@@ -50,13 +51,15 @@ pub enum Error {
 
 impl From<Error> for tonic::Status {
     fn from(error: Error) -> Self {
-        match error {
+        let detail = ErrorDetail::from(&error);
+        let status = match error {
             Error::InvalidProtocol {
                 ..
             } => Self::invalid_argument(error.to_string()),
             Error::ReplicaNotFound {} => Self::not_found(error.to_string()),
             _ => Self::internal(error.to_string()),
-        }
+        };
+        detail.attach(status)
     }
 }
 
@@ -0,0 +1,222 @@
+//!
+//! Status of the restoring of the objects of mayastor as it starts.
+//!
+//! As it starts, mayastor imports the pools of its pool configuration, a
+//! given number of them at a time, before it serves gRPC calls. With many
+//! pools this takes a while, during which the `startup_status` json-rpc
+//! method, served from the start, reports which pools were restored, which
+//! are being restored and which failed to, with the reason why.
+//!
+//! Nexuses are not restored by mayastor itself but recreated by the control
+//! plane once mayastor is up, so pools are the only objects reported for
+//! now.
+
+use std::{sync::Mutex, time::Instant};
+
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+
+use crate::jsonrpc::{jsonrpc_register, JsonRpcError};
+
+/// Phase of the startup of mayastor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StartupPhase {
+    /// the subsystems are being initialized
+    Starting,
+    /// waiting for another mayastor to hand its pools over
+    Standby,
+    /// the objects of the previous run are being restored
+    Restoring,
+    /// all objects were restored, whether successfully or not
+    Ready,
+}
+
+/// Kind of an object restored at startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectKind {
+    Pool,
+}
+
+/// State of the restoring of an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreState {
+    Pending,
+    Running,
+    Restored,
+    Failed,
+}
+
+/// Restoring of an object at startup.
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreStatus {
+    pub kind: ObjectKind,
+    pub name: String,
+    pub state: RestoreState,
+    /// time it took to restore the object, once done
+    pub duration_ms: Option<u64>,
+    /// reason the object could not be restored
+    pub error: Option<String>,
+    #[serde(skip)]
+    started: Option<Instant>,
+}
+
+/// Status of the startup of mayastor.
+#[derive(Debug, Clone, Serialize)]
+pub struct StartupStatus {
+    pub phase: StartupPhase,
+    /// number of objects restored at a time
+    pub concurrency: usize,
+    /// time since mayastor started, or that it took to start once ready
+    pub elapsed_ms: u64,
+    pub objects: Vec<RestoreStatus>,
+}
+
+struct Startup {
+    started: Instant,
+    finished: Option<Instant>,
+    phase: StartupPhase,
+    concurrency: usize,
+    objects: Vec<RestoreStatus>,
+}
+
+static STARTUP: Lazy<Mutex<Startup>> = Lazy::new(|| {
+    Mutex::new(Startup {
+        started: Instant::now(),
+        finished: None,
+        phase: StartupPhase::Starting,
+        concurrency: 1,
+        objects: Vec::new(),
+    })
+});
+
+/// Record the start of mayastor, which the elapsed time is counted from.
+pub(crate) fn starting() {
+    Lazy::force(&STARTUP);
+}
+
+/// Record that mayastor waits for another one to hand its pools over.
+pub(crate) fn standby() {
+    STARTUP.lock().unwrap().phase = StartupPhase::Standby;
+}
+
+/// Record the objects about to be restored, the given number at a time.
+pub(crate) fn restoring(
+    kind: ObjectKind,
+    names: impl Iterator<Item = String>,
+    concurrency: usize,
+) {
+    let mut startup = STARTUP.lock().unwrap();
+    startup.phase = StartupPhase::Restoring;
+    startup.concurrency = concurrency;
+    startup.objects.extend(names.map(|name| RestoreStatus {
+        kind,
+        name,
+        state: RestoreState::Pending,
+        duration_ms: None,
+        error: None,
+        started: None,
+    }));
+}
+
+/// Record the restoring of an object as having moved to the given state,
+/// with the reason why if it failed.
+pub(crate) fn set_state(
+    kind: ObjectKind,
+    name: &str,
+    state: RestoreState,
+    error: Option<String>,
+) {
+    let mut startup = STARTUP.lock().unwrap();
+    let object = startup
+        .objects
+        .iter_mut()
+        .find(|o| o.kind == kind && o.name == name);
+    if let Some(object) = object {
+        match state {
+            RestoreState::Running => object.started = Some(Instant::now()),
+            RestoreState::Restored | RestoreState::Failed => {
+                object.duration_ms =
+                    object.started.map(|s| s.elapsed().as_millis() as u64);
+            }
+            RestoreState::Pending => {}
+        }
+        object.state = state;
+        object.error = error;
+    }
+}
+
+/// Record that all objects were restored and mayastor is ready.
+pub(crate) fn ready() {
+    let mut startup = STARTUP.lock().unwrap();
+    if startup.phase != StartupPhase::Ready {
+        startup.phase = StartupPhase::Ready;
+        startup.finished = Some(Instant::now());
+        info!(
+            "startup done in {}ms",
+            startup.started.elapsed().as_millis()
+        );
+    }
+}
+
+/// returns the status of the startup of mayastor
+pub fn startup_status() -> StartupStatus {
+    let startup = STARTUP.lock().unwrap();
+    let elapsed = match startup.finished {
+        Some(finished) => finished.duration_since(startup.started),
+        None => startup.started.elapsed(),
+    };
+    StartupStatus {
+        phase: startup.phase,
+        concurrency: startup.concurrency,
+        elapsed_ms: elapsed.as_millis() as u64,
+        objects: startup.objects.clone(),
+    }
+}
+
+async fn status(_: ()) -> Result<StartupStatus, JsonRpcError> {
+    Ok(startup_status())
+}
+
+/// Register the json-rpc method returning the status of the startup.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, JsonRpcError>("startup_status", |args: ()| {
+        status(args).boxed_local()
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn restore() {
+        starting();
+        restoring(
+            ObjectKind::Pool,
+            vec!["p0".to_string(), "p1".to_string()].into_iter(),
+            4,
+        );
+        set_state(ObjectKind::Pool, "p0", RestoreState::Running, None);
+        set_state(ObjectKind::Pool, "p0", RestoreState::Restored, None);
+        set_state(
+            ObjectKind::Pool,
+            "p1",
+            RestoreState::Failed,
+            Some("no such disk".to_string()),
+        );
+
+        let status = startup_status();
+        assert_eq!(status.phase, StartupPhase::Restoring);
+        assert_eq!(status.concurrency, 4);
+        assert_eq!(status.objects[0].state, RestoreState::Restored);
+        assert!(status.objects[0].duration_ms.is_some());
+        assert_eq!(status.objects[1].error.as_deref(), Some("no such disk"));
+
+        ready();
+        assert_eq!(startup_status().phase, StartupPhase::Ready);
+    }
+}
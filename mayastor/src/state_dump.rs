@@ -0,0 +1,431 @@
+//!
+//! Structured snapshot of the in-memory state of mayastor.
+//!
+//! When mayastor panics, the state of its nexuses, their children, the
+//! rebuild jobs, the pools and replicas and the reactors is written as json
+//! to a crash file in the crash directory before the process aborts, so that
+//! postmortems do not rely solely on logs. The default panic handling runs
+//! afterwards, so the process still aborts and leaves a core dump behind.
+//!
+//! Most of this state may only be walked on a SPDK thread. A panic on any
+//! other thread has the state collected by the init thread, waiting for it a
+//! short while only as the reactor may be what is stuck.
+//!
+//! For support bundles, the `dump_state` json-rpc method returns the same
+//! snapshot of a running instance, including the state of the channels of
+//! every nexus on each core, which is not collected on panic as that needs
+//! the reactors to be responsive.
+
+use std::{
+    collections::HashMap,
+    panic::{self, PanicInfo},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use chrono::{SecondsFormat, Utc};
+use futures::FutureExt;
+use serde::Serialize;
+use spdk_rs::ChannelTraverseStatus;
+
+use crate::{
+    bdev::nexus::{
+        nexus_iter,
+        nexus_lookup,
+        ChildState,
+        NexusChannel,
+        NexusState,
+        NexusStatus,
+    },
+    core::{for_each_channel, Cores, Mthread, Reactors, Share, UntypedBdev},
+    jsonrpc::{jsonrpc_register, JsonRpcError},
+    lvs::{replica_erasures, Lvs, ReplicaErasure},
+    rebuild::{ClientOperations, RebuildJob},
+    subsys::NvmfSubsystem,
+};
+
+/// how long a panic on a non SPDK thread waits for the state to be collected
+const COLLECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// set while dumping the state on panic, so that a panic while doing so does
+/// not try again
+static DUMPING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Serialize)]
+pub struct ReactorDump {
+    pub core: u32,
+    pub state: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChildDump {
+    pub uri: String,
+    pub state: ChildState,
+    /// rebuild progress in %, -1 when not rebuilding
+    pub rebuild_progress: i32,
+    /// IO faults of the child seen by each core
+    pub io_faults: HashMap<u32, u64>,
+}
+
+/// State of a channel of a nexus, i.e. of the nexus on one core.
+#[derive(Debug, Serialize)]
+pub struct ChannelDump {
+    pub core: u32,
+    /// devices of the children read from
+    pub readers: Vec<String>,
+    /// devices of the children written to
+    pub writers: Vec<String>,
+    pub qos: bool,
+    pub write_cache: bool,
+    pub read_cache: bool,
+    pub checksums: bool,
+    pub encrypted: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NexusDump {
+    pub name: String,
+    pub uuid: String,
+    pub size: u64,
+    /// None when the state is locked, e.g. by the code that panicked
+    pub state: Option<NexusState>,
+    pub status: Option<NexusStatus>,
+    pub share_uri: Option<String>,
+    pub children: Vec<ChildDump>,
+    /// only collected by a live dump
+    pub channels: Option<Vec<ChannelDump>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RebuildJobDump {
+    pub nexus: String,
+    pub source: String,
+    pub destination: String,
+    pub state: String,
+    pub progress: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplicaDump {
+    pub name: String,
+    pub size: u64,
+    pub share_uri: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BdevDump {
+    pub name: String,
+    pub uuid: String,
+    pub product: String,
+    pub block_len: u32,
+    pub num_blocks: u64,
+    pub claimed_by: Option<String>,
+    pub share_uri: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareDump {
+    pub nqn: String,
+    pub subtype: String,
+    pub bdev: Option<String>,
+    pub uris: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PoolDump {
+    pub name: String,
+    pub disk: String,
+    pub capacity: u64,
+    pub used: u64,
+    pub replicas: Vec<ReplicaDump>,
+}
+
+/// State of the objects living on the SPDK threads.
+#[derive(Debug, Serialize)]
+pub struct SpdkStateDump {
+    pub nexuses: Vec<NexusDump>,
+    pub rebuild_jobs: Vec<RebuildJobDump>,
+    /// erasures of destroyed replicas
+    pub erasures: Vec<ReplicaErasure>,
+    pub pools: Vec<PoolDump>,
+    pub bdevs: Vec<BdevDump>,
+    /// NVMe-oF subsystems
+    pub shares: Vec<ShareDump>,
+}
+
+/// Snapshot of the in-memory state.
+#[derive(Debug, Serialize)]
+pub struct StateDump {
+    /// time of the snapshot, RFC 3339 in UTC
+    pub timestamp: String,
+    pub pid: u32,
+    /// what caused the snapshot to be taken, e.g. the panic message
+    pub reason: Option<String>,
+    /// name of the thread taking the snapshot
+    pub thread: Option<String>,
+    pub reactors: Vec<ReactorDump>,
+    /// None when it could not be collected in time
+    pub spdk: Option<SpdkStateDump>,
+}
+
+impl SpdkStateDump {
+    /// Collect the state of the objects living on the SPDK threads. This
+    /// must be called from a SPDK thread.
+    pub fn collect() -> Self {
+        let nexuses = nexus_iter()
+            .map(|n| {
+                let state = n.state.try_lock().map(|s| *s);
+                NexusDump {
+                    name: n.name.clone(),
+                    uuid: n.uuid().to_string(),
+                    size: n.req_size,
+                    state,
+                    status: state.map(|_| n.status()),
+                    share_uri: n.get_share_uri(),
+                    children: n
+                        .children
+                        .iter()
+                        .map(|c| ChildDump {
+                            uri: c.get_name().to_string(),
+                            state: c.state(),
+                            rebuild_progress: c.get_rebuild_progress(),
+                            io_faults: c.io_faults_per_core(),
+                        })
+                        .collect(),
+                    channels: None,
+                }
+            })
+            .collect();
+
+        let rebuild_jobs = RebuildJob::list()
+            .into_iter()
+            .map(|j| RebuildJobDump {
+                nexus: j.nexus.clone(),
+                source: j.source.clone(),
+                destination: j.destination.clone(),
+                state: j.state().to_string(),
+                progress: j.stats().progress,
+                error: j.error.as_ref().map(|e| e.to_string()),
+            })
+            .collect();
+
+        let pools = Lvs::iter()
+            .map(|p| PoolDump {
+                name: p.name().to_string(),
+                disk: p.base_bdev().name().to_string(),
+                capacity: p.capacity(),
+                used: p.used(),
+                replicas: p
+                    .lvols()
+                    .map(|lvols| {
+                        lvols
+                            .map(|l| ReplicaDump {
+                                name: l.name(),
+                                size: l.size(),
+                                share_uri: l.share_uri(),
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            })
+            .collect();
+
+        let bdevs = UntypedBdev::bdev_first()
+            .into_iter()
+            .flat_map(|b| b.into_iter())
+            .map(|b| BdevDump {
+                name: b.name().to_string(),
+                uuid: b.uuid_as_string(),
+                product: b.product_name().to_string(),
+                block_len: b.block_len(),
+                num_blocks: b.num_blocks(),
+                claimed_by: b.claimed_by(),
+                share_uri: b.share_uri(),
+            })
+            .collect();
+
+        let shares = NvmfSubsystem::first()
+            .into_iter()
+            .flat_map(|s| s.into_iter())
+            .map(|s| ShareDump {
+                nqn: s.get_nqn(),
+                subtype: s.subtype().to_string(),
+                bdev: s.bdev().map(|b| b.name().to_string()),
+                uris: s.uri_endpoints().unwrap_or_default(),
+            })
+            .collect();
+
+        Self {
+            nexuses,
+            rebuild_jobs,
+            erasures: replica_erasures(),
+            pools,
+            bdevs,
+            shares,
+        }
+    }
+
+    /// Collect the state from any thread, waiting at most for the given
+    /// time when not on a SPDK thread.
+    fn collect_any(timeout: Duration) -> Option<Self> {
+        if Mthread::current().is_some() {
+            return Some(Self::collect());
+        }
+
+        let mut rx = Mthread::get_init()
+            .spawn_local(async { Self::collect() })
+            .ok()?;
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            match rx.try_recv() {
+                Ok(Some(state)) => return Some(state),
+                Ok(None) => std::thread::sleep(Duration::from_millis(10)),
+                Err(_) => return None,
+            }
+        }
+        None
+    }
+}
+
+impl StateDump {
+    /// Take a snapshot of the in-memory state.
+    pub fn collect(reason: Option<String>) -> Self {
+        Self {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            pid: std::process::id(),
+            reason,
+            thread: std::thread::current().name().map(String::from),
+            reactors: Reactors::iter()
+                .map(|r| ReactorDump {
+                    core: r.core(),
+                    state: format!("{:?}", r.get_state()),
+                })
+                .collect(),
+            spdk: SpdkStateDump::collect_any(COLLECT_TIMEOUT),
+        }
+    }
+
+    /// Take a snapshot of the in-memory state of a running instance,
+    /// including the channels of the nexuses. This must be called from a
+    /// SPDK thread.
+    pub async fn collect_live() -> Self {
+        let mut dump = Self::collect(None);
+        if let Some(spdk) = dump.spdk.as_mut() {
+            for nexus in spdk.nexuses.iter_mut() {
+                nexus.channels = channel_dumps(&nexus.name).await;
+            }
+        }
+        dump
+    }
+
+    /// Write the snapshot to a new crash file in the given directory,
+    /// returning its path.
+    pub fn write(&self, dir: &str) -> std::io::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let path = PathBuf::from(dir).join(format!(
+            "mayastor-crash-{}-{}.json",
+            self.pid,
+            Utc::now().format("%Y%m%dT%H%M%S%.6fZ")
+        ));
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+        Ok(path)
+    }
+}
+
+/// Context to collect the state of the channels of a nexus.
+struct ChannelDumpCtx {
+    channels: Vec<ChannelDump>,
+}
+
+fn channel_dump_cb(
+    channel: &mut NexusChannel,
+    ctx: &mut ChannelDumpCtx,
+) -> ChannelTraverseStatus {
+    let inner = channel.inner();
+    ctx.channels.push(ChannelDump {
+        core: Cores::current(),
+        readers: inner
+            .readers
+            .iter()
+            .map(|h| h.get_device().device_name())
+            .collect(),
+        writers: inner
+            .writers
+            .iter()
+            .map(|h| h.get_device().device_name())
+            .collect(),
+        qos: inner.qos.is_some(),
+        write_cache: inner.cache.is_some(),
+        read_cache: inner.read_cache.is_some(),
+        checksums: inner.checksums.is_some(),
+        encrypted: inner.crypto.is_some(),
+    });
+    ChannelTraverseStatus::Ok
+}
+
+/// collect the state of the channels of a nexus on all cores
+async fn channel_dumps(name: &str) -> Option<Vec<ChannelDump>> {
+    let nexus = nexus_lookup(name)?;
+    if !nexus.has_io_device {
+        return None;
+    }
+
+    let r = for_each_channel(
+        &*nexus,
+        ChannelDumpCtx {
+            channels: Vec::new(),
+        },
+        channel_dump_cb,
+    );
+    r.await.ok().map(|ctx| ctx.channels)
+}
+
+async fn dump_state(_: ()) -> Result<StateDump, JsonRpcError> {
+    Ok(StateDump::collect_live().await)
+}
+
+/// Register the json-rpc method returning a snapshot of the state.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, JsonRpcError>("dump_state", |args: ()| {
+        dump_state(args).boxed_local()
+    });
+}
+
+/// describe a panic: its message and location
+fn panic_reason(info: &PanicInfo) -> String {
+    let msg = if let Some(s) = info.payload().downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = info.payload().downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown".to_string()
+    };
+
+    match info.location() {
+        Some(l) => format!("panic at {}:{}: {}", l.file(), l.line(), msg),
+        None => format!("panic: {}", msg),
+    }
+}
+
+/// Install a panic hook writing a snapshot of the state to a crash file in
+/// the given directory, or the temporary directory when not given, before
+/// running the default panic handling.
+pub fn install_panic_hook(crash_dir: Option<String>) {
+    let dir = crash_dir
+        .unwrap_or_else(|| std::env::temp_dir().to_string_lossy().into_owned());
+    let default_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        if !DUMPING.swap(true, Ordering::SeqCst) {
+            let dump = StateDump::collect(Some(panic_reason(info)));
+            match dump.write(&dir) {
+                Ok(path) => eprintln!("state dumped to {}", path.display()),
+                Err(e) => eprintln!("failed to dump state to {}: {}", dir, e),
+            }
+            DUMPING.store(false, Ordering::SeqCst);
+        }
+        default_hook(info);
+    }));
+}
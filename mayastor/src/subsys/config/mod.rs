@@ -27,7 +27,10 @@ use crate::{
         GetOpts,
         NexusOpts,
         NvmeBdevOpts,
+        NvmePollOpts,
         NvmfTgtConfig,
+        PoolOpts,
+        ReplicaOpts,
     },
 };
 
@@ -123,10 +126,16 @@ pub struct Config {
     pub nvmf_tcp_tgt_conf: NvmfTgtConfig,
     /// options specific to NVMe bdev types
     pub nvme_bdev_opts: NvmeBdevOpts,
+    /// polling of the NVMe children
+    pub nvme_poll_opts: NvmePollOpts,
     /// generic bdev options
     pub bdev_opts: BdevOpts,
     /// nexus specific options
     pub nexus_opts: NexusOpts,
+    /// replica specific options
+    pub replica_opts: ReplicaOpts,
+    /// pool specific options
+    pub pool_opts: PoolOpts,
 }
 
 impl Config {
@@ -186,8 +195,11 @@ impl Config {
             source: self.source.clone(),
             nvmf_tcp_tgt_conf: self.nvmf_tcp_tgt_conf.get(),
             nvme_bdev_opts: self.nvme_bdev_opts.get(),
+            nvme_poll_opts: self.nvme_poll_opts.get(),
             bdev_opts: self.bdev_opts.get(),
             nexus_opts: self.nexus_opts.get(),
+            replica_opts: self.replica_opts.get(),
+            pool_opts: self.pool_opts.get(),
         }
     }
 
@@ -6,6 +6,11 @@ use std::ptr::copy_nonoverlapping;
 
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    bdev::nexus::{ChildSizePolicy, RebuildWindow},
+    lvs::{DeletionPolicy, InitMode},
+};
+
 use spdk_rs::libspdk::{
     bdev_nvme_get_opts,
     bdev_nvme_set_opts,
@@ -44,6 +49,56 @@ pub struct NexusOpts {
     /// NOTE: we do not (yet) differentiate between
     /// the nexus and replica nvmf target
     pub nvmf_replica_port: u16,
+    /// maximum number of children of a nexus
+    pub max_children: u32,
+    /// interval in seconds at which subsystems that no longer share a bdev
+    /// are looked for, 0 only looks when the target starts
+    pub share_gc_interval_secs: u64,
+    /// destroy the orphaned subsystems that are found, rather than only
+    /// reporting them
+    pub share_gc_remove: bool,
+    /// time in seconds after which a share no initiator is connected to is
+    /// unshared, unless it has a timeout of its own, 0 never unshares it
+    pub share_idle_timeout_secs: u64,
+    /// maximum number of IOs per channel deferred while the channel has no
+    /// children during a reconfiguration, 0 fails such IO right away
+    pub io_retry_queue_depth: u32,
+    /// time in milliseconds after which deferred IO is failed
+    pub io_retry_timeout_ms: u64,
+    /// maximum number of IOs per channel held back inside a nexus before new
+    /// IO is pushed back to the initiator, 0 never pushes back
+    pub io_backpressure_depth: u32,
+    /// maximum number of IOs outstanding on a nexus, 0 does not limit it
+    pub max_io_outstanding: u32,
+    /// size in KiB of the extents a nexus reads ahead of sequential reads, 0
+    /// does not read ahead
+    pub read_ahead_kb: u32,
+    /// number of sequential reads in a row before a nexus reads ahead
+    pub read_ahead_trigger: u32,
+    /// what a nexus does with children smaller than its requested size,
+    /// unless it is created with a policy of its own
+    pub child_size_policy: ChildSizePolicy,
+    /// windows of the day, HH:MM-HH:MM in UTC, rebuilds run in, none to let
+    /// them run at any time
+    pub rebuild_windows: Vec<RebuildWindow>,
+    /// serialize overlapping writes to a nexus, so that the children apply
+    /// them in the same order
+    pub serialize_writes: bool,
+    /// keep a journal of the recent writes on the children, so that a child
+    /// that comes back only has the blocks it missed rebuilt
+    pub write_journal: bool,
+    /// time in milliseconds a nexus waits for its IOs to complete when it is
+    /// unshared, before it is unshared regardless
+    pub unshare_drain_timeout_ms: u64,
+    /// time in milliseconds a nexus accepts writes for after it found its
+    /// epoch current in the persistent store, 0 accepts writes without
+    /// checking it
+    pub epoch_lease_ms: u64,
+    /// maximum number of writes per channel waiting for the epoch to be
+    /// checked, the writes past it fail
+    pub epoch_hold_depth: u32,
+    /// time in milliseconds after which a write waiting for the epoch fails
+    pub epoch_hold_timeout_ms: u64,
 }
 
 /// Default nvmf port used for replicas.
@@ -51,6 +106,9 @@ pub struct NexusOpts {
 /// to conflict with nexus exported over nvmf running on the same node.
 const NVMF_PORT_REPLICA: u16 = 8420;
 const NVMF_PORT_NEXUS: u16 = 4421;
+/// Default maximum number of children of a nexus, enough for a migration
+/// chain holding the old and new replicas of a wide nexus at the same time.
+const MAX_NEXUS_CHILDREN: u32 = 64;
 
 impl Default for NexusOpts {
     fn default() -> Self {
@@ -59,6 +117,24 @@ impl Default for NexusOpts {
             nvmf_discovery_enable: true,
             nvmf_nexus_port: NVMF_PORT_NEXUS,
             nvmf_replica_port: NVMF_PORT_REPLICA,
+            max_children: MAX_NEXUS_CHILDREN,
+            share_gc_interval_secs: 60,
+            share_gc_remove: true,
+            share_idle_timeout_secs: 0,
+            io_retry_queue_depth: 256,
+            io_retry_timeout_ms: 5000,
+            io_backpressure_depth: 1024,
+            max_io_outstanding: 0,
+            read_ahead_kb: 0,
+            read_ahead_trigger: 4,
+            child_size_policy: ChildSizePolicy::default(),
+            rebuild_windows: Vec::new(),
+            serialize_writes: false,
+            write_journal: false,
+            unshare_drain_timeout_ms: 5000,
+            epoch_lease_ms: 0,
+            epoch_hold_depth: 256,
+            epoch_hold_timeout_ms: 5000,
         }
     }
 }
@@ -69,6 +145,43 @@ impl GetOpts for NexusOpts {
     }
 }
 
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplicaOpts {
+    /// how the data of a replica is erased when the replica is destroyed
+    pub deletion_policy: DeletionPolicy,
+}
+
+impl GetOpts for ReplicaOpts {
+    fn get(&self) -> Self {
+        self.clone()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolOpts {
+    /// how the data area of a pool is initialized when the pool is created
+    pub init: InitMode,
+    /// number of pools imported at a time at startup
+    pub import_concurrency: usize,
+}
+
+impl Default for PoolOpts {
+    fn default() -> Self {
+        Self {
+            init: InitMode::default(),
+            import_concurrency: 8,
+        }
+    }
+}
+
+impl GetOpts for PoolOpts {
+    fn get(&self) -> Self {
+        self.clone()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(default, deny_unknown_fields)]
 pub struct NvmfTgtConfig {
@@ -78,6 +191,15 @@ pub struct NvmfTgtConfig {
     pub max_namespaces: u32,
     /// TCP transport options
     pub opts: NvmfTcpTransportOpts,
+    /// prefix of the NQNs of the shares, followed by the name of the bdev
+    pub nqn_prefix: String,
+    /// serial number reported by the controllers of the shares
+    pub serial_number: String,
+    /// model number reported by the controllers of the shares
+    pub model_number: String,
+    /// addresses the target and the shares listen on besides the address of
+    /// the pod, e.g. the one of a dedicated storage network
+    pub listen_addresses: Vec<String>,
 }
 
 impl From<NvmfTgtConfig> for Box<spdk_nvmf_target_opts> {
@@ -101,6 +223,11 @@ impl Default for NvmfTgtConfig {
             name: "mayastor_target".to_string(),
             max_namespaces: 110,
             opts: NvmfTcpTransportOpts::default(),
+            nqn_prefix: "nqn.2019-05.io.openebs".to_string(),
+            // look closely, its a race car!
+            serial_number: "33' ~'~._`o##o>".to_string(),
+            model_number: "Mayastor NVMe controller".to_string(),
+            listen_addresses: Vec::new(),
         }
     }
 }
@@ -239,7 +366,11 @@ pub struct NvmeBdevOpts {
     pub nvme_ioq_poll_period_us: u64,
     /// number of requests per nvme IO queue
     pub io_queue_requests: u32,
-    /// allow for batching of commands
+    /// allow for batching of commands, the commands submitted to a qpair
+    /// during a poll iteration are submitted together when it is polled,
+    /// which requires nvme_ioq_poll_period_us to be 0; off by default, as a
+    /// command then waits for the poller of its qpair, which adds the rest of
+    /// the reactor iteration to its latency
     pub delay_cmd_submit: bool,
     /// attempts per I/O in bdev layer before I/O fails
     pub bdev_retry_count: i32,
@@ -282,7 +413,7 @@ impl Default for NvmeBdevOpts {
             ),
             nvme_ioq_poll_period_us: try_from_env("NVME_IOQ_POLL_PERIOD_US", 0),
             io_queue_requests: 0,
-            delay_cmd_submit: true,
+            delay_cmd_submit: try_from_env("NVME_DELAY_CMD_SUBMIT", false),
             bdev_retry_count: try_from_env("NVME_BDEV_RETRY_COUNT", 0),
         }
     }
@@ -330,6 +461,39 @@ impl From<&NvmeBdevOpts> for spdk_bdev_nvme_opts {
     }
 }
 
+/// polling of the IO qpairs of the NVMe children, adapted to their load
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NvmePollOpts {
+    /// adapt the poll period of the qpairs to their load, rather than
+    /// polling them every nvme_ioq_poll_period_us, off by default
+    pub adaptive: bool,
+    /// poll period in microseconds of a qpair without IO outstanding
+    pub idle_period_us: u64,
+    /// longest time in microseconds the completions of a qpair under low
+    /// load are left to coalesce
+    pub max_coalesce_us: u64,
+    /// IOPS of a qpair from which its completions are not coalesced
+    pub low_load_iops: u64,
+}
+
+impl Default for NvmePollOpts {
+    fn default() -> Self {
+        Self {
+            adaptive: try_from_env("NVME_ADAPTIVE_POLL", false),
+            idle_period_us: try_from_env("NVME_IDLE_POLL_PERIOD_US", 1_000),
+            max_coalesce_us: try_from_env("NVME_MAX_COALESCE_US", 50),
+            low_load_iops: try_from_env("NVME_LOW_LOAD_IOPS", 2_000),
+        }
+    }
+}
+
+impl GetOpts for NvmePollOpts {
+    fn get(&self) -> Self {
+        crate::bdev::nvmx::poll_opts()
+    }
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(default, deny_unknown_fields)]
 pub struct BdevOpts {
@@ -1,17 +1,29 @@
-use std::{fmt::Display, fs, path::Path, sync::Mutex};
+use std::{collections::BTreeMap, fmt::Display, fs, path::Path, sync::Mutex};
 
-use futures::channel::oneshot;
+use futures::{channel::oneshot, stream, StreamExt};
 use once_cell::sync::{Lazy, OnceCell};
 use serde::{Deserialize, Serialize};
 use tonic::Status;
 
 use crate::{
-    bdev::nexus::VerboseError,
+    bdev::{nexus::VerboseError, uri},
     core::{runtime, Cores, Mthread, Reactor, Share},
     grpc::rpc_submit,
-    lvs::{Error as LvsError, Lvs},
+    lvs::{
+        set_replica_owner,
+        Error as LvsError,
+        Lvs,
+        PoolLabels,
+        PoolQuota,
+        SyncPolicy,
+        TrimPolicy,
+        DEFAULT_CLUSTER_SIZE,
+    },
+    nexus_uri::NexusBdevError,
     pool::{Pool as SpdkPool, PoolArgs, PoolsIter},
     replica::ShareType,
+    startup::{self, ObjectKind, RestoreState},
+    subsys::Config,
 };
 
 static CONFIG_FILE: OnceCell<String> = OnceCell::new();
@@ -124,21 +136,56 @@ impl PoolConfig {
 
     /// Create pools specified in this configuration
     async fn create_pools(&self) -> usize {
-        let mut failures = 0;
-        if let Some(pools) = self.pools.as_ref() {
-            for pool in pools.iter() {
-                info!("creating pool {}", pool.name);
-                if let Err(error) = create_pool(pool.into()).await {
-                    error!(
-                        "failed to create pool {}: {}",
-                        pool.name,
-                        error.verbose()
-                    );
-                    failures += 1;
+        let pools = match self.pools.as_ref() {
+            Some(pools) => pools,
+            None => return 0,
+        };
+        let concurrency = Config::get().pool_opts.import_concurrency.max(1);
+        startup::restoring(
+            ObjectKind::Pool,
+            pools.iter().map(|p| p.name.clone()),
+            concurrency,
+        );
+
+        stream::iter(pools.iter())
+            .map(create_configured_pool)
+            .buffer_unordered(concurrency)
+            .fold(0, |failures, ok| async move {
+                if ok {
+                    failures
+                } else {
+                    failures + 1
+                }
+            })
+            .await
+    }
+
+    /// Create the devices of the pools without loading the pools, which a
+    /// mayastor taking the pools over from another does while the other one
+    /// still has them loaded.
+    pub(crate) fn create_disks(&self) {
+        assert_eq!(Cores::current(), Cores::first());
+        let disks = self
+            .pools
+            .iter()
+            .flatten()
+            .flat_map(|pool| pool.disks.clone())
+            .collect::<Vec<_>>();
+        Reactor::block_on(async move {
+            for disk in disks {
+                let result = match uri::parse(&disk) {
+                    Ok(parsed) => parsed.create().await.map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(())
+                    | Err(NexusBdevError::BdevExists {
+                        ..
+                    }) => {}
+                    Err(e) => warn!("failed to create disk {}: {}", disk, e),
                 }
             }
-        }
-        failures
+        });
     }
 
     /// Import pools
@@ -164,6 +211,25 @@ struct Pool {
     name: String,
     /// bdevs to create outside of the nexus control
     disks: Vec<String>,
+    /// labels describing the pool, used for replica placement
+    #[serde(default, skip_serializing_if = "PoolLabels::is_empty")]
+    labels: PoolLabels,
+    /// size of the clusters of the pool when it is created, the default of
+    /// the lvol store if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cluster_size: Option<u32>,
+    /// when the clusters freed on the pool are unmapped
+    #[serde(default, skip_serializing_if = "TrimPolicy::is_immediate")]
+    trim: TrimPolicy,
+    /// when the metadata of the replicas of the pool is written
+    #[serde(default, skip_serializing_if = "SyncPolicy::is_write_through")]
+    sync: SyncPolicy,
+    /// space the owners of the replicas may take on the pool
+    #[serde(default, skip_serializing_if = "PoolQuota::is_empty")]
+    quota: PoolQuota,
+    /// owners of the replicas of the pool, by replica name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    owners: BTreeMap<String, String>,
     /// list of replicas (not required, informational only)
     #[serde(skip_serializing)]
     replicas: Option<Vec<Replica>>,
@@ -176,6 +242,8 @@ impl From<&Pool> for PoolArgs {
             name: pool.name.clone(),
             disks: pool.disks.clone(),
             uuid: None,
+            labels: pool.labels.clone(),
+            cluster_size: pool.cluster_size,
         }
     }
 }
@@ -184,11 +252,33 @@ impl From<&Pool> for PoolArgs {
 impl From<SpdkPool> for Pool {
     fn from(pool: SpdkPool) -> Self {
         let base = pool.get_base_bdev();
+        let lvs = Lvs::lookup(pool.get_name());
+        let labels = lvs.as_ref().map(|lvs| lvs.labels()).unwrap_or_default();
+        let cluster_size = lvs
+            .as_ref()
+            .map(|lvs| lvs.cluster_size() as u32)
+            .filter(|&size| size != DEFAULT_CLUSTER_SIZE);
+        let sync = lvs
+            .as_ref()
+            .map(|lvs| lvs.sync_policy())
+            .unwrap_or_default();
+        let quota = lvs.as_ref().map(|lvs| lvs.quota()).unwrap_or_default();
+        let owners = lvs
+            .as_ref()
+            .map(|lvs| lvs.replica_owners())
+            .unwrap_or_default();
+        let trim = lvs.map(|lvs| lvs.trim_policy()).unwrap_or_default();
         Self {
             name: pool.get_name().to_string(),
             disks: vec![base
                 .bdev_uri()
                 .unwrap_or_else(|| base.name().to_string())],
+            labels,
+            cluster_size,
+            trim,
+            sync,
+            quota,
+            owners,
             replicas: None,
         }
     }
@@ -204,6 +294,53 @@ struct Replica {
     share: Option<ShareType>,
 }
 
+/// create or import a pool of the configuration and apply its policies,
+/// returning false if it could not be
+async fn create_configured_pool(pool: &Pool) -> bool {
+    info!("creating pool {}", pool.name);
+    startup::set_state(
+        ObjectKind::Pool,
+        &pool.name,
+        RestoreState::Running,
+        None,
+    );
+
+    if let Err(error) = create_pool(pool.into()).await {
+        error!("failed to create pool {}: {}", pool.name, error.verbose());
+        startup::set_state(
+            ObjectKind::Pool,
+            &pool.name,
+            RestoreState::Failed,
+            Some(error.verbose()),
+        );
+        return false;
+    }
+    for (replica, owner) in &pool.owners {
+        if let Err(error) = set_replica_owner(replica, Some(owner.clone())) {
+            error!("failed to set the owner of replica {}: {}", replica, error);
+        }
+    }
+    if let Some(lvs) = Lvs::lookup(&pool.name) {
+        if let Err(error) = lvs.set_quota(pool.quota.clone()) {
+            error!("failed to set the quota of pool {}: {}", pool.name, error);
+        }
+        lvs.set_trim_policy(pool.trim);
+        if let Err(error) = lvs.set_sync_policy(pool.sync).await {
+            error!(
+                "failed to set the sync policy of pool {}: {}",
+                pool.name, error
+            );
+        }
+    }
+    startup::set_state(
+        ObjectKind::Pool,
+        &pool.name,
+        RestoreState::Restored,
+        None,
+    );
+    true
+}
+
 async fn create_pool(args: PoolArgs) -> Result<rpc::mayastor::Pool, Status> {
     if args.disks.is_empty() {
         return Err(Status::invalid_argument("Missing devices"));
@@ -2,20 +2,51 @@
 //! Main file to register additional subsystems
 
 pub use config::{
-    opts::{NexusOpts, NvmeBdevOpts},
+    opts::{NexusOpts, NvmeBdevOpts, NvmePollOpts, PoolOpts, ReplicaOpts},
     pool::PoolConfig,
     Config,
     ConfigSubsystem,
 };
 pub use nvmf::{
+    add_share_listener,
+    collect_orphaned_shares,
     create_snapshot,
+    idle_shares,
+    remove_share_listener,
+    set_connect_token,
+    set_share_idle_timeout,
+    set_share_naming,
+    set_share_token,
     set_snapshot_time,
+    share_has_token,
+    share_identity,
+    share_listeners,
+    share_locked,
+    target_accepting,
+    token_host_nqn,
+    unshare_idle_shares,
+    ConnectedHost,
     Error as NvmfError,
+    IdleShare,
+    IdleShares,
     NvmeCpl,
     NvmfReq,
     NvmfSubsystem,
+    OrphanedShare,
+    ShareAccess,
+    ShareIdentity,
+    ShareListeners,
+    ShareNaming,
     SubType,
     Target as NvmfTarget,
+    UnsharedShare,
+};
+pub(crate) use nvmf::{
+    apply_share_token,
+    connect_host_nqn,
+    lock_share,
+    unwatch_share,
+    watch_share,
 };
 use spdk_rs::libspdk::{
     spdk_add_subsystem,
@@ -46,4 +77,5 @@ pub(crate) fn register_subsystem() {
         spdk_add_subsystem_depend(Box::into_raw(depend));
     }
     RegistrationSubsystem::register();
+    nvmf::register_jsonrpc_methods();
 }
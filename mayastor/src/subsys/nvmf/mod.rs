@@ -15,13 +15,48 @@ use snafu::Snafu;
 
 pub use admin_cmd::{create_snapshot, set_snapshot_time, NvmeCpl, NvmfReq};
 use poll_groups::PollGroup;
+pub(crate) use share_access::{
+    apply_share_token,
+    connect_host_nqn,
+    lock_share,
+};
+pub use share_access::{
+    set_connect_token,
+    set_share_token,
+    share_has_token,
+    share_locked,
+    token_host_nqn,
+    ShareAccess,
+};
+pub(crate) use share_events::{unwatch_share, watch_share};
+pub use share_gc::{collect_orphaned_shares, OrphanedShare};
+pub use share_idle::{
+    idle_shares,
+    set_share_idle_timeout,
+    unshare_idle_shares,
+    IdleShare,
+    IdleShares,
+    UnsharedShare,
+};
+pub use share_listeners::{
+    add_share_listener,
+    remove_share_listener,
+    share_listeners,
+    ShareListeners,
+};
+pub use share_naming::{
+    set_share_naming,
+    share_identity,
+    ShareIdentity,
+    ShareNaming,
+};
 use spdk_rs::libspdk::{
     spdk_subsystem,
     spdk_subsystem_fini_next,
     spdk_subsystem_init_next,
 };
-pub use subsystem::{NvmfSubsystem, SubType};
-pub use target::Target;
+pub use subsystem::{ConnectedHost, NvmfSubsystem, SubType};
+pub use target::{target_accepting, Target};
 
 use crate::{
     jsonrpc::{Code, RpcErrorCode},
@@ -30,6 +65,12 @@ use crate::{
 
 mod admin_cmd;
 mod poll_groups;
+mod share_access;
+mod share_events;
+mod share_gc;
+mod share_idle;
+mod share_listeners;
+mod share_naming;
 mod subsystem;
 mod target;
 mod transport;
@@ -43,9 +84,35 @@ impl Default for Nvmf {
     }
 }
 
+/// Register the json-rpc methods managing the shares.
+pub(crate) fn register_jsonrpc_methods() {
+    share_access::register_jsonrpc_methods();
+    share_gc::register_jsonrpc_methods();
+    share_idle::register_jsonrpc_methods();
+    share_listeners::register_jsonrpc_methods();
+    share_naming::register_jsonrpc_methods();
+}
+
 impl RpcErrorCode for Error {
     fn rpc_error_code(&self) -> Code {
-        Code::InternalError
+        match self {
+            Self::Naming {
+                ..
+            }
+            | Self::Token {
+                ..
+            }
+            | Self::InvalidAddress {
+                ..
+            } => Code::InvalidParams,
+            Self::Listener {
+                ..
+            }
+            | Self::NotShared {
+                ..
+            } => Code::NotFound,
+            _ => Code::InternalError,
+        }
     }
 }
 #[derive(Debug, Clone, Snafu)]
@@ -75,6 +142,14 @@ pub enum Error {
     Namespace { bdev: String, msg: String },
     #[snafu(display("Failed to find listener for {} {}", nqn, trid))]
     Listener { nqn: String, trid: String },
+    #[snafu(display("Invalid naming of the share of {}: {}", bdev, msg))]
+    Naming { bdev: String, msg: String },
+    #[snafu(display("Invalid token of the share of {}: {}", bdev, msg))]
+    Token { bdev: String, msg: String },
+    #[snafu(display("Bdev {} is not shared", bdev))]
+    NotShared { bdev: String },
+    #[snafu(display("Invalid listener address {}", address))]
+    InvalidAddress { address: String },
 }
 
 thread_local! {
@@ -0,0 +1,314 @@
+//!
+//! Access tokens of the nvmf shares.
+//!
+//! Any host that knows the NQN of a share can connect to it, and NQNs are
+//! made of the names of the shared bdevs, which are no secret: a replica is
+//! named after its volume. A share can be given a token that the hosts
+//! connecting to it must know. The share then only lets in the host NQN
+//! derived from the token and its own NQN, by an HMAC-SHA256 of the NQN keyed
+//! by the token, so a host connected to one replica does not learn the host
+//! NQN to connect to another replica with the same token.
+//!
+//! Tokens of shares are kept in memory only and are set with the
+//! `nvmf_set_share_token` json-rpc method, best before the bdev is shared, and
+//! `nvmf_share_access` reports whether a share has one. Setting or removing
+//! the token of a share changes which hosts can connect from then on, the
+//! hosts already connected stay so. An lvol keeps on disk whether its share
+//! has a token, so when its pool is imported again its share is locked: it
+//! lets in no host until the token is set again, or removed.
+//!
+//! The host NQN is a bearer credential: it is sent in clear in the connect
+//! command, so a token keeps out the hosts that were not given it but not
+//! those that can see the traffic of the storage network.
+//!
+//! A nexus connects to a share with the host NQN of the token set for the NQN
+//! of the share with the `nvmf_set_connect_token` json-rpc method, before its
+//! child is created or added. The token is not part of the URI of the child,
+//! which is listed, logged and persisted, and a URI with a `token` parameter
+//! is refused.
+
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    pin::Pin,
+};
+
+use futures::FutureExt;
+use hmac::{Hmac, Mac, NewMac};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    core::UntypedBdev,
+    jsonrpc::jsonrpc_register,
+    lvs::{Lvol, PropValue},
+    subsys::{
+        nvmf::{Error, NvmfSubsystem},
+        Config,
+    },
+};
+
+/// prefix of the host NQNs derived from tokens
+const TOKEN_HOST_NQN_PREFIX: &str = "nqn.2019-05.io.openebs:token:";
+/// maximum length of a token in bytes
+const MAX_TOKEN_LEN: usize = 256;
+
+/// tokens of the shares, by bdev name
+static TOKENS: Lazy<Mutex<HashMap<String, String>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// shares that had a token before the pool was imported, by bdev name
+static LOCKED: Lazy<Mutex<HashSet<String>>> =
+    Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// tokens to connect to the shares of other nodes with, by subsystem NQN
+static CONNECT_TOKENS: Lazy<Mutex<HashMap<String, String>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Returns an error if the token is not of a valid size.
+fn check_token(name: &str, token: &str) -> Result<(), Error> {
+    if token.is_empty() || token.len() > MAX_TOKEN_LEN {
+        return Err(Error::Token {
+            bdev: name.to_string(),
+            msg: format!("a token must be 1 to {} bytes", MAX_TOKEN_LEN),
+        });
+    }
+    Ok(())
+}
+
+/// Returns the host NQN a host that knows the token connects to the
+/// subsystem with the given NQN with.
+pub fn token_host_nqn(token: &str, nqn: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(token.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(nqn.as_bytes());
+    let digest = mac.finalize().into_bytes();
+    let hex = digest[.. 16]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    format!("{}{}", TOKEN_HOST_NQN_PREFIX, hex)
+}
+
+/// Let only the hosts that know the token of the share of the bdev connect
+/// to its subsystem, no host if the share is locked, or any host if the share
+/// has no token.
+pub(crate) fn apply_share_token(
+    bdev: &str,
+    subsystem: &NvmfSubsystem,
+) -> Result<(), Error> {
+    let token = TOKENS.lock().get(bdev).cloned();
+    match token {
+        Some(token) => {
+            subsystem
+                .add_host(&token_host_nqn(&token, &subsystem.get_nqn()))?;
+            subsystem.allow_any(false);
+        }
+        None => subsystem.allow_any(!LOCKED.lock().contains(bdev)),
+    }
+    Ok(())
+}
+
+/// Lock the share of the bdev, whose token is not known, so that it lets in
+/// no host until its token is set again.
+pub(crate) fn lock_share(bdev: &str) {
+    if !TOKENS.lock().contains_key(bdev) {
+        warn!(
+            "the share of {} had a token, no host is let in until it is set",
+            bdev
+        );
+        LOCKED.lock().insert(bdev.to_string());
+    }
+}
+
+/// Set the token the hosts connecting to the share of the bdev must know,
+/// None to let any host connect. Whether the share has a token is kept on
+/// disk if the bdev is an lvol.
+pub async fn set_share_token(
+    bdev: &str,
+    token: Option<String>,
+) -> Result<(), Error> {
+    if let Some(token) = &token {
+        check_token(bdev, token)?;
+    }
+
+    if let Some(mut lvol) =
+        UntypedBdev::lookup_by_name(bdev).and_then(|b| Lvol::try_from(b).ok())
+    {
+        Pin::new(&mut lvol)
+            .set(PropValue::ShareToken(token.is_some()))
+            .await
+            .map_err(|e| Error::Token {
+                bdev: bdev.to_string(),
+                msg: format!("failed to persist: {}", e),
+            })?;
+    }
+
+    LOCKED.lock().remove(bdev);
+    let previous = {
+        let mut tokens = TOKENS.lock();
+        match token {
+            Some(token) => tokens.insert(bdev.to_string(), token),
+            None => tokens.remove(bdev),
+        }
+    };
+
+    if !Config::get().nexus_opts.nvmf_enable {
+        return Ok(());
+    }
+    if let Some(subsystem) = NvmfSubsystem::nqn_lookup(bdev) {
+        if let Some(previous) = previous {
+            subsystem.remove_host(&token_host_nqn(
+                &previous,
+                &subsystem.get_nqn(),
+            ))?;
+        }
+        apply_share_token(bdev, &subsystem)?;
+    }
+    Ok(())
+}
+
+/// returns true if the share of the bdev has a token
+pub fn share_has_token(bdev: &str) -> bool {
+    TOKENS.lock().contains_key(bdev)
+}
+
+/// returns true if the share of the bdev waits for its token to be set again
+pub fn share_locked(bdev: &str) -> bool {
+    LOCKED.lock().contains(bdev)
+}
+
+/// Set the token to connect to the share with the given subsystem NQN with,
+/// None to connect without one. Controllers connected already are not
+/// affected.
+pub fn set_connect_token(
+    nqn: &str,
+    token: Option<String>,
+) -> Result<(), Error> {
+    if let Some(token) = &token {
+        check_token(nqn, token)?;
+    }
+    let mut tokens = CONNECT_TOKENS.lock();
+    match token {
+        Some(token) => tokens.insert(nqn.to_string(), token),
+        None => tokens.remove(nqn),
+    };
+    Ok(())
+}
+
+/// Returns the host NQN to connect to the share with the given subsystem NQN
+/// with, if a token is set for it.
+pub(crate) fn connect_host_nqn(nqn: &str) -> Option<String> {
+    CONNECT_TOKENS
+        .lock()
+        .get(nqn)
+        .map(|token| token_host_nqn(token, nqn))
+}
+
+/// Whether the share of a bdev has a token, the token itself is not
+/// reported.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareAccess {
+    pub name: String,
+    pub token: bool,
+    /// the share lets in no host until its token is set again
+    pub locked: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetTokenArgs {
+    /// name of the bdev
+    name: String,
+    /// None lets any host connect
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SetConnectTokenArgs {
+    /// NQN of the subsystem of the share
+    nqn: String,
+    /// None connects without a token
+    token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessArgs {
+    /// name of the bdev
+    name: String,
+}
+
+async fn set_token(args: SetTokenArgs) -> Result<ShareAccess, Error> {
+    info!(
+        "{} the token of the share of {}",
+        if args.token.is_some() {
+            "setting"
+        } else {
+            "removing"
+        },
+        args.name
+    );
+    set_share_token(&args.name, args.token).await?;
+    Ok(ShareAccess {
+        token: share_has_token(&args.name),
+        locked: share_locked(&args.name),
+        name: args.name,
+    })
+}
+
+async fn set_connect(args: SetConnectTokenArgs) -> Result<(), Error> {
+    info!(
+        "{} the token to connect to {}",
+        if args.token.is_some() {
+            "setting"
+        } else {
+            "removing"
+        },
+        args.nqn
+    );
+    set_connect_token(&args.nqn, args.token)
+}
+
+async fn access(args: AccessArgs) -> Result<ShareAccess, Error> {
+    Ok(ShareAccess {
+        token: share_has_token(&args.name),
+        locked: share_locked(&args.name),
+        name: args.name,
+    })
+}
+
+/// Register the json-rpc methods to set the tokens of the shares and those
+/// to connect to them with.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nvmf_set_share_token",
+        |args: SetTokenArgs| set_token(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nvmf_set_connect_token",
+        |args: SetConnectTokenArgs| set_connect(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nvmf_share_access",
+        |args: AccessArgs| access(args).boxed_local(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_nqn() {
+        let nqn = "nqn.2019-05.io.openebs:replica";
+        let host = token_host_nqn("secret", nqn);
+        assert!(host.starts_with(TOKEN_HOST_NQN_PREFIX));
+        assert_eq!(host.len(), TOKEN_HOST_NQN_PREFIX.len() + 32);
+        assert_eq!(host, token_host_nqn("secret", nqn));
+
+        // the host NQN is of the token and of the share
+        assert_ne!(host, token_host_nqn("other", nqn));
+        assert_ne!(host, token_host_nqn("secret", "nqn.2019-05.io.openebs:x"));
+    }
+}
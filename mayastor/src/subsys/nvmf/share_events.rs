@@ -0,0 +1,69 @@
+//!
+//! Events of the bdevs shared over NVMe-oF.
+//!
+//! A share subscribes to the events of its bdev for as long as the bdev is
+//! shared, the same events the children of a nexus and the pools get. When
+//! the bdev is removed the target drops its namespace, which would leave a
+//! subsystem without a namespace that initiators keep connecting to until
+//! the orphaned shares are collected, so the subsystem is stopped and
+//! destroyed right away instead. The target reports resizes to the
+//! initiators on its own, they are only logged.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use crate::{
+    bdev::device_lookup,
+    core::{DeviceEventSubscription, DeviceEventType, Reactors},
+    subsys::nvmf::NvmfSubsystem,
+};
+
+thread_local! {
+    /// subscriptions of the shares to the events of their bdevs, by bdev
+    /// name, on the master core
+    static SUBSCRIPTIONS: RefCell<HashMap<String, DeviceEventSubscription>> =
+        RefCell::new(HashMap::new());
+}
+
+/// handle an event of the bdev of a share
+fn share_event(event: DeviceEventType, bdev: &str) {
+    match event {
+        DeviceEventType::DeviceRemoved => {
+            warn!("shared bdev {} removed, destroying its subsystem", bdev);
+            // the subscription is running this, it is dropped afterwards
+            let bdev = bdev.to_string();
+            Reactors::master().send_future(async move {
+                unwatch_share(&bdev);
+                if let Some(subsystem) = NvmfSubsystem::nqn_lookup(&bdev) {
+                    if let Err(e) = subsystem.stop().await {
+                        error!("failed to stop subsystem of {}: {}", bdev, e);
+                    }
+                    subsystem.destroy();
+                }
+            });
+        }
+        _ => info!("shared bdev {}: {:?}", bdev, event),
+    }
+}
+
+/// subscribe the share of the bdev to the events of the bdev
+pub(crate) fn watch_share(bdev: &str) {
+    let device = match device_lookup(bdev) {
+        Some(device) => device,
+        None => return,
+    };
+    match DeviceEventSubscription::new(
+        device.as_ref(),
+        &format!("share {}", bdev),
+        share_event,
+    ) {
+        Ok(subscription) => SUBSCRIPTIONS.with(|s| {
+            s.borrow_mut().insert(bdev.to_string(), subscription);
+        }),
+        Err(e) => error!("share of {} can not watch its bdev: {}", bdev, e),
+    }
+}
+
+/// stop watching the bdev of a share
+pub(crate) fn unwatch_share(bdev: &str) {
+    SUBSCRIPTIONS.with(|s| s.borrow_mut().remove(bdev));
+}
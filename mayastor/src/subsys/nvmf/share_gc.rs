@@ -0,0 +1,153 @@
+//!
+//! Garbage collection of orphaned shares.
+//!
+//! A subsystem is normally destroyed when the bdev it shares is unshared.
+//! When the bdev goes away without being unshared first, for instance when a
+//! replica is hot-removed or a destroy fails half way, SPDK removes the
+//! namespace but the subsystem stays behind. Initiators can still connect to
+//! it and find an empty controller, which confuses them more than a
+//! subsystem that does not exist.
+//!
+//! Subsystems without a namespace are collected once when the target starts
+//! running and then periodically, at the interval set in the nexus options.
+//! Depending on the options they are stopped and destroyed, or only reported.
+//! The `nvmf_orphaned_shares` json-rpc method runs a collection on demand.
+//!
+//! NBD devices belong to the nexus they expose and are destroyed along with
+//! it, so they can not become orphaned.
+
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{poller, Reactors},
+    jsonrpc::jsonrpc_register,
+    subsys::{
+        nvmf::{Error, NvmfSubsystem, SubType},
+        Config,
+    },
+};
+
+thread_local! {
+    /// poller that periodically starts a collection, on the master core
+    static SHARE_GC_POLLER: RefCell<Option<poller::Poller<'static>>> =
+        RefCell::new(None);
+}
+
+/// set while a collection is running so that collections do not overlap
+static COLLECTING: AtomicBool = AtomicBool::new(false);
+
+/// A subsystem found without a namespace.
+#[derive(Debug, Clone, Serialize)]
+pub struct OrphanedShare {
+    pub nqn: String,
+    /// set when the subsystem has been destroyed
+    pub removed: bool,
+    /// reason destroying the subsystem failed
+    pub error: Option<String>,
+}
+
+/// Find the subsystems that no longer share a bdev, and destroy them if
+/// remove is set.
+pub async fn collect_orphaned_shares(remove: bool) -> Vec<OrphanedShare> {
+    if !Config::get().nexus_opts.nvmf_enable
+        || COLLECTING.swap(true, Ordering::SeqCst)
+    {
+        return Vec::new();
+    }
+
+    let orphans = match NvmfSubsystem::first() {
+        Some(first) => first
+            .into_iter()
+            .filter(|s| s.subtype() == SubType::Nvme && s.bdev().is_none())
+            .collect::<Vec<_>>(),
+        None => Vec::new(),
+    };
+
+    let mut result = Vec::with_capacity(orphans.len());
+    for subsystem in orphans {
+        let nqn = subsystem.get_nqn();
+        let mut orphan = OrphanedShare {
+            nqn: nqn.clone(),
+            removed: false,
+            error: None,
+        };
+
+        if remove {
+            warn!("destroying orphaned subsystem {}", nqn);
+            match subsystem.stop().await {
+                Ok(_) => {
+                    subsystem.destroy();
+                    orphan.removed = true;
+                }
+                Err(e) => {
+                    error!("failed to stop orphaned subsystem {}: {}", nqn, e);
+                    orphan.error = Some(e.to_string());
+                }
+            }
+        } else {
+            warn!("subsystem {} shares no bdev", nqn);
+        }
+        result.push(orphan);
+    }
+
+    COLLECTING.store(false, Ordering::SeqCst);
+    result
+}
+
+/// Collect orphaned shares now and start the periodic collection, if
+/// enabled. Called on the master core when the target starts running.
+pub(crate) fn start() {
+    let opts = &Config::get().nexus_opts;
+    let remove = opts.share_gc_remove;
+
+    Reactors::master().send_future(async move {
+        collect_orphaned_shares(remove).await;
+    });
+
+    if opts.share_gc_interval_secs == 0 {
+        return;
+    }
+
+    let poller = poller::Builder::new()
+        .with_name("nvmf_share_gc")
+        .with_interval(opts.share_gc_interval_secs * 1_000_000)
+        .with_poll_fn(move || {
+            Reactors::master().send_future(async move {
+                collect_orphaned_shares(remove).await;
+            });
+            0
+        })
+        .build();
+
+    SHARE_GC_POLLER.with(|p| *p.borrow_mut() = Some(poller));
+}
+
+/// Stop the periodic collection, when the target shuts down.
+pub(crate) fn stop() {
+    SHARE_GC_POLLER.with(|p| p.borrow_mut().take());
+}
+
+#[derive(Debug, Deserialize)]
+struct CollectArgs {
+    /// destroy the orphaned subsystems rather than only listing them
+    #[serde(default)]
+    remove: bool,
+}
+
+async fn collect(args: CollectArgs) -> Result<Vec<OrphanedShare>, Error> {
+    Ok(collect_orphaned_shares(args.remove).await)
+}
+
+/// Register the json-rpc method to collect orphaned shares on demand.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nvmf_orphaned_shares",
+        |args: CollectArgs| collect(args).boxed_local(),
+    );
+}
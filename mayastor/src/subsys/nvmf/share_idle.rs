@@ -0,0 +1,266 @@
+//!
+//! Unsharing of the shares no initiator is connected to.
+//!
+//! Migration workflows share a nexus or a replica on a node for a while and
+//! do not always unshare it when they are done, leaving a subsystem holding
+//! on to target resources that nobody connects to again. A share can be
+//! given an idle timeout: once no initiator has been connected to it for that
+//! long, the bdev is unshared and the event is reported. Shares without a
+//! timeout of their own use the `share_idle_timeout_secs` nexus option, 0
+//! keeps them shared for as long as they are not unshared.
+//!
+//! The shares are checked at a fixed interval, so a share is unshared up to
+//! that interval after its timeout expires. Timeouts are set with the
+//! `nvmf_set_share_idle_timeout` json-rpc method and the idle shares, as well
+//! as the last shares unshared for being idle, are reported by
+//! `nvmf_idle_shares`.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    pin::Pin,
+    time::Instant,
+};
+
+use chrono::{SecondsFormat, Utc};
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bdev::nexus::nexus_lookup_mut,
+    core::{poller, Reactors, Share, UntypedBdev},
+    jsonrpc::jsonrpc_register,
+    subsys::{
+        nvmf::{Error, NvmfSubsystem, SubType},
+        Config,
+    },
+};
+
+/// interval at which the shares are checked, in seconds
+const IDLE_CHECK_INTERVAL_SECS: u64 = 10;
+
+/// number of shares unshared for being idle that are reported
+const MAX_UNSHARED: usize = 64;
+
+thread_local! {
+    /// poller that periodically checks the shares, on the master core
+    static SHARE_IDLE_POLLER: RefCell<Option<poller::Poller<'static>>> =
+        RefCell::new(None);
+}
+
+/// idle timeouts of the shares that have their own, by bdev name
+static TIMEOUTS: Lazy<Mutex<HashMap<String, u64>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// time since when no initiator has been connected to a share, by bdev name
+static IDLE_SINCE: Lazy<Mutex<HashMap<String, Instant>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// the last shares unshared for being idle, oldest first
+static UNSHARED: Lazy<Mutex<VecDeque<UnsharedShare>>> =
+    Lazy::new(|| Mutex::new(VecDeque::new()));
+
+/// A share no initiator is connected to.
+#[derive(Debug, Clone, Serialize)]
+pub struct IdleShare {
+    /// name of the shared bdev
+    pub bdev: String,
+    pub nqn: String,
+    /// idle timeout of the share in seconds, 0 if it has none
+    pub idle_timeout_secs: u64,
+    /// number of seconds no initiator has been connected to it
+    pub idle_secs: u64,
+}
+
+/// A share unshared for being idle.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnsharedShare {
+    pub bdev: String,
+    pub nqn: String,
+    pub idle_secs: u64,
+    pub time: String,
+    /// reason unsharing the bdev failed
+    pub error: Option<String>,
+}
+
+/// Both the shares no initiator is connected to and the ones unshared for
+/// being so.
+#[derive(Debug, Clone, Serialize)]
+pub struct IdleShares {
+    pub idle: Vec<IdleShare>,
+    pub unshared: Vec<UnsharedShare>,
+}
+
+/// Set the idle timeout of the share of the bdev, None uses the timeout of
+/// the nexus options.
+pub fn set_share_idle_timeout(bdev: &str, timeout_secs: Option<u64>) {
+    match timeout_secs {
+        Some(secs) => TIMEOUTS.lock().insert(bdev.to_string(), secs),
+        None => TIMEOUTS.lock().remove(bdev),
+    };
+}
+
+/// returns the shares no initiator is connected to and the last ones
+/// unshared for being so
+pub fn idle_shares() -> IdleShares {
+    IdleShares {
+        idle: find_idle(),
+        unshared: UNSHARED.lock().iter().cloned().collect(),
+    }
+}
+
+/// returns the idle timeout of the share of the bdev in seconds
+fn idle_timeout(bdev: &str) -> u64 {
+    TIMEOUTS
+        .lock()
+        .get(bdev)
+        .copied()
+        .unwrap_or(Config::get().nexus_opts.share_idle_timeout_secs)
+}
+
+/// Returns the shares no initiator is connected to and updates the time
+/// they have been idle since.
+fn find_idle() -> Vec<IdleShare> {
+    let subsystems = match NvmfSubsystem::first() {
+        Some(first) => first
+            .into_iter()
+            .filter(|s| s.subtype() == SubType::Nvme)
+            .filter_map(|s| s.bdev().map(|b| (s, b.name().to_string())))
+            .collect::<Vec<_>>(),
+        None => Vec::new(),
+    };
+
+    let now = Instant::now();
+    let mut since = IDLE_SINCE.lock();
+    since.retain(|bdev, _| subsystems.iter().any(|(_, b)| b == bdev));
+
+    let mut idle = Vec::new();
+    for (subsystem, bdev) in subsystems {
+        if subsystem.has_controllers() {
+            since.remove(&bdev);
+            continue;
+        }
+        let idle_secs =
+            now.duration_since(*since.entry(bdev.clone()).or_insert(now));
+        idle.push(IdleShare {
+            nqn: subsystem.get_nqn(),
+            idle_timeout_secs: idle_timeout(&bdev),
+            idle_secs: idle_secs.as_secs(),
+            bdev,
+        });
+    }
+    idle
+}
+
+/// unshare the bdev, through its nexus if it is one
+async fn unshare(bdev: &str) -> Result<(), String> {
+    if let Some(mut nexus) = nexus_lookup_mut(bdev) {
+        // a nexus can also be shared as a bdev, bypassing its target
+        nexus
+            .as_mut()
+            .unshare_nexus()
+            .await
+            .map_err(|e| e.to_string())?;
+        return nexus.unshare().await.map(|_| ()).map_err(|e| e.to_string());
+    }
+    match UntypedBdev::lookup_by_name(bdev) {
+        Some(mut bdev) => Pin::new(&mut bdev)
+            .unshare()
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        None => Err(format!("bdev {} not found", bdev)),
+    }
+}
+
+/// Unshare the shares that have been idle for longer than their timeout.
+pub async fn unshare_idle_shares() {
+    let expired = find_idle()
+        .into_iter()
+        .filter(|s| s.idle_timeout_secs > 0)
+        .filter(|s| s.idle_secs >= s.idle_timeout_secs)
+        .collect::<Vec<_>>();
+
+    for share in expired {
+        warn!(
+            "unsharing {} as no initiator connected to {} for {}s",
+            share.bdev, share.nqn, share.idle_secs
+        );
+        let error = unshare(&share.bdev).await.err();
+        if let Some(e) = &error {
+            error!("failed to unshare idle share {}: {}", share.bdev, e);
+        } else {
+            IDLE_SINCE.lock().remove(&share.bdev);
+            TIMEOUTS.lock().remove(&share.bdev);
+        }
+
+        let mut unshared = UNSHARED.lock();
+        if unshared.len() == MAX_UNSHARED {
+            unshared.pop_front();
+        }
+        unshared.push_back(UnsharedShare {
+            bdev: share.bdev,
+            nqn: share.nqn,
+            idle_secs: share.idle_secs,
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            error,
+        });
+    }
+}
+
+/// Start checking the shares for being idle. Called on the master core when
+/// the target starts running.
+pub(crate) fn start() {
+    let poller = poller::Builder::new()
+        .with_name("nvmf_share_idle")
+        .with_interval(IDLE_CHECK_INTERVAL_SECS * 1_000_000)
+        .with_poll_fn(|| {
+            Reactors::master().send_future(unshare_idle_shares());
+            0
+        })
+        .build();
+
+    SHARE_IDLE_POLLER.with(|p| *p.borrow_mut() = Some(poller));
+}
+
+/// Stop checking the shares, when the target shuts down.
+pub(crate) fn stop() {
+    SHARE_IDLE_POLLER.with(|p| p.borrow_mut().take());
+}
+
+#[derive(Debug, Deserialize)]
+struct SetTimeoutArgs {
+    /// name of the shared bdev
+    name: String,
+    /// idle timeout in seconds, 0 never unshares the bdev, omit to use the
+    /// timeout of the nexus options
+    #[serde(default)]
+    idle_timeout_secs: Option<u64>,
+}
+
+async fn set_timeout(args: SetTimeoutArgs) -> Result<(), Error> {
+    info!(
+        "setting the idle timeout of the share of {} to {:?}",
+        args.name, args.idle_timeout_secs
+    );
+    set_share_idle_timeout(&args.name, args.idle_timeout_secs);
+    Ok(())
+}
+
+async fn list(_: ()) -> Result<IdleShares, Error> {
+    Ok(idle_shares())
+}
+
+/// Register the json-rpc methods to set the idle timeouts of shares and to
+/// list the idle shares.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nvmf_set_share_idle_timeout",
+        |args: SetTimeoutArgs| set_timeout(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>("nvmf_idle_shares", |args: ()| {
+        list(args).boxed_local()
+    });
+}
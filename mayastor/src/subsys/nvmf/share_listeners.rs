@@ -0,0 +1,113 @@
+//!
+//! Listeners of the nvmf shares.
+//!
+//! A share listens on the address of the pod and on the `listen_addresses`
+//! of the nvmf target configuration, e.g. a dedicated storage network next
+//! to the management one. Listeners can be added to and removed from a share
+//! while it is shared, to move its initiators to another network without
+//! unsharing it. The target starts listening on an address when a share is
+//! first given a listener on it.
+//!
+//! Listeners are managed with the `nvmf_add_listener` and
+//! `nvmf_remove_listener` json-rpc methods and reported by
+//! `nvmf_listeners`.
+
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    jsonrpc::jsonrpc_register,
+    subsys::nvmf::{Error, NvmfSubsystem},
+};
+
+/// The listeners of a share.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareListeners {
+    pub nqn: String,
+    /// addresses the share listens on
+    pub addresses: Vec<String>,
+    /// URIs of the share, one per listener
+    pub uris: Vec<String>,
+}
+
+/// returns the subsystem sharing the bdev
+fn subsystem(bdev: &str) -> Result<NvmfSubsystem, Error> {
+    NvmfSubsystem::nqn_lookup(bdev).ok_or_else(|| Error::NotShared {
+        bdev: bdev.to_string(),
+    })
+}
+
+/// returns the listeners of the share of the bdev
+pub fn share_listeners(bdev: &str) -> Result<ShareListeners, Error> {
+    let subsystem = subsystem(bdev)?;
+    Ok(ShareListeners {
+        nqn: subsystem.get_nqn(),
+        addresses: subsystem.listener_addresses(),
+        uris: subsystem.uri_endpoints().unwrap_or_default(),
+    })
+}
+
+/// Add a listener on the address to the share of the bdev.
+pub async fn add_share_listener(
+    bdev: &str,
+    address: &str,
+) -> Result<ShareListeners, Error> {
+    subsystem(bdev)?.add_listener_address(address).await?;
+    share_listeners(bdev)
+}
+
+/// Remove the listener on the address from the share of the bdev.
+pub async fn remove_share_listener(
+    bdev: &str,
+    address: &str,
+) -> Result<ShareListeners, Error> {
+    subsystem(bdev)?.remove_listener_address(address).await?;
+    share_listeners(bdev)
+}
+
+#[derive(Debug, Deserialize)]
+struct ListenerArgs {
+    /// name of the shared bdev
+    name: String,
+    /// address of the listener
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListenersArgs {
+    /// name of the shared bdev
+    name: String,
+}
+
+async fn add(args: ListenerArgs) -> Result<ShareListeners, Error> {
+    info!("adding a listener on {} to {}", args.address, args.name);
+    add_share_listener(&args.name, &args.address).await
+}
+
+async fn remove(args: ListenerArgs) -> Result<ShareListeners, Error> {
+    info!(
+        "removing the listener on {} from {}",
+        args.address, args.name
+    );
+    remove_share_listener(&args.name, &args.address).await
+}
+
+async fn list(args: ListenersArgs) -> Result<ShareListeners, Error> {
+    share_listeners(&args.name)
+}
+
+/// Register the json-rpc methods to manage the listeners of the shares.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nvmf_add_listener",
+        |args: ListenerArgs| add(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nvmf_remove_listener",
+        |args: ListenerArgs| remove(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nvmf_listeners",
+        |args: ListenersArgs| list(args).boxed_local(),
+    );
+}
@@ -0,0 +1,174 @@
+//!
+//! Names the nvmf shares expose to initiators.
+//!
+//! The NQN of a share is made of a prefix and the name of the shared bdev,
+//! and its controllers report a serial number and a model number. Some
+//! hypervisors key on these, so the prefix, the serial number and the model
+//! number are taken from the nvmf target configuration and can be overridden
+//! per share, before the bdev is shared. Overrides are kept in memory only
+//! and can not change while the bdev is shared, as its subsystem is looked up
+//! by its NQN.
+//!
+//! Overrides are set with the `nvmf_set_share_naming` json-rpc method, or
+//! along with the share by `nexus_share`, and the names a share exposes are
+//! reported by `nvmf_share_naming`.
+
+use std::collections::HashMap;
+
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    jsonrpc::jsonrpc_register,
+    subsys::{
+        nvmf::{Error, NvmfSubsystem},
+        Config,
+    },
+};
+
+/// maximum length of an NQN in bytes
+const MAX_NQN_LEN: usize = 223;
+/// maximum length of a serial number in bytes
+const MAX_SN_LEN: usize = 20;
+/// maximum length of a model number in bytes
+const MAX_MN_LEN: usize = 40;
+
+/// Overrides of the names of the share of a bdev, None takes the name from
+/// the target configuration.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShareNaming {
+    pub nqn_prefix: Option<String>,
+    pub serial_number: Option<String>,
+    pub model_number: Option<String>,
+}
+
+/// The names the share of a bdev exposes.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShareIdentity {
+    pub nqn: String,
+    pub serial_number: String,
+    pub model_number: String,
+}
+
+/// overrides of the names of the shares, by bdev name
+static NAMING: Lazy<Mutex<HashMap<String, ShareNaming>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// check that a name is printable ASCII that fits
+fn validate(
+    bdev: &str,
+    what: &str,
+    value: &str,
+    max_len: usize,
+) -> Result<(), Error> {
+    if value.is_empty()
+        || value.len() > max_len
+        || !value.bytes().all(|b| (0x20 ..= 0x7e).contains(&b))
+    {
+        return Err(Error::Naming {
+            bdev: bdev.to_string(),
+            msg: format!(
+                "{} '{}' must be 1 to {} printable ASCII characters",
+                what, value, max_len
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Set the overrides of the names of the share of the bdev, which must not
+/// be shared.
+pub fn set_share_naming(bdev: &str, naming: ShareNaming) -> Result<(), Error> {
+    if Config::get().nexus_opts.nvmf_enable
+        && NvmfSubsystem::nqn_lookup(bdev).is_some()
+    {
+        return Err(Error::Naming {
+            bdev: bdev.to_string(),
+            msg: "the bdev is shared".to_string(),
+        });
+    }
+    if let Some(prefix) = &naming.nqn_prefix {
+        validate(bdev, "NQN", &format!("{}:{}", prefix, bdev), MAX_NQN_LEN)?;
+        if !prefix.starts_with("nqn.") {
+            return Err(Error::Naming {
+                bdev: bdev.to_string(),
+                msg: format!("NQN prefix '{}' must start with nqn.", prefix),
+            });
+        }
+    }
+    if let Some(sn) = &naming.serial_number {
+        validate(bdev, "serial number", sn, MAX_SN_LEN)?;
+    }
+    if let Some(mn) = &naming.model_number {
+        validate(bdev, "model number", mn, MAX_MN_LEN)?;
+    }
+
+    let mut overrides = NAMING.lock();
+    if naming == ShareNaming::default() {
+        overrides.remove(bdev);
+    } else {
+        overrides.insert(bdev.to_string(), naming);
+    }
+    Ok(())
+}
+
+/// returns the names the share of the bdev exposes
+pub fn share_identity(bdev: &str) -> ShareIdentity {
+    let naming = NAMING.lock().get(bdev).cloned().unwrap_or_default();
+    let cfg = &Config::get().nvmf_tcp_tgt_conf;
+    ShareIdentity {
+        nqn: format!(
+            "{}:{}",
+            naming.nqn_prefix.as_ref().unwrap_or(&cfg.nqn_prefix),
+            bdev
+        ),
+        serial_number: naming
+            .serial_number
+            .unwrap_or_else(|| cfg.serial_number.clone()),
+        model_number: naming
+            .model_number
+            .unwrap_or_else(|| cfg.model_number.clone()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SetNamingArgs {
+    /// name of the bdev
+    name: String,
+    #[serde(flatten)]
+    naming: ShareNaming,
+}
+
+#[derive(Debug, Deserialize)]
+struct NamingArgs {
+    /// name of the bdev
+    name: String,
+}
+
+async fn set_naming(args: SetNamingArgs) -> Result<ShareIdentity, Error> {
+    info!(
+        "setting the naming of the share of {}: {:?}",
+        args.name, args.naming
+    );
+    set_share_naming(&args.name, args.naming)?;
+    Ok(share_identity(&args.name))
+}
+
+async fn naming(args: NamingArgs) -> Result<ShareIdentity, Error> {
+    Ok(share_identity(&args.name))
+}
+
+/// Register the json-rpc methods to name the shares.
+pub(crate) fn register_jsonrpc_methods() {
+    jsonrpc_register::<_, _, _, Error>(
+        "nvmf_set_share_naming",
+        |args: SetNamingArgs| set_naming(args).boxed_local(),
+    );
+    jsonrpc_register::<_, _, _, Error>(
+        "nvmf_share_naming",
+        |args: NamingArgs| naming(args).boxed_local(),
+    );
+}
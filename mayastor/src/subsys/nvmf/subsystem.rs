@@ -7,6 +7,7 @@ use std::{
 
 use futures::channel::oneshot;
 use nix::errno::Errno;
+use serde::Serialize;
 
 use spdk_rs::libspdk::{
     nvmf_subsystem_find_listener,
@@ -16,10 +17,12 @@ use spdk_rs::libspdk::{
     spdk_nvmf_ns_get_bdev,
     spdk_nvmf_ns_opts,
     spdk_nvmf_subsystem,
+    spdk_nvmf_subsystem_add_host,
     spdk_nvmf_subsystem_add_listener,
     spdk_nvmf_subsystem_add_ns_ext,
     spdk_nvmf_subsystem_create,
     spdk_nvmf_subsystem_destroy,
+    spdk_nvmf_subsystem_get_ana_reporting,
     spdk_nvmf_subsystem_get_first,
     spdk_nvmf_subsystem_get_first_listener,
     spdk_nvmf_subsystem_get_first_ns,
@@ -28,6 +31,8 @@ use spdk_rs::libspdk::{
     spdk_nvmf_subsystem_get_nqn,
     spdk_nvmf_subsystem_listener_get_trid,
     spdk_nvmf_subsystem_pause,
+    spdk_nvmf_subsystem_remove_host,
+    spdk_nvmf_subsystem_remove_listener,
     spdk_nvmf_subsystem_resume,
     spdk_nvmf_subsystem_set_allow_any_host,
     spdk_nvmf_subsystem_set_ana_reporting,
@@ -44,11 +49,24 @@ use crate::{
     core::{Bdev, Reactors, UntypedBdev},
     ffihelper::{cb_arg, AsStr, FfiResult, IntoCString},
     subsys::{
-        nvmf::{transport::TransportId, Error, NVMF_TGT},
+        nvmf::{
+            share_access::apply_share_token,
+            share_naming::share_identity,
+            transport::{check_address, listen_addresses, TransportId},
+            Error,
+            NVMF_TGT,
+        },
         Config,
     },
 };
 
+/// An initiator connected to a subsystem, through one controller.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectedHost {
+    pub host_nqn: String,
+    pub cntlid: u16,
+}
+
 #[derive(Debug, PartialOrd, PartialEq)]
 pub enum SubType {
     Nvme,
@@ -132,8 +150,9 @@ impl NvmfSubsystem {
         }
         let ss = NvmfSubsystem::new(bdev.name())?;
         ss.set_ana_reporting(true)?;
-        ss.allow_any(true);
-        if let Err(e) = ss.add_namespace(bdev) {
+        if let Err(e) = apply_share_token(bdev.name(), &ss)
+            .and_then(|_| ss.add_namespace(bdev))
+        {
             ss.destroy();
             return Err(e);
         }
@@ -144,7 +163,8 @@ impl NvmfSubsystem {
 impl NvmfSubsystem {
     /// create a new subsystem where the NQN is based on the UUID
     pub fn new(uuid: &str) -> Result<Self, Error> {
-        let nqn = gen_nqn(uuid).into_cstring();
+        let identity = share_identity(uuid);
+        let nqn = identity.nqn.into_cstring();
         let ss = NVMF_TGT
             .with(|t| {
                 let tgt = t.borrow().tgt.as_ptr();
@@ -163,8 +183,7 @@ impl NvmfSubsystem {
                 msg: "ss ptr is null".into(),
             })?;
 
-        // look closely, its a race car!
-        let sn = CString::new("33' ~'~._`o##o>").unwrap();
+        let sn = CString::new(identity.serial_number).unwrap();
 
         unsafe { spdk_nvmf_subsystem_set_sn(ss.as_ptr(), sn.as_ptr()) }
             .to_result(|e| Error::Subsystem {
@@ -173,7 +192,7 @@ impl NvmfSubsystem {
                 msg: "failed to set serial".into(),
             })?;
 
-        let mn = CString::new("Mayastor NVMe controller").unwrap();
+        let mn = CString::new(identity.model_number).unwrap();
         unsafe { spdk_nvmf_subsystem_set_mn(ss.as_ptr(), mn.as_ptr()) }
             .to_result(|e| Error::Subsystem {
                 source: Errno::from_i32(e),
@@ -197,7 +216,9 @@ impl NvmfSubsystem {
         Ok(ss)
     }
 
-    /// add the given bdev to this namespace
+    /// Add the given bdev to this namespace. The NGUID of the namespace is
+    /// the UUID of the bdev, as is its UUID when left unset, so the
+    /// namespace of a replica carries the UUID of the replica.
     pub fn add_namespace<T>(&self, bdev: &Bdev<T>) -> Result<(), Error>
     where
         T: spdk_rs::BdevOps,
@@ -262,6 +283,56 @@ impl NvmfSubsystem {
         };
     }
 
+    /// allow the host to connect to the subsystem
+    pub fn add_host(&self, host_nqn: &str) -> Result<(), Error> {
+        let cnqn = CString::new(host_nqn).unwrap();
+        unsafe { spdk_nvmf_subsystem_add_host(self.0.as_ptr(), cnqn.as_ptr()) }
+            .to_result(|e| Error::Subsystem {
+                source: Errno::from_i32(e),
+                nqn: self.get_nqn(),
+                msg: format!("failed to add host {}", host_nqn),
+            })
+    }
+
+    /// no longer allow the host to connect to the subsystem
+    pub fn remove_host(&self, host_nqn: &str) -> Result<(), Error> {
+        let cnqn = CString::new(host_nqn).unwrap();
+        unsafe {
+            spdk_nvmf_subsystem_remove_host(self.0.as_ptr(), cnqn.as_ptr())
+        }
+        .to_result(|e| Error::Subsystem {
+            source: Errno::from_i32(e),
+            nqn: self.get_nqn(),
+            msg: format!("failed to remove host {}", host_nqn),
+        })
+    }
+
+    /// returns true if an initiator is connected to the subsystem
+    pub fn has_controllers(&self) -> bool {
+        unsafe { !self.0.as_ref().ctrlrs.tqh_first.is_null() }
+    }
+
+    /// returns the initiators connected to the subsystem, one per controller
+    pub fn connected_hosts(&self) -> Vec<ConnectedHost> {
+        let mut hosts = Vec::new();
+        let mut ctrlr = unsafe { self.0.as_ref().ctrlrs.tqh_first };
+        while !ctrlr.is_null() {
+            unsafe {
+                hosts.push(ConnectedHost {
+                    host_nqn: (*ctrlr).hostnqn.as_ptr().as_str().to_string(),
+                    cntlid: (*ctrlr).cntlid,
+                });
+                ctrlr = (*ctrlr).link.tqe_next;
+            }
+        }
+        hosts
+    }
+
+    /// returns true if Asymmetric Namespace Access (ANA) is reported
+    pub fn ana_reporting(&self) -> bool {
+        unsafe { spdk_nvmf_subsystem_get_ana_reporting(self.0.as_ptr()) }
+    }
+
     /// enable Asymmetric Namespace Access (ANA) reporting
     pub fn set_ana_reporting(&self, enable: bool) -> Result<(), Error> {
         match std::env::var("NEXUS_NVMF_ANA_ENABLE") {
@@ -309,8 +380,9 @@ impl NvmfSubsystem {
         Ok(())
     }
 
-    // we currently allow all listeners to the subsystem
-    async fn add_listener(&self) -> Result<(), Error> {
+    /// add a listener on the replica port of the address to the subsystem,
+    /// which must be inactive or paused
+    async fn add_listener(&self, address: &str) -> Result<(), Error> {
         extern "C" fn listen_cb(arg: *mut c_void, status: i32) {
             let s = unsafe { Box::from_raw(arg as *mut oneshot::Sender<i32>) };
             s.send(status).unwrap();
@@ -320,7 +392,10 @@ impl NvmfSubsystem {
 
         // dont yet enable both ports, IOW just add one transportID now
 
-        let trid_replica = TransportId::new(cfg.nexus_opts.nvmf_replica_port);
+        let trid_replica = TransportId::with_address(
+            address,
+            cfg.nexus_opts.nvmf_replica_port,
+        );
 
         let (s, r) = oneshot::channel::<i32>();
         unsafe {
@@ -335,11 +410,87 @@ impl NvmfSubsystem {
         r.await.expect("listener callback gone").to_result(|e| {
             Error::Transport {
                 source: Errno::from_i32(e),
-                msg: "Failed to add listener".to_string(),
+                msg: format!("Failed to add listener on {}", address),
             }
         })
     }
 
+    /// Add a listener on the address to the started subsystem, making the
+    /// target listen on it first if it does not yet.
+    pub async fn add_listener_address(
+        &self,
+        address: &str,
+    ) -> Result<(), Error> {
+        check_address(address)?;
+        if self.listener_addresses().iter().any(|a| a == address) {
+            return Ok(());
+        }
+        NVMF_TGT.with(|t| t.borrow_mut().listen_on(address))?;
+
+        self.pause().await?;
+        let result = self.add_listener(address).await;
+        self.resume().await?;
+        result?;
+
+        info!("{} now listening on {}", self.get_nqn(), address);
+        Ok(())
+    }
+
+    /// Remove the listener on the address from the started subsystem, the
+    /// last listener of a subsystem can not be removed. Initiators connected
+    /// through it have to reconnect through another one.
+    pub async fn remove_listener_address(
+        &self,
+        address: &str,
+    ) -> Result<(), Error> {
+        let addresses = self.listener_addresses();
+        if !addresses.iter().any(|a| a == address) {
+            return Err(Error::Listener {
+                nqn: self.get_nqn(),
+                trid: address.to_string(),
+            });
+        }
+        if addresses.len() == 1 {
+            return Err(Error::Subsystem {
+                source: Errno::EBUSY,
+                nqn: self.get_nqn(),
+                msg: format!("{} is the last listener", address),
+            });
+        }
+
+        let cfg = Config::get();
+        let trid_replica = TransportId::with_address(
+            address,
+            cfg.nexus_opts.nvmf_replica_port,
+        );
+
+        self.pause().await?;
+        let rc = unsafe {
+            spdk_nvmf_subsystem_remove_listener(
+                self.0.as_ptr(),
+                trid_replica.as_ptr(),
+            )
+        };
+        self.resume().await?;
+        rc.to_result(|e| Error::Subsystem {
+            source: Errno::from_i32(-e),
+            nqn: self.get_nqn(),
+            msg: format!("failed to remove the listener on {}", address),
+        })?;
+
+        info!("{} no longer listening on {}", self.get_nqn(), address);
+        Ok(())
+    }
+
+    /// returns the addresses the subsystem listens on
+    pub fn listener_addresses(&self) -> Vec<String> {
+        self.listeners_to_vec()
+            .unwrap_or_default()
+            .iter()
+            .map(|t| t.address())
+            .collect()
+    }
+
     /// start the subsystem previously created -- note that we destroy it on
     /// failure to ensure the state is not in limbo and to avoid leaking
     /// resources
@@ -362,7 +513,9 @@ impl NvmfSubsystem {
             s.send(status).unwrap();
         }
 
-        self.add_listener().await?;
+        for address in listen_addresses() {
+            self.add_listener(&address).await?;
+        }
 
         let (s, r) = oneshot::channel::<i32>();
 
@@ -527,7 +680,14 @@ impl NvmfSubsystem {
     /// get ANA state
     pub async fn get_ana_state(&self) -> Result<u32, Error> {
         let cfg = Config::get();
-        let trid_replica = TransportId::new(cfg.nexus_opts.nvmf_replica_port);
+        let trid_replica = TransportId::with_address(
+            &self
+                .listener_addresses()
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+            cfg.nexus_opts.nvmf_replica_port,
+        );
         let listener = unsafe {
             nvmf_subsystem_find_listener(self.0.as_ptr(), trid_replica.as_ptr())
         };
@@ -549,28 +709,36 @@ impl NvmfSubsystem {
             s.send(status).unwrap();
         }
         let cfg = Config::get();
-        let trid_replica = TransportId::new(cfg.nexus_opts.nvmf_replica_port);
-
-        let (s, r) = oneshot::channel::<i32>();
 
-        unsafe {
-            nvmf_subsystem_set_ana_state(
-                self.0.as_ptr(),
-                trid_replica.as_ptr(),
-                ana_state,
-                0,
-                Some(set_ana_state_cb),
-                cb_arg(s),
+        // the state is reported through every listener of the subsystem
+        for address in self.listener_addresses() {
+            let trid_replica = TransportId::with_address(
+                &address,
+                cfg.nexus_opts.nvmf_replica_port,
             );
-        }
 
-        r.await
-            .expect("Cancellation is not supported")
-            .to_result(|e| Error::Subsystem {
-                source: Errno::from_i32(-e),
-                nqn: self.get_nqn(),
-                msg: "failed to set_ana_state of the subsystem".to_string(),
-            })
+            let (s, r) = oneshot::channel::<i32>();
+
+            unsafe {
+                nvmf_subsystem_set_ana_state(
+                    self.0.as_ptr(),
+                    trid_replica.as_ptr(),
+                    ana_state,
+                    0,
+                    Some(set_ana_state_cb),
+                    cb_arg(s),
+                );
+            }
+
+            r.await
+                .expect("Cancellation is not supported")
+                .to_result(|e| Error::Subsystem {
+                    source: Errno::from_i32(-e),
+                    nqn: self.get_nqn(),
+                    msg: "failed to set_ana_state of the subsystem".to_string(),
+                })?;
+        }
+        Ok(())
     }
 
     /// destroy all subsystems associated with our target, subsystems must be in
@@ -690,5 +858,5 @@ impl NvmfSubsystem {
 }
 
 fn gen_nqn(id: &str) -> String {
-    format!("nqn.2019-05.io.openebs:{}", id)
+    share_identity(id).nqn
 }
@@ -2,6 +2,7 @@ use std::{
     cell::RefCell,
     ffi::{c_void, CString},
     ptr::NonNull,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use nix::errno::Errno;
@@ -28,12 +29,15 @@ use spdk_rs::libspdk::{
 use crate::{
     core::{Cores, Mthread, Reactor, Reactors},
     ffihelper::{AsStr, FfiResult},
+    handoff,
     subsys::{
         nvmf::{
             poll_groups::PollGroup,
+            share_gc,
+            share_idle,
             subsystem::NvmfSubsystem,
             transport,
-            transport::{get_ipv4_address, TransportId},
+            transport::TransportId,
             Error,
             NVMF_PGS,
         },
@@ -47,6 +51,14 @@ thread_local! {
 pub (crate) static NVMF_TGT: RefCell<Target> = RefCell::new(Target::new());
 }
 
+/// set while the target accepts connections, read from other threads
+static ACCEPTING: AtomicBool = AtomicBool::new(false);
+
+/// returns true if the target listens and accepts new connections
+pub fn target_accepting() -> bool {
+    ACCEPTING.load(Ordering::Relaxed)
+}
+
 #[derive(Debug)]
 pub struct Target {
     /// the raw pointer to  our target
@@ -55,6 +67,8 @@ pub struct Target {
     poll_group_count: u16,
     /// The current state of the target
     next_state: TargetState,
+    /// the addresses the target listens on
+    addresses: Vec<String>,
 }
 
 impl Default for Target {
@@ -100,6 +114,7 @@ impl Target {
             tgt: NonNull::dangling(),
             poll_group_count: 0,
             next_state: TargetState::Init,
+            addresses: Vec::new(),
         }
     }
 
@@ -219,11 +234,15 @@ impl Target {
         });
     }
 
-    /// Listen for incoming connections by default we only listen on the replica
-    /// port
-    fn listen(&mut self) -> Result<()> {
+    /// Listen for incoming connections on the given address, on both the
+    /// nexus and the replica port. Listening again on an address the target
+    /// already listens on is a no-op.
+    pub(crate) fn listen_on(&mut self, address: &str) -> Result<()> {
+        if self.addresses.iter().any(|a| a == address) {
+            return Ok(());
+        }
+
         let cfg = Config::get();
-        let trid_nexus = TransportId::new(cfg.nexus_opts.nvmf_nexus_port);
         let mut opts = spdk_nvmf_listen_opts::default();
         unsafe {
             spdk_nvmf_listen_opts_init(
@@ -231,6 +250,9 @@ impl Target {
                 std::mem::size_of::<spdk_nvmf_listen_opts>() as u64,
             );
         }
+
+        let trid_nexus =
+            TransportId::with_address(address, cfg.nexus_opts.nvmf_nexus_port);
         let rc = unsafe {
             spdk_nvmf_tgt_listen_ext(
                 self.tgt.as_ptr(),
@@ -241,11 +263,14 @@ impl Target {
 
         if rc != 0 {
             return Err(Error::CreateTarget {
-                msg: "failed to back target".into(),
+                msg: format!("failed to back target on {}", address),
             });
         }
 
-        let trid_replica = TransportId::new(cfg.nexus_opts.nvmf_replica_port);
+        let trid_replica = TransportId::with_address(
+            address,
+            cfg.nexus_opts.nvmf_replica_port,
+        );
         let rc = unsafe {
             spdk_nvmf_tgt_listen_ext(
                 self.tgt.as_ptr(),
@@ -255,20 +280,94 @@ impl Target {
         };
 
         if rc != 0 {
+            unsafe {
+                spdk_nvmf_tgt_stop_listen(
+                    self.tgt.as_ptr(),
+                    trid_nexus.as_ptr(),
+                )
+            };
             return Err(Error::CreateTarget {
-                msg: "failed to front target".into(),
+                msg: format!("failed to front target on {}", address),
             });
         }
         info!(
             "nvmf target listening on {}:({},{})",
-            get_ipv4_address().unwrap(),
+            address,
             trid_nexus.trsvcid.as_str(),
             trid_replica.trsvcid.as_str(),
         );
+        self.addresses.push(address.to_string());
+        Ok(())
+    }
+
+    /// Listen for incoming connections on the configured addresses, unless
+    /// they are held by the mayastor this one takes over from.
+    fn listen(&mut self) -> Result<()> {
+        if handoff::is_standby() {
+            info!("nvmf target not listening until the handoff");
+        } else {
+            self.listen_configured()?;
+        }
         self.next_state();
         Ok(())
     }
 
+    fn listen_configured(&mut self) -> Result<()> {
+        for address in transport::listen_addresses() {
+            self.listen_on(&address)?;
+        }
+        Ok(())
+    }
+
+    /// stop listening on all addresses, dropping the listeners of the
+    /// subsystems on them
+    fn stop_listening(&mut self) {
+        let cfg = Config::get();
+        for address in self.addresses.drain(..) {
+            let trid_nexus = TransportId::with_address(
+                &address,
+                cfg.nexus_opts.nvmf_nexus_port,
+            );
+            let trid_replica = TransportId::with_address(
+                &address,
+                cfg.nexus_opts.nvmf_replica_port,
+            );
+
+            unsafe {
+                spdk_nvmf_tgt_stop_listen(
+                    self.tgt.as_ptr(),
+                    trid_replica.as_ptr(),
+                )
+            };
+
+            unsafe {
+                spdk_nvmf_tgt_stop_listen(
+                    self.tgt.as_ptr(),
+                    trid_nexus.as_ptr(),
+                )
+            };
+        }
+    }
+
+    /// Listen on the configured addresses once the mayastor this one takes
+    /// over from released them.
+    pub(crate) fn resume_listening() -> Result<()> {
+        NVMF_TGT.with(|t| {
+            let mut tgt = t.borrow_mut();
+            tgt.listen_configured()?;
+            ACCEPTING.store(true, Ordering::Relaxed);
+            Ok(())
+        })
+    }
+
+    /// Stop listening on all addresses, releasing them to the mayastor
+    /// taking over from this one.
+    pub(crate) fn release_listeners() {
+        ACCEPTING.store(false, Ordering::Relaxed);
+        NVMF_TGT.with(|t| t.borrow_mut().stop_listening());
+        info!("nvmf target released its listeners");
+    }
+
     /// enable discovery for the target -- note that the discovery system is not
     /// started
     fn enable_discovery(&self) {
@@ -354,6 +453,9 @@ impl Target {
             "nvmf target accepting new connections and is ready to roll..{}",
             '\u{1F483}'
         );
+        share_gc::start();
+        share_idle::start();
+        ACCEPTING.store(!self.addresses.is_empty(), Ordering::Relaxed);
 
         unsafe { spdk_subsystem_init_next(0) }
     }
@@ -367,17 +469,7 @@ impl Target {
             }
         }
 
-        let cfg = Config::get();
-        let trid_nexus = TransportId::new(cfg.nexus_opts.nvmf_nexus_port);
-        let trid_replica = TransportId::new(cfg.nexus_opts.nvmf_replica_port);
-
-        unsafe {
-            spdk_nvmf_tgt_stop_listen(self.tgt.as_ptr(), trid_replica.as_ptr())
-        };
-
-        unsafe {
-            spdk_nvmf_tgt_stop_listen(self.tgt.as_ptr(), trid_nexus.as_ptr())
-        };
+        self.stop_listening();
 
         unsafe {
             spdk_nvmf_tgt_destroy(
@@ -390,6 +482,9 @@ impl Target {
 
     /// start the shutdown of the target and subsystems
     pub(crate) fn start_shutdown(&mut self) {
+        ACCEPTING.store(false, Ordering::Relaxed);
+        share_gc::stop();
+        share_idle::stop();
         self.next_state = TargetState::ShutdownSubsystems;
         Reactors::master().send_future(async {
             NVMF_TGT.with(|tgt| {
@@ -2,7 +2,7 @@ use std::{
     env,
     ffi::CString,
     fmt::{Debug, Display, Formatter},
-    net::Ipv4Addr,
+    net::{IpAddr, Ipv6Addr},
     ops::{Deref, DerefMut},
     ptr::copy_nonoverlapping,
 };
@@ -17,6 +17,7 @@ use spdk_rs::libspdk::{
     spdk_nvmf_transport_create,
     SPDK_NVME_TRANSPORT_TCP,
     SPDK_NVMF_ADRFAM_IPV4,
+    SPDK_NVMF_ADRFAM_IPV6,
     SPDK_NVMF_TRSVCID_MAX_LEN,
 };
 
@@ -84,16 +85,19 @@ impl DerefMut for TransportId {
 }
 
 impl TransportId {
-    pub fn new(port: u16) -> Self {
-        let address = get_ipv4_address().unwrap();
-
+    /// transport ID of the given address and port
+    pub fn with_address(address: &str, port: u16) -> Self {
         let mut trid = spdk_nvme_transport_id {
             trtype: SPDK_NVME_TRANSPORT_TCP,
-            adrfam: SPDK_NVMF_ADRFAM_IPV4,
+            adrfam: if address.parse::<Ipv6Addr>().is_ok() {
+                SPDK_NVMF_ADRFAM_IPV6
+            } else {
+                SPDK_NVMF_ADRFAM_IPV4
+            },
             ..Default::default()
         };
 
-        let c_addr = address.into_cstring();
+        let c_addr = address.to_string().into_cstring();
         let port = format!("{}", port);
 
         assert!(port.len() < SPDK_NVMF_TRSVCID_MAX_LEN as usize);
@@ -122,16 +126,31 @@ impl TransportId {
     pub fn as_ptr(&self) -> *mut spdk_nvme_transport_id {
         &self.0 as *const _ as *mut spdk_nvme_transport_id
     }
+
+    /// returns the address of the transport ID
+    pub fn address(&self) -> String {
+        self.0.traddr.as_str().to_string()
+    }
 }
 
 impl Display for TransportId {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "nvmf://{}:{}",
-            self.0.traddr.as_str(),
-            self.0.trsvcid.as_str()
-        )
+        // IPv6 literals are bracketed in URIs
+        if self.0.adrfam == SPDK_NVMF_ADRFAM_IPV6 {
+            write!(
+                f,
+                "nvmf://[{}]:{}",
+                self.0.traddr.as_str(),
+                self.0.trsvcid.as_str()
+            )
+        } else {
+            write!(
+                f,
+                "nvmf://{}:{}",
+                self.0.traddr.as_str(),
+                self.0.trsvcid.as_str()
+            )
+        }
     }
 }
 
@@ -145,14 +164,15 @@ impl Debug for TransportId {
             .finish()
     }
 }
-pub(crate) fn get_ipv4_address() -> Result<String, Error> {
+/// returns the address of the pod, either an IPv4 or an IPv6 one
+pub(crate) fn get_ip_address() -> Result<String, Error> {
     let address = match env::var("MY_POD_IP") {
         Ok(val) => {
-            if val.parse::<Ipv4Addr>().is_ok() {
+            if val.parse::<IpAddr>().is_ok() {
                 Ok(val)
             } else {
                 Err(Error::CreateTarget {
-                    msg: "Invalid IPv4 address".into(),
+                    msg: "Invalid IP address".into(),
                 })
             }
         }
@@ -161,3 +181,25 @@ pub(crate) fn get_ipv4_address() -> Result<String, Error> {
 
     Ok(address)
 }
+
+/// check that the address is one the target can listen on
+pub(crate) fn check_address(address: &str) -> Result<(), Error> {
+    if address.parse::<IpAddr>().is_err() {
+        return Err(Error::InvalidAddress {
+            address: address.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Returns the addresses the target listens on by default, the address of
+/// the pod followed by the ones of the target configuration.
+pub(crate) fn listen_addresses() -> Vec<String> {
+    let mut addresses = vec![get_ip_address().unwrap()];
+    for address in &Config::get().nvmf_tcp_tgt_conf.listen_addresses {
+        if !addresses.contains(address) {
+            addresses.push(address.clone());
+        }
+    }
+    addresses
+}
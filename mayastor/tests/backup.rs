@@ -0,0 +1,172 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use common::MayastorTest;
+use mayastor::{
+    backup::{
+        backup_jobs,
+        backup_replica,
+        restore_backup,
+        BackupError,
+        Compression,
+        JobState,
+        ObjectStore,
+    },
+    core::{MayastorCliArgs, UntypedBdev},
+    lvs::Lvs,
+    pool::PoolArgs,
+};
+
+pub mod common;
+
+static DISKNAME: &str = "/tmp/backup.img";
+
+/// object store keeping its objects in memory
+#[derive(Default)]
+struct TestStore(Mutex<HashMap<String, Vec<u8>>>);
+
+#[async_trait]
+impl ObjectStore for TestStore {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), BackupError> {
+        self.0.lock().unwrap().insert(key.to_string(), data);
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, BackupError> {
+        self.0.lock().unwrap().get(key).cloned().ok_or_else(|| {
+            BackupError::Refused {
+                key: key.to_string(),
+                status: 404,
+            }
+        })
+    }
+}
+
+/// wait for the backup jobs to finish
+async fn wait_jobs(ms: &MayastorTest<'_>) {
+    for _ in 0 .. 100 {
+        if ms
+            .spawn(async {
+                backup_jobs().iter().all(|j| j.state != JobState::Running)
+            })
+            .await
+        {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("backup jobs did not finish");
+}
+
+/// fill a block of a replica at the offset with the pattern
+async fn write_replica(name: &str, offset: u64, pattern: u8) {
+    let hdl = UntypedBdev::open_by_name(name, true)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = hdl.dma_malloc(4096).unwrap();
+    buf.fill(pattern);
+    hdl.write_at(offset, &buf).await.unwrap();
+}
+
+/// returns true if the block of a replica at the offset holds the pattern
+async fn replica_holds(name: &str, offset: u64, pattern: u8) -> bool {
+    let hdl = UntypedBdev::open_by_name(name, false)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = hdl.dma_malloc(4096).unwrap();
+    hdl.read_at(offset, &mut buf).await.unwrap();
+    buf.as_slice().iter().all(|b| *b == pattern)
+}
+
+#[tokio::test]
+async fn backup_restore() {
+    common::delete_file(&[DISKNAME.into()]);
+    common::truncate_file(DISKNAME, 128 * 1024);
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+    let store = Arc::new(TestStore::default());
+
+    let s = store.clone();
+    ms.spawn(async move {
+        let pool = Lvs::create_or_import(PoolArgs {
+            name: "bpool".into(),
+            disks: vec![format!("aio://{}", DISKNAME)],
+            uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
+        })
+        .await
+        .unwrap();
+        pool.create_lvol("backup-src", 16 * 1024 * 1024, None, false)
+            .await
+            .unwrap();
+        write_replica("backup-src", 0, 0xa5).await;
+        write_replica("backup-src", 9 * 1024 * 1024, 0x5a).await;
+
+        backup_replica(s, "backup-src", "b1", Compression::Lz4)
+            .await
+            .unwrap();
+    })
+    .await;
+    wait_jobs(&ms).await;
+
+    let s = store.clone();
+    ms.spawn(async move {
+        let job = backup_jobs()
+            .into_iter()
+            .find(|j| j.backup == "b1")
+            .unwrap();
+        assert_eq!(job.state, JobState::Completed);
+        assert_eq!(job.done, 16 * 1024 * 1024);
+
+        // chunks holding only zeroes are not stored
+        let objects = s.0.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert!(objects.contains(&"b1/manifest.json".to_string()));
+        assert!(objects.contains(&"b1/00000000".to_string()));
+        assert!(objects.contains(&"b1/00000002".to_string()));
+        assert!(!objects.contains(&"b1/00000001".to_string()));
+
+        restore_backup(s, "b1", "bpool", "backup-dst", None, true)
+            .await
+            .unwrap();
+    })
+    .await;
+    wait_jobs(&ms).await;
+
+    let s = store.clone();
+    ms.spawn(async move {
+        let job = backup_jobs()
+            .into_iter()
+            .find(|j| j.replica == "backup-dst")
+            .unwrap();
+        assert_eq!(job.state, JobState::Completed);
+        assert!(replica_holds("backup-dst", 0, 0xa5).await);
+        assert!(replica_holds("backup-dst", 4 * 1024 * 1024, 0).await);
+        assert!(replica_holds("backup-dst", 9 * 1024 * 1024, 0x5a).await);
+
+        // a corrupted chunk fails the restore, which leaves no replica
+        s.0.lock().unwrap().get_mut("b1/00000002").unwrap()[0] ^= 1;
+        restore_backup(s, "b1", "bpool", "backup-bad", None, true)
+            .await
+            .unwrap();
+    })
+    .await;
+    wait_jobs(&ms).await;
+
+    ms.spawn(async {
+        let job = backup_jobs()
+            .into_iter()
+            .find(|j| j.replica == "backup-bad")
+            .unwrap();
+        assert_eq!(job.state, JobState::Failed);
+        assert!(UntypedBdev::lookup_by_name("backup-bad").is_none());
+
+        Lvs::lookup("bpool").unwrap().destroy().await.unwrap();
+    })
+    .await;
+}
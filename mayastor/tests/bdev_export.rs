@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use common::MayastorTest;
+use mayastor::{
+    bdev::device_create,
+    core::{
+        export::{bdev_exports, ExportSpec, ExportState},
+        MayastorCliArgs,
+        UntypedBdev,
+    },
+};
+
+pub mod common;
+
+static EXPORT_FILE: &str = "/tmp/export.img";
+
+#[tokio::test]
+async fn bdev_export() {
+    common::delete_file(&[EXPORT_FILE.into()]);
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        device_create("malloc:///e0?size_mb=8").await.unwrap();
+        let hdl = UntypedBdev::open_by_name("e0", true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        buf.fill(0xa5);
+        hdl.write_at(5 * 1024 * 1024, &buf).await.unwrap();
+
+        let export = ExportSpec {
+            bdev: "e0".into(),
+            file: EXPORT_FILE.into(),
+            rate_mb: 16,
+            overwrite: false,
+        }
+        .start()
+        .unwrap();
+        assert_eq!(export.size, 8 * 1024 * 1024);
+
+        // an export to the same file can not be started meanwhile
+        assert!(ExportSpec {
+            bdev: "e0".into(),
+            file: EXPORT_FILE.into(),
+            rate_mb: 0,
+            overwrite: true,
+        }
+        .start()
+        .is_err());
+    })
+    .await;
+
+    // the export is throttled to 16MiB/s, it takes half a second
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    let running = ms
+        .spawn(async {
+            bdev_exports()
+                .iter()
+                .any(|e| e.state == ExportState::Running)
+        })
+        .await;
+    assert!(running);
+
+    for _ in 0 .. 50 {
+        let done = ms
+            .spawn(async {
+                bdev_exports()
+                    .iter()
+                    .all(|e| e.state != ExportState::Running)
+            })
+            .await;
+        if done {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    ms.spawn(async {
+        let export = &bdev_exports()[0];
+        assert_eq!(export.state, ExportState::Completed);
+        assert_eq!(export.exported, 8 * 1024 * 1024);
+    })
+    .await;
+
+    let data = std::fs::read(EXPORT_FILE).unwrap();
+    assert_eq!(data.len(), 8 * 1024 * 1024);
+    assert!(data[5 * 1024 * 1024 .. 5 * 1024 * 1024 + 4096]
+        .iter()
+        .all(|b| *b == 0xa5));
+    assert!(data[.. 5 * 1024 * 1024].iter().all(|b| *b == 0));
+    common::delete_file(&[EXPORT_FILE.into()]);
+}
@@ -14,7 +14,7 @@ use url::{ParseError, Url};
 use tracing::{error, info, trace};
 
 use mayastor::{
-    core::{MayastorEnvironment, Mthread},
+    core::{MayastorEnvironment, Mthread, UntypedBdev},
     logger,
     rebuild::{ClientOperations, RebuildJob, RebuildState},
 };
@@ -25,6 +25,28 @@ pub mod error_bdev;
 
 pub use compose::MayastorTest;
 
+/// fill the blocks of a bdev at the offset with the pattern
+pub async fn write_bdev(name: &str, offset: u64, pattern: u8) {
+    let hdl = UntypedBdev::open_by_name(name, true)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = hdl.dma_malloc(4096).unwrap();
+    buf.fill(pattern);
+    hdl.write_at(offset, &buf).await.unwrap();
+}
+
+/// returns true if the blocks of a bdev at the offset hold the pattern
+pub async fn bdev_holds(name: &str, offset: u64, pattern: u8) -> bool {
+    let hdl = UntypedBdev::open_by_name(name, false)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = hdl.dma_malloc(4096).unwrap();
+    hdl.read_at(offset, &mut buf).await.unwrap();
+    buf.as_slice().iter().all(|b| *b == pattern)
+}
+
 /// call F cnt times, and sleep for a duration between each invocation
 pub fn retry<F, T, E>(mut cnt: u32, timeout: Duration, mut f: F) -> T
 where
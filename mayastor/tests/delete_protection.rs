@@ -0,0 +1,72 @@
+use std::pin::Pin;
+
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut},
+    core::MayastorCliArgs,
+    grpc::v1::nexus::nexus_destroy,
+    lvs::Lvs,
+    pool::PoolArgs,
+};
+pub mod common;
+
+#[tokio::test]
+async fn delete_protection() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            "pnexus",
+            32 * 1024 * 1024,
+            None,
+            &["malloc:///pn0?size_mb=64".into()],
+        )
+        .await
+        .unwrap();
+
+        let nexus = nexus_lookup_mut("pnexus").unwrap();
+        nexus.set_protected(true);
+        assert!(nexus_destroy("pnexus").await.is_err());
+        assert!(nexus_lookup_mut("pnexus").is_some());
+
+        // force overrides the protection
+        let nexus = nexus_lookup_mut("pnexus").unwrap();
+        nexus.check_protected(true).unwrap();
+        nexus.set_protected(false);
+        nexus_destroy("pnexus").await.unwrap();
+        assert!(nexus_lookup_mut("pnexus").is_none());
+    })
+    .await;
+
+    ms.spawn(async {
+        let pool = Lvs::create_or_import(PoolArgs {
+            name: "ppool".into(),
+            disks: vec!["malloc:///pdisk?size_mb=64".into()],
+            uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
+        })
+        .await
+        .unwrap();
+
+        let mut lvol = pool
+            .create_lvol("plvol", 8 * 1024 * 1024, None, true)
+            .await
+            .unwrap();
+        assert!(!lvol.is_protected().await);
+
+        Pin::new(&mut lvol).set_protected(true).await.unwrap();
+        assert!(lvol.is_protected().await);
+        assert!(lvol.check_protected(false).await.is_err());
+        assert!(lvol.check_protected(true).await.is_ok());
+
+        // neither can the pool holding it be destroyed
+        assert!(pool.check_protected().await.is_err());
+
+        Pin::new(&mut lvol).set_protected(false).await.unwrap();
+        assert!(pool.check_protected().await.is_ok());
+        lvol.destroy().await.unwrap();
+        pool.destroy().await.unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,140 @@
+use std::time::{Duration, Instant};
+
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut, ChildState},
+    core::{
+        fault_injection::{
+            self,
+            FaultAction,
+            FaultDomain,
+            FaultError,
+            FaultIo,
+            FaultRule,
+        },
+        MayastorCliArgs,
+        UntypedBdev,
+    },
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "fault_nexus";
+
+fn rule(id: &str, io: FaultIo, action: FaultAction) -> FaultRule {
+    FaultRule {
+        id: id.into(),
+        domain: FaultDomain::Child,
+        device: None,
+        io,
+        min_size: 0,
+        percent: 100,
+        every: 1,
+        max_faults: None,
+        action,
+    }
+}
+
+#[tokio::test]
+async fn fault_injection() {
+    // rules are refused until fault injection is enabled
+    assert!(matches!(
+        fault_injection::add_rule(rule(
+            "early",
+            FaultIo::Any,
+            FaultAction::Fail
+        )),
+        Err(FaultError::NotEnabled {})
+    ));
+
+    let ms = MayastorTest::new(MayastorCliArgs {
+        enable_fault_injection: true,
+        ..Default::default()
+    });
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &[
+                "malloc:///fi0?size_mb=64".to_string(),
+                "malloc:///fi1?size_mb=64".to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let hdl = UntypedBdev::open_by_name(NEXUS_NAME, true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        buf.fill(0xa5);
+
+        // reads complete late but successfully
+        fault_injection::add_rule(rule(
+            "slow",
+            FaultIo::Read,
+            FaultAction::Delay {
+                delay_ms: 50,
+            },
+        ))
+        .unwrap();
+        let start = Instant::now();
+        hdl.read_at(0, &mut buf).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        hdl.write_at(0, &buf).await.unwrap();
+
+        let stats = fault_injection::remove_rule("slow").unwrap();
+        assert_eq!((stats.matched, stats.faults), (1, 1));
+        assert!(fault_injection::rules().is_empty());
+
+        // a failed write takes the child out
+        fault_injection::add_rule(FaultRule {
+            device: Some("fi1".into()),
+            max_faults: Some(1),
+            ..rule("flaky", FaultIo::Write, FaultAction::Fail)
+        })
+        .unwrap();
+        assert!(matches!(
+            fault_injection::add_rule(rule(
+                "flaky",
+                FaultIo::Any,
+                FaultAction::Fail
+            )),
+            Err(FaultError::RuleExists { .. })
+        ));
+        let _ = hdl.write_at(4096, &buf).await;
+        assert_eq!(fault_injection::rules()[0].faults, 1);
+    })
+    .await;
+
+    let mut faulted = false;
+    for _ in 0 .. 50 {
+        faulted = ms
+            .spawn(async {
+                let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+                nexus
+                    .children
+                    .iter()
+                    .any(|c| matches!(c.state(), ChildState::Faulted(_)))
+            })
+            .await;
+        if faulted {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(faulted);
+
+    ms.spawn(async {
+        fault_injection::remove_rule("flaky").unwrap();
+        nexus_lookup_mut(NEXUS_NAME)
+            .unwrap()
+            .destroy()
+            .await
+            .unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,110 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create_encrypted, nexus_lookup_mut},
+    core::MayastorCliArgs,
+    key_manager::KeyManager,
+    kms::kms_defs::{DataKey, Kms, KmsError},
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "kms_nexus";
+
+/// KMS keeping its keys in memory, the data key of version n of a key is
+/// its n-th entry and unwrapping returns the wrapped key reversed.
+struct TestKms(HashMap<String, Vec<String>>);
+
+#[async_trait]
+impl Kms for TestKms {
+    async fn fetch(
+        &self,
+        key_id: &str,
+        version: Option<u64>,
+    ) -> Result<DataKey, KmsError> {
+        let not_found = || KmsError::Refused {
+            key_id: key_id.to_string(),
+            status: 404,
+        };
+        let versions = self.0.get(key_id).ok_or_else(not_found)?;
+        let version = version.unwrap_or(versions.len() as u64);
+        versions
+            .get((version as usize).wrapping_sub(1))
+            .map(|k| DataKey::new(version, k.clone()))
+            .ok_or_else(not_found)
+    }
+
+    async fn rotate(&self, key_id: &str) -> Result<u64, KmsError> {
+        Ok(self.0.get(key_id).map_or(0, |v| v.len() as u64 + 1))
+    }
+
+    async fn unwrap(
+        &self,
+        _key_id: &str,
+        wrapped: &str,
+    ) -> Result<DataKey, KmsError> {
+        Ok(DataKey::new(1, wrapped.chars().rev().collect()))
+    }
+}
+
+#[tokio::test]
+async fn key_manager_resolve() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    let mut keys = HashMap::new();
+    keys.insert(
+        "vol-1".to_string(),
+        vec![
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"
+                .to_string(),
+            "202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f"
+                .to_string(),
+        ],
+    );
+    KeyManager::init_with(Arc::new(TestKms(keys)));
+
+    ms.spawn(async {
+        // plain keys are used as they are
+        assert_eq!(KeyManager::resolve("abcd").await.unwrap(), "abcd");
+
+        assert!(KeyManager::resolve("kms://vol-1")
+            .await
+            .unwrap()
+            .starts_with("2021"));
+        assert!(KeyManager::resolve("kms://vol-1?version=1")
+            .await
+            .unwrap()
+            .starts_with("0001"));
+        assert_eq!(
+            KeyManager::resolve("kms://kek?wrapped=abcd").await.unwrap(),
+            "dcba"
+        );
+        assert_eq!(KeyManager::rotate("vol-1").await.unwrap(), 3);
+
+        assert!(KeyManager::resolve("kms://").await.is_err());
+        assert!(KeyManager::resolve("kms://vol-1?version=x").await.is_err());
+        assert!(KeyManager::resolve("kms://vol-1?other=1").await.is_err());
+        assert!(KeyManager::resolve("kms://vol-2").await.is_err());
+    })
+    .await;
+
+    // a nexus can be encrypted with a key of the KMS
+    ms.spawn(async {
+        nexus_create_encrypted(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &["malloc:///m0?size_mb=64".to_string()],
+            "kms://vol-1?version=1",
+        )
+        .await
+        .unwrap();
+
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        assert!(nexus.is_encrypted());
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
@@ -1,7 +1,7 @@
 use common::MayastorTest;
 use mayastor::{
     core::{MayastorCliArgs, Protocol, Share, UntypedBdev},
-    lvs::{Lvs, PropName, PropValue},
+    lvs::{Lvs, PoolLabels, PropName, PropValue},
     nexus_uri::bdev_create,
     pool::PoolArgs,
     subsys::NvmfSubsystem,
@@ -36,6 +36,8 @@ async fn lvs_pool_test() {
             name: "tpool".into(),
             disks: vec!["aio:///tmp/disk1.img".into()],
             uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
         })
         .await
         .unwrap();
@@ -47,7 +49,7 @@ async fn lvs_pool_test() {
     // have an idempotent snafu, we dont crash and
     // burn
     ms.spawn(async {
-        assert!(Lvs::create("tpool", "aio:///tmp/disk1.img", None)
+        assert!(Lvs::create("tpool", "aio:///tmp/disk1.img", None, None)
             .await
             .is_err())
     })
@@ -103,7 +105,7 @@ async fn lvs_pool_test() {
         assert!(Lvs::import("tpool", "aio:///tmp/disk1.img").await.is_err());
 
         assert_eq!(Lvs::iter().count(), 0);
-        assert!(Lvs::create("tpool", "aio:///tmp/disk1.img", None)
+        assert!(Lvs::create("tpool", "aio:///tmp/disk1.img", None, None)
             .await
             .is_ok());
 
@@ -137,6 +139,8 @@ async fn lvs_pool_test() {
             name: "tpool2".to_string(),
             disks: vec!["malloc:///malloc0?size_mb=64".to_string()],
             uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
         })
         .await
         .unwrap();
@@ -171,6 +175,8 @@ async fn lvs_pool_test() {
             name: "tpool".to_string(),
             disks: vec!["aio:///tmp/disk1.img".to_string()],
             uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
         })
         .await
         .unwrap();
@@ -187,6 +193,40 @@ async fn lvs_pool_test() {
     })
     .await;
 
+    // labels given on import are reported by the pool and can be used to
+    // select a pool, they are dropped when the pool is exported
+    ms.spawn(async {
+        let mut labels = PoolLabels::new();
+        labels.insert("media".into(), "ssd".into());
+
+        let pool = Lvs::lookup("tpool").unwrap();
+        pool.export().await.unwrap();
+        let pool = Lvs::create_or_import(PoolArgs {
+            name: "tpool".to_string(),
+            disks: vec!["aio:///tmp/disk1.img".to_string()],
+            uuid: None,
+            labels: labels.clone(),
+            cluster_size: None,
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(pool.labels(), labels);
+        assert!(pool.matches_labels(&labels));
+        assert!(!Lvs::lookup("tpool2").unwrap().matches_labels(&labels));
+        assert_eq!(
+            Lvs::select_by_labels(&labels, 4 * 1024 * 1024)
+                .unwrap()
+                .name(),
+            "tpool"
+        );
+        assert!(Lvs::select_by_labels(&labels, pool.capacity() + 1).is_none());
+
+        pool.set_labels(PoolLabels::new());
+        assert!(Lvs::select_by_labels(&labels, 0).is_none());
+    })
+    .await;
+
     // share all the replica's on the pool tpool2
     ms.spawn(async {
         let pool2 = Lvs::lookup("tpool2").unwrap();
@@ -308,6 +348,8 @@ async fn lvs_pool_test() {
             name: "tpool".into(),
             disks: vec!["aio:///tmp/disk1.img".into()],
             uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
         })
         .await
         .unwrap();
@@ -337,6 +379,8 @@ async fn lvs_pool_test() {
             name: "jpool".into(),
             disks: vec!["aio:///tmp/disk1.img".into()],
             uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
         })
         .await
         .err()
@@ -352,6 +396,8 @@ async fn lvs_pool_test() {
             name: "tpool2".into(),
             disks: vec!["/tmp/disk2.img".into()],
             uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
         })
         .await
         .unwrap();
@@ -0,0 +1,114 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut},
+    core::{BdevHandle, MayastorCliArgs, Protocol},
+};
+pub mod common;
+
+#[tokio::test]
+async fn nexus_block_sizes() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            "emu0",
+            32 * 1024 * 1024,
+            None,
+            &[
+                "malloc:///m512?blk_size=512&size_mb=64".into(),
+                "malloc:///m4k?blk_size=4096&size_mb=64".into(),
+            ],
+        )
+        .await
+        .unwrap();
+
+        // the nexus presents the smallest block size of its children
+        let nexus = nexus_lookup_mut("emu0").unwrap();
+        assert_eq!(nexus.block_len(), 512);
+
+        let hdl = BdevHandle::open("emu0", true, false).unwrap();
+
+        // zero a whole 4KiB block of the larger child, then write two 512
+        // byte blocks within it, which are read-modify-writes on that child
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        buf.fill(0);
+        hdl.write_at(8192, &buf).await.unwrap();
+
+        let mut b = hdl.dma_malloc(512).unwrap();
+        b.fill(0xa5);
+        hdl.write_at(8192 + 512, &b).await.unwrap();
+        b.fill(0x5a);
+        hdl.write_at(8192 + 3584, &b).await.unwrap();
+
+        // reads are spread over the children, so read a few times to read
+        // from both of them
+        for _ in 0 .. 4 {
+            let mut r = hdl.dma_malloc(4096).unwrap();
+            r.fill(0xff);
+            hdl.read_at(8192, &mut r).await.unwrap();
+            let s = r.as_slice();
+            assert!(s[.. 512].iter().all(|b| *b == 0));
+            assert!(s[512 .. 1024].iter().all(|b| *b == 0xa5));
+            assert!(s[1024 .. 3584].iter().all(|b| *b == 0));
+            assert!(s[3584 ..].iter().all(|b| *b == 0x5a));
+
+            // an unaligned read within a native block of the larger child
+            let mut r = hdl.dma_malloc(512).unwrap();
+            hdl.read_at(8192 + 3584, &mut r).await.unwrap();
+            assert!(r.as_slice().iter().all(|b| *b == 0x5a));
+        }
+
+        drop(hdl);
+        nexus_lookup_mut("emu0").unwrap().destroy().await.unwrap();
+    })
+    .await;
+
+    ms.spawn(async {
+        nexus_create(
+            "emu1",
+            32 * 1024 * 1024,
+            None,
+            &[
+                "malloc:///n0?blk_size=512&size_mb=64".into(),
+                "malloc:///n1?blk_size=512&size_mb=64".into(),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let mut nexus = nexus_lookup_mut("emu1").unwrap();
+        let size = nexus.size_in_bytes();
+        assert!(nexus.as_mut().set_logical_block_size(1024).await.is_err());
+
+        // expose 4K native blocks on top of 512 byte children
+        nexus.as_mut().set_logical_block_size(4096).await.unwrap();
+        assert_eq!(nexus.block_len(), 4096);
+        // the nexus ends on a whole block of the new size
+        assert_eq!(nexus.size_in_bytes(), size - size % 4096);
+
+        let hdl = BdevHandle::open("emu1", true, false).unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        buf.fill(0x3c);
+        hdl.write_at(4096, &buf).await.unwrap();
+        let mut r = hdl.dma_malloc(4096).unwrap();
+        hdl.read_at(4096, &mut r).await.unwrap();
+        assert!(r.as_slice().iter().all(|b| *b == 0x3c));
+        drop(hdl);
+
+        // and back to 512 byte blocks, which keeps the data in place
+        nexus.as_mut().set_logical_block_size(512).await.unwrap();
+        let hdl = BdevHandle::open("emu1", true, false).unwrap();
+        let mut r = hdl.dma_malloc(512).unwrap();
+        hdl.read_at(4096 + 512, &mut r).await.unwrap();
+        assert!(r.as_slice().iter().all(|b| *b == 0x3c));
+        drop(hdl);
+
+        // the block size of a shared nexus can not change
+        nexus.as_mut().share(Protocol::Nvmf, None).await.unwrap();
+        assert!(nexus.as_mut().set_logical_block_size(4096).await.is_err());
+        nexus.as_mut().unshare_nexus().await.unwrap();
+
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,105 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut, NexusCacheOpts},
+    core::{MayastorCliArgs, UntypedBdev},
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "cache_nexus";
+
+/// write the pattern to the first blocks of the nexus
+async fn write_pattern(pattern: u8) {
+    let hdl = UntypedBdev::open_by_name(NEXUS_NAME, true)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = hdl.dma_malloc(4096).unwrap();
+    buf.fill(pattern);
+    hdl.write_at(0, &buf).await.unwrap();
+}
+
+/// check that the first blocks of the nexus hold the pattern
+async fn check_pattern(pattern: u8) {
+    let hdl = UntypedBdev::open_by_name(NEXUS_NAME, false)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = hdl.dma_malloc(4096).unwrap();
+    hdl.read_at(0, &mut buf).await.unwrap();
+    assert!(buf.as_slice().iter().all(|b| *b == pattern));
+}
+
+#[tokio::test]
+async fn nexus_write_cache() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &["malloc:///m0?size_mb=64".to_string()],
+        )
+        .await
+        .unwrap();
+        write_pattern(1).await;
+    })
+    .await;
+
+    // a cache smaller than a block is refused
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        assert!(nexus
+            .set_write_cache(NexusCacheOpts {
+                size: 16,
+                ..Default::default()
+            })
+            .await
+            .is_err());
+    })
+    .await;
+
+    // cached data is read back, and is on the child once the cache has been
+    // drained by disabling it
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus
+            .set_write_cache(NexusCacheOpts {
+                size: 1024 * 1024,
+                writeback_ms: 60_000,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(nexus.write_cache_opts().size, 1024 * 1024);
+
+        write_pattern(2).await;
+        check_pattern(2).await;
+
+        nexus
+            .set_write_cache(NexusCacheOpts::default())
+            .await
+            .unwrap();
+        assert_eq!(nexus.write_cache_opts(), NexusCacheOpts::default());
+        check_pattern(2).await;
+    })
+    .await;
+
+    // the read cache is for nexuses with remote children only
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        assert!(nexus
+            .set_read_cache(Some("malloc:///rc0?size_mb=8"))
+            .await
+            .is_err());
+        assert_eq!(nexus.read_cache_uri(), None);
+    })
+    .await;
+
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,90 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut},
+    core::{partition::DATA_PARTITION_OFFSET, MayastorCliArgs, UntypedBdev},
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "checksum_nexus";
+
+#[tokio::test]
+async fn nexus_checksum_repair() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &[
+                "malloc:///m0?size_mb=64".to_string(),
+                "malloc:///m1?size_mb=64".to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus
+            .set_checksums(Some("malloc:///cs0?size_mb=1"))
+            .await
+            .unwrap();
+        assert_eq!(
+            nexus.checksums_uri().as_deref(),
+            Some("malloc:///cs0?size_mb=1")
+        );
+    })
+    .await;
+
+    // write through the nexus so that the checksums are recorded, then
+    // corrupt the first block on one of the children behind its back
+    ms.spawn(async {
+        let hdl = UntypedBdev::open_by_name(NEXUS_NAME, true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        buf.fill(0xa5);
+        hdl.write_at(0, &buf).await.unwrap();
+
+        let child = UntypedBdev::open_by_name("m0", true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut garbage = child.dma_malloc(512).unwrap();
+        garbage.fill(0xff);
+        child
+            .write_at(DATA_PARTITION_OFFSET, &garbage)
+            .await
+            .unwrap();
+    })
+    .await;
+
+    // every read returns the good copy, whichever child serves it
+    ms.spawn(async {
+        let hdl = UntypedBdev::open_by_name(NEXUS_NAME, false)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        for _ in 0 .. 4 {
+            hdl.read_at(0, &mut buf).await.unwrap();
+            assert!(buf.as_slice().iter().all(|b| *b == 0xa5));
+        }
+
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        let stats = nexus.checksum_stats().unwrap();
+        assert_eq!(stats.unrecoverable, 0);
+        assert_eq!(stats.mismatches, stats.repaired);
+    })
+    .await;
+
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus.set_checksums(None).await.unwrap();
+        assert!(nexus.checksum_stats().is_none());
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
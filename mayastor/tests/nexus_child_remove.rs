@@ -0,0 +1,123 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{
+        nexus_create,
+        nexus_lookup_mut,
+        NexusStatus,
+        TraceOp,
+        TraceOpts,
+    },
+    core::{BdevHandle, MayastorCliArgs, UntypedBdev},
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "remove_nexus";
+static CHILD_0: &str = "malloc:///rm0?size_mb=64";
+static CHILD_1: &str = "malloc:///rm1?size_mb=64";
+
+#[tokio::test]
+async fn nexus_child_remove_during_io() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &[CHILD_0.to_string(), CHILD_1.to_string()],
+        )
+        .await
+        .unwrap();
+
+        let hdl = BdevHandle::open(NEXUS_NAME, true, false).unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        buf.fill(0x5a);
+
+        // remove a child while IO is going on, none of it may fail
+        let io = async {
+            for i in 0 .. 256u64 {
+                hdl.write_at(i * 4096, &buf).await.unwrap();
+                let mut r = hdl.dma_malloc(4096).unwrap();
+                hdl.read_at(i * 4096, &mut r).await.unwrap();
+                assert!(r.as_slice().iter().all(|b| *b == 0x5a));
+            }
+        };
+        let remove = async {
+            nexus_lookup_mut(NEXUS_NAME)
+                .unwrap()
+                .remove_child(CHILD_0)
+                .await
+                .unwrap();
+        };
+        futures::join!(io, remove);
+
+        // the removal completes once the channels are reconfigured, which
+        // releases the device of the child
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        assert!(UntypedBdev::lookup_by_name("rm0").is_none());
+        assert_eq!(nexus.children.len(), 1);
+        assert_eq!(nexus.status(), NexusStatus::Online);
+
+        // and the channels no longer read from the child
+        nexus
+            .start_trace(TraceOpts {
+                sample: 1,
+                capacity: 64,
+                file: None,
+            })
+            .await
+            .unwrap();
+        for i in 0 .. 16u64 {
+            let mut r = hdl.dma_malloc(4096).unwrap();
+            hdl.read_at(i * 4096, &mut r).await.unwrap();
+            assert!(r.as_slice().iter().all(|b| *b == 0x5a));
+        }
+        nexus.stop_trace().await.unwrap();
+        let records = nexus.trace_records(None);
+        assert_eq!(records.len(), 16);
+        assert!(records.iter().all(|r| {
+            r.op == TraceOp::Read && r.ok && r.child.as_deref() == Some("rm1")
+        }));
+
+        drop(hdl);
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn nexus_child_remove_normalized_uri() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            "remove_nexus_uri",
+            32 * 1024 * 1024,
+            None,
+            &[
+                "malloc:///rmu0?size_mb=64".to_string(),
+                "malloc:///rmu1?size_mb=64".to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+
+        // a child is found however its URI is written, as long as it
+        // normalizes to the URI the child was added with
+        let nexus = nexus_lookup_mut("remove_nexus_uri").unwrap();
+        assert_eq!(
+            nexus.child_uri("MALLOC:///rmu0/?size_mb=64"),
+            "malloc:///rmu0?size_mb=64"
+        );
+        nexus
+            .remove_child("MALLOC:///rmu0/?size_mb=64")
+            .await
+            .unwrap();
+        assert_eq!(nexus.children.len(), 1);
+        assert!(UntypedBdev::lookup_by_name("rmu0").is_none());
+
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
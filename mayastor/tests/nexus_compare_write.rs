@@ -0,0 +1,128 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut},
+    core::{partition::DATA_PARTITION_OFFSET, MayastorCliArgs, UntypedBdev},
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "caw_nexus";
+
+/// fill the first block of a bdev with the pattern
+async fn write_block(name: &str, offset: u64, pattern: u8) {
+    let hdl = UntypedBdev::open_by_name(name, true)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = hdl.dma_malloc(512).unwrap();
+    buf.fill(pattern);
+    hdl.write_at(offset, &buf).await.unwrap();
+}
+
+/// returns true if the first block of a bdev holds the pattern
+async fn block_holds(name: &str, offset: u64, pattern: u8) -> bool {
+    let hdl = UntypedBdev::open_by_name(name, false)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = hdl.dma_malloc(512).unwrap();
+    hdl.read_at(offset, &mut buf).await.unwrap();
+    buf.as_slice().iter().all(|b| *b == pattern)
+}
+
+/// compare the first block of the nexus with one pattern and write another
+async fn compare_and_write(compare: u8, write: u8) -> bool {
+    let hdl = UntypedBdev::open_by_name(NEXUS_NAME, true)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut cmp = hdl.dma_malloc(512).unwrap();
+    cmp.fill(compare);
+    let mut buf = hdl.dma_malloc(512).unwrap();
+    buf.fill(write);
+    hdl.compare_and_write_at(0, &cmp, &buf).await.is_ok()
+}
+
+#[tokio::test]
+async fn nexus_compare_and_write() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &[
+                "malloc:///c0?size_mb=64".to_string(),
+                "malloc:///c1?size_mb=64".to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+        write_block(NEXUS_NAME, 0, 1).await;
+    })
+    .await;
+
+    // the write takes place on all children when the data matches, and not
+    // at all when it does not
+    ms.spawn(async {
+        assert!(compare_and_write(1, 2).await);
+        assert!(block_holds("c0", DATA_PARTITION_OFFSET, 2).await);
+        assert!(block_holds("c1", DATA_PARTITION_OFFSET, 2).await);
+
+        assert!(!compare_and_write(1, 3).await);
+        assert!(block_holds(NEXUS_NAME, 0, 2).await);
+    })
+    .await;
+
+    // children that diverged are reconciled with the first one
+    ms.spawn(async {
+        write_block("c1", DATA_PARTITION_OFFSET, 9).await;
+        assert!(compare_and_write(2, 4).await);
+        assert!(block_holds("c0", DATA_PARTITION_OFFSET, 4).await);
+        assert!(block_holds("c1", DATA_PARTITION_OFFSET, 4).await);
+
+        write_block("c1", DATA_PARTITION_OFFSET, 9).await;
+        assert!(!compare_and_write(9, 5).await);
+        assert!(block_holds("c1", DATA_PARTITION_OFFSET, 4).await);
+    })
+    .await;
+
+    // a child being rebuilt gets the write too, whether the rebuild copied
+    // the blocks already or not
+    ms.spawn(async {
+        let mut nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus
+            .as_mut()
+            .add_child("malloc:///c2?size_mb=64", true)
+            .await
+            .unwrap();
+        let _ = nexus
+            .as_mut()
+            .start_rebuild("malloc:///c2?size_mb=64")
+            .await
+            .unwrap();
+        nexus
+            .as_mut()
+            .pause_rebuild("malloc:///c2?size_mb=64")
+            .await
+            .unwrap();
+
+        assert!(compare_and_write(4, 6).await);
+        assert!(block_holds("c0", DATA_PARTITION_OFFSET, 6).await);
+        assert!(block_holds("c2", DATA_PARTITION_OFFSET, 6).await);
+
+        nexus
+            .as_mut()
+            .resume_rebuild("malloc:///c2?size_mb=64")
+            .await
+            .unwrap();
+    })
+    .await;
+
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
@@ -2,7 +2,12 @@ pub mod common;
 use common::compose::Builder;
 
 use composer::RpcHandle;
-use rpc::mayastor::{CreateNexusRequest, DestroyNexusRequest, Nexus};
+use rpc::mayastor::{
+    AddChildNexusRequest,
+    CreateNexusRequest,
+    DestroyNexusRequest,
+    Nexus,
+};
 
 const NEXUS_COUNT: usize = 10;
 
@@ -74,6 +79,76 @@ async fn nexus_create_multiple_then_destroy() {
     }
 }
 
+/// Create a nexus with more children than used to be possible, up to the
+/// default limit of children
+#[tokio::test]
+async fn nexus_create_wide() {
+    let compose = Builder::new()
+        .name("cargo-test")
+        .network("10.1.0.0/16")
+        .add_container("ms1")
+        .build()
+        .await
+        .unwrap();
+
+    let mut hdl = compose.grpc_handle("ms1").await.unwrap();
+    let children = |range: std::ops::Range<usize>| {
+        range
+            .map(|i| format!("malloc:///w{}?size_mb=4", i))
+            .collect::<Vec<_>>()
+    };
+
+    let err = hdl
+        .mayastor
+        .create_nexus(CreateNexusRequest {
+            uuid: uuid::Uuid::new_v4().to_string(),
+            size: 2 * 1024 * 1024,
+            children: children(0 .. 65),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+
+    let nexus = hdl
+        .mayastor
+        .create_nexus(CreateNexusRequest {
+            uuid: uuid::Uuid::new_v4().to_string(),
+            size: 2 * 1024 * 1024,
+            children: children(0 .. 63),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(nexus.children.len(), 63);
+
+    hdl.mayastor
+        .add_child_nexus(AddChildNexusRequest {
+            uuid: nexus.uuid.clone(),
+            uri: "malloc:///w63?size_mb=4".into(),
+            norebuild: true,
+        })
+        .await
+        .unwrap();
+
+    let err = hdl
+        .mayastor
+        .add_child_nexus(AddChildNexusRequest {
+            uuid: nexus.uuid.clone(),
+            uri: "malloc:///w64?size_mb=4".into(),
+            norebuild: true,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+
+    hdl.mayastor
+        .destroy_nexus(DestroyNexusRequest {
+            uuid: nexus.uuid,
+        })
+        .await
+        .unwrap();
+}
+
 async fn create_nexuses(handle: &mut RpcHandle, count: usize) -> Vec<Nexus> {
     let mut nexuses = vec![];
     for i in 0 .. count {
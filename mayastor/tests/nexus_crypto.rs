@@ -0,0 +1,136 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_create_encrypted, nexus_lookup_mut},
+    core::{
+        partition::DATA_PARTITION_OFFSET,
+        MayastorCliArgs,
+        Protocol,
+        UntypedBdev,
+    },
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "crypto_nexus";
+static KEY: &str =
+    "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+#[tokio::test]
+async fn nexus_encryption() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    // keys of the wrong length or with equal halves are refused before the
+    // nexus is created
+    ms.spawn(async {
+        for key in &["0123456789123456".to_string(), "00".repeat(32)] {
+            assert!(nexus_create_encrypted(
+                NEXUS_NAME,
+                32 * 1024 * 1024,
+                None,
+                &["malloc:///m0?size_mb=64".to_string()],
+                key,
+            )
+            .await
+            .is_err());
+            assert!(nexus_lookup_mut(NEXUS_NAME).is_none());
+        }
+    })
+    .await;
+
+    ms.spawn(async {
+        nexus_create_encrypted(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &["malloc:///m0?size_mb=64".to_string()],
+            KEY,
+        )
+        .await
+        .unwrap();
+
+        let mut nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        assert!(nexus.is_encrypted());
+
+        // creating it again only succeeds with the same key
+        assert!(nexus_create_encrypted(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &["malloc:///m0?size_mb=64".to_string()],
+            &KEY.replace("00", "ff"),
+        )
+        .await
+        .is_err());
+
+        // the 16 character key of PublishNexus is still accepted
+        nexus
+            .as_mut()
+            .share(Protocol::Nvmf, Some("0123456789123456".to_string()))
+            .await
+            .unwrap();
+
+        // features working on plaintext can not be combined with encryption
+        assert!(nexus
+            .set_checksums(Some("malloc:///cs0?size_mb=1"))
+            .await
+            .is_err());
+    })
+    .await;
+
+    // data reads back as written through the nexus, the child only holds
+    // ciphertext
+    ms.spawn(async {
+        let hdl = UntypedBdev::open_by_name(NEXUS_NAME, true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        buf.fill(0xa5);
+        hdl.write_at(0, &buf).await.unwrap();
+
+        buf.fill(0);
+        hdl.read_at(0, &mut buf).await.unwrap();
+        assert!(buf.as_slice().iter().all(|b| *b == 0xa5));
+
+        let child = UntypedBdev::open_by_name("m0", false)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut raw = child.dma_malloc(4096).unwrap();
+        child
+            .read_at(DATA_PARTITION_OFFSET, &mut raw)
+            .await
+            .unwrap();
+        assert!(raw.as_slice().iter().any(|b| *b != 0xa5));
+    })
+    .await;
+
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+
+    // a nexus created without a key is not encrypted by publishing it with
+    // one
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &["malloc:///m1?size_mb=64".to_string()],
+        )
+        .await
+        .unwrap();
+
+        let mut nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus
+            .as_mut()
+            .share(Protocol::Nvmf, Some("0123456789123456".to_string()))
+            .await
+            .unwrap();
+        assert!(!nexus.is_encrypted());
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
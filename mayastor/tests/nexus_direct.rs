@@ -0,0 +1,86 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{
+        nexus_create,
+        nexus_lookup_mut,
+        NexusOptionsUpdate,
+        TraceOp,
+        TraceOpts,
+    },
+    core::{MayastorCliArgs, UntypedBdev},
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "direct_nexus";
+
+/// write 4 blocks of the pattern to the nexus and read them back
+async fn write_read(pattern: u8) {
+    let hdl = UntypedBdev::open_by_name(NEXUS_NAME, true)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = hdl.dma_malloc(4096).unwrap();
+    for i in 0 .. 4 {
+        buf.fill(pattern);
+        hdl.write_at(i * 4096, &buf).await.unwrap();
+        buf.fill(0);
+        hdl.read_at(i * 4096, &mut buf).await.unwrap();
+        assert!(buf.as_slice().iter().all(|b| *b == pattern));
+    }
+}
+
+#[tokio::test]
+async fn nexus_direct_trace() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &["malloc:///d0?size_mb=64".to_string()],
+        )
+        .await
+        .unwrap();
+
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        let options = nexus
+            .update_options(NexusOptionsUpdate {
+                direct: Some(true),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(options.io.direct);
+        write_read(1).await;
+
+        // tracing turns the direct path off, so every IO is traced
+        nexus
+            .start_trace(TraceOpts {
+                sample: 1,
+                capacity: 16,
+                file: None,
+            })
+            .await
+            .unwrap();
+        write_read(2).await;
+
+        let stats = nexus.stop_trace().await.unwrap();
+        assert_eq!((stats.seen, stats.recorded, stats.lost), (8, 8, 0));
+        let records = nexus.trace_records(None);
+        assert_eq!(
+            records.iter().filter(|r| r.op == TraceOp::Write).count(),
+            4
+        );
+        assert!(records.iter().all(|r| r.ok && r.child.is_some()));
+
+        // and stopping the trace turns it on again
+        write_read(3).await;
+        assert_eq!(nexus.trace_stats().unwrap().seen, 8);
+        assert!(nexus.io_opts().direct);
+
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut, DrainOpts, Error},
+    core::{
+        fault_injection::{self, FaultAction, FaultDomain, FaultIo, FaultRule},
+        MayastorCliArgs,
+        Protocol,
+        Reactors,
+        Share,
+        UntypedBdev,
+    },
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "drain_nexus";
+
+#[tokio::test]
+async fn nexus_drain_on_unshare() {
+    let ms = MayastorTest::new(MayastorCliArgs {
+        enable_fault_injection: true,
+        ..Default::default()
+    });
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &["malloc:///dr0?size_mb=64".to_string()],
+        )
+        .await
+        .unwrap();
+
+        let mut nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus.as_mut().share(Protocol::Nvmf, None).await.unwrap();
+        assert_eq!(nexus.io_in_flight().await, 0);
+
+        // hold a write in flight for a while
+        fault_injection::add_rule(FaultRule {
+            id: "held".into(),
+            domain: FaultDomain::Child,
+            device: None,
+            io: FaultIo::Write,
+            min_size: 0,
+            percent: 100,
+            every: 1,
+            max_faults: Some(1),
+            action: FaultAction::Delay {
+                delay_ms: 1000,
+            },
+        })
+        .unwrap();
+        let hdl = UntypedBdev::open_by_name(NEXUS_NAME, true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        Reactors::current()
+            .spawn_local(async move {
+                let buf = hdl.dma_malloc(4096).unwrap();
+                hdl.write_at(0, &buf).await.unwrap();
+            })
+            .detach();
+    })
+    .await;
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    ms.spawn(async {
+        let mut nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        assert_eq!(nexus.io_in_flight().await, 1);
+
+        // the write does not complete in time, the nexus stays shared
+        let err = nexus
+            .as_mut()
+            .unshare_nexus_drained(DrainOpts {
+                timeout_ms: 50,
+                force: false,
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::DrainTimeout {
+                in_flight: 1,
+                ..
+            }
+        ));
+        assert_eq!(nexus.shared(), Some(Protocol::Nvmf));
+        assert!(nexus.get_share_uri().is_some());
+
+        // it does once it is waited for
+        nexus
+            .as_mut()
+            .unshare_nexus_drained(DrainOpts {
+                timeout_ms: 5000,
+                force: false,
+            })
+            .await
+            .unwrap();
+        assert_eq!(nexus.io_in_flight().await, 0);
+        assert!(nexus.get_share_uri().is_none());
+
+        fault_injection::remove_rule("held").unwrap();
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,79 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut, NexusCacheOpts},
+    core::{partition::DATA_PARTITION_OFFSET, MayastorCliArgs, UntypedBdev},
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "flush_nexus";
+
+/// write the pattern to the first blocks of the nexus and flush it
+async fn write_and_flush(pattern: u8) {
+    let hdl = UntypedBdev::open_by_name(NEXUS_NAME, true)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = hdl.dma_malloc(4096).unwrap();
+    buf.fill(pattern);
+    hdl.write_at(0, &buf).await.unwrap();
+    hdl.flush().await.unwrap();
+}
+
+/// check that the first blocks of a child hold the pattern
+async fn check_child(child: &str, pattern: u8) {
+    let hdl = UntypedBdev::open_by_name(child, false)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = hdl.dma_malloc(4096).unwrap();
+    hdl.read_at(DATA_PARTITION_OFFSET, &mut buf).await.unwrap();
+    assert!(buf.as_slice().iter().all(|b| *b == pattern));
+}
+
+#[tokio::test]
+async fn nexus_flush() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    // a flush is forwarded to all children
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &[
+                "malloc:///f0?size_mb=64".to_string(),
+                "malloc:///f1?size_mb=64".to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+        write_and_flush(1).await;
+    })
+    .await;
+
+    // with a write cache, the flush completes once the cached data has been
+    // written back to the children
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus
+            .set_write_cache(NexusCacheOpts {
+                size: 1024 * 1024,
+                writeback_ms: 60_000,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        write_and_flush(2).await;
+        check_child("f0", 2).await;
+        check_child("f1", 2).await;
+    })
+    .await;
+
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
@@ -570,6 +570,8 @@ async fn nexus_io_write_zeroes() {
                 name: POOL_NAME.to_string(),
                 disks: vec![BDEVNAME1.to_string()],
                 uuid: None,
+                labels: Default::default(),
+                cluster_size: None,
             })
             .await
             .unwrap();
@@ -0,0 +1,56 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut},
+    core::{BdevHandle, MayastorCliArgs},
+};
+pub mod common;
+
+#[tokio::test]
+async fn nexus_io_retry() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            "retry0",
+            32 * 1024 * 1024,
+            None,
+            &[
+                "malloc:///r0?size_mb=64".into(),
+                "malloc:///r1?size_mb=64".into(),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let hdl = BdevHandle::open("retry0", true, false).unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        buf.fill(0x55);
+
+        // replace a child while IO is going on, none of it may fail
+        let io = async {
+            for i in 0 .. 256u64 {
+                hdl.write_at(i * 4096, &buf).await.unwrap();
+                let mut r = hdl.dma_malloc(4096).unwrap();
+                hdl.read_at(i * 4096, &mut r).await.unwrap();
+            }
+        };
+        let reconfigure = async {
+            let mut nexus = nexus_lookup_mut("retry0").unwrap();
+            nexus
+                .as_mut()
+                .remove_child("malloc:///r0?size_mb=64")
+                .await
+                .unwrap();
+            nexus
+                .as_mut()
+                .add_child("malloc:///r2?size_mb=64", true)
+                .await
+                .unwrap();
+        };
+        futures::join!(io, reconfigure);
+
+        drop(hdl);
+        nexus_lookup_mut("retry0").unwrap().destroy().await.unwrap();
+    })
+    .await;
+}
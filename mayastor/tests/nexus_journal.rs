@@ -0,0 +1,70 @@
+use common::{bdev_holds, write_bdev, MayastorTest};
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut, Reason},
+    core::{partition::DATA_PARTITION_OFFSET, MayastorCliArgs},
+    rebuild::RebuildState,
+    subsys::{Config, NexusOpts},
+};
+use std::time::Duration;
+
+pub mod common;
+
+static NEXUS_NAME: &str = "journal_nexus";
+static CHILD_0: &str = "malloc:///j0?size_mb=64";
+static CHILD_1: &str = "malloc:///j1?size_mb=64";
+
+#[tokio::test]
+async fn nexus_journal_resync() {
+    Config::get_or_init(|| Config {
+        nexus_opts: NexusOpts {
+            write_journal: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &[CHILD_0.to_string(), CHILD_1.to_string()],
+        )
+        .await
+        .unwrap();
+        write_bdev(NEXUS_NAME, 0, 1).await;
+    })
+    .await;
+
+    // let the journal reach the children
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus.fault_child(CHILD_1, Reason::OutOfSync).await.unwrap();
+        write_bdev(NEXUS_NAME, 8 * 1024 * 1024, 2).await;
+
+        // blocks the nexus did not write since are left alone by the resync
+        write_bdev("j1", DATA_PARTITION_OFFSET + 16 * 1024 * 1024, 9).await;
+
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        let done = nexus.start_rebuild(CHILD_1).await.unwrap();
+        assert_eq!(done.await.unwrap(), RebuildState::Completed);
+
+        assert!(bdev_holds("j1", DATA_PARTITION_OFFSET, 1).await);
+        assert!(
+            bdev_holds("j1", DATA_PARTITION_OFFSET + 8 * 1024 * 1024, 2).await
+        );
+        assert!(
+            bdev_holds("j1", DATA_PARTITION_OFFSET + 16 * 1024 * 1024, 9).await
+        );
+    })
+    .await;
+
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
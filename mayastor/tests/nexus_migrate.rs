@@ -0,0 +1,115 @@
+use once_cell::sync::OnceCell;
+
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{
+        nexus_lookup_mut,
+        nexus_migrate_prepare,
+        MigrationRole,
+        NexusNvmeParams,
+        NvmeAnaState,
+    },
+    core::MayastorCliArgs,
+    subsys::NvmfSubsystem,
+};
+pub mod common;
+
+static MAYASTOR: OnceCell<MayastorTest> = OnceCell::new();
+
+static NEXUS_NAME: &str = "migrate_nexus";
+static NEXUS_UUID: &str = "cdc2a7db-3ac3-403a-af80-7fadc1581c47";
+static FAILED_NAME: &str = "migrate_failed";
+static FAILED_UUID: &str = "cdc2a7db-3ac3-403a-af80-7fadc1581c48";
+
+fn get_ms() -> &'static MayastorTest<'static> {
+    MAYASTOR.get_or_init(|| MayastorTest::new(MayastorCliArgs::default()))
+}
+
+fn children(prefix: &str) -> Vec<String> {
+    vec![
+        format!("malloc:///{}0?size_mb=64", prefix),
+        format!("malloc:///{}1?size_mb=64", prefix),
+    ]
+}
+
+async fn ana_state() -> NvmeAnaState {
+    nexus_lookup_mut(NEXUS_NAME)
+        .unwrap()
+        .get_ana_state()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn nexus_migrate() {
+    get_ms()
+        .spawn(async {
+            // the destination is shared with an inaccessible path
+            let uri = nexus_migrate_prepare(
+                NEXUS_NAME,
+                NEXUS_UUID,
+                32 * 1024 * 1024,
+                NexusNvmeParams::default(),
+                &children(NEXUS_NAME),
+            )
+            .await
+            .unwrap();
+            assert!(uri.starts_with("nvmf://"));
+            assert_eq!(ana_state().await, NvmeAnaState::InaccessibleState);
+
+            nexus_lookup_mut(NEXUS_NAME)
+                .unwrap()
+                .migrate_switchover(MigrationRole::Destination)
+                .await
+                .unwrap();
+            assert_eq!(ana_state().await, NvmeAnaState::OptimizedState);
+
+            // a nexus with an accessible path is not finalized
+            assert!(nexus_lookup_mut(NEXUS_NAME)
+                .unwrap()
+                .migrate_finalize()
+                .await
+                .is_err());
+            assert!(nexus_lookup_mut(NEXUS_NAME).is_some());
+
+            nexus_lookup_mut(NEXUS_NAME)
+                .unwrap()
+                .migrate_switchover(MigrationRole::Source)
+                .await
+                .unwrap();
+            assert_eq!(ana_state().await, NvmeAnaState::InaccessibleState);
+
+            nexus_lookup_mut(NEXUS_NAME)
+                .unwrap()
+                .migrate_finalize()
+                .await
+                .unwrap();
+            assert!(nexus_lookup_mut(NEXUS_NAME).is_none());
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn nexus_migrate_prepare_failure() {
+    get_ms()
+        .spawn(async {
+            // a subsystem of the same name makes the share fail
+            let subsystem = NvmfSubsystem::new(FAILED_NAME).unwrap();
+
+            assert!(nexus_migrate_prepare(
+                FAILED_NAME,
+                FAILED_UUID,
+                32 * 1024 * 1024,
+                NexusNvmeParams::default(),
+                &children(FAILED_NAME),
+            )
+            .await
+            .is_err());
+
+            // the nexus created for the migration is destroyed again
+            assert!(nexus_lookup_mut(FAILED_NAME).is_none());
+
+            subsystem.destroy();
+        })
+        .await;
+}
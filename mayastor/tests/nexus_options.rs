@@ -0,0 +1,109 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{
+        nexus_create,
+        nexus_lookup_mut,
+        NexusOptionsUpdate,
+        NexusQos,
+        ReadPolicy,
+        SlowChildOpts,
+    },
+    core::{MayastorCliArgs, UntypedBdev},
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "options_nexus";
+
+#[tokio::test]
+async fn nexus_update_options() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &[
+                "malloc:///opt0?size_mb=64".to_string(),
+                "malloc:///opt1?size_mb=64".to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        let defaults = nexus.options();
+        assert_eq!(defaults.io.read_policy, ReadPolicy::RoundRobin);
+        assert!(defaults.slow_child_detection.is_none());
+
+        let options = nexus
+            .update_options(NexusOptionsUpdate {
+                read_policy: Some(ReadPolicy::Preferred),
+                io_retry_timeout_ms: Some(100),
+                io_backpressure_depth: Some(16),
+                max_io_outstanding: Some(1),
+                read_ahead_kb: Some(64),
+                qos: Some(NexusQos {
+                    iops: 1000,
+                    ..Default::default()
+                }),
+                slow_child_detection: Some(SlowChildOpts::default()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(options.io.read_policy, ReadPolicy::Preferred);
+        assert_eq!(options.io.io_retry_timeout_ms, 100);
+        assert_eq!(options.io.io_backpressure_depth, 16);
+        assert_eq!(nexus.read_ahead_stats().read_ahead_kb, 64);
+        assert_eq!(
+            options.io.io_retry_queue_depth,
+            defaults.io.io_retry_queue_depth
+        );
+        assert_eq!(options.qos.iops, 1000);
+        assert!(options.slow_child_detection.is_some());
+
+        // the nexus keeps serving IO with the new options
+        let hdl = UntypedBdev::open_by_name(NEXUS_NAME, true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        hdl.write_at(0, &buf).await.unwrap();
+        hdl.read_at(0, &mut buf).await.unwrap();
+        drop(hdl);
+
+        // the IOs admitted under the limit freed their slots
+        let limit = nexus.io_limit_stats();
+        assert_eq!(limit.max_io_outstanding, 1);
+        assert_eq!(limit.outstanding, 0);
+        assert_eq!(limit.waiting, 0);
+
+        // options not given are kept
+        let options = nexus
+            .update_options(NexusOptionsUpdate {
+                no_slow_child_detection: true,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(options.io.read_policy, ReadPolicy::Preferred);
+        assert_eq!(options.qos.iops, 1000);
+        assert!(options.slow_child_detection.is_none());
+
+        assert!(nexus
+            .update_options(NexusOptionsUpdate {
+                qos: Some(NexusQos {
+                    group: Some("missing".into()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .await
+            .is_err());
+
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
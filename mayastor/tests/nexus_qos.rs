@@ -0,0 +1,116 @@
+use std::time::Instant;
+
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{
+        nexus_create,
+        nexus_lookup_mut,
+        qos_group_set,
+        NexusQos,
+        QosGroup,
+    },
+    core::{MayastorCliArgs, UntypedBdev},
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "qos_nexus";
+
+/// read the first block of the nexus the given number of times
+async fn read_blocks(count: u32) {
+    let hdl = UntypedBdev::open_by_name(NEXUS_NAME, false)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = hdl.dma_malloc(512).unwrap();
+
+    for _ in 0 .. count {
+        hdl.read_at(0, &mut buf).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn nexus_qos_limits() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &["malloc:///m0?size_mb=64".to_string()],
+        )
+        .await
+        .unwrap();
+    })
+    .await;
+
+    // 20 reads at 100 IOPS with a 100ms burst take at least 100ms
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus
+            .set_qos(NexusQos {
+                iops: 100,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let start = Instant::now();
+        read_blocks(20).await;
+        assert!(start.elapsed().as_millis() >= 80);
+    })
+    .await;
+
+    // joining a group that does not exist fails
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        assert!(nexus
+            .set_qos(NexusQos {
+                group: Some("qos_group".to_string()),
+                ..Default::default()
+            })
+            .await
+            .is_err());
+    })
+    .await;
+
+    // a member of a group is limited by the group capacity, and removing
+    // the limits lets the IO through right away
+    ms.spawn(async {
+        qos_group_set(
+            "qos_group",
+            QosGroup {
+                iops: 100,
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus
+            .set_qos(NexusQos {
+                group: Some("qos_group".to_string()),
+                weight: 2,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let start = Instant::now();
+        read_blocks(40).await;
+        assert!(start.elapsed().as_millis() >= 80);
+
+        nexus.set_qos(NexusQos::default()).await.unwrap();
+        let start = Instant::now();
+        read_blocks(40).await;
+        assert!(start.elapsed().as_millis() < 80);
+    })
+    .await;
+
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,84 @@
+use common::{bdev_holds, write_bdev, MayastorTest};
+use mayastor::{
+    bdev::nexus::{
+        nexus_create,
+        nexus_lookup_mut,
+        ReplicationOpts,
+        ReplicationState,
+    },
+    core::{partition::DATA_PARTITION_OFFSET, MayastorCliArgs, UntypedBdev},
+};
+use std::time::Duration;
+
+pub mod common;
+
+static NEXUS_NAME: &str = "replicated_nexus";
+static TARGET: &str = "malloc:///r_target?size_mb=64";
+
+#[tokio::test]
+async fn nexus_replication() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &["malloc:///r0?size_mb=64".to_string()],
+        )
+        .await
+        .unwrap();
+        write_bdev(NEXUS_NAME, 0, 1).await;
+
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        assert!(nexus.replication_status().is_none());
+        nexus
+            .set_replication(Some(ReplicationOpts {
+                target: TARGET.to_string(),
+                interval_secs: 1,
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+    })
+    .await;
+
+    // the first cycle ships the whole nexus
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    ms.spawn(async {
+        let status = nexus_lookup_mut(NEXUS_NAME)
+            .unwrap()
+            .replication_status()
+            .unwrap();
+        assert_eq!(status.state, ReplicationState::Idle);
+        assert_eq!(status.bytes_shipped, 32 * 1024 * 1024);
+        assert!(bdev_holds("r_target", DATA_PARTITION_OFFSET, 1).await);
+
+        write_bdev(NEXUS_NAME, 8 * 1024 * 1024, 2).await;
+    })
+    .await;
+
+    // the next cycle ships only the segment written to
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        let status = nexus.replication_status().unwrap();
+        assert_eq!(status.segments_pending, 0);
+        assert_eq!(
+            status.bytes_shipped,
+            32 * 1024 * 1024 + status.segment_size
+        );
+        assert!(
+            bdev_holds("r_target", DATA_PARTITION_OFFSET + 8 * 1024 * 1024, 2)
+                .await
+        );
+
+        nexus.set_replication(None).await.unwrap();
+        assert!(nexus.replication_status().is_none());
+        assert!(UntypedBdev::lookup_by_name("r_target").is_none());
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
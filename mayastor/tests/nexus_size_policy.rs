@@ -0,0 +1,54 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{
+        nexus_create_with_size_policy,
+        nexus_lookup_mut,
+        ChildSizePolicy,
+    },
+    core::MayastorCliArgs,
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "size_policy_nexus";
+
+#[tokio::test]
+async fn nexus_child_size_policy() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        let size = 64 * 1024 * 1024;
+        let children = [
+            "malloc:///size0?size_mb=64".to_string(),
+            "malloc:///size1?size_mb=60".to_string(),
+        ];
+
+        // a child smaller than the nexus fails the creation
+        assert!(nexus_create_with_size_policy(
+            NEXUS_NAME,
+            size,
+            None,
+            &children,
+            ChildSizePolicy::Strict,
+        )
+        .await
+        .is_err());
+
+        // or the nexus takes the size of the smallest child
+        nexus_create_with_size_policy(
+            NEXUS_NAME,
+            size,
+            None,
+            &children,
+            ChildSizePolicy::UseMinimum,
+        )
+        .await
+        .unwrap();
+
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        let nexus_size = nexus.size_in_bytes();
+        assert!(nexus_size > 0 && nexus_size <= 60 * 1024 * 1024);
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
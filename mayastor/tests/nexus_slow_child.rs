@@ -0,0 +1,109 @@
+use std::time::{Duration, Instant};
+
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut, SlowChildOpts},
+    core::{
+        fault_injection::{self, FaultAction, FaultDomain, FaultIo, FaultRule},
+        MayastorCliArgs,
+        UntypedBdev,
+    },
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "slow_child_nexus";
+
+#[tokio::test]
+async fn nexus_slow_child() {
+    let ms = MayastorTest::new(MayastorCliArgs {
+        enable_fault_injection: true,
+        ..Default::default()
+    });
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &[
+                "malloc:///sc0?size_mb=64".to_string(),
+                "malloc:///sc1?size_mb=64".to_string(),
+                "malloc:///sc2?size_mb=64".to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        assert!(nexus.slow_child_status().is_none());
+        assert!(nexus
+            .set_slow_child_detection(Some(SlowChildOpts {
+                factor: 1,
+                ..Default::default()
+            }))
+            .await
+            .is_err());
+        nexus
+            .set_slow_child_detection(Some(SlowChildOpts {
+                windows: 2,
+                window_ms: 500,
+                eject_reads: true,
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+
+        fault_injection::add_rule(FaultRule {
+            id: "sick".into(),
+            domain: FaultDomain::Child,
+            device: Some("sc2".into()),
+            io: FaultIo::Any,
+            min_size: 0,
+            percent: 100,
+            every: 1,
+            max_faults: None,
+            action: FaultAction::Delay {
+                delay_ms: 5,
+            },
+        })
+        .unwrap();
+
+        let hdl = UntypedBdev::open_by_name(NEXUS_NAME, false)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+
+        // read until the sick child is no longer read from
+        let start = Instant::now();
+        let slow = loop {
+            hdl.read_at(0, &mut buf).await.unwrap();
+            let status = nexus.slow_child_status().unwrap();
+            if let Some(c) = status.children.iter().find(|c| c.ejected) {
+                break c.clone();
+            }
+            assert!(start.elapsed() < Duration::from_secs(20));
+        };
+        assert_eq!(slow.child, "sc2");
+        assert!(slow.slow);
+        let status = nexus.slow_child_status().unwrap();
+        assert_eq!(status.events.len(), 1);
+        assert!(status.events[0].slow);
+        assert!(status.events[0].p99_us > 4 * status.events[0].siblings_p99_us);
+
+        // the reads are served by the healthy children only
+        let start = Instant::now();
+        for _ in 0 .. 30 {
+            hdl.read_at(0, &mut buf).await.unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_millis(5 * 30));
+
+        fault_injection::remove_rule("sick").unwrap();
+        nexus.set_slow_child_detection(None).await.unwrap();
+        assert!(nexus.slow_child_status().is_none());
+
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
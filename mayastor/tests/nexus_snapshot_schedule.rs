@@ -0,0 +1,51 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut, SnapshotSchedule},
+    core::MayastorCliArgs,
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "schedule_nexus";
+
+#[tokio::test]
+async fn nexus_snapshot_schedule() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &["malloc:///s0?size_mb=64".to_string()],
+        )
+        .await
+        .unwrap();
+
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        assert_eq!(nexus.snapshot_schedule(), None);
+
+        let schedule = SnapshotSchedule {
+            cron: "0 */6 * * 1-5".to_string(),
+            keep_last: 4,
+            keep_daily: 7,
+            ..Default::default()
+        };
+        nexus.set_snapshot_schedule(Some(schedule.clone())).unwrap();
+        assert_eq!(nexus.snapshot_schedule(), Some(schedule.clone()));
+
+        // an invalid schedule leaves the current one in place
+        nexus
+            .set_snapshot_schedule(Some(SnapshotSchedule {
+                cron: "0 24 * * *".to_string(),
+                ..Default::default()
+            }))
+            .unwrap_err();
+        assert_eq!(nexus.snapshot_schedule(), Some(schedule));
+
+        nexus.set_snapshot_schedule(None).unwrap();
+        assert_eq!(nexus.snapshot_schedule(), None);
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,81 @@
+#[macro_use]
+extern crate assert_matches;
+
+use mayastor::{
+    bdev::nexus::{
+        nexus_create,
+        nexus_lookup_mut,
+        ChildState,
+        NexusStatus,
+        Reason,
+    },
+    core::MayastorCliArgs,
+};
+
+static NEXUS_NAME: &str = "standby_nexus";
+
+static FILE_SIZE: u64 = 64 * 1024 * 1024; // 64MiB
+
+static DISKNAME1: &str = "/tmp/standby1.img";
+static BDEVNAME1: &str = "aio:///tmp/standby1.img?blk_size=512";
+
+static DISKNAME2: &str = "/tmp/standby2.img";
+static BDEVNAME2: &str = "aio:///tmp/standby2.img?blk_size=512";
+
+pub mod common;
+use common::MayastorTest;
+
+#[tokio::test]
+async fn nexus_standby_child() {
+    let disks = [DISKNAME1.into(), DISKNAME2.into()];
+    common::delete_file(&disks);
+    common::truncate_file(DISKNAME1, FILE_SIZE);
+    common::truncate_file(DISKNAME2, FILE_SIZE);
+
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+    ms.spawn(async {
+        let children = vec![BDEVNAME1.to_string()];
+        nexus_create(NEXUS_NAME, 512 * 131_072, None, &children)
+            .await
+            .expect("Failed to create nexus");
+    })
+    .await;
+
+    // a standby child is open but neither degrades the nexus nor is rebuilt
+    ms.spawn(async {
+        let mut nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        let status = nexus
+            .as_mut()
+            .add_child_standby(BDEVNAME2)
+            .await
+            .expect("Failed to add standby child");
+        assert_eq!(status, NexusStatus::Online);
+        assert_eq!(nexus.children.len(), 2);
+        assert_matches!(
+            nexus.children[1].state(),
+            ChildState::Faulted(Reason::Standby)
+        );
+
+        // only a standby child can be activated
+        assert!(nexus.as_mut().activate_child(BDEVNAME1).await.is_err());
+    })
+    .await;
+
+    // activation starts the rebuild of the child
+    ms.spawn(async {
+        let mut nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus
+            .as_mut()
+            .activate_child(BDEVNAME2)
+            .await
+            .expect("Failed to activate standby child");
+        assert_ne!(
+            nexus.children[1].state(),
+            ChildState::Faulted(Reason::Standby)
+        );
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+
+    common::delete_file(&disks);
+}
@@ -0,0 +1,66 @@
+use common::{bdev_holds, write_bdev, MayastorTest};
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut, TieringOpts},
+    core::{partition::DATA_PARTITION_OFFSET, MayastorCliArgs},
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "tiered_nexus";
+static FAST: &str = "malloc:///t_fast?size_mb=64";
+static CAPACITY: &str = "malloc:///t_capacity?size_mb=64";
+
+#[tokio::test]
+async fn nexus_tier() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &[FAST.to_string(), CAPACITY.to_string()],
+        )
+        .await
+        .unwrap();
+
+        let mut nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        assert!(nexus.tiering_status().is_none());
+        // the fast child must be a child of the nexus
+        assert!(nexus
+            .as_mut()
+            .set_tiering(Some(TieringOpts {
+                fast: "malloc:///nope?size_mb=64".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .is_err());
+        nexus
+            .as_mut()
+            .set_tiering(Some(TieringOpts {
+                fast: FAST.to_string(),
+                destage_interval_ms: 60_000,
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+
+        // writes only go to the fast child and are read back from it
+        write_bdev(NEXUS_NAME, 0, 7).await;
+        assert!(bdev_holds("t_fast", DATA_PARTITION_OFFSET, 7).await);
+        assert!(!bdev_holds("t_capacity", DATA_PARTITION_OFFSET, 7).await);
+        for _ in 0 .. 4 {
+            assert!(bdev_holds(NEXUS_NAME, 0, 7).await);
+        }
+        let status = nexus.tiering_status().unwrap();
+        assert_eq!(status.extents_on_fast, 1);
+
+        // turning tiering off destages what is left
+        nexus.as_mut().set_tiering(None).await.unwrap();
+        assert!(nexus.tiering_status().is_none());
+        assert!(bdev_holds("t_capacity", DATA_PARTITION_OFFSET, 7).await);
+
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
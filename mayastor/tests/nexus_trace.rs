@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut, TraceOp, TraceOpts},
+    core::{
+        perf_test::{PerfError, PerfReplay},
+        MayastorCliArgs,
+        UntypedBdev,
+    },
+    nexus_uri::{bdev_create, bdev_destroy},
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "trace_nexus";
+static TRACE_FILE: &str = "/tmp/nexus_trace.json";
+
+#[tokio::test]
+async fn nexus_trace_replay() {
+    common::delete_file(&[TRACE_FILE.into()]);
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &[
+                "malloc:///t0?size_mb=64".to_string(),
+                "malloc:///t1?size_mb=64".to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus
+            .start_trace(TraceOpts {
+                sample: 1,
+                capacity: 16,
+                file: Some(TRACE_FILE.into()),
+            })
+            .await
+            .unwrap();
+
+        let hdl = UntypedBdev::open_by_name(NEXUS_NAME, true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        buf.fill(0xa5);
+        for i in 0 .. 8 {
+            hdl.write_at(i * 4096, &buf).await.unwrap();
+        }
+        for i in 0 .. 8 {
+            hdl.read_at(i * 4096, &mut buf).await.unwrap();
+        }
+        drop(hdl);
+
+        let stats = nexus.stop_trace().await.unwrap();
+        assert!(!stats.active);
+        assert_eq!((stats.seen, stats.recorded, stats.lost), (16, 16, 0));
+
+        let records = nexus.trace_records(None);
+        assert_eq!(records.len(), 16);
+        assert!(records[.. 8].iter().all(|r| r.op == TraceOp::Write));
+        assert!(records[8 ..].iter().all(|r| {
+            r.op == TraceOp::Read && r.ok && r.len == 4096 && r.child.is_some()
+        }));
+        assert_eq!(records[9].offset, 4096);
+
+        // IOs are no longer traced
+        let hdl = UntypedBdev::open_by_name(NEXUS_NAME, false)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        hdl.read_at(0, &mut buf).await.unwrap();
+        assert_eq!(nexus.trace_stats().unwrap().seen, 16);
+    })
+    .await;
+
+    // the writer thread closes the file once the trace is dropped
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    ms.spawn(async {
+        bdev_create("malloc:///replay0?size_mb=64").await.unwrap();
+
+        let replay = PerfReplay {
+            bdev: "replay0".into(),
+            file: TRACE_FILE.into(),
+            queue_depth: 4,
+            timed: true,
+            allow_writes: false,
+        };
+        assert!(matches!(
+            replay.clone().run().await,
+            Err(PerfError::WriteNotAllowed { .. })
+        ));
+
+        let result = PerfReplay {
+            allow_writes: true,
+            ..replay
+        }
+        .run()
+        .await
+        .unwrap();
+        assert_eq!((result.ios, result.errors, result.skipped), (16, 0, 0));
+
+        bdev_destroy("malloc:///replay0?size_mb=64").await.unwrap();
+        nexus_lookup_mut(NEXUS_NAME)
+            .unwrap()
+            .destroy()
+            .await
+            .unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,76 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{
+        nexus_create_v2,
+        nexus_lookup_any_mut,
+        nexus_lookup_uuid_mut,
+        NexusNvmeParams,
+        Reason,
+    },
+    core::{MayastorCliArgs, UntypedBdev},
+    nexus_uri::{bdev_create, bdev_destroy},
+};
+pub mod common;
+
+static NEXUS_UUID: &str = "4cd1bdf4-3a38-4f84-9b9a-5f0bf0d1b1a2";
+static CHILD0_UUID: &str = "b6a5e31c-6a0f-4bde-9a89-f8b2a3f29a01";
+static CHILD1_UUID: &str = "b6a5e31c-6a0f-4bde-9a89-f8b2a3f29a02";
+
+#[tokio::test]
+async fn nexus_uuid_addressing() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        let children = [
+            format!("malloc:///u0?size_mb=64&uuid={}", CHILD0_UUID),
+            format!("malloc:///u1?size_mb=64&uuid={}", CHILD1_UUID),
+        ];
+        nexus_create_v2(
+            "volume0",
+            32 * 1024 * 1024,
+            NEXUS_UUID,
+            NexusNvmeParams::default(),
+            &children,
+            None,
+        )
+        .await
+        .unwrap();
+
+        // the nexus can be found by its name and by its uuid
+        let nexus = nexus_lookup_uuid_mut(NEXUS_UUID).unwrap();
+        assert_eq!(nexus.children.len(), 2);
+        assert!(nexus_lookup_any_mut("volume0").is_some());
+        assert!(nexus_lookup_any_mut(NEXUS_UUID).is_some());
+
+        // and so can its children, and the bdevs of the replicas
+        let mut nexus = nexus_lookup_any_mut(NEXUS_UUID).unwrap();
+        assert_eq!(nexus.child_uri(CHILD1_UUID), children[1]);
+        assert_eq!(nexus.child_uri(&children[1]), children[1]);
+        assert!(UntypedBdev::lookup_by_name_or_uuid(CHILD0_UUID).is_some());
+        assert!(UntypedBdev::lookup_by_name_or_uuid("u0").is_some());
+
+        // a uuid refers to the bdev it is the uuid of, not to a bdev named
+        // after it
+        let named = format!("malloc:///{}?size_mb=8", CHILD0_UUID);
+        bdev_create(&named).await.unwrap();
+        assert_eq!(
+            UntypedBdev::lookup_by_name_or_uuid(CHILD0_UUID)
+                .unwrap()
+                .name(),
+            "u0"
+        );
+        bdev_destroy(&named).await.unwrap();
+
+        nexus
+            .as_mut()
+            .fault_child(CHILD1_UUID, Reason::Rpc)
+            .await
+            .unwrap();
+        nexus.as_mut().remove_child(CHILD1_UUID).await.unwrap();
+        assert_eq!(nexus.children.len(), 1);
+
+        nexus.destroy().await.unwrap();
+        assert!(nexus_lookup_uuid_mut(NEXUS_UUID).is_none());
+    })
+    .await;
+}
@@ -0,0 +1,84 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::{
+        device_lookup,
+        nexus::{nexus_create, nexus_lookup_mut, nexus_validate_create},
+    },
+    core::MayastorCliArgs,
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "validate_nexus";
+
+#[tokio::test]
+async fn nexus_validate_create_dry_run() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        let children = [
+            "malloc:///val0?size_mb=64".to_string(),
+            "malloc:///val1?size_mb=64".to_string(),
+        ];
+
+        let v = nexus_validate_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &children,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(v.valid, "{:?}", v.problems);
+        assert_eq!(v.block_size, 512);
+        assert!(v.children.iter().all(|c| c.reachable));
+        assert!(v.children.iter().all(|c| c.protection_info.is_none()));
+
+        // nothing is left behind
+        assert!(device_lookup("val0").is_none());
+        assert!(device_lookup("val1").is_none());
+
+        // too large for the children
+        let v = nexus_validate_create(
+            NEXUS_NAME,
+            128 * 1024 * 1024,
+            None,
+            &children,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(!v.valid);
+
+        // the children of an existing nexus are in use
+        nexus_create(NEXUS_NAME, 32 * 1024 * 1024, None, &children)
+            .await
+            .unwrap();
+        assert!(nexus_lookup_mut(NEXUS_NAME)
+            .unwrap()
+            .protection_info()
+            .is_none());
+        let v = nexus_validate_create(
+            "validate_other",
+            32 * 1024 * 1024,
+            None,
+            &children,
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(!v.valid);
+        assert!(v
+            .children
+            .iter()
+            .all(|c| c.in_use_by.as_deref() == Some(NEXUS_NAME)));
+
+        nexus_lookup_mut(NEXUS_NAME)
+            .unwrap()
+            .destroy()
+            .await
+            .unwrap();
+    })
+    .await;
+}
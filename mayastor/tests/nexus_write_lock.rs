@@ -0,0 +1,79 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut},
+    core::{partition::DATA_PARTITION_OFFSET, MayastorCliArgs, UntypedBdev},
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "write_lock_nexus";
+
+/// read the first blocks of a child
+async fn read_child(child: &str) -> Vec<u8> {
+    let hdl = UntypedBdev::open_by_name(child, false)
+        .unwrap()
+        .into_handle()
+        .unwrap();
+    let mut buf = hdl.dma_malloc(8192).unwrap();
+    hdl.read_at(DATA_PARTITION_OFFSET, &mut buf).await.unwrap();
+    buf.as_slice().to_vec()
+}
+
+#[tokio::test]
+async fn nexus_serialize_writes() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &[
+                "malloc:///w0?size_mb=64".to_string(),
+                "malloc:///w1?size_mb=64".to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        assert!(!nexus.serializes_writes());
+        nexus.set_serialize_writes(true);
+        assert!(nexus.serializes_writes());
+    })
+    .await;
+
+    // overlapping writes in flight at the same time all complete, and leave
+    // the children with the same data
+    ms.spawn(async {
+        let hdl = UntypedBdev::open_by_name(NEXUS_NAME, true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+
+        let bufs = (1 ..= 16u8)
+            .map(|i| {
+                let mut buf = hdl.dma_malloc(4096).unwrap();
+                buf.fill(i);
+                buf
+            })
+            .collect::<Vec<_>>();
+        let writes = bufs
+            .iter()
+            .enumerate()
+            .map(|(i, buf)| hdl.write_at((i as u64 % 3) * 2048, buf));
+        for r in futures::future::join_all(writes).await {
+            r.unwrap();
+        }
+
+        assert_eq!(read_child("w0").await, read_child("w1").await);
+    })
+    .await;
+
+    ms.spawn(async {
+        let nexus = nexus_lookup_mut(NEXUS_NAME).unwrap();
+        nexus.set_serialize_writes(false);
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,41 @@
+use std::pin::Pin;
+
+use common::MayastorTest;
+use mayastor::{
+    bdev::{device_create, device_destroy, device_lookup},
+    core::{MayastorCliArgs, Share, UntypedBdev},
+    subsys::{add_share_listener, share_listeners},
+};
+pub mod common;
+
+#[tokio::test]
+async fn nvmf_child_by_hostname() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        device_create("malloc:///named_target?size_mb=8")
+            .await
+            .unwrap();
+        let mut bdev = UntypedBdev::lookup_by_name("named_target").unwrap();
+        Pin::new(&mut bdev).share_nvmf(None).await.unwrap();
+        // localhost resolves to either loopback address
+        add_share_listener("named_target", "::1").await.unwrap();
+        let nqn = share_listeners("named_target").unwrap().nqn;
+
+        // the child is named after the hostname, not the address
+        let uri = format!("nvmf://localhost:8420/{}", nqn);
+        let name = device_create(&uri).await.unwrap();
+        assert!(name.starts_with("localhost:8420/"));
+        let device = device_lookup(&name).unwrap();
+        assert_eq!(device.size_in_bytes(), 8 * 1024 * 1024);
+        device_destroy(&uri).await.unwrap();
+
+        // hostnames that do not resolve fail to connect
+        assert!(device_create("nvmf://no-such-host.invalid:8420/nqn")
+            .await
+            .is_err());
+
+        Pin::new(&mut bdev).unshare().await.unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,41 @@
+use std::pin::Pin;
+
+use common::MayastorTest;
+use mayastor::{
+    bdev::{device_create, device_destroy, device_lookup},
+    core::{MayastorCliArgs, Share, UntypedBdev},
+    subsys::{add_share_listener, share_listeners},
+};
+pub mod common;
+
+#[tokio::test]
+async fn nvmf_ipv6_share_and_child() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        device_create("malloc:///v6disk?size_mb=8").await.unwrap();
+        let mut bdev = UntypedBdev::lookup_by_name("v6disk").unwrap();
+        Pin::new(&mut bdev).share_nvmf(None).await.unwrap();
+
+        // the share listens on the IPv6 loopback address too
+        add_share_listener("v6disk", "::1").await.unwrap();
+        let listeners = share_listeners("v6disk").unwrap();
+        assert!(listeners.addresses.contains(&"::1".to_string()));
+        let uri = listeners
+            .uris
+            .iter()
+            .find(|u| u.starts_with("nvmf://[::1]:"))
+            .cloned()
+            .unwrap();
+        assert_eq!(uri, format!("nvmf://[::1]:8420/{}", listeners.nqn));
+
+        // and can be connected to through a bracketed child URI
+        let name = device_create(&uri).await.unwrap();
+        let device = device_lookup(&name).unwrap();
+        assert_eq!(device.size_in_bytes(), 8 * 1024 * 1024);
+        device_destroy(&uri).await.unwrap();
+
+        Pin::new(&mut bdev).unshare().await.unwrap();
+    })
+    .await;
+}
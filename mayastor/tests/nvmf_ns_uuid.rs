@@ -0,0 +1,41 @@
+use std::pin::Pin;
+
+use common::MayastorTest;
+use mayastor::{
+    bdev::{device_create, device_destroy, device_lookup},
+    core::{BlockDevice, MayastorCliArgs, Share, UntypedBdev},
+};
+pub mod common;
+
+static REPLICA_UUID: &str = "9d2b7e3c-54f6-4b1e-8c0a-0f6e2d3a4b5c";
+static OTHER_UUID: &str = "1f0c2a6e-7d3b-4e59-a1c8-6b4d9e2f0a17";
+
+#[tokio::test]
+async fn nvmf_namespace_uuid() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        device_create(&format!(
+            "malloc:///ns_uuid?size_mb=8&uuid={}",
+            REPLICA_UUID
+        ))
+        .await
+        .unwrap();
+        let mut bdev = UntypedBdev::lookup_by_name("ns_uuid").unwrap();
+        let share = Pin::new(&mut bdev).share_nvmf(None).await.unwrap();
+
+        // the namespace of another replica is not connected to
+        let wrong = format!("{}?uuid={}", share, OTHER_UUID);
+        assert!(device_create(&wrong).await.is_err());
+
+        // the namespace carries the UUID of the replica
+        let right = format!("{}?uuid={}", share, REPLICA_UUID);
+        let name = device_create(&right).await.unwrap();
+        let remote = device_lookup(&name).unwrap();
+        assert_eq!(remote.uuid().to_string(), REPLICA_UUID);
+        device_destroy(&right).await.unwrap();
+
+        Pin::new(&mut bdev).unshare().await.unwrap();
+    })
+    .await;
+}
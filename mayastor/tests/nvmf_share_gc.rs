@@ -0,0 +1,35 @@
+use common::MayastorTest;
+use mayastor::{
+    core::MayastorCliArgs,
+    subsys::{collect_orphaned_shares, NvmfSubsystem},
+};
+pub mod common;
+
+#[tokio::test]
+async fn orphaned_share_gc() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        // a subsystem without a namespace, as left behind by a bdev that
+        // went away while shared
+        let ss = NvmfSubsystem::new("orphan").unwrap();
+        ss.start().await.unwrap();
+        let nqn = NvmfSubsystem::nqn_lookup("orphan").unwrap().get_nqn();
+
+        // reporting leaves it in place
+        let orphans = collect_orphaned_shares(false).await;
+        assert!(orphans.iter().any(|o| o.nqn == nqn && !o.removed));
+        assert!(NvmfSubsystem::nqn_lookup("orphan").is_some());
+
+        let orphans = collect_orphaned_shares(true).await;
+        assert!(orphans.iter().any(|o| o.nqn == nqn && o.removed));
+    })
+    .await;
+
+    // the subsystem is destroyed asynchronously
+    ms.spawn(async {
+        assert!(NvmfSubsystem::nqn_lookup("orphan").is_none());
+        assert!(collect_orphaned_shares(false).await.is_empty());
+    })
+    .await;
+}
@@ -0,0 +1,56 @@
+use std::{pin::Pin, time::Duration};
+
+use common::MayastorTest;
+use mayastor::{
+    core::{MayastorCliArgs, Protocol, Share, UntypedBdev},
+    subsys::{idle_shares, set_share_idle_timeout, unshare_idle_shares},
+};
+pub mod common;
+
+#[tokio::test]
+async fn idle_share_unshared() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        for name in &["idle0", "idle1"] {
+            mayastor::bdev::device_create(&format!(
+                "malloc:///{}?size_mb=8",
+                name
+            ))
+            .await
+            .unwrap();
+            let mut bdev = UntypedBdev::lookup_by_name(name).unwrap();
+            Pin::new(&mut bdev).share_nvmf(None).await.unwrap();
+        }
+        set_share_idle_timeout("idle0", Some(1));
+
+        // both shares are idle but have not been for long enough
+        unshare_idle_shares().await;
+        let shares = idle_shares();
+        assert_eq!(shares.idle.len(), 2);
+        assert!(shares.unshared.is_empty());
+    })
+    .await;
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    ms.spawn(async {
+        unshare_idle_shares().await;
+        let shares = idle_shares();
+        assert_eq!(shares.unshared.len(), 1);
+        assert_eq!(shares.unshared[0].bdev, "idle0");
+        assert!(shares.unshared[0].error.is_none());
+    })
+    .await;
+
+    // the subsystem is destroyed asynchronously
+    ms.spawn(async {
+        // the share without a timeout stays
+        let bdev = UntypedBdev::lookup_by_name("idle0").unwrap();
+        assert_eq!(bdev.shared(), Some(Protocol::Off));
+        let bdev = UntypedBdev::lookup_by_name("idle1").unwrap();
+        assert_eq!(bdev.shared(), Some(Protocol::Nvmf));
+        assert_eq!(idle_shares().idle.len(), 1);
+    })
+    .await;
+}
@@ -0,0 +1,59 @@
+use std::pin::Pin;
+
+use common::MayastorTest;
+use mayastor::{
+    core::{MayastorCliArgs, Share, UntypedBdev},
+    subsys::{
+        add_share_listener,
+        remove_share_listener,
+        share_listeners,
+        NvmfError,
+    },
+};
+pub mod common;
+
+#[tokio::test]
+async fn share_listeners_rehoming() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        mayastor::bdev::device_create("malloc:///listened?size_mb=8")
+            .await
+            .unwrap();
+        assert!(matches!(
+            share_listeners("listened"),
+            Err(NvmfError::NotShared { .. })
+        ));
+
+        let mut bdev = UntypedBdev::lookup_by_name("listened").unwrap();
+        Pin::new(&mut bdev).share_nvmf(None).await.unwrap();
+        let listeners = share_listeners("listened").unwrap();
+        assert_eq!(listeners.addresses, vec!["127.0.0.1".to_string()]);
+
+        assert!(add_share_listener("listened", "not an address")
+            .await
+            .is_err());
+
+        // move the share to another address
+        let listeners =
+            add_share_listener("listened", "127.0.0.2").await.unwrap();
+        assert_eq!(listeners.addresses.len(), 2);
+        assert_eq!(listeners.uris.len(), 2);
+        let listeners = remove_share_listener("listened", "127.0.0.1")
+            .await
+            .unwrap();
+        assert_eq!(listeners.addresses, vec!["127.0.0.2".to_string()]);
+        assert!(bdev.share_uri().unwrap().starts_with("nvmf://127.0.0.2:"));
+
+        // the last listener stays
+        assert!(remove_share_listener("listened", "127.0.0.2")
+            .await
+            .is_err());
+        assert!(remove_share_listener("listened", "127.0.0.1")
+            .await
+            .is_err());
+
+        Pin::new(&mut bdev).unshare().await.unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,66 @@
+use std::pin::Pin;
+
+use common::MayastorTest;
+use mayastor::{
+    core::{MayastorCliArgs, Share, UntypedBdev},
+    subsys::{set_share_naming, share_identity, NvmfSubsystem, ShareNaming},
+};
+pub mod common;
+
+#[tokio::test]
+async fn share_naming() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        mayastor::bdev::device_create("malloc:///named?size_mb=8")
+            .await
+            .unwrap();
+
+        let default = share_identity("named");
+        assert_eq!(default.nqn, "nqn.2019-05.io.openebs:named");
+
+        // names that do not fit are refused
+        assert!(set_share_naming(
+            "named",
+            ShareNaming {
+                serial_number: Some("a serial number that is too long".into()),
+                ..Default::default()
+            }
+        )
+        .is_err());
+        assert!(set_share_naming(
+            "named",
+            ShareNaming {
+                nqn_prefix: Some("com.example".into()),
+                ..Default::default()
+            }
+        )
+        .is_err());
+
+        set_share_naming(
+            "named",
+            ShareNaming {
+                nqn_prefix: Some("nqn.2014-08.com.example".into()),
+                serial_number: Some("SN0001".into()),
+                model_number: None,
+            },
+        )
+        .unwrap();
+        let identity = share_identity("named");
+        assert_eq!(identity.nqn, "nqn.2014-08.com.example:named");
+        assert_eq!(identity.serial_number, "SN0001");
+        assert_eq!(identity.model_number, default.model_number);
+
+        let mut bdev = UntypedBdev::lookup_by_name("named").unwrap();
+        let uri = Pin::new(&mut bdev).share_nvmf(None).await.unwrap();
+        assert!(uri.ends_with("nqn.2014-08.com.example:named"));
+        let ss = NvmfSubsystem::nqn_lookup("named").unwrap();
+        assert_eq!(ss.get_nqn(), identity.nqn);
+
+        // the naming of a shared bdev can not change
+        assert!(set_share_naming("named", ShareNaming::default()).is_err());
+
+        Pin::new(&mut bdev).unshare().await.unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,119 @@
+use std::pin::Pin;
+
+use common::MayastorTest;
+use mayastor::{
+    bdev::{device_create, device_destroy},
+    core::{MayastorCliArgs, Share, UntypedBdev},
+    lvs::Lvs,
+    pool::PoolArgs,
+    subsys::{
+        set_connect_token,
+        set_share_token,
+        share_has_token,
+        share_locked,
+    },
+};
+pub mod common;
+
+static DISK: &str = "/tmp/share_token.img";
+
+#[tokio::test]
+async fn nvmf_share_token() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        device_create("malloc:///tokened?size_mb=8").await.unwrap();
+
+        // tokens are checked for size
+        assert!(set_share_token("tokened", Some(String::new()))
+            .await
+            .is_err());
+        set_share_token("tokened", Some("tenant-a".into()))
+            .await
+            .unwrap();
+        assert!(share_has_token("tokened"));
+
+        let mut bdev = UntypedBdev::lookup_by_name("tokened").unwrap();
+        let share = Pin::new(&mut bdev).share_nvmf(None).await.unwrap();
+        let nqn = share.rsplit('/').next().unwrap().to_string();
+
+        // hosts that do not know the token are not let in
+        assert!(device_create(&share).await.is_err());
+        set_connect_token(&nqn, Some("tenant-b".into())).unwrap();
+        assert!(device_create(&share).await.is_err());
+
+        set_connect_token(&nqn, Some("tenant-a".into())).unwrap();
+        device_create(&share).await.unwrap();
+        device_destroy(&share).await.unwrap();
+
+        // the token is not taken in the uri, where it would be listed and
+        // persisted, nor repeated in the error
+        let err = device_create(&format!("{}?token=tenant-a", share))
+            .await
+            .unwrap_err();
+        assert!(!err.to_string().contains("tenant-a"));
+
+        // without a token any host is let in again
+        set_connect_token(&nqn, None).unwrap();
+        set_share_token("tokened", None).await.unwrap();
+        assert!(!share_has_token("tokened"));
+        device_create(&share).await.unwrap();
+        device_destroy(&share).await.unwrap();
+
+        Pin::new(&mut bdev).unshare().await.unwrap();
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn nvmf_share_token_import() {
+    common::delete_file(&[DISK.into()]);
+    common::truncate_file(DISK, 64 * 1024);
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        let args = PoolArgs {
+            name: "tokpool".into(),
+            disks: vec![format!("aio://{}", DISK)],
+            uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
+        };
+        let pool = Lvs::create_or_import(args.clone()).await.unwrap();
+        let mut lvol = pool
+            .create_lvol("tokvol", 8 * 1024 * 1024, None, true)
+            .await
+            .unwrap();
+        set_share_token("tokvol", Some("tenant-a".into()))
+            .await
+            .unwrap();
+        let share = Pin::new(&mut lvol).share_nvmf(None).await.unwrap();
+        let nqn = share.rsplit('/').next().unwrap().to_string();
+        set_connect_token(&nqn, Some("tenant-a".into())).unwrap();
+
+        // the token is not kept over an import of the pool, the share comes
+        // back locked rather than open to any host
+        pool.export().await.unwrap();
+        set_share_token("tokvol", None).await.unwrap();
+        set_connect_token(&nqn, None).unwrap();
+        let pool = Lvs::create_or_import(args).await.unwrap();
+        assert!(share_locked("tokvol"));
+        assert!(!share_has_token("tokvol"));
+        assert!(device_create(&share).await.is_err());
+        set_connect_token(&nqn, Some("tenant-a".into())).unwrap();
+        assert!(device_create(&share).await.is_err());
+
+        // until the token is set again
+        set_share_token("tokvol", Some("tenant-a".into()))
+            .await
+            .unwrap();
+        assert!(!share_locked("tokvol"));
+        device_create(&share).await.unwrap();
+        device_destroy(&share).await.unwrap();
+
+        pool.destroy().await.unwrap();
+    })
+    .await;
+
+    common::delete_file(&[DISK.into()]);
+}
@@ -0,0 +1,90 @@
+use common::MayastorTest;
+use mayastor::{
+    core::{
+        perf_test::{Pattern, PerfError, PerfTest},
+        MayastorCliArgs,
+    },
+    nexus_uri::{bdev_create, bdev_destroy},
+};
+
+pub mod common;
+
+static BDEV: &str = "malloc:///perf0?size_mb=64";
+
+fn test(pattern: Pattern) -> PerfTest {
+    PerfTest {
+        bdev: "perf0".into(),
+        pattern,
+        block_size: 4096,
+        queue_depth: 16,
+        runtime_secs: 1,
+        read_percent: 70,
+        allow_writes: false,
+    }
+}
+
+#[tokio::test]
+async fn perf_test_malloc() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        bdev_create(BDEV).await.unwrap();
+
+        let result = test(Pattern::RandRead).run().await.unwrap();
+        assert_eq!(result.writes, 0);
+        assert_eq!(result.errors, 0);
+        assert!(result.reads > 0 && result.iops > 0);
+        assert!(result.latency_us.min <= result.latency_us.p50);
+        assert!(result.latency_us.p50 <= result.latency_us.p99);
+        assert!(result.latency_us.p99 <= result.latency_us.max);
+
+        // writes destroy data and must be allowed
+        assert!(matches!(
+            test(Pattern::RandRw).run().await,
+            Err(PerfError::WriteNotAllowed { .. })
+        ));
+
+        let result = PerfTest {
+            allow_writes: true,
+            ..test(Pattern::RandRw)
+        }
+        .run()
+        .await
+        .unwrap();
+        assert!(result.reads > 0 && result.writes > 0);
+        assert_eq!(result.errors, 0);
+
+        let result = PerfTest {
+            allow_writes: true,
+            ..test(Pattern::Write)
+        }
+        .run()
+        .await
+        .unwrap();
+        assert!(result.writes > 0 && result.reads == 0);
+
+        // IOs must be whole blocks
+        assert!(matches!(
+            PerfTest {
+                block_size: 1000,
+                ..test(Pattern::Read)
+            }
+            .run()
+            .await,
+            Err(PerfError::InvalidTest { .. })
+        ));
+
+        assert!(matches!(
+            PerfTest {
+                bdev: "nope".into(),
+                ..test(Pattern::Read)
+            }
+            .run()
+            .await,
+            Err(PerfError::OpenBdev { .. })
+        ));
+
+        bdev_destroy(BDEV).await.unwrap();
+    })
+    .await;
+}
@@ -290,6 +290,110 @@ async fn persist_io_failure() {
     assert!(!child.healthy);
 }
 
+/// This test checks that a nexus whose persistent entry has been claimed by a
+/// newer nexus stops accepting writes and leaves the entry alone.
+#[tokio::test]
+async fn persist_stale_epoch() {
+    // the nexus checks its epoch before writes with a lease of 1s
+    let test = start_infrastructure_with(
+        "persist_stale_epoch",
+        Some("nexus_opts:\n  epoch_lease_ms: 1000\n"),
+    )
+    .await;
+    let ms1 = &mut test.grpc_handle("ms1").await.unwrap();
+    let ms2 = &mut test.grpc_handle("ms2").await.unwrap();
+    let ms3 = &mut test.grpc_handle("ms3").await.unwrap();
+
+    let child1 = create_and_share_bdevs(ms2, CHILD1_UUID).await;
+    let child2 = create_and_share_bdevs(ms3, CHILD2_UUID).await;
+
+    let nexus_uuid = "8272e9d3-3738-4e33-b8c3-769d8eed5771";
+    create_nexus(ms1, nexus_uuid, vec![child1.clone(), child2.clone()]).await;
+    let nexus_uri = publish_nexus(ms1, nexus_uuid).await;
+
+    let mut etcd = Client::connect([ETCD_ENDPOINT], None).await.unwrap();
+    let response = etcd.get(nexus_uuid, None).await.expect("No entry found");
+    let value = response.kvs().first().unwrap().value();
+    let mut nexus_info: NexusInfo = serde_json::from_slice(value).unwrap();
+    assert_eq!(nexus_info.epoch, 0);
+
+    // Writes are accepted while the epoch of the nexus is current.
+    let target = libnvme_rs::NvmeTarget::try_from(nexus_uri).unwrap();
+    target.connect().unwrap();
+    let devices = target.block_devices(2).unwrap();
+    let device = devices[0].to_string();
+    let dev = device.clone();
+    tokio::spawn(async move { fio_run_verify(&dev).unwrap() })
+        .await
+        .unwrap();
+
+    // A newer nexus claims the entry, the lease of the nexus runs out.
+    nexus_info.epoch = 1;
+    etcd.put(nexus_uuid, serde_json::to_vec(&nexus_info).unwrap(), None)
+        .await
+        .expect("Failed to put entry");
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let failed = tokio::spawn(async move { fio_run_verify(&device).is_err() })
+        .await
+        .unwrap();
+    assert!(failed);
+    target.disconnect().unwrap();
+
+    assert_eq!(
+        get_nexus_state(ms1, nexus_uuid).await.unwrap(),
+        NexusState::NexusFaulted as i32
+    );
+
+    // The stale nexus leaves the entry of the newer nexus alone.
+    destroy_nexus(ms1, nexus_uuid).await;
+    let response = etcd.get(nexus_uuid, None).await.expect("No entry found");
+    let value = response.kvs().first().unwrap().value();
+    let nexus_info: NexusInfo = serde_json::from_slice(value).unwrap();
+    assert_eq!(nexus_info.epoch, 1);
+    assert!(!nexus_info.clean_shutdown);
+}
+
+/// This test checks that a nexus created again claims a newer epoch and
+/// accepts writes once the lease of the previous nexus has run out.
+#[tokio::test]
+async fn persist_epoch_claimed() {
+    let test = start_infrastructure("persist_epoch_claimed").await;
+    let ms1 = &mut test.grpc_handle("ms1").await.unwrap();
+    let ms2 = &mut test.grpc_handle("ms2").await.unwrap();
+    let ms3 = &mut test.grpc_handle("ms3").await.unwrap();
+
+    let child1 = create_and_share_bdevs(ms2, CHILD1_UUID).await;
+    let child2 = create_and_share_bdevs(ms3, CHILD2_UUID).await;
+
+    let nexus_uuid = "8272e9d3-3738-4e33-b8c3-769d8eed5771";
+    let children = vec![child1, child2];
+    create_nexus(ms1, nexus_uuid, children.clone()).await;
+    destroy_nexus(ms1, nexus_uuid).await;
+    create_nexus(ms1, nexus_uuid, children).await;
+
+    let mut etcd = Client::connect([ETCD_ENDPOINT], None).await.unwrap();
+    let response = etcd.get(nexus_uuid, None).await.expect("No entry found");
+    let value = response.kvs().first().unwrap().value();
+    let nexus_info: NexusInfo = serde_json::from_slice(value).unwrap();
+    assert_eq!(nexus_info.epoch, 1);
+
+    let nexus_uri = publish_nexus(ms1, nexus_uuid).await;
+    let target = libnvme_rs::NvmeTarget::try_from(nexus_uri).unwrap();
+    target.connect().unwrap();
+    let devices = target.block_devices(2).unwrap();
+    let device = devices[0].to_string();
+    tokio::spawn(async move { fio_run_verify(&device).unwrap() })
+        .await
+        .unwrap();
+    target.disconnect().unwrap();
+
+    assert_eq!(
+        get_nexus_state(ms1, nexus_uuid).await.unwrap(),
+        NexusState::NexusOnline as i32
+    );
+}
+
 /// This test checks the behaviour when a connection to the persistent store is
 /// faulty.
 #[tokio::test]
@@ -334,7 +438,24 @@ async fn persistent_store_connection() {
 
 /// Start the containers for the tests.
 async fn start_infrastructure(test_name: &str) -> ComposeTest {
+    start_infrastructure_with(test_name, None).await
+}
+
+/// Start the containers for the tests, ms1 loading the given configuration.
+async fn start_infrastructure_with(
+    test_name: &str,
+    config: Option<&str>,
+) -> ComposeTest {
     let etcd_endpoint = format!("http://etcd.{}:2379", test_name);
+    let mut ms1 =
+        Binary::from_dbg("mayastor").with_args(vec!["-p", &etcd_endpoint]);
+    if let Some(config) = config {
+        let file = format!("{}.yaml", test_name);
+        std::fs::write(format!("/tmp/{}", file), config).unwrap();
+        ms1 = ms1
+            .with_bind("/tmp", "/host/tmp")
+            .with_args(vec!["-y".to_string(), format!("/host/tmp/{}", file)]);
+    }
     let test = Builder::new()
         .name(test_name)
         .add_container_spec(
@@ -352,10 +473,7 @@ async fn start_infrastructure(test_name: &str) -> ComposeTest {
             .with_portmap("2379", "2379")
             .with_portmap("2380", "2380"),
         )
-        .add_container_bin(
-            "ms1",
-            Binary::from_dbg("mayastor").with_args(vec!["-p", &etcd_endpoint]),
-        )
+        .add_container_bin("ms1", ms1)
         .add_container_bin(
             "ms2",
             Binary::from_dbg("mayastor").with_args(vec!["-p", &etcd_endpoint]),
@@ -413,6 +531,15 @@ async fn remove_child_nexus(hdl: &mut RpcHandle, uuid: &str, child: &str) {
         .expect("Failed to remove child from nexus.");
 }
 
+async fn destroy_nexus(hdl: &mut RpcHandle, uuid: &str) {
+    hdl.mayastor
+        .destroy_nexus(DestroyNexusRequest {
+            uuid: uuid.to_string(),
+        })
+        .await
+        .expect("Failed to destroy nexus.");
+}
+
 /// Publish a nexus with the given UUID over NVMf.
 async fn publish_nexus(hdl: &mut RpcHandle, uuid: &str) -> String {
     hdl.mayastor
@@ -431,7 +558,7 @@ async fn publish_nexus(hdl: &mut RpcHandle, uuid: &str) -> String {
 async fn create_and_share_bdevs(hdl: &mut RpcHandle, uuid: &str) -> String {
     hdl.bdev
         .create(BdevUri {
-            uri: "malloc:///disk0?size_mb=64".into(),
+            uri: format!("malloc:///disk0?size_mb=64&uuid={}", uuid),
         })
         .await
         .unwrap();
@@ -0,0 +1,63 @@
+use std::process::Command;
+
+use common::{bdev_holds, write_bdev, MayastorTest};
+use mayastor::{
+    core::{MayastorCliArgs, UntypedBdev},
+    nexus_uri::{bdev_create, bdev_destroy},
+};
+
+pub mod common;
+
+static POOL_FILE: &str = "/tmp/pmem_pool.img";
+static BDEV_NAME: &str = "pmem_bdev";
+
+/// create the pmemblk pool, returns false if pmempool is not installed
+fn create_pool() -> bool {
+    let _ = std::fs::remove_file(POOL_FILE);
+    match Command::new("pmempool")
+        .args(&["create", "-s", "64M", "blk", "4096", POOL_FILE])
+        .output()
+    {
+        Ok(output) => {
+            assert!(output.status.success(), "failed to create pmem pool");
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[tokio::test]
+async fn pmem_bdev() {
+    if !create_pool() {
+        println!("pmempool is not installed, skipping");
+        return;
+    }
+
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+    let uri = format!("pmem://{}?name={}", POOL_FILE, BDEV_NAME);
+
+    ms.spawn(async move {
+        // the bdev is named after the parameter, not the file of the pool
+        assert_eq!(bdev_create(&uri).await.unwrap(), BDEV_NAME);
+        let bdev = UntypedBdev::lookup_by_name(BDEV_NAME).unwrap();
+        assert_eq!(bdev.block_len(), 4096);
+        assert!(UntypedBdev::lookup_by_name(POOL_FILE).is_none());
+
+        // a second bdev of the same name is refused
+        assert!(bdev_create(&uri).await.is_err());
+
+        write_bdev(BDEV_NAME, 0, 7).await;
+        assert!(bdev_holds(BDEV_NAME, 0, 7).await);
+
+        bdev_destroy(&uri).await.unwrap();
+        assert!(UntypedBdev::lookup_by_name(BDEV_NAME).is_none());
+
+        // the pool is left in place and keeps the data
+        bdev_create(&uri).await.unwrap();
+        assert!(bdev_holds(BDEV_NAME, 0, 7).await);
+        bdev_destroy(&uri).await.unwrap();
+    })
+    .await;
+
+    std::fs::remove_file(POOL_FILE).unwrap();
+}
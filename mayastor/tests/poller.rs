@@ -82,5 +82,34 @@ fn poller() {
     Reactors::master().poll_times(64);
     drop(poller);
 
+    // named pollers are listed with their runs, and paused by name
+    let poller = poller::Builder::new()
+        .with_name("test_registry")
+        .with_interval(0)
+        .with_poll_fn(|| 1)
+        .build();
+
+    Reactors::master().poll_times(64);
+    let status = poller::pollers()
+        .into_iter()
+        .find(|p| p.id == poller.id())
+        .unwrap();
+    assert_eq!(status.name, "test_registry");
+    assert_eq!(status.runs, 64);
+    assert_eq!(status.busy_runs, 64);
+
+    assert_eq!(poller::set_paused(None, Some("test_registry"), true), 1);
+    Reactors::master().poll_times(64);
+    let status = poller::pollers()
+        .into_iter()
+        .find(|p| p.id == poller.id())
+        .unwrap();
+    assert!(status.paused);
+    assert_eq!(status.runs, 64);
+
+    let id = poller.id();
+    poller.stop();
+    assert!(poller::pollers().iter().all(|p| p.id != id));
+
     mayastor_env_stop(0);
 }
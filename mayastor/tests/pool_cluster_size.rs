@@ -0,0 +1,64 @@
+use common::MayastorTest;
+use mayastor::{core::MayastorCliArgs, lvs::Lvs, pool::PoolArgs};
+
+pub mod common;
+
+fn args(name: &str, disk: &str, cluster_size: Option<u32>) -> PoolArgs {
+    PoolArgs {
+        name: name.into(),
+        disks: vec![disk.into()],
+        uuid: None,
+        labels: Default::default(),
+        cluster_size,
+    }
+}
+
+#[tokio::test]
+async fn pool_cluster_size() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        let pool = Lvs::create_or_import(args(
+            "cpool1",
+            "malloc:///cdisk1?size_mb=64",
+            Some(1024 * 1024),
+        ))
+        .await
+        .unwrap();
+        let format = pool.format();
+        assert_eq!(format.cluster_size, 1024 * 1024);
+        assert_eq!(format.io_unit_size, 512);
+        pool.destroy().await.unwrap();
+
+        // the default cluster size of the lvol store
+        let pool = Lvs::create_or_import(args(
+            "cpool2",
+            "malloc:///cdisk2?size_mb=64",
+            None,
+        ))
+        .await
+        .unwrap();
+        assert_eq!(pool.cluster_size(), 4 * 1024 * 1024);
+        pool.destroy().await.unwrap();
+
+        // not a power of two
+        assert!(Lvs::create_or_import(args(
+            "cpool3",
+            "malloc:///cdisk3?size_mb=64",
+            Some(3 * 4096),
+        ))
+        .await
+        .is_err());
+
+        // not enough clusters on the disk
+        assert!(Lvs::create_or_import(args(
+            "cpool4",
+            "malloc:///cdisk4?size_mb=64",
+            Some(8 * 1024 * 1024),
+        ))
+        .await
+        .is_err());
+        assert_eq!(Lvs::iter().count(), 0);
+    })
+    .await;
+}
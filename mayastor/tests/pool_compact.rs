@@ -0,0 +1,101 @@
+use std::{convert::TryFrom, time::Duration};
+
+use common::MayastorTest;
+use mayastor::{
+    core::{MayastorCliArgs, UntypedBdev},
+    lvs::{pool_compactions, CompactState, Lvol, Lvs},
+    pool::PoolArgs,
+};
+
+pub mod common;
+
+static DISKNAME: &str = "/tmp/compact.img";
+
+#[tokio::test]
+async fn pool_compact() {
+    common::delete_file(&[DISKNAME.into()]);
+    common::truncate_file(DISKNAME, 128 * 1024);
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    let uuid = ms
+        .spawn(async {
+            let pool = Lvs::create_or_import(PoolArgs {
+                name: "cpool".into(),
+                disks: vec![format!("aio://{}", DISKNAME)],
+                uuid: None,
+                labels: Default::default(),
+                cluster_size: None,
+            })
+            .await
+            .unwrap();
+
+            // interleave the writes to two thin replicas
+            let a = pool.create_lvol("thin-a", 32 * 1024 * 1024, None, true);
+            let a = a.await.unwrap();
+            let b = pool.create_lvol("thin-b", 32 * 1024 * 1024, None, true);
+            let b = b.await.unwrap();
+            for (lvol, pattern) in [(&a, 0xa5), (&b, 0x5a)] {
+                let hdl = UntypedBdev::open_by_name(&lvol.name(), true)
+                    .unwrap()
+                    .into_handle()
+                    .unwrap();
+                let mut buf = hdl.dma_malloc(4096).unwrap();
+                buf.fill(pattern);
+                for mb in [20, 4, 12] {
+                    hdl.write_at(mb * 1024 * 1024, &buf).await.unwrap();
+                }
+            }
+
+            let compaction = pool.compact(0).unwrap();
+            assert_eq!(compaction.state, CompactState::Running);
+            // only one compaction of a pool at a time
+            assert!(pool.compact(0).is_err());
+            a.uuid()
+        })
+        .await;
+
+    for _ in 0 .. 100 {
+        if ms
+            .spawn(async {
+                pool_compactions()
+                    .iter()
+                    .all(|c| c.state == CompactState::Running)
+            })
+            .await
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        } else {
+            break;
+        }
+    }
+
+    ms.spawn(async move {
+        let compaction = pool_compactions()
+            .into_iter()
+            .find(|c| c.pool == "cpool")
+            .unwrap();
+        assert_eq!(compaction.state, CompactState::Completed);
+        assert_eq!(compaction.compacted.len(), 2);
+        assert!(compaction.skipped.is_empty());
+
+        // the data and the identity of the replica are kept
+        let lvol =
+            Lvol::try_from(UntypedBdev::lookup_by_name("thin-a").unwrap())
+                .unwrap();
+        assert_eq!(lvol.uuid(), uuid);
+        let hdl = UntypedBdev::open_by_name("thin-a", false)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        for mb in [20, 4, 12] {
+            hdl.read_at(mb * 1024 * 1024, &mut buf).await.unwrap();
+            assert!(buf.as_slice().iter().all(|b| *b == 0xa5));
+        }
+        drop(hdl);
+        assert!(UntypedBdev::lookup_by_name("thin-a.compacted").is_none());
+
+        Lvs::lookup("cpool").unwrap().destroy().await.unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use common::MayastorTest;
+use mayastor::{
+    core::MayastorCliArgs,
+    lvs::{pool_inits, InitMode, InitState, Lvs},
+    pool::PoolArgs,
+};
+
+pub mod common;
+
+/// returns the state of the initialization of the pool
+fn init_state(pool: &str) -> Option<InitState> {
+    pool_inits()
+        .into_iter()
+        .find(|i| i.pool == pool)
+        .map(|i| i.state)
+}
+
+#[tokio::test]
+async fn pool_init() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    let available = ms
+        .spawn(async {
+            let pool = Lvs::create_or_import(PoolArgs {
+                name: "ipool".into(),
+                disks: vec!["malloc:///idisk?size_mb=64".into()],
+                uuid: None,
+                labels: Default::default(),
+                cluster_size: None,
+            })
+            .await
+            .unwrap();
+            let available = pool.available();
+
+            // a full initialization is done once it returns
+            pool.init(InitMode::Full).await.unwrap();
+            assert_eq!(init_state("ipool"), Some(InitState::Completed));
+            assert_eq!(pool.available(), available);
+            assert_eq!(pool.lvols().unwrap().count(), 0);
+
+            // a replica created during a lazy initialization gets its space
+            pool.init(InitMode::Lazy).await.unwrap();
+            pool.create_lvol("init-1", available / 2, None, false)
+                .await
+                .unwrap();
+            available
+        })
+        .await;
+
+    for _ in 0 .. 50 {
+        if ms.spawn(async { init_state("ipool") }).await
+            != Some(InitState::Running)
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    ms.spawn(async move {
+        assert_eq!(init_state("ipool"), Some(InitState::Completed));
+        let pool = Lvs::lookup("ipool").unwrap();
+        let lvols = pool.lvols().unwrap().collect::<Vec<_>>();
+        assert_eq!(lvols.len(), 1);
+        assert_eq!(pool.available() + lvols[0].size(), available);
+        pool.destroy().await.unwrap();
+    })
+    .await;
+}
@@ -0,0 +1,87 @@
+#[macro_use]
+extern crate assert_matches;
+
+use common::MayastorTest;
+use mayastor::{
+    core::MayastorCliArgs,
+    lvs::{set_replica_owner, Error, Lvs, PoolQuota},
+    pool::PoolArgs,
+};
+
+pub mod common;
+
+static DISKNAME: &str = "/tmp/quota.img";
+
+const MB: u64 = 1024 * 1024;
+
+#[tokio::test]
+async fn pool_quota() {
+    common::delete_file(&[DISKNAME.into()]);
+    common::truncate_file(DISKNAME, 64 * 1024);
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        let pool = Lvs::create_or_import(PoolArgs {
+            name: "qpool".into(),
+            disks: vec![format!("aio://{}", DISKNAME)],
+            uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
+        })
+        .await
+        .unwrap();
+
+        // every owner may take a quarter of the pool, tenant-b half of it
+        assert!(pool
+            .set_quota(PoolQuota {
+                percent: Some(0),
+                ..Default::default()
+            })
+            .is_err());
+        pool.set_quota(PoolQuota {
+            percent: Some(25),
+            owners: vec![("tenant-b".to_string(), 50)].into_iter().collect(),
+        })
+        .unwrap();
+
+        set_replica_owner("quota-a1", Some("tenant-a".into())).unwrap();
+        set_replica_owner("quota-a2", Some("tenant-a".into())).unwrap();
+        set_replica_owner("quota-b1", Some("tenant-b".into())).unwrap();
+        set_replica_owner("quota-b2", Some("tenant-b".into())).unwrap();
+
+        let a1 = pool
+            .create_lvol("quota-a1", 8 * MB, None, true)
+            .await
+            .unwrap();
+        assert_matches!(
+            pool.create_lvol("quota-a2", 8 * MB, None, true).await,
+            Err(Error::QuotaExceeded { .. })
+        );
+        let b1 = pool
+            .create_lvol("quota-b1", 8 * MB, None, true)
+            .await
+            .unwrap();
+        let b2 = pool
+            .create_lvol("quota-b2", 8 * MB, None, true)
+            .await
+            .unwrap();
+        assert_eq!(pool.owner_usage().get("tenant-a"), Some(&(8 * MB)));
+        assert_eq!(pool.owner_usage().get("tenant-b"), Some(&(16 * MB)));
+
+        // replicas without an owner are not limited
+        let free = pool.create_lvol("quota-free", 16 * MB, None, true).await;
+        let free = free.unwrap();
+
+        // an owner can not be given a replica beyond its quota
+        assert!(
+            set_replica_owner("quota-free", Some("tenant-a".into())).is_err()
+        );
+
+        for lvol in vec![a1, b1, b2, free] {
+            lvol.destroy().await.unwrap();
+        }
+        pool.destroy().await.unwrap();
+    })
+    .await;
+    common::delete_file(&[DISKNAME.into()]);
+}
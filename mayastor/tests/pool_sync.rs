@@ -0,0 +1,60 @@
+use std::pin::Pin;
+
+use common::MayastorTest;
+use mayastor::{
+    core::MayastorCliArgs,
+    lvs::{pool_syncs, Lvs, SyncPolicy},
+    pool::PoolArgs,
+};
+
+pub mod common;
+
+#[tokio::test]
+async fn pool_sync() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        let pool = Lvs::create_or_import(PoolArgs {
+            name: "spool".into(),
+            disks: vec!["malloc:///sdisk?size_mb=64".into()],
+            uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
+        })
+        .await
+        .unwrap();
+        assert_eq!(pool.sync_policy(), SyncPolicy::WriteThrough);
+        let mut lvol = pool
+            .create_lvol("sync-1", 8 * 1024 * 1024, None, false)
+            .await
+            .unwrap();
+
+        // changes are left for the next sync, which is far away
+        pool.set_sync_policy(SyncPolicy::WriteBack {
+            interval_ms: 3_600_000,
+        })
+        .await
+        .unwrap();
+        Pin::new(&mut lvol).set_protected(true).await.unwrap();
+        assert!(lvol.is_protected().await);
+        let sync = pool_syncs().into_iter().find(|s| s.pool == "spool");
+        assert!(sync.unwrap().dirty.contains("sync-1"));
+
+        assert_eq!(pool.sync().await.unwrap(), 1);
+        let sync = pool_syncs().into_iter().find(|s| s.pool == "spool");
+        let sync = sync.unwrap();
+        assert!(sync.dirty.is_empty());
+        assert_eq!(sync.synced, 1);
+        assert!(sync.last_sync.is_some());
+
+        // going back to write-through syncs what is left and keeps no state
+        Pin::new(&mut lvol).set_protected(false).await.unwrap();
+        pool.set_sync_policy(SyncPolicy::WriteThrough)
+            .await
+            .unwrap();
+        assert!(pool_syncs().iter().all(|s| s.pool != "spool"));
+
+        pool.destroy().await.unwrap();
+    })
+    .await;
+}
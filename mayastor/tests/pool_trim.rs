@@ -0,0 +1,60 @@
+use common::MayastorTest;
+use mayastor::{
+    core::{MayastorCliArgs, UntypedBdev},
+    lvs::{pool_trims, Lvs, TrimPolicy},
+    pool::PoolArgs,
+};
+
+pub mod common;
+
+static DISKNAME: &str = "/tmp/trim.img";
+
+#[tokio::test]
+async fn pool_trim() {
+    common::delete_file(&[DISKNAME.into()]);
+    common::truncate_file(DISKNAME, 64 * 1024);
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        let pool = Lvs::create_or_import(PoolArgs {
+            name: "tpool".into(),
+            disks: vec![format!("aio://{}", DISKNAME)],
+            uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
+        })
+        .await
+        .unwrap();
+        assert_eq!(pool.trim_policy(), TrimPolicy::Immediate);
+
+        pool.set_trim_policy(TrimPolicy::Batched {
+            threshold_mb: 1024,
+        });
+        let available = pool.available();
+
+        // the clusters of a destroyed replica are left for the next trim
+        let lvol = pool
+            .create_lvol("trim-1", 8 * 1024 * 1024, None, false)
+            .await
+            .unwrap();
+        lvol.destroy().await.unwrap();
+        let trim = pool_trims().into_iter().find(|t| t.pool == "tpool");
+        assert_eq!(trim.unwrap().pending, 8 * 1024 * 1024);
+
+        assert_eq!(pool.trim().await.unwrap(), available);
+        let trim = pool_trims().into_iter().find(|t| t.pool == "tpool");
+        let trim = trim.unwrap();
+        assert_eq!(trim.pending, 0);
+        assert_eq!(trim.trimmed, available);
+        assert!(trim.last_trim.is_some());
+        assert!(UntypedBdev::lookup_by_name("tpool.trim").is_none());
+        assert_eq!(pool.available(), available);
+
+        // the default policy keeps no state
+        pool.set_trim_policy(TrimPolicy::Immediate);
+        assert!(pool_trims().iter().all(|t| t.pool != "tpool"));
+
+        pool.destroy().await.unwrap();
+    })
+    .await;
+}
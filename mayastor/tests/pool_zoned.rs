@@ -0,0 +1,58 @@
+use rpc::mayastor::{BdevUri, CreatePoolRequest, JsonRpcRequest};
+use tonic::Code;
+
+pub mod common;
+use common::compose::Builder;
+
+#[tokio::test]
+async fn pool_zoned() {
+    let test = Builder::new()
+        .name("pool_zoned")
+        .network("10.1.0.0/16")
+        .add_container("ms1")
+        .with_clean(true)
+        .with_default_tracing()
+        .build()
+        .await
+        .unwrap();
+
+    let mut hdl = test.grpc_handle("ms1").await.unwrap();
+
+    // put a zoned vbdev on top of a malloc bdev
+    hdl.bdev
+        .create(BdevUri {
+            uri: "malloc:///zbase?size_mb=64".into(),
+        })
+        .await
+        .unwrap();
+    hdl.jsonrpc
+        .json_rpc_call(JsonRpcRequest {
+            method: "bdev_zone_block_create".to_string(),
+            params: "{\"name\": \"zoned0\", \"base_bdev\": \"zbase\", \
+                     \"zone_capacity\": 4096, \"optimal_open_zones\": 1}"
+                .to_string(),
+        })
+        .await
+        .unwrap();
+
+    // a pool on the zoned device is refused up front
+    let err = hdl
+        .mayastor
+        .create_pool(CreatePoolRequest {
+            name: "zpool".into(),
+            disks: vec!["bdev:///zoned0".into()],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    assert!(err.message().contains("zoned"), "{}", err.message());
+
+    // while a conventional device still takes a pool
+    hdl.mayastor
+        .create_pool(CreatePoolRequest {
+            name: "cpool".into(),
+            disks: vec!["malloc:///conv0?size_mb=64".into()],
+        })
+        .await
+        .unwrap();
+}
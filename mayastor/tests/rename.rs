@@ -0,0 +1,81 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut},
+    core::{BdevHandle, MayastorCliArgs, Protocol},
+    lvs::Lvs,
+    pool::PoolArgs,
+};
+pub mod common;
+
+#[tokio::test]
+async fn rename_pool_and_nexus() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        let pool = Lvs::create_or_import(PoolArgs {
+            name: "rpool".into(),
+            disks: vec!["malloc:///rdisk?size_mb=64".into()],
+            uuid: None,
+            labels: [("zone".to_string(), "a".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+            cluster_size: None,
+        })
+        .await
+        .unwrap();
+        pool.create_lvol("rlvol", 8 * 1024 * 1024, None, true)
+            .await
+            .unwrap();
+
+        pool.rename("rpool2").await.unwrap();
+        assert!(Lvs::lookup("rpool").is_none());
+        let pool = Lvs::lookup("rpool2").unwrap();
+        assert_eq!(pool.labels().get("zone").unwrap(), "a");
+        assert_eq!(pool.lvols().unwrap().count(), 1);
+
+        // a pool can not take the name of another one
+        let other = Lvs::create_or_import(PoolArgs {
+            name: "rpool3".into(),
+            disks: vec!["malloc:///rdisk3?size_mb=64".into()],
+            uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
+        })
+        .await
+        .unwrap();
+        assert!(other.rename("rpool2").await.is_err());
+
+        other.destroy().await.unwrap();
+        pool.destroy().await.unwrap();
+    })
+    .await;
+
+    ms.spawn(async {
+        nexus_create(
+            "rnexus",
+            32 * 1024 * 1024,
+            None,
+            &["malloc:///rn0?size_mb=64".into()],
+        )
+        .await
+        .unwrap();
+
+        let nexus = nexus_lookup_mut("rnexus").unwrap();
+        nexus.rename("rnexus2").await.unwrap();
+        assert!(nexus_lookup_mut("rnexus").is_none());
+
+        // the nexus can be opened under its new name
+        let hdl = BdevHandle::open("rnexus2", true, false).unwrap();
+        drop(hdl);
+
+        // a shared nexus keeps its name
+        let mut nexus = nexus_lookup_mut("rnexus2").unwrap();
+        nexus.as_mut().share(Protocol::Nvmf, None).await.unwrap();
+        assert!(nexus.as_mut().rename("rnexus3").await.is_err());
+        nexus.as_mut().unshare_nexus().await.unwrap();
+
+        nexus.destroy().await.unwrap();
+    })
+    .await;
+}
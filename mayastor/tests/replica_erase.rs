@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use common::MayastorTest;
+use mayastor::{
+    core::{MayastorCliArgs, UntypedBdev},
+    lvs::{replica_erasures, DeletionPolicy, Lvs},
+    pool::PoolArgs,
+};
+
+pub mod common;
+
+static DISKNAME: &str = "/tmp/erase.img";
+
+#[tokio::test]
+async fn replica_erase_on_destroy() {
+    common::delete_file(&[DISKNAME.into()]);
+    common::truncate_file(DISKNAME, 128 * 1024);
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        let pool = Lvs::create_or_import(PoolArgs {
+            name: "epool".into(),
+            disks: vec![format!("aio://{}", DISKNAME)],
+            uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
+        })
+        .await
+        .unwrap();
+
+        let lvol = pool
+            .create_lvol("erase-1", 48 * 1024 * 1024, None, false)
+            .await
+            .unwrap();
+
+        let hdl = UntypedBdev::open_by_name("erase-1", true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        buf.fill(0xa5);
+        hdl.write_at(0, &buf).await.unwrap();
+        drop(hdl);
+
+        // the replica is erased in the background and destroyed once done
+        lvol.destroy_with_policy(DeletionPolicy::WriteZeroes)
+            .await
+            .unwrap();
+        assert!(replica_erasures()
+            .iter()
+            .any(|e| e.name == "erase-1" && e.size == 48 * 1024 * 1024));
+    })
+    .await;
+
+    for _ in 0 .. 100 {
+        if ms.spawn(async { replica_erasures().is_empty() }).await {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    ms.spawn(async {
+        assert!(replica_erasures().is_empty());
+        assert!(UntypedBdev::lookup_by_name("erase-1").is_none());
+
+        let pool = Lvs::lookup("epool").unwrap();
+
+        // without a policy the replica is gone right away
+        let lvol = pool
+            .create_lvol("erase-2", 8 * 1024 * 1024, None, true)
+            .await
+            .unwrap();
+        lvol.destroy_with_policy(DeletionPolicy::None)
+            .await
+            .unwrap();
+        assert!(UntypedBdev::lookup_by_name("erase-2").is_none());
+
+        pool.destroy().await.unwrap();
+    })
+    .await;
+
+    common::delete_file(&[DISKNAME.into()]);
+}
@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use common::MayastorTest;
+use mayastor::{
+    core::{MayastorCliArgs, UntypedBdev},
+    lvs::{replica_imports, ImportState, Lvs},
+    pool::PoolArgs,
+};
+
+pub mod common;
+
+static DISKNAME: &str = "/tmp/import.img";
+static SOURCE: &str = "/tmp/import-source.img";
+
+#[tokio::test]
+async fn replica_import() {
+    common::delete_file(&[DISKNAME.into(), SOURCE.into()]);
+    common::truncate_file(DISKNAME, 128 * 1024);
+    common::truncate_file(SOURCE, 24 * 1024);
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        // put some data on the volume to import
+        let src = mayastor::bdev::device_create(&format!("aio://{}", SOURCE))
+            .await
+            .unwrap();
+        let hdl = UntypedBdev::open_by_name(&src, true)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        buf.fill(0xa5);
+        hdl.write_at(20 * 1024 * 1024, &buf).await.unwrap();
+        drop(hdl);
+        mayastor::bdev::device_destroy(&format!("aio://{}", SOURCE))
+            .await
+            .unwrap();
+
+        let pool = Lvs::create_or_import(PoolArgs {
+            name: "ipool".into(),
+            disks: vec![format!("aio://{}", DISKNAME)],
+            uuid: None,
+            labels: Default::default(),
+            cluster_size: None,
+        })
+        .await
+        .unwrap();
+
+        let import = pool
+            .import_lvol("import-1", &format!("aio://{}", SOURCE), None, true)
+            .await
+            .unwrap();
+        assert_eq!(import.size, 24 * 1024 * 1024);
+        assert_eq!(import.state, ImportState::Running);
+
+        // the source must exist
+        assert!(pool
+            .import_lvol("import-2", "aio:///tmp/no-such-file.img", None, true)
+            .await
+            .is_err());
+        assert!(UntypedBdev::lookup_by_name("import-2").is_none());
+    })
+    .await;
+
+    for _ in 0 .. 100 {
+        if ms
+            .spawn(async {
+                replica_imports()
+                    .iter()
+                    .all(|i| i.state != ImportState::Running)
+            })
+            .await
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    ms.spawn(async {
+        let import = replica_imports()
+            .into_iter()
+            .find(|i| i.name == "import-1")
+            .unwrap();
+        assert_eq!(import.state, ImportState::Completed);
+        assert_eq!(import.copied, 24 * 1024 * 1024);
+
+        let hdl = UntypedBdev::open_by_name("import-1", false)
+            .unwrap()
+            .into_handle()
+            .unwrap();
+        let mut buf = hdl.dma_malloc(4096).unwrap();
+        hdl.read_at(20 * 1024 * 1024, &mut buf).await.unwrap();
+        assert!(buf.as_slice().iter().all(|b| *b == 0xa5));
+        drop(hdl);
+
+        // the source is released once copied
+        assert!(UntypedBdev::lookup_by_name(SOURCE).is_none());
+
+        Lvs::lookup("ipool").unwrap().destroy().await.unwrap();
+    })
+    .await;
+}
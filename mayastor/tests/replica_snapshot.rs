@@ -77,6 +77,8 @@ async fn replica_snapshot() {
                 name: POOL1_NAME.to_string(),
                 disks: vec![format!("aio://{}", DISKNAME1)],
                 uuid: None,
+                labels: Default::default(),
+                cluster_size: None,
             })
             .await
             .unwrap();
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use common::MayastorTest;
+use mayastor::core::{
+    runtime::{spawn_await, spawn_on_core, SpawnError},
+    Cores,
+    MayastorCliArgs,
+    Reactors,
+};
+
+pub mod common;
+
+#[tokio::test]
+async fn runtime_bridge() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    // from tokio to the reactor of a core and back
+    let core =
+        spawn_on_core(Cores::first(), || async { Reactors::current().core() })
+            .unwrap()
+            .await
+            .unwrap();
+    assert_eq!(core, Cores::first());
+
+    assert!(matches!(
+        spawn_on_core(u32::MAX, || async {}),
+        Err(SpawnError::NoReactor {
+            core: u32::MAX
+        })
+    ));
+
+    // from a reactor to tokio and back
+    let slept = ms
+        .spawn(async {
+            spawn_await(async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                42
+            })
+            .await
+            .unwrap()
+        })
+        .await;
+    assert_eq!(slept, 42);
+}
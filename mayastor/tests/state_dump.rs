@@ -0,0 +1,67 @@
+use common::MayastorTest;
+use mayastor::{
+    bdev::nexus::{nexus_create, nexus_lookup_mut},
+    core::MayastorCliArgs,
+    state_dump::StateDump,
+};
+
+pub mod common;
+
+static NEXUS_NAME: &str = "dump_nexus";
+
+#[tokio::test]
+async fn state_dump() {
+    let ms = MayastorTest::new(MayastorCliArgs::default());
+
+    ms.spawn(async {
+        nexus_create(
+            NEXUS_NAME,
+            32 * 1024 * 1024,
+            None,
+            &[
+                "malloc:///m0?size_mb=64".to_string(),
+                "malloc:///m1?size_mb=64".to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+    })
+    .await;
+
+    // taken on a SPDK thread, the state of the nexus is part of the dump
+    let dump = ms
+        .spawn(async { StateDump::collect(Some("test".to_string())) })
+        .await;
+    assert_eq!(dump.reason.as_deref(), Some("test"));
+    assert!(!dump.reactors.is_empty());
+    let spdk = dump.spdk.as_ref().unwrap();
+    let nexus = spdk.nexuses.iter().find(|n| n.name == NEXUS_NAME).unwrap();
+    assert_eq!(nexus.children.len(), 2);
+    assert!(nexus.status.is_some());
+
+    // the crash file holds the same state as json
+    let dir = std::env::temp_dir().join("mayastor-state-dump-test");
+    let path = dump.write(dir.to_str().unwrap()).unwrap();
+    let json: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(json["spdk"]["nexuses"][0]["name"], NEXUS_NAME);
+    std::fs::remove_file(path).unwrap();
+
+    // a live dump also holds the channels of the nexus and its bdevs
+    let dump = ms.spawn(StateDump::collect_live()).await;
+    let spdk = dump.spdk.unwrap();
+    let nexus = spdk.nexuses.iter().find(|n| n.name == NEXUS_NAME).unwrap();
+    let channels = nexus.channels.as_ref().unwrap();
+    assert!(!channels.is_empty());
+    assert!(channels.iter().all(|c| c.writers.len() == 2));
+    assert!(spdk.bdevs.iter().any(|b| b.name == NEXUS_NAME));
+
+    ms.spawn(async {
+        nexus_lookup_mut(NEXUS_NAME)
+            .unwrap()
+            .destroy()
+            .await
+            .unwrap();
+    })
+    .await;
+}